@@ -3,4 +3,5 @@ set -e
 
 cargo fmt --manifest-path=kernel-rs/Cargo.toml -- --check -l
 cargo clippy --manifest-path=kernel-rs/Cargo.toml
+make mkfs-test
 make qemu USERTEST=yes RUST_MODE=release
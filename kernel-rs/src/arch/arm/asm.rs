@@ -125,7 +125,7 @@ pub enum SmcFunctions {
     _Features = 0x8400000A,
     _MigInfoType = 0x84000006,
     _SystemOff = 0x84000008,
-    _SystemReset = 0x84000009,
+    SystemReset = 0x84000009,
 }
 
 /// Secure Monitor call
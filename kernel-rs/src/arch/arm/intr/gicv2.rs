@@ -14,7 +14,7 @@ use tock_registers::{
 use crate::arch::{
     asm::cpu_id,
     interface::{MemLayout, TimeManager},
-    memlayout::TIMER0_IRQ,
+    memlayout::{IPI_SGI, TIMER0_IRQ},
     Armv8,
 };
 
@@ -298,6 +298,19 @@ impl Gic {
         let gicc = &GICC;
         gicc.EOIR.set(int as u32);
     }
+
+    /// Send the software generated interrupt `sgi` to the core `core_id`.
+    /// `Gic::init` must have been called.
+    pub fn send_sgi(&self, core_id: usize, sgi: Interrupt) {
+        let gicd = &GICD;
+        gicd.SGIR.set((((1 << core_id) << 16) | sgi) as u32);
+    }
+}
+
+/// Returns the interrupt number of `int` fetched from GIC, without the id of the core that
+/// sent it if it is a software generated interrupt.
+pub fn irq_number(int: Interrupt) -> Interrupt {
+    int & 0x3ff
 }
 
 pub const INT_TIMER: Interrupt = 27; // virtual timer
@@ -321,6 +334,7 @@ pub unsafe fn intr_init_core() {
     unsafe {
         INTERRUPT_CONTROLLER.init();
         INTERRUPT_CONTROLLER.enable(TIMER0_IRQ);
+        INTERRUPT_CONTROLLER.enable(IPI_SGI);
     }
 
     Armv8::timer_init();
@@ -334,6 +348,10 @@ pub unsafe fn intr_init_core() {
         unsafe {
             // virtio_blk
             INTERRUPT_CONTROLLER.enable(Armv8::VIRTIO0_IRQ);
+            // virtio_console
+            INTERRUPT_CONTROLLER.enable(Armv8::VIRTIO2_IRQ);
+            // virtio_9p
+            INTERRUPT_CONTROLLER.enable(Armv8::VIRTIO3_IRQ);
             // pl011 uart
             INTERRUPT_CONTROLLER.enable(Armv8::UART0_IRQ);
         }
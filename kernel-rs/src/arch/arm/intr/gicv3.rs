@@ -11,7 +11,7 @@ use tock_registers::interfaces::Writeable;
 use crate::arch::{
     asm::{cpu_id, cpu_relax, isb, r_icc_ctlr_el1, r_mpidr},
     interface::{MemLayout, TimeManager},
-    memlayout::TIMER0_IRQ,
+    memlayout::{IPI_SGI, TIMER0_IRQ},
     timer::udelay,
     Armv8,
 };
@@ -1169,6 +1169,25 @@ impl Gic {
             asm!("msr icc_eoir1_el1, {}", in(reg) x);
         }
     }
+
+    /// Send the software generated interrupt `sgi` to the core `core_id`.
+    /// Assumes that the affinity level 0 of each core is its id, and the other levels are 0,
+    /// as in qemu.
+    pub fn send_sgi(&self, core_id: usize, sgi: Interrupt) {
+        let x = (((sgi as u64) << ICC_SGI1R_SGI_ID_SHIFT) & ICC_SGI1R_SGI_ID_MASK)
+            | ((1 << core_id) & ICC_SGI1R_TARGET_LIST_MASK as u64);
+        // SAFETY: x contains a valid value for icc_sgi1r_el1 register.
+        unsafe {
+            asm!("dsb ishst");
+            asm!("msr icc_sgi1r_el1, {}", in(reg) x);
+        }
+        isb();
+    }
+}
+
+/// Returns the interrupt number of `int` fetched from GIC.
+pub fn irq_number(int: Interrupt) -> Interrupt {
+    int
 }
 
 pub const INT_TIMER: Interrupt = 27; // virtual timer
@@ -1193,6 +1212,7 @@ pub unsafe fn intr_init_core() {
     Armv8::timer_init();
     unsafe {
         intr_controller.enable(TIMER0_IRQ);
+        intr_controller.enable(IPI_SGI);
     }
 
     // Order matters!
@@ -1204,6 +1224,12 @@ pub unsafe fn intr_init_core() {
             // virtio_blk
             intr_controller.enable(Armv8::VIRTIO0_IRQ);
 
+            // virtio_console
+            intr_controller.enable(Armv8::VIRTIO2_IRQ);
+
+            // virtio_9p
+            intr_controller.enable(Armv8::VIRTIO3_IRQ);
+
             // pl011 uart
             intr_controller.enable(Armv8::UART0_IRQ);
         }
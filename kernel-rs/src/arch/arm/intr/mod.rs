@@ -7,6 +7,7 @@ pub use gicv2::*;
 pub use gicv3::*;
 
 use crate::arch::interface::InterruptManager;
+use crate::arch::memlayout::IPI_SGI;
 use crate::arch::Armv8;
 
 impl InterruptManager for Armv8 {
@@ -21,4 +22,8 @@ impl InterruptManager for Armv8 {
             intr_init_core();
         }
     }
+
+    fn send_ipi(cpu: usize) {
+        INTERRUPT_CONTROLLER.send_sgi(cpu, IPI_SGI);
+    }
 }
@@ -6,7 +6,11 @@
 //! 00000000 -- boot ROM, provided by qemu, space up to 0x8000000 is reserved.
 //! 08000000 -- GIC
 //! 09000000 -- uart0
+//! 09010000 -- PL031 RTC
 //! 0a000000 -- virtio disk
+//! 0a000200 -- virtio entropy device
+//! 0a000400 -- virtio console
+//! 0a000600 -- virtio 9p transport
 //! 40010000 -- boot ROM jumps here in machine mode
 //!             -kernel loads the kernel here
 //! unused RAM after 40000000.
@@ -32,13 +36,24 @@ impl MemLayout for Armv8 {
     /// virtio mmio interface
     const VIRTIO0: usize = 0x0a000000;
     const VIRTIO0_IRQ: usize = 48;
+    const VIRTIO1: usize = 0x0a000200;
+    const VIRTIO2: usize = 0x0a000400;
+    const VIRTIO2_IRQ: usize = 50;
+    const VIRTIO3: usize = 0x0a000600;
+    const VIRTIO3_IRQ: usize = 51;
 }
 
 // TODO: Find counterpart of this in ARM, seems that it doesn't exist.
 /// SiFive Test Finisher. (virt device only)
 // pub const FINISHER: usize = 0x100000;
 
+/// PrimeCell PL031 real-time clock.
+pub const RTC: usize = 0x09010000;
+
 /// qemu puts Arm generic Interrupt controller (GIC) here.
 pub const GIC: usize = 0x08000000;
 
 pub const TIMER0_IRQ: usize = 27;
+
+/// The software generated interrupt (SGI) used for inter-processor interrupts.
+pub const IPI_SGI: usize = 1;
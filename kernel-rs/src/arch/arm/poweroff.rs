@@ -1,5 +1,8 @@
+use cortex_a::asm::wfi;
+
 use super::Armv8;
-use crate::arch::interface::PowerOff;
+use crate::arch::asm::{smc_call, SmcFunctions};
+use crate::arch::interface::{PowerManager, PowerOff};
 
 impl PowerOff for Armv8 {
     /// Shutdowns this machine, discarding all unsaved data.
@@ -8,4 +11,26 @@ impl PowerOff for Armv8 {
     fn machine_poweroff(_exitcode: u16) -> ! {
         todo!("Is there any way to replace this in arm?")
     }
+
+    /// Restarts this machine, discarding all unsaved data.
+    ///
+    /// This function uses PSCI SYSTEM_RESET, which resets the whole QEMU virt device and
+    /// reloads the kernel image.
+    fn machine_reboot() -> ! {
+        // SAFETY: SYSTEM_RESET takes no arguments.
+        let _ = unsafe { smc_call(SmcFunctions::SystemReset as u64, 0, 0, 0) };
+
+        unreachable!("Reboot failed");
+    }
+}
+
+impl PowerManager for Armv8 {
+    fn cpu_suspend() {
+        wfi();
+    }
+
+    /// QEMU virt device does not have performance states.
+    fn set_perf_level(_level: usize) -> Result<(), ()> {
+        Err(())
+    }
 }
@@ -140,10 +140,18 @@ pub struct Context {
 }
 
 impl const TrapFrameManager for TrapFrame {
+    fn get_pc(&self) -> usize {
+        self.pc
+    }
+
     fn set_pc(&mut self, val: usize) {
         self.pc = val;
     }
 
+    fn get_sp(&self) -> usize {
+        self.sp
+    }
+
     fn set_sp(&mut self, val: usize) {
         self.sp = val;
     }
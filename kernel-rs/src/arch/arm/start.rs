@@ -100,7 +100,7 @@ pub unsafe fn start() {
             + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
             + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
             + TCR_EL1::EPD0::EnableTTBR0Walks
-            + TCR_EL1::EPD1::EnableTTBR1Walks
+            + TCR_EL1::EPD1::DisableTTBR1Walks
             + TCR_EL1::A1::TTBR0 // use TTBR0_EL1's ASID as an ASID
             + TCR_EL1::T0SZ.val(25) // this can be changed, possible up to 44
             + TCR_EL1::T1SZ.val(25) // this can be changed, possible up to 44
@@ -1,11 +1,22 @@
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use array_macro::array;
 use cortex_a::{asm::barrier, registers::*};
 use tock_registers::interfaces::{Readable, Writeable};
 
-use crate::arch::{interface::TimeManager, Armv8};
+use crate::{
+    arch::{asm::cpu_id, interface::TimeManager, memlayout::RTC, Armv8},
+    param::{NCPU, TICK_US},
+};
 
 const US_PER_S: u64 = 1_000_000;
 
-const TIMER_TICK_MS: u64 = 100;
+/// Interval between timer interrupts, in microseconds.
+static TICK_INTERVAL: AtomicUsize = AtomicUsize::new(TICK_US);
+
+/// Has each CPU stopped its timer interrupts?
+static TIMER_STOPPED: [AtomicBool; NCPU] = array![_ => AtomicBool::new(false); NCPU];
 
 // pub struct Timer;
 
@@ -14,13 +25,59 @@ impl TimeManager for Armv8 {
         set_next_timer();
     }
 
+    fn set_tick_interval(us: usize) {
+        TICK_INTERVAL.store(us, Ordering::Relaxed);
+    }
+
+    fn tick_interval() -> usize {
+        TICK_INTERVAL.load(Ordering::Relaxed)
+    }
+
+    fn timer_stop() {
+        TIMER_STOPPED[cpu_id()].store(true, Ordering::Relaxed);
+        unsafe { barrier::isb(barrier::SY) };
+        CNTV_CTL_EL0.write(CNTV_CTL_EL0::ENABLE.val(1) + CNTV_CTL_EL0::IMASK.val(1));
+        unsafe { barrier::isb(barrier::SY) };
+    }
+
+    fn timer_resume() {
+        TIMER_STOPPED[cpu_id()].store(false, Ordering::Relaxed);
+        set_next_timer();
+    }
+
     fn uptime_as_micro() -> Result<usize, ()> {
         Ok((read_cntpct() * US_PER_S / read_freq()) as usize)
     }
 
+    fn rtc_micro() -> usize {
+        // The data register of the PL031 counts seconds.
+        // SAFETY: the kernel page table maps the PL031 RTC.
+        let secs = unsafe { ptr::read_volatile(RTC as *const u32) };
+        secs as usize * US_PER_S as usize
+    }
+
     fn r_cycle() -> usize {
         read_cntpct() as usize
     }
+
+    /// Counting instructions needs the PMU, which is not supported yet.
+    fn r_instret() -> usize {
+        0
+    }
+
+    fn serialize() {
+        unsafe {
+            barrier::dsb(barrier::SY);
+            barrier::isb(barrier::SY);
+        }
+    }
+
+    /// The PMU is not supported yet.
+    fn r_perf_counter(_i: usize) -> usize {
+        0
+    }
+
+    unsafe fn set_perf_event(_i: usize, _event: usize) {}
 }
 
 pub fn read_cntpct() -> u64 {
@@ -35,9 +92,14 @@ pub fn read_freq() -> u64 {
 }
 
 pub fn set_next_timer() {
+    // A timer interrupt taken right before the timer is stopped must not restart it.
+    if TIMER_STOPPED[cpu_id()].load(Ordering::Relaxed) {
+        return;
+    }
+
     unsafe { barrier::isb(barrier::SY) };
     let freq = CNTFRQ_EL0.get();
-    let count = TIMER_TICK_MS * freq / 1000;
+    let count = TICK_INTERVAL.load(Ordering::Relaxed) as u64 * freq / US_PER_S;
 
     unsafe { barrier::isb(barrier::SY) };
     CNTV_TVAL_EL0.set(count);
@@ -10,13 +10,15 @@ use crate::{
     arch::interface::{MemLayout, TrapManager},
     arch::{
         asm::{intr_get, intr_off, intr_on, r_fpsr, w_fpsr},
-        intr::INTERRUPT_CONTROLLER,
-        memlayout::TIMER0_IRQ,
+        intr::{irq_number, INTERRUPT_CONTROLLER},
+        memlayout::{IPI_SGI, TIMER0_IRQ},
         proc::TrapFrame,
         timer::set_next_timer,
         Armv8,
     },
     memlayout::{TRAMPOLINE, TRAPFRAME},
+    param::NTRIGGER,
+    proc::Trigger,
     trap::{IrqNum, IrqTypes, TrapTypes},
 };
 
@@ -54,6 +56,8 @@ impl From<&IrqTypes> for IrqNum {
         match item {
             IrqTypes::Uart => Armv8::UART0_IRQ,
             IrqTypes::Virtio => Armv8::VIRTIO0_IRQ,
+            IrqTypes::VirtioConsole => Armv8::VIRTIO2_IRQ,
+            IrqTypes::Virtio9p => Armv8::VIRTIO3_IRQ,
             IrqTypes::Unknown(i) => *i,
             IrqTypes::Others(i) => *i,
         }
@@ -87,8 +91,12 @@ impl TrapManager for Armv8 {
                 } else if ESR_EL1
                     .matches_any(ESR_EL1::EC::DataAbortLowerEL + ESR_EL1::EC::InstrAbortLowerEL)
                 {
-                    // TODO: Should handle these exceptions?
-                    TrapTypes::BadTrap
+                    // WnR, bit 6 of the ISS of a data abort, tells whether it writes.
+                    TrapTypes::PageFault {
+                        addr: FAR_EL1.get() as usize,
+                        write: ESR_EL1.matches_all(ESR_EL1::EC::DataAbortLowerEL)
+                            && ESR_EL1.read(ESR_EL1::ISS) & (1 << 6) != 0,
+                    }
                 } else {
                     TrapTypes::BadTrap
                 }
@@ -98,12 +106,21 @@ impl TrapManager for Armv8 {
 
                 let irq_type = match irq {
                     Some(i) => {
-                        match i {
+                        match irq_number(i) {
                             TIMER0_IRQ => {
                                 return TrapTypes::TimerInterrupt;
                             }
+                            IPI_SGI => {
+                                // The messages are in memory, so finish it at once, with the id
+                                // of the sending core that GICv2 puts in `i`.
+                                // SAFETY: `i` has been received, and not been finished yet.
+                                unsafe { INTERRUPT_CONTROLLER.finish(i) };
+                                return TrapTypes::Ipi;
+                            }
                             Armv8::UART0_IRQ => IrqTypes::Uart,
                             Armv8::VIRTIO0_IRQ => IrqTypes::Virtio,
+                            Armv8::VIRTIO2_IRQ => IrqTypes::VirtioConsole,
+                            Armv8::VIRTIO3_IRQ => IrqTypes::Virtio9p,
                             _ => IrqTypes::Unknown(i),
                         }
                     }
@@ -193,6 +210,7 @@ impl TrapManager for Armv8 {
 
     unsafe fn user_trap_ret(
         user_pagetable_addr: usize,
+        asid: usize,
         trapframe: &mut TrapFrame,
         kernel_stack: usize,
         usertrap: usize,
@@ -219,9 +237,16 @@ impl TrapManager for Armv8 {
         let fn_0: usize =
             TRAMPOLINE + unsafe { userret.as_ptr().offset_from(trampoline.as_ptr()) } as usize;
         let fn_0 = unsafe { mem::transmute::<_, unsafe extern "C" fn(usize, usize) -> !>(fn_0) };
-        unsafe { fn_0(TRAPFRAME, user_pagetable_addr) }
+        unsafe { fn_0(TRAPFRAME, user_pagetable_addr | (asid << 48)) }
     }
 
+    /// The hardware breakpoints and watchpoints of ARMv8 are not supported yet.
+    fn ntrigger() -> usize {
+        0
+    }
+
+    unsafe fn load_triggers(_triggers: &[Trigger; NTRIGGER]) {}
+
     fn save_trap_regs(store: &mut [usize; 10]) {
         let elr_el1 = ELR_EL1.get();
         let spsr_el1 = SPSR_EL1.get();
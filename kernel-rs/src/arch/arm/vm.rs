@@ -3,13 +3,13 @@ use cortex_a::registers::*;
 use tock_registers::interfaces::ReadWriteable;
 
 use crate::{
-    addr::PAddr,
+    addr::{PAddr, PGSIZE},
     arch::Armv8,
     arch::{
         addr::{pa2pte, pte2pa, PLNUM},
         asm::{isb, tlbi_vmalle1},
         interface::{IPageTableEntry, MemLayout, PageTableManager},
-        memlayout::GIC,
+        memlayout::{GIC, RTC},
     },
     vm::{AccessFlags, RawPageTable},
 };
@@ -32,10 +32,18 @@ bitflags! {
         const U = 1 << 6;
         /// Access Flag
         const ACCESS_FLAG = 1 << 10;
+        /// Not global: the TLB entries are tagged with the ASID
+        const NG = 1 << 11;
         /// Unprivileged execute-never, stage 1 only
         const UXN = 1 << 54;
         /// Privileged execute-never, stage 1 only
         const PXN = 1 << 53;
+        /// copy-on-write (reserved for software)
+        const COW = 1 << 55;
+        /// freed before it is written (reserved for software)
+        const LAZYFREE = 1 << 56;
+        /// in the compressed swap, for an invalid entry (reserved for software)
+        const SWAPPED = 1 << 57;
 
         // TODO: are these necessary?
         const MEM_ATTR_IDX_0 = (0 << 2);
@@ -53,7 +61,9 @@ bitflags! {
 
 impl From<AccessFlags> for PteFlags {
     fn from(item: AccessFlags) -> Self {
+        // The kernel's page table has an ASID of its own as well, so no entry is global.
         Self::ACCESS_FLAG
+            | Self::NG
             | match item {
                 AccessFlags::R => {
                     // Privileged Read-Only
@@ -138,6 +148,25 @@ impl IPageTableEntry for PageTableEntry {
             && self.flag_intersects(Self::EntryFlags::PAGE | Self::EntryFlags::ACCESS_FLAG)
     }
 
+    fn get_access_flags(&self) -> AccessFlags {
+        let flags = self.get_flags();
+        // Every valid page is readable. AP[2] makes the page read-only, and
+        // AP[1] makes it accessible from EL0.
+        let mut ret = AccessFlags::R;
+        if !flags.contains(Self::EntryFlags::RO_P) {
+            ret |= AccessFlags::W;
+        }
+        if flags.contains(Self::EntryFlags::U) {
+            ret |= AccessFlags::U;
+            if !flags.contains(Self::EntryFlags::UXN) {
+                ret |= AccessFlags::X;
+            }
+        } else if !flags.contains(Self::EntryFlags::PXN) {
+            ret |= AccessFlags::X;
+        }
+        ret
+    }
+
     /// Make the entry refer to a given page-table page.
     fn set_table(&mut self, page: *mut RawPageTable) {
         self.inner = pa2pte((page as usize).into())
@@ -164,6 +193,60 @@ impl IPageTableEntry for PageTableEntry {
         self.inner &= !(Self::EntryFlags::U.bits());
     }
 
+    fn is_cow(&self) -> bool {
+        self.flag_intersects(Self::EntryFlags::COW)
+    }
+
+    fn is_lazyfree(&self) -> bool {
+        self.flag_intersects(Self::EntryFlags::LAZYFREE)
+    }
+
+    fn set_cow(&mut self, lazyfree: bool) {
+        // AP[2] makes the page read-only.
+        self.inner &= !Self::EntryFlags::LAZYFREE.bits();
+        self.inner |= (Self::EntryFlags::RO_P | Self::EntryFlags::COW).bits();
+        if lazyfree {
+            self.inner |= Self::EntryFlags::LAZYFREE.bits();
+        }
+    }
+
+    fn clear_cow(&mut self) {
+        self.inner &=
+            !(Self::EntryFlags::RO_P | Self::EntryFlags::COW | Self::EntryFlags::LAZYFREE).bits();
+    }
+
+    // A page descriptor is told apart from a table descriptor by its access flag, and the
+    // hardware does not manage a dirty bit without FEAT_HAFDBS. Hence, every page counts as
+    // accessed, and every writable page as dirty.
+    fn is_accessed(&self) -> bool {
+        true
+    }
+
+    fn is_dirty(&self) -> bool {
+        !self.flag_intersects(Self::EntryFlags::RO_P)
+    }
+
+    fn set_accessed(&mut self, _write: bool) {}
+
+    fn clear_accessed(&mut self) {}
+
+    fn swapped(&self) -> Option<usize> {
+        if self.is_valid() || !self.flag_intersects(Self::EntryFlags::SWAPPED) {
+            return None;
+        }
+        Some(self.get_pa().into_usize() / PGSIZE)
+    }
+
+    fn swap_out(&mut self, slot: usize) {
+        let flags = self.get_flags() - Self::EntryFlags::V;
+        self.inner = pa2pte((slot * PGSIZE).into()) | (flags | Self::EntryFlags::SWAPPED).bits();
+    }
+
+    fn swap_in(&mut self, pa: PAddr) {
+        let flags = self.get_flags() - Self::EntryFlags::SWAPPED;
+        self.set_entry(pa, flags);
+    }
+
     /// Invalidate the entry by making every bit 0.
     fn invalidate(&mut self) {
         self.inner = 0;
@@ -172,8 +255,8 @@ impl IPageTableEntry for PageTableEntry {
 
 impl Armv8 {
     // TODO: put ARM's counterpart of SiFive Test Finisher here
-    // GIC
-    const DEV_MAPPING: [(usize, usize); 1] = [(GIC, Armv8::UART0 - GIC)];
+    // GIC and PL031 RTC
+    const DEV_MAPPING: [(usize, usize); 2] = [(GIC, Armv8::UART0 - GIC), (RTC, PGSIZE)];
 }
 
 impl PageTableManager for Armv8 {
@@ -205,4 +288,17 @@ impl PageTableManager for Armv8 {
         isb();
         tlbi_vmalle1();
     }
+
+    fn flush_tlb() {
+        // Make the updates of the page tables visible to the table walker first.
+        unsafe { asm!("dsb ishst") };
+        tlbi_vmalle1();
+        unsafe { asm!("dsb ish") };
+        isb();
+    }
+
+    fn num_asids() -> usize {
+        // Every ARMv8-A CPU supports at least 8-bit ASIDs.
+        1 << 8
+    }
 }
@@ -3,7 +3,8 @@ use core::fmt;
 use crate::{
     addr::{Addr, PAddr},
     arch::TargetArch,
-    proc::RegNum,
+    param::NTRIGGER,
+    proc::{RegNum, Trigger},
     trap::TrapTypes,
     vm::{AccessFlags, RawPageTable},
 };
@@ -11,7 +12,14 @@ use crate::{
 // TODO: Is this abstraction appropriate?
 
 pub trait Arch:
-    PageTableManager + MemLayout + TimeManager + TrapManager + InterruptManager + ProcManager + PowerOff
+    PageTableManager
+    + MemLayout
+    + TimeManager
+    + TrapManager
+    + InterruptManager
+    + ProcManager
+    + PowerOff
+    + PowerManager
 {
     type Uart: UartManager;
 
@@ -28,6 +36,15 @@ pub trait MemLayout {
     /// virtio mmio interface
     const VIRTIO0: usize;
 
+    /// virtio mmio interface of the entropy device
+    const VIRTIO1: usize;
+
+    /// virtio mmio interface of the console device
+    const VIRTIO2: usize;
+
+    /// virtio mmio interface of the 9p transport
+    const VIRTIO3: usize;
+
     /// the kernel expects there to be RAM
     /// for use by the kernel and user pages
     /// from physical address KERNBASE to PHYSTOP.
@@ -35,16 +52,52 @@ pub trait MemLayout {
 
     const UART0_IRQ: usize;
     const VIRTIO0_IRQ: usize;
+    const VIRTIO2_IRQ: usize;
+    const VIRTIO3_IRQ: usize;
 }
 
 pub trait TimeManager {
     fn timer_init();
 
+    /// Sets the interval between timer interrupts of every CPU, in microseconds.
+    /// It takes effect from the next timer interrupt of each CPU.
+    fn set_tick_interval(us: usize);
+
+    /// Returns the interval between timer interrupts, in microseconds.
+    fn tick_interval() -> usize;
+
+    /// Stops the timer interrupts of the current CPU until `timer_resume` is called.
+    fn timer_stop();
+
+    /// Resumes the timer interrupts of the current CPU.
+    fn timer_resume();
+
     /// The uptime since power-on of the device, in microseconds.
     /// This includes time consumed by firmware and bootloaders.
     fn uptime_as_micro() -> Result<usize, ()>;
 
+    /// Reads the real-time clock, in microseconds since the Unix epoch.
+    fn rtc_micro() -> usize;
+
     fn r_cycle() -> usize;
+
+    /// Reads the number of instructions retired, or 0 if the CPU does not count them.
+    fn r_instret() -> usize;
+
+    /// Waits for the earlier instructions to complete before the later ones begin.
+    fn serialize();
+
+    /// Reads the hardware performance counter `i` of the current CPU, or 0 if the CPU does not
+    /// have it.
+    fn r_perf_counter(i: usize) -> usize;
+
+    /// Sets the event that the hardware performance counter `i` of the current CPU counts, where
+    /// 0 counts nothing. Events are numbered by the CPU.
+    ///
+    /// # Safety
+    ///
+    /// `i` must be less than `NPERFCTR`.
+    unsafe fn set_perf_event(i: usize, event: usize);
 }
 
 pub trait TrapManager {
@@ -132,11 +185,22 @@ pub trait TrapManager {
     /// Must be called by `user_trap_ret`, after handling the user trap.
     unsafe fn user_trap_ret(
         user_pagetable_addr: usize,
+        asid: usize,
         trap: &mut <TargetArch as ProcManager>::TrapFrame,
         kernel_stack: usize,
         usertrap: usize,
     ) -> !;
 
+    /// Returns how many hardware triggers of the CPU a process can use, at most `NTRIGGER`.
+    fn ntrigger() -> usize;
+
+    /// Load the hardware triggers of the process about to return to user space into the CPU.
+    ///
+    /// # Safety
+    ///
+    /// Must be called by `user_trap_ret` with interrupts disabled.
+    unsafe fn load_triggers(triggers: &[Trigger; NTRIGGER]);
+
     fn save_trap_regs(store: &mut [usize; 10]);
 
     /// Restore trap registers from `store`.
@@ -151,6 +215,18 @@ pub trait TrapManager {
 pub trait PowerOff {
     /// Shutdowns this machine, discarding all unsaved data.
     fn machine_poweroff(_exitcode: u16) -> !;
+
+    /// Restarts this machine from its reset vector, discarding all unsaved data.
+    fn machine_reboot() -> !;
+}
+
+pub trait PowerManager {
+    /// Suspends the current CPU until an interrupt arrives, retaining its state.
+    fn cpu_suspend();
+
+    /// Sets the performance state of every CPU, where 0 is the fastest.
+    /// Returns Err(()) if the state is not supported.
+    fn set_perf_level(level: usize) -> Result<(), ()>;
 }
 
 pub trait InterruptManager {
@@ -169,6 +245,9 @@ pub trait InterruptManager {
     /// * Must be called only once for each core.
     /// * Must be called before any interrupt occurs.
     unsafe fn intr_init_core();
+
+    /// Raises an inter-processor interrupt on the CPU `cpu`, which arrives as `TrapTypes::Ipi`.
+    fn send_ipi(cpu: usize);
 }
 
 pub trait ProcManager {
@@ -184,9 +263,15 @@ pub trait ProcManager {
 }
 
 pub trait TrapFrameManager: Copy + Clone {
+    /// Get user pc.
+    fn get_pc(&self) -> usize;
+
     /// Set user pc.
     fn set_pc(&mut self, val: usize);
 
+    /// Get the value of user stack pointer.
+    fn get_sp(&self) -> usize;
+
     /// Set the value of user stack pointer.
     fn set_sp(&mut self, val: usize);
 
@@ -226,6 +311,14 @@ pub trait PageTableManager {
     ///
     /// `page_table_base` must contain base address for a valid page table, containing mapping for current pc.
     unsafe fn switch_page_table_and_enable_mmu(page_table_base: usize);
+
+    /// Flushes every TLB entry of the current CPU.
+    fn flush_tlb();
+
+    /// Returns the number of address space identifiers (ASIDs) of the CPUs. The kernel's page
+    /// table uses ASID 0. If it is 1, user page tables use ASID 0 as well, and the TLB is
+    /// flushed on every switch between page tables.
+    fn num_asids() -> usize;
 }
 
 /// # Safety
@@ -250,6 +343,9 @@ pub trait IPageTableEntry: Default {
 
     fn is_data(&self) -> bool;
 
+    /// Return the access permissions of the data page this entry refers to.
+    fn get_access_flags(&self) -> AccessFlags;
+
     /// Make the entry refer to a given page-table page.
     fn set_table(&mut self, page: *mut RawPageTable);
 
@@ -261,6 +357,44 @@ pub trait IPageTableEntry: Default {
     /// Make the entry inaccessible by user processes by clearing PteFlags::U.
     fn clear_user(&mut self);
 
+    /// Return whether the entry is copy-on-write: it is read-only only until the first write,
+    /// which gets the page a copy of its own if it is shared.
+    fn is_cow(&self) -> bool;
+
+    /// Return whether the entry is copy-on-write, and its page may be freed before it is written.
+    fn is_lazyfree(&self) -> bool;
+
+    /// Make the entry copy-on-write by taking its write permission away. If `lazyfree`, its page
+    /// may be freed before it is written.
+    fn set_cow(&mut self, lazyfree: bool);
+
+    /// Give a copy-on-write entry its write permission back.
+    fn clear_cow(&mut self);
+
+    /// Return whether the page has been accessed since its accessed bit was cleared.
+    fn is_accessed(&self) -> bool;
+
+    /// Return whether the page has been written since its dirty bit was cleared.
+    fn is_dirty(&self) -> bool;
+
+    /// Set the accessed bit, and the dirty bit as well if `write`, for hardware that faults
+    /// rather than setting them.
+    fn set_accessed(&mut self, write: bool);
+
+    /// Clear the accessed and dirty bits.
+    fn clear_accessed(&mut self);
+
+    /// Return the slot of the compressed swap that holds the page of the entry, if its page has
+    /// been swapped out.
+    fn swapped(&self) -> Option<usize>;
+
+    /// Make the entry invalid, but keep its permissions, and refer to a given slot of the
+    /// compressed swap rather than a page.
+    fn swap_out(&mut self, slot: usize);
+
+    /// Make a swapped-out entry refer to a given page again, with the permissions it had.
+    fn swap_in(&mut self, pa: PAddr);
+
     /// Invalidate the entry by making every bit 0.
     fn invalidate(&mut self);
 
@@ -275,6 +409,18 @@ pub trait IPageTableEntry: Default {
             None
         }
     }
+
+    /// Return `Some(..)` if it refers to a page-table page.
+    /// Return `None` if it refers to a data page.
+    /// Return `None` if it is invalid.
+    fn as_table(&self) -> Option<&RawPageTable> {
+        if self.is_table() {
+            // SAFETY: invariant.
+            Some(unsafe { &*(self.get_pa().into_usize() as *const _) })
+        } else {
+            None
+        }
+    }
 }
 
 pub trait UartManagerConst {
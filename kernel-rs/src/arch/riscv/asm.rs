@@ -262,6 +262,47 @@ pub unsafe fn w_mtvec(x: usize) {
     }
 }
 
+/// Writes tdata1 and tdata2 of the debug trigger `i`. The trigger registers belong to machine
+/// mode, so timervec in kernelvec.S writes them for supervisor mode at an ecall.
+#[inline]
+pub unsafe fn w_trigger(i: usize, tdata1: usize, tdata2: usize) {
+    unsafe {
+        asm!("ecall", in("a0") i, in("a1") tdata1, in("a2") tdata2, in("a3") 0usize);
+    }
+}
+
+/// Sets the event that hpmcounter 3 + `i` counts, for `i` less than 4. The event selectors
+/// belong to machine mode, so timervec in kernelvec.S writes them at an ecall.
+#[inline]
+pub unsafe fn w_hpmevent(i: usize, event: usize) {
+    unsafe {
+        asm!("ecall", in("a0") i, in("a1") event, in("a3") 1usize);
+    }
+}
+
+/// Reads hpmcounter 3 + `i`, for `i` less than 4.
+#[inline]
+pub fn r_hpmcounter(i: usize) -> usize {
+    let mut x = 0;
+    unsafe {
+        match i {
+            0 => asm!("csrr {}, hpmcounter3", out(reg) x),
+            1 => asm!("csrr {}, hpmcounter4", out(reg) x),
+            2 => asm!("csrr {}, hpmcounter5", out(reg) x),
+            3 => asm!("csrr {}, hpmcounter6", out(reg) x),
+            _ => (),
+        }
+    }
+    x
+}
+
+/// tdata1 of an address match trigger (mcontrol) that raises a breakpoint exception in user
+/// mode, before the matching access. Add `TDATA1_EXECUTE`, `TDATA1_STORE`, or `TDATA1_LOAD`.
+pub const TDATA1_MCONTROL: usize = (2 << 60) | (1 << 3);
+pub const TDATA1_EXECUTE: usize = 1 << 2;
+pub const TDATA1_STORE: usize = 1 << 1;
+pub const TDATA1_LOAD: usize = 1 << 0;
+
 /// Use riscv's sv39 page table scheme.
 pub const SATP_SV39: usize = (8) << 60;
 
@@ -269,6 +310,10 @@ pub const fn make_satp(pagetable: usize) -> usize {
     SATP_SV39 | pagetable >> 12
 }
 
+/// The ASID field of satp.
+pub const SATP_ASID_SHIFT: usize = 44;
+pub const SATP_ASID_MASK: usize = 0xffff << SATP_ASID_SHIFT;
+
 /// Supervisor address translation and protection;
 /// holds the address of the page table.
 #[inline]
@@ -1,9 +1,11 @@
 //! the riscv Platform Level Interrupt Controller (PLIC).
+use core::ptr;
+
 use crate::arch::{
     asm::r_tp,
     interface::InterruptManager,
     interface::MemLayout,
-    memlayout::{plic_sclaim, plic_senable, plic_spriority, PLIC},
+    memlayout::{clint_msip, plic_sclaim, plic_senable, plic_spriority, PLIC},
     RiscV,
 };
 
@@ -12,6 +14,8 @@ impl InterruptManager for RiscV {
         // set desired IRQ priorities non-zero (otherwise disabled).
         unsafe { *((PLIC.wrapping_add(RiscV::UART0_IRQ.wrapping_mul(4))) as *mut u32) = 1 };
         unsafe { *((PLIC + RiscV::VIRTIO0_IRQ * 4) as *mut u32) = 1 };
+        unsafe { *((PLIC + RiscV::VIRTIO2_IRQ * 4) as *mut u32) = 1 };
+        unsafe { *((PLIC + RiscV::VIRTIO3_IRQ * 4) as *mut u32) = 1 };
     }
 
     unsafe fn intr_init_core() {
@@ -19,13 +23,22 @@ impl InterruptManager for RiscV {
 
         // set uart's enable bit for this hart's S-mode.
         unsafe {
-            *(plic_senable(hart) as *mut u32) =
-                (1 << RiscV::UART0_IRQ | 1 << RiscV::VIRTIO0_IRQ) as u32
+            *(plic_senable(hart) as *mut u32) = (1 << RiscV::UART0_IRQ
+                | 1 << RiscV::VIRTIO0_IRQ
+                | 1 << RiscV::VIRTIO2_IRQ
+                | 1 << RiscV::VIRTIO3_IRQ) as u32
         };
 
         // set this hart's S-mode priority threshold to 0.
         unsafe { *(plic_spriority(hart) as *mut u32) = 0 };
     }
+
+    /// Raises a machine-mode software interrupt on the hart, which timervec in kernelvec.S
+    /// forwards as a supervisor software interrupt.
+    fn send_ipi(cpu: usize) {
+        // SAFETY: the kernel page table maps the MSIP registers of the CLINT.
+        unsafe { ptr::write_volatile(clint_msip(cpu) as *mut u32, 1) };
+    }
 }
 
 /// ask the PLIC what interrupt we should serve.
@@ -4,10 +4,14 @@
 //! based on qemu's hw/riscv/virt.c:
 //!
 //! 00001000 -- boot ROM, provided by qemu
+//! 00101000 -- goldfish RTC
 //! 02000000 -- CLINT
 //! 0C000000 -- PLIC
 //! 10000000 -- uart0
 //! 10001000 -- virtio disk
+//! 10002000 -- virtio entropy device
+//! 10003000 -- virtio console
+//! 10004000 -- virtio 9p transport
 //! 80000000 -- boot ROM jumps here in machine mode
 //!             -kernel loads the kernel here
 //! unused RAM after 80000000.
@@ -33,11 +37,19 @@ impl MemLayout for RiscV {
     /// virtio mmio interface
     const VIRTIO0: usize = 0x10001000;
     const VIRTIO0_IRQ: usize = 1;
+    const VIRTIO1: usize = 0x10002000;
+    const VIRTIO2: usize = 0x10003000;
+    const VIRTIO2_IRQ: usize = 3;
+    const VIRTIO3: usize = 0x10004000;
+    const VIRTIO3_IRQ: usize = 4;
 }
 
 /// SiFive Test Finisher. (virt device only)
 pub const FINISHER: usize = 0x100000;
 
+/// Goldfish real-time clock. (virt device only)
+pub const RTC: usize = 0x101000;
+
 /// core local interruptor (CLINT), which contains the timer.
 pub const CLINT: usize = 0x2000000;
 pub const fn clint_mtimecmp(hartid: usize) -> usize {
@@ -46,6 +58,11 @@ pub const fn clint_mtimecmp(hartid: usize) -> usize {
         .wrapping_add(hartid.wrapping_mul(8))
 }
 
+/// Writing 1 raises a machine-mode software interrupt on the hart, and writing 0 clears it.
+pub const fn clint_msip(hartid: usize) -> usize {
+    CLINT.wrapping_add(hartid.wrapping_mul(4))
+}
+
 /// cycles since boot.
 pub const CLINT_MTIME: usize = CLINT.wrapping_add(0xbff8);
 
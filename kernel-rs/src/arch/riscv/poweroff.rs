@@ -1,7 +1,7 @@
 use core::ptr;
 
 use super::RiscV;
-use crate::arch::interface::PowerOff;
+use crate::arch::interface::{PowerManager, PowerOff};
 use crate::arch::memlayout;
 
 impl PowerOff for RiscV {
@@ -22,4 +22,31 @@ impl PowerOff for RiscV {
 
         unreachable!("Power off failed");
     }
+
+    /// Restarts this machine, discarding all unsaved data.
+    ///
+    /// This function uses SiFive Test Finalizer, which resets the whole QEMU virt device and
+    /// reloads the kernel image.
+    fn machine_reboot() -> ! {
+        const RESET_CODE: u32 = 0x7777;
+        // SAFETY: see machine_poweroff.
+        unsafe {
+            ptr::write_volatile(memlayout::FINISHER as *mut u32, RESET_CODE);
+        }
+
+        unreachable!("Reboot failed");
+    }
+}
+
+impl PowerManager for RiscV {
+    /// The kernel handles machine mode by itself instead of running on SBI firmware, so it
+    /// suspends the hart with `wfi` directly, as the default retentive suspend of SBI does.
+    fn cpu_suspend() {
+        unsafe { asm!("wfi") };
+    }
+
+    /// QEMU virt device does not have performance states.
+    fn set_perf_level(_level: usize) -> Result<(), ()> {
+        Err(())
+    }
 }
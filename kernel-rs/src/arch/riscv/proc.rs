@@ -170,10 +170,18 @@ pub struct TrapFrame {
 }
 
 impl const TrapFrameManager for TrapFrame {
+    fn get_pc(&self) -> usize {
+        self.epc
+    }
+
     fn set_pc(&mut self, val: usize) {
         self.epc = val;
     }
 
+    fn get_sp(&self) -> usize {
+        self.sp
+    }
+
     fn set_sp(&mut self, val: usize) {
         self.sp = val;
     }
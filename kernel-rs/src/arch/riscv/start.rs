@@ -1,16 +1,22 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::{
     arch::asm::{
-        r_mhartid, w_medeleg, w_mepc, w_mideleg, w_mscratch, w_mtvec, w_satp, w_tp, Mstatus, MIE,
-        SIE,
+        r_mhartid, w_mcounteren, w_medeleg, w_mepc, w_mideleg, w_mscratch, w_mtvec, w_satp, w_tp,
+        Mstatus, MIE, SIE, TDATA1_MCONTROL,
     },
-    arch::memlayout::{clint_mtimecmp, CLINT_MTIME},
+    arch::memlayout::{clint_msip, clint_mtimecmp, CLINT_MTIME},
     kernel::main,
-    param::NCPU,
+    param::{NCPU, NTRIGGER, TICK_US},
 };
 
 extern "C" {
-    // assembly code in kernelvec.S for machine-mode timer interrupt.
+    // assembly code in kernelvec.S for machine-mode timer and software interrupts,
+    // and for writing debug triggers.
     fn timervec();
+
+    // assembly code in kernelvec.S for counting debug triggers.
+    fn probevec();
 }
 
 /// entry.S needs one stack per CPU.
@@ -27,11 +33,55 @@ impl Stack {
 #[no_mangle]
 pub static mut stack0: Stack = Stack::new();
 
-/// A scratch area per CPU for machine-mode timer interrupts.
-static mut TIMER_SCRATCH: [[usize; NCPU]; 5] = [[0; NCPU]; 5];
+/// Size of the emergency stack of a CPU.
+const EMERGENCY_STACK_SIZE: usize = 2 * 4096;
+
+/// kernelvec.S handles exceptions from kernel code on one of these stacks, one per CPU, since
+/// the stack of the faulting code may be broken. The two words at the top of each hold a
+/// scratch register and the number of exceptions taken on it.
+#[derive(Debug)]
+#[repr(C, align(16))]
+pub struct EmergencyStack([[u8; EMERGENCY_STACK_SIZE]; NCPU]);
+
+impl EmergencyStack {
+    const fn new() -> Self {
+        Self([[0; EMERGENCY_STACK_SIZE]; NCPU])
+    }
+}
+
+static mut EMERGENCY_STACK: EmergencyStack = EmergencyStack::new();
+
+/// Returns the top of the emergency stack of the CPU `hart`.
+pub fn emergency_stack_top(hart: usize) -> usize {
+    // SAFETY: only the address is taken.
+    unsafe { &raw const EMERGENCY_STACK.0[hart] as usize + EMERGENCY_STACK_SIZE }
+}
+
+/// A scratch area per CPU for machine-mode timer and software interrupts.
+pub static mut TIMER_SCRATCH: [[usize; 8]; NCPU] = [[0; 8]; NCPU];
+
+/// Index of the interval between timer interrupts in a `TIMER_SCRATCH` area.
+pub const SCRATCH_INTERVAL: usize = 4;
+
+/// Index of the flag to skip timer interrupts in a `TIMER_SCRATCH` area.
+pub const SCRATCH_SKIP: usize = 5;
+
+/// Index of the flag that a timer interrupt has been forwarded to supervisor mode, in a
+/// `TIMER_SCRATCH` area. Forwarded inter-processor interrupts do not set it.
+pub const SCRATCH_TICK: usize = 7;
+
+/// Number of CLINT time units per microsecond; the CLINT of qemu runs at 10MHz.
+pub const MTIME_PER_US: usize = 10;
+
+/// Number of debug triggers of each CPU that can match user addresses, at most `NTRIGGER`.
+pub static NTRIGGERS: AtomicUsize = AtomicUsize::new(0);
 
 /// entry.S jumps here in machine mode on stack0.
 pub unsafe fn start() {
+    // count the debug triggers, before setting up mstatus for mret,
+    // since probevec returns from its traps with mret.
+    unsafe { triggerinit() };
+
     // set M Previous Privilege mode to Supervisor, for mret.
     let mut x = Mstatus::read();
     x.remove(Mstatus::MPP_MASK);
@@ -44,8 +94,13 @@ pub unsafe fn start() {
     // disable paging for now.
     unsafe { w_satp(0) };
 
-    // delegate all interrupts and exceptions to supervisor mode.
-    unsafe { w_medeleg(0xffff) };
+    // let supervisor mode read the cycle, time, and instret counters,
+    // and hpmcounter3..6.
+    unsafe { w_mcounteren(0x7f) };
+
+    // delegate all interrupts and exceptions to supervisor mode,
+    // except ecalls from supervisor mode, which ask timervec to write debug triggers.
+    unsafe { w_medeleg(0xffff & !(1 << 9)) };
     unsafe { w_mideleg(0xffff) };
     let mut x = SIE::read();
     x.insert(SIE::SEIE);
@@ -65,24 +120,62 @@ pub unsafe fn start() {
     }
 }
 
-/// set up to receive timer interrupts in machine mode,
+/// count the debug triggers that can match addresses, which are those that
+/// keep the type of an address match trigger (mcontrol) written to them.
+/// the triggers are left matching nothing.
+unsafe fn triggerinit() {
+    unsafe { w_mtvec(probevec as _) };
+
+    let mut n = 0;
+    while n < NTRIGGER {
+        let (selected, tdata1, failed): (usize, usize, usize);
+        // SAFETY: probevec skips the instructions that raise an exception, setting t6.
+        unsafe {
+            asm!(
+                "li t6, 0",
+                "csrw tselect, {n}",
+                "csrr {selected}, tselect",
+                "csrw tdata1, {tdata1}",
+                "csrr {tdata1}, tdata1",
+                n = in(reg) n,
+                selected = out(reg) selected,
+                tdata1 = inout(reg) TDATA1_MCONTROL => tdata1,
+                out("t6") failed,
+            );
+        }
+        if failed != 0 || selected != n || tdata1 >> 60 != TDATA1_MCONTROL >> 60 {
+            break;
+        }
+        n += 1;
+    }
+    // every CPU has the same triggers.
+    NTRIGGERS.store(n, Ordering::Relaxed);
+}
+
+/// set up to receive timer and software interrupts in machine mode,
 /// which arrive at timervec in kernelvec.S,
-/// which turns them into software interrupts for devintr() in trap.c.
+/// which turns them into supervisor software interrupts for `get_trap_type`.
 unsafe fn timerinit() {
     // each CPU has a separate source of timer interrupts.
     let id = r_mhartid();
 
     // ask the CLINT for a timer interrupt.
-    let interval: usize = 1_000_000; // cycles; about 1/10th second in qemu.
+    let interval: usize = TICK_US * MTIME_PER_US;
     unsafe { *(clint_mtimecmp(id) as *mut usize) = (*(CLINT_MTIME as *mut usize)) + interval };
 
     // prepare information in scratch[] for timervec.
     // scratch[0..2] : space for timervec to save registers.
     // scratch[3] : address of CLINT MTIMECMP register.
     // scratch[4] : desired interval (in cycles) between timer interrupts.
+    // scratch[5] : if nonzero, timer interrupts are not passed to supervisor mode.
+    // scratch[6] : address of CLINT MSIP register.
+    // scratch[7] : set when a timer interrupt is passed to supervisor mode.
     let scratch = unsafe { &mut TIMER_SCRATCH[id][..] };
     *unsafe { scratch.get_unchecked_mut(3) } = clint_mtimecmp(id);
-    *unsafe { scratch.get_unchecked_mut(4) } = interval;
+    *unsafe { scratch.get_unchecked_mut(SCRATCH_INTERVAL) } = interval;
+    *unsafe { scratch.get_unchecked_mut(SCRATCH_SKIP) } = 0;
+    *unsafe { scratch.get_unchecked_mut(6) } = clint_msip(id);
+    *unsafe { scratch.get_unchecked_mut(SCRATCH_TICK) } = 0;
     unsafe { w_mscratch(&scratch[0] as *const _ as usize) };
 
     // set the machine-mode trap handler.
@@ -93,8 +186,10 @@ unsafe fn timerinit() {
     x.insert(Mstatus::MIE);
     unsafe { x.write() };
 
-    // enable machine-mode timer interrupts.
+    // enable machine-mode timer interrupts, and software interrupts,
+    // which other CPUs raise as inter-processor interrupts.
     let mut y = MIE::read();
     y.insert(MIE::MTIE);
+    y.insert(MIE::MSIE);
     unsafe { y.write() };
 }
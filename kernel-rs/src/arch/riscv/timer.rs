@@ -1,4 +1,11 @@
-use super::RiscV;
+use core::ptr;
+
+use super::{
+    asm::{cpu_id, r_hpmcounter, w_hpmevent},
+    memlayout::{CLINT_MTIME, RTC},
+    start::{MTIME_PER_US, SCRATCH_INTERVAL, SCRATCH_SKIP, TIMER_SCRATCH},
+    RiscV,
+};
 use crate::arch::interface::TimeManager;
 
 impl TimeManager for RiscV {
@@ -6,10 +13,47 @@ impl TimeManager for RiscV {
         // nothing to do
     }
 
-    /// The uptime since power-on of the device, in microseconds.
-    /// This function is only supporeted on ARM now.
+    fn set_tick_interval(us: usize) {
+        // SAFETY: timervec in machine mode only reads the interval.
+        let scratches = unsafe { &mut *(&raw mut TIMER_SCRATCH) };
+        for scratch in scratches.iter_mut() {
+            // SAFETY: `scratch[SCRATCH_INTERVAL]` is a valid location.
+            unsafe { ptr::write_volatile(&mut scratch[SCRATCH_INTERVAL], us * MTIME_PER_US) };
+        }
+    }
+
+    fn tick_interval() -> usize {
+        // SAFETY: the interval is written only by `set_tick_interval`.
+        unsafe { ptr::read_volatile(&raw const TIMER_SCRATCH[0][SCRATCH_INTERVAL]) / MTIME_PER_US }
+    }
+
+    fn timer_stop() {
+        // The machine-mode timer interrupts keep arriving, but timervec does not pass them to
+        // supervisor mode.
+        // SAFETY: timervec in machine mode only reads the flag.
+        unsafe { ptr::write_volatile(&raw mut TIMER_SCRATCH[cpu_id()][SCRATCH_SKIP], 1) };
+    }
+
+    fn timer_resume() {
+        // SAFETY: timervec in machine mode only reads the flag.
+        unsafe { ptr::write_volatile(&raw mut TIMER_SCRATCH[cpu_id()][SCRATCH_SKIP], 0) };
+    }
+
     fn uptime_as_micro() -> Result<usize, ()> {
-        todo!()
+        // SAFETY: the kernel page table maps the MTIME register of the CLINT.
+        let mtime = unsafe { ptr::read_volatile(CLINT_MTIME as *const usize) };
+        Ok(mtime / MTIME_PER_US)
+    }
+
+    fn rtc_micro() -> usize {
+        // Reading the low half latches the high half.
+        // SAFETY: the kernel page table maps the goldfish RTC.
+        let nanos = unsafe {
+            let low = ptr::read_volatile(RTC as *const u32) as usize;
+            let high = ptr::read_volatile((RTC + 4) as *const u32) as usize;
+            high << 32 | low
+        };
+        nanos / 1000
     }
 
     fn r_cycle() -> usize {
@@ -19,4 +63,27 @@ impl TimeManager for RiscV {
         }
         x
     }
+
+    fn r_instret() -> usize {
+        let mut x;
+        unsafe {
+            asm!("rdinstret {}", out(reg) x);
+        }
+        x
+    }
+
+    fn serialize() {
+        // fence orders the memory accesses, and fence.i the instruction fetches.
+        unsafe {
+            asm!("fence", "fence.i");
+        }
+    }
+
+    fn r_perf_counter(i: usize) -> usize {
+        r_hpmcounter(i)
+    }
+
+    unsafe fn set_perf_event(i: usize, event: usize) {
+        unsafe { w_hpmevent(i, event) };
+    }
 }
@@ -1,18 +1,26 @@
 use core::fmt;
 use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::{
     addr::PGSIZE,
     arch::asm::{
         intr_get, intr_off, intr_on, make_satp, r_satp, r_scause, r_sepc, r_sip, r_stval, r_tp,
-        w_sepc, w_sip, w_stvec, Sstatus,
+        w_sepc, w_sip, w_sscratch, w_stvec, w_trigger, Sstatus, SATP_ASID_SHIFT, TDATA1_EXECUTE,
+        TDATA1_LOAD, TDATA1_MCONTROL, TDATA1_STORE,
     },
     arch::interface::{MemLayout, TrapManager},
     arch::intr::{plic_claim, plic_complete},
     arch::proc::TrapFrame,
+    arch::start::{emergency_stack_top, NTRIGGERS, SCRATCH_TICK, TIMER_SCRATCH},
     arch::RiscV,
+    console::Severity,
+    kernel::kernel_ref,
     memlayout::{TRAMPOLINE, TRAPFRAME},
+    param::NTRIGGER,
+    proc::{Trigger, TRIGGER_EXEC, TRIGGER_LOAD, TRIGGER_STORE},
     trap::{IrqNum, IrqTypes, TrapTypes},
+    util::spin_loop,
 };
 
 extern "C" {
@@ -23,15 +31,138 @@ extern "C" {
 
     static mut userret: [u8; 0];
 
-    // In kernelvec.S, calls kerneltrap().
+    // In kernelvec.S, calls kerneltrap(), or kernelfault() for exceptions.
     fn kernelvec();
 }
 
+/// Bit i is set if the debug triggers of the CPU i may match, since it has returned to a process
+/// with triggers and has not cleared them since.
+static TRIGGERS_ARMED: AtomicUsize = AtomicUsize::new(0);
+
+/// Names of the registers x0..x31.
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// The state of kernel code that took an exception.
+struct Fault<'a> {
+    /// x0..x31, as kernelvec.S saved them.
+    regs: &'a [usize; 32],
+    scause: usize,
+    sepc: usize,
+    stval: usize,
+    sstatus: usize,
+    satp: usize,
+}
+
+impl Fault<'_> {
+    fn cause(&self) -> &'static str {
+        match self.scause {
+            0 => "instruction address misaligned",
+            1 => "instruction access fault",
+            2 => "illegal instruction",
+            3 => "breakpoint",
+            4 => "load address misaligned",
+            5 => "load access fault",
+            6 => "store address misaligned",
+            7 => "store access fault",
+            9 => "environment call from supervisor mode",
+            12 => "instruction page fault",
+            13 => "load page fault",
+            15 => "store page fault",
+            _ => "unknown exception",
+        }
+    }
+
+    /// Returns true if the fault is a page fault near the stack pointer, which is what a
+    /// stack overflow into the guard page looks like.
+    fn near_sp(&self) -> bool {
+        let sp = self.regs[2];
+        matches!(self.scause, 12 | 13 | 15)
+            && self.stval.wrapping_sub(sp).wrapping_add(PGSIZE) < 2 * PGSIZE
+    }
+}
+
+impl fmt::Display for Fault<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} (scause {:#x}) at sepc={:#018x} stval={:#018x}",
+            self.cause(),
+            self.scause,
+            self.sepc,
+            self.stval
+        )?;
+        writeln!(f, "sstatus={:#018x} satp={:#018x}", self.sstatus, self.satp)?;
+        for (i, (name, reg)) in REG_NAMES.iter().zip(self.regs.iter()).enumerate() {
+            write!(f, "{:>4}={:#018x}", name, reg)?;
+            f.write_str(if i % 4 == 3 { "\n" } else { " " })?;
+        }
+        if self.near_sp() {
+            f.write_str("the fault is near sp, so the kernel stack may have overflowed\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Exceptions from kernel code go here via kernelvec, on the emergency stack of the CPU.
+/// `regs` holds x0..x31 of the faulting code, and `depth` counts the exceptions taken on the
+/// emergency stack, including this one. The first one panics with a dump of the registers, and
+/// the second one, which reporting the first one has caused, is reported briefly.
+#[no_mangle]
+pub unsafe extern "C" fn kernelfault(regs: &[usize; 32], depth: usize) -> ! {
+    let fault = Fault {
+        regs,
+        scause: r_scause(),
+        sepc: r_sepc(),
+        stval: r_stval(),
+        sstatus: Sstatus::read().bits(),
+        satp: r_satp(),
+    };
+    if depth == 1 {
+        panic!("kernel fault: {}", fault);
+    }
+
+    // The registers may be what reporting the first exception tripped over.
+    // SAFETY: kernelfault can be reached only after the initialization of the kernel.
+    unsafe {
+        kernel_ref(|kref| {
+            kref.as_ref().log(
+                Severity::Error,
+                format_args!(
+                    "kernel fault while reporting one: {} at sepc={:#018x} stval={:#018x}\n",
+                    fault.cause(),
+                    fault.sepc,
+                    fault.stval
+                ),
+            )
+        })
+    };
+    spin_loop()
+}
+
+/// Sends traps to kernelvec, which finds the top of the emergency stack of this CPU in
+/// sscratch.
+///
+/// # Safety
+///
+/// The kernel must be ready to handle traps.
+unsafe fn use_kernelvec() {
+    unsafe {
+        w_sscratch(emergency_stack_top(r_tp()));
+        w_stvec(kernelvec as _);
+    }
+}
+
 impl From<&IrqTypes> for IrqNum {
     fn from(item: &IrqTypes) -> Self {
         match item {
             IrqTypes::Uart => RiscV::UART0_IRQ,
             IrqTypes::Virtio => RiscV::VIRTIO0_IRQ,
+            IrqTypes::VirtioConsole => RiscV::VIRTIO2_IRQ,
+            IrqTypes::Virtio9p => RiscV::VIRTIO3_IRQ,
             IrqTypes::Unknown(i) => *i,
             IrqTypes::Others(_) => 0,
         }
@@ -54,7 +185,7 @@ impl TrapManager for RiscV {
     /// `vectors` must contain base address for a valid ARMv8-A exception vector table.
     unsafe fn trap_init_core() {
         // SAFETY: `kernelvec` contains a valid trap vector.
-        unsafe { w_stvec(kernelvec as _) };
+        unsafe { use_kernelvec() };
     }
 
     fn get_trap_type(_trap_info: usize) -> TrapTypes {
@@ -70,6 +201,8 @@ impl TrapManager for RiscV {
             match irq {
                 RiscV::UART0_IRQ => TrapTypes::Irq(IrqTypes::Uart),
                 RiscV::VIRTIO0_IRQ => TrapTypes::Irq(IrqTypes::Virtio),
+                RiscV::VIRTIO2_IRQ => TrapTypes::Irq(IrqTypes::VirtioConsole),
+                RiscV::VIRTIO3_IRQ => TrapTypes::Irq(IrqTypes::Virtio9p),
                 0 => {
                     // TODO: should we handle this?
                     TrapTypes::Irq(IrqTypes::Others(0))
@@ -77,10 +210,25 @@ impl TrapManager for RiscV {
                 _ => TrapTypes::Irq(IrqTypes::Unknown(irq)),
             }
         } else if scause == 0x8000000000000001 {
-            // Software interrupt from a machine-mode timer interrupt,
-            // forwarded by timervec in selfvec.S.
-
-            TrapTypes::TimerInterrupt
+            // Software interrupt from a machine-mode timer or software interrupt,
+            // forwarded by timervec in kernelvec.S, which flags the timer interrupts.
+            // SAFETY: `TIMER_SCRATCH[r_tp()][SCRATCH_TICK]` is a valid location, which machine
+            // mode only writes 1 to.
+            let tick =
+                unsafe { &*(&raw const TIMER_SCRATCH[r_tp()][SCRATCH_TICK] as *const AtomicUsize) };
+            if tick.swap(0, Ordering::Relaxed) != 0 {
+                TrapTypes::TimerInterrupt
+            } else {
+                TrapTypes::Ipi
+            }
+        } else if scause == 3 {
+            TrapTypes::Breakpoint
+        } else if scause == 12 || scause == 13 || scause == 15 {
+            // An instruction, load, or store page fault.
+            TrapTypes::PageFault {
+                addr: r_stval(),
+                write: scause == 15,
+            }
         } else {
             TrapTypes::BadTrap
         }
@@ -122,7 +270,7 @@ impl TrapManager for RiscV {
                     }
                 }
             }
-            TrapTypes::TimerInterrupt => {
+            TrapTypes::TimerInterrupt | TrapTypes::Ipi => {
                 // Acknowledge the software interrupt by clearing
                 // the SSIP bit in sip.
                 unsafe { w_sip(r_sip() & !2) };
@@ -159,7 +307,7 @@ impl TrapManager for RiscV {
     }
 
     unsafe fn switch_to_kernel_vec() {
-        unsafe { w_stvec(kernelvec as _) };
+        unsafe { use_kernelvec() };
     }
 
     unsafe fn switch_to_user_vec() {
@@ -174,6 +322,7 @@ impl TrapManager for RiscV {
 
     unsafe fn user_trap_ret(
         user_pagetable_addr: usize,
+        asid: usize,
         trapframe: &mut TrapFrame,
         kernel_stack: usize,
         usertrap: usize,
@@ -223,7 +372,7 @@ impl TrapManager for RiscV {
         unsafe { w_sepc(trapframe.epc) };
 
         // Tell trampoline.S the user page table to switch to.
-        let satp: usize = make_satp(user_pagetable_addr);
+        let satp: usize = make_satp(user_pagetable_addr) | (asid << SATP_ASID_SHIFT);
 
         // Jump to trampoline.S at the top of memory, which
         // switches to the user page table, restores user registers,
@@ -234,6 +383,41 @@ impl TrapManager for RiscV {
         unsafe { fn_0(TRAPFRAME, satp) }
     }
 
+    fn ntrigger() -> usize {
+        NTRIGGERS.load(Ordering::Relaxed)
+    }
+
+    unsafe fn load_triggers(triggers: &[Trigger; NTRIGGER]) {
+        let hart = 1 << r_tp();
+        let armed = triggers.iter().any(|trigger| trigger.kind != 0);
+        // Most processes have no triggers, so avoid the trips to machine mode for them.
+        if !armed && TRIGGERS_ARMED.load(Ordering::Relaxed) & hart == 0 {
+            return;
+        }
+        for (i, trigger) in triggers.iter().enumerate().take(Self::ntrigger()) {
+            let mut tdata1 = 0;
+            if trigger.kind != 0 {
+                tdata1 = TDATA1_MCONTROL;
+                if trigger.kind & TRIGGER_EXEC != 0 {
+                    tdata1 |= TDATA1_EXECUTE;
+                }
+                if trigger.kind & TRIGGER_LOAD != 0 {
+                    tdata1 |= TDATA1_LOAD;
+                }
+                if trigger.kind & TRIGGER_STORE != 0 {
+                    tdata1 |= TDATA1_STORE;
+                }
+            }
+            // SAFETY: the CPU has the trigger i, which only matches in user mode.
+            unsafe { w_trigger(i, tdata1, trigger.addr) };
+        }
+        if armed {
+            let _ = TRIGGERS_ARMED.fetch_or(hart, Ordering::Relaxed);
+        } else {
+            let _ = TRIGGERS_ARMED.fetch_and(!hart, Ordering::Relaxed);
+        }
+    }
+
     /// Save trap registers in `store`.
     fn save_trap_regs(store: &mut [usize; 10]) {
         let sepc = r_sepc();
@@ -1,13 +1,15 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use bitflags::bitflags;
 
 use super::RiscV;
 use crate::{
     addr::{PAddr, PGSIZE},
     arch::interface::{IPageTableEntry, PageTableManager},
-    arch::memlayout::{FINISHER, PLIC},
+    arch::memlayout::{CLINT, CLINT_MTIME, FINISHER, PLIC, RTC},
     arch::{
         addr::{pa2pte, pte2pa, PLNUM},
-        asm::{make_satp, sfence_vma, w_satp},
+        asm::{make_satp, r_satp, sfence_vma, w_satp, SATP_ASID_MASK, SATP_ASID_SHIFT},
     },
     vm::{AccessFlags, RawPageTable},
 };
@@ -24,6 +26,16 @@ bitflags! {
         const X = 1 << 3;
         /// user-accessible
         const U = 1 << 4;
+        /// accessed
+        const A = 1 << 6;
+        /// dirty
+        const D = 1 << 7;
+        /// copy-on-write (reserved for software)
+        const COW = 1 << 8;
+        /// freed before it is written (reserved for software)
+        const LAZYFREE = 1 << 9;
+        /// in the compressed swap, for an invalid entry, whose other bits the hardware ignores
+        const SWAPPED = 1 << 5;
     }
 }
 
@@ -94,6 +106,24 @@ impl IPageTableEntry for PageTableEntry {
             && self.flag_intersects(Self::EntryFlags::R | Self::EntryFlags::W | Self::EntryFlags::X)
     }
 
+    fn get_access_flags(&self) -> AccessFlags {
+        let flags = self.get_flags();
+        let mut ret = AccessFlags::empty();
+        if flags.intersects(Self::EntryFlags::R) {
+            ret |= AccessFlags::R;
+        }
+        if flags.intersects(Self::EntryFlags::W) {
+            ret |= AccessFlags::W;
+        }
+        if flags.intersects(Self::EntryFlags::X) {
+            ret |= AccessFlags::X;
+        }
+        if flags.intersects(Self::EntryFlags::U) {
+            ret |= AccessFlags::U;
+        }
+        ret
+    }
+
     /// Make the entry refer to a given page-table page.
     fn set_table(&mut self, page: *mut RawPageTable) {
         self.inner = pa2pte((page as usize).into()) | Self::EntryFlags::V.bits();
@@ -112,16 +142,84 @@ impl IPageTableEntry for PageTableEntry {
         self.inner &= !(Self::EntryFlags::U.bits());
     }
 
+    fn is_cow(&self) -> bool {
+        self.flag_intersects(Self::EntryFlags::COW)
+    }
+
+    fn is_lazyfree(&self) -> bool {
+        self.flag_intersects(Self::EntryFlags::LAZYFREE)
+    }
+
+    fn set_cow(&mut self, lazyfree: bool) {
+        self.inner &= !(Self::EntryFlags::W | Self::EntryFlags::LAZYFREE).bits();
+        self.inner |= Self::EntryFlags::COW.bits();
+        if lazyfree {
+            self.inner |= Self::EntryFlags::LAZYFREE.bits();
+        }
+    }
+
+    fn clear_cow(&mut self) {
+        self.inner &= !(Self::EntryFlags::COW | Self::EntryFlags::LAZYFREE).bits();
+        self.inner |= Self::EntryFlags::W.bits();
+    }
+
+    fn is_accessed(&self) -> bool {
+        self.flag_intersects(Self::EntryFlags::A)
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.flag_intersects(Self::EntryFlags::D)
+    }
+
+    fn set_accessed(&mut self, write: bool) {
+        self.inner |= Self::EntryFlags::A.bits();
+        if write {
+            self.inner |= Self::EntryFlags::D.bits();
+        }
+    }
+
+    fn clear_accessed(&mut self) {
+        self.inner &= !(Self::EntryFlags::A | Self::EntryFlags::D).bits();
+    }
+
+    fn swapped(&self) -> Option<usize> {
+        if self.is_valid() || !self.flag_intersects(Self::EntryFlags::SWAPPED) {
+            return None;
+        }
+        Some(self.get_pa().into_usize() / PGSIZE)
+    }
+
+    fn swap_out(&mut self, slot: usize) {
+        let flags =
+            self.get_flags() - (Self::EntryFlags::V | Self::EntryFlags::A | Self::EntryFlags::D);
+        self.inner = pa2pte((slot * PGSIZE).into()) | (flags | Self::EntryFlags::SWAPPED).bits();
+    }
+
+    fn swap_in(&mut self, pa: PAddr) {
+        let flags = self.get_flags() - Self::EntryFlags::SWAPPED;
+        self.set_entry(pa, flags);
+    }
+
     /// Invalidate the entry by making every bit 0.
     fn invalidate(&mut self) {
         self.inner = 0;
     }
 }
 
+/// Number of ASIDs, found by `switch_page_table_and_enable_mmu`.
+static NUM_ASIDS: AtomicUsize = AtomicUsize::new(1);
+
 impl RiscV {
     // Device mappings in memory.
-    // SiFive Test Finisher MMIO, PLIC.
-    const DEV_MAPPING: [(usize, usize); 2] = [(FINISHER, PGSIZE), (PLIC, 0x400000)];
+    // SiFive Test Finisher MMIO, goldfish RTC, PLIC, and the MSIP and MTIME registers of the
+    // CLINT.
+    const DEV_MAPPING: [(usize, usize); 5] = [
+        (FINISHER, PGSIZE),
+        (RTC, PGSIZE),
+        (PLIC, 0x400000),
+        (CLINT, PGSIZE),
+        (CLINT_MTIME / PGSIZE * PGSIZE, PGSIZE),
+    ];
 }
 
 impl PageTableManager for RiscV {
@@ -130,7 +228,7 @@ impl PageTableManager for RiscV {
     const PLNUM: usize = PLNUM;
 
     fn kernel_page_dev_mappings() -> &'static [(usize, usize)] {
-        &Self::DEV_MAPPING[0..2]
+        &Self::DEV_MAPPING
     }
 
     /// Switch the page table to `page_table_base` and enable paging.
@@ -141,8 +239,22 @@ impl PageTableManager for RiscV {
     unsafe fn switch_page_table_and_enable_mmu(page_table_base: usize) {
         // SAFETY: `page_table_base` contains address for a valid page table.
         unsafe {
+            // Only the implemented bits of the ASID field keep the ones written to them.
+            w_satp(make_satp(page_table_base) | SATP_ASID_MASK);
+            let max_asid = (r_satp() & SATP_ASID_MASK) >> SATP_ASID_SHIFT;
+            NUM_ASIDS.store(max_asid + 1, Ordering::Relaxed);
+
             w_satp(make_satp(page_table_base));
             sfence_vma();
         }
     }
+
+    fn flush_tlb() {
+        // SAFETY: flushing the TLB does not change any mapping.
+        unsafe { sfence_vma() };
+    }
+
+    fn num_asids() -> usize {
+        NUM_ASIDS.load(Ordering::Relaxed)
+    }
 }
@@ -1,13 +1,17 @@
 //! Array based arena.
 
-use core::{marker::PhantomPinned, ptr::NonNull};
+use core::{
+    marker::PhantomPinned,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use array_macro::array;
 use pin_project::pin_project;
 
-use super::{Arena, ArenaObject, ArenaRc};
+use super::{Arena, ArenaObject, ArenaRc, ArenaWeak};
 use crate::{
-    lock::{SpinLock, SpinLockGuard},
+    lock::{LockStat, Mutex, MutexGuard},
     util::{
         static_arc::StaticArc,
         strong_pin::{StrongPin, StrongPinMut},
@@ -15,7 +19,11 @@ use crate::{
 };
 
 pub struct ArrayArena<T, const CAPACITY: usize> {
-    inner: SpinLock<ArrayArenaInner<T, CAPACITY>>,
+    inner: Mutex<ArrayArenaInner<T, CAPACITY>>,
+
+    /// Number of entries that can be allocated, from the front of the array. The rest of the
+    /// entries are not allocated, but the ones still in use can be found.
+    capacity: AtomicUsize,
 }
 
 /// A homogeneous memory allocator equipped with reference counts.
@@ -35,14 +43,30 @@ impl<T, const CAPACITY: usize> ArrayArena<T, CAPACITY> {
             _marker: PhantomPinned,
         };
         ArrayArena {
-            inner: SpinLock::new(name, inner),
+            inner: Mutex::new(name, inner),
+            capacity: AtomicUsize::new(CAPACITY),
         }
     }
 
+    /// Returns the number of entries that can be allocated.
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Limits the number of entries that can be allocated to `capacity`, which is at most
+    /// `CAPACITY`. Entries in use beyond the new capacity are not affected.
+    pub fn set_capacity(&self, capacity: usize) {
+        assert!(capacity <= CAPACITY, "set_capacity");
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    /// Returns the contention statistics of the lock of the arena.
+    pub fn lock_stat(&self) -> LockStat {
+        self.inner.stat()
+    }
+
     #[allow(clippy::needless_lifetimes)]
-    fn inner<'s>(
-        self: StrongPin<'s, Self>,
-    ) -> StrongPin<'s, SpinLock<ArrayArenaInner<T, CAPACITY>>> {
+    fn inner<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Mutex<ArrayArenaInner<T, CAPACITY>>> {
         unsafe { StrongPin::new_unchecked(&(*self.ptr()).inner) }
     }
 }
@@ -59,7 +83,7 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
     for ArrayArena<T, CAPACITY>
 {
     type Data = T;
-    type Guard<'s> = SpinLockGuard<'s, ArrayArenaInner<T, CAPACITY>>;
+    type Guard<'s> = MutexGuard<'s, ArrayArenaInner<T, CAPACITY>>;
 
     fn find_or_alloc<C: Fn(&Self::Data) -> bool, N: FnOnce(&mut Self::Data)>(
         self: StrongPin<'_, Self>,
@@ -69,10 +93,13 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
         let mut guard = self.inner().strong_pinned_lock();
         let this = guard.get_strong_pinned_mut();
 
+        let capacity = self.capacity();
         let mut empty: Option<NonNull<StaticArc<T>>> = None;
-        for mut entry in this.entries().iter_mut() {
+        for (i, mut entry) in this.entries().iter_mut().enumerate() {
             if !entry.as_mut().is_borrowed() {
-                let _ = empty.get_or_insert(entry.ptr());
+                if i < capacity {
+                    let _ = empty.get_or_insert(entry.ptr());
+                }
                 // Note: Do not use `break` here.
                 // We must first search through all entries, and then alloc at empty
                 // only if the entry we're finding for doesn't exist.
@@ -94,7 +121,7 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
         let mut guard = self.inner().strong_pinned_lock();
         let this = guard.get_strong_pinned_mut();
 
-        for mut entry in this.entries().iter_mut() {
+        for mut entry in this.entries().iter_mut().take(self.capacity()) {
             if let Some(data) = entry.as_mut().get_mut() {
                 *data = f();
                 return Some(ArenaRc::new(self, entry.borrow()));
@@ -102,4 +129,24 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
         }
         None
     }
+
+    fn count_used(self: StrongPin<'_, Self>) -> usize {
+        let mut guard = self.inner().strong_pinned_lock();
+        let this = guard.get_strong_pinned_mut();
+        this.entries()
+            .iter_mut()
+            .map(|entry| entry.is_borrowed())
+            .filter(|&used| used)
+            .count()
+    }
+
+    fn upgrade(weak: &ArenaWeak<Self>) -> Option<ArenaRc<Self>> {
+        let arena = weak.arena();
+        let _guard = arena.inner().strong_pinned_lock();
+        // SAFETY: the entry of `weak` is in `arena`, whose lock is held.
+        let entry = unsafe { StrongPinMut::new_unchecked(weak.inner().cell()) };
+        entry
+            .upgrade(weak.inner())
+            .map(|inner| ArenaRc::new(arena, inner))
+    }
 }
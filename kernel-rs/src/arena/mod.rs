@@ -3,19 +3,22 @@
 //! Includes the `Arena` trait, which represents a type that can be used as an arena.
 //! For types that `impl Arena`, you can allocate a thread safe `Rc` (reference counted pointer) from it.
 //!
-//! This module also includes pre-built arenas, such as `ArrayArena`(array based arena) or `MruArena`(list based arena).
+//! This module also includes pre-built arenas, such as `ArrayArena`(array based arena) or `MruArena`(list based arena),
+//! and `Reaper`, which defers the finalization of objects to a point where its context is available.
 
 use core::mem::ManuallyDrop;
 use core::ops::Deref;
 
-use crate::util::static_arc::Ref;
+use crate::util::static_arc::{Ref, Weak};
 use crate::util::strong_pin::StrongPin;
 
 mod array_arena;
 mod mru_arena;
+mod reaper;
 
 pub use array_arena::ArrayArena;
 pub use mru_arena::MruArena;
+pub use reaper::Reaper;
 
 /// A homogeneous memory allocator. Provides `Rc<Arena>` to the outside.
 pub trait Arena: Sized + Sync {
@@ -41,6 +44,12 @@ pub trait Arena: Sized + Sync {
     /// Otherwise, returns `None`.
     fn alloc<F: FnOnce() -> Self::Data>(self: StrongPin<'_, Self>, f: F) -> Option<ArenaRc<Self>>;
 
+    /// Returns the number of entries that are in use.
+    fn count_used(self: StrongPin<'_, Self>) -> usize;
+
+    /// Returns an `Rc` to the object of `weak`, or `None` if its entry has been reused.
+    fn upgrade(weak: &ArenaWeak<Self>) -> Option<ArenaRc<Self>>;
+
     /// Deallocate a given handle, decreasing the reference count
     /// Finalizes the referred object if there are no more handles.
     ///
@@ -48,13 +57,12 @@ pub trait Arena: Sized + Sync {
     ///
     /// This method is automatically used by the `Rc`.
     /// Usually, you don't need to manually call this method.
-    fn dealloc(mut rc: ArenaRc<Self>, ctx: <Self::Data as ArenaObject>::Ctx<'_, '_>) {
-        let inner = unsafe { ManuallyDrop::take(&mut rc.inner) };
+    fn dealloc(rc: ArenaRc<Self>, ctx: <Self::Data as ArenaObject>::Ctx<'_, '_>) {
+        let (_, inner) = rc.into_parts();
         if let Ok(mut rm) = inner.into_mut() {
             // Finalize the arena object.
             rm.finalize(ctx);
         }
-        core::mem::forget(rc);
     }
 }
 
@@ -63,6 +71,8 @@ pub trait ArenaObject {
 
     /// Finalizes the `ArenaObject`.
     /// This function is automatically called when the last `Rc` referring to this `ArenaObject` gets dropped.
+    /// It runs without holding the arena's lock, so it may sleep. If the code dropping the last `Rc` cannot
+    /// provide `Ctx`, it can hand the `Rc` to a `Reaper` instead.
     fn finalize<'a, 'b: 'a>(&mut self, ctx: Self::Ctx<'a, 'b>);
 }
 
@@ -89,6 +99,48 @@ impl<T, A: Arena<Data = T>> ArenaRc<A> {
     }
 }
 
+/// A reference that does not keep its object alive, made by `ArenaRc::downgrade`.
+/// It can be upgraded back to an `Rc` until the entry of the object gets reused, so caches can
+/// refer to objects without keeping them from being finalized.
+///
+/// # Safety
+///
+/// * `arena` is pinned.
+/// * `inner` refers to an entry of `arena`.
+pub struct ArenaWeak<A: Arena> {
+    arena: *const A,
+    inner: Weak<A::Data>,
+}
+
+impl<A: Arena> ArenaWeak<A> {
+    pub fn upgrade(&self) -> Option<ArenaRc<A>> {
+        A::upgrade(self)
+    }
+
+    /// Returns the arena of `self`.
+    pub fn arena(&self) -> StrongPin<'_, A> {
+        // SAFETY: invariant.
+        unsafe { StrongPin::new_unchecked(&*self.arena) }
+    }
+
+    /// Returns the `Weak` of `self`.
+    pub fn inner(&self) -> &Weak<A::Data> {
+        &self.inner
+    }
+}
+
+impl<A: Arena> Clone for ArenaWeak<A> {
+    fn clone(&self) -> Self {
+        Self {
+            arena: self.arena,
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+// `ArenaWeak` is `Send` because it is upgraded only while holding `Arena`'s lock.
+unsafe impl<T: Sync, A: Arena<Data = T>> Send for ArenaWeak<A> {}
+
 // `Rc` is `Send` because it does not impl `DerefMut`,
 // and when we access the inner `Arena`, we do it after acquiring `Arena`'s lock.
 // Also, `Rc` does not point to thread-local data.
@@ -115,6 +167,34 @@ impl<A: Arena> ArenaRc<A> {
     pub fn free(self, ctx: <A::Data as ArenaObject>::Ctx<'_, '_>) {
         A::dealloc(self, ctx);
     }
+
+    /// Drops `self` if it is not the last `Rc` referring to its object, which needs no finalization.
+    /// Returns Err(self) if it is the last one, which must be freed with `free`.
+    pub fn try_free(self) -> Result<(), Self> {
+        let (arena, inner) = self.into_parts();
+        inner.try_drop().map_err(|inner| {
+            Self {
+                arena,
+                inner: ManuallyDrop::new(inner),
+            }
+        })
+    }
+
+    /// Makes an `ArenaWeak` to the object of `self`.
+    pub fn downgrade(&self) -> ArenaWeak<A> {
+        ArenaWeak {
+            arena: self.arena,
+            inner: self.inner.downgrade(),
+        }
+    }
+
+    /// Consumes `self` without dropping the reference it holds.
+    fn into_parts(self) -> (*const A, Ref<A::Data>) {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is not used again.
+        let inner = unsafe { ManuallyDrop::take(&mut this.inner) };
+        (this.arena, inner)
+    }
 }
 
 impl<A: Arena> Drop for ArenaRc<A> {
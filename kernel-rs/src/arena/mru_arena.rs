@@ -1,14 +1,12 @@
 //! List based arena.
 
 use core::mem;
-use core::mem::ManuallyDrop;
 use core::pin::Pin;
-use core::ptr::NonNull;
 
 use array_macro::array;
 use pin_project::pin_project;
 
-use super::{Arena, ArenaObject, ArenaRc};
+use super::{Arena, ArenaObject, ArenaRc, ArenaWeak};
 use crate::util::strong_pin::StrongPin;
 use crate::{
     lock::{SpinLock, SpinLockGuard},
@@ -102,6 +100,58 @@ impl<T, const CAPACITY: usize> MruArena<T, CAPACITY> {
     }
 }
 
+impl<T: Default, const CAPACITY: usize> MruArena<T, CAPACITY> {
+    /// Returns the number of entries in the list, which can be allocated.
+    pub fn capacity(self: StrongPin<'_, Self>) -> usize {
+        let mut guard = self.inner().strong_pinned_lock();
+        guard
+            .get_strong_pinned_mut()
+            .list()
+            .iter_shared_mut()
+            .count()
+    }
+
+    /// Keeps `capacity` entries, which is at most `CAPACITY`, in the list. Free entries taken out
+    /// of the list are reset, since they stop caching their data, and entries in use stay in the
+    /// list even if it has more than `capacity` entries.
+    pub fn set_capacity(self: StrongPin<'_, Self>, capacity: usize) {
+        assert!(capacity <= CAPACITY, "set_capacity");
+        let mut guard = self.inner().strong_pinned_lock();
+        let mut this = guard.get_strong_pinned_mut();
+        let mut len = this.as_mut().list().iter_shared_mut().count();
+
+        // Take out the free entries closest to the back first.
+        let mut cursor = this.as_mut().list().cursor_back_mut();
+        while let Some(entry) = cursor.current() {
+            if len <= capacity {
+                break;
+            }
+            if let Some(data) = entry.data().get_mut() {
+                *data = T::default();
+                let _ = cursor.remove_current();
+                len -= 1;
+            }
+            cursor.move_prev();
+        }
+
+        // Put the entries taken out back at the back of the list.
+        // SAFETY: the entries are pinned, and only their `ListEntry`s are accessed.
+        let entries = unsafe { &(*this.ptr().as_ptr()).entries };
+        let list = this.list().as_ref().as_pin();
+        for entry in entries {
+            if len >= capacity {
+                break;
+            }
+            // SAFETY: the entries are pinned.
+            let entry = unsafe { Pin::new_unchecked(entry) };
+            if entry.get_list_entry().is_unlinked() {
+                list.push_back(entry);
+                len += 1;
+            }
+        }
+    }
+}
+
 impl<T, const CAPACITY: usize> MruArenaInner<T, CAPACITY> {
     fn init(self: Pin<&mut Self>) {
         let mut this = self.project();
@@ -133,46 +183,68 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
         let mut guard = self.inner().strong_pinned_lock();
         let this = guard.get_strong_pinned_mut();
 
-        let mut empty: Option<NonNull<StaticArc<T>>> = None;
-        for entry in this.list().iter_shared_mut() {
-            let mut entry = entry.data();
-
-            if let Some(entry) = entry.as_mut().try_borrow() {
+        let mut cursor = this.list().cursor_front_mut();
+        while let Some(entry) = cursor.current() {
+            if let Some(entry) = entry.data().try_borrow() {
                 // The entry is not under finalization. Check its data.
                 if c(&entry) {
                     return Some(ArenaRc::new(self, entry));
                 }
             }
+            cursor.move_next();
+        }
 
-            if !entry.as_mut().is_borrowed() {
-                empty = Some(entry.ptr());
+        // The cursor is at the ghost position. Reuse the free entry closest to the back.
+        cursor.move_prev();
+        while let Some(entry) = cursor.current() {
+            let mut entry = entry.data();
+            if let Some(data) = entry.as_mut().get_mut() {
+                n(data);
+                return Some(ArenaRc::new(self, entry.borrow()));
             }
+            cursor.move_prev();
         }
-
-        empty.map(|ptr| {
-            // SAFETY: `ptr` is valid, and there's no `StrongPinMut`.
-            let mut entry = unsafe { StrongPinMut::new_unchecked(ptr.as_ptr()) };
-            n(entry.as_mut().get_mut().unwrap());
-            ArenaRc::new(self, entry.borrow())
-        })
+        None
     }
 
     fn alloc<F: FnOnce() -> Self::Data>(self: StrongPin<'_, Self>, f: F) -> Option<ArenaRc<Self>> {
         let mut guard = self.inner().strong_pinned_lock();
         let this = guard.get_strong_pinned_mut();
 
-        for entry in this.list().iter_shared_mut().rev() {
+        let mut cursor = this.list().cursor_back_mut();
+        while let Some(entry) = cursor.current() {
             let mut entry = entry.data();
             if let Some(data) = entry.as_mut().get_mut() {
                 *data = f();
                 return Some(ArenaRc::new(self, entry.borrow()));
             }
+            cursor.move_prev();
         }
         None
     }
 
-    fn dealloc(mut rc: ArenaRc<Self>, ctx: <Self::Data as ArenaObject>::Ctx<'_, '_>) {
-        let inner = unsafe { ManuallyDrop::take(&mut rc.inner) };
+    fn count_used(self: StrongPin<'_, Self>) -> usize {
+        let mut guard = self.inner().strong_pinned_lock();
+        let this = guard.get_strong_pinned_mut();
+        this.list()
+            .iter_shared_mut()
+            .map(|entry| entry.data().is_borrowed())
+            .filter(|&used| used)
+            .count()
+    }
+
+    fn upgrade(weak: &ArenaWeak<Self>) -> Option<ArenaRc<Self>> {
+        let arena = weak.arena();
+        let _guard = arena.inner().strong_pinned_lock();
+        // SAFETY: the entry of `weak` is in `arena`, whose lock is held.
+        let entry = unsafe { StrongPinMut::new_unchecked(weak.inner().cell()) };
+        entry
+            .upgrade(weak.inner())
+            .map(|inner| ArenaRc::new(arena, inner))
+    }
+
+    fn dealloc(rc: ArenaRc<Self>, ctx: <Self::Data as ArenaObject>::Ctx<'_, '_>) {
+        let (arena, inner) = rc.into_parts();
         if let Ok(mut rm) = inner.into_mut() {
             // Finalize the arena object.
             rm.finalize(ctx);
@@ -181,15 +253,18 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
             let ptr: *const MruEntry<Self::Data> =
                 (rm.cell() as usize - MruEntry::<T>::DATA_OFFSET) as _;
             // SAFETY:
-            // * `rm.cell()` is an `RcCell` inside an `MruEntry`.
+            // * `rm.cell()` is a `StaticArc` inside an `MruEntry`.
             // * The value of `DATA_OFFSET` is proper.
             let ptr = unsafe { Pin::new_unchecked(&*ptr) };
 
-            let arena = unsafe { StrongPin::new_unchecked(&*rc.arena) };
+            // SAFETY: the invariant of `ArenaRc`.
+            let arena = unsafe { StrongPin::new_unchecked(&*arena) };
             let mut this = arena.inner().strong_pinned_lock();
-            let this = this.get_strong_pinned_mut().as_ref().as_pin().get_ref();
-            unsafe { Pin::new_unchecked(&this.list) }.push_back(ptr);
+            this.get_strong_pinned_mut()
+                .list()
+                .as_ref()
+                .as_pin()
+                .push_back(ptr);
         }
-        core::mem::forget(rc);
     }
 }
@@ -0,0 +1,60 @@
+//! Deferred finalization.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use super::{Arena, ArenaRc};
+use crate::lock::SpinLock;
+
+/// A queue of `Rc`s whose finalization is deferred, because it needs a context, such as a
+/// transaction, that the code dropping them cannot provide.
+///
+/// The owner of the context pops the `Rc`s, and frees them one by one. No lock is held while
+/// an object is finalized, so finalization may sleep.
+pub struct Reaper<A: Arena, const CAPACITY: usize> {
+    queue: SpinLock<[Option<ArenaRc<A>>; CAPACITY]>,
+
+    /// Number of `Rc`s in `queue`, read without the lock.
+    len: AtomicUsize,
+}
+
+impl<A: Arena, const CAPACITY: usize> Reaper<A, CAPACITY> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            queue: SpinLock::new(name, array![_ => None; CAPACITY]),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Drops `rc` if it is not the last `Rc` referring to its object. Otherwise, queues it to be
+    /// finalized later.
+    /// Returns Err(rc) if the queue is full, in which case the caller must free it.
+    pub fn defer(&self, rc: ArenaRc<A>) -> Result<(), ArenaRc<A>> {
+        let rc = match rc.try_free() {
+            Ok(()) => return Ok(()),
+            Err(rc) => rc,
+        };
+        let mut queue = self.queue.lock();
+        match queue.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(rc);
+                let _ = self.len.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            None => Err(rc),
+        }
+    }
+
+    /// Takes an `Rc` out of the queue, which the caller must free.
+    /// Returns None if the queue is empty.
+    pub fn pop(&self) -> Option<ArenaRc<A>> {
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let mut queue = self.queue.lock();
+        let rc = queue.iter_mut().find_map(|slot| slot.take())?;
+        let _ = self.len.fetch_sub(1, Ordering::Relaxed);
+        Some(rc)
+    }
+}
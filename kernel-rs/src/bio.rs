@@ -19,7 +19,7 @@ use crate::util::strong_pin::StrongPin;
 use crate::{
     arena::{Arena, ArenaObject, MruArena},
     lock::SleepLock,
-    param::{BSIZE, NBUF},
+    param::{BSIZE, MAXNBUF},
     proc::{KernelCtx, WaitChannel},
 };
 
@@ -100,7 +100,7 @@ impl BufInner {
     }
 }
 
-pub type Bcache = MruArena<BufEntry, NBUF>;
+pub type Bcache = MruArena<BufEntry, MAXNBUF>;
 
 /// A reference counted smart pointer to a `BufEntry`.
 pub struct BufUnlocked(ManuallyDrop<ArenaRc<Bcache>>);
@@ -186,7 +186,7 @@ impl Bcache {
     ///
     /// Must be used only after initializing it with `MruArena::init`.
     pub const unsafe fn new_bcache() -> Self {
-        unsafe { MruArena::<BufEntry, NBUF>::new("BCACHE") }
+        unsafe { MruArena::<BufEntry, MAXNBUF>::new("BCACHE") }
     }
 
     /// Return a unlocked buf with the contents of the indicated block.
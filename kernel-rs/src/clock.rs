@@ -0,0 +1,96 @@
+//! Wall-clock time.
+//!
+//! The real-time clock is read once at boot. Afterwards, the time is kept by adding the uptime
+//! of the timer, which is finer than the real-time clock, to the time read at boot.
+//! `settimeofday()` moves the clock by changing the offset between the two.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    arch::interface::TimeManager, arch::TargetArch, proc::KernelCtx, syscall::SyscallTable,
+};
+
+const US_PER_S: usize = 1_000_000;
+
+/// A time of `gettimeofday()` and `settimeofday()`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct TimeVal {
+    /// Seconds since the Unix epoch
+    sec: i64,
+
+    /// Microseconds, less than a second
+    usec: i64,
+}
+
+pub struct WallClock {
+    /// The time at the uptime 0, in microseconds since the Unix epoch.
+    offset: AtomicUsize,
+}
+
+/// Returns the uptime in microseconds.
+pub fn uptime() -> usize {
+    TargetArch::uptime_as_micro().unwrap_or(0)
+}
+
+impl WallClock {
+    pub const fn new() -> Self {
+        Self {
+            offset: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the clock to the time of the real-time clock.
+    pub fn init(&self) {
+        self.set(TargetArch::rtc_micro());
+    }
+
+    /// Returns the current time, in microseconds since the Unix epoch.
+    pub fn now(&self) -> usize {
+        self.offset.load(Ordering::Relaxed) + uptime()
+    }
+
+    /// Sets the current time to `us` microseconds since the Unix epoch.
+    pub fn set(&self, us: usize) {
+        self.offset
+            .store(us.saturating_sub(uptime()), Ordering::Relaxed);
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Store the current time into the struct timeval at addr. The time zone is ignored.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_gettimeofday(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let now = self.kernel().clock().now();
+        let tv = TimeVal {
+            sec: (now / US_PER_S) as i64,
+            usec: (now % US_PER_S) as i64,
+        };
+        self.proc_mut().memory_mut().copy_out(addr.into(), &tv)?;
+        Ok(0)
+    }
+
+    /// Set the current time to the struct timeval at addr. The time zone is ignored.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_settimeofday(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let mut tv = TimeVal::default();
+        // SAFETY: TimeVal does not have any internal structure.
+        unsafe { self.proc_mut().memory_mut().copy_in(&mut tv, addr.into()) }?;
+        if tv.sec < 0 || !(0..US_PER_S as i64).contains(&tv.usec) {
+            return Err(());
+        }
+        let us = (tv.sec as usize).checked_mul(US_PER_S).ok_or(())?;
+        self.kernel().clock().set(us + tv.usec as usize);
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of the wall clock.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(77, |ctx| ctx.sys_gettimeofday());
+    table.register(78, |ctx| ctx.sys_settimeofday());
+}
@@ -0,0 +1,231 @@
+//! Persistent kernel configuration.
+//!
+//! The boot configuration is kept in the boot block of the root device, which the file system
+//! does not use, so that it survives reboots of the same disk image. mkfs zeroes the block, and
+//! a block without `CONFIG_MAGIC` holds the default configuration.
+//!
+//! The initial process applies the configuration when it starts, and the other processes inherit
+//! its scheduling class. The sizes of the kernel tables can only be lowered from the sizes they
+//! are compiled with, except the buffer cache, which has room for twice its default size.
+//! Processes are allocated as they are needed, so their number is limited only if the
+//! configuration says so. `setconfig()` stores a configuration, which takes effect at the next
+//! boot.
+
+use core::{mem, ptr};
+
+use static_assertions::const_assert;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    arch::interface::TimeManager,
+    arch::TargetArch,
+    bio::BufData,
+    hal::hal,
+    param::{BSIZE, LOGSIZE, MAXNBUF, MINTICK_US, NBUF, NFILE, NINODE, NOFILE, ROOTDEV, TICK_US},
+    power::IdlePolicy,
+    proc::{KernelCtx, SchedClass},
+    syscall::SyscallTable,
+};
+
+/// Block of the root device that holds the configuration.
+const CONFIG_BLOCK: u32 = 0;
+
+const CONFIG_MAGIC: u32 = 0x6366676b;
+
+/// Settings applied at boot, read by `getconfig()` and written by `setconfig()`.
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct KernelConfig {
+    /// Scheduling class of the initial process
+    pub sched_class: u32,
+
+    /// Interval between timer interrupts, in microseconds
+    pub tick_us: u32,
+
+    /// What idle CPUs do
+    pub idle_policy: u32,
+
+    /// Whether to kill the process with the largest memory when memory runs out (0 or 1)
+    pub oom_killer: u32,
+
+    /// How to mount the root file system: read-write (0), read-only (1), or read-only after
+    /// replaying the log (2)
+    pub read_only: u32,
+
+    /// Sizes of the file table, the inode table, and the buffer cache, or 0 for the default size,
+    /// and the maximum number of processes, or 0 for no limit
+    pub nfile: u32,
+    pub ninode: u32,
+    pub nbuf: u32,
+    pub nproc: u32,
+
+    /// Whether to color the severity prefixes of kernel messages (0 or 1)
+    pub log_color: u32,
+}
+
+/// The configuration as stored on the disk.
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+struct DiskConfig {
+    /// Must be CONFIG_MAGIC
+    magic: u32,
+
+    config: KernelConfig,
+}
+
+impl Default for KernelConfig {
+    fn default() -> Self {
+        Self {
+            sched_class: SchedClass::Interactive as u32,
+            tick_us: TICK_US as u32,
+            idle_policy: IdlePolicy::Poll as u32,
+            oom_killer: 0,
+            read_only: 0,
+            nfile: 0,
+            ninode: 0,
+            nbuf: 0,
+            nproc: 0,
+            log_color: 0,
+        }
+    }
+}
+
+impl KernelConfig {
+    /// Returns Ok((scheduling class, idle policy)) if every setting is in range,
+    /// Err(()) otherwise.
+    fn check(&self) -> Result<(SchedClass, IdlePolicy), ()> {
+        let class = SchedClass::from_usize(self.sched_class as usize).ok_or(())?;
+        let policy = IdlePolicy::from_usize(self.idle_policy as usize).ok_or(())?;
+        if (self.tick_us as usize) < MINTICK_US
+            || self.oom_killer > 1
+            || self.read_only > 2
+            || self.log_color > 1
+        {
+            return Err(());
+        }
+        // The buffer cache must hold the blocks of a whole log, and the initial process and
+        // a shell must be able to run.
+        if !size_in(self.nfile, NOFILE, NFILE)
+            || !size_in(self.ninode, NOFILE, NINODE)
+            || !size_in(self.nbuf, LOGSIZE, MAXNBUF)
+            || !size_in(self.nproc, 2, usize::MAX)
+        {
+            return Err(());
+        }
+        Ok((class, policy))
+    }
+}
+
+/// Returns whether the table size `size` is 0, which means the default size, or between `min`
+/// and `max`.
+fn size_in(size: u32, min: usize, max: usize) -> bool {
+    size == 0 || (min..=max).contains(&(size as usize))
+}
+
+/// Returns the table size `size`, or `default` if it is 0.
+fn size_or(size: u32, default: usize) -> usize {
+    if size == 0 {
+        default
+    } else {
+        size as usize
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Reads the boot configuration from the disk.
+    pub fn read_config(&self) -> KernelConfig {
+        const_assert!(mem::size_of::<DiskConfig>() <= BSIZE);
+        const_assert!(mem::align_of::<BufData>() % mem::align_of::<DiskConfig>() == 0);
+        let buf = hal().disk().read(ROOTDEV, CONFIG_BLOCK, self);
+        // SAFETY:
+        // * buf.data is larger than DiskConfig, and aligned properly.
+        // * DiskConfig does not have any internal structure.
+        // * buf is locked, so we can access it exclusively.
+        let disk = unsafe { ptr::read(buf.deref_inner().data.as_ptr() as *const DiskConfig) };
+        buf.free(self);
+        if disk.magic == CONFIG_MAGIC {
+            disk.config
+        } else {
+            KernelConfig::default()
+        }
+    }
+
+    /// Writes `config` to the disk as the boot configuration.
+    /// Returns Ok(()) on success, Err(()) if a setting is out of range or the root file system
+    /// is read-only.
+    pub fn write_config(&self, config: &KernelConfig) -> Result<(), ()> {
+        let _ = config.check()?;
+        if self.kernel().fs().read_only() {
+            return Err(());
+        }
+        let disk = DiskConfig {
+            magic: CONFIG_MAGIC,
+            config: *config,
+        };
+        let mut buf = hal().disk().read(ROOTDEV, CONFIG_BLOCK, self);
+        let data = &mut buf.deref_inner_mut().data;
+        data[..mem::size_of::<DiskConfig>()].copy_from_slice(disk.as_bytes());
+        // The block is not managed by the log, so it is written directly.
+        hal().disk().write(&mut buf, self);
+        buf.free(self);
+        Ok(())
+    }
+
+    /// Applies `config` to the current process and the whole kernel.
+    /// Returns Ok(()) on success, Err(()) if a setting is out of range.
+    pub fn apply_config(&self, config: &KernelConfig) -> Result<(), ()> {
+        let (class, policy) = config.check()?;
+        self.kernel().procs().set_sched_class(0, class, self)?;
+        TargetArch::set_tick_interval(config.tick_us as usize);
+        self.kernel().power().set_idle_policy(policy);
+        self.kernel().procs().set_oom_killer(config.oom_killer != 0);
+        hal().console().set_log_color(config.log_color != 0);
+        self.kernel()
+            .ftable()
+            .set_capacity(size_or(config.nfile, NFILE));
+        self.kernel()
+            .fs()
+            .itable()
+            .set_capacity(size_or(config.ninode, NINODE));
+        self.kernel()
+            .bcache()
+            .set_capacity(size_or(config.nbuf, NBUF));
+        self.kernel()
+            .procs()
+            .set_capacity(size_or(config.nproc, usize::MAX));
+        Ok(())
+    }
+
+    /// Copy the boot configuration into struct kconfig at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_getconfig(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let config = self.read_config();
+        self.proc_mut()
+            .memory_mut()
+            .copy_out(addr.into(), &config)?;
+        Ok(0)
+    }
+
+    /// Store struct kconfig at addr as the boot configuration, which takes effect at the next
+    /// boot.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_setconfig(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let mut config = KernelConfig::default();
+        // SAFETY: KernelConfig does not have any internal structure.
+        unsafe {
+            self.proc_mut()
+                .memory_mut()
+                .copy_in(&mut config, addr.into())
+        }?;
+        self.write_config(&config)?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of the boot configuration.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(58, |ctx| ctx.sys_getconfig());
+    table.register(59, |ctx| ctx.sys_setconfig());
+}
@@ -1,31 +1,76 @@
 //! Console input and output, to the uart. Reads are line at a time.
 //!
+//! Processes reading the console take turns in the order they started reading, and each read
+//! returns at most one line. Only the reader whose turn it is is woken up when a line arrives.
+//!
 //! Implements special input characters:
 //! * newline -- end of line
 //! * control-h -- backspace
 //! * control-u -- kill line
 //! * control-d -- end of file
 //! * control-p -- print process list
+//! * control-t n -- switch to virtual console n
+//! * control-s, control-q -- stop and resume the output (XOFF and XON)
+//!
+//! The console also sends XOFF when the input buffer of the active console is filling up, and
+//! XON once readers have drained it, so that pasted or scripted input is not dropped by an other
+//! end that honors software flow control.
+//!
+//! `NVCONSOLE` virtual consoles share the uart. Console 0 is /dev/console, and console n > 0 is
+//! /dev/ttyn. Only the active console writes to the uart and receives input, but every console
+//! keeps the last `SCREEN_BUF` bytes of its output, which are written again when it becomes
+//! active.
+//!
+//! The console follows its output through the basic ANSI escape sequences, to know the column of
+//! the cursor and whether colors are on. Kernel messages use it to start on a line of their own
+//! and without the colors left on by user output, and their severity prefixes are colored if the
+//! boot configuration says so.
+
+use core::{
+    fmt,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+};
 
-use core::{fmt, pin::Pin};
+use static_assertions::const_assert;
 
 use crate::{
     addr::UVAddr,
     arch::interface::{Arch, UartManager, UartManagerConst},
     arch::TargetArch,
+    file::Devsw,
     hal::hal,
     kernel::{Kernel, KernelRef},
     lock::{SleepableLock, SleepableLockGuard, SpinLock, SpinLockGuard},
-    proc::KernelCtx,
+    param::{NDEV, NVCONSOLE},
+    proc::{KernelCtx, WaitChannel},
+    some_or,
     util::spin_loop,
 };
 
 type Uart = <TargetArch as Arch>::Uart;
 
 /// Size of console input buffer.
-const INPUT_BUF: usize = 128;
+const INPUT_BUF: usize = 1024;
+/// Number of buffered input characters at which the console sends XOFF.
+const INPUT_HIGH: usize = INPUT_BUF * 3 / 4;
+/// Number of buffered input characters at which the console sends XON after XOFF.
+const INPUT_LOW: usize = INPUT_BUF / 4;
 /// Size of console output buffer.
 const OUTPUT_BUF: usize = 32;
+/// Maximum number of readers waiting for their turn.
+const NREADER: usize = 64;
+/// Size of the output kept by each virtual console.
+const SCREEN_BUF: usize = 2048;
+
+/// Major device number of virtual console 1. Virtual console n uses the next ones.
+const VCONSOLE_DEVSW: usize = 6;
+
+// `register_devices` registers up to three virtual consoles besides the console.
+const_assert!(NVCONSOLE <= 4 && VCONSOLE_DEVSW + NVCONSOLE - 1 <= NDEV);
+
+/// Clears the terminal and moves the cursor home, with the attributes reset.
+const CLEAR_SCREEN: &[u8] = b"\x1b[0m\x1b[2J\x1b[H";
 
 struct OutputBuffer {
     buf: [u8; OUTPUT_BUF],
@@ -53,6 +98,15 @@ struct InputBuffer {
     w: usize,
     /// Edit index.
     e: usize,
+
+    /// Turn of the next reader to arrive.
+    next_reader: usize,
+
+    /// Turn of the reader allowed to read now.
+    serving: usize,
+
+    /// Readers that were killed while waiting for their turn, indexed by turn % NREADER.
+    abandoned: [bool; NREADER],
 }
 
 impl InputBuffer {
@@ -62,14 +116,228 @@ impl InputBuffer {
             w: 0,
             r: 0,
             e: 0,
+            next_reader: 0,
+            serving: 0,
+            abandoned: [false; NREADER],
+        }
+    }
+}
+
+/// The last output of a virtual console.
+struct Screen {
+    buf: [u8; SCREEN_BUF],
+    /// Number of bytes written so far.
+    w: usize,
+}
+
+impl Screen {
+    const fn new() -> Self {
+        Self {
+            buf: [0; SCREEN_BUF],
+            w: 0,
+        }
+    }
+
+    fn push(&mut self, c: u8) {
+        self.buf[self.w % SCREEN_BUF] = c;
+        self.w = self.w.wrapping_add(1);
+    }
+
+    /// Returns the kept output, from the oldest byte.
+    fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        let start = self.w.saturating_sub(SCREEN_BUF);
+        (start..self.w).map(move |i| self.buf[i % SCREEN_BUF])
+    }
+}
+
+/// A virtual console.
+struct Terminal {
+    input_buffer: SleepableLock<InputBuffer>,
+
+    /// Readers wait here for their turn, indexed by turn % NREADER.
+    readers: [WaitChannel; NREADER],
+
+    screen: SpinLock<Screen>,
+}
+
+const READER: WaitChannel = WaitChannel::new();
+
+impl Terminal {
+    const fn new() -> Self {
+        Self {
+            input_buffer: SleepableLock::new("console_input", InputBuffer::new()),
+            readers: [READER; NREADER],
+            screen: SpinLock::new("console_screen", Screen::new()),
+        }
+    }
+}
+
+const TERMINAL: Terminal = Terminal::new();
+
+/// States of the parser of escape sequences in `Cursor`.
+const ESC_NONE: u32 = 0;
+const ESC_ESC: u32 = 1;
+const ESC_CSI: u32 = 2;
+
+/// The cursor of the terminal, as far as the output tells, packed into a `u32` so that it can be
+/// updated atomically.
+#[derive(Clone, Copy)]
+struct Cursor(u32);
+
+impl Cursor {
+    /// Column of the cursor.
+    const COLUMN_MASK: u32 = 0xffff;
+    /// State of the parser of escape sequences.
+    const ESCAPE_SHIFT: u32 = 16;
+    /// Some parameter of the current control sequence is nonzero.
+    const NONZERO: u32 = 1 << 26;
+    /// First parameter of the current control sequence, up to 255.
+    const PARAM_SHIFT: u32 = 18;
+    /// The current control sequence has more than one parameter.
+    const SECOND: u32 = 1 << 27;
+    /// Colors or other attributes are on.
+    const STYLED: u32 = 1 << 28;
+
+    fn column(self) -> u32 {
+        self.0 & Self::COLUMN_MASK
+    }
+
+    fn escape(self) -> u32 {
+        (self.0 >> Self::ESCAPE_SHIFT) & 0x3
+    }
+
+    fn param(self) -> u32 {
+        (self.0 >> Self::PARAM_SHIFT) & 0xff
+    }
+
+    fn styled(self) -> bool {
+        self.0 & Self::STYLED != 0
+    }
+
+    fn with_column(self, column: u32) -> Self {
+        Self(self.0 & !Self::COLUMN_MASK | column.min(Self::COLUMN_MASK))
+    }
+
+    /// Returns the cursor in the state `escape`, with the parameters of the control sequence
+    /// cleared.
+    fn with_escape(self, escape: u32) -> Self {
+        let keep = Self::COLUMN_MASK | Self::STYLED;
+        Self(self.0 & keep | escape << Self::ESCAPE_SHIFT)
+    }
+
+    /// Returns the cursor after `c` is written.
+    fn advance(self, c: u8) -> Self {
+        match self.escape() {
+            ESC_ESC => self.advance_escape(c),
+            ESC_CSI => self.advance_control(c),
+            _ => self.advance_text(c),
+        }
+    }
+
+    fn advance_text(self, c: u8) -> Self {
+        let column = self.column();
+        match c {
+            0x1b => self.with_escape(ESC_ESC),
+            b'\r' | b'\n' => self.with_column(0),
+            8 => self.with_column(column.saturating_sub(1)),
+            b'\t' => self.with_column((column / 8 + 1) * 8),
+            // Other control characters and continuation bytes of UTF-8 do not move the cursor.
+            _ if c < 0x20 || c == 0x7f || c & 0xc0 == 0x80 => self,
+            _ => self.with_column(column + 1),
+        }
+    }
+
+    /// Follows the byte after an ESC.
+    fn advance_escape(self, c: u8) -> Self {
+        match c {
+            b'[' => self.with_escape(ESC_CSI),
+            // Reset to the initial state.
+            b'c' => Self(0),
+            _ => self.with_escape(ESC_NONE),
+        }
+    }
+
+    /// Follows a byte of a control sequence, which begins with ESC [.
+    fn advance_control(self, c: u8) -> Self {
+        let column = self.column();
+        let second = self.0 & Self::SECOND != 0;
+        match c {
+            b'0'..=b'9' => {
+                let mut cursor = self;
+                if c != b'0' {
+                    cursor.0 |= Self::NONZERO;
+                }
+                if !second {
+                    let param = (self.param() * 10 + (c - b'0') as u32).min(0xff);
+                    cursor.0 = cursor.0 & !(0xff << Self::PARAM_SHIFT) | param << Self::PARAM_SHIFT;
+                }
+                cursor
+            }
+            b';' => Self(self.0 | Self::SECOND),
+            // Private markers and intermediate bytes.
+            0x20..=0x2f | b':' | b'<'..=b'?' => self,
+            // Final bytes.
+            0x40..=0x7e => {
+                let n = self.param().max(1);
+                let cursor = self.with_escape(ESC_NONE);
+                match c {
+                    b'C' => cursor.with_column(column + n),
+                    b'D' => cursor.with_column(column.saturating_sub(n)),
+                    b'G' => cursor.with_column(n - 1),
+                    // Only the row is given, or the cursor moves home.
+                    b'H' | b'f' if !second => cursor.with_column(0),
+                    b'm' if self.0 & Self::NONZERO != 0 => Self(cursor.0 | Self::STYLED),
+                    b'm' => Self(cursor.0 & !Self::STYLED),
+                    _ => cursor,
+                }
+            }
+            _ => self.with_escape(ESC_NONE),
+        }
+    }
+}
+
+/// Severities of kernel messages.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Returns the prefix of a message of this severity, and the SGR parameter of its color.
+    fn prefix(self) -> (&'static str, u8) {
+        match self {
+            Severity::Info => ("", 0),
+            Severity::Warning => ("warning:", 33),
+            Severity::Error => ("error:", 31),
         }
     }
 }
 
 pub struct Console {
     uart: Uart,
-    input_buffer: SleepableLock<InputBuffer>,
     output_buffer: SleepableLock<OutputBuffer>,
+
+    terminals: [Terminal; NVCONSOLE],
+
+    /// The virtual console that is shown and receives input.
+    active: AtomicUsize,
+
+    /// Whether control-t has been typed, so the next character chooses a virtual console.
+    switching: AtomicBool,
+
+    /// Whether XOFF has been sent, and not yet XON.
+    input_stopped: AtomicBool,
+
+    /// Whether XOFF has been received, and not yet XON.
+    output_stopped: AtomicBool,
+
+    /// The cursor after the characters given to the uart so far.
+    cursor: AtomicU32,
+
+    /// Whether the prefixes of kernel messages are colored.
+    log_color: AtomicBool,
 }
 
 impl Console {
@@ -79,8 +347,14 @@ impl Console {
     pub const unsafe fn new(uart: usize) -> Self {
         Self {
             uart: unsafe { Uart::new(uart) },
-            input_buffer: SleepableLock::new("console_input", InputBuffer::new()),
             output_buffer: SleepableLock::new("console_output", OutputBuffer::new()),
+            terminals: [TERMINAL; NVCONSOLE],
+            active: AtomicUsize::new(0),
+            switching: AtomicBool::new(false),
+            input_stopped: AtomicBool::new(false),
+            output_stopped: AtomicBool::new(false),
+            cursor: AtomicU32::new(0),
+            log_color: AtomicBool::new(false),
         }
     }
 
@@ -88,49 +362,113 @@ impl Console {
         self.uart.init();
     }
 
+    /// Colors the prefixes of kernel messages if `on`.
+    pub fn set_log_color(&self, on: bool) {
+        self.log_color.store(on, Ordering::Relaxed);
+    }
+
+    /// Gives `c` to the uart, following the cursor. The uart must have room for it.
+    fn put(&self, c: u8) {
+        let _ = self
+            .cursor
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |cursor| {
+                Some(Cursor(cursor).advance(c).0)
+            });
+        self.uart.putc(c);
+    }
+
     /// Doesn't use interrupts, for use by kernel println() and to echo characters.
     /// It spins waiting for the uart's output register to be empty.
     fn putc_spin<A: Arch>(&self, c: u8, kernel: Pin<&Kernel<A>>) {
         let intr = hal().cpus().push_off();
-        if kernel.is_panicked() {
+        if kernel.should_freeze() {
             spin_loop();
         }
 
         // Wait for Transmit Holding Empty to be set in LSR.
         while self.uart.is_full() {}
 
+        self.put(c);
+
+        unsafe { hal().cpus().pop_off(intr) };
+    }
+
+    /// Sends the flow control character `c`, spinning like `putc_spin`. It is not output, so the
+    /// cursor doesn't follow it.
+    fn send_spin<A: Arch>(&self, c: u8, kernel: Pin<&Kernel<A>>) {
+        let intr = hal().cpus().push_off();
+        if kernel.should_freeze() {
+            spin_loop();
+        }
+
+        while self.uart.is_full() {}
+
         self.uart.putc(c);
 
         unsafe { hal().cpus().pop_off(intr) };
     }
 
-    fn put_backspace_spin(&self, kernel: Pin<&Kernel<TargetArch>>) {
+    /// Sends XOFF if the input buffer `buf` of the active console is filling up, or XON if it
+    /// has been drained after XOFF.
+    fn flow_control<A: Arch>(&self, buf: &InputBuffer, kernel: Pin<&Kernel<A>>) {
+        let used = buf.e.wrapping_sub(buf.r);
+        if used >= INPUT_HIGH {
+            if !self.input_stopped.swap(true, Ordering::Relaxed) {
+                self.send_spin(XOFF, kernel);
+            }
+        } else if used <= INPUT_LOW && self.input_stopped.swap(false, Ordering::Relaxed) {
+            self.send_spin(XON, kernel);
+        }
+    }
+
+    /// Echoes `c` typed to the active virtual console `term`.
+    fn echo_spin(&self, term: &Terminal, c: u8, kernel: Pin<&Kernel<TargetArch>>) {
+        term.screen.lock().push(c);
+        self.putc_spin(c, kernel);
+    }
+
+    fn put_backspace_spin(&self, term: &Terminal, kernel: Pin<&Kernel<TargetArch>>) {
         // Overwrite with a space.
-        self.putc_spin(8, kernel);
-        self.putc_spin(b' ', kernel);
-        self.putc_spin(8, kernel);
+        self.echo_spin(term, 8, kernel);
+        self.echo_spin(term, b' ', kernel);
+        self.echo_spin(term, 8, kernel);
+    }
+
+    /// Makes the virtual console `n` active, and redraws the terminal with its output.
+    fn switch_spin(&self, n: usize, kernel: Pin<&Kernel<TargetArch>>) {
+        if self.active.swap(n, Ordering::Relaxed) == n {
+            return;
+        }
+        for &c in CLEAR_SCREEN {
+            self.putc_spin(c, kernel);
+        }
+        let screen = self.terminals[n].screen.lock();
+        for c in screen.iter() {
+            self.putc_spin(c, kernel);
+        }
+        drop(screen);
+        self.flow_control(&self.terminals[n].input_buffer.lock(), kernel);
     }
 
     /// Add a character to the output buffer and tell the UART to start sending if it isn't
     /// already. Blocks if the output buffer is full. Since it may block, it can't be called
     /// from interrupts; it's only suitable for use by write().
-    fn putc_sleep(&self, c: u8, ctx: &KernelCtx<'_, '_>) {
+    /// Returns Err(()) if the process is killed while blocked.
+    fn putc_sleep(&self, c: u8, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
         if ctx.kernel().as_ref().is_panicked() {
             spin_loop();
         }
 
         let mut guard = self.output_buffer.lock();
 
-        while guard.w == guard.r.wrapping_add(OUTPUT_BUF) {
-            // Buffer is full.
-            // Wait for flush_output_buffer() to open up space in the buffer.
-            guard.sleep(ctx);
-        }
+        // Wait for flush_output_buffer() to open up space in the buffer if it is full.
+        guard.wait_while_killable(|buf| buf.w == buf.r.wrapping_add(OUTPUT_BUF), ctx)?;
 
         let ind = guard.w % OUTPUT_BUF;
         guard.buf[ind] = c;
         guard.w += 1;
         self.flush_output_buffer(guard, ctx.kernel());
+        Ok(())
     }
 
     /// If the UART is idle, and a character is waiting in the transmit buffer, send it.
@@ -146,6 +484,11 @@ impl Console {
                 return;
             }
 
+            if self.output_stopped.load(Ordering::Relaxed) {
+                // The other end sent XOFF. The output resumes when it sends XON.
+                return;
+            }
+
             if self.uart.is_full() {
                 // The UART transmit holding register is full, so we cannot give it another byte.
                 // It will interrupt when it's ready for a new byte.
@@ -158,11 +501,13 @@ impl Console {
             // Maybe uart.putc() is waiting for space in the buffer.
             guard.wakeup(kernel);
 
-            self.uart.putc(c);
+            self.put(c);
         }
     }
 
-    fn write(&self, src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    /// Writes to the virtual console `vc`, which goes to the uart only if `vc` is active.
+    fn write(&self, vc: usize, src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+        let term = &self.terminals[vc];
         for i in 0..n {
             let mut c = [0u8];
             if ctx
@@ -173,23 +518,40 @@ impl Console {
             {
                 return i;
             }
-            self.putc_sleep(c[0], ctx);
+            term.screen.lock().push(c[0]);
+            if self.active.load(Ordering::Relaxed) == vc && self.putc_sleep(c[0], ctx).is_err() {
+                return if i > 0 { i } else { -1 };
+            }
         }
         n
     }
 
-    fn read(&self, mut dst: UVAddr, mut n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
-        let mut guard = self.input_buffer.lock();
-        let target = n;
-        while n > 0 {
-            // Wait until interrupt handler has put some
-            // input into CONS.buffer.
-            while guard.r == guard.w {
-                if ctx.proc().killed() {
-                    return -1;
-                }
-                guard.sleep(ctx);
+    /// Reads a line typed to the virtual console `vc`.
+    fn read(&self, vc: usize, mut dst: UVAddr, mut n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+        let term = &self.terminals[vc];
+        let mut guard = term.input_buffer.lock();
+        if guard.next_reader.wrapping_sub(guard.serving) >= NREADER {
+            return -1;
+        }
+        let turn = guard.next_reader;
+        guard.next_reader = guard.next_reader.wrapping_add(1);
+
+        // Wait until it is our turn, and interrupt handler has put some
+        // input into CONS.buffer.
+        if term.readers[turn % NREADER]
+            .wait_while_killable(&mut guard, |buf| buf.serving != turn || buf.r == buf.w, ctx)
+            .is_err()
+        {
+            if guard.serving == turn {
+                Self::pass_turn(term, &mut guard, ctx.kernel());
+            } else {
+                guard.abandoned[turn % NREADER] = true;
             }
+            return -1;
+        }
+
+        let target = n;
+        while n > 0 && guard.r != guard.w {
             let cin = guard.buf[guard.r % INPUT_BUF] as i32;
             guard.r = guard.r.wrapping_add(1);
 
@@ -221,12 +583,34 @@ impl Console {
                 }
             }
         }
+        if self.active.load(Ordering::Relaxed) == vc {
+            self.flow_control(&guard, ctx.kernel().as_ref());
+        }
+        Self::pass_turn(term, &mut guard, ctx.kernel());
         target - n
     }
 
+    /// Pass the turn to the next reader, skipping the readers that were killed while waiting,
+    /// and wake it up.
+    fn pass_turn(
+        term: &Terminal,
+        guard: &mut SleepableLockGuard<'_, InputBuffer>,
+        kernel: KernelRef<'_, '_>,
+    ) {
+        loop {
+            guard.serving = guard.serving.wrapping_add(1);
+            let i = guard.serving % NREADER;
+            if guard.serving == guard.next_reader || !guard.abandoned[i] {
+                break;
+            }
+            guard.abandoned[i] = false;
+        }
+        term.readers[guard.serving % NREADER].wakeup(kernel);
+    }
+
     /// Handle a uart interrupt, raised because input has arrived, or the uart is ready for more
-    /// output, or both. Called from trap.c. Do erase/kill processing, append to the input buffer,
-    /// and wake up read() if a whole line has arrived.
+    /// output, or both. Called from trap.c. Do erase/kill processing, append to the input buffer
+    /// of the active virtual console, and wake up read() if a whole line has arrived.
     ///
     /// # Note
     ///
@@ -234,7 +618,28 @@ impl Console {
     pub unsafe fn intr(&self, kernel: KernelRef<'_, '_>) {
         // Read and process incoming characters.
         while let Ok(c) = self.uart.getc() {
-            let mut guard = self.input_buffer.lock();
+            // Switch virtual consoles.
+            if self.switching.swap(false, Ordering::Relaxed) {
+                let n = c - '0' as i32;
+                if (0..NVCONSOLE as i32).contains(&n) {
+                    self.switch_spin(n as usize, kernel.as_ref());
+                    continue;
+                }
+            }
+            if c == ctrl('T') {
+                self.switching.store(true, Ordering::Relaxed);
+                continue;
+            }
+
+            // Stop or resume the output.
+            if c == XOFF as i32 || c == XON as i32 {
+                self.output_stopped
+                    .store(c == XOFF as i32, Ordering::Relaxed);
+                continue;
+            }
+
+            let term = &self.terminals[self.active.load(Ordering::Relaxed)];
+            let mut guard = term.input_buffer.lock();
             match c {
                 // Print process list.
                 m if m == ctrl('P') => {
@@ -247,7 +652,7 @@ impl Console {
                         && guard.buf[guard.e.wrapping_sub(1) % INPUT_BUF] != b'\n'
                     {
                         guard.e = guard.e.wrapping_sub(1);
-                        self.put_backspace_spin(kernel.as_ref());
+                        self.put_backspace_spin(term, kernel.as_ref());
                     }
                 }
 
@@ -255,7 +660,7 @@ impl Console {
                 m if m == ctrl('H') | '\x7f' as i32 => {
                     if guard.e != guard.w {
                         guard.e = guard.e.wrapping_sub(1);
-                        self.put_backspace_spin(kernel.as_ref());
+                        self.put_backspace_spin(term, kernel.as_ref());
                     }
                 }
 
@@ -264,7 +669,7 @@ impl Console {
                         let c = if c == '\r' as i32 { '\n' as i32 } else { c };
 
                         // Echo back to the user.
-                        self.putc_spin(c as u8, kernel.as_ref());
+                        self.echo_spin(term, c as u8, kernel.as_ref());
 
                         // Store for consumption by read().
                         let ind = guard.e % INPUT_BUF;
@@ -272,12 +677,15 @@ impl Console {
                         guard.e = guard.e.wrapping_add(1);
                         if c == '\n' as i32
                             || c == ctrl('D')
-                            || guard.e == guard.r.wrapping_add(INPUT_BUF)
+                            || guard.e.wrapping_sub(guard.r) >= INPUT_HIGH
                         {
-                            // Wake up read() if a whole line (or end-of-file) has arrived.
+                            // Wake up the reader whose turn it is if a whole line
+                            // (or end-of-file) has arrived, or if the buffer is filling up,
+                            // since the other end may stop sending before the line ends.
                             guard.w = guard.e;
-                            guard.wakeup(kernel);
+                            term.readers[guard.serving % NREADER].wakeup(kernel);
                         }
+                        self.flow_control(&guard, kernel.as_ref());
                     }
                 }
             }
@@ -315,6 +723,31 @@ impl Printer {
     }
 }
 
+impl<A: Arch> PrinterGuard<'_, A> {
+    /// Begins a kernel message of `severity` on a new line, turning off the colors of the user
+    /// output, and writes its prefix.
+    pub fn begin(&mut self, severity: Severity) {
+        let console = hal().console();
+        let cursor = Cursor(console.cursor.load(Ordering::Relaxed));
+        if cursor.escape() != ESC_NONE || cursor.styled() {
+            // Cancel an unfinished escape sequence and reset the attributes.
+            let _ = fmt::Write::write_str(self, "\x1b[0m");
+        }
+        if cursor.column() != 0 {
+            let _ = fmt::Write::write_str(self, "\n");
+        }
+        let (prefix, color) = severity.prefix();
+        if prefix.is_empty() {
+            return;
+        }
+        let _ = if console.log_color.load(Ordering::Relaxed) {
+            fmt::Write::write_fmt(self, format_args!("\x1b[1;{}m{}\x1b[0m ", color, prefix))
+        } else {
+            fmt::Write::write_fmt(self, format_args!("{} ", prefix))
+        };
+    }
+}
+
 impl<A: Arch> fmt::Write for PrinterGuard<'_, A> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for c in s.bytes() {
@@ -329,14 +762,64 @@ const fn ctrl(x: char) -> i32 {
     x as i32 - '@' as i32
 }
 
+/// Stops the other end from sending, which is control-s.
+const XOFF: u8 = ctrl('S') as u8;
+/// Lets the other end send again, which is control-q.
+const XON: u8 = ctrl('Q') as u8;
+
 /// User write()s to the console go here.
 pub fn console_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
-    hal().console().write(src, n, ctx)
+    hal().console().write(0, src, n, ctx)
 }
 
 /// User read()s from the console go here.
 /// Copy (up to) a whole input line to dst.
 /// User_dist indicates whether dst is a user or kernel address.
 pub fn console_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
-    hal().console().read(dst, n, ctx)
+    hal().console().read(0, dst, n, ctx)
+}
+
+/// User write()s to /dev/ttyN go here.
+fn vconsole_write<const N: usize>(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    hal().console().write(N, src, n, ctx)
+}
+
+/// User read()s from /dev/ttyN go here.
+fn vconsole_read<const N: usize>(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    hal().console().read(N, dst, n, ctx)
+}
+
+/// Registers the virtual consoles other than the console itself.
+pub fn register_devices(devsw: &mut [Devsw; NDEV]) {
+    let vconsoles = [
+        Devsw {
+            read: Some(vconsole_read::<1>),
+            write: Some(vconsole_write::<1>),
+        },
+        Devsw {
+            read: Some(vconsole_read::<2>),
+            write: Some(vconsole_write::<2>),
+        },
+        Devsw {
+            read: Some(vconsole_read::<3>),
+            write: Some(vconsole_write::<3>),
+        },
+    ];
+    for (i, vconsole) in vconsoles.iter().take(NVCONSOLE - 1).enumerate() {
+        devsw[VCONSOLE_DEVSW + i] = *vconsole;
+    }
+}
+
+/// User write()s to /dev/tty go here, to the controlling terminal of the process.
+pub fn tty_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    let major = some_or!(ctx.proc().deref_data().ctty, return -1);
+    let write = some_or!(ctx.kernel().devsw()[major as usize].write, return -1);
+    write(src, n, ctx)
+}
+
+/// User read()s from /dev/tty go here, from the controlling terminal of the process.
+pub fn tty_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    let major = some_or!(ctx.proc().deref_data().ctty, return -1);
+    let read = some_or!(ctx.kernel().devsw()[major as usize].read, return -1);
+    read(dst, n, ctx)
 }
@@ -2,14 +2,16 @@ use core::{
     cell::{Cell, UnsafeCell},
     marker::PhantomData,
     ptr::{self, NonNull},
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
 };
 
 use array_macro::array;
+use zerocopy::AsBytes;
 
 use crate::{
-    arch::interface::{ContextManager, ProcManager, TrapManager},
+    arch::interface::{ContextManager, ProcManager, TimeManager, TrapManager},
     arch::TargetArch,
-    param::NCPU,
+    param::{LOADWINDOW, NCPU},
     proc::Proc,
 };
 
@@ -39,6 +41,76 @@ impl Cpus {
     pub const fn new() -> Self {
         Self(array![_ => UnsafeCell::new(Cpu::new()); NCPU])
     }
+
+    /// Returns the context switch counters summed over all CPUs.
+    pub fn sched_stat(&self) -> SchedStat {
+        let mut stat = SchedStat::default();
+        for cpu in &self.0 {
+            // SAFETY: the counters are atomic, and no `&mut Cpu` is ever created.
+            let (nswitch, switch_cycles) = unsafe {
+                let cpu = cpu.get();
+                (&(*cpu).nswitch, &(*cpu).switch_cycles)
+            };
+            stat.nswitch += nswitch.load(Ordering::Relaxed);
+            stat.switch_cycles += switch_cycles.load(Ordering::Relaxed);
+            // SAFETY: the counter is atomic, and no `&mut Cpu` is ever created.
+            stat.nmigrate += unsafe { (*cpu.get()).nmigrate.load(Ordering::Relaxed) };
+            // SAFETY: the counter is atomic, and no `&mut Cpu` is ever created.
+            stat.ninherit += unsafe { (*cpu.get()).ninherit.load(Ordering::Relaxed) };
+        }
+        stat
+    }
+
+    /// Returns whether CPU `id` has started its scheduler.
+    pub fn is_started(&self, id: usize) -> bool {
+        // SAFETY: the field is atomic, and no `&mut Cpu` is ever created.
+        unsafe { (*self.0[id].get()).window_start.load(Ordering::Relaxed) != 0 }
+    }
+
+    /// Returns whether CPU `id` is running a process.
+    pub fn is_running(&self, id: usize) -> bool {
+        // SAFETY: the field is atomic, and no `&mut Cpu` is ever created.
+        !unsafe { (*self.0[id].get()).running.load(Ordering::Relaxed) }.is_null()
+    }
+
+    /// Returns whether a CPU is running process `proc`.
+    pub fn runs(&self, proc: *const Proc) -> bool {
+        self.0.iter().any(|cpu| {
+            // SAFETY: the field is atomic, and no `&mut Cpu` is ever created.
+            let running = unsafe { (*cpu.get()).running.load(Ordering::Relaxed) };
+            ptr::eq(running, proc)
+        })
+    }
+
+    /// Returns the load of each CPU.
+    pub fn load(&self) -> [CpuLoad; NCPU] {
+        let mut load = [CpuLoad::default(); NCPU];
+        for (cpu, load) in self.0.iter().zip(load.iter_mut()) {
+            // SAFETY: the counters are atomic, and no `&mut Cpu` is ever created.
+            let (window_start, busy_cycles, prev_window, prev_busy, nrunnable) = unsafe {
+                let cpu = cpu.get();
+                (
+                    &(*cpu).window_start,
+                    &(*cpu).busy_cycles,
+                    &(*cpu).prev_window,
+                    &(*cpu).prev_busy,
+                    &(*cpu).nrunnable,
+                )
+            };
+            let start = window_start.load(Ordering::Relaxed);
+            if start == 0 {
+                continue;
+            }
+            *load = CpuLoad {
+                nrunnable: nrunnable.load(Ordering::Relaxed),
+                busy_cycles: prev_busy.load(Ordering::Relaxed)
+                    + busy_cycles.load(Ordering::Relaxed),
+                window_cycles: prev_window.load(Ordering::Relaxed)
+                    + TargetArch::r_cycle().wrapping_sub(start),
+            };
+        }
+        load
+    }
 }
 
 impl Cpus {
@@ -107,6 +179,9 @@ pub struct Cpu {
     /// The process running on this cpu, or null.
     proc: *const Proc,
 
+    /// The same as `proc`, for the other CPUs to see.
+    running: AtomicPtr<Proc>,
+
     /// swtch() here to enter scheduler().
     context: <TargetArch as ProcManager>::Context,
 
@@ -115,15 +190,81 @@ pub struct Cpu {
 
     /// Were interrupts enabled before push_off()?
     interrupt_enabled: bool,
+
+    /// Cycle counter when the ongoing switch between a process and the scheduler began.
+    switch_start: usize,
+
+    /// Number of switches from a process to the scheduler.
+    nswitch: AtomicUsize,
+
+    /// Cycles spent switching between processes and the scheduler, in both directions.
+    switch_cycles: AtomicUsize,
+
+    /// Number of processes that ran on this CPU after they last ran on another.
+    nmigrate: AtomicUsize,
+
+    /// Number of times a process waiting for a sleep lock on this CPU raised the priority of
+    /// the holder.
+    ninherit: AtomicUsize,
+
+    /// Cycle counter when the current load window began, or 0 before the scheduler starts.
+    window_start: AtomicUsize,
+
+    /// Cycles spent running processes in the current load window.
+    busy_cycles: AtomicUsize,
+
+    /// Length of the previous load window.
+    prev_window: AtomicUsize,
+
+    /// Cycles spent running processes in the previous load window.
+    prev_busy: AtomicUsize,
+
+    /// Number of runnable processes found by the last scan of the scheduler.
+    nrunnable: AtomicUsize,
+}
+
+/// Context switch counters, read by `schedstat()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct SchedStat {
+    pub nswitch: usize,
+    pub switch_cycles: usize,
+    pub nmigrate: usize,
+    pub ninherit: usize,
+}
+
+/// Load of a CPU over its current and previous load windows, read by `cpuload()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct CpuLoad {
+    /// Number of runnable processes found by the last scan of the scheduler
+    pub nrunnable: usize,
+
+    /// Cycles spent running processes
+    pub busy_cycles: usize,
+
+    /// Cycles the windows have lasted, or 0 if the CPU has not started
+    pub window_cycles: usize,
 }
 
 impl Cpu {
     const fn new() -> Self {
         Self {
             proc: ptr::null_mut(),
+            running: AtomicPtr::new(ptr::null_mut()),
             context: <TargetArch as ProcManager>::Context::new(),
             noff: 0,
             interrupt_enabled: false,
+            switch_start: 0,
+            nswitch: AtomicUsize::new(0),
+            switch_cycles: AtomicUsize::new(0),
+            nmigrate: AtomicUsize::new(0),
+            ninherit: AtomicUsize::new(0),
+            window_start: AtomicUsize::new(0),
+            busy_cycles: AtomicUsize::new(0),
+            prev_window: AtomicUsize::new(0),
+            prev_busy: AtomicUsize::new(0),
+            nrunnable: AtomicUsize::new(0),
         }
     }
 }
@@ -168,6 +309,9 @@ impl CpuMut<'_> {
         // SAFETY: invariant of `CpuMut`
         unsafe {
             (*self.ptr.as_ptr()).proc = proc;
+            (*self.ptr.as_ptr())
+                .running
+                .store(proc as *mut _, Ordering::Relaxed);
         }
     }
 
@@ -195,6 +339,71 @@ impl CpuMut<'_> {
         }
     }
 
+    /// Marks the beginning of a switch between a process and the scheduler.
+    pub fn switch_begin(&self) {
+        // SAFETY: invariant of `CpuMut`
+        unsafe {
+            (*self.ptr()).switch_start = TargetArch::r_cycle();
+        }
+    }
+
+    /// Marks the end of a switch between a process and the scheduler, and accounts for its
+    /// cycles. `to_scheduler` tells whether the switch was from a process to the scheduler.
+    pub fn switch_end(&self, to_scheduler: bool) {
+        // SAFETY: invariant of `CpuMut`
+        let (switch_start, nswitch, switch_cycles) = unsafe {
+            let cpu = self.ptr();
+            ((*cpu).switch_start, &(*cpu).nswitch, &(*cpu).switch_cycles)
+        };
+        let cycles = TargetArch::r_cycle().wrapping_sub(switch_start);
+        let _ = switch_cycles.fetch_add(cycles, Ordering::Relaxed);
+        if to_scheduler {
+            let _ = nswitch.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a process that runs on this CPU after it last ran on another.
+    pub fn count_migration(&self) {
+        // SAFETY: invariant of `CpuMut`
+        let nmigrate = unsafe { &(*self.ptr()).nmigrate };
+        let _ = nmigrate.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a process on this CPU that has raised the priority of the holder of a sleep lock.
+    pub fn count_inheritance(&self) {
+        // SAFETY: invariant of `CpuMut`
+        let ninherit = unsafe { &(*self.ptr()).ninherit };
+        let _ = ninherit.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts for `cycles` spent running a process.
+    pub fn add_busy(&self, cycles: usize) {
+        // SAFETY: invariant of `CpuMut`
+        let busy_cycles = unsafe { &(*self.ptr()).busy_cycles };
+        let _ = busy_cycles.fetch_add(cycles, Ordering::Relaxed);
+    }
+
+    /// Records the number of runnable processes found by a scan of the scheduler, and begins a
+    /// new load window if the current one has lasted `LOADWINDOW` cycles.
+    pub fn update_load(&self, nrunnable: usize) {
+        // SAFETY: invariant of `CpuMut`
+        let cpu = unsafe { &*self.ptr() };
+        cpu.nrunnable.store(nrunnable, Ordering::Relaxed);
+        let now = TargetArch::r_cycle();
+        let start = cpu.window_start.load(Ordering::Relaxed);
+        if start == 0 {
+            cpu.window_start.store(now, Ordering::Relaxed);
+        } else if now.wrapping_sub(start) >= LOADWINDOW {
+            cpu.prev_window
+                .store(now.wrapping_sub(start), Ordering::Relaxed);
+            cpu.prev_busy.store(
+                cpu.busy_cycles.swap(0, Ordering::Relaxed),
+                Ordering::Relaxed,
+            );
+            cpu.window_start.store(now, Ordering::Relaxed);
+        }
+    }
+
     fn push_off(&self, old: bool) {
         let noff = self.get_noff();
         if noff == 0 {
@@ -0,0 +1,119 @@
+//! Cycle and instruction counters for benchmarks.
+//!
+//! `cycles()` reads the cycle counter and the number of instructions retired. A benchmark can ask
+//! to serialize execution around the read, so that the instructions around it do not overlap it,
+//! and to read the counters of the process instead of those of the CPU. The counters of a process
+//! only advance while it runs, so the time it spends preempted or sleeping is not measured.
+
+use core::ops::{Add, Sub};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    arch::interface::TimeManager, arch::TargetArch, proc::KernelCtx, syscall::SyscallTable,
+};
+
+/// Flags of `cycles()`.
+pub const CYCLES_SERIALIZE: i32 = 1;
+pub const CYCLES_VIRTUAL: i32 = 2;
+
+/// Counters read by `cycles()`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Cycles {
+    /// Cycles
+    pub cycle: usize,
+
+    /// Instructions retired
+    pub instret: usize,
+}
+
+impl Cycles {
+    pub const fn new() -> Self {
+        Self {
+            cycle: 0,
+            instret: 0,
+        }
+    }
+
+    /// Reads the counters of the current CPU.
+    pub fn read() -> Self {
+        Self {
+            cycle: TargetArch::r_cycle(),
+            instret: TargetArch::r_instret(),
+        }
+    }
+}
+
+impl Add for Cycles {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            cycle: self.cycle.wrapping_add(other.cycle),
+            instret: self.instret.wrapping_add(other.instret),
+        }
+    }
+}
+
+impl Sub for Cycles {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            cycle: self.cycle.wrapping_sub(other.cycle),
+            instret: self.instret.wrapping_sub(other.instret),
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Reads the counters of the current CPU, or of the current process if `CYCLES_VIRTUAL` is
+    /// in flags.
+    fn cycles(&self, flags: i32) -> Cycles {
+        if flags & CYCLES_SERIALIZE != 0 {
+            TargetArch::serialize();
+        }
+        let now = Cycles::read();
+        if flags & CYCLES_SERIALIZE != 0 {
+            TargetArch::serialize();
+        }
+        if flags & CYCLES_VIRTUAL != 0 {
+            self.proc().run_cycles(now)
+        } else {
+            now
+        }
+    }
+
+    /// Store the cycle counter into the word at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_clock(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let clk = self.cycles(0).cycle;
+        self.proc_mut().memory_mut().copy_out(addr.into(), &clk)?;
+        Ok(0)
+    }
+
+    /// Store the counters into the struct cycles at addr, according to flags:
+    /// * `CYCLES_SERIALIZE` waits for the earlier instructions to complete before reading the
+    ///   counters, and the later ones to wait for the read.
+    /// * `CYCLES_VIRTUAL` reads the counters of the current process instead of the CPU.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_cycles(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let flags = self.proc().argint(1)?;
+        if flags & !(CYCLES_SERIALIZE | CYCLES_VIRTUAL) != 0 {
+            return Err(());
+        }
+        let counters = self.cycles(flags);
+        self.proc_mut()
+            .memory_mut()
+            .copy_out(addr.into(), &counters)?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of the cycle counters.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(80, |ctx| ctx.sys_cycles());
+}
@@ -0,0 +1,57 @@
+//! Pseudo-devices.
+//!
+//! * null -- reads hit end-of-file at once, and writes are discarded.
+//! * zero -- reads return zeroes, and writes are discarded.
+//! * full -- reads return zeroes, and writes fail as if the device were full.
+
+use crate::{addr::UVAddr, file::Devsw, param::NDEV, proc::KernelCtx};
+
+/// Major device numbers.
+const NULL_DEVSW: usize = 3;
+const ZERO_DEVSW: usize = 4;
+const FULL_DEVSW: usize = 5;
+
+fn null_read(_dst: UVAddr, _n: i32, _ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    0
+}
+
+fn discard_write(_src: UVAddr, n: i32, _ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    if n < 0 {
+        return -1;
+    }
+    n
+}
+
+fn full_write(_src: UVAddr, _n: i32, _ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    -1
+}
+
+/// Zeroes the user pages at dst directly, without copying from a kernel buffer.
+fn zero_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    if n < 0
+        || ctx
+            .proc_mut()
+            .memory_mut()
+            .zero_out(dst, n as usize)
+            .is_err()
+    {
+        return -1;
+    }
+    n
+}
+
+/// Registers the pseudo-devices.
+pub fn register_devices(devsw: &mut [Devsw; NDEV]) {
+    devsw[NULL_DEVSW] = Devsw {
+        read: Some(null_read),
+        write: Some(discard_write),
+    };
+    devsw[ZERO_DEVSW] = Devsw {
+        read: Some(zero_read),
+        write: Some(discard_write),
+    };
+    devsw[FULL_DEVSW] = Devsw {
+        read: Some(zero_read),
+        write: Some(full_write),
+    };
+}
@@ -0,0 +1,523 @@
+//! epoll-style event notification.
+//!
+//! A process creates an epoll with `epoll_create()`, adds the file descriptors it is interested
+//! in to the epoll's interest list with `epoll_ctl()`, and waits with `epoll_wait()` until some
+//! of them are ready. Pipes, sockets, and watches can be added.
+//!
+//! Each pollable object keeps a list of the epolls that watch it, its `Waiters`. Whenever the
+//! object wakes up its readers or writers, it also posts to its waiters, which marks the object's
+//! files pending in the epolls and wakes up the processes waiting on the epolls. So a process
+//! sleeping in `epoll_wait()` wakes up only when one of the files it watches may have become
+//! ready, however many files it watches.
+//! * A level-triggered file is reported whenever it is ready.
+//! * An edge-triggered file (`EPOLLET`) is reported only if an event was posted to it since it
+//!   was last reported, or since it was added or modified.
+//!
+//! A registration is removed from an object's waiters lazily, when a post finds that the epoll
+//! no longer watches the file.
+
+use core::{mem, ops::Deref, ptr::NonNull};
+
+use array_macro::array;
+use bitflags::bitflags;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    addr::UVAddr,
+    arch::interface::TimeManager,
+    arch::TargetArch,
+    file::{File, FileType, RcFile, SelectEvent},
+    kernel::KernelRef,
+    lock::SleepableLock,
+    param::{NEPOLL, NEPOLLWAITER, NOFILE},
+    proc::KernelCtx,
+    some_or,
+    syscall::SyscallTable,
+};
+
+/// Operations of `epoll_ctl()`.
+const EPOLL_CTL_ADD: i32 = 1;
+const EPOLL_CTL_DEL: i32 = 2;
+const EPOLL_CTL_MOD: i32 = 3;
+
+bitflags! {
+    pub struct EpollFlags: u32 {
+        /// The file can be read without blocking.
+        const IN = 0x1;
+        /// The file can be written without blocking.
+        const OUT = 0x4;
+        /// Report the file only when an event is posted to it.
+        const ET = 1 << 31;
+    }
+}
+
+/// `struct epoll_event`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct EpollEvent {
+    /// `EpollFlags` to watch, or the ready ones
+    events: u32,
+
+    pad: u32,
+
+    /// Returned as is by `epoll_wait()`
+    data: u64,
+}
+
+/// An epoll that watches a file of a pollable object.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Waiter {
+    /// Index of the epoll in the kernel's `EpollTable`
+    epoll: usize,
+
+    /// The file, as `File::id()` returns it
+    file: usize,
+}
+
+/// The epolls that watch a pollable object.
+pub struct Waiters {
+    list: [Option<Waiter>; NEPOLLWAITER],
+}
+
+#[derive(Copy, Clone)]
+struct EpollEntry {
+    /// The file, as `File::id()` returns it. The file descriptor the entry is indexed by may be
+    /// closed and reused for another file while the file is still open.
+    file: usize,
+    events: EpollFlags,
+    data: u64,
+
+    /// An event was posted to the file since the file was last reported.
+    pending: bool,
+}
+
+struct EpollInner {
+    /// A file descriptor still refers to this epoll.
+    open: bool,
+
+    /// The interest list, indexed by file descriptor.
+    entries: [Option<EpollEntry>; NOFILE],
+
+    /// Incremented whenever an event is posted or the interest list changes.
+    seq: u32,
+
+    /// Number of processes waiting on the epoll with a timeout.
+    ntimed: usize,
+}
+
+pub struct Epoll {
+    inner: SleepableLock<EpollInner>,
+}
+
+pub struct EpollTable {
+    epolls: [Epoll; NEPOLL],
+}
+
+/// # Safety
+///
+/// `ptr` always refers to an open epoll in the kernel's `EpollTable`.
+/// For a single epoll, we have a single `AllocatedEpoll`, and the epoll is marked closed
+/// only when the `AllocatedEpoll` is closed.
+pub struct AllocatedEpoll {
+    ptr: NonNull<Epoll>,
+}
+
+// `AllocatedEpoll` is `Send` because we access `EpollInner` only after acquring a lock
+// and because `AllocatedEpoll` does not point to thread-local data.
+unsafe impl Send for AllocatedEpoll {}
+
+impl Deref for AllocatedEpoll {
+    type Target = Epoll;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `ptr` always refers to an epoll in the kernel's `EpollTable`.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl Waiters {
+    pub const fn new() -> Self {
+        Self {
+            list: [None; NEPOLLWAITER],
+        }
+    }
+
+    /// Registers `waiter`. If the list is full, first removes the registrations of epolls that
+    /// no longer watch their files.
+    pub fn add(&mut self, waiter: Waiter, kernel: KernelRef<'_, '_>) -> Result<(), ()> {
+        if self.list.contains(&Some(waiter)) {
+            return Ok(());
+        }
+        if self.list.iter().all(Option::is_some) {
+            for w in self.list.iter_mut() {
+                if matches!(w, Some(w) if !kernel.epolls().watches(*w)) {
+                    *w = None;
+                }
+            }
+        }
+        let slot = self.list.iter_mut().find(|w| w.is_none()).ok_or(())?;
+        *slot = Some(waiter);
+        Ok(())
+    }
+
+    /// Posts an event to the files of the object in every epoll that watches them, and wakes up
+    /// the processes waiting on the epolls.
+    pub fn post(&mut self, kernel: KernelRef<'_, '_>) {
+        for w in self.list.iter_mut() {
+            if let Some(waiter) = *w {
+                if !kernel.epolls().post(waiter, kernel) {
+                    *w = None;
+                }
+            }
+        }
+    }
+}
+
+impl EpollInner {
+    const fn new() -> Self {
+        Self {
+            open: false,
+            entries: [None; NOFILE],
+            seq: 0,
+            ntimed: 0,
+        }
+    }
+
+    /// Returns the entry of `waiter`'s file, if the epoll still watches it.
+    fn entry_mut(&mut self, waiter: Waiter) -> Option<&mut EpollEntry> {
+        if !self.open {
+            return None;
+        }
+        self.entries
+            .iter_mut()
+            .flatten()
+            .find(|e| e.file == waiter.file)
+    }
+}
+
+impl Epoll {
+    const fn new() -> Self {
+        Self {
+            inner: SleepableLock::new("epoll", EpollInner::new()),
+        }
+    }
+}
+
+impl EpollTable {
+    pub const fn new() -> Self {
+        Self {
+            epolls: array![_ => Epoll::new(); NEPOLL],
+        }
+    }
+
+    /// Allocates an unused epoll.
+    fn alloc(&self) -> Result<AllocatedEpoll, ()> {
+        for epoll in &self.epolls {
+            let mut inner = epoll.inner.lock();
+            if !inner.open {
+                *inner = EpollInner::new();
+                inner.open = true;
+                return Ok(AllocatedEpoll {
+                    ptr: NonNull::from(epoll),
+                });
+            }
+        }
+        Err(())
+    }
+
+    fn index(&self, epoll: &Epoll) -> usize {
+        (epoll as *const Epoll as usize - self.epolls.as_ptr() as usize) / mem::size_of::<Epoll>()
+    }
+
+    /// Does the epoll of `waiter` still watch `waiter`'s file?
+    fn watches(&self, waiter: Waiter) -> bool {
+        self.epolls[waiter.epoll]
+            .inner
+            .lock()
+            .entry_mut(waiter)
+            .is_some()
+    }
+
+    /// Marks `waiter`'s file pending, and wakes up the processes waiting on `waiter`'s epoll.
+    /// Returns false if the epoll no longer watches the file.
+    fn post(&self, waiter: Waiter, kernel: KernelRef<'_, '_>) -> bool {
+        let mut inner = self.epolls[waiter.epoll].inner.lock();
+        let entry = some_or!(inner.entry_mut(waiter), return false);
+        entry.pending = true;
+        inner.seq = inner.seq.wrapping_add(1);
+        inner.wakeup(kernel);
+        true
+    }
+
+    /// Removes the file `file` from every epoll, since the file was closed and its `File` may be
+    /// reused.
+    pub fn forget(&self, file: usize) {
+        for epoll in &self.epolls {
+            let mut inner = epoll.inner.lock();
+            for entry in inner.entries.iter_mut() {
+                if matches!(entry, Some(e) if e.file == file) {
+                    *entry = None;
+                }
+            }
+        }
+    }
+
+    /// Wakes up the processes waiting on an epoll with a timeout, so that they check whether
+    /// the timeout has expired. Called on every clock tick.
+    pub fn tick(&self, kernel: KernelRef<'_, '_>) {
+        for epoll in &self.epolls {
+            let inner = epoll.inner.lock();
+            if inner.ntimed > 0 {
+                inner.wakeup(kernel);
+            }
+        }
+    }
+}
+
+/// Has `deadline`, in microseconds since boot, passed? There is no deadline if it is `None`.
+fn expired(deadline: Option<usize>) -> bool {
+    deadline.map_or(false, |deadline| {
+        TargetArch::uptime_as_micro().map_or(true, |now| now >= deadline)
+    })
+}
+
+impl AllocatedEpoll {
+    /// Adds the file `target` of file descriptor `fd` to the interest list, or modifies or
+    /// removes its entry, as `op` says.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn ctl(
+        &self,
+        op: i32,
+        fd: i32,
+        target: &File,
+        event: &EpollEvent,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let file = target.id();
+        let events = EpollFlags::from_bits_truncate(event.events);
+        let fd = fd as usize;
+        let mut inner = self.inner.lock();
+        match op {
+            EPOLL_CTL_ADD => {
+                // Reject a file that cannot be watched before adding the entry, since another
+                // process may collect events from the entry once the lock is released.
+                if !target.is_watchable() || matches!(inner.entries[fd], Some(e) if e.file == file)
+                {
+                    return Err(());
+                }
+                inner.entries[fd] = Some(EpollEntry {
+                    file,
+                    events,
+                    data: event.data,
+                    pending: true,
+                });
+                drop(inner);
+
+                // Register only after adding the entry, since a post to a file the epoll does
+                // not watch removes the registration.
+                let waiter = Waiter {
+                    epoll: ctx.kernel().epolls().index(self),
+                    file,
+                };
+                let result = target.add_waiter(waiter, ctx.kernel());
+                inner = self.inner.lock();
+                if result.is_err() {
+                    inner.entries[fd] = None;
+                    return Err(());
+                }
+            }
+            EPOLL_CTL_MOD => {
+                let entry = inner.entries[fd]
+                    .as_mut()
+                    .filter(|e| e.file == file)
+                    .ok_or(())?;
+                entry.events = events;
+                entry.data = event.data;
+                entry.pending = true;
+            }
+            EPOLL_CTL_DEL => {
+                if !matches!(inner.entries[fd], Some(e) if e.file == file) {
+                    return Err(());
+                }
+                inner.entries[fd] = None;
+                return Ok(());
+            }
+            _ => return Err(()),
+        }
+        // The file may be ready already.
+        inner.seq = inner.seq.wrapping_add(1);
+        inner.wakeup(ctx.kernel());
+        Ok(())
+    }
+
+    /// Copies out to `addr` an `EpollEvent` for each ready file of the interest list, by at
+    /// most `maxevents`.
+    /// Returns Ok(number of events) on success, Err(()) on error.
+    fn collect(
+        &self,
+        addr: UVAddr,
+        maxevents: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let mut n = 0;
+        for fd in 0..NOFILE {
+            if n == maxevents {
+                break;
+            }
+            let entry = {
+                let mut inner = self.inner.lock();
+                let entry = some_or!(inner.entries[fd].as_mut(), continue);
+                if entry.events.contains(EpollFlags::ET) {
+                    if !entry.pending {
+                        continue;
+                    }
+                    entry.pending = false;
+                }
+                *entry
+            };
+
+            // The file is checked without holding the epoll's lock, since an object posts to
+            // epolls while holding its own lock.
+            let mut ready = EpollFlags::empty();
+            {
+                let fdentry = ctx.proc().deref_data().open_files[fd].as_ref();
+                let f = some_or!(fdentry.filter(|e| e.file.id() == entry.file), continue);
+                if entry.events.contains(EpollFlags::IN)
+                    && f.file.is_ready(SelectEvent::Read).unwrap_or(false)
+                {
+                    ready |= EpollFlags::IN;
+                }
+                if entry.events.contains(EpollFlags::OUT)
+                    && f.file.is_ready(SelectEvent::Write).unwrap_or(false)
+                {
+                    ready |= EpollFlags::OUT;
+                }
+            }
+            if ready.is_empty() {
+                continue;
+            }
+
+            let event = EpollEvent {
+                events: ready.bits(),
+                pad: 0,
+                data: entry.data,
+            };
+            ctx.proc_mut()
+                .memory_mut()
+                .copy_out(addr + n * mem::size_of::<EpollEvent>(), &event)?;
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Waits until some files of the interest list are ready, and copies out to `addr` an
+    /// `EpollEvent` for each of them, by at most `maxevents`.
+    /// Waits at most `timeout` milliseconds, or forever if `timeout` is negative.
+    /// Returns Ok(number of events, which is 0 on timeout) on success, Err(()) on error.
+    pub fn wait(
+        &self,
+        addr: UVAddr,
+        maxevents: usize,
+        timeout: i32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let deadline = if timeout < 0 {
+            None
+        } else {
+            Some(TargetArch::uptime_as_micro()?.saturating_add(timeout as usize * 1000))
+        };
+        loop {
+            let seq = self.inner.lock().seq;
+            let n = self.collect(addr, maxevents, ctx)?;
+            if n > 0 || expired(deadline) {
+                return Ok(n);
+            }
+
+            // Sleep until an event is posted or the interest list changes after `seq`, as the
+            // clock wakes up the epoll on every tick while it has a timeout.
+            let mut inner = self.inner.lock();
+            if deadline.is_some() {
+                inner.ntimed += 1;
+            }
+            let result =
+                inner.wait_while_killable(|inner| inner.seq == seq && !expired(deadline), ctx);
+            if deadline.is_some() {
+                inner.ntimed -= 1;
+            }
+            result?;
+        }
+    }
+
+    pub fn close(self) {
+        let mut inner = self.inner.lock();
+        inner.open = false;
+        inner.entries = [None; NOFILE];
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Create an epoll with an empty interest list.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    pub fn sys_epoll_create(&mut self) -> Result<usize, ()> {
+        // As in Linux, the size is only a hint, which must be positive.
+        if self.proc().argint(0)? <= 0 {
+            return Err(());
+        }
+        let epoll = self.kernel().epolls().alloc()?;
+        let ptr = epoll.ptr;
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Epoll { epoll }, true, false, self)
+            .map_err(|_| AllocatedEpoll { ptr }.close())?;
+        let fd = f.fdalloc(self)?;
+        Ok(fd as usize)
+    }
+
+    /// Add fd to the interest list of the epoll epfd with the struct epoll_event at event, or
+    /// modify or remove its entry, as op says.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_epoll_ctl(&mut self) -> Result<usize, ()> {
+        let op = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(3)?;
+        let mut event = EpollEvent::default();
+        if op != EPOLL_CTL_DEL {
+            // SAFETY: EpollEvent does not have any internal structure.
+            unsafe {
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_in(&mut event, addr.into())
+            }?;
+        }
+        let (_, f) = self.proc().argfd(0)?;
+        let (fd, target) = self.proc().argfd(2)?;
+        f.epoll()?.ctl(op, fd, target, &event, self)?;
+        Ok(0)
+    }
+
+    /// Wait at most timeout milliseconds for the files of the epoll epfd to be ready, and store
+    /// at most maxevents struct epoll_events of the ready ones at events.
+    /// Returns Ok(number of ready files) on success, Err(()) on error.
+    pub fn sys_epoll_wait(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let f = f as *const RcFile;
+        let addr = self.proc().argaddr(1)?;
+        let maxevents = self.proc().argint(2)?;
+        let timeout = self.proc().argint(3)?;
+        if maxevents <= 0 {
+            return Err(());
+        }
+        // SAFETY: epoll_wait only reads proc's open_files, and does not close any file.
+        unsafe {
+            (*f).epoll()?
+                .wait(addr.into(), maxevents as usize, timeout, self)
+        }
+    }
+}
+
+/// Registers the system calls of epolls.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(92, |ctx| ctx.sys_epoll_create());
+    table.register(93, |ctx| ctx.sys_epoll_ctl());
+    table.register(94, |ctx| ctx.sys_epoll_wait());
+}
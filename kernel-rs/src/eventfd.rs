@@ -0,0 +1,368 @@
+//! eventfd and timerfd.
+//!
+//! Both are a 64-bit counter that a process reads as 8 bytes, so that an event loop waits on
+//! them with an epoll along with pipes and sockets.
+//! * An eventfd is a counter processes signal each other with. A write adds to the counter,
+//!   and waits while the sum would overflow. A read returns the counter and resets it to 0, or,
+//!   with `EFD_SEMAPHORE`, returns 1 and decrements it. A read waits while the counter is 0.
+//! * A timerfd counts the expirations of a timer, which the clock checks on every tick, so the
+//!   timer expires at the first tick after its deadline. A read returns the number of
+//!   expirations since the last read, and waits while there was none.
+
+use core::{mem, ops::Deref, ptr::NonNull};
+
+use array_macro::array;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    addr::UVAddr,
+    arch::interface::TimeManager,
+    arch::TargetArch,
+    epoll::{Waiter, Waiters},
+    file::{FileType, SelectEvent},
+    kernel::KernelRef,
+    lock::SleepableLock,
+    ok_or,
+    param::NEVENTFD,
+    proc::KernelCtx,
+    some_or,
+    syscall::{SyscallTable, TimeSpec},
+};
+
+/// Flag of `eventfd()`, which makes a read take 1 from the counter.
+const EFD_SEMAPHORE: i32 = 1;
+
+/// Clocks of `timerfd_create()`. Both measure the timer from when it is set.
+const CLOCK_REALTIME: i32 = 0;
+const CLOCK_MONOTONIC: i32 = 1;
+
+/// The largest value of the counter.
+const COUNT_MAX: u64 = u64::MAX - 1;
+
+/// `struct itimerspec`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct ITimerSpec {
+    /// Interval of a periodic timer, or 0 for a one-shot timer
+    interval: TimeSpec,
+
+    /// Time until the next expiration, or 0 for a disarmed timer
+    value: TimeSpec,
+}
+
+#[derive(Copy, Clone)]
+struct Timer {
+    /// The next expiration, in microseconds since boot.
+    deadline: usize,
+
+    /// Interval of a periodic timer in microseconds, or 0.
+    interval: usize,
+}
+
+struct EventFdInner {
+    /// A file descriptor still refers to this eventfd or timerfd.
+    open: bool,
+
+    count: u64,
+
+    /// A read takes 1 from the counter, rather than all of it.
+    semaphore: bool,
+
+    /// The armed timer of a timerfd.
+    timer: Option<Timer>,
+
+    /// Epolls watching the eventfd or timerfd.
+    waiters: Waiters,
+}
+
+pub struct EventFd {
+    inner: SleepableLock<EventFdInner>,
+}
+
+pub struct EventFdTable {
+    eventfds: [EventFd; NEVENTFD],
+}
+
+/// # Safety
+///
+/// `ptr` always refers to an open eventfd or timerfd in the kernel's `EventFdTable`.
+/// For a single eventfd or timerfd, we have a single `AllocatedEventFd`, and it is marked closed
+/// only when the `AllocatedEventFd` is closed.
+pub struct AllocatedEventFd {
+    ptr: NonNull<EventFd>,
+}
+
+// `AllocatedEventFd` is `Send` because we access `EventFdInner` only after acquring a lock
+// and because `AllocatedEventFd` does not point to thread-local data.
+unsafe impl Send for AllocatedEventFd {}
+
+impl Deref for AllocatedEventFd {
+    type Target = EventFd;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `ptr` always refers to an eventfd in the kernel's `EventFdTable`.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl EventFdInner {
+    const fn new() -> Self {
+        Self {
+            open: false,
+            count: 0,
+            semaphore: false,
+            timer: None,
+            waiters: Waiters::new(),
+        }
+    }
+
+    /// Returns the setting of the timer at `now`. A timer whose deadline has passed expires at
+    /// the next tick, so it reports that it has time left.
+    fn timer_spec(&self, now: usize) -> ITimerSpec {
+        let timer = some_or!(self.timer, return ITimerSpec::default());
+        ITimerSpec {
+            interval: TimeSpec::from_micro(timer.interval),
+            value: TimeSpec::from_micro(timer.deadline.saturating_sub(now).max(1)),
+        }
+    }
+}
+
+impl EventFd {
+    const fn new() -> Self {
+        Self {
+            inner: SleepableLock::new("eventfd", EventFdInner::new()),
+        }
+    }
+}
+
+impl EventFdTable {
+    pub const fn new() -> Self {
+        Self {
+            eventfds: array![_ => EventFd::new(); NEVENTFD],
+        }
+    }
+
+    /// Allocates an unused eventfd whose counter is `count`.
+    fn alloc(&self, count: u64, semaphore: bool) -> Result<AllocatedEventFd, ()> {
+        for eventfd in &self.eventfds {
+            let mut inner = eventfd.inner.lock();
+            if !inner.open {
+                *inner = EventFdInner::new();
+                inner.open = true;
+                inner.count = count;
+                inner.semaphore = semaphore;
+                return Ok(AllocatedEventFd {
+                    ptr: NonNull::from(eventfd),
+                });
+            }
+        }
+        Err(())
+    }
+
+    /// Counts the expirations of the timers whose deadlines have passed, and wakes up their
+    /// readers. Called on every clock tick.
+    pub fn tick(&self, kernel: KernelRef<'_, '_>) {
+        let now = ok_or!(TargetArch::uptime_as_micro(), return);
+        for eventfd in &self.eventfds {
+            let mut inner = eventfd.inner.lock();
+            let timer = match inner.timer {
+                Some(timer) if now >= timer.deadline => timer,
+                _ => continue,
+            };
+            let expired = if timer.interval == 0 {
+                inner.timer = None;
+                1
+            } else {
+                // Count the expirations missed since the last tick as well.
+                let n = (now - timer.deadline) / timer.interval + 1;
+                inner.timer = Some(Timer {
+                    deadline: timer.deadline + n * timer.interval,
+                    interval: timer.interval,
+                });
+                n
+            };
+            inner.count = inner.count.saturating_add(expired as u64).min(COUNT_MAX);
+            inner.wakeup(kernel);
+            inner.waiters.post(kernel);
+        }
+    }
+}
+
+impl AllocatedEventFd {
+    /// Reads the counter as 8 bytes into `addr`, waiting while it is 0.
+    /// Returns Ok(8) on success, Err(()) on error.
+    pub fn read(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+        if n < mem::size_of::<u64>() {
+            return Err(());
+        }
+
+        let mut inner = self.inner.lock();
+        inner.wait_while_killable(|inner| inner.count == 0, ctx)?;
+        let value = if inner.semaphore { 1 } else { inner.count };
+        inner.count -= value;
+        // Wake up the writers waiting for the counter to drop.
+        inner.wakeup(ctx.kernel());
+        inner.waiters.post(ctx.kernel());
+        drop(inner);
+
+        ctx.proc_mut().memory_mut().copy_out(addr, &value)?;
+        Ok(mem::size_of::<u64>())
+    }
+
+    /// Adds the 8 bytes at `addr` to the counter, waiting while the sum would overflow.
+    /// Returns Ok(8) on success, Err(()) on error.
+    pub fn write(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+        if n < mem::size_of::<u64>() {
+            return Err(());
+        }
+        let mut value = 0u64;
+        // SAFETY: u64 does not have any internal structure.
+        unsafe { ctx.proc_mut().memory_mut().copy_in(&mut value, addr) }?;
+        if value > COUNT_MAX {
+            return Err(());
+        }
+
+        let mut inner = self.inner.lock();
+        inner.wait_while_killable(|inner| inner.count > COUNT_MAX - value, ctx)?;
+        inner.count += value;
+        inner.wakeup(ctx.kernel());
+        inner.waiters.post(ctx.kernel());
+        Ok(mem::size_of::<u64>())
+    }
+
+    /// Arms the timer with `new`, or disarms it if the value of `new` is 0. The expirations
+    /// of the old setting are discarded.
+    /// Returns Ok(the old setting) on success, Err(()) on error.
+    pub fn settime(&self, new: &ITimerSpec) -> Result<ITimerSpec, ()> {
+        let value = new.value.as_micro()?;
+        let interval = new.interval.as_micro()?;
+        let now = TargetArch::uptime_as_micro()?;
+
+        let mut inner = self.inner.lock();
+        let old = inner.timer_spec(now);
+        inner.count = 0;
+        inner.timer = if value == 0 {
+            None
+        } else {
+            Some(Timer {
+                deadline: now.saturating_add(value),
+                interval,
+            })
+        };
+        Ok(old)
+    }
+
+    /// Returns the setting of the timer, with the time left until the next expiration.
+    pub fn gettime(&self) -> Result<ITimerSpec, ()> {
+        let now = TargetArch::uptime_as_micro()?;
+        Ok(self.inner.lock().timer_spec(now))
+    }
+
+    pub fn close(self) {
+        let mut inner = self.inner.lock();
+        inner.open = false;
+        inner.timer = None;
+    }
+
+    pub fn is_ready(&self, event: SelectEvent) -> bool {
+        let inner = self.inner.lock();
+        match event {
+            SelectEvent::Read => inner.count > 0,
+            SelectEvent::Write => inner.count < COUNT_MAX,
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Registers `waiter` to be posted whenever the counter changes.
+    pub fn add_waiter(&self, waiter: Waiter, kernel: KernelRef<'_, '_>) -> Result<(), ()> {
+        self.inner.lock().waiters.add(waiter, kernel)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Allocate a file descriptor for a new eventfd or timerfd. The eventfd or timerfd is closed
+    /// on failure.
+    fn eventfd_fd(&mut self, typ: FileType, ptr: NonNull<EventFd>) -> Result<usize, ()> {
+        let writable = matches!(typ, FileType::EventFd { .. });
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(typ, true, writable, self)
+            .map_err(|_| AllocatedEventFd { ptr }.close())?;
+        let fd = f.fdalloc(self)?;
+        Ok(fd as usize)
+    }
+
+    /// Create an eventfd whose counter is initval. With EFD_SEMAPHORE in flags, a read takes 1
+    /// from the counter.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    pub fn sys_eventfd(&mut self) -> Result<usize, ()> {
+        let initval = self.proc().argint(0)? as u32;
+        let flags = self.proc().argint(1)?;
+        if flags & !EFD_SEMAPHORE != 0 {
+            return Err(());
+        }
+        let eventfd = self
+            .kernel()
+            .eventfds()
+            .alloc(initval as u64, flags & EFD_SEMAPHORE != 0)?;
+        let ptr = eventfd.ptr;
+        self.eventfd_fd(FileType::EventFd { eventfd }, ptr)
+    }
+
+    /// Create a disarmed timerfd of the clock clockid.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    pub fn sys_timerfd_create(&mut self) -> Result<usize, ()> {
+        let clockid = self.proc().argint(0)?;
+        let flags = self.proc().argint(1)?;
+        if !matches!(clockid, CLOCK_REALTIME | CLOCK_MONOTONIC) || flags != 0 {
+            return Err(());
+        }
+        let timerfd = self.kernel().eventfds().alloc(0, false)?;
+        let ptr = timerfd.ptr;
+        self.eventfd_fd(FileType::TimerFd { timerfd }, ptr)
+    }
+
+    /// Set the timer of the timerfd fd to the struct itimerspec at new, and store its old
+    /// setting at old unless old is 0. No flags are supported.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_timerfd_settime(&mut self) -> Result<usize, ()> {
+        let flags = self.proc().argint(1)?;
+        let new_addr = self.proc().argaddr(2)?;
+        let old_addr = self.proc().argaddr(3)?;
+        if flags != 0 {
+            return Err(());
+        }
+        let mut new = ITimerSpec::default();
+        // SAFETY: ITimerSpec does not have any internal structure.
+        unsafe {
+            self.proc_mut()
+                .memory_mut()
+                .copy_in(&mut new, new_addr.into())
+        }?;
+        let old = self.proc().argfd(0)?.1.timerfd()?.settime(&new)?;
+        if old_addr != 0 {
+            self.proc_mut()
+                .memory_mut()
+                .copy_out(old_addr.into(), &old)?;
+        }
+        Ok(0)
+    }
+
+    /// Store the setting of the timer of the timerfd fd at cur.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_timerfd_gettime(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(1)?;
+        let cur = self.proc().argfd(0)?.1.timerfd()?.gettime()?;
+        self.proc_mut().memory_mut().copy_out(addr.into(), &cur)?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of eventfds and timerfds.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(95, |ctx| ctx.sys_eventfd());
+    table.register(96, |ctx| ctx.sys_timerfd_create());
+    table.register(97, |ctx| ctx.sys_timerfd_settime());
+    table.register(98, |ctx| ctx.sys_timerfd_gettime());
+}
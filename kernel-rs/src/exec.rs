@@ -1,19 +1,37 @@
+//! exec() and the exec cache.
+//!
+//! exec() loads a program by mapping the whole pages of its read-only segments from the page
+//! cache, and copying the rest. The exec cache keeps what exec() needs from the headers of the
+//! programs it ran recently, and the pages it mapped from them, so that running a program again
+//! neither parses its headers nor reads its text from the file. An entry is keyed by the device,
+//! the inode number, and the generation of the program, and the file system invalidates it when
+//! the program is written or truncated.
+//!
+//! A program that names a program interpreter in PT_INTERP, such as a dynamic linker, starts with
+//! the interpreter, which exec() loads above the program and tells of it with AT_BASE and
+//! AT_ENTRY.
+
 #![allow(clippy::unit_arg)]
 
-use core::{cmp, mem};
+use core::{cmp, mem, pin::Pin};
 
+use array_macro::array;
 use bitflags::bitflags;
 use itertools::*;
 use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
-    fs::{FileSystem, FileSystemExt, Path},
     addr::{pgroundup, PAddr, PGSIZE},
     arch::interface::TrapFrameManager,
+    file::FdEntry,
+    fs::{DefaultFs, FileSystem, FileSystemExt, InodeGuard, Path, RcInode},
     hal::hal,
+    kalloc::Kmem,
+    lock::SpinLock,
     page::Page,
-    param::MAXARG,
-    proc::{KernelCtx, RegNum},
+    param::{MAXARG, MAXPATH, NEXECCACHE, NEXECPAGE, NOFILE},
+    proc::{Abi, KernelCtx, RegNum},
+    some_or,
     vm::UserMemory,
 };
 
@@ -22,6 +40,28 @@ const ELF_MAGIC: u32 = 0x464c457f;
 
 /// Values for Proghdr type
 const ELF_PROG_LOAD: u32 = 1;
+const ELF_PROG_INTERP: u32 = 3;
+
+/// Types of the auxiliary vector entries
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+
+/// Number of the random bytes that AT_RANDOM points to, from which the C library takes the
+/// canary of its stack protector.
+const AT_RANDOM_SIZE: usize = 16;
+
+/// Number of words that programs find on the stack besides argv[]: argc and the null
+/// environment (Linux programs only), and the auxiliary vector.
+const STACK_EXTRA: usize = 16;
+
+/// Maximum number of loadable segments of a program.
+const MAXPROGLOAD: usize = 4;
 
 /// File header
 #[derive(Default, Clone)]
@@ -67,7 +107,7 @@ impl Default for ProgFlags {
 }
 
 /// Program section header
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Copy)]
 // It needs repr(C) because it's struct for in-disk representation
 // which should follow C(=machine) representation
 // https://github.com/kaist-cp/rv6/issues/52
@@ -84,6 +124,51 @@ struct ProgHdr {
     align: usize,
 }
 
+/// What exec() needs from the headers of a program.
+#[derive(Clone)]
+struct ProgInfo {
+    entry: usize,
+    phnum: u16,
+
+    /// Where the program finds its program headers in memory, if a segment loads them.
+    phdr: Option<usize>,
+
+    /// The loadable segments.
+    loads: [ProgHdr; MAXPROGLOAD],
+    nload: usize,
+
+    /// Path of the program interpreter that PT_INTERP names, without the NUL. Empty if none.
+    interp: [u8; MAXPATH],
+    interplen: usize,
+}
+
+/// A program that exec() ran recently.
+struct ExecEntry {
+    dev: u32,
+
+    /// Inode number of the program. 0 if the entry is unused.
+    inum: u32,
+
+    gen: u32,
+
+    info: ProgInfo,
+
+    /// Pages of the program mapped into processes, by their index within the file.
+    pages: [Option<Page>; NEXECPAGE],
+
+    /// Clock value at the last exec(), for LRU replacement.
+    last_used: u32,
+}
+
+struct ExecCacheInner {
+    entries: [ExecEntry; NEXECCACHE],
+    clock: u32,
+}
+
+pub struct ExecCache {
+    inner: SpinLock<ExecCacheInner>,
+}
+
 impl ElfHdr {
     pub fn is_valid(&self) -> bool {
         self.magic == ELF_MAGIC
@@ -91,12 +176,277 @@ impl ElfHdr {
 }
 
 impl ProgHdr {
+    const fn new() -> Self {
+        Self {
+            typ: 0,
+            flags: ProgFlags::empty(),
+            off: 0,
+            vaddr: 0,
+            paddr: 0,
+            filesz: 0,
+            memsz: 0,
+            align: 0,
+        }
+    }
+
     pub fn is_prog_load(&self) -> bool {
         self.typ == ELF_PROG_LOAD
     }
 }
 
+impl ProgInfo {
+    const fn new() -> Self {
+        Self {
+            entry: 0,
+            phnum: 0,
+            phdr: None,
+            loads: [ProgHdr::new(); MAXPROGLOAD],
+            nload: 0,
+            interp: [0; MAXPATH],
+            interplen: 0,
+        }
+    }
+
+    /// Reads the headers of the program ip.
+    fn read(ip: &mut InodeGuard<'_, DefaultFs>, ctx: &KernelCtx<'_, '_>) -> Result<Self, ()> {
+        // Check ELF header
+        let mut elf: ElfHdr = Default::default();
+        ip.read_kernel(&mut elf, 0, ctx)?;
+        if !elf.is_valid() {
+            return Err(());
+        }
+
+        let mut info = Self::new();
+        info.entry = elf.entry;
+        info.phnum = elf.phnum;
+        let phsize = elf.phnum as usize * mem::size_of::<ProgHdr>();
+        for i in 0..elf.phnum as usize {
+            let off = elf.phoff + i * mem::size_of::<ProgHdr>();
+
+            let mut ph: ProgHdr = Default::default();
+            ip.read_kernel(&mut ph, off as _, ctx)?;
+            if ph.is_prog_load() {
+                if ph.memsz < ph.filesz
+                    || ph.vaddr % PGSIZE != ph.off % PGSIZE
+                    || info.nload == MAXPROGLOAD
+                {
+                    return Err(());
+                }
+                info.loads[info.nload] = ph;
+                info.nload += 1;
+
+                // Programs find their program headers in memory if a segment loads them.
+                if ph.off <= elf.phoff && elf.phoff + phsize <= ph.off.saturating_add(ph.filesz) {
+                    info.phdr = Some(ph.vaddr + (elf.phoff - ph.off));
+                }
+            } else if ph.typ == ELF_PROG_INTERP {
+                // The path must end with its only NUL.
+                let len = ph.filesz.checked_sub(1).ok_or(())?;
+                if len == 0 || len >= MAXPATH {
+                    return Err(());
+                }
+                let path = &mut info.interp[..len + 1];
+                if ip.read_bytes_kernel(path, ph.off as _, ctx) != len + 1
+                    || path.iter().position(|c| *c == 0) != Some(len)
+                {
+                    return Err(());
+                }
+                info.interplen = len;
+            }
+        }
+        Ok(info)
+    }
+
+    fn loads(&self) -> &[ProgHdr] {
+        &self.loads[..self.nload]
+    }
+
+    /// Returns the path of the program interpreter, if any.
+    fn interp(&self) -> Option<&Path> {
+        if self.interplen == 0 {
+            return None;
+        }
+        // SAFETY: read() checked that the path has no NUL.
+        Some(unsafe { Path::from_bytes(&self.interp[..self.interplen]) })
+    }
+}
+
+impl ExecEntry {
+    const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            gen: 0,
+            info: ProgInfo::new(),
+            pages: array![_ => None; NEXECPAGE],
+            last_used: 0,
+        }
+    }
+
+    fn is_for(&self, dev: u32, inum: u32) -> bool {
+        self.inum != 0 && self.inum == inum && self.dev == dev
+    }
+
+    /// Frees the pages, and makes the entry unused.
+    fn clear(&mut self) {
+        for page in self.pages.iter_mut().filter_map(Option::take) {
+            hal().kmem().free(page);
+        }
+        self.inum = 0;
+    }
+}
+
+impl ExecCache {
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new(
+                "exec_cache",
+                ExecCacheInner {
+                    entries: array![_ => ExecEntry::new(); NEXECCACHE],
+                    clock: 0,
+                },
+            ),
+        }
+    }
+
+    /// Returns the headers of the program of generation `gen` of inode `inum` of device `dev`, if
+    /// cached.
+    fn get(&self, dev: u32, inum: u32, gen: u32) -> Option<ProgInfo> {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        inner.clock = inner.clock.wrapping_add(1);
+        let entry = inner
+            .entries
+            .iter_mut()
+            .find(|e| e.is_for(dev, inum) && e.gen == gen)?;
+        entry.last_used = inner.clock;
+        Some(entry.info.clone())
+    }
+
+    /// Caches the headers of a program, recycling the least recently used entry.
+    fn insert(&self, dev: u32, inum: u32, gen: u32, info: &ProgInfo) {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        for entry in inner.entries.iter_mut().filter(|e| e.is_for(dev, inum)) {
+            entry.clear();
+        }
+        let entry = inner
+            .entries
+            .iter_mut()
+            .min_by_key(|e| (e.inum != 0, e.last_used))
+            .expect("ExecCache::insert");
+        entry.clear();
+        entry.dev = dev;
+        entry.inum = inum;
+        entry.gen = gen;
+        entry.info = info.clone();
+        entry.last_used = inner.clock;
+    }
+
+    /// Returns the `index`th page of a cached program, shared with the cache.
+    fn share_page(&self, dev: u32, inum: u32, gen: u32, index: usize) -> Option<Page> {
+        let guard = self.inner.lock();
+        let entry = guard
+            .entries
+            .iter()
+            .find(|e| e.is_for(dev, inum) && e.gen == gen)?;
+        let page = entry.pages.get(index)?.as_ref()?;
+        Some(hal().kmem().share(page))
+    }
+
+    /// Keeps `page`, the `index`th page of a cached program, in the cache as well.
+    fn add_page(&self, dev: u32, inum: u32, gen: u32, index: usize, page: &Page) {
+        let mut guard = self.inner.lock();
+        let entry = some_or!(
+            guard
+                .entries
+                .iter_mut()
+                .find(|e| e.is_for(dev, inum) && e.gen == gen),
+            return
+        );
+        if let Some(slot) = entry.pages.get_mut(index) {
+            if slot.is_none() {
+                *slot = Some(hal().kmem().share(page));
+            }
+        }
+    }
+
+    /// Discards the cached program of inode `inum` of device `dev`, if any.
+    pub fn invalidate(&self, dev: u32, inum: u32) {
+        let mut guard = self.inner.lock();
+        for entry in guard.entries.iter_mut().filter(|e| e.is_for(dev, inum)) {
+            entry.clear();
+        }
+    }
+}
+
 impl KernelCtx<'_, '_> {
+    /// Loads the program `ptr` into `mem`, placing each segment at `base` plus its address.
+    /// Returns the headers of the program.
+    fn load_program(
+        &self,
+        mem: &mut UserMemory,
+        ptr: &RcInode<DefaultFs>,
+        base: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<ProgInfo, ()> {
+        // The generation does not change while we hold a reference to the inode.
+        let st = ptr.stat(self);
+        let (dev, inum, gen) = (st.dev as u32, st.ino, st.gen);
+        let ip = ptr.lock(self);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(self));
+
+        let cache = self.kernel().exec_cache();
+        let cached = cache.get(dev, inum, gen);
+        let hit = cached.is_some();
+        let info = match cached {
+            Some(info) => info,
+            None => {
+                let info = ProgInfo::read(&mut ip, self)?;
+                cache.insert(dev, inum, gen, &info);
+                info
+            }
+        };
+
+        let mut shared_any = false;
+        for ph in info.loads() {
+            let vaddr = base.checked_add(ph.vaddr).ok_or(())?;
+            // The whole pages of a read-only segment, such as the text, are shared with the
+            // exec cache, the page cache, and the other processes running the program, rather
+            // than copied.
+            let mut shared = 0;
+            if !ph.flags.contains(ProgFlags::WRITE) && vaddr % PGSIZE == 0 {
+                let _ = mem.alloc(vaddr, allocator)?;
+                if mem.size() == vaddr {
+                    let ip = &mut *ip;
+                    let share_page = |index| {
+                        cache.share_page(dev, inum, gen, index).or_else(|| {
+                            let page = ip.share_page(index, self)?;
+                            cache.add_page(dev, inum, gen, index, &page);
+                            Some(page)
+                        })
+                    };
+                    shared = mem.map_file(share_page, ph.off, ph.filesz, allocator);
+                }
+            }
+            shared_any |= shared > 0;
+            let _ = mem.alloc(vaddr.checked_add(ph.memsz).ok_or(())?, allocator)?;
+            mem.load_file(
+                (vaddr + shared).into(),
+                &mut ip,
+                (ph.off + shared) as _,
+                (ph.filesz - shared) as _,
+                self,
+            )?;
+        }
+        if !hit && !shared_any {
+            // Only programs whose pages the file system shares are kept, since a file system that
+            // does not, such as a host file system, may change them behind our back.
+            cache.invalidate(dev, inum);
+        }
+        Ok(info)
+    }
+
     pub fn exec(&mut self, path: &Path, args: &[Page]) -> Result<usize, ()> {
         if args.len() > MAXARG {
             return Err(());
@@ -106,38 +456,30 @@ impl KernelCtx<'_, '_> {
 
         let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
         let tx = scopeguard::guard(tx, |t| t.end(self));
-        let ptr = self.kernel().fs().namei(path, &tx, self)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((&tx, self)));
-        let ip = ptr.lock(self);
-        let mut ip = scopeguard::guard(ip, |ip| ip.free(self));
-
-        // Check ELF header
-        let mut elf: ElfHdr = Default::default();
-        ip.read_kernel(&mut elf, 0, self)?;
-        if !elf.is_valid() {
-            return Err(());
-        }
 
         let trap_frame: PAddr = (self.proc().trap_frame() as *const _ as usize).into();
         let mem = UserMemory::new(trap_frame, None, allocator).ok_or(())?;
         let mut mem = scopeguard::guard(mem, |mem| mem.free(allocator));
 
         // Load program into memory.
-        for i in 0..elf.phnum as usize {
-            let off = elf.phoff + i * mem::size_of::<ProgHdr>();
+        let ptr = self.kernel().fs().namei(path, None, &tx, self)?;
+        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((&tx, self)));
+        let info = self.load_program(&mut mem, &ptr, 0, allocator)?;
+        drop(ptr);
 
-            let mut ph: ProgHdr = Default::default();
-            ip.read_kernel(&mut ph, off as _, self)?;
-            if ph.is_prog_load() {
-                if ph.memsz < ph.filesz || ph.vaddr % PGSIZE != 0 {
-                    return Err(());
-                }
-                let _ = mem.alloc(ph.vaddr.checked_add(ph.memsz).ok_or(())?, allocator)?;
-                mem.load_file(ph.vaddr.into(), &mut ip, ph.off as _, ph.filesz as _, self)?;
+        // Load the program interpreter, if any, at the next page boundary. The interpreter starts
+        // instead of the program, and starts the program at AT_ENTRY after linking it.
+        let mut interp = None;
+        if let Some(interp_path) = info.interp() {
+            let ptr = self.kernel().fs().namei(interp_path, None, &tx, self)?;
+            let ptr = scopeguard::guard(ptr, |ptr| ptr.free((&tx, self)));
+            let base = pgroundup(mem.size());
+            let interp_info = self.load_program(&mut mem, &ptr, base, allocator)?;
+            if interp_info.interp().is_some() {
+                return Err(());
             }
+            interp = Some((base, base + interp_info.entry));
         }
-        drop(ip);
-        drop(ptr);
         drop(tx);
 
         // Allocate two pages at the next page boundary.
@@ -145,12 +487,25 @@ impl KernelCtx<'_, '_> {
         let mut sz = pgroundup(mem.size());
         sz = mem.alloc(sz + 2 * PGSIZE, allocator)?;
         mem.clear((sz - 2 * PGSIZE).into());
+        mem.start_heap();
         let mut sp: usize = sz;
         let stackbase: usize = sp - PGSIZE;
 
+        // Push the random bytes for the stack protector, fresh for every exec.
+        let mut random = [0u8; AT_RANDOM_SIZE];
+        self.kernel().entropy().fill(&mut random);
+        sp = (sp - AT_RANDOM_SIZE) & !0xf;
+        mem.copy_out_bytes(sp.into(), &random)?;
+        let random_addr = sp;
+
+        // Linux programs find argc below argv[], and an empty environment and the auxiliary
+        // vector above it. rv6 programs find the auxiliary vector right above argv[].
+        let abi = self.proc().deref_data().exec_abi;
+        let argv_start = if abi == Abi::Linux { 1 } else { 0 };
+
         // Push argument strings, prepare rest of stack in ustack.
-        let mut ustack = [0usize; MAXARG + 1];
-        for (arg, stack) in izip!(args, &mut ustack) {
+        let mut ustack = [0usize; MAXARG + 1 + STACK_EXTRA];
+        for (arg, stack) in izip!(args, &mut ustack[argv_start..]) {
             let null_idx = arg
                 .iter()
                 .position(|c| *c == 0)
@@ -168,10 +523,32 @@ impl KernelCtx<'_, '_> {
             *stack = sp;
         }
         let argc: usize = args.len();
-        ustack[argc] = 0;
+        ustack[argv_start + argc] = 0;
+        let mut nwords = argv_start + argc + 1;
+        if abi == Abi::Linux {
+            ustack[0] = argc;
+            ustack[nwords] = 0;
+            nwords += 1;
+        }
+        let auxv_start = nwords;
+        let auxv = [
+            info.phdr.map(|phdr| (AT_PHDR, phdr)),
+            interp.map(|(base, _)| (AT_BASE, base)),
+            Some((AT_PHENT, mem::size_of::<ProgHdr>())),
+            Some((AT_PHNUM, info.phnum as usize)),
+            Some((AT_PAGESZ, PGSIZE)),
+            Some((AT_ENTRY, info.entry)),
+            Some((AT_RANDOM, random_addr)),
+            Some((AT_NULL, 0)),
+        ];
+        for (typ, val) in auxv.iter().flatten() {
+            ustack[nwords] = *typ;
+            ustack[nwords + 1] = *val;
+            nwords += 2;
+        }
 
         // push the array of argv[] pointers.
-        let argv_size = (argc + 1) * mem::size_of::<usize>();
+        let argv_size = nwords * mem::size_of::<usize>();
         sp -= argv_size;
         sp &= !0xf;
         if sp < stackbase {
@@ -194,6 +571,13 @@ impl KernelCtx<'_, '_> {
         if len < proc_name.len() {
             proc_name[len] = 0;
         }
+        let data = self.proc_mut().deref_mut_data();
+        data.title[0] = 0;
+        data.set_args(args.iter().map(|arg| {
+            let len = arg.iter().position(|c| *c == 0).unwrap_or(arg.len());
+            &arg[..len]
+        }));
+        self.proc_mut().deref_mut_data().abi = abi;
 
         // Commit to the user image.
         mem::replace(
@@ -202,18 +586,38 @@ impl KernelCtx<'_, '_> {
         )
         .free(allocator);
 
+        // Close the files marked close-on-exec, now that exec cannot fail.
+        for fd in 0..NOFILE {
+            let entry = &mut self.proc_mut().deref_mut_data().open_files[fd];
+            if matches!(entry, Some(FdEntry { cloexec: true, .. })) {
+                if let Some(entry) = entry.take() {
+                    entry.free(self);
+                }
+            }
+        }
+
         // arguments to user main(argc, argv)
         // argc is returned via the system call return
         // value, which goes in a0.
-        *self.proc_mut().trap_frame_mut().param_reg_mut(RegNum::R1) = sp;
+        *self.proc_mut().trap_frame_mut().param_reg_mut(RegNum::R1) =
+            sp + argv_start * mem::size_of::<usize>();
+
+        // rv6 programs take the auxiliary vector as the third argument, _start(argc, argv, auxv).
+        *self.proc_mut().trap_frame_mut().param_reg_mut(RegNum::R2) =
+            sp + auxv_start * mem::size_of::<usize>();
 
-        // initial program counter = main
-        self.proc_mut().trap_frame_mut().set_pc(elf.entry);
+        // initial program counter = main, or the entry of the interpreter
+        let entry = interp.map_or(info.entry, |(_, entry)| entry);
+        self.proc_mut().trap_frame_mut().set_pc(entry);
 
         // initial stack pointer
         self.proc_mut().trap_frame_mut().sp = sp;
 
         // this ends up in a0, the first argument to main(argc, argv)
+        // Linux programs take a0 as a function to register with atexit(), which must be null.
+        if abi == Abi::Linux {
+            return Ok(0);
+        }
         Ok(argc)
     }
 }
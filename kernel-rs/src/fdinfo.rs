@@ -0,0 +1,73 @@
+//! File descriptor statistics.
+//!
+//! `fdinfo()` lists the file descriptors of a process, and which inode or pipe each of them
+//! refers to. Together with the number of open files of each process reported by `meminfo()`,
+//! it tells which processes hold the open files when the file table runs out.
+
+use core::cmp;
+
+use zerocopy::AsBytes;
+
+use crate::{param::NOFILE, proc::KernelCtx, syscall::SyscallTable};
+
+/// Types of open files, as reported by `fdinfo()`.
+pub const FD_PIPE: u32 = 1;
+pub const FD_INODE: u32 = 2;
+pub const FD_DEVICE: u32 = 3;
+pub const FD_WATCH: u32 = 4;
+pub const FD_SOCKET: u32 = 6;
+pub const FD_EPOLL: u32 = 7;
+pub const FD_EVENTFD: u32 = 8;
+pub const FD_TIMERFD: u32 = 9;
+pub const FD_MQUEUE: u32 = 10;
+pub const FD_SEM: u32 = 11;
+
+/// Flags of a file descriptor, as reported by `fdinfo()`.
+pub const FDINFO_READ: u32 = 0x1;
+pub const FDINFO_WRITE: u32 = 0x2;
+pub const FDINFO_CLOEXEC: u32 = 0x4;
+
+/// A file descriptor of a process, read by `fdinfo()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct FdInfo {
+    pub fd: i32,
+
+    /// Type of the open file
+    pub typ: u32,
+
+    /// `FDINFO_READ`, `FDINFO_WRITE`, and `FDINFO_CLOEXEC`
+    pub flags: u32,
+
+    /// Device of an inode or a device file
+    pub dev: u32,
+
+    /// Inode number of an inode or a device file, a number shared by the two ends of a pipe, the
+    /// port of an Internet socket, or the inode number of the name of a UNIX domain socket
+    pub ino: u32,
+}
+
+impl KernelCtx<'_, '_> {
+    /// Place the file descriptors of the process pid into at most n struct fdinfo at addr.
+    /// Returns Ok(number of file descriptors stored) on success, Err(()) on error.
+    pub fn sys_fdinfo(&mut self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?;
+        let n = self.proc().argint(2)?;
+        if n < 0 {
+            return Err(());
+        }
+        let mut fds = [FdInfo::default(); NOFILE];
+        let stored = self.kernel().procs().fd_info(pid, &mut fds, self)?;
+        let stored = cmp::min(stored, n as usize);
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr.into(), fds[..stored].as_bytes())?;
+        Ok(stored)
+    }
+}
+
+/// Registers the system calls of file descriptor statistics.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(73, |ctx| ctx.sys_fdinfo());
+}
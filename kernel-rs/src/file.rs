@@ -1,22 +1,36 @@
 //! Support functions for system calls that involve file descriptors.
 
 use core::{
-    cell::UnsafeCell,
     cmp,
     mem::{self, ManuallyDrop},
     ops::Deref,
     ops::DerefMut,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use crate::{
     addr::UVAddr,
     arena::{Arena, ArenaObject, ArenaRc, ArrayArena},
-    fs::{DefaultFs, FileSystem, FileSystemExt, InodeGuard, RcInode},
+    console::Severity,
+    epoll::{AllocatedEpoll, Waiter},
+    eventfd::AllocatedEventFd,
+    fdinfo::{
+        FdInfo, FDINFO_CLOEXEC, FDINFO_READ, FDINFO_WRITE, FD_DEVICE, FD_EPOLL, FD_EVENTFD,
+        FD_INODE, FD_MQUEUE, FD_PIPE, FD_SEM, FD_SOCKET, FD_TIMERFD, FD_WATCH,
+    },
+    fs::{DefaultFs, FileSystem, FileSystemExt, InodeGuard, InodeType, Path, RcInode},
     hal::hal,
+    kernel::KernelRef,
+    lock::{SleepLock, SleepLockGuard},
+    mqueue::AllocatedMsgQueue,
+    net::AllocatedSocket,
+    ok_or,
     param::{BSIZE, MAXOPBLOCKS, NFILE},
-    pipe::AllocatedPipe,
+    pipe::{AllocatedPipe, PIPESIZE},
     proc::KernelCtx,
+    semaphore::AllocatedSemaphore,
     util::strong_pin::StrongPin,
+    watch::{AllocatedWatch, WatchMask},
 };
 
 pub enum FileType {
@@ -24,27 +38,35 @@ pub enum FileType {
     Pipe { pipe: AllocatedPipe },
     Inode { inner: InodeFileType },
     Device { ip: RcInode<DefaultFs>, major: u16 },
+    Watch { watch: AllocatedWatch },
+    Socket { socket: AllocatedSocket },
+    Epoll { epoll: AllocatedEpoll },
+    EventFd { eventfd: AllocatedEventFd },
+    TimerFd { timerfd: AllocatedEventFd },
+    MsgQueue { mq: AllocatedMsgQueue },
+    Semaphore { sem: AllocatedSemaphore },
 }
 
 /// It has an inode and an offset.
-///
-/// # Safety
-///
-/// The offset should be accessed only when the inode is locked.
 pub struct InodeFileType {
     pub ip: RcInode<DefaultFs>,
-    // It should be accessed only when `ip` is locked.
-    pub off: UnsafeCell<u32>,
+    /// The offset is shared by every file descriptor of the open file description, even across
+    /// fork(). It stays locked during a whole read, write, or seek, so that the operations on
+    /// the open file description do not interleave, even if one of them takes several
+    /// transactions. It is locked before the inode.
+    pub off: SleepLock<u32>,
+    /// Was it opened with O_TMPFILE, and not linked yet? Only then can its inode be linked while
+    /// it has no links.
+    tmpfile: AtomicBool,
 }
 
-/// It can be acquired when the inode of `InodeFileType` is locked. `ip` is the guard of the locked
-/// inode. `off` is a mutable reference to the offset. Accessing `off` is guaranteed to be safe
-/// since the inode is locked.
+/// The locked offset and inode of an `InodeFileType`.
 struct InodeFileTypeGuard<'a, FS: FileSystem> {
     ip: ManuallyDrop<InodeGuard<'a, FS>>,
-    off: &'a mut u32,
+    off: ManuallyDrop<SleepLockGuard<'a, u32>>,
 }
 
+/// An open file description, which dup() and fork() share.
 pub struct File {
     pub typ: FileType,
     readable: bool,
@@ -53,6 +75,17 @@ pub struct File {
 
 pub type FileTable = ArrayArena<File, NFILE>;
 
+/// Has `alloc_file` warned that the file table is almost full?
+static FTABLE_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Maximum number of bytes written to an inode in a single transaction.
+///
+/// Write a few blocks at a time to avoid exceeding
+/// the maximum log transaction size, including
+/// i-node, indirect block, allocation blocks,
+/// and 2 blocks of slop for non-aligned writes.
+const MAXWRITE: usize = (MAXOPBLOCKS - 1 - 1 - 2) / 2 * BSIZE;
+
 /// map major device number to device functions.
 #[derive(Copy, Clone)]
 pub struct Devsw {
@@ -63,6 +96,16 @@ pub struct Devsw {
 /// A reference counted smart pointer to a `File`.
 pub type RcFile = ArenaRc<FileTable>;
 
+/// An entry of the file descriptor table of a process. It refers to an open file description,
+/// and has the flags of the file descriptor itself, which dup() does not share.
+#[derive(Clone)]
+pub struct FdEntry {
+    pub file: RcFile,
+
+    /// Is it closed by exec()?
+    pub cloexec: bool,
+}
+
 // Events for `select`
 #[derive(Copy, Clone)]
 pub enum SelectEvent {
@@ -84,13 +127,20 @@ impl Default for FileType {
 }
 
 impl InodeFileType {
+    pub fn new(ip: RcInode<DefaultFs>, tmpfile: bool) -> Self {
+        Self {
+            ip,
+            off: SleepLock::new("offset", 0),
+            tmpfile: AtomicBool::new(tmpfile),
+        }
+    }
+
     fn lock(&self, ctx: &KernelCtx<'_, '_>) -> InodeFileTypeGuard<'_, DefaultFs> {
+        let off = self.off.lock(ctx);
         let ip = self.ip.lock(ctx);
-        // SAFETY: `ip` is locked and `off` can be exclusively accessed.
-        let off = unsafe { &mut *self.off.get() };
         InodeFileTypeGuard {
             ip: ManuallyDrop::new(ip),
-            off,
+            off: ManuallyDrop::new(off),
         }
     }
 }
@@ -99,6 +149,8 @@ impl<FS: FileSystem> InodeFileTypeGuard<'_, FS> {
     fn free(mut self, ctx: &KernelCtx<'_, '_>) {
         let ip = unsafe { ManuallyDrop::take(&mut self.ip) };
         ip.free(ctx);
+        let off = unsafe { ManuallyDrop::take(&mut self.off) };
+        off.free(ctx);
         core::mem::forget(self);
     }
 }
@@ -173,6 +225,12 @@ impl File {
                 let read = major.read.ok_or(())?;
                 Ok(read(addr, n, ctx) as usize)
             }
+            FileType::Watch { watch } => watch.read(addr, n as usize, ctx),
+            FileType::Epoll { .. } => Err(()),
+            FileType::Socket { socket } => socket.recv(addr, n as usize, ctx).map(|(n, _)| n),
+            FileType::EventFd { eventfd } => eventfd.read(addr, n as usize, ctx),
+            FileType::TimerFd { timerfd } => timerfd.read(addr, n as usize, ctx),
+            FileType::MsgQueue { .. } | FileType::Semaphore { .. } => Err(()),
             FileType::None => panic!("File::read"),
         }
     }
@@ -189,20 +247,17 @@ impl File {
             FileType::Inode { inner } => {
                 let n = n as usize;
 
-                // write a few blocks at a time to avoid exceeding
-                // the maximum log transaction size, including
-                // i-node, indirect block, allocation blocks,
-                // and 2 blocks of slop for non-aligned writes.
                 // this really belongs lower down, since write()
                 // might be writing a device like the console.
-                let max = (MAXOPBLOCKS - 1 - 1 - 2) / 2 * BSIZE;
-
+                // The offset stays locked across the transactions, so that the whole write
+                // is contiguous.
+                let mut off = inner.off.lock(ctx);
                 let mut bytes_written: usize = 0;
                 while bytes_written < n {
-                    let bytes_to_write = cmp::min(n - bytes_written, max);
+                    let bytes_to_write = cmp::min(n - bytes_written, MAXWRITE);
                     let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-                    let mut ip = inner.lock(ctx);
-                    let curr_off = *ip.off;
+                    let mut ip = inner.ip.lock(ctx);
+                    let curr_off = *off;
                     let r = ip.write_user(
                         addr + bytes_written,
                         curr_off,
@@ -211,17 +266,30 @@ impl File {
                         &tx,
                     );
                     if let Ok(r) = r {
-                        *ip.off += r as u32;
+                        *off += r as u32;
                     }
                     tx.end(ctx);
                     ip.free(ctx);
-                    let r = r?;
+                    let r = ok_or!(r, {
+                        off.free(ctx);
+                        return Err(());
+                    });
                     if r != bytes_to_write {
                         // error from write_user
                         break;
                     }
                     bytes_written += r;
                 }
+                off.free(ctx);
+                if bytes_written > 0 {
+                    ctx.kernel().watches().post(
+                        inner.ip.dev,
+                        inner.ip.inum,
+                        WatchMask::MODIFY,
+                        inner.ip.inum,
+                        ctx,
+                    );
+                }
                 if bytes_written != n {
                     return Err(());
                 }
@@ -232,6 +300,12 @@ impl File {
                 let write = major.write.ok_or(())?;
                 Ok(write(addr, n, ctx) as usize)
             }
+            FileType::Watch { .. } | FileType::Epoll { .. } => Err(()),
+            FileType::Socket { socket } => socket.send(addr, n as usize, None, ctx),
+            FileType::EventFd { eventfd } => eventfd.write(addr, n as usize, ctx),
+            FileType::TimerFd { .. } | FileType::MsgQueue { .. } | FileType::Semaphore { .. } => {
+                Err(())
+            }
             FileType::None => panic!("File::read"),
         }
     }
@@ -267,9 +341,303 @@ impl File {
         }
     }
 
+    /// Read directory entries from file self by at most n bytes.
+    /// addr is a user virtual address.
+    /// Returns Ok(number of bytes read) on success, Err(()) on error.
+    pub fn getdents(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+        if !self.readable {
+            return Err(());
+        }
+
+        if let FileType::Inode { inner } = &self.typ {
+            let mut ip = inner.lock(ctx);
+            let mut off = *ip.off;
+            let ret = DefaultFs::inode_getdents(&mut ip, &mut off, addr, n as u32, ctx);
+            *ip.off = off;
+            ip.free(ctx);
+            ret
+        } else {
+            Err(())
+        }
+    }
+
+    /// Copy at most n bytes from file src, starting from its offset, into file self without
+    /// copying them into the user memory.
+    /// The bytes go through a kernel page, a chunk at a time, and src is unlocked while each
+    /// chunk is written, so that a reader of a pipe self may access src before draining it.
+    /// Returns Ok(number of bytes copied) on success, Err(()) on error.
+    pub fn sendfile(&self, src: &File, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+        if !self.writable || !src.readable {
+            return Err(());
+        }
+        let src = if let FileType::Inode { inner } = &src.typ {
+            inner
+        } else {
+            return Err(());
+        };
+        let chunk = match &self.typ {
+            FileType::Pipe { .. } => PIPESIZE,
+            FileType::Inode { inner } => {
+                if inner.ip.dev == src.ip.dev && inner.ip.inum == src.ip.inum {
+                    return Err(());
+                }
+                MAXWRITE
+            }
+            _ => return Err(()),
+        };
+
+        let allocator = hal().kmem();
+        let page = allocator.alloc().ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+
+        let mut sent: usize = 0;
+        while sent < n {
+            let m = cmp::min(n - sent, chunk);
+            let mut ip = src.lock(ctx);
+            let curr_off = *ip.off;
+            let r = ip.read_bytes_kernel(&mut page[..m], curr_off, ctx);
+            *ip.off += r as u32;
+            ip.free(ctx);
+            if r == 0 {
+                break;
+            }
+
+            let w = match &self.typ {
+                FileType::Pipe { pipe } => {
+                    match pipe.write_kernel(&page[..r], ctx) {
+                        Ok(w) => w,
+                        // The bytes sent so far are reported, as a write to a pipe would.
+                        Err(()) if sent > 0 => break,
+                        Err(()) => return Err(()),
+                    }
+                }
+                FileType::Inode { inner } => {
+                    let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+                    let mut ip = inner.lock(ctx);
+                    let curr_off = *ip.off;
+                    let w = ip.write_bytes_kernel(&page[..r], curr_off, &tx, ctx);
+                    if let Ok(w) = w {
+                        *ip.off += w as u32;
+                    }
+                    tx.end(ctx);
+                    ip.free(ctx);
+                    w?
+                }
+                _ => unreachable!(),
+            };
+            sent += w;
+            if w != r {
+                break;
+            }
+        }
+        if let FileType::Inode { inner } = &self.typ {
+            if sent > 0 {
+                ctx.kernel().watches().post(
+                    inner.ip.dev,
+                    inner.ip.inum,
+                    WatchMask::MODIFY,
+                    inner.ip.inum,
+                    ctx,
+                );
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Link the inode of file self into path.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn link(&self, path: &Path, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        if let FileType::Inode { inner } = &self.typ {
+            let tmpfile = inner.tmpfile.load(Ordering::Acquire);
+            let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+            let res = ctx
+                .kernel()
+                .fs()
+                .link(inner.ip.clone(), path, None, tmpfile, &tx, ctx);
+            tx.end(ctx);
+            if res.is_ok() {
+                inner.tmpfile.store(false, Ordering::Release);
+            }
+            res
+        } else {
+            Err(())
+        }
+    }
+
+    /// Shrink or extend file self to size bytes.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn truncate(&self, size: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        if !self.writable {
+            return Err(());
+        }
+
+        if let FileType::Inode { inner } = &self.typ {
+            let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+            let mut ip = inner.ip.lock(ctx);
+            let res = if ip.deref_inner().typ == InodeType::File {
+                ip.truncate(size, &tx, ctx)
+            } else {
+                Err(())
+            };
+            ip.free(ctx);
+            tx.end(ctx);
+            res
+        } else {
+            Err(())
+        }
+    }
+
+    /// Relocate the blocks of file self into a contiguous run on the disk.
+    /// Returns Ok(number of relocated blocks) on success, Err(()) on error.
+    pub fn defrag(&self, ctx: &KernelCtx<'_, '_>) -> Result<usize, ()> {
+        if !self.writable {
+            return Err(());
+        }
+
+        if let FileType::Inode { inner } = &self.typ {
+            ctx.kernel().fs().defrag(&inner.ip, ctx)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Start watching the file at path with the watch self for the events in mask.
+    /// Returns Ok(watch descriptor) on success, Err(()) on error.
+    pub fn watch_add(&self, path: &Path, mask: u32, ctx: &KernelCtx<'_, '_>) -> Result<usize, ()> {
+        if let FileType::Watch { watch } = &self.typ {
+            let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+            let res = ctx.kernel().fs().namei(path, None, &tx, ctx).map(|ip| {
+                let target = (ip.dev, ip.inum);
+                ip.free((&tx, ctx));
+                target
+            });
+            tx.end(ctx);
+            let (dev, inum) = res?;
+            watch.add(dev, inum, mask)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Stop watching the file of watch descriptor wd with the watch self.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn watch_remove(&self, wd: usize) -> Result<(), ()> {
+        if let FileType::Watch { watch } = &self.typ {
+            watch.remove(wd)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns the socket of file self.
+    pub fn socket(&self) -> Result<&AllocatedSocket, ()> {
+        if let FileType::Socket { socket } = &self.typ {
+            Ok(socket)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns the epoll of file self.
+    pub fn epoll(&self) -> Result<&AllocatedEpoll, ()> {
+        if let FileType::Epoll { epoll } = &self.typ {
+            Ok(epoll)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns the timerfd of file self.
+    pub fn timerfd(&self) -> Result<&AllocatedEventFd, ()> {
+        if let FileType::TimerFd { timerfd } = &self.typ {
+            Ok(timerfd)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns the message queue of file self.
+    pub fn msgqueue(&self) -> Result<&AllocatedMsgQueue, ()> {
+        if let FileType::MsgQueue { mq } = &self.typ {
+            Ok(mq)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns the semaphore of file self.
+    pub fn semaphore(&self) -> Result<&AllocatedSemaphore, ()> {
+        if let FileType::Semaphore { sem } = &self.typ {
+            Ok(sem)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Sends the n bytes at addr as a message of priority prio to the message queue self.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn mq_send(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        prio: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        if !self.writable {
+            return Err(());
+        }
+        self.msgqueue()?.send(addr, n, prio, ctx)
+    }
+
+    /// Receives a message of the message queue self into addr, which has room for n bytes.
+    /// Returns Ok((length, priority) of the message) on success, Err(()) on error.
+    pub fn mq_receive(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(usize, u32), ()> {
+        if !self.readable {
+            return Err(());
+        }
+        self.msgqueue()?.receive(addr, n, ctx)
+    }
+
+    /// Returns a number that identifies file self while it is open.
+    pub fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Returns true if an epoll can watch file self, i.e., if it is a pipe, socket, watch,
+    /// eventfd, timerfd, or message queue.
+    pub fn is_watchable(&self) -> bool {
+        matches!(
+            self.typ,
+            FileType::Pipe { .. }
+                | FileType::Watch { .. }
+                | FileType::Socket { .. }
+                | FileType::EventFd { .. }
+                | FileType::TimerFd { .. }
+                | FileType::MsgQueue { .. }
+        )
+    }
+
+    /// Registers the epoll of `waiter` on the pipe, socket, watch, eventfd, timerfd, or message
+    /// queue of file self, so that the epoll is woken up when the file may become ready.
+    /// Returns Ok(()) on success, Err(()) if file self cannot be watched.
+    pub fn add_waiter(&self, waiter: Waiter, kernel: KernelRef<'_, '_>) -> Result<(), ()> {
+        match &self.typ {
+            FileType::Pipe { pipe } => pipe.add_waiter(waiter, kernel),
+            FileType::Watch { watch } => watch.add_waiter(waiter, kernel),
+            FileType::Socket { socket } => socket.add_waiter(waiter, kernel),
+            FileType::EventFd { eventfd } => eventfd.add_waiter(waiter, kernel),
+            FileType::TimerFd { timerfd } => timerfd.add_waiter(waiter, kernel),
+            FileType::MsgQueue { mq } => mq.add_waiter(waiter, kernel),
+            _ => Err(()),
+        }
+    }
+
     /// Check file is ready for specified select event.
-    /// It only supports pipe now.
-    /// TODO: support other type of files
     pub fn is_ready(&self, event: SelectEvent) -> Result<bool, ()> {
         match event {
             SelectEvent::Read => {
@@ -284,17 +652,51 @@ impl File {
                             return Ok(true);
                         }
                     }
-                    FileType::Inode { .. } => {
-                        unimplemented!()
+                    // A read from a regular file does not wait.
+                    FileType::Inode { .. } => return Ok(true),
+                    // Devices cannot tell whether a read would wait.
+                    FileType::Device { .. } => return Err(()),
+                    FileType::Watch { watch } => {
+                        if watch.is_ready(event) {
+                            return Ok(true);
+                        }
+                    }
+                    FileType::Epoll { .. } | FileType::Semaphore { .. } => (),
+                    FileType::Socket { socket } => {
+                        if socket.is_ready(event) {
+                            return Ok(true);
+                        }
+                    }
+                    FileType::EventFd { eventfd: e } | FileType::TimerFd { timerfd: e } => {
+                        if e.is_ready(event) {
+                            return Ok(true);
+                        }
+                    }
+                    FileType::MsgQueue { mq } => {
+                        if mq.is_ready(event) {
+                            return Ok(true);
+                        }
                     }
-                    FileType::Device { .. } => unimplemented!(""),
                     FileType::None => panic!("Syscall::sys_select"),
                 }
                 Ok(false)
             }
-            _ => {
-                todo!("Select for write and error is not implemented yet")
+            SelectEvent::Write => {
+                if !self.writable {
+                    return Err(());
+                }
+
+                match &self.typ {
+                    FileType::Pipe { pipe } => Ok(pipe.is_ready(event)),
+                    FileType::Socket { socket } => Ok(socket.is_ready(event)),
+                    FileType::EventFd { eventfd } => Ok(eventfd.is_ready(event)),
+                    FileType::MsgQueue { mq } => Ok(mq.is_ready(event)),
+                    // A write to a regular file or a device does not wait.
+                    FileType::Inode { .. } | FileType::Device { .. } => Ok(true),
+                    _ => Err(()),
+                }
             }
+            SelectEvent::Error => Err(()),
         }
     }
 }
@@ -310,6 +712,18 @@ impl ArenaObject for File {
 
     fn finalize<'a, 'id: 'a>(&mut self, ctx: Self::Ctx<'a, 'id>) {
         let typ = mem::replace(&mut self.typ, FileType::None);
+        if matches!(
+            typ,
+            FileType::Pipe { .. }
+                | FileType::Watch { .. }
+                | FileType::Socket { .. }
+                | FileType::EventFd { .. }
+                | FileType::TimerFd { .. }
+                | FileType::MsgQueue { .. }
+        ) {
+            // Another file may take the place of this file, so epolls should forget it.
+            ctx.kernel().epolls().forget(self.id());
+        }
         match typ {
             FileType::Pipe { pipe } => {
                 if let Some(page) = pipe.close(self.writable, ctx) {
@@ -320,10 +734,15 @@ impl ArenaObject for File {
                 inner: InodeFileType { ip, .. },
             }
             | FileType::Device { ip, .. } => {
-                let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-                ip.free((&tx, ctx));
-                tx.end(ctx);
+                // The caller may be in a transaction, so do not begin another one here.
+                ctx.kernel().fs().defer_free(ip, ctx);
             }
+            FileType::Watch { watch } => watch.close(),
+            FileType::Socket { socket } => socket.close(ctx),
+            FileType::Epoll { epoll } => epoll.close(),
+            FileType::EventFd { eventfd: e } | FileType::TimerFd { timerfd: e } => e.close(),
+            FileType::MsgQueue { mq } => mq.close(),
+            FileType::Semaphore { sem } => sem.close(),
             _ => (),
         }
     }
@@ -335,24 +754,43 @@ impl FileTable {
     }
 
     /// Allocate a file structure.
+    /// Warns once when the table is almost full, until most of it is freed again.
     pub fn alloc_file(
         self: StrongPin<'_, Self>,
         typ: FileType,
         readable: bool,
         writable: bool,
+        ctx: &KernelCtx<'_, '_>,
     ) -> Result<RcFile, ()> {
-        self.alloc(|| File::new(typ, readable, writable)).ok_or(())
+        let f = self.alloc(|| File::new(typ, readable, writable));
+        let (used, capacity) = (self.count_used(), self.capacity());
+        if used < capacity / 2 {
+            FTABLE_WARNED.store(false, Ordering::Relaxed);
+        } else if used >= capacity - capacity / 8 && !FTABLE_WARNED.swap(true, Ordering::Relaxed) {
+            ctx.kernel().as_ref().log(
+                Severity::Warning,
+                format_args!(
+                    "ftable: {} of {} files in use; fdinfo lists their holders\n",
+                    used, capacity
+                ),
+            );
+        }
+        f.ok_or(())
     }
 }
 
 impl RcFile {
     /// Allocate a file descriptor for the given file.
+    /// The file descriptor is not closed on exec().
     /// Takes over file reference from caller on success.
     pub fn fdalloc(self, ctx: &mut KernelCtx<'_, '_>) -> Result<i32, ()> {
         let proc_data = ctx.proc_mut().deref_mut_data();
         for (fd, f) in proc_data.open_files.iter_mut().enumerate() {
             if f.is_none() {
-                *f = Some(self);
+                *f = Some(FdEntry {
+                    file: self,
+                    cloexec: false,
+                });
                 return Ok(fd as i32);
             }
         }
@@ -360,3 +798,44 @@ impl RcFile {
         Err(())
     }
 }
+
+impl FdEntry {
+    /// Closes the file descriptor, dropping its reference to the open file description.
+    pub fn free(self, ctx: &KernelCtx<'_, '_>) {
+        self.file.free(ctx);
+    }
+
+    /// Returns what `fdinfo()` reports about the file descriptor `fd`.
+    pub fn info(&self, fd: i32) -> FdInfo {
+        let mut flags = 0;
+        if self.file.readable {
+            flags |= FDINFO_READ;
+        }
+        if self.file.writable {
+            flags |= FDINFO_WRITE;
+        }
+        if self.cloexec {
+            flags |= FDINFO_CLOEXEC;
+        }
+        let (typ, dev, ino) = match &self.file.typ {
+            FileType::None => (0, 0, 0),
+            FileType::Pipe { pipe } => (FD_PIPE, 0, pipe.id()),
+            FileType::Inode { inner } => (FD_INODE, inner.ip.dev, inner.ip.inum),
+            FileType::Device { ip, .. } => (FD_DEVICE, ip.dev, ip.inum),
+            FileType::Watch { .. } => (FD_WATCH, 0, 0),
+            FileType::Socket { socket } => (FD_SOCKET, 0, socket.id()),
+            FileType::Epoll { .. } => (FD_EPOLL, 0, 0),
+            FileType::EventFd { .. } => (FD_EVENTFD, 0, 0),
+            FileType::TimerFd { .. } => (FD_TIMERFD, 0, 0),
+            FileType::MsgQueue { .. } => (FD_MQUEUE, 0, 0),
+            FileType::Semaphore { .. } => (FD_SEM, 0, 0),
+        };
+        FdInfo {
+            fd,
+            typ,
+            flags,
+            dev,
+            ino,
+        }
+    }
+}
@@ -7,7 +7,7 @@ use core::ops::Deref;
 use spin::Once;
 
 use super::{FcntlFlags, FileSystem, Inode, InodeGuard, InodeType, Path, RcInode, Stat, Tx};
-use crate::{proc::KernelCtx, util::strong_pin::StrongPin};
+use crate::{addr::UVAddr, page::Page, proc::KernelCtx, util::strong_pin::StrongPin};
 
 mod inode;
 mod superblock;
@@ -52,6 +52,7 @@ impl FileSystem for Lfs {
     fn namei(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<RcInode<Self>, ()> {
@@ -62,6 +63,8 @@ impl FileSystem for Lfs {
         self: StrongPin<'_, Self>,
         inode: RcInode<Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
+        unnamed: bool,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(), ()> {
@@ -71,6 +74,7 @@ impl FileSystem for Lfs {
     fn unlink(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(), ()> {
@@ -80,6 +84,7 @@ impl FileSystem for Lfs {
     fn create<F, T>(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         typ: InodeType,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
@@ -94,6 +99,7 @@ impl FileSystem for Lfs {
     fn open(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         omode: FcntlFlags,
         tx: &Tx<'_, Self>,
         ctx: &mut KernelCtx<'_, '_>,
@@ -110,6 +116,15 @@ impl FileSystem for Lfs {
         todo!()
     }
 
+    fn getcwd(
+        self: StrongPin<'_, Self>,
+        buf: &mut [u8],
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        todo!()
+    }
+
     fn tx_begin(&self, ctx: &KernelCtx<'_, '_>) {
         todo!()
     }
@@ -150,7 +165,20 @@ impl FileSystem for Lfs {
         todo!()
     }
 
-    fn inode_trunc(guard: &mut InodeGuard<'_, Self>, tx: &Tx<'_, Self>, ctx: &KernelCtx<'_, '_>) {
+    fn inode_trunc(
+        guard: &mut InodeGuard<'_, Self>,
+        size: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        todo!()
+    }
+
+    fn inode_share_page(
+        guard: &mut InodeGuard<'_, Self>,
+        index: usize,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Option<Page> {
         todo!()
     }
 
@@ -169,4 +197,57 @@ impl FileSystem for Lfs {
     fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat {
         todo!()
     }
+
+    fn inode_getdents(
+        guard: &mut InodeGuard<'_, Self>,
+        off: &mut u32,
+        dst: UVAddr,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        todo!()
+    }
+
+    fn defrag(
+        self: StrongPin<'_, Self>,
+        inode: &Inode<Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        todo!()
+    }
+
+    fn snapshot(self: StrongPin<'_, Self>, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        todo!()
+    }
+
+    fn snapshot_drop(self: StrongPin<'_, Self>, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        todo!()
+    }
+
+    fn snapshot_read(
+        self: StrongPin<'_, Self>,
+        inum: u32,
+        off: u32,
+        dst: UVAddr,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        todo!()
+    }
+
+    fn snapshot_stat(
+        self: StrongPin<'_, Self>,
+        inum: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Stat, ()> {
+        todo!()
+    }
+
+    fn quiesce<F: FnOnce()>(self: StrongPin<'_, Self>, f: F, ctx: &KernelCtx<'_, '_>) {
+        todo!()
+    }
+
+    fn unmount<F: FnOnce()>(self: StrongPin<'_, Self>, f: F, ctx: &KernelCtx<'_, '_>) {
+        todo!()
+    }
 }
@@ -2,29 +2,44 @@ use core::mem;
 use core::ops::Deref;
 
 use bitflags::bitflags;
+use cfg_if::cfg_if;
 use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
     addr::UVAddr,
     arena::{ArenaObject, ArenaRc, ArrayArena},
     lock::SleepLock,
+    page::Page,
     param::NINODE,
     proc::KernelCtx,
     util::strong_pin::StrongPin,
 };
 
 mod lfs;
+#[cfg(feature = "ninep")]
+mod ninep;
+mod page_cache;
 mod path;
 mod stat;
 mod ufs;
 
 pub use lfs::Lfs;
+#[cfg(feature = "ninep")]
+pub use ninep::Ninep;
+pub use page_cache::PageCache;
 pub use path::{FileName, Path};
 pub use stat::Stat;
 pub use ufs::Ufs;
 
-/// The default file system.
-pub type DefaultFs = Ufs;
+cfg_if! {
+    if #[cfg(feature = "ninep")] {
+        /// The default file system: a directory of the host, shared over virtio 9p.
+        pub type DefaultFs = Ninep;
+    } else {
+        /// The default file system.
+        pub type DefaultFs = Ufs;
+    }
+}
 
 bitflags! {
     pub struct FcntlFlags: i32 {
@@ -33,16 +48,36 @@ bitflags! {
         const O_RDWR = 0x2;
         const O_CREATE = 0x200;
         const O_TRUNC = 0x400;
+        const O_TMPFILE = 0x800;
+        const O_CLOEXEC = 0x1000;
     }
 }
 
+bitflags! {
+    /// What `access()` checks. An empty mode only checks that the file exists.
+    pub struct AccessFlags: i32 {
+        const X_OK = 0x1;
+        const W_OK = 0x2;
+        const R_OK = 0x4;
+    }
+}
+
+/// Directory file descriptor that makes the `*at()` system calls resolve relative paths from the
+/// current directory.
+pub const AT_FDCWD: i32 = -100;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 #[repr(i16)]
 pub enum InodeType {
     None,
     Dir,
     File,
-    Device { major: u16, minor: u16 },
+    Device {
+        major: u16,
+        minor: u16,
+    },
+    /// The name of a UNIX domain socket, which has no data.
+    Socket,
 }
 
 /// InodeGuard implies that `SleepLock<InodeInner>` is held by current thread.
@@ -147,6 +182,12 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
         )
     }
 
+    /// Returns another `Page` of the cached `index`th page of the file contents, to map read-only
+    /// into a process. Returns None if the page cannot be shared.
+    pub fn share_page(&mut self, index: usize, ctx: &KernelCtx<'_, '_>) -> Option<Page> {
+        FS::inode_share_page(self, index, ctx)
+    }
+
     /// Copy data from `src` into the inode at offset `off`.
     /// Return Ok(()) on success, Err(()) on failure.
     pub fn write_kernel<T: AsBytes>(
@@ -214,7 +255,19 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
     /// Truncate inode (discard contents).
     /// This function is called with Inode's lock is held.
     pub fn trunc(&mut self, tx: &Tx<'_, FS>, ctx: &KernelCtx<'_, '_>) {
-        FS::inode_trunc(self, tx, ctx);
+        FS::inode_trunc(self, 0, tx, ctx).expect("trunc");
+    }
+
+    /// Shrink or extend inode to `size` bytes.
+    /// This function is called with Inode's lock is held.
+    /// Returns Ok(()) on success, Err(()) if `size` is larger than a file can be.
+    pub fn truncate(
+        &mut self,
+        size: u32,
+        tx: &Tx<'_, FS>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        FS::inode_trunc(self, size, tx, ctx)
     }
 }
 
@@ -298,19 +351,24 @@ pub trait FileSystem: 'static + Sized {
     fn root(self: StrongPin<'_, Self>) -> RcInode<Self>;
 
     /// Finds inode from the given path.
+    /// A relative path is resolved from `dir`, or from the current directory if `dir` is `None`.
     fn namei(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<RcInode<Self>, ()>;
 
     /// Create another name(newname) for the file oldname.
+    /// An inode with no links left is linked only if unnamed, i.e., it was created without a name.
     /// Returns Ok(()) on success, Err(()) on error.
     fn link(
         self: StrongPin<'_, Self>,
         inode: RcInode<Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
+        unnamed: bool,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(), ()>;
@@ -320,6 +378,7 @@ pub trait FileSystem: 'static + Sized {
     fn unlink(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(), ()>;
@@ -329,6 +388,7 @@ pub trait FileSystem: 'static + Sized {
     fn create<F, T>(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         typ: InodeType,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
@@ -342,6 +402,7 @@ pub trait FileSystem: 'static + Sized {
     fn open(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         omode: FcntlFlags,
         tx: &Tx<'_, Self>,
         ctx: &mut KernelCtx<'_, '_>,
@@ -356,6 +417,16 @@ pub trait FileSystem: 'static + Sized {
         ctx: &mut KernelCtx<'_, '_>,
     ) -> Result<(), ()>;
 
+    /// Copy the absolute path of the current directory into `buf`, without a NUL terminator.
+    /// Returns Ok(length of the path) on success, Err(()) if the path does not fit in `buf`
+    /// or the current directory has been removed.
+    fn getcwd(
+        self: StrongPin<'_, Self>,
+        buf: &mut [u8],
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()>;
+
     /// Begins a transaction.
     ///
     /// Called for each FS system call.
@@ -435,9 +506,24 @@ pub trait FileSystem: 'static + Sized {
         k: K,
     ) -> Result<usize, ()>;
 
-    /// Truncate inode (discard contents).
+    /// Shrink or extend inode to `size` bytes. The contents past the old size read as zeroes.
     /// This function is called with Inode's lock is held.
-    fn inode_trunc(guard: &mut InodeGuard<'_, Self>, tx: &Tx<'_, Self>, ctx: &KernelCtx<'_, '_>);
+    /// Returns Ok(()) on success, Err(()) if `size` is larger than a file can be.
+    fn inode_trunc(
+        guard: &mut InodeGuard<'_, Self>,
+        size: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()>;
+
+    /// Returns another `Page` of the cached `index`th page of the file contents, to map read-only
+    /// into a process. This function is called with Inode's lock is held.
+    /// Returns None if the page is not entirely within the file, or the file is not cached.
+    fn inode_share_page(
+        guard: &mut InodeGuard<'_, Self>,
+        index: usize,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Option<Page>;
 
     /// Lock the given inode.
     /// Reads the inode from disk if necessary.
@@ -458,6 +544,64 @@ pub trait FileSystem: 'static + Sized {
 
     /// Copy stat information from inode.
     fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat;
+
+    /// Copy directory entries of the directory inode, starting from the entry at offset
+    /// `*off`, into virtual address `dst` of the current process by at most `n` bytes.
+    /// `*off` is advanced past the copied entries.
+    /// Returns Ok(number of bytes copied) on success, Err(()) on error.
+    fn inode_getdents(
+        guard: &mut InodeGuard<'_, Self>,
+        off: &mut u32,
+        dst: UVAddr,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()>;
+
+    /// Relocate the data blocks of the inode into a contiguous run of free blocks.
+    /// Begins its own transactions, so it must not be called inside a transaction.
+    /// Returns Ok(number of relocated blocks) on success, Err(()) on error.
+    fn defrag(
+        self: StrongPin<'_, Self>,
+        inode: &Inode<Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()>;
+
+    /// Take a point-in-time snapshot of the file system, replacing the previous one.
+    /// Afterwards, blocks referenced by the snapshot are copied before being modified.
+    /// Returns Ok(()) on success, Err(()) on error.
+    fn snapshot(self: StrongPin<'_, Self>, ctx: &KernelCtx<'_, '_>) -> Result<(), ()>;
+
+    /// Drop the snapshot, freeing the blocks referenced only by it.
+    /// Returns Ok(()) on success, Err(()) on error.
+    fn snapshot_drop(self: StrongPin<'_, Self>, ctx: &KernelCtx<'_, '_>) -> Result<(), ()>;
+
+    /// Copy data into virtual address `dst` of the current process by `n` bytes
+    /// from the content of inode `inum` of the snapshot at offset `off`.
+    /// Returns Ok(number of bytes copied) on success, Err(()) on error.
+    fn snapshot_read(
+        self: StrongPin<'_, Self>,
+        inum: u32,
+        off: u32,
+        dst: UVAddr,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()>;
+
+    /// Copy stat information from inode `inum` of the snapshot.
+    fn snapshot_stat(
+        self: StrongPin<'_, Self>,
+        inum: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Stat, ()>;
+
+    /// Run `f` while no FS system call is executing and every committed block has been
+    /// written to its home location, so that the disk is consistent without the log.
+    /// New FS system calls wait until `f` returns.
+    fn quiesce<F: FnOnce()>(self: StrongPin<'_, Self>, f: F, ctx: &KernelCtx<'_, '_>);
+
+    /// Like `quiesce`, but also marks the file system as cleanly unmounted while `f` runs, so
+    /// that the next mount does not recover the log. `f` is meant to stop the machine.
+    fn unmount<F: FnOnce()>(self: StrongPin<'_, Self>, f: F, ctx: &KernelCtx<'_, '_>);
 }
 
 pub trait FileSystemExt: FileSystem {
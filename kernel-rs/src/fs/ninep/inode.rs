@@ -0,0 +1,522 @@
+//! Inodes of the 9p client.
+//!
+//! An in-memory inode stands for a file of the server, which the client names by a fid. The fid
+//! is obtained by walking to the file from its directory, and clunked when the last reference to
+//! the inode is dropped, so the inode table doubles as the cache of the fids the client holds.
+//! Reads and writes go through a second fid, which is opened by the first of them.
+//!
+//! The inode number of a file is the low 32 bits of the path of its qid, which the server keeps
+//! unique among its files, except that the root is always `ROOTINO`. qemu derives the paths from
+//! the inode numbers of the host, so two files share an inode only if the exported directory
+//! spans several host file systems.
+//!
+//! The attributes of an inode are read from the server when it is locked for the first time, and
+//! again whenever the client changes the file through another inode, such as its directory. The
+//! host may change the files at any time, so reads go past the cached size, and stat() always
+//! asks the server.
+
+use core::cmp;
+
+use ufs_layout::{dirent_reclen, DInodeType, DirentHeader, DIRENT_HEADER_SIZE, MAXNAMELEN};
+use zerocopy::AsBytes;
+
+use super::{
+    proto::{
+        rpc, Decoder, Qid, DT_CHR, DT_DIR, DT_REG, DT_SOCK, GETATTR_BASIC, L_O_RDONLY, L_O_RDWR,
+        NOFID, S_IFCHR, S_IFDIR, S_IFMT, S_IFSOCK, TCLUNK, TGETATTR, TLOPEN, TREADDIR, TWALK,
+    },
+    Ninep, ROOTINO, ROOT_FID,
+};
+use crate::{
+    addr::UVAddr,
+    arena::{Arena, ArrayArena},
+    fs::{FileName, Inode, InodeGuard, InodeType, Itable, Path, RcInode, Tx},
+    hal::hal,
+    lock::SleepLock,
+    page::PGSIZE,
+    param::{NINODE, ROOTDEV},
+    proc::KernelCtx,
+    util::strong_pin::StrongPin,
+};
+
+pub struct InodeInner {
+    /// Have the attributes been read from the server?
+    pub valid: bool,
+    /// Fid that names the file on the server
+    pub fid: u32,
+    /// Fid of the file opened for reads and writes, or NOFID if it has not been opened
+    pub io_fid: u32,
+    /// Can `io_fid` be written?
+    pub io_writable: bool,
+    /// copy of the attributes on the server
+    pub typ: InodeType,
+    pub nlink: i16,
+    pub size: u32,
+    pub gen: u32,
+}
+
+/// Returns the major number of a device number of the host, in the encoding of glibc.
+fn dev_major(rdev: u64) -> u16 {
+    (((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff)) as u16
+}
+
+/// Returns the minor number of a device number of the host, in the encoding of glibc.
+fn dev_minor(rdev: u64) -> u16 {
+    ((rdev & 0xff) | ((rdev >> 12) & !0xff)) as u16
+}
+
+/// Returns the type of a file as recorded in a directory entry, given the type of Treaddir.
+fn dirent_type(typ: u8) -> u8 {
+    let typ = match typ {
+        DT_DIR => DInodeType::Dir,
+        DT_REG => DInodeType::File,
+        DT_CHR => DInodeType::Device,
+        DT_SOCK => DInodeType::Socket,
+        _ => return 0,
+    };
+    typ as u8
+}
+
+/// Gives up the fid. Errors are ignored, as the fid cannot be used either way.
+pub fn clunk(fid: u32, ctx: &KernelCtx<'_, '_>) {
+    let _ = rpc(
+        TCLUNK,
+        |e| {
+            let _ = e.u32(fid);
+        },
+        |_| Ok(()),
+        ctx,
+    );
+}
+
+/// Returns a new fid that names the same file as fid.
+pub fn clone_fid(fid: u32, ctx: &KernelCtx<'_, '_>) -> Result<u32, ()> {
+    let newfid = ctx.kernel().fs().alloc_fid();
+    rpc(
+        TWALK,
+        |e| {
+            let _ = e.u32(fid).u32(newfid).u16(0);
+        },
+        |_| Ok(()),
+        ctx,
+    )?;
+    Ok(newfid)
+}
+
+impl InodeInner {
+    /// Read the attributes of the file from the server.
+    /// A file that the server no longer finds, such as one removed by the host, keeps its
+    /// attributes but has no links.
+    pub fn getattr(&mut self, ctx: &KernelCtx<'_, '_>) {
+        let fid = self.fid;
+        let attr = rpc(
+            TGETATTR,
+            |e| {
+                let _ = e.u32(fid).u64(GETATTR_BASIC);
+            },
+            |d| {
+                let _valid = d.u64()?;
+                let _qid = d.qid()?;
+                let mode = d.u32()?;
+                let _uid = d.u32()?;
+                let _gid = d.u32()?;
+                let nlink = d.u64()?;
+                let rdev = d.u64()?;
+                let size = d.u64()?;
+                // blksize, blocks, and the seconds and nanoseconds of atime, mtime, ctime, and
+                // btime
+                let _ = d.bytes(10 * 8)?;
+                let gen = d.u64()?;
+                Ok((mode, nlink, rdev, size, gen))
+            },
+            ctx,
+        );
+        self.valid = true;
+        let (mode, nlink, rdev, size, gen) = match attr {
+            Ok(attr) => attr,
+            Err(()) => {
+                self.nlink = 0;
+                return;
+            }
+        };
+        self.typ = match mode & S_IFMT {
+            S_IFDIR => InodeType::Dir,
+            S_IFCHR => {
+                InodeType::Device {
+                    major: dev_major(rdev),
+                    minor: dev_minor(rdev),
+                }
+            }
+            S_IFSOCK => InodeType::Socket,
+            // Other kinds of files, such as symbolic links, read as regular files.
+            _ => InodeType::File,
+        };
+        self.nlink = cmp::min(nlink, i16::MAX as u64) as i16;
+        self.size = cmp::min(size, u32::MAX as u64) as u32;
+        self.gen = gen as u32;
+    }
+}
+
+impl InodeGuard<'_, Ninep> {
+    /// Walk from the directory to its entry name.
+    /// Returns Ok((fid of the entry, qid of the entry)) on success, Err(()) if there is no such
+    /// entry.
+    fn walk(&self, name: &[u8], ctx: &KernelCtx<'_, '_>) -> Result<(u32, Qid), ()> {
+        let fid = self.deref_inner().fid;
+        let newfid = ctx.kernel().fs().alloc_fid();
+        let qid = rpc(
+            TWALK,
+            |e| {
+                let _ = e.u32(fid).u32(newfid).u16(1).str(name);
+            },
+            |d| {
+                // A walk that fails leaves no qid, and newfid unused.
+                if d.u16()? != 1 {
+                    return Err(());
+                }
+                d.qid()
+            },
+            ctx,
+        )?;
+        Ok((newfid, qid))
+    }
+
+    /// Look for a directory entry in a directory.
+    pub fn dirlookup(
+        &mut self,
+        name: &FileName<MAXNAMELEN>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Ninep>, ()> {
+        assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
+
+        let (fid, qid) = self.walk(name.as_bytes(), ctx)?;
+        let fs = ctx.kernel().fs();
+        Ok(fs.itable().get_inode(self.dev, fs.inum(qid), fid, ctx))
+    }
+
+    /// Returns a fid of the file opened for reads, and for writes too if `write` is true.
+    /// The fid is kept for later reads and writes until the inode is dropped.
+    pub fn io_fid(&mut self, write: bool, ctx: &KernelCtx<'_, '_>) -> Result<u32, ()> {
+        let inner = self.deref_inner();
+        if inner.io_fid != NOFID && (inner.io_writable || !write) {
+            return Ok(inner.io_fid);
+        }
+
+        // Files are opened for both reads and writes if possible, so that a single fid serves
+        // both. Directories can only be read.
+        let fid = clone_fid(inner.fid, ctx)?;
+        let lopen = |flags: u32| {
+            rpc(
+                TLOPEN,
+                |e| {
+                    let _ = e.u32(fid).u32(flags);
+                },
+                |_| Ok(()),
+                ctx,
+            )
+        };
+        let writable = inner.typ == InodeType::File && lopen(L_O_RDWR).is_ok();
+        if !writable && (write || lopen(L_O_RDONLY).is_err()) {
+            clunk(fid, ctx);
+            return Err(());
+        }
+
+        let inner = self.deref_inner_mut();
+        if inner.io_fid != NOFID {
+            let old = inner.io_fid;
+            clunk(old, ctx);
+        }
+        inner.io_fid = fid;
+        inner.io_writable = writable;
+        Ok(fid)
+    }
+
+    /// Call f with the qid, the type, and the name of each entry of the directory, in order,
+    /// until f returns false.
+    /// Returns Ok(()) on success, Err(()) on error.
+    fn readdir<F: FnMut(Qid, u8, &[u8]) -> bool>(
+        &mut self,
+        mut f: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let fid = self.io_fid(false, ctx)?;
+        let count = ctx.kernel().fs().iounit() as u32;
+        let allocator = hal().kmem();
+        let page = allocator.alloc().ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+
+        // The offset of an entry is a cookie of the server that continues after it.
+        let mut offset = 0;
+        loop {
+            let len = rpc(
+                TREADDIR,
+                |e| {
+                    let _ = e.u32(fid).u64(offset).u32(count);
+                },
+                |d| {
+                    let len = d.u32()? as usize;
+                    page[..len].copy_from_slice(d.bytes(len)?);
+                    Ok(len)
+                },
+                ctx,
+            )?;
+            if len == 0 {
+                return Ok(());
+            }
+            let mut d = Decoder::new(&page[..len]);
+            while !d.is_empty() {
+                let qid = d.qid()?;
+                offset = d.u64()?;
+                let typ = d.u8()?;
+                let name = d.str()?;
+                if !f(qid, typ, name) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Look for the entry of the inode `inum` in the directory dp, other than "." and "..",
+    /// and copy its name to the end of `buf`.
+    /// Returns Ok(length of the name) on success, Err(()) if there is no such entry or the name
+    /// does not fit in `buf`.
+    pub fn dirname(
+        &mut self,
+        inum: u32,
+        buf: &mut [u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirname not DIR");
+
+        let fs = ctx.kernel().fs();
+        let mut res = Err(());
+        self.readdir(
+            |qid, _, name| {
+                if fs.inum(qid) != inum || name == b"." || name == b".." {
+                    return true;
+                }
+                res = buf
+                    .len()
+                    .checked_sub(name.len())
+                    .map(|start| {
+                        buf[start..].copy_from_slice(name);
+                        name.len()
+                    })
+                    .ok_or(());
+                false
+            },
+            ctx,
+        )?;
+        res
+    }
+
+    /// Copy the directory entries of the directory dp, starting from the entry at index `*off`,
+    /// into virtual address `dst` of the current process by at most `n` bytes.
+    /// Entries are copied in the long name format of ufs, and `*off` is advanced past the copied
+    /// entries. The offsets of the server are cookies that need not fit in `*off`, so the
+    /// directory is read again from its beginning each time.
+    /// Returns Ok(number of bytes copied) on success, Err(()) on error.
+    pub fn getdents(
+        &mut self,
+        off: &mut u32,
+        dst: UVAddr,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        if self.deref_inner().typ != InodeType::Dir {
+            return Err(());
+        }
+
+        let allocator = hal().kmem();
+        let page = allocator.alloc().ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+        let cap = cmp::min(n as usize, PGSIZE);
+        let mut tot = 0;
+        let mut index = 0;
+        let mut full = false;
+        self.readdir(
+            |qid, typ, name| {
+                // Names too long for the entries of ufs are skipped.
+                if index < *off || name.len() > MAXNAMELEN {
+                    index += 1;
+                    return true;
+                }
+                let reclen = dirent_reclen(name.len()) as usize;
+                if tot + reclen > cap {
+                    full = true;
+                    return false;
+                }
+                // getdents() reports only the low bits of the inode number.
+                let header = DirentHeader {
+                    inum: qid.path as u16,
+                    reclen: reclen as u16,
+                    namelen: name.len() as u8,
+                    typ: dirent_type(typ),
+                };
+                let entry = &mut page[tot..tot + reclen];
+                entry.fill(0);
+                entry[..DIRENT_HEADER_SIZE].copy_from_slice(header.as_bytes());
+                entry[DIRENT_HEADER_SIZE..DIRENT_HEADER_SIZE + name.len()].copy_from_slice(name);
+                tot += reclen;
+                index += 1;
+                true
+            },
+            ctx,
+        )?;
+        if full && tot == 0 {
+            // The buffer cannot hold even a single entry.
+            return Err(());
+        }
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(dst, &page[..tot])?;
+        *off = index;
+        Ok(tot)
+    }
+}
+
+impl const Default for Inode<Ninep> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inode<Ninep> {
+    pub const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            inner: SleepLock::new(
+                "inode",
+                InodeInner {
+                    valid: false,
+                    fid: NOFID,
+                    io_fid: NOFID,
+                    io_writable: false,
+                    typ: InodeType::None,
+                    nlink: 0,
+                    size: 0,
+                    gen: 0,
+                },
+            ),
+        }
+    }
+}
+
+impl Itable<Ninep> {
+    pub const fn new_itable() -> Self {
+        ArrayArena::<Inode<Ninep>, NINODE>::new("ITABLE")
+    }
+
+    /// Find the inode with number inum on device dev and return the in-memory copy, which names
+    /// the file by fid unless the inode is already in memory, in which case fid is clunked.
+    /// Does not lock the inode and does not read it from the server.
+    pub fn get_inode(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        inum: u32,
+        fid: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> RcInode<Ninep> {
+        let mut fresh = false;
+        let ptr = self
+            .find_or_alloc(
+                |inode| inode.dev == dev && inode.inum == inum,
+                |inode| {
+                    inode.dev = dev;
+                    inode.inum = inum;
+                    let inner = inode.inner.get_mut();
+                    inner.valid = false;
+                    inner.fid = fid;
+                    inner.io_fid = NOFID;
+                    fresh = true;
+                },
+            )
+            .expect("[Itable::get_inode] no inodes");
+        if !fresh {
+            clunk(fid, ctx);
+        }
+        ptr
+    }
+
+    /// Returns the root inode, which names the root by the fid of Tattach. It works before the
+    /// client attaches to the server, as long as it is not locked.
+    pub fn root(self: StrongPin<'_, Self>) -> RcInode<Ninep> {
+        self.find_or_alloc(
+            |inode| inode.dev == ROOTDEV && inode.inum == ROOTINO,
+            |inode| {
+                inode.dev = ROOTDEV;
+                inode.inum = ROOTINO;
+                let inner = inode.inner.get_mut();
+                inner.valid = false;
+                inner.fid = ROOT_FID;
+                inner.io_fid = NOFID;
+            },
+        )
+        .expect("[Itable::root] no inodes")
+    }
+
+    pub fn namei(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        dir: Option<&RcInode<Ninep>>,
+        tx: &Tx<'_, Ninep>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Ninep>, ()> {
+        Ok(self.namex(path, dir, false, tx, ctx)?.0)
+    }
+
+    pub fn nameiparent<'s>(
+        self: StrongPin<'_, Self>,
+        path: &'s Path,
+        dir: Option<&RcInode<Ninep>>,
+        tx: &Tx<'_, Ninep>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Ninep>, &'s FileName<{ MAXNAMELEN }>), ()> {
+        let (ip, name_in_path) = self.namex(path, dir, true, tx, ctx)?;
+        let name_in_path = name_in_path.ok_or(())?;
+        Ok((ip, name_in_path))
+    }
+
+    fn namex<'s>(
+        self: StrongPin<'_, Self>,
+        mut path: &'s Path,
+        dir: Option<&RcInode<Ninep>>,
+        parent: bool,
+        tx: &Tx<'_, Ninep>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Ninep>, Option<&'s FileName<{ MAXNAMELEN }>>), ()> {
+        let mut ptr = if path.is_absolute() {
+            self.root()
+        } else if let Some(dir) = dir {
+            dir.clone()
+        } else {
+            ctx.proc().cwd().clone()
+        };
+
+        while let Some((new_path, name)) = path.skipelem() {
+            path = new_path;
+
+            let mut ip = ptr.lock(ctx);
+            if ip.deref_inner().typ != InodeType::Dir {
+                ip.free(ctx);
+                ptr.free((tx, ctx));
+                return Err(());
+            }
+            if parent && path.is_empty_string() {
+                // Stop one level early.
+                ip.free(ctx);
+                return Ok((ptr, Some(name)));
+            }
+            let next = ip.dirlookup(name, ctx);
+            ip.free(ctx);
+            ptr.free((tx, ctx));
+            ptr = next?
+        }
+        if parent {
+            ptr.free((tx, ctx));
+            return Err(());
+        }
+        Ok((ptr, None))
+    }
+}
@@ -0,0 +1,820 @@
+//! 9P2000.L client file system, which mounts a directory of the host through the virtio 9p
+//! transport.
+//!
+//! The server keeps the files, so the client has no log and no buffer cache: every operation is
+//! a request to the server, and transactions do nothing. The client keeps only an inode table of
+//! the files it holds fids for. It exists to feed inputs to programs and collect their outputs
+//! without rebuilding the disk image, so it supports neither defrag() nor snapshots.
+//!
+//! The client attaches to the server in `init`, and panics if there is no server.
+
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::{cmp, mem};
+
+use pin_project::pin_project;
+use spin::Once;
+
+use self::inode::{clone_fid, clunk};
+use self::proto::{
+    rpc, Qid, AT_REMOVEDIR, IOHDR_SIZE, L_O_RDWR, NOFID, SETATTR_SIZE, S_IFCHR, S_IFDIR, S_IFREG,
+    S_IFSOCK, TATTACH, TLCREATE, TLINK, TMKDIR, TMKNOD, TREAD, TSETATTR, TUNLINKAT, TVERSION,
+    TWRITE,
+};
+use super::{
+    FcntlFlags, FileName, FileSystem, FileSystemExt, Inode, InodeGuard, InodeType, Itable, Path,
+    RcInode, Stat, Tx,
+};
+use crate::{
+    addr::UVAddr,
+    file::{FileType, InodeFileType},
+    hal::hal,
+    ok_or,
+    page::{Page, PGSIZE},
+    param::{MAXPATH, ROOTDEV},
+    proc::KernelCtx,
+    util::strong_pin::StrongPin,
+    virtio::NINEP_MSIZE,
+    watch::WatchMask,
+};
+
+mod inode;
+mod proto;
+
+pub use inode::InodeInner;
+
+/// Inode number of the root directory.
+const ROOTINO: u32 = 1;
+
+/// Fid of the root directory, given by Tattach.
+const ROOT_FID: u32 = 0;
+
+/// The version of the protocol.
+const VERSION: &[u8] = b"9P2000.L";
+
+/// What the client learned when it attached to the server.
+struct Session {
+    /// The largest message the server accepts.
+    msize: u32,
+    /// Path of the qid of the root directory.
+    root_path: u64,
+}
+
+#[pin_project]
+pub struct Ninep {
+    /// Attaching to the server should run only once because forkret() calls FileSystem::init().
+    session: Once<Session>,
+
+    /// The fid to give to the next file. Fids are never reused.
+    next_fid: AtomicU32,
+
+    #[pin]
+    itable: Itable<Self>,
+}
+
+impl Ninep {
+    pub const fn new() -> Self {
+        Self {
+            session: Once::new(),
+            next_fid: AtomicU32::new(ROOT_FID + 1),
+            itable: Itable::new_itable(),
+        }
+    }
+
+    fn session(&self) -> &Session {
+        self.session.get().expect("session")
+    }
+
+    /// Is the file system mounted read-only? The server decides which writes succeed, so it
+    /// never is.
+    pub fn read_only(&self) -> bool {
+        false
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    pub fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    }
+
+    /// Drop `ip`. Transactions do nothing, so its finalization is not deferred.
+    pub fn defer_free(&self, ip: RcInode<Self>, ctx: &KernelCtx<'_, '_>) {
+        let tx = self.begin_tx(ctx);
+        ip.free((&tx, ctx));
+        tx.end(ctx);
+    }
+
+    /// Nothing is deferred by `defer_free`.
+    pub fn reap(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    /// Returns a fid that no file has.
+    fn alloc_fid(&self) -> u32 {
+        self.next_fid.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The most bytes a single Tread or Twrite carries, which also fit in a page.
+    fn iounit(&self) -> usize {
+        cmp::min(PGSIZE, self.session().msize as usize - IOHDR_SIZE)
+    }
+
+    /// Returns the inode number of the file with qid.
+    fn inum(&self, qid: Qid) -> u32 {
+        if qid.path == self.session().root_path {
+            ROOTINO
+        } else {
+            qid.path as u32
+        }
+    }
+
+    /// Copy the absolute path of the current directory into `buf`, finding it by walking ".."
+    /// up to the root. Returns Ok(length of the path) on success, Err(()) if the path does not
+    /// fit in `buf` or the current directory has been removed.
+    fn find_cwd(
+        self: StrongPin<'_, Self>,
+        buf: &mut [u8],
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let mut end = buf.len();
+        let mut ptr = ctx.proc().cwd().clone();
+        let res = loop {
+            let mut ip = ptr.lock(ctx);
+            // A removed directory has no path.
+            if ip.deref_inner().nlink == 0 {
+                ip.free(ctx);
+                break Err(());
+            }
+            if ip.dev == ROOTDEV && ip.inum == ROOTINO {
+                ip.free(ctx);
+                break Ok(());
+            }
+            let inum = ip.inum;
+            // SAFETY: b".." does not contain any NUL characters.
+            let parent = ip.dirlookup(unsafe { FileName::from_bytes(b"..") }, ctx);
+            ip.free(ctx);
+            let parent = ok_or!(parent, break Err(()));
+            mem::replace(&mut ptr, parent).free((tx, ctx));
+
+            let mut dp = ptr.lock(ctx);
+            let len = dp.dirname(inum, &mut buf[..end], ctx);
+            dp.free(ctx);
+            end -= ok_or!(len, break Err(()));
+            if end == 0 {
+                break Err(());
+            }
+            end -= 1;
+            buf[end] = b'/';
+        };
+        ptr.free((tx, ctx));
+        res?;
+
+        // The root directory itself.
+        if end == buf.len() {
+            if end == 0 {
+                return Err(());
+            }
+            end -= 1;
+            buf[end] = b'/';
+        }
+        let len = buf.len() - end;
+        buf.copy_within(end.., 0);
+        Ok(len)
+    }
+}
+
+impl FileSystem for Ninep {
+    type Dirent = ();
+    type InodeInner = InodeInner;
+
+    fn init(&self, _dev: u32, ctx: &KernelCtx<'_, '_>) {
+        if !self.session.is_completed() {
+            let msize = rpc(
+                TVERSION,
+                |e| {
+                    let _ = e.u32(NINEP_MSIZE as u32).str(VERSION);
+                },
+                |d| {
+                    let msize = d.u32()?;
+                    if d.str()? != VERSION || (msize as usize) <= IOHDR_SIZE {
+                        return Err(());
+                    }
+                    Ok(cmp::min(msize, NINEP_MSIZE as u32))
+                },
+                ctx,
+            )
+            .expect("9p: no server");
+            let root = rpc(
+                TATTACH,
+                |e| {
+                    let _ = e.u32(ROOT_FID).u32(NOFID).str(b"").str(b"").u32(0);
+                },
+                |d| d.qid(),
+                ctx,
+            )
+            .expect("9p: attach");
+            let _ = self.session.call_once(|| {
+                Session {
+                    msize,
+                    root_path: root.path,
+                }
+            });
+        }
+    }
+
+    fn root(self: StrongPin<'_, Self>) -> RcInode<Self> {
+        self.itable().root()
+    }
+
+    fn namei(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        dir: Option<&RcInode<Self>>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, ()> {
+        self.itable().namei(path, dir, tx, ctx)
+    }
+
+    fn link(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self>,
+        path: &Path,
+        dir: Option<&RcInode<Self>>,
+        _unnamed: bool,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let inode = scopeguard::guard(inode, |ptr| ptr.free((tx, ctx)));
+        let ip = inode.lock(ctx);
+        let (typ, fid) = (ip.deref_inner().typ, ip.deref_inner().fid);
+        ip.free(ctx);
+        if typ == InodeType::Dir {
+            return Err(());
+        }
+
+        let (ptr2, name) = self.itable().nameiparent(path, dir, tx, ctx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+        let dp = ptr2.lock(ctx);
+        let dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        let dfid = dp.deref_inner().fid;
+        rpc(
+            TLINK,
+            |e| {
+                let _ = e.u32(dfid).u32(fid).str(name.as_bytes());
+            },
+            |_| Ok(()),
+            ctx,
+        )?;
+        ctx.kernel()
+            .watches()
+            .post(dp.dev, dp.inum, WatchMask::CREATE, inode.inum, ctx);
+        drop(dp);
+
+        let mut ip = inode.lock(ctx);
+        ip.deref_inner_mut().getattr(ctx);
+        ip.free(ctx);
+        Ok(())
+    }
+
+    fn unlink(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        dir: Option<&RcInode<Self>>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let (ptr, name) = self.itable().nameiparent(path, dir, tx, ctx)?;
+        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+        let dp = ptr.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+
+        // Cannot unlink "." or "..".
+        if name.as_bytes() == b"." || name.as_bytes() == b".." {
+            return Err(());
+        }
+
+        let ptr2 = dp.dirlookup(name, ctx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+        let ip = ptr2.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+
+        // The server refuses to remove a directory that is not empty.
+        let flags = if ip.deref_inner().typ == InodeType::Dir {
+            AT_REMOVEDIR
+        } else {
+            0
+        };
+        let dfid = dp.deref_inner().fid;
+        rpc(
+            TUNLINKAT,
+            |e| {
+                let _ = e.u32(dfid).str(name.as_bytes()).u32(flags);
+            },
+            |_| Ok(()),
+            ctx,
+        )?;
+        ctx.kernel()
+            .watches()
+            .post(dp.dev, dp.inum, WatchMask::DELETE, ip.inum, ctx);
+        dp.deref_inner_mut().getattr(ctx);
+        drop(dp);
+        drop(ptr);
+        ip.deref_inner_mut().getattr(ctx);
+        Ok(())
+    }
+
+    fn create<F, T>(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        dir: Option<&RcInode<Self>>,
+        typ: InodeType,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+        f: F,
+    ) -> Result<(RcInode<Self>, T), ()>
+    where
+        F: FnOnce(&mut InodeGuard<'_, Self>) -> T,
+    {
+        let (ptr, name) = self.itable().nameiparent(path, dir, tx, ctx)?;
+        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+        let dp = ptr.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        if let Ok(ptr2) = dp.dirlookup(name, ctx) {
+            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+            drop(dp);
+            if typ != InodeType::File {
+                return Err(());
+            }
+            let ip = ptr2.lock(ctx);
+            let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+            if let InodeType::None | InodeType::Dir = ip.deref_inner().typ {
+                return Err(());
+            }
+            let ret = f(&mut ip);
+            drop(ip);
+            return Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret));
+        }
+
+        let dfid = dp.deref_inner().fid;
+        let name_bytes = name.as_bytes();
+        let qid = match typ {
+            InodeType::None => return Err(()),
+            InodeType::File => {
+                // Tlcreate turns the fid it is given into one of the new file, opened.
+                let fid = clone_fid(dfid, ctx)?;
+                let qid = rpc(
+                    TLCREATE,
+                    |e| {
+                        let _ = e
+                            .u32(fid)
+                            .str(name_bytes)
+                            .u32(L_O_RDWR)
+                            .u32(S_IFREG | 0o644)
+                            .u32(0);
+                    },
+                    |d| d.qid(),
+                    ctx,
+                );
+                clunk(fid, ctx);
+                qid?
+            }
+            InodeType::Dir => {
+                rpc(
+                    TMKDIR,
+                    |e| {
+                        let _ = e.u32(dfid).str(name_bytes).u32(S_IFDIR | 0o755).u32(0);
+                    },
+                    |d| d.qid(),
+                    ctx,
+                )?
+            }
+            InodeType::Device { major, minor } => {
+                rpc(
+                    TMKNOD,
+                    |e| {
+                        let _ = e
+                            .u32(dfid)
+                            .str(name_bytes)
+                            .u32(S_IFCHR | 0o644)
+                            .u32(major as u32)
+                            .u32(minor as u32)
+                            .u32(0);
+                    },
+                    |d| d.qid(),
+                    ctx,
+                )?
+            }
+            InodeType::Socket => {
+                rpc(
+                    TMKNOD,
+                    |e| {
+                        let _ = e
+                            .u32(dfid)
+                            .str(name_bytes)
+                            .u32(S_IFSOCK | 0o644)
+                            .u32(0)
+                            .u32(0)
+                            .u32(0);
+                    },
+                    |d| d.qid(),
+                    ctx,
+                )?
+            }
+        };
+        ctx.kernel()
+            .watches()
+            .post(dp.dev, dp.inum, WatchMask::CREATE, self.inum(qid), ctx);
+        if typ == InodeType::Dir {
+            // for ".."
+            dp.deref_inner_mut().getattr(ctx);
+        }
+
+        let ptr2 = dp.dirlookup(name, ctx)?;
+        drop(dp);
+        let ip = ptr2.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        let ret = f(&mut ip);
+        drop(ip);
+        Ok((ptr2, ret))
+    }
+
+    fn open(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        dir: Option<&RcInode<Self>>,
+        omode: FcntlFlags,
+        tx: &Tx<'_, Self>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let writable = omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR);
+        // The server has no unnamed files.
+        let (ip, typ) = if omode.contains(FcntlFlags::O_TMPFILE) {
+            return Err(());
+        } else if omode.contains(FcntlFlags::O_CREATE) {
+            self.create(path, dir, InodeType::File, tx, ctx, |ip| {
+                ip.deref_inner().typ
+            })?
+        } else {
+            let ptr = self.itable().namei(path, dir, tx, ctx)?;
+            let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+            let ip = ptr.lock(ctx);
+            let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+            let typ = ip.deref_inner().typ;
+
+            if typ == InodeType::Dir && omode != FcntlFlags::O_RDONLY {
+                return Err(());
+            }
+            drop(ip);
+            (scopeguard::ScopeGuard::into_inner(ptr), typ)
+        };
+
+        let filetype = match typ {
+            InodeType::Device { major, .. } => FileType::Device { ip, major },
+            // A socket is connected to rather than opened.
+            InodeType::Socket => {
+                ip.free((tx, ctx));
+                return Err(());
+            }
+            _ => {
+                FileType::Inode {
+                    inner: InodeFileType::new(ip, false),
+                }
+            }
+        };
+
+        let f = ctx.kernel().ftable().alloc_file(
+            filetype,
+            !omode.intersects(FcntlFlags::O_WRONLY),
+            writable,
+            ctx,
+        )?;
+
+        if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
+            match &f.typ {
+                FileType::Device { ip, .. }
+                | FileType::Inode {
+                    inner: InodeFileType { ip, .. },
+                } => {
+                    let mut ip = ip.lock(ctx);
+                    ip.trunc(tx, ctx);
+                    ip.free(ctx);
+                }
+                _ => panic!("sys_open : Not reach"),
+            };
+        }
+        let fd = f.fdalloc(ctx)?;
+        if let Some(entry) = &mut ctx.proc_mut().deref_mut_data().open_files[fd as usize] {
+            entry.cloexec = omode.contains(FcntlFlags::O_CLOEXEC);
+        }
+        Ok(fd as usize)
+    }
+
+    fn chdir(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self>,
+        tx: &Tx<'_, Self>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let ip = inode.lock(ctx);
+        let typ = ip.deref_inner().typ;
+        ip.free(ctx);
+        if typ != InodeType::Dir {
+            inode.free((tx, ctx));
+            return Err(());
+        }
+        mem::replace(ctx.proc_mut().cwd_mut(), inode).free((tx, ctx));
+
+        // Find the path once here, for getcwd(). The client renames no directories, though the
+        // host may.
+        let mut path = [0; MAXPATH];
+        let len = self.find_cwd(&mut path, tx, ctx).ok();
+        ctx.proc_mut()
+            .deref_mut_data()
+            .set_cwd_path(len.map(|len| &path[..len]));
+        Ok(())
+    }
+
+    fn getcwd(
+        self: StrongPin<'_, Self>,
+        buf: &mut [u8],
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        // A removed directory has no path.
+        let ip = ctx.proc().cwd().lock(ctx);
+        let removed = ip.deref_inner().nlink == 0;
+        ip.free(ctx);
+        if removed {
+            return Err(());
+        }
+        if let Some(path) = ctx.proc().deref_data().cwd_path() {
+            buf.get_mut(..path.len()).ok_or(())?.copy_from_slice(path);
+            return Ok(path.len());
+        }
+        self.find_cwd(buf, tx, ctx)
+    }
+
+    fn tx_begin(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    unsafe fn tx_end(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    #[inline]
+    fn inode_read<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), ()>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        mut off: u32,
+        n: u32,
+        mut f: F,
+        mut k: K,
+    ) -> Result<usize, ()> {
+        // Directories are read with getdents().
+        if guard.deref_inner().typ != InodeType::File || off.wrapping_add(n) < off {
+            return Ok(0);
+        }
+        let fid = guard.io_fid(false, &k)?;
+        let iounit = k.kernel().fs().iounit();
+        let allocator = hal().kmem();
+        let page = allocator.alloc().ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+
+        // The host may have extended the file, so reads are not limited by the cached size.
+        let mut tot: u32 = 0;
+        while tot < n {
+            let m = cmp::min((n - tot) as usize, iounit);
+            let count = rpc(
+                TREAD,
+                |e| {
+                    let _ = e.u32(fid).u64(off as u64).u32(m as u32);
+                },
+                |d| {
+                    let count = cmp::min(d.u32()? as usize, m);
+                    page[..count].copy_from_slice(d.bytes(count)?);
+                    Ok(count)
+                },
+                &k,
+            )?;
+            if count == 0 {
+                break;
+            }
+            f(tot, &page[..count], &mut k)?;
+            tot += count as u32;
+            off += count as u32;
+            if count < m {
+                break;
+            }
+        }
+        if off > guard.deref_inner().size {
+            guard.deref_inner_mut().size = off;
+        }
+        Ok(tot as usize)
+    }
+
+    #[inline]
+    fn inode_write<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), ()>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        mut off: u32,
+        n: u32,
+        mut f: F,
+        tx: &Tx<'_, Self>,
+        mut k: K,
+    ) -> Result<usize, ()> {
+        if guard.deref_inner().typ != InodeType::File || off > guard.deref_inner().size {
+            return Err(());
+        }
+        off.checked_add(n).ok_or(())?;
+        let fid = guard.io_fid(true, &k)?;
+        let iounit = tx.fs.iounit();
+        let allocator = hal().kmem();
+        let page = allocator.alloc().ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+
+        let mut tot: u32 = 0;
+        let mut full = false;
+        while tot < n {
+            let m = cmp::min((n - tot) as usize, iounit);
+            if f(tot, &mut page[..m], &mut k).is_err() {
+                break;
+            }
+            let count = ok_or!(
+                rpc(
+                    TWRITE,
+                    |e| {
+                        let _ = e.u32(fid).u64(off as u64).u32(m as u32).bytes(&page[..m]);
+                    },
+                    |d| Ok(cmp::min(d.u32()? as usize, m)),
+                    &k,
+                ),
+                {
+                    full = true;
+                    break;
+                }
+            );
+            tot += count as u32;
+            off += count as u32;
+            if count < m {
+                break;
+            }
+        }
+
+        if off > guard.deref_inner().size {
+            guard.deref_inner_mut().size = off;
+        }
+        if full && tot == 0 {
+            // The server failed the write, for example because the host's disk is full.
+            return Err(());
+        }
+        Ok(tot as usize)
+    }
+
+    fn inode_trunc(
+        guard: &mut InodeGuard<'_, Self>,
+        size: u32,
+        _tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let fid = guard.deref_inner().fid;
+        rpc(
+            TSETATTR,
+            |e| {
+                let _ = e
+                    .u32(fid)
+                    .u32(SETATTR_SIZE)
+                    // mode, uid, and gid
+                    .u32(0)
+                    .u32(0)
+                    .u32(0)
+                    .u64(size as u64)
+                    // atime and mtime
+                    .bytes(&[0; 32]);
+            },
+            |_| Ok(()),
+            ctx,
+        )?;
+        guard.deref_inner_mut().size = size;
+        Ok(())
+    }
+
+    fn inode_share_page(
+        _guard: &mut InodeGuard<'_, Self>,
+        _index: usize,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Option<Page> {
+        // File contents are not cached, but read from the host every time.
+        None
+    }
+
+    fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
+        let mut guard = inode.inner.lock(ctx);
+        if !guard.valid {
+            guard.getattr(ctx);
+        }
+        mem::forget(guard);
+        InodeGuard { inode }
+    }
+
+    fn inode_finalize<'a, 'id: 'a>(
+        inode: &mut Inode<Self>,
+        _tx: &'a Tx<'a, Self>,
+        ctx: &'a KernelCtx<'id, 'a>,
+    ) {
+        // The server frees the file once it has no links and no open fids.
+        let inner = inode.inner.get_mut();
+        if inner.io_fid != NOFID {
+            clunk(inner.io_fid, ctx);
+            inner.io_fid = NOFID;
+        }
+        // The root keeps the fid of Tattach.
+        if inner.fid != ROOT_FID {
+            clunk(inner.fid, ctx);
+        }
+        inner.fid = NOFID;
+        if inner.valid && inner.nlink == 0 {
+            ctx.kernel().watches().forget(inode.dev, inode.inum);
+        }
+        inner.valid = false;
+    }
+
+    fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat {
+        // The host may have changed the file.
+        let mut inner = inode.inner.lock(ctx);
+        inner.getattr(ctx);
+        let st = Stat {
+            dev: inode.dev as i32,
+            ino: inode.inum,
+            typ: match inner.typ {
+                InodeType::None => 0,
+                InodeType::Dir => 1,
+                InodeType::File => 2,
+                InodeType::Device { .. } => 3,
+                InodeType::Socket => 4,
+            },
+            nlink: inner.nlink,
+            gen: inner.gen,
+            size: inner.size as usize,
+        };
+        inner.free(ctx);
+        st
+    }
+
+    fn inode_getdents(
+        guard: &mut InodeGuard<'_, Self>,
+        off: &mut u32,
+        dst: UVAddr,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        guard.getdents(off, dst, n, ctx)
+    }
+
+    fn defrag(
+        self: StrongPin<'_, Self>,
+        _inode: &Inode<Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        Err(())
+    }
+
+    fn snapshot(self: StrongPin<'_, Self>, _ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn snapshot_drop(self: StrongPin<'_, Self>, _ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn snapshot_read(
+        self: StrongPin<'_, Self>,
+        _inum: u32,
+        _off: u32,
+        _dst: UVAddr,
+        _n: u32,
+        _ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        Err(())
+    }
+
+    fn snapshot_stat(
+        self: StrongPin<'_, Self>,
+        _inum: u32,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Stat, ()> {
+        Err(())
+    }
+
+    fn quiesce<F: FnOnce()>(self: StrongPin<'_, Self>, f: F, _ctx: &KernelCtx<'_, '_>) {
+        // The server writes the files to the host as it receives them.
+        f();
+    }
+
+    fn unmount<F: FnOnce()>(self: StrongPin<'_, Self>, f: F, _ctx: &KernelCtx<'_, '_>) {
+        f();
+    }
+}
@@ -0,0 +1,218 @@
+//! Messages of the 9P2000.L protocol.
+//!
+//! A message is its size[4], type[1], and tag[2], followed by the fields of its type. Integers
+//! are little-endian, and a string is its length[2] followed by its bytes. The client sends one
+//! request at a time, so every request uses the same tag.
+//!
+//! The protocol:
+//! https://github.com/chaos/diod/blob/master/protocol.md
+
+use core::convert::TryInto;
+
+use crate::{hal::hal, proc::KernelCtx};
+
+pub const TVERSION: u8 = 100;
+pub const TATTACH: u8 = 104;
+pub const TWALK: u8 = 110;
+pub const TLOPEN: u8 = 12;
+pub const TLCREATE: u8 = 14;
+pub const TMKNOD: u8 = 18;
+pub const TGETATTR: u8 = 24;
+pub const TSETATTR: u8 = 26;
+pub const TREADDIR: u8 = 40;
+pub const TLINK: u8 = 70;
+pub const TMKDIR: u8 = 72;
+pub const TUNLINKAT: u8 = 76;
+pub const TREAD: u8 = 116;
+pub const TWRITE: u8 = 118;
+pub const TCLUNK: u8 = 120;
+
+/// The reply of a failed request, with the errno of the server.
+const RLERROR: u8 = 7;
+
+/// Tag of Tversion, which comes before any other request.
+const NOTAG: u16 = !0;
+
+/// Tag of the other requests.
+const TAG: u16 = 0;
+
+/// "No fid", for the afid of Tattach, which does not authenticate.
+pub const NOFID: u32 = !0;
+
+/// Bytes of a message before its fields.
+pub const HEADER_SIZE: usize = 7;
+
+/// Bytes of a Twrite before its data, the largest such overhead of the messages that carry data.
+pub const IOHDR_SIZE: usize = HEADER_SIZE + 16;
+
+/// The attributes Tgetattr asks for: the mode, nlink, uid, gid, rdev, times, size, and blocks.
+pub const GETATTR_BASIC: u64 = 0x7ff;
+
+/// The size attribute of Tsetattr.
+pub const SETATTR_SIZE: u32 = 0x8;
+
+/// Flags of Tlopen and Tlcreate, as those of Linux open().
+pub const L_O_RDONLY: u32 = 0;
+pub const L_O_RDWR: u32 = 2;
+
+/// The flag of Tunlinkat to remove a directory.
+pub const AT_REMOVEDIR: u32 = 0x200;
+
+/// File modes, as those of Linux.
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFSOCK: u32 = 0o140000;
+
+/// Types of directory entries of Treaddir, as those of Linux.
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+pub const DT_SOCK: u8 = 12;
+
+/// The server's unique identification of a file.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Qid {
+    pub typ: u8,
+    pub version: u32,
+    /// Unique among the files of the server, like an inode number.
+    pub path: u64,
+}
+
+/// Writes the fields of a request.
+pub struct Encoder<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+/// Reads the fields of a reply.
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Encoder<'a> {
+    /// Begins a request of type typ in buf.
+    pub fn new(buf: &'a mut [u8], typ: u8) -> Self {
+        let mut e = Self {
+            buf,
+            len: HEADER_SIZE,
+        };
+        e.buf[4] = typ;
+        let tag = if typ == TVERSION { NOTAG } else { TAG };
+        e.buf[5..7].copy_from_slice(&tag.to_le_bytes());
+        e
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        self
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.bytes(&[v])
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Self {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub fn str(&mut self, s: &[u8]) -> &mut Self {
+        self.u16(s.len() as u16).bytes(s)
+    }
+
+    /// Ends the request. Returns its size.
+    pub fn finish(self) -> usize {
+        let len = self.len;
+        self.buf[..4].copy_from_slice(&(len as u32).to_le_bytes());
+        len
+    }
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], ()> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos.checked_add(n).ok_or(())?)
+            .ok_or(())?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, ()> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, ()> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, ()> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, ()> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn str(&mut self) -> Result<&'a [u8], ()> {
+        let len = self.u16()?;
+        self.bytes(len as usize)
+    }
+
+    pub fn qid(&mut self) -> Result<Qid, ()> {
+        Ok(Qid {
+            typ: self.u8()?,
+            version: self.u32()?,
+            path: self.u64()?,
+        })
+    }
+
+    /// Is there nothing left to read?
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+}
+
+/// Sends a request of type typ, whose fields encode writes, and gives the fields of the reply to
+/// decode. Returns Ok(the result of decode), or Err(()) if there is no server, or the server
+/// fails the request or replies with a malformed message.
+pub fn rpc<E, D, T>(typ: u8, encode: E, decode: D, ctx: &KernelCtx<'_, '_>) -> Result<T, ()>
+where
+    E: FnOnce(&mut Encoder<'_>),
+    D: FnOnce(&mut Decoder<'_>) -> Result<T, ()>,
+{
+    hal().ninep().transact(
+        |buf| {
+            let mut e = Encoder::new(buf, typ);
+            encode(&mut e);
+            e.finish()
+        },
+        |buf| {
+            let mut d = Decoder::new(buf);
+            let size = d.u32()? as usize;
+            let rtyp = d.u8()?;
+            let _tag = d.u16()?;
+            if rtyp == RLERROR || rtyp != typ + 1 || size < HEADER_SIZE || size > buf.len() {
+                return Err(());
+            }
+            let mut d = Decoder::new(&buf[HEADER_SIZE..size]);
+            decode(&mut d)
+        },
+        ctx,
+    )?
+}
@@ -0,0 +1,250 @@
+//! Page cache.
+//!
+//! The page cache holds copies of file contents in whole pages, indexed by the inode and the
+//! page index within the file. All reads and writes of regular files go through it, so file data
+//! are cached once, while the buffer cache remains for metadata such as inodes, directories, and
+//! indirect blocks.
+//!
+//! The page cache is write-through: writes still go to the disk blocks through the log, and also
+//! update the cached page if any. Hence the log stays the source of truth for crash recovery, and
+//! a cached page never needs to be written back.
+//!
+//! exec() maps pages of a program read-only into processes, sharing them with the cache. A shared
+//! page keeps its contents for the processes: the cache gives the page up, rather than reusing or
+//! updating it, and caches the file in a new page.
+//!
+//! Interface:
+//! * To get a page of a file, call get. If the page is not valid, fill it and call set_valid.
+//! * Pages of an inode may be accessed only while the inode is locked.
+//! * After writing file contents, call update. After truncating a file, call invalidate.
+//! * To map a page into a process, call share on a valid page.
+
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use array_macro::array;
+
+use crate::{
+    hal::hal,
+    lock::SpinLock,
+    page::{Page, RawPage, PGSIZE},
+    param::NPAGECACHE,
+};
+
+struct PageEntry {
+    dev: u32,
+
+    /// Inode number of the cached file. 0 if the entry is unused.
+    inum: u32,
+
+    /// Index of the cached page within the file.
+    index: u32,
+
+    /// Allocated on the first use of the entry, and kept afterwards.
+    page: Option<Page>,
+
+    /// Has the page been filled with the file contents?
+    valid: bool,
+
+    refcnt: u32,
+
+    /// Clock value at the last access, for LRU replacement.
+    last_used: u32,
+}
+
+struct PageCacheInner {
+    entries: [PageEntry; NPAGECACHE],
+    clock: u32,
+}
+
+pub struct PageCache {
+    inner: SpinLock<PageCacheInner>,
+}
+
+/// A reference to a page in the page cache.
+///
+/// # Safety
+///
+/// `data` refers to the page of `cache.entries[slot]`. The entry is not recycled while the
+/// reference is alive, since its refcnt is positive.
+pub struct PageRef<'s> {
+    cache: &'s PageCache,
+    slot: usize,
+    data: NonNull<RawPage>,
+    valid: bool,
+}
+
+impl PageEntry {
+    const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            index: 0,
+            page: None,
+            valid: false,
+            refcnt: 0,
+            last_used: 0,
+        }
+    }
+
+    fn is_for(&self, dev: u32, inum: u32) -> bool {
+        self.inum != 0 && self.inum == inum && self.dev == dev
+    }
+
+    /// Returns whether processes map the page.
+    fn is_shared(&self) -> bool {
+        self.page
+            .as_ref()
+            .map_or(false, |page| hal().kmem().is_shared(page))
+    }
+}
+
+impl PageCache {
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new(
+                "page_cache",
+                PageCacheInner {
+                    entries: array![_ => PageEntry::new(); NPAGECACHE],
+                    clock: 0,
+                },
+            ),
+        }
+    }
+
+    /// Returns the `index`th page of inode `inum` of device `dev`.
+    /// If the page is not cached, recycles the least recently used page, which is not valid yet.
+    /// Returns None if every page is in use or a page cannot be allocated.
+    pub fn get(&self, dev: u32, inum: u32, index: usize) -> Option<PageRef<'_>> {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        inner.clock = inner.clock.wrapping_add(1);
+
+        let slot = match inner
+            .entries
+            .iter()
+            .position(|e| e.is_for(dev, inum) && e.index == index as u32)
+        {
+            Some(slot) => slot,
+            None => {
+                // Prefer entries without a page, so that the cache grows up to NPAGECACHE pages.
+                let slot = (0..NPAGECACHE)
+                    .filter(|&i| inner.entries[i].refcnt == 0)
+                    .min_by_key(|&i| {
+                        (inner.entries[i].page.is_some(), inner.entries[i].last_used)
+                    })?;
+                let entry = &mut inner.entries[slot];
+                if entry.is_shared() {
+                    hal()
+                        .kmem()
+                        .free(entry.page.take().expect("PageCache::get"));
+                }
+                if entry.page.is_none() {
+                    entry.page = Some(hal().kmem().alloc()?);
+                }
+                entry.dev = dev;
+                entry.inum = inum;
+                entry.index = index as u32;
+                entry.valid = false;
+                slot
+            }
+        };
+
+        let entry = &mut inner.entries[slot];
+        entry.refcnt += 1;
+        entry.last_used = inner.clock;
+        let page = entry.page.as_mut().expect("PageCache::get");
+        Some(PageRef {
+            cache: self,
+            slot,
+            data: NonNull::from(&mut **page),
+            valid: entry.valid,
+        })
+    }
+
+    /// Copies `src` into the cached page of inode `inum` of device `dev` at byte offset `off`,
+    /// if the page is cached. `src` must not cross a page boundary.
+    pub fn update(&self, dev: u32, inum: u32, off: u32, src: &[u8]) {
+        let index = off / PGSIZE as u32;
+        let begin = off as usize % PGSIZE;
+        let mut guard = self.inner.lock();
+        if let Some(entry) = guard
+            .entries
+            .iter_mut()
+            .find(|e| e.is_for(dev, inum) && e.index == index && e.valid)
+        {
+            if entry.is_shared() {
+                // Processes keep the old contents of the page.
+                hal()
+                    .kmem()
+                    .free(entry.page.take().expect("PageCache::update"));
+                entry.inum = 0;
+                entry.valid = false;
+                return;
+            }
+            let page = entry.page.as_mut().expect("PageCache::update");
+            page[begin..begin + src.len()].copy_from_slice(src);
+        }
+    }
+
+    /// Discards all cached pages of inode `inum` of device `dev`.
+    pub fn invalidate(&self, dev: u32, inum: u32) {
+        let mut guard = self.inner.lock();
+        for entry in guard.entries.iter_mut().filter(|e| e.is_for(dev, inum)) {
+            entry.inum = 0;
+            entry.valid = false;
+        }
+    }
+
+    /// Returns the number of pages the cache has allocated.
+    pub fn num_pages(&self) -> usize {
+        let guard = self.inner.lock();
+        guard.entries.iter().filter(|e| e.page.is_some()).count()
+    }
+}
+
+impl PageRef<'_> {
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    /// Marks the page as filled with the file contents.
+    pub fn set_valid(&mut self) {
+        self.cache.inner.lock().entries[self.slot].valid = true;
+        self.valid = true;
+    }
+
+    /// Returns another `Page` of the page, which must be valid, to map into a process.
+    pub fn share(&self) -> Page {
+        assert!(self.valid, "PageRef::share");
+        let guard = self.cache.inner.lock();
+        let page = guard.entries[self.slot]
+            .page
+            .as_ref()
+            .expect("PageRef::share");
+        hal().kmem().share(page)
+    }
+}
+
+impl Deref for PageRef<'_> {
+    type Target = [u8; PGSIZE];
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `data` refers to a page that is not recycled while `self` is alive.
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl DerefMut for PageRef<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: `data` refers to a page that is not recycled while `self` is alive.
+        // Pages of an inode are accessed only while the inode is locked.
+        unsafe { self.data.as_mut() }
+    }
+}
+
+impl Drop for PageRef<'_> {
+    fn drop(&mut self) {
+        self.cache.inner.lock().entries[self.slot].refcnt -= 1;
+    }
+}
@@ -15,8 +15,8 @@ pub struct Stat {
     /// Number of links to file
     pub nlink: i16,
 
-    /// Padding for safetly serializing the struct
-    pub _padding: u32,
+    /// Generation number, which tells the file from later files with the same inode number
+    pub gen: u32,
 
     /// Size of file in bytes
     pub size: usize,
@@ -67,39 +67,31 @@
 //! dev, and inum.  One must hold ip->lock in order to
 //! read or write that inode's ip->valid, ip->size, ip->type, &c.
 
-use core::{iter::StepBy, mem, ops::Range, ptr};
+use core::{cmp, mem, ptr};
 
 use static_assertions::const_assert;
-use zerocopy::{AsBytes, FromBytes};
+use ufs_layout::{dirent_reclen, DIRENT_HEADER_SIZE, DIRENT_SIZE, DIRSIZ, MAXNAMELEN};
 
-use super::{FileName, Path, Ufs, IPB, NDIRECT, NINDIRECT, ROOTINO};
+use super::{
+    DInodeType, Dinode, Dirent, DirentHeader, FileName, Path, Ufs, IPB, NDIRECT, NINDIRECT, ROOTINO,
+};
 use crate::{
+    addr::UVAddr,
     arena::{Arena, ArrayArena},
     bio::BufData,
     fs::{Inode, InodeGuard, InodeType, Itable, RcInode, Tx},
     hal::hal,
     lock::SleepLock,
+    page::PGSIZE,
+    param::BSIZE,
     param::NINODE,
     param::ROOTDEV,
     proc::KernelCtx,
+    some_or,
     util::strong_pin::StrongPin,
+    watch::WatchMask,
 };
 
-/// Directory is a file containing a sequence of Dirent structures.
-pub const DIRSIZ: usize = 14;
-
-/// dirent size
-pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
-
-#[derive(Copy, Clone, PartialEq, Debug)]
-#[repr(i16)]
-pub enum DInodeType {
-    None,
-    Dir,
-    File,
-    Device,
-}
-
 pub struct InodeInner {
     /// inode has been read from disk?
     pub valid: bool,
@@ -109,109 +101,131 @@ pub struct InodeInner {
     pub size: u32,
     pub addr_direct: [u32; NDIRECT],
     pub addr_indirect: u32,
+    pub gen: u32,
 }
 
-/// On-disk inode structure
-/// Both the kernel and user programs use this header file.
-// It needs repr(C) because it's struct for in-disk representation
-// which should follow C(=machine) representation
-// https://github.com/kaist-cp/rv6/issues/52
-#[repr(C)]
-pub struct Dinode {
-    /// File type
-    pub typ: DInodeType,
-
-    /// Major device number (T_DEVICE only)
-    pub major: u16,
-
-    /// Minor device number (T_DEVICE only)
-    pub minor: u16,
-
-    /// Number of links to inode in file system
-    pub nlink: i16,
-
-    /// Size of file (bytes)
-    pub size: u32,
-
-    /// Direct data block addresses
-    pub addr_direct: [u32; NDIRECT],
-
-    /// Indirect data block address
-    pub addr_indirect: u32,
+/// Returns the type of a file as recorded in a directory entry.
+fn dirent_type(typ: InodeType) -> u8 {
+    let typ = match typ {
+        InodeType::None => DInodeType::None,
+        InodeType::Dir => DInodeType::Dir,
+        InodeType::File => DInodeType::File,
+        InodeType::Device { .. } => DInodeType::Device,
+        InodeType::Socket => DInodeType::Socket,
+    };
+    typ as u8
 }
 
-#[repr(C)]
-#[derive(Default, AsBytes, FromBytes)]
-pub struct Dirent {
-    pub inum: u16,
-    name: [u8; DIRSIZ],
+/// A directory entry read from the disk, in either of the directory formats.
+struct DirEntry {
+    inum: u16,
+    /// Length of the entry on the disk in bytes
+    reclen: u32,
+    /// Type of the file, or 0 if unknown
+    typ: u8,
+    namelen: usize,
+    name: [u8; MAXNAMELEN],
 }
 
-impl Dirent {
-    fn new(ip: &mut InodeGuard<'_, Ufs>, off: u32, ctx: &KernelCtx<'_, '_>) -> Result<Dirent, ()> {
-        let mut dirent = Dirent::default();
-        ip.read_kernel(&mut dirent, off, ctx)?;
-        Ok(dirent)
-    }
-
-    /// Fill in name. If name is shorter than DIRSIZ, NUL character is appended as
-    /// terminator.
-    ///
-    /// `name` must not contain NUL characters, but this is not a safety invariant.
-    fn set_name(&mut self, name: &FileName<{ DIRSIZ }>) {
-        let name = name.as_bytes();
-        if name.len() == DIRSIZ {
-            self.name.copy_from_slice(name);
+impl DirEntry {
+    fn new(ip: &mut InodeGuard<'_, Ufs>, off: u32, ctx: &KernelCtx<'_, '_>) -> Result<Self, ()> {
+        let mut name = [0; MAXNAMELEN];
+        if ctx.kernel().fs().long_names() {
+            let mut header = DirentHeader::default();
+            ip.read_kernel(&mut header, off, ctx)?;
+            let namelen = header.namelen as usize;
+            if (header.reclen as u32) < dirent_reclen(namelen) {
+                return Err(());
+            }
+            let bytes =
+                ip.read_bytes_kernel(&mut name[..namelen], off + DIRENT_HEADER_SIZE as u32, ctx);
+            if bytes != namelen {
+                return Err(());
+            }
+            Ok(Self {
+                inum: header.inum,
+                reclen: header.reclen as u32,
+                typ: header.typ,
+                namelen,
+                name,
+            })
         } else {
-            self.name[..name.len()].copy_from_slice(name);
-            self.name[name.len()] = 0;
+            let mut dirent = Dirent::default();
+            ip.read_kernel(&mut dirent, off, ctx)?;
+            let namelen = dirent.name.iter().position(|ch| *ch == 0).unwrap_or(DIRSIZ);
+            name[..namelen].copy_from_slice(&dirent.name[..namelen]);
+            Ok(Self {
+                inum: dirent.inum,
+                reclen: DIRENT_SIZE as u32,
+                typ: 0,
+                namelen,
+                name,
+            })
         }
     }
 
     /// Returns slice which exactly contains `name`.
     ///
     /// It contains no NUL characters.
-    fn get_name(&self) -> &FileName<{ DIRSIZ }> {
-        let len = self.name.iter().position(|ch| *ch == 0).unwrap_or(DIRSIZ);
-        // SAFETY: self.name[..len] doesn't contain '\0', and len must be <= DIRSIZ.
-        unsafe { FileName::from_bytes(&self.name[..len]) }
+    fn get_name(&self) -> &FileName<{ MAXNAMELEN }> {
+        // SAFETY: self.name[..self.namelen] doesn't contain '\0', and self.namelen must be
+        // <= MAXNAMELEN.
+        unsafe { FileName::from_bytes(&self.name[..self.namelen]) }
     }
 }
 
 struct DirentIter<'id, 's, 't> {
     guard: &'s mut InodeGuard<'t, Ufs>,
-    iter: StepBy<Range<u32>>,
+    off: u32,
     ctx: &'s KernelCtx<'id, 's>,
 }
 
 impl Iterator for DirentIter<'_, '_, '_> {
-    type Item = (Dirent, u32);
+    type Item = (DirEntry, u32);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let off = self.iter.next()?;
-        let dirent = Dirent::new(self.guard, off, self.ctx).expect("DirentIter");
+        let off = self.off;
+        if off >= self.guard.deref_inner().size {
+            return None;
+        }
+        let dirent = DirEntry::new(self.guard, off, self.ctx).expect("DirentIter");
+        self.off += dirent.reclen;
         Some((dirent, off))
     }
 }
 
 impl<'t> InodeGuard<'t, Ufs> {
     fn iter_dirents<'id, 's>(&'s mut self, ctx: &'s KernelCtx<'id, 's>) -> DirentIter<'id, 's, 't> {
-        let iter = (0..self.deref_inner().size).step_by(DIRENT_SIZE);
         DirentIter {
             guard: self,
-            iter,
+            off: 0,
             ctx,
         }
     }
 }
 
+/// Truncate `name` to DIRSIZ bytes if the file system uses the legacy directory format.
+fn dirent_name<'s>(
+    name: &'s FileName<{ MAXNAMELEN }>,
+    ctx: &KernelCtx<'_, '_>,
+) -> &'s FileName<{ MAXNAMELEN }> {
+    if ctx.kernel().fs().long_names() {
+        name
+    } else {
+        let bytes = name.as_bytes();
+        // SAFETY: `bytes` is a subslice of a FileName, which contains no NUL characters.
+        unsafe { FileName::from_bytes(&bytes[..cmp::min(DIRSIZ, bytes.len())]) }
+    }
+}
+
 // Directories
 impl InodeGuard<'_, Ufs> {
-    /// Write a new directory entry (name, inum) into the directory dp.
+    /// Write a new directory entry (name, inum) of a file of type `typ` into the directory dp.
     pub fn dirlink(
         &mut self,
-        name: &FileName<DIRSIZ>,
+        name: &FileName<MAXNAMELEN>,
         inum: u32,
+        typ: InodeType,
         tx: &Tx<'_, Ufs>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(), ()> {
@@ -221,26 +235,130 @@ impl InodeGuard<'_, Ufs> {
             return Err(());
         };
 
-        // Look for an empty Dirent.
-        let (mut de, off) = self
-            .iter_dirents(ctx)
-            .find(|(de, _)| de.inum == 0)
-            .unwrap_or((Default::default(), self.deref_inner().size));
-        de.inum = inum as _;
-        de.set_name(name);
-        self.write_kernel(&de, off, tx, ctx).expect("dirlink");
+        if !ctx.kernel().fs().long_names() {
+            // Look for an empty Dirent.
+            let off = self
+                .iter_dirents(ctx)
+                .find(|(de, _)| de.inum == 0)
+                .map(|(_, off)| off)
+                .unwrap_or(self.deref_inner().size);
+            let mut de = Dirent::default();
+            de.inum = inum as _;
+            de.set_name(name.as_bytes());
+            // Fails only if the disk is full.
+            self.write_kernel(&de, off, tx, ctx)?;
+            ctx.kernel()
+                .watches()
+                .post(self.dev, self.inum, WatchMask::CREATE, inum, ctx);
+            return Ok(());
+        }
+
+        // Look for an empty entry large enough, or an entry with enough room after its name.
+        let namelen = name.as_bytes().len();
+        let needed = dirent_reclen(namelen);
+        let slot = self.iter_dirents(ctx).find_map(|(de, off)| {
+            if de.inum == 0 && de.reclen >= needed {
+                return Some((off, de.reclen, None));
+            }
+            let used = dirent_reclen(de.namelen);
+            if de.inum != 0 && de.reclen >= used + needed {
+                return Some((off + used, de.reclen - used, Some((off, de))));
+            }
+            None
+        });
+        // Otherwise, append a new block.
+        let (off, reclen, shrunk) = slot.unwrap_or((self.deref_inner().size, BSIZE as u32, None));
+
+        // The first write fails only if the disk is full, in which case nothing has been written.
+        // The block is then writable, and the later writes do not fail.
+        let types = ctx.kernel().fs().dir_types();
+        if let Some((prev, de)) = shrunk {
+            // Record the type of an entry written before FS_DIRTYPES, now that it is rewritten.
+            let typ = if types && de.typ == 0 {
+                self.entry_type(&de, tx, ctx)
+            } else {
+                de.typ
+            };
+            let header = DirentHeader {
+                inum: de.inum,
+                reclen: dirent_reclen(de.namelen) as u16,
+                namelen: de.namelen as u8,
+                typ,
+            };
+            self.write_kernel(&header, prev, tx, ctx)?;
+        }
+        let header = DirentHeader {
+            inum: inum as _,
+            reclen: reclen as u16,
+            namelen: namelen as u8,
+            typ: if types { dirent_type(typ) } else { 0 },
+        };
+        self.write_kernel(&header, off, tx, ctx)?;
+        let bytes = self
+            .write_bytes_kernel(name.as_bytes(), off + DIRENT_HEADER_SIZE as u32, tx, ctx)
+            .expect("dirlink");
+        assert_eq!(bytes, namelen, "dirlink");
+        if off + reclen > self.deref_inner().size {
+            // Cover the padding of the last entry, so that the directory consists of whole blocks.
+            self.deref_inner_mut().size = off + reclen;
+            self.update(tx, ctx);
+        }
+        ctx.kernel()
+            .watches()
+            .post(self.dev, self.inum, WatchMask::CREATE, inum, ctx);
         Ok(())
     }
 
+    /// Returns the type of the file of the entry `de` in the directory dp.
+    fn entry_type(&self, de: &DirEntry, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) -> u8 {
+        let name = de.get_name().as_bytes();
+        // Do not lock dp itself or its parent.
+        if name == b"." || name == b".." {
+            return DInodeType::Dir as u8;
+        }
+        let ptr = ctx
+            .kernel()
+            .fs()
+            .itable()
+            .get_inode(self.dev, de.inum as u32);
+        let ip = ptr.lock(ctx);
+        let typ = ip.deref_inner().typ;
+        ip.free(ctx);
+        ptr.free((tx, ctx));
+        dirent_type(typ)
+    }
+
+    /// Remove the directory entry at byte offset `off` from the directory dp.
+    /// Returns Err(()) if the disk is full, which happens only if the directory block is
+    /// referenced by the snapshot and must be copied.
+    pub fn dirunlink(
+        &mut self,
+        off: u32,
+        tx: &Tx<'_, Ufs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        if ctx.kernel().fs().long_names() {
+            // Keep the record length, so that the entry can be reused later.
+            let mut header = DirentHeader::default();
+            self.read_kernel(&mut header, off, ctx)
+                .expect("dirunlink: read_kernel");
+            header.inum = 0;
+            self.write_kernel(&header, off, tx, ctx)
+        } else {
+            self.write_kernel(&Dirent::default(), off, tx, ctx)
+        }
+    }
+
     /// Look for a directory entry in a directory.
     /// If found, return the entry and byte offset of entry.
     pub fn dirlookup(
         &mut self,
-        name: &FileName<DIRSIZ>,
+        name: &FileName<MAXNAMELEN>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(RcInode<Ufs>, u32), ()> {
         assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
 
+        let name = dirent_name(name, ctx);
         self.iter_dirents(ctx)
             .find(|(de, _)| de.inum != 0 && de.get_name() == name)
             .map(|(de, off)| {
@@ -254,6 +372,79 @@ impl InodeGuard<'_, Ufs> {
             })
             .ok_or(())
     }
+
+    /// Look for the entry of the inode `inum` in the directory dp, other than "." and "..",
+    /// and copy its name to the end of `buf`.
+    /// Returns Ok(length of the name) on success, Err(()) if there is no such entry or the name
+    /// does not fit in `buf`.
+    pub fn dirname(
+        &mut self,
+        inum: u32,
+        buf: &mut [u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirname not DIR");
+
+        let (de, _) = self
+            .iter_dirents(ctx)
+            .find(|(de, _)| {
+                let name = de.get_name().as_bytes();
+                de.inum as u32 == inum && name != b"." && name != b".."
+            })
+            .ok_or(())?;
+        let name = de.get_name().as_bytes();
+        let start = buf.len().checked_sub(name.len()).ok_or(())?;
+        buf[start..].copy_from_slice(name);
+        Ok(name.len())
+    }
+
+    /// Copy the directory entries of the directory dp, starting from the entry at byte
+    /// offset `*off`, into virtual address `dst` of the current process by at most `n` bytes.
+    /// Entries are copied in the long name format regardless of the format on the disk,
+    /// skipping empty ones, and `*off` is advanced past the copied entries. The type of an
+    /// entry is 0 if the directory does not record it.
+    /// Returns Ok(number of bytes copied) on success, Err(()) on error.
+    pub fn getdents(
+        &mut self,
+        off: &mut u32,
+        dst: UVAddr,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        if self.deref_inner().typ != InodeType::Dir {
+            return Err(());
+        }
+
+        let mut tot: u32 = 0;
+        while *off < self.deref_inner().size {
+            let de = DirEntry::new(self, *off, ctx)?;
+            if de.inum != 0 {
+                let reclen = dirent_reclen(de.namelen);
+                if tot + reclen > n {
+                    if tot == 0 {
+                        // The buffer cannot hold even a single entry.
+                        return Err(());
+                    }
+                    break;
+                }
+                let header = DirentHeader {
+                    inum: de.inum,
+                    reclen: reclen as u16,
+                    namelen: de.namelen as u8,
+                    typ: de.typ,
+                };
+                let memory = ctx.proc_mut().memory_mut();
+                memory.copy_out(dst + tot as usize, &header)?;
+                memory.copy_out_bytes(
+                    dst + tot as usize + DIRENT_HEADER_SIZE,
+                    de.get_name().as_bytes(),
+                )?;
+                tot += reclen;
+            }
+            *off += de.reclen;
+        }
+        Ok(tot as usize)
+    }
 }
 
 impl InodeGuard<'_, Ufs> {
@@ -298,6 +489,11 @@ impl InodeGuard<'_, Ufs> {
                 dip.major = 0;
                 dip.minor = 0;
             }
+            InodeType::Socket => {
+                dip.typ = DInodeType::Socket;
+                dip.major = 0;
+                dip.minor = 0;
+            }
         }
 
         (*dip).nlink = inner.nlink;
@@ -315,12 +511,56 @@ impl InodeGuard<'_, Ufs> {
     /// listed in block self->addr_indirect.
     /// Return the disk block address of the nth block in inode self.
     /// If there is no such block, bmap allocates one.
-    pub fn bmap_or_alloc(&mut self, bn: usize, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) -> u32 {
-        self.bmap_internal(bn, Some(tx), ctx)
+    /// If the block is referenced by the snapshot, it is copied to a new block
+    /// first, so the caller may freely modify the returned block.
+    /// Returns Err(()) if the disk is full.
+    pub fn bmap_or_alloc(
+        &mut self,
+        bn: usize,
+        tx: &Tx<'_, Ufs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<u32, ()> {
+        let addr = self.bmap_internal(bn, Some(tx), ctx)?;
+        if !tx.fs.in_snapshot(self.dev, addr, ctx) {
+            return Ok(addr);
+        }
+
+        let new = tx.balloc(self.dev, self.inum, ctx)?;
+        tx.copy_block(self.dev, addr, new, ctx);
+        if self.set_block(bn, new, tx, ctx).is_err() {
+            tx.bfree(self.dev, new, ctx);
+            return Err(());
+        }
+        tx.bfree(self.dev, addr, ctx);
+        Ok(new)
     }
 
+    /// Return the disk block address of the nth block in inode self, or 0 if the block is in a
+    /// hole of the file.
     pub fn bmap(&mut self, bn: usize, ctx: &KernelCtx<'_, '_>) -> u32 {
-        self.bmap_internal(bn, None, ctx)
+        // Without a transaction, nothing is allocated, so it never fails.
+        self.bmap_internal(bn, None, ctx).unwrap_or(0)
+    }
+
+    /// Fill `page` with the `index`th page of the file contents.
+    /// Bytes past the end of the file are zeroed.
+    pub fn fill_page(&mut self, index: usize, page: &mut [u8; PGSIZE], ctx: &KernelCtx<'_, '_>) {
+        let size = self.deref_inner().size as usize;
+        for (i, data) in page.chunks_mut(BSIZE).enumerate() {
+            let bn = index * (PGSIZE / BSIZE) + i;
+            let addr = if bn * BSIZE < size {
+                self.bmap(bn, ctx)
+            } else {
+                0
+            };
+            if addr == 0 {
+                data.fill(0);
+                continue;
+            }
+            let bp = hal().disk().read(self.dev, addr, ctx);
+            data.copy_from_slice(&bp.deref_inner().data);
+            bp.free(ctx);
+        }
     }
 
     fn bmap_internal(
@@ -328,53 +568,202 @@ impl InodeGuard<'_, Ufs> {
         bn: usize,
         tx_opt: Option<&Tx<'_, Ufs>>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> u32 {
+    ) -> Result<u32, ()> {
         let inner = self.deref_inner();
 
+        // Without a transaction, a missing block is a hole, and is not allocated.
         if bn < NDIRECT {
             let mut addr = inner.addr_direct[bn];
             if addr == 0 {
-                addr = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
+                addr = some_or!(tx_opt, return Ok(0)).balloc(self.dev, self.inum, ctx)?;
                 self.deref_inner_mut().addr_direct[bn] = addr;
             }
-            addr
+            Ok(addr)
         } else {
             let bn = bn - NDIRECT;
             assert!(bn < NINDIRECT, "bmap: out of range");
 
             let mut indirect = inner.addr_indirect;
             if indirect == 0 {
-                indirect = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
+                indirect = some_or!(tx_opt, return Ok(0)).balloc(self.dev, self.inum, ctx)?;
                 self.deref_inner_mut().addr_indirect = indirect;
+            } else if let Some(tx) = tx_opt {
+                indirect = self.cow_indirect(tx, ctx)?;
             }
 
             let mut bp = hal().disk().read(self.dev, indirect, ctx);
             let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
             debug_assert_eq!(prefix.len(), 0, "bmap: Buf data unaligned");
             let mut addr = data[bn];
-            if addr == 0 {
-                let tx = tx_opt.expect("bmap: out of range");
-                addr = tx.balloc(self.dev, ctx);
-                data[bn] = addr;
-                tx.write(bp, ctx);
-            } else {
-                bp.free(ctx);
+            match tx_opt {
+                Some(tx) if addr == 0 => {
+                    match tx.balloc(self.dev, self.inum, ctx) {
+                        Ok(new) => {
+                            addr = new;
+                            data[bn] = addr;
+                            tx.write(bp, ctx);
+                        }
+                        Err(()) => {
+                            bp.free(ctx);
+                            return Err(());
+                        }
+                    }
+                }
+                _ => bp.free(ctx),
             }
-            addr
+            Ok(addr)
         }
     }
 
-    /// Is the directory dp empty except for "." and ".." ?
-    pub fn is_dir_empty(&mut self, ctx: &KernelCtx<'_, '_>) -> bool {
-        let mut de: Dirent = Default::default();
-        for off in (2 * DIRENT_SIZE as u32..self.deref_inner().size).step_by(DIRENT_SIZE) {
-            self.read_kernel(&mut de, off, ctx)
-                .expect("is_dir_empty: read_kernel");
-            if de.inum != 0 {
-                return false;
+    /// Copy the indirect block to a new block if it is referenced by the snapshot,
+    /// so that it can be modified.
+    /// Returns Ok(disk block address of the indirect block) on success, Err(()) if the disk is
+    /// full.
+    fn cow_indirect(&mut self, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) -> Result<u32, ()> {
+        let indirect = self.deref_inner().addr_indirect;
+        if !tx.fs.in_snapshot(self.dev, indirect, ctx) {
+            return Ok(indirect);
+        }
+
+        let new = tx.balloc(self.dev, self.inum, ctx)?;
+        tx.copy_block(self.dev, indirect, new, ctx);
+        self.deref_inner_mut().addr_indirect = new;
+        self.update(tx, ctx);
+        tx.bfree(self.dev, indirect, ctx);
+        Ok(new)
+    }
+
+    /// Make the `bn`th block of the inode refer to the disk block `addr`.
+    /// Returns Ok(()) on success, Err(()) if the disk is full.
+    fn set_block(
+        &mut self,
+        bn: usize,
+        addr: u32,
+        tx: &Tx<'_, Ufs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        if bn < NDIRECT {
+            self.deref_inner_mut().addr_direct[bn] = addr;
+            self.update(tx, ctx);
+        } else {
+            let indirect = self.cow_indirect(tx, ctx)?;
+            let mut bp = hal().disk().read(self.dev, indirect, ctx);
+            // SAFETY: u32 does not have internal structure.
+            let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
+            debug_assert_eq!(prefix.len(), 0, "set_block: Buf data unaligned");
+            data[bn - NDIRECT] = addr;
+            tx.write(bp, ctx);
+        }
+        Ok(())
+    }
+
+    /// Move the `bn`th block of the inode to the free disk block `to`, and free the old one.
+    /// Everything happens inside `tx`, so a crash never leaves the inode pointing to
+    /// a half-copied block.
+    /// Returns Ok(()) on success, Err(()) if `bn` is out of range, `to` is in use, or the disk is
+    /// full.
+    pub fn relocate(
+        &mut self,
+        bn: usize,
+        to: u32,
+        tx: &Tx<'_, Ufs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        if bn * BSIZE >= self.deref_inner().size as usize {
+            return Err(());
+        }
+        let from = self.bmap(bn, ctx);
+        if from == 0 {
+            return Err(());
+        }
+        if from == to {
+            return Ok(());
+        }
+        tx.balloc_at(self.dev, to, ctx)?;
+        tx.copy_block(self.dev, from, to, ctx);
+        if self.set_block(bn, to, tx, ctx).is_err() {
+            tx.bfree(self.dev, to, ctx);
+            return Err(());
+        }
+        tx.bfree(self.dev, from, ctx);
+        Ok(())
+    }
+
+    /// Free the blocks of the inode from the `from`th block on, and the indirect block if it
+    /// no longer lists any block.
+    /// Returns Err(()) if the disk is full, which happens only if the indirect block is referenced
+    /// by the snapshot and must be copied.
+    pub fn free_blocks(
+        &mut self,
+        from: usize,
+        tx: &Tx<'_, Ufs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let dev = self.dev;
+        for addr in self.deref_inner_mut().addr_direct.iter_mut().skip(from) {
+            if *addr != 0 {
+                tx.bfree(dev, *addr, ctx);
+                *addr = 0;
             }
         }
-        true
+
+        let indirect = self.deref_inner().addr_indirect;
+        if indirect == 0 {
+            return Ok(());
+        }
+        if from <= NDIRECT {
+            let mut bp = hal().disk().read(dev, indirect, ctx);
+            // SAFETY: u32 does not have internal structure.
+            let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
+            debug_assert_eq!(prefix.len(), 0, "free_blocks: Buf data unaligned");
+            for a in data {
+                if *a != 0 {
+                    tx.bfree(dev, *a, ctx);
+                }
+            }
+            bp.free(ctx);
+            tx.bfree(dev, indirect, ctx);
+            self.deref_inner_mut().addr_indirect = 0
+        } else {
+            let indirect = self.cow_indirect(tx, ctx)?;
+            let mut bp = hal().disk().read(dev, indirect, ctx);
+            // SAFETY: u32 does not have internal structure.
+            let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
+            debug_assert_eq!(prefix.len(), 0, "free_blocks: Buf data unaligned");
+            for a in data.iter_mut().skip(from - NDIRECT) {
+                if *a != 0 {
+                    tx.bfree(dev, *a, ctx);
+                    *a = 0;
+                }
+            }
+            tx.write(bp, ctx);
+        }
+        Ok(())
+    }
+
+    /// Zero the bytes of the inode from offset `off` to the end of the block that holds it.
+    /// Returns Err(()) if the disk is full, which happens only if the block is referenced by the
+    /// snapshot and must be copied.
+    pub fn zero_tail(
+        &mut self,
+        off: usize,
+        tx: &Tx<'_, Ufs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let bn = off / BSIZE;
+        if off % BSIZE == 0 || self.bmap(bn, ctx) == 0 {
+            return Ok(());
+        }
+        let addr = self.bmap_or_alloc(bn, tx, ctx)?;
+        let mut bp = hal().disk().read(self.dev, addr, ctx);
+        bp.deref_inner_mut().data[off % BSIZE..].fill(0);
+        tx.write(bp, ctx);
+        Ok(())
+    }
+
+    /// Is the directory dp empty except for "." and ".." ?
+    pub fn is_dir_empty(&mut self, ctx: &KernelCtx<'_, '_>) -> bool {
+        self.iter_dirents(ctx).skip(2).all(|(de, _)| de.inum == 0)
     }
 }
 
@@ -398,6 +787,7 @@ impl Inode<Ufs> {
                     size: 0,
                     addr_direct: [0; NDIRECT],
                     addr_indirect: 0,
+                    gen: 0,
                 },
             ),
         }
@@ -424,17 +814,19 @@ impl Itable<Ufs> {
         .expect("[Itable::get_inode] no inodes")
     }
 
-    /// Allocate an inode on device dev.
+    /// Allocate an inode on device dev, in the group of the directory `parent` if possible.
     /// Mark it as allocated by giving it type.
-    /// Returns an unlocked but allocated and referenced inode.
+    /// Returns Ok(unlocked but allocated and referenced inode) on success, Err(()) if there are
+    /// no free inodes on the disk.
     pub fn alloc_inode(
         self: StrongPin<'_, Self>,
         dev: u32,
+        parent: u32,
         typ: InodeType,
         tx: &Tx<'_, Ufs>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> RcInode<Ufs> {
-        for inum in 1..tx.fs.superblock().ninodes {
+    ) -> Result<RcInode<Ufs>, ()> {
+        for inum in tx.fs.superblock().inode_order(parent) {
             let mut bp = hal().disk().read(dev, tx.fs.superblock().iblock(inum), ctx);
 
             const_assert!(IPB <= mem::size_of::<BufData>() / mem::size_of::<Dinode>());
@@ -452,7 +844,10 @@ impl Itable<Ufs> {
 
             // a free inode
             if dip.typ == DInodeType::None {
+                // A recycled inode number gets a new generation.
+                let gen = dip.gen.wrapping_add(1);
                 unsafe { ptr::write_bytes(dip as _, 0, 1) };
+                dip.gen = gen;
                 match typ {
                     InodeType::None => dip.typ = DInodeType::None,
                     InodeType::Dir => dip.typ = DInodeType::Dir,
@@ -462,16 +857,17 @@ impl Itable<Ufs> {
                         dip.major = major;
                         dip.minor = minor
                     }
+                    InodeType::Socket => dip.typ = DInodeType::Socket,
                 }
 
                 // mark it allocated on the disk
                 tx.write(bp, ctx);
-                return self.get_inode(dev, inum);
+                return Ok(self.get_inode(dev, inum));
             } else {
                 bp.free(ctx);
             }
         }
-        panic!("[Itable::alloc_inode] no inodes");
+        Err(())
     }
 
     pub fn root(self: StrongPin<'_, Self>) -> RcInode<Ufs> {
@@ -481,19 +877,21 @@ impl Itable<Ufs> {
     pub fn namei(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Ufs>>,
         tx: &Tx<'_, Ufs>,
         proc: &KernelCtx<'_, '_>,
     ) -> Result<RcInode<Ufs>, ()> {
-        Ok(self.namex(path, false, tx, proc)?.0)
+        Ok(self.namex(path, dir, false, tx, proc)?.0)
     }
 
     pub fn nameiparent<'s>(
         self: StrongPin<'_, Self>,
         path: &'s Path,
+        dir: Option<&RcInode<Ufs>>,
         tx: &Tx<'_, Ufs>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Ufs>, &'s FileName<{ DIRSIZ }>), ()> {
-        let (ip, name_in_path) = self.namex(path, true, tx, ctx)?;
+    ) -> Result<(RcInode<Ufs>, &'s FileName<{ MAXNAMELEN }>), ()> {
+        let (ip, name_in_path) = self.namex(path, dir, true, tx, ctx)?;
         let name_in_path = name_in_path.ok_or(())?;
         Ok((ip, name_in_path))
     }
@@ -501,12 +899,15 @@ impl Itable<Ufs> {
     fn namex<'s>(
         self: StrongPin<'_, Self>,
         mut path: &'s Path,
+        dir: Option<&RcInode<Ufs>>,
         parent: bool,
         tx: &Tx<'_, Ufs>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Ufs>, Option<&'s FileName<{ DIRSIZ }>>), ()> {
+    ) -> Result<(RcInode<Ufs>, Option<&'s FileName<{ MAXNAMELEN }>>), ()> {
         let mut ptr = if path.is_absolute() {
             self.root()
+        } else if let Some(dir) = dir {
+            dir.clone()
         } else {
             ctx.proc().cwd().clone()
         };
@@ -20,6 +20,11 @@
 //!   block C
 //!   ...
 //! Log appends are synchronous.
+//!
+//! A disk that caches writes may write them back in any order, so the
+//! log flushes the disk cache between the steps of a commit, and writes
+//! the header of a transaction with FUA. A disk without a cache makes
+//! its writes durable in order, and the flushes do nothing.
 use core::mem;
 
 use arrayvec::ArrayVec;
@@ -28,6 +33,7 @@ use static_assertions::const_assert;
 
 use crate::{
     bio::{Buf, BufData, BufUnlocked},
+    console::Severity,
     hal::hal,
     lock::SleepableLock,
     param::{BSIZE, LOGSIZE, MAXOPBLOCKS},
@@ -56,7 +62,10 @@ struct LogHeader {
 }
 
 impl Log {
-    pub fn new(dev: u32, start: i32, size: i32, ctx: &KernelCtx<'_, '_>) -> Self {
+    /// Returns the log of `dev`, which starts at the block `start` and has `size` blocks.
+    /// Committed blocks left in the log are copied to their home locations if `replay`, and
+    /// are ignored otherwise, so that nothing is written to the disk.
+    pub fn new(dev: u32, start: i32, size: i32, replay: bool, ctx: &KernelCtx<'_, '_>) -> Self {
         let mut log = Self {
             dev,
             start,
@@ -65,7 +74,18 @@ impl Log {
             committing: false,
             bufs: ArrayVec::new(),
         };
-        log.recover_from_log(ctx);
+        if replay {
+            log.recover_from_log(ctx);
+        } else {
+            log.read_head(ctx);
+            if !log.bufs.is_empty() {
+                ctx.kernel().as_ref().log(
+                    Severity::Warning,
+                    format_args!("log: ignoring {} committed blocks\n", log.bufs.len()),
+                );
+                log.bufs.clear();
+            }
+        }
         log
     }
 
@@ -118,7 +138,8 @@ impl Log {
 
     /// Write in-memory log header to disk.
     /// This is the true point at which the
-    /// current transaction commits.
+    /// current transaction commits, so a header
+    /// with blocks is durable when this returns.
     fn write_head(&mut self, ctx: &KernelCtx<'_, '_>) {
         let mut buf = hal().disk().read(self.dev, self.start as u32, ctx);
 
@@ -135,7 +156,11 @@ impl Log {
         for (db, b) in izip!(&mut lh.block, &self.bufs) {
             *db = b.blockno;
         }
-        hal().disk().write(&mut buf, ctx);
+        if self.bufs.is_empty() {
+            hal().disk().write(&mut buf, ctx);
+        } else {
+            hal().disk().write_fua(&mut buf, ctx);
+        }
         buf.free(ctx);
     }
 
@@ -144,6 +169,7 @@ impl Log {
 
         // If committed, copy from log to disk.
         self.install_trans(ctx);
+        hal().disk().flush(ctx);
 
         // Clear the log.
         self.write_head(ctx);
@@ -177,12 +203,18 @@ impl Log {
             // Write modified blocks from cache to self.
             self.write_log(ctx);
 
+            // The log must be durable before the header is.
+            hal().disk().flush(ctx);
+
             // Write header to disk -- the real commit.
             self.write_head(ctx);
 
             // Now install writes to home locations.
             self.install_trans(ctx);
 
+            // The home locations must be durable before the header is erased.
+            hal().disk().flush(ctx);
+
             // Erase the transaction from the self.
             self.write_head(ctx);
         };
@@ -216,17 +248,15 @@ impl SleepableLock<Log> {
     /// Called at the start of each FS system call.
     pub fn begin_op(&self, ctx: &KernelCtx<'_, '_>) {
         let mut guard = self.lock();
-        loop {
-            if guard.committing ||
-            // This op might exhaust log space; wait for commit.
-            guard.bufs.len() as i32 + (guard.outstanding + 1) * MAXOPBLOCKS as i32 > LOGSIZE as i32
-            {
-                guard.sleep(ctx);
-            } else {
-                guard.outstanding += 1;
-                break;
-            }
-        }
+        guard.wait_while(
+            |log| {
+                log.committing ||
+                // This op might exhaust log space; wait for commit.
+                log.bufs.len() as i32 + (log.outstanding + 1) * MAXOPBLOCKS as i32 > LOGSIZE as i32
+            },
+            ctx,
+        );
+        guard.outstanding += 1;
     }
 
     /// Called at the end of each FS system call.
@@ -254,4 +284,17 @@ impl SleepableLock<Log> {
         // the amount of reserved space.
         guard.wakeup(ctx.kernel());
     }
+
+    /// Runs `f` while no FS system call is executing and every committed block
+    /// has been installed to its home location.
+    /// New FS system calls wait until `f` returns.
+    pub fn freeze<F: FnOnce()>(&self, f: F, ctx: &KernelCtx<'_, '_>) {
+        let mut guard = self.lock();
+        guard.wait_while(|log| log.committing || log.outstanding > 0, ctx);
+        // Committing is true, so new transactions cannot start even after releasing the lock.
+        guard.committing = true;
+        guard.reacquire_after(f);
+        guard.committing = false;
+        guard.wakeup(ctx.kernel());
+    }
 }
@@ -9,42 +9,44 @@
 //! routines.  The (higher-level) system call implementations
 //! are in sysfile.c.
 //!
-//! On-disk file system format used for both kernel and user programs are also included here.
+//! On-disk file system format used for both kernel and mkfs is in the ufs-layout crate.
 
-use core::cell::UnsafeCell;
 use core::ops::Deref;
-use core::{cmp, mem};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::{mem, ptr};
 
 use pin_project::pin_project;
 use spin::Once;
 
 use self::log::Log;
 use super::{
-    FcntlFlags, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, Path, RcInode, Stat, Tx,
+    FcntlFlags, FileName, FileSystem, FileSystemExt, Inode, InodeGuard, InodeType, Itable, Path,
+    RcInode, Stat, Tx,
 };
 use crate::util::strong_pin::StrongPin;
 use crate::{
+    addr::UVAddr,
+    arena::Reaper,
     bio::Buf,
     file::{FileType, InodeFileType},
     hal::hal,
-    lock::SleepableLock,
-    param::BSIZE,
+    lock::{SleepLock, SleepableLock},
+    ok_or,
+    page::{Page, PGSIZE},
+    param::{BSIZE, MAXPATH, NINODE, ROOTDEV},
     proc::KernelCtx,
+    watch::WatchMask,
 };
 
 mod inode;
 mod log;
-mod superblock;
 
-pub use inode::{DInodeType, Dinode, Dirent, InodeInner, DIRENT_SIZE, DIRSIZ};
-pub use superblock::{Superblock, BPB, IPB};
-
-/// root i-number
-const ROOTINO: u32 = 1;
-
-const NDIRECT: usize = 12;
-const NINDIRECT: usize = BSIZE.wrapping_div(mem::size_of::<u32>());
-const MAXFILE: usize = NDIRECT.wrapping_add(NINDIRECT);
+pub use inode::InodeInner;
+pub use ufs_layout::{
+    DInodeType, Dinode, Dirent, DirentHeader, Superblock, BPB, DIRENT_HEADER_SIZE, DIRENT_SIZE,
+    DIRSIZ, FS_DIRTYPES, FS_LONGNAMES, IPB, MAXNAMELEN,
+};
+use ufs_layout::{FS_CLEAN, MAXFILE, NDIRECT, NINDIRECT, ROOTINO, SNAP_VALID};
 
 #[pin_project]
 pub struct Ufs {
@@ -52,8 +54,22 @@ pub struct Ufs {
     /// There should be one superblock per disk device, but we run with only one device.
     superblock: Once<Superblock>,
     log: Once<SleepableLock<Log>>,
+
+    /// Is the file system mounted read-only? Then nothing is written to the disk, and the
+    /// operations that would write fail.
+    read_only: AtomicBool,
+
+    /// Do the snapshot blocks hold a complete snapshot? Only changes while the log is frozen.
+    snapshot_valid: AtomicBool,
+
+    /// Held while the snapshot blocks are read or rewritten.
+    snapshot_lock: SleepLock<()>,
+
     #[pin]
     itable: Itable<Self>,
+
+    /// Inodes whose last reference was dropped outside a transaction
+    reaper: Reaper<Itable<Self>, NINODE>,
 }
 
 impl Ufs {
@@ -61,7 +77,11 @@ impl Ufs {
         Self {
             superblock: Once::new(),
             log: Once::new(),
+            read_only: AtomicBool::new(false),
+            snapshot_valid: AtomicBool::new(false),
+            snapshot_lock: SleepLock::new("SNAPSHOT", ()),
             itable: Itable::new_itable(),
+            reaper: Reaper::new("IREAPER"),
         }
     }
 
@@ -73,10 +93,72 @@ impl Ufs {
         self.superblock.get().expect("superblock")
     }
 
+    /// Is the file system mounted read-only?
+    pub fn read_only(&self) -> bool {
+        self.read_only.load(Ordering::Acquire)
+    }
+
+    /// Does the file system use the long name directory format?
+    fn long_names(&self) -> bool {
+        self.superblock().incompat & FS_LONGNAMES != 0
+    }
+
+    /// Do directory entries record the type of their file?
+    fn dir_types(&self) -> bool {
+        self.long_names() && self.superblock().incompat & FS_DIRTYPES != 0
+    }
+
     #[allow(clippy::needless_lifetimes)]
-    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+    pub fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
         unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
     }
+
+    /// Drop `ip` without a transaction. If it is the last reference, its finalization, which
+    /// needs a transaction, is deferred to the next `reap`.
+    pub fn defer_free(&self, ip: RcInode<Self>, ctx: &KernelCtx<'_, '_>) {
+        if let Err(ip) = self.reaper.defer(ip) {
+            // The reaper is full, so finalize it now.
+            let tx = self.begin_tx(ctx);
+            ip.free((&tx, ctx));
+            tx.end(ctx);
+        }
+    }
+
+    /// Finalize the inodes deferred by `defer_free`, each in its own transaction.
+    pub fn reap(&self, ctx: &KernelCtx<'_, '_>) {
+        while let Some(ip) = self.reaper.pop() {
+            let tx = self.begin_tx(ctx);
+            ip.free((&tx, ctx));
+            tx.end(ctx);
+        }
+    }
+
+    /// Allocate an unnamed file on the device of the directory at `path`.
+    /// The file has no links, so it is freed when its last reference is dropped,
+    /// unless it is linked into a directory by then.
+    fn create_unnamed(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        dir: Option<&RcInode<Self>>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, ()> {
+        let ptr = self.itable().namei(path, dir, tx, ctx)?;
+        let dp = ptr.lock(ctx);
+        let (dev, inum, typ) = (dp.dev, dp.inum, dp.deref_inner().typ);
+        dp.free(ctx);
+        ptr.free((tx, ctx));
+        if typ != InodeType::Dir {
+            return Err(());
+        }
+
+        let ptr = self
+            .itable()
+            .alloc_inode(dev, inum, InodeType::File, tx, ctx)?;
+        // Read the inode from the disk, so that it is freed when the last reference is dropped.
+        ptr.lock(ctx).free(ctx);
+        Ok(ptr)
+    }
 }
 
 impl Tx<'_, Ufs> {
@@ -89,6 +171,7 @@ impl Tx<'_, Ufs> {
     ///   modify bp->data[]
     ///   write(bp)
     fn write(&self, b: Buf, ctx: &KernelCtx<'_, '_>) {
+        assert!(!self.fs.read_only(), "write: read-only file system");
         self.fs.log().lock().write(b, ctx);
     }
 
@@ -101,30 +184,37 @@ impl Tx<'_, Ufs> {
     }
 
     /// Blocks.
-    /// Allocate a zeroed disk block.
-    fn balloc(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
-        for b in num_iter::range_step(0, self.fs.superblock().size, BPB as u32) {
-            let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
-            for bi in 0..cmp::min(BPB as u32, self.fs.superblock().size - b) {
+    /// Allocate a zeroed disk block for the inode `inum`, in its group if possible.
+    /// Returns Ok(block number) on success, Err(()) if the disk is full or read-only.
+    fn balloc(&self, dev: u32, inum: u32, ctx: &KernelCtx<'_, '_>) -> Result<u32, ()> {
+        if self.fs.read_only() {
+            return Err(());
+        }
+        let sb = *self.fs.superblock();
+        for (b, n) in sb.bmap_ranges(sb.inode_group(inum)) {
+            let mut bp = hal().disk().read(dev, sb.bblock(b), ctx);
+            for bi in 0..n {
                 let m = 1 << (bi % 8);
-                if bp.deref_inner_mut().data[(bi / 8) as usize] & m == 0 {
+                if bp.deref_inner_mut().data[(bi / 8) as usize] & m == 0
+                    && !self.fs.in_snapshot(dev, b + bi, ctx)
+                {
                     // Is block free?
                     bp.deref_inner_mut().data[(bi / 8) as usize] |= m; // Mark block in use.
                     self.write(bp, ctx);
                     self.bzero(dev, b + bi, ctx);
-                    return b + bi;
+                    return Ok(b + bi);
                 }
             }
             bp.free(ctx);
         }
-
-        panic!("balloc: out of blocks");
+        Err(())
     }
 
     /// Free a disk block.
+    /// If the block is referenced by the snapshot, it stays in use until the snapshot is dropped.
     fn bfree(&self, dev: u32, b: u32, ctx: &KernelCtx<'_, '_>) {
         let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
-        let bi = b as usize % BPB;
+        let bi = self.fs.superblock().bbit(b);
         let m = 1u8 << (bi % 8);
         assert_ne!(
             bp.deref_inner_mut().data[bi / 8] & m,
@@ -134,6 +224,278 @@ impl Tx<'_, Ufs> {
         bp.deref_inner_mut().data[bi / 8] &= !m;
         self.write(bp, ctx);
     }
+
+    /// Mark the given disk block in use without zeroing it.
+    /// Returns Ok(()) on success, Err(()) if the block is already in use.
+    fn balloc_at(&self, dev: u32, b: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
+        let bi = self.fs.superblock().bbit(b);
+        let m = 1u8 << (bi % 8);
+        if bp.deref_inner_mut().data[bi / 8] & m != 0 || self.fs.in_snapshot(dev, b, ctx) {
+            bp.free(ctx);
+            return Err(());
+        }
+        bp.deref_inner_mut().data[bi / 8] |= m;
+        self.write(bp, ctx);
+        Ok(())
+    }
+
+    /// Copy the content of disk block `from` to disk block `to`.
+    fn copy_block(&self, dev: u32, from: u32, to: u32, ctx: &KernelCtx<'_, '_>) {
+        let src = hal().disk().read(dev, from, ctx);
+        let mut dst = ctx.kernel().bcache().get_buf(dev, to).lock(ctx);
+        dst.deref_inner_mut()
+            .data
+            .copy_from_slice(&src.deref_inner().data[..]);
+        dst.deref_inner_mut().valid = true;
+        src.free(ctx);
+        self.write(dst, ctx);
+    }
+}
+
+impl Ufs {
+    /// Find `len` consecutive free disk blocks, in the group of the inode `inum` if possible,
+    /// and return the first of them.
+    /// The blocks are not marked in use, so the caller must claim each of them
+    /// with `Tx::balloc_at` and be prepared for them to be taken in the meantime.
+    fn find_free_run(&self, dev: u32, inum: u32, len: u32, ctx: &KernelCtx<'_, '_>) -> Option<u32> {
+        let sb = *self.superblock();
+        let mut run = 0;
+        let mut end = 0;
+        for (b, n) in sb.bmap_ranges(sb.inode_group(inum)) {
+            // A run continues into the next range only if the ranges are adjacent.
+            if b != end {
+                run = 0;
+            }
+            end = b + n;
+            let bp = hal().disk().read(dev, sb.bblock(b), ctx);
+            for bi in 0..n {
+                let m = 1 << (bi % 8);
+                if bp.deref_inner().data[(bi / 8) as usize] & m == 0
+                    && !self.in_snapshot(dev, b + bi, ctx)
+                {
+                    run += 1;
+                    if run == len {
+                        bp.free(ctx);
+                        return Some(b + bi + 1 - len);
+                    }
+                } else {
+                    run = 0;
+                }
+            }
+            bp.free(ctx);
+        }
+        None
+    }
+
+    /// Is disk block b referenced by the snapshot?
+    fn in_snapshot(&self, dev: u32, b: u32, ctx: &KernelCtx<'_, '_>) -> bool {
+        let sb = self.superblock();
+        if !self.snapshot_valid.load(Ordering::Acquire) {
+            return false;
+        }
+        let bp = hal().disk().read(dev, sb.snap_bblock(b), ctx);
+        let bi = sb.bbit(b);
+        let m = 1u8 << (bi % 8);
+        let res = bp.deref_inner().data[bi / 8] & m != 0;
+        bp.free(ctx);
+        res
+    }
+
+    /// Write the super block of `dev` with the state `state` (FS_CLEAN or 0), bypassing the log.
+    /// The state is durable, along with the writes before it, when this returns.
+    fn write_state(&self, dev: u32, state: u32, ctx: &KernelCtx<'_, '_>) {
+        let snapshot = if self.snapshot_valid.load(Ordering::Acquire) {
+            SNAP_VALID
+        } else {
+            0
+        };
+        let sb = Superblock {
+            state,
+            snapshot,
+            ..*self.superblock()
+        };
+        let mut buf = hal().disk().read(dev, 1, ctx);
+        sb.encode(&mut buf.deref_inner_mut().data[..]);
+        hal().disk().flush(ctx);
+        hal().disk().write_fua(&mut buf, ctx);
+        buf.free(ctx);
+    }
+
+    /// Mark the snapshot blocks as holding a complete snapshot or not in the super block.
+    /// The mark is durable, along with the writes before it, when this returns.
+    /// Must be called while the log is frozen.
+    fn set_snapshot_valid(&self, valid: bool, ctx: &KernelCtx<'_, '_>) {
+        self.snapshot_valid.store(valid, Ordering::Release);
+        // The log is frozen, so the file system is mounted and its state is 0.
+        self.write_state(ROOTDEV, 0, ctx);
+    }
+
+    /// Write `n` blocks starting from `from` (or zeroes if `from` is None) to the blocks
+    /// starting from `to`, bypassing the log.
+    /// Must be called while the log is frozen.
+    fn copy_blocks_direct(&self, from: Option<u32>, to: u32, n: u32, ctx: &KernelCtx<'_, '_>) {
+        for i in 0..n {
+            let mut dst = ctx.kernel().bcache().get_buf(ROOTDEV, to + i).lock(ctx);
+            if let Some(from) = from {
+                let src = hal().disk().read(ROOTDEV, from + i, ctx);
+                dst.deref_inner_mut()
+                    .data
+                    .copy_from_slice(&src.deref_inner().data[..]);
+                src.free(ctx);
+            } else {
+                dst.deref_inner_mut().data.fill(0);
+            }
+            dst.deref_inner_mut().valid = true;
+            hal().disk().write(&mut dst, ctx);
+            dst.free(ctx);
+        }
+    }
+
+    /// Read the on-disk inode `inum` of the snapshot.
+    fn snapshot_dinode(&self, inum: u32, ctx: &KernelCtx<'_, '_>) -> Result<Dinode, ()> {
+        let sb = self.superblock();
+        if !self.snapshot_valid.load(Ordering::Acquire) || inum == 0 || inum >= sb.ninodes {
+            return Err(());
+        }
+
+        let mut bp = hal().disk().read(ROOTDEV, sb.snap_iblock(inum), ctx);
+        // SAFETY: dip is inside bp.data.
+        let dip = unsafe {
+            (bp.deref_inner_mut().data.as_mut_ptr() as *mut Dinode).add(inum as usize % IPB)
+        };
+        // SAFETY: i16 does not have internal structure.
+        let t = unsafe { *(dip as *const i16) };
+        // If t >= #(variants of DInodeType), UB will happen when we read dip.typ.
+        assert!(t < core::mem::variant_count::<DInodeType>() as i16);
+        // SAFETY: dip is aligned properly and t < #(variants of DInodeType).
+        let dinode = unsafe { ptr::read(dip) };
+        bp.free(ctx);
+
+        if dinode.typ == DInodeType::None {
+            return Err(());
+        }
+        Ok(dinode)
+    }
+
+    /// Copy data into virtual address `dst` of the current process by `n` bytes
+    /// from the content of inode `inum` of the snapshot at offset `off`.
+    /// Must be called while holding the snapshot lock.
+    /// Returns Ok(number of bytes copied) on success, Err(()) on error.
+    fn read_snapshot(
+        &self,
+        inum: u32,
+        mut off: u32,
+        dst: UVAddr,
+        mut n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let dinode = self.snapshot_dinode(inum, ctx)?;
+        if off > dinode.size || off.wrapping_add(n) < off {
+            return Ok(0);
+        }
+        if off + n > dinode.size {
+            n = dinode.size - off;
+        }
+
+        // Blocks referenced by the snapshot are not modified until it is dropped or replaced,
+        // both of which wait for the snapshot lock.
+        let mut tot: u32 = 0;
+        while tot < n {
+            let bn = off as usize / BSIZE;
+            let addr = if bn < NDIRECT {
+                dinode.addr_direct[bn]
+            } else if dinode.addr_indirect == 0 {
+                0
+            } else {
+                let bp = hal().disk().read(ROOTDEV, dinode.addr_indirect, ctx);
+                // SAFETY: u32 does not have internal structure.
+                let (prefix, data, _) = unsafe { bp.deref_inner().data.align_to::<u32>() };
+                debug_assert_eq!(prefix.len(), 0, "snapshot_read: Buf data unaligned");
+                let addr = data[bn - NDIRECT];
+                bp.free(ctx);
+                addr
+            };
+            let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
+            let begin = (off % BSIZE as u32) as usize;
+            let end = begin + m as usize;
+            if addr == 0 {
+                // A hole reads as zeroes.
+                ctx.proc_mut()
+                    .memory_mut()
+                    .copy_out_bytes(dst + tot as usize, &[0; BSIZE][begin..end])?;
+            } else {
+                let bp = hal().disk().read(ROOTDEV, addr, ctx);
+                let res = ctx
+                    .proc_mut()
+                    .memory_mut()
+                    .copy_out_bytes(dst + tot as usize, &bp.deref_inner().data[begin..end]);
+                bp.free(ctx);
+                res?;
+            }
+            tot += m;
+            off += m;
+        }
+        Ok(tot as usize)
+    }
+
+    /// Copy the absolute path of the current directory into `buf`, finding it by walking ".."
+    /// up to the root. Returns Ok(length of the path) on success, Err(()) if the path does not
+    /// fit in `buf` or the current directory has been removed.
+    fn find_cwd(
+        self: StrongPin<'_, Self>,
+        buf: &mut [u8],
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        // Build the path from its end, walking ".." up to the root and looking up the name of
+        // each directory in its parent. Only one directory is locked at a time.
+        let mut end = buf.len();
+        let mut ptr = ctx.proc().cwd().clone();
+        let res = loop {
+            let mut ip = ptr.lock(ctx);
+            // A removed directory has no path.
+            if ip.deref_inner().nlink == 0 {
+                ip.free(ctx);
+                break Err(());
+            }
+            if ip.dev == ROOTDEV && ip.inum == ROOTINO {
+                ip.free(ctx);
+                break Ok(());
+            }
+            let inum = ip.inum;
+            // SAFETY: b".." does not contain any NUL characters.
+            let parent = ip.dirlookup(unsafe { FileName::from_bytes(b"..") }, ctx);
+            ip.free(ctx);
+            let (parent, _) = ok_or!(parent, break Err(()));
+            mem::replace(&mut ptr, parent).free((tx, ctx));
+
+            // The directory may have been moved or removed since we read its "..".
+            let mut dp = ptr.lock(ctx);
+            let len = dp.dirname(inum, &mut buf[..end], ctx);
+            dp.free(ctx);
+            end -= ok_or!(len, break Err(()));
+            if end == 0 {
+                break Err(());
+            }
+            end -= 1;
+            buf[end] = b'/';
+        };
+        ptr.free((tx, ctx));
+        res?;
+
+        // The root directory itself.
+        if end == buf.len() {
+            if end == 0 {
+                return Err(());
+            }
+            end -= 1;
+            buf[end] = b'/';
+        }
+        let len = buf.len() - end;
+        buf.copy_within(end.., 0);
+        Ok(len)
+    }
 }
 
 impl FileSystem for Ufs {
@@ -143,12 +505,41 @@ impl FileSystem for Ufs {
     fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
         if !self.superblock.is_completed() {
             let buf = hal().disk().read(dev, 1, ctx);
-            let superblock = self.superblock.call_once(|| Superblock::new(&buf));
+            let superblock = self.superblock.call_once(|| {
+                Superblock::decode(&buf.deref_inner().data[..]).expect("invalid file system")
+            });
             buf.free(ctx);
+            // A file system with unknown features that affect writing is mounted read-only.
+            let writable = superblock
+                .writable()
+                .expect("unsupported file system features");
+
+            // A cleanly unmounted file system has nothing to recover. Otherwise, the log is
+            // replayed before mounting read-only only if the boot configuration forces it, and
+            // never into a read-only device or a file system that cannot be written.
+            let disk_ro = hal().disk().read_only() || !writable;
+            let config = ctx.read_config().read_only;
+            let read_only = disk_ro || config != 0;
+            let replay = superblock.state != FS_CLEAN && !disk_ro && config != 1;
+            self.read_only.store(read_only, Ordering::Release);
+            self.snapshot_valid.store(
+                superblock.snapbmapstart != 0 && superblock.snapshot == SNAP_VALID,
+                Ordering::Release,
+            );
+            if !read_only {
+                // Until it is unmounted, the file system may be left with a log to recover.
+                self.write_state(dev, 0, ctx);
+            }
             let _ = self.log.call_once(|| {
                 SleepableLock::new(
                     "LOG",
-                    Log::new(dev, superblock.logstart as i32, superblock.nlog as i32, ctx),
+                    Log::new(
+                        dev,
+                        superblock.logstart as i32,
+                        superblock.nlog as i32,
+                        replay,
+                        ctx,
+                    ),
                 )
             });
         }
@@ -161,34 +552,45 @@ impl FileSystem for Ufs {
     fn namei(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<RcInode<Self>, ()> {
-        self.itable().namei(path, tx, ctx)
+        self.itable().namei(path, dir, tx, ctx)
     }
 
     fn link(
         self: StrongPin<'_, Self>,
         inode: RcInode<Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
+        unnamed: bool,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(), ()> {
         let inode = scopeguard::guard(inode, |ptr| ptr.free((tx, ctx)));
+        if self.read_only() {
+            return Err(());
+        }
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         if ip.deref_inner().typ == InodeType::Dir {
             return Err(());
         }
+        // An unlinked file may already be on its way to being freed.
+        if ip.deref_inner().nlink == 0 && !unnamed {
+            return Err(());
+        }
         ip.deref_inner_mut().nlink += 1;
         ip.update(tx, ctx);
+        let typ = ip.deref_inner().typ;
         drop(ip);
 
-        if let Ok((ptr2, name)) = self.itable().nameiparent(path, tx, ctx) {
+        if let Ok((ptr2, name)) = self.itable().nameiparent(path, dir, tx, ctx) {
             let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
             let dp = ptr2.lock(ctx);
             let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
-            if dp.dev == inode.dev && dp.dirlink(name, inode.inum, tx, ctx).is_ok() {
+            if dp.dev == inode.dev && dp.dirlink(name, inode.inum, typ, tx, ctx).is_ok() {
                 return Ok(());
             }
         }
@@ -203,10 +605,14 @@ impl FileSystem for Ufs {
     fn unlink(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(), ()> {
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
+        if self.read_only() {
+            return Err(());
+        }
+        let (ptr, name) = self.itable().nameiparent(path, dir, tx, ctx)?;
         let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
         let dp = ptr.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
@@ -226,8 +632,10 @@ impl FileSystem for Ufs {
             return Err(());
         }
 
-        dp.write_kernel(&Dirent::default(), off, tx, ctx)
-            .expect("unlink: writei");
+        dp.dirunlink(off, tx, ctx)?;
+        ctx.kernel()
+            .watches()
+            .post(dp.dev, dp.inum, WatchMask::DELETE, ip.inum, ctx);
         if ip.deref_inner().typ == InodeType::Dir {
             dp.deref_inner_mut().nlink -= 1;
             dp.update(tx, ctx);
@@ -242,6 +650,7 @@ impl FileSystem for Ufs {
     fn create<F, T>(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         typ: InodeType,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
@@ -250,7 +659,10 @@ impl FileSystem for Ufs {
     where
         F: FnOnce(&mut InodeGuard<'_, Self>) -> T,
     {
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
+        if self.read_only() {
+            return Err(());
+        }
+        let (ptr, name) = self.itable().nameiparent(path, dir, tx, ctx)?;
         let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
         let dp = ptr.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
@@ -269,14 +681,14 @@ impl FileSystem for Ufs {
             drop(ip);
             return Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret));
         }
-        let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx, ctx);
+        let ptr2 = self.itable().alloc_inode(dp.dev, dp.inum, typ, tx, ctx)?;
         let ip = ptr2.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink = 1;
         ip.update(tx, ctx);
 
         // Create . and .. entries.
-        if typ == InodeType::Dir {
+        let res = if typ == InodeType::Dir {
             // for ".."
             dp.deref_inner_mut().nlink += 1;
             dp.update(tx, ctx);
@@ -284,12 +696,30 @@ impl FileSystem for Ufs {
             let inum = ip.inum;
             // No ip->nlink++ for ".": avoid cyclic ref count.
             // SAFETY: b"." does not contain any NUL characters.
-            ip.dirlink(unsafe { FileName::from_bytes(b".") }, inum, tx, ctx)
-                // SAFETY: b".." does not contain any NUL characters.
-                .and_then(|_| ip.dirlink(unsafe { FileName::from_bytes(b"..") }, dp.inum, tx, ctx))
-                .expect("create dots");
+            ip.dirlink(unsafe { FileName::from_bytes(b".") }, inum, typ, tx, ctx)
+                .and_then(|_| {
+                    // SAFETY: b".." does not contain any NUL characters.
+                    let name = unsafe { FileName::from_bytes(b"..") };
+                    ip.dirlink(name, dp.inum, InodeType::Dir, tx, ctx)
+                })
+        } else {
+            Ok(())
+        };
+        if res
+            .and_then(|_| dp.dirlink(name, ip.inum, typ, tx, ctx))
+            .is_err()
+        {
+            // The disk is full. The new inode is freed when its last reference is dropped.
+            ip.deref_inner_mut().nlink = 0;
+            ip.update(tx, ctx);
+            if typ == InodeType::Dir {
+                dp.deref_inner_mut().nlink -= 1;
+                dp.update(tx, ctx);
+            }
+            drop(ip);
+            ptr2.free((tx, ctx));
+            return Err(());
         }
-        dp.dirlink(name, ip.inum, tx, ctx).expect("create: dirlink");
         let ret = f(&mut ip);
         drop(ip);
         Ok((ptr2, ret))
@@ -298,14 +728,27 @@ impl FileSystem for Ufs {
     fn open(
         self: StrongPin<'_, Self>,
         path: &Path,
+        dir: Option<&RcInode<Self>>,
         omode: FcntlFlags,
         tx: &Tx<'_, Self>,
         ctx: &mut KernelCtx<'_, '_>,
     ) -> Result<usize, ()> {
-        let (ip, typ) = if omode.contains(FcntlFlags::O_CREATE) {
-            self.create(path, InodeType::File, tx, ctx, |ip| ip.deref_inner().typ)?
+        let writable = omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR);
+        if self.read_only() && omode.intersects(FcntlFlags::O_TMPFILE | FcntlFlags::O_TRUNC) {
+            return Err(());
+        }
+        let (ip, typ) = if omode.contains(FcntlFlags::O_TMPFILE) {
+            // An unnamed file is useless unless it can be written.
+            if omode.contains(FcntlFlags::O_CREATE) || !writable {
+                return Err(());
+            }
+            (self.create_unnamed(path, dir, tx, ctx)?, InodeType::File)
+        } else if omode.contains(FcntlFlags::O_CREATE) {
+            self.create(path, dir, InodeType::File, tx, ctx, |ip| {
+                ip.deref_inner().typ
+            })?
         } else {
-            let ptr = self.itable().namei(path, tx, ctx)?;
+            let ptr = self.itable().namei(path, dir, tx, ctx)?;
             let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
             let ip = ptr.lock(ctx);
             let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
@@ -314,18 +757,24 @@ impl FileSystem for Ufs {
             if typ == InodeType::Dir && omode != FcntlFlags::O_RDONLY {
                 return Err(());
             }
+            // Devices do not keep their data in the file system, so they stay writable.
+            if self.read_only() && writable && !matches!(typ, InodeType::Device { .. }) {
+                return Err(());
+            }
             drop(ip);
             (scopeguard::ScopeGuard::into_inner(ptr), typ)
         };
 
         let filetype = match typ {
             InodeType::Device { major, .. } => FileType::Device { ip, major },
+            // A socket is connected to rather than opened.
+            InodeType::Socket => {
+                ip.free((tx, ctx));
+                return Err(());
+            }
             _ => {
                 FileType::Inode {
-                    inner: InodeFileType {
-                        ip,
-                        off: UnsafeCell::new(0),
-                    },
+                    inner: InodeFileType::new(ip, omode.contains(FcntlFlags::O_TMPFILE)),
                 }
             }
         };
@@ -333,7 +782,8 @@ impl FileSystem for Ufs {
         let f = ctx.kernel().ftable().alloc_file(
             filetype,
             !omode.intersects(FcntlFlags::O_WRONLY),
-            omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
+            writable,
+            ctx,
         )?;
 
         if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
@@ -351,6 +801,9 @@ impl FileSystem for Ufs {
             };
         }
         let fd = f.fdalloc(ctx)?;
+        if let Some(entry) = &mut ctx.proc_mut().deref_mut_data().open_files[fd as usize] {
+            entry.cloexec = omode.contains(FcntlFlags::O_CLOEXEC);
+        }
         Ok(fd as usize)
     }
 
@@ -368,9 +821,37 @@ impl FileSystem for Ufs {
             return Err(());
         }
         mem::replace(ctx.proc_mut().cwd_mut(), inode).free((tx, ctx));
+
+        // Directories can be neither renamed nor linked, so the path of a directory does not
+        // change while it exists. Find it once here, from the inode itself, for getcwd().
+        let mut path = [0; MAXPATH];
+        let len = self.find_cwd(&mut path, tx, ctx).ok();
+        ctx.proc_mut()
+            .deref_mut_data()
+            .set_cwd_path(len.map(|len| &path[..len]));
         Ok(())
     }
 
+    fn getcwd(
+        self: StrongPin<'_, Self>,
+        buf: &mut [u8],
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        // A removed directory has no path.
+        let ip = ctx.proc().cwd().lock(ctx);
+        let removed = ip.deref_inner().nlink == 0;
+        ip.free(ctx);
+        if removed {
+            return Err(());
+        }
+        if let Some(path) = ctx.proc().deref_data().cwd_path() {
+            buf.get_mut(..path.len()).ok_or(())?.copy_from_slice(path);
+            return Ok(path.len());
+        }
+        self.find_cwd(buf, tx, ctx)
+    }
+
     fn tx_begin(&self, ctx: &KernelCtx<'_, '_>) {
         self.log().begin_op(ctx);
     }
@@ -400,16 +881,42 @@ impl FileSystem for Ufs {
         if off + n > inner.size {
             n = inner.size - off;
         }
+        // File contents are read through the page cache, and other contents through the buffer
+        // cache.
+        let cached = inner.typ == InodeType::File;
         let mut tot: u32 = 0;
         while tot < n {
-            let bp = hal()
-                .disk()
-                .read(guard.dev, guard.bmap(off as usize / BSIZE, &k), &k);
-            let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
-            let begin = (off % BSIZE as u32) as usize;
-            let end = begin + m as usize;
-            let res = f(tot, &bp.deref_inner().data[begin..end], &mut k);
-            bp.free(&k);
+            let page = if cached {
+                k.kernel()
+                    .page_cache()
+                    .get(guard.dev, guard.inum, off as usize / PGSIZE)
+            } else {
+                None
+            };
+            let (m, res) = if let Some(mut page) = page {
+                if !page.is_valid() {
+                    guard.fill_page(off as usize / PGSIZE, &mut page, &k);
+                    page.set_valid();
+                }
+                let m = core::cmp::min(n - tot, PGSIZE as u32 - off % PGSIZE as u32);
+                let begin = (off % PGSIZE as u32) as usize;
+                let end = begin + m as usize;
+                (m, f(tot, &page[begin..end], &mut k))
+            } else {
+                let addr = guard.bmap(off as usize / BSIZE, &k);
+                let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
+                let begin = (off % BSIZE as u32) as usize;
+                let end = begin + m as usize;
+                if addr == 0 {
+                    // A hole reads as zeroes.
+                    (m, f(tot, &[0; BSIZE][begin..end], &mut k))
+                } else {
+                    let bp = hal().disk().read(guard.dev, addr, &k);
+                    let res = f(tot, &bp.deref_inner().data[begin..end], &mut k);
+                    bp.free(&k);
+                    (m, res)
+                }
+            };
             res?;
             tot += m;
             off += m;
@@ -431,23 +938,37 @@ impl FileSystem for Ufs {
         tx: &Tx<'_, Self>,
         mut k: K,
     ) -> Result<usize, ()> {
-        if off > guard.deref_inner().size {
+        if tx.fs.read_only() || off > guard.deref_inner().size {
             return Err(());
         }
         if off.checked_add(n).ok_or(())? as usize > MAXFILE * BSIZE {
             return Err(());
         }
+        let cached = guard.deref_inner().typ == InodeType::File;
+        if cached {
+            k.kernel().exec_cache().invalidate(guard.dev, guard.inum);
+        }
         let mut tot: u32 = 0;
+        let mut full = false;
         while tot < n {
-            let mut bp = hal().disk().read(
-                guard.dev,
-                guard.bmap_or_alloc(off as usize / BSIZE, tx, &k),
-                &k,
-            );
+            let addr = ok_or!(guard.bmap_or_alloc(off as usize / BSIZE, tx, &k), {
+                full = true;
+                break;
+            });
+            let mut bp = hal().disk().read(guard.dev, addr, &k);
             let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
             let begin = (off % BSIZE as u32) as usize;
             let end = begin + m as usize;
             if f(tot, &mut bp.deref_inner_mut().data[begin..end], &mut k).is_ok() {
+                if cached {
+                    // Keep the cached page, if any, up to date with the disk block.
+                    k.kernel().page_cache().update(
+                        guard.dev,
+                        guard.inum,
+                        off,
+                        &bp.deref_inner().data[begin..end],
+                    );
+                }
                 tx.write(bp, &k);
             } else {
                 bp.free(&k);
@@ -465,37 +986,55 @@ impl FileSystem for Ufs {
         // because the loop above might have called bmap() and added a new
         // block to self->addrs[].
         guard.update(tx, &k);
+        if full && tot == 0 {
+            // The disk is full.
+            return Err(());
+        }
         Ok(tot as usize)
     }
 
-    fn inode_trunc(guard: &mut InodeGuard<'_, Self>, tx: &Tx<'_, Self>, ctx: &KernelCtx<'_, '_>) {
-        let dev = guard.dev;
-        for addr in &mut guard.deref_inner_mut().addr_direct {
-            if *addr != 0 {
-                tx.bfree(dev, *addr, ctx);
-                *addr = 0;
-            }
+    fn inode_trunc(
+        guard: &mut InodeGuard<'_, Self>,
+        size: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        if tx.fs.read_only() || size as usize > MAXFILE * BSIZE {
+            return Err(());
         }
-
-        if guard.deref_inner().addr_indirect != 0 {
-            let mut bp = hal()
-                .disk()
-                .read(dev, guard.deref_inner().addr_indirect, ctx);
-            // SAFETY: u32 does not have internal structure.
-            let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
-            debug_assert_eq!(prefix.len(), 0, "itrunc: Buf data unaligned");
-            for a in data {
-                if *a != 0 {
-                    tx.bfree(dev, *a, ctx);
-                }
-            }
-            bp.free(ctx);
-            tx.bfree(dev, guard.deref_inner().addr_indirect, ctx);
-            guard.deref_inner_mut().addr_indirect = 0
+        ctx.kernel().page_cache().invalidate(guard.dev, guard.inum);
+        ctx.kernel().exec_cache().invalidate(guard.dev, guard.inum);
+        if size < guard.deref_inner().size {
+            // Bytes past the end of the file must be zero, in case it is extended again.
+            // This comes first, since it copies the indirect block out of the snapshot if needed,
+            // so free_blocks() cannot fail after it has modified anything.
+            guard.zero_tail(size as usize, tx, ctx)?;
         }
-
-        guard.deref_inner_mut().size = 0;
+        // Extending the file leaves a hole, which bmap() reports as block 0.
+        guard.free_blocks((size as usize + BSIZE - 1) / BSIZE, tx, ctx)?;
+        guard.deref_inner_mut().size = size;
         guard.update(tx, ctx);
+        Ok(())
+    }
+
+    fn inode_share_page(
+        guard: &mut InodeGuard<'_, Self>,
+        index: usize,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Option<Page> {
+        let inner = guard.deref_inner();
+        if inner.typ != InodeType::File || (index + 1) * PGSIZE > inner.size as usize {
+            return None;
+        }
+        let mut page = ctx
+            .kernel()
+            .page_cache()
+            .get(guard.dev, guard.inum, index)?;
+        if !page.is_valid() {
+            guard.fill_page(index, &mut page, ctx);
+            page.set_valid();
+        }
+        Some(page.share())
     }
 
     fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
@@ -529,11 +1068,13 @@ impl FileSystem for Ufs {
                         minor: dip.minor,
                     }
                 }
+                DInodeType::Socket => guard.typ = InodeType::Socket,
             }
             guard.nlink = dip.nlink;
             guard.size = dip.size;
             guard.addr_direct.copy_from_slice(&dip.addr_direct);
             guard.addr_indirect = dip.addr_indirect;
+            guard.gen = dip.gen;
             bp.free(ctx);
             guard.valid = true;
             assert_ne!(guard.typ, InodeType::None, "Inode::lock: no type");
@@ -547,7 +1088,9 @@ impl FileSystem for Ufs {
         tx: &'a Tx<'a, Self>,
         ctx: &'a KernelCtx<'id, 'a>,
     ) {
-        if inode.inner.get_mut().valid && inode.inner.get_mut().nlink == 0 {
+        // On a read-only file system, an inode without links stays on the disk until the file
+        // system is mounted read-write.
+        if inode.inner.get_mut().valid && inode.inner.get_mut().nlink == 0 && !tx.fs.read_only() {
             // inode has no links and no other references: truncate and free.
 
             // self->ref == 1 means no other process can have self locked,
@@ -560,6 +1103,8 @@ impl FileSystem for Ufs {
             ip.deref_inner_mut().valid = false;
 
             ip.free(ctx);
+            ctx.kernel().watches().forget(inode.dev, inode.inum);
+            ctx.kernel().exec_cache().invalidate(inode.dev, inode.inum);
         }
     }
 
@@ -573,12 +1118,185 @@ impl FileSystem for Ufs {
                 InodeType::Dir => 1,
                 InodeType::File => 2,
                 InodeType::Device { .. } => 3,
+                InodeType::Socket => 4,
             },
             nlink: inner.nlink,
-            _padding: 0,
+            gen: inner.gen,
             size: inner.size as usize,
         };
         inner.free(ctx);
         st
     }
+
+    fn inode_getdents(
+        guard: &mut InodeGuard<'_, Self>,
+        off: &mut u32,
+        dst: UVAddr,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        guard.getdents(off, dst, n, ctx)
+    }
+
+    fn defrag(
+        self: StrongPin<'_, Self>,
+        inode: &Inode<Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        if self.read_only() {
+            return Err(());
+        }
+        let ip = inode.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        if ip.deref_inner().typ != InodeType::File {
+            return Err(());
+        }
+        let nblocks = (ip.deref_inner().size as usize + BSIZE - 1) / BSIZE;
+        if nblocks < 2 {
+            return Ok(0);
+        }
+        // Holes have no blocks to relocate.
+        if (0..nblocks).any(|bn| ip.bmap(bn, ctx) == 0) {
+            return Err(());
+        }
+        let first = ip.bmap(0, ctx);
+        if (1..nblocks).all(|bn| ip.bmap(bn, ctx) == first + bn as u32) {
+            return Ok(0);
+        }
+        drop(ip);
+
+        let start = self
+            .find_free_run(inode.dev, inode.inum, nblocks as u32, ctx)
+            .ok_or(())?;
+
+        // Move one block per transaction. A crash leaves every block either at
+        // its old or at its new location, never both or neither.
+        let mut moved = 0;
+        for bn in 0..nblocks {
+            let tx = self.as_pin().get_ref().begin_tx(ctx);
+            let mut ip = inode.lock(ctx);
+            let res = ip.relocate(bn, start + bn as u32, &tx, ctx);
+            ip.free(ctx);
+            tx.end(ctx);
+            if res.is_err() {
+                break;
+            }
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    fn snapshot(self: StrongPin<'_, Self>, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let sb = *self.superblock();
+        if sb.snapbmapstart == 0 || self.read_only() {
+            return Err(());
+        }
+
+        let guard = self.snapshot_lock.lock(ctx);
+        self.log().freeze(
+            || {
+                // Drop the old snapshot first, so that a crash in the middle leaves no
+                // snapshot rather than a broken one. The new snapshot takes effect
+                // when the super block marks it valid, after all of it is on the disk.
+                self.set_snapshot_valid(false, ctx);
+                for (start, snap, n) in sb.inode_runs() {
+                    self.copy_blocks_direct(Some(start), snap, n, ctx);
+                }
+                for (start, snap, n) in sb.bmap_runs() {
+                    self.copy_blocks_direct(Some(start), snap, n, ctx);
+                }
+                self.set_snapshot_valid(true, ctx);
+            },
+            ctx,
+        );
+        guard.free(ctx);
+        Ok(())
+    }
+
+    fn snapshot_drop(self: StrongPin<'_, Self>, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let sb = *self.superblock();
+        if sb.snapbmapstart == 0 || self.read_only() {
+            return Err(());
+        }
+
+        let guard = self.snapshot_lock.lock(ctx);
+        self.log().freeze(
+            || {
+                // The snapshot is gone once the super block marks it invalid. Clearing the
+                // snapshot blocks afterwards only keeps stale contents off the disk.
+                self.set_snapshot_valid(false, ctx);
+                for (_, snap, n) in sb.bmap_runs() {
+                    self.copy_blocks_direct(None, snap, n, ctx);
+                }
+                for (_, snap, n) in sb.inode_runs() {
+                    self.copy_blocks_direct(None, snap, n, ctx);
+                }
+            },
+            ctx,
+        );
+        guard.free(ctx);
+        Ok(())
+    }
+
+    fn snapshot_read(
+        self: StrongPin<'_, Self>,
+        inum: u32,
+        off: u32,
+        dst: UVAddr,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let guard = self.snapshot_lock.lock(ctx);
+        let res = self.read_snapshot(inum, off, dst, n, ctx);
+        guard.free(ctx);
+        res
+    }
+
+    fn snapshot_stat(
+        self: StrongPin<'_, Self>,
+        inum: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Stat, ()> {
+        let guard = self.snapshot_lock.lock(ctx);
+        let dinode = self.snapshot_dinode(inum, ctx);
+        guard.free(ctx);
+        let dinode = dinode?;
+        Ok(Stat {
+            dev: ROOTDEV as i32,
+            ino: inum,
+            typ: match dinode.typ {
+                DInodeType::None => 0,
+                DInodeType::Dir => 1,
+                DInodeType::File => 2,
+                DInodeType::Device => 3,
+                DInodeType::Socket => 4,
+            },
+            nlink: dinode.nlink,
+            gen: dinode.gen,
+            size: dinode.size as usize,
+        })
+    }
+
+    fn quiesce<F: FnOnce()>(self: StrongPin<'_, Self>, f: F, ctx: &KernelCtx<'_, '_>) {
+        self.log().freeze(f, ctx);
+    }
+
+    fn unmount<F: FnOnce()>(self: StrongPin<'_, Self>, f: F, ctx: &KernelCtx<'_, '_>) {
+        // Free the inodes whose finalization was deferred, which would otherwise be lost.
+        self.reap(ctx);
+        self.log().freeze(
+            || {
+                let read_only = self.read_only();
+                if !read_only {
+                    self.write_state(ROOTDEV, FS_CLEAN, ctx);
+                }
+                f();
+                // `f` returned, so the file system is still in use.
+                if !read_only {
+                    self.write_state(ROOTDEV, 0, ctx);
+                }
+            },
+            ctx,
+        );
+    }
 }
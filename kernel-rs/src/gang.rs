@@ -0,0 +1,207 @@
+//! Gang scheduling.
+//!
+//! The processes that `gang()` puts in the same gang run simultaneously on different CPUs, or
+//! not at all, so that a parallel program whose processes wait for each other at barriers does
+//! not waste its time waiting for a process that is not running. Time is divided into slots of
+//! `GANGSLICE` ticks, which go to each gang in turn and then to the processes in no gang. During
+//! the slot of a gang, the CPUs run only its members, and no process in no gang, even if a CPU
+//! is left idle; during the slot of the processes in no gang, no member of a gang runs. A gang
+//! has at most as many members as there are CPUs, so that all of them can run at once. Without
+//! any gang, every slot goes to the processes in no gang, and scheduling is unaffected.
+
+use core::sync::atomic::{AtomicI32, Ordering};
+
+use zerocopy::AsBytes;
+
+use crate::{
+    hal::hal,
+    lock::SpinLock,
+    param::{GANGSLICE, NCPU, NGANG},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// Statistics of gang scheduling, read by `gang()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct GangInfo {
+    /// Gang whose slot it is now, or 0 in the slot of the processes in no gang
+    pub active: usize,
+
+    /// Number of gangs
+    pub gangs: usize,
+
+    /// Number of members of the gang of the process, or 0 if it is in no gang
+    pub members: usize,
+
+    /// Number of slots begun so far
+    pub slots: usize,
+}
+
+struct GangsInner {
+    /// ID and number of members of each gang.
+    gangs: [Option<(i32, usize)>; NGANG],
+
+    /// Index in `gangs` of the gang whose slot it is now, or `NGANG` in the slot of the
+    /// processes in no gang.
+    slot: usize,
+
+    /// Ticks when the current slot began
+    start: u32,
+
+    /// Number of slots begun so far
+    slots: usize,
+}
+
+pub struct Gangs {
+    /// Gang whose slot it is now, or 0. The scheduler reads it without the lock.
+    active: AtomicI32,
+
+    inner: SpinLock<GangsInner>,
+}
+
+impl GangsInner {
+    /// Removes a member from gang `gang`, and the gang as well if it has no member left.
+    fn leave(&mut self, gang: i32, active: &AtomicI32) {
+        let index = match self
+            .gangs
+            .iter()
+            .position(|g| g.map_or(false, |g| g.0 == gang))
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let entry = self.gangs[index].as_mut().expect("GangsInner::leave");
+        entry.1 -= 1;
+        if entry.1 == 0 {
+            self.gangs[index] = None;
+            // The slot of a gang with no members ends right away.
+            if self.slot == index {
+                self.slot = NGANG;
+                active.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Gangs {
+    pub const fn new() -> Self {
+        Self {
+            active: AtomicI32::new(0),
+            inner: SpinLock::new(
+                "gangs",
+                GangsInner {
+                    gangs: [None; NGANG],
+                    slot: NGANG,
+                    start: 0,
+                    slots: 0,
+                },
+            ),
+        }
+    }
+
+    /// Returns the gang whose slot it is now, or 0 in the slot of the processes in no gang.
+    pub fn active(&self) -> i32 {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Moves a process from gang `old` to gang `new`, where 0 is no gang.
+    /// Returns Ok(()) on success, or Err(()) if there are `NGANG` gangs already or gang `new` has
+    /// as many members as there are CPUs.
+    pub fn join(&self, old: i32, new: i32) -> Result<(), ()> {
+        if old == new {
+            return Ok(());
+        }
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        if new != 0 {
+            let ncpu = (0..NCPU).filter(|id| hal().cpus().is_started(*id)).count();
+            match inner.gangs.iter_mut().flatten().find(|g| g.0 == new) {
+                Some(entry) if entry.1 >= ncpu => return Err(()),
+                Some(entry) => entry.1 += 1,
+                None => {
+                    let entry = inner.gangs.iter_mut().find(|g| g.is_none()).ok_or(())?;
+                    *entry = Some((new, 1));
+                }
+            }
+        }
+        if old != 0 {
+            inner.leave(old, &self.active);
+        }
+        Ok(())
+    }
+
+    /// Removes a member from gang `gang`, if it is not 0.
+    pub fn leave(&self, gang: i32) {
+        if gang != 0 {
+            self.inner.lock().leave(gang, &self.active);
+        }
+    }
+
+    /// Begins the next slot if the current one has lasted `GANGSLICE` ticks at `now`.
+    /// Called on each tick.
+    pub fn tick(&self, now: u32) {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        if now.wrapping_sub(inner.start) < GANGSLICE {
+            return;
+        }
+        inner.start = now;
+
+        // The slot of the processes in no gang follows that of the last gang.
+        let from = if inner.slot == NGANG {
+            0
+        } else {
+            inner.slot + 1
+        };
+        let next = (from..NGANG)
+            .find(|i| inner.gangs[*i].is_some())
+            .unwrap_or(NGANG);
+        if next == inner.slot {
+            return;
+        }
+        inner.slot = next;
+        inner.slots += 1;
+        let active = inner.gangs.get(next).and_then(|g| *g).map_or(0, |g| g.0);
+        self.active.store(active, Ordering::Relaxed);
+    }
+
+    /// Returns the statistics of gang scheduling, for a process in gang `gang`.
+    pub fn info(&self, gang: i32) -> GangInfo {
+        let inner = self.inner.lock();
+        let gangs = inner.gangs.iter().flatten();
+        GangInfo {
+            active: self.active() as usize,
+            gangs: gangs.clone().count(),
+            members: gangs.filter(|g| g.0 == gang).map(|g| g.1).sum(),
+            slots: inner.slots,
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Make the process pid, or the current process if pid is 0, join gang id, or leave its gang
+    /// if id is 0, and leave it as it is if id is -1. Then, place the statistics into struct
+    /// ganginfo at addr, if addr is not 0.
+    /// Returns Ok(gang of the process) on success, Err(()) on error.
+    pub fn sys_gang(&mut self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        let id = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?;
+        if id < -1 {
+            return Err(());
+        }
+        let gang = if id == -1 { None } else { Some(id) };
+        let gang = self.kernel().procs().set_gang(pid, gang, self)?;
+        if addr != 0 {
+            let info = self.kernel().gangs().info(gang);
+            self.proc_mut().memory_mut().copy_out(addr.into(), &info)?;
+        }
+        Ok(gang as usize)
+    }
+}
+
+/// Registers the system calls of gang scheduling.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(118, |ctx| ctx.sys_gang());
+}
@@ -7,9 +7,11 @@ use crate::{
     arch::TargetArch,
     console::{Console, Printer},
     cpu::Cpus,
+    ipi::Ipi,
     kalloc::Kmem,
     lock::{SleepableLock, SpinLock},
-    virtio::VirtioDisk,
+    virtio::{Virtio9p, VirtioConsole, VirtioDisk, VirtioRng},
+    zram::Zram,
 };
 
 static mut HAL: Hal = unsafe { Hal::new::<TargetArch>() };
@@ -46,10 +48,23 @@ pub struct Hal {
     #[pin]
     kmem: SpinLock<Kmem>,
 
+    zram: Zram,
+
     cpus: Cpus,
 
+    ipi: Ipi,
+
     #[pin]
     disk: SleepableLock<VirtioDisk>,
+
+    #[pin]
+    rng: SpinLock<VirtioRng>,
+
+    #[pin]
+    virtio_console: SleepableLock<VirtioConsole>,
+
+    #[pin]
+    ninep: SleepableLock<Virtio9p>,
 }
 
 impl Hal {
@@ -61,8 +76,13 @@ impl Hal {
             console: unsafe { Console::new(A::UART0) },
             printer: Printer::new(),
             kmem: SpinLock::new("KMEM", unsafe { Kmem::new() }),
+            zram: Zram::new(),
             cpus: Cpus::new(),
+            ipi: Ipi::new(),
             disk: SleepableLock::new("DISK", unsafe { VirtioDisk::new() }),
+            rng: SpinLock::new("RNG", unsafe { VirtioRng::new() }),
+            virtio_console: SleepableLock::new("VIRTIO_CONSOLE", unsafe { VirtioConsole::new() }),
+            ninep: SleepableLock::new("NINEP", unsafe { Virtio9p::new() }),
         }
     }
 
@@ -80,7 +100,10 @@ impl Hal {
         // Physical page allocator.
         unsafe { this.kmem.get_pin_mut().init() };
 
-        this.disk.get_pin_mut().as_ref().init();
+        this.disk.get_pin_mut().init();
+        this.rng.get_pin_mut().init();
+        this.virtio_console.get_pin_mut().init();
+        this.ninep.get_pin_mut().init();
     }
 
     pub fn console(&self) -> &Console {
@@ -96,12 +119,35 @@ impl Hal {
         unsafe { Pin::new_unchecked(&self.get_ref().kmem) }
     }
 
+    pub fn zram(&self) -> &Zram {
+        &self.zram
+    }
+
     pub fn cpus(&self) -> &Cpus {
         &self.cpus
     }
 
+    pub fn ipi(&self) -> &Ipi {
+        &self.ipi
+    }
+
     pub fn disk(self: Pin<&Self>) -> Pin<&SleepableLock<VirtioDisk>> {
         // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
         unsafe { Pin::new_unchecked(&self.get_ref().disk) }
     }
+
+    pub fn rng(self: Pin<&Self>) -> Pin<&SpinLock<VirtioRng>> {
+        // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
+        unsafe { Pin::new_unchecked(&self.get_ref().rng) }
+    }
+
+    pub fn virtio_console(self: Pin<&Self>) -> Pin<&SleepableLock<VirtioConsole>> {
+        // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
+        unsafe { Pin::new_unchecked(&self.get_ref().virtio_console) }
+    }
+
+    pub fn ninep(self: Pin<&Self>) -> Pin<&SleepableLock<Virtio9p>> {
+        // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
+        unsafe { Pin::new_unchecked(&self.get_ref().ninep) }
+    }
 }
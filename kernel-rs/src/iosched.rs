@@ -0,0 +1,309 @@
+//! Disk I/O scheduling.
+//!
+//! Disk requests wait in a queue until the disk has descriptors for them, and the policy chosen
+//! by `iosched()` decides which request goes to the disk next:
+//! * `Noop` sends them in the order they came.
+//! * `Elevator` sends them in the order of their block numbers, sweeping up from the block after
+//!   the last one sent and then starting over from the lowest one.
+//! * `Fair` shares the disk evenly between processes by start-time fair queueing. A request is
+//!   tagged with the virtual time at which its process gets its next turn, and the request with
+//!   the smallest tag goes first.
+//!
+//! Whatever the policy is, the waiting requests for the blocks next to the one sent, in the same
+//! direction, are merged into a single disk request.
+//!
+//! Each process accounts the blocks it read and wrote, and the cycles it waited for them.
+
+use core::{
+    cmp,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use arrayvec::ArrayVec;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    bio::Buf,
+    param::{NIOMERGE, NIOQUEUE},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// Operations of `iosched()`.
+pub const IOSCHED_SETPOLICY: i32 = 1;
+pub const IOSCHED_GETPOLICY: i32 = 2;
+pub const IOSCHED_STAT: i32 = 3;
+pub const IOSCHED_QSTAT: i32 = 4;
+
+/// Which request goes to the disk next.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum IoPolicy {
+    /// The oldest one.
+    Noop = 0,
+
+    /// The one with the next block number.
+    Elevator = 1,
+
+    /// The one of the process that used the disk least.
+    Fair = 2,
+}
+
+/// The policy, as an `IoPolicy`.
+static POLICY: AtomicUsize = AtomicUsize::new(IoPolicy::Noop as usize);
+
+/// Number of disk requests sent.
+static NREQUEST: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of blocks in the disk requests sent.
+static NBLOCK: AtomicUsize = AtomicUsize::new(0);
+
+/// Disk I/O of a process, read by `iosched(IOSCHED_STAT)`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct IoStats {
+    /// Blocks read
+    pub reads: usize,
+
+    /// Blocks written
+    pub writes: usize,
+
+    /// Cycles spent waiting for the blocks
+    pub wait: usize,
+}
+
+/// Disk requests sent, read by `iosched(IOSCHED_QSTAT)`. Blocks exceed requests by the blocks
+/// merged into the requests of others.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct IoQueueStats {
+    pub requests: usize,
+    pub blocks: usize,
+}
+
+/// Disk I/O accounting of a process.
+#[derive(Copy, Clone)]
+pub struct IoAccount {
+    pub stats: IoStats,
+
+    /// Virtual time at which the last request of the process finishes.
+    finish: usize,
+}
+
+/// A request for a block, waiting in an `IoQueue`.
+///
+/// # Safety
+///
+/// `b` refers to a valid `Buf` until the request completes.
+#[derive(Copy, Clone)]
+pub struct Request {
+    pub b: *mut Buf,
+    pub blockno: u32,
+    pub write: bool,
+
+    /// Virtual time at which the request starts, in fair queueing.
+    tag: usize,
+
+    /// Order of arrival.
+    seq: usize,
+}
+
+/// Requests waiting for the disk.
+pub struct IoQueue {
+    requests: [Option<Request>; NIOQUEUE],
+
+    /// Order of arrival of the next request.
+    seq: usize,
+
+    /// The block after the last one sent, where the elevator goes on from.
+    head: u32,
+
+    /// Virtual time of fair queueing, the tag of the last request sent.
+    vtime: usize,
+}
+
+impl IoPolicy {
+    pub fn from_usize(n: usize) -> Option<Self> {
+        match n {
+            0 => Some(Self::Noop),
+            1 => Some(Self::Elevator),
+            2 => Some(Self::Fair),
+            _ => None,
+        }
+    }
+}
+
+impl IoAccount {
+    pub const fn new() -> Self {
+        Self {
+            stats: IoStats {
+                reads: 0,
+                writes: 0,
+                wait: 0,
+            },
+            finish: 0,
+        }
+    }
+
+    /// Returns the tag of a new request of the process, given vtime, the virtual time of the
+    /// queue. Each request takes a unit of virtual time.
+    pub fn tag(&mut self, vtime: usize) -> usize {
+        let start = cmp::max(vtime, self.finish);
+        self.finish = start + 1;
+        start
+    }
+
+    /// Accounts a block read or written after waiting wait cycles for it.
+    pub fn add(&mut self, write: bool, wait: usize) {
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+        self.stats.wait += wait;
+    }
+}
+
+impl Request {
+    pub fn new(b: &mut Buf, write: bool, tag: usize) -> Self {
+        Self {
+            blockno: b.blockno,
+            b,
+            write,
+            tag,
+            seq: 0,
+        }
+    }
+
+    /// The request that goes first has the smallest key.
+    fn key(&self, policy: IoPolicy, head: u32) -> (usize, usize, usize) {
+        match policy {
+            IoPolicy::Noop => (0, 0, self.seq),
+            IoPolicy::Elevator => {
+                (
+                    (self.blockno < head) as usize,
+                    self.blockno as usize,
+                    self.seq,
+                )
+            }
+            IoPolicy::Fair => (self.tag, 0, self.seq),
+        }
+    }
+}
+
+impl IoQueue {
+    pub const fn new() -> Self {
+        Self {
+            requests: [None; NIOQUEUE],
+            seq: 0,
+            head: 0,
+            vtime: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.requests.iter().all(|r| r.is_some())
+    }
+
+    pub fn vtime(&self) -> usize {
+        self.vtime
+    }
+
+    /// Adds request to the queue, which must not be full.
+    pub fn push(&mut self, mut request: Request) {
+        request.seq = self.seq;
+        self.seq += 1;
+        let slot = self
+            .requests
+            .iter_mut()
+            .find(|r| r.is_none())
+            .expect("IoQueue::push");
+        *slot = Some(request);
+    }
+
+    /// Removes the first request that matches f.
+    fn take<F: Fn(&Request) -> bool>(&mut self, f: F) -> Option<Request> {
+        self.requests
+            .iter_mut()
+            .find(|r| r.as_ref().map_or(false, &f))?
+            .take()
+    }
+
+    /// Removes the request that goes to the disk next, together with the requests merged into
+    /// it, at most max of them. Returns them in the order of their block numbers, or None if the
+    /// queue is empty.
+    pub fn pop(&mut self, max: usize) -> Option<ArrayVec<Request, NIOMERGE>> {
+        let policy = policy();
+        let head = self.head;
+        let first = self
+            .requests
+            .iter()
+            .flatten()
+            .min_by_key(|r| r.key(policy, head))?
+            .seq;
+        let first = self.take(|r| r.seq == first)?;
+
+        let mut batch = ArrayVec::<_, NIOMERGE>::new();
+        batch.push(first);
+        while batch.len() < cmp::min(max, NIOMERGE) {
+            let (low, high) = (batch[0].blockno, batch[batch.len() - 1].blockno);
+            if let Some(r) = self.take(|r| r.write == first.write && r.blockno == high + 1) {
+                batch.push(r);
+            } else if let Some(r) = self.take(|r| r.write == first.write && r.blockno + 1 == low) {
+                batch.insert(0, r);
+            } else {
+                break;
+            }
+        }
+
+        self.head = batch[batch.len() - 1].blockno + 1;
+        self.vtime = cmp::max(self.vtime, first.tag);
+        let _ = NREQUEST.fetch_add(1, Ordering::Relaxed);
+        let _ = NBLOCK.fetch_add(batch.len(), Ordering::Relaxed);
+        Some(batch)
+    }
+}
+
+/// Returns the policy.
+pub fn policy() -> IoPolicy {
+    IoPolicy::from_usize(POLICY.load(Ordering::Relaxed)).unwrap_or(IoPolicy::Noop)
+}
+
+impl KernelCtx<'_, '_> {
+    /// Controls disk I/O scheduling by op:
+    /// * `IOSCHED_SETPOLICY` makes arg the policy.
+    /// * `IOSCHED_GETPOLICY` returns the policy.
+    /// * `IOSCHED_STAT` copies the struct iostat of the process arg, or of the current process if
+    ///   arg is 0, to addr.
+    /// * `IOSCHED_QSTAT` copies the struct ioqstat of the disk requests sent to addr.
+    /// Returns Ok(0 or the policy) on success, Err(()) on error.
+    pub fn sys_iosched(&mut self) -> Result<usize, ()> {
+        let op = self.proc().argint(0)?;
+        let arg = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?;
+        match op {
+            IOSCHED_SETPOLICY => {
+                let policy = IoPolicy::from_usize(arg as usize).ok_or(())?;
+                POLICY.store(policy as usize, Ordering::Relaxed);
+            }
+            IOSCHED_GETPOLICY => return Ok(policy() as usize),
+            IOSCHED_STAT => {
+                let stats = self.kernel().procs().io_stats(arg, self)?;
+                self.proc_mut().memory_mut().copy_out(addr.into(), &stats)?;
+            }
+            IOSCHED_QSTAT => {
+                let stats = IoQueueStats {
+                    requests: NREQUEST.load(Ordering::Relaxed),
+                    blocks: NBLOCK.load(Ordering::Relaxed),
+                };
+                self.proc_mut().memory_mut().copy_out(addr.into(), &stats)?;
+            }
+            _ => return Err(()),
+        }
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of disk I/O scheduling.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(82, |ctx| ctx.sys_iosched());
+}
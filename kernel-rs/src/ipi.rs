@@ -0,0 +1,144 @@
+//! Inter-processor interrupts (IPIs).
+//!
+//! A CPU sends a message to another CPU by setting the bit of the message in the set of pending
+//! messages of the other CPU, and raising an IPI on it. The receiving CPU handles all of its
+//! pending messages when it takes the IPI. Since the messages are kept in memory, an IPI may
+//! deliver several messages, and messages sent to the same CPU before it handles them are merged.
+//! Pending messages are also handled on timer interrupts, so a message is handled within a tick
+//! even if its IPI is lost.
+//!
+//! A TLB shootdown is sent only to the CPUs that may have TLB entries of the user page table
+//! whose entries have changed, which the `UserMemory` keeps track of. A sender numbers its
+//! request with the request generation of the target, and waits until the flush generation of
+//! the target reaches the number. A target reads its request generation before flushing its
+//! TLB, and publishes it as its flush generation afterwards, so one flush serves every request
+//! made until then.
+
+// Dead code is allowed in this file because the kernel does not send every message yet.
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use crate::{
+    arch::interface::{InterruptManager, PageTableManager, ProcManager},
+    arch::TargetArch,
+    hal::hal,
+    param::NCPU,
+    some_or,
+    util::spin_loop,
+};
+
+/// A message sent by an IPI.
+#[derive(Clone, Copy, Debug)]
+#[repr(usize)]
+pub enum IpiMessage {
+    /// Gives up the CPU at the end of the trap, so the scheduler runs again.
+    Reschedule = 0,
+
+    /// Flushes the TLB of the CPU. The sender waits until the CPU has done so.
+    TlbShootdown = 1,
+
+    /// Stops the CPU forever. Sent by a panicking CPU.
+    StopAll = 2,
+}
+
+impl IpiMessage {
+    const fn bit(self) -> usize {
+        1 << self as usize
+    }
+}
+
+pub struct Ipi {
+    /// The messages each CPU has not handled yet.
+    pending: [AtomicUsize; NCPU],
+
+    /// Number of TLB shootdowns requested to each CPU.
+    tlb_requests: [AtomicUsize; NCPU],
+
+    /// Number of the TLB shootdowns requested to each CPU that it has completed.
+    tlb_flushes: [AtomicUsize; NCPU],
+}
+
+impl Ipi {
+    pub const fn new() -> Self {
+        Self {
+            pending: array![_ => AtomicUsize::new(0); NCPU],
+            tlb_requests: array![_ => AtomicUsize::new(0); NCPU],
+            tlb_flushes: array![_ => AtomicUsize::new(0); NCPU],
+        }
+    }
+
+    /// Sends `msg` to the CPU `cpu`.
+    pub fn send(&self, cpu: usize, msg: IpiMessage) {
+        let _ = self.pending[cpu].fetch_or(msg.bit(), Ordering::Release);
+        TargetArch::send_ipi(cpu);
+    }
+
+    /// Sends `msg` to every CPU except the current one.
+    /// Must be called with interrupts disabled.
+    pub fn send_others(&self, msg: IpiMessage) {
+        let id = TargetArch::cpu_id();
+        for cpu in (0..NCPU).filter(|&cpu| cpu != id) {
+            self.send(cpu, msg);
+        }
+    }
+
+    /// Flushes the TLBs of the CPUs in the set `cpus`, where bit i stands for CPU i, and returns
+    /// after all of them have done so.
+    pub fn tlb_shootdown(&self, cpus: usize) {
+        let intr = hal().cpus().push_off();
+        let id = TargetArch::cpu_id();
+        let mut targets = [None; NCPU];
+        for (cpu, target) in targets.iter_mut().enumerate() {
+            if cpus & (1 << cpu) == 0 {
+                continue;
+            }
+            if cpu == id {
+                TargetArch::flush_tlb();
+            } else {
+                *target = Some(self.tlb_requests[cpu].fetch_add(1, Ordering::SeqCst) + 1);
+                self.send(cpu, IpiMessage::TlbShootdown);
+            }
+        }
+
+        for (cpu, request) in targets.iter().enumerate() {
+            let request = some_or!(request, continue);
+            while self.tlb_flushes[cpu].load(Ordering::Acquire) < *request {
+                // Another CPU may be waiting for this CPU, with interrupts disabled.
+                let _ = self.handle_tlb_shootdown();
+                ::core::hint::spin_loop();
+            }
+        }
+        unsafe { hal().cpus().pop_off(intr) };
+    }
+
+    /// Flushes the TLB of the current CPU if it has been asked to.
+    /// Returns true if it has.
+    fn handle_tlb_shootdown(&self) -> bool {
+        let id = TargetArch::cpu_id();
+        let msg = IpiMessage::TlbShootdown.bit();
+        if self.pending[id].fetch_and(!msg, Ordering::SeqCst) & msg == 0 {
+            return false;
+        }
+        let request = self.tlb_requests[id].load(Ordering::SeqCst);
+        TargetArch::flush_tlb();
+        self.tlb_flushes[id].store(request, Ordering::Release);
+        true
+    }
+
+    /// Handles the pending messages of the current CPU.
+    /// Returns true if the CPU must give up itself at the end of the trap.
+    /// Must be called with interrupts disabled.
+    pub fn handle(&self) -> bool {
+        // TLB shootdowns are acknowledged, so leave them to `handle_tlb_shootdown`.
+        let pending = self.pending[TargetArch::cpu_id()]
+            .fetch_and(IpiMessage::TlbShootdown.bit(), Ordering::Acquire);
+        if pending & IpiMessage::StopAll.bit() != 0 {
+            spin_loop();
+        }
+        let _ = self.handle_tlb_shootdown();
+        pending & IpiMessage::Reschedule.bit() != 0
+    }
+}
@@ -0,0 +1,120 @@
+//! Interrupt statistics.
+//!
+//! Every device interrupt dispatched by `handle_irq()` is counted by IRQ number and by the CPU
+//! that serviced it, together with the cycles its handler took. `irqstat()` copies the
+//! statistics of the IRQs that have fired to user space.
+
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+use zerocopy::AsBytes;
+
+use crate::{
+    addr::UVAddr,
+    param::{NCPU, NIRQ},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+    trap::IrqNum,
+};
+
+struct IrqEntry {
+    /// Number of interrupts serviced by each CPU
+    count: [AtomicUsize; NCPU],
+
+    /// Cycles spent in the handler
+    cycles: AtomicUsize,
+
+    /// Cycles spent in the slowest run of the handler
+    max_cycles: AtomicUsize,
+}
+
+pub struct IrqStats {
+    entries: [IrqEntry; NIRQ],
+}
+
+/// Statistics of an IRQ, read by `irqstat()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct IrqStat {
+    pub irq: usize,
+    pub count: [usize; NCPU],
+    pub cycles: usize,
+    pub max_cycles: usize,
+}
+
+impl IrqEntry {
+    const fn new() -> Self {
+        Self {
+            count: array![_ => AtomicUsize::new(0); NCPU],
+            cycles: AtomicUsize::new(0),
+            max_cycles: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl IrqStats {
+    pub const fn new() -> Self {
+        Self {
+            entries: array![_ => IrqEntry::new(); NIRQ],
+        }
+    }
+
+    /// Accounts for an interrupt of `irq` serviced by CPU `cpu`, whose handler took `cycles`.
+    /// IRQs numbered NIRQ or above are not accounted for.
+    pub fn record(&self, irq: IrqNum, cpu: usize, cycles: usize) {
+        if let Some(entry) = self.entries.get(irq) {
+            let _ = entry.count[cpu].fetch_add(1, Ordering::Relaxed);
+            let _ = entry.cycles.fetch_add(cycles, Ordering::Relaxed);
+            let _ = entry.max_cycles.fetch_max(cycles, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the statistics of `irq`, or None if it has not fired.
+    pub fn get(&self, irq: IrqNum) -> Option<IrqStat> {
+        let entry = self.entries.get(irq)?;
+        let mut stat = IrqStat {
+            irq,
+            ..Default::default()
+        };
+        for (count, c) in stat.count.iter_mut().zip(entry.count.iter()) {
+            *count = c.load(Ordering::Relaxed);
+        }
+        if stat.count.iter().all(|&count| count == 0) {
+            return None;
+        }
+        stat.cycles = entry.cycles.load(Ordering::Relaxed);
+        stat.max_cycles = entry.max_cycles.load(Ordering::Relaxed);
+        Some(stat)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Copy the statistics of the first n IRQs that have fired into the array of struct irqstat
+    /// at addr.
+    /// Returns Ok(number of statistics copied) on success, Err(()) on error.
+    pub fn sys_irqstat(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let n = self.proc().argint(1)?;
+        if n < 0 {
+            return Err(());
+        }
+        let mut stored = 0;
+        for irq in 0..NIRQ {
+            if stored == n as usize {
+                break;
+            }
+            if let Some(stat) = self.kernel().irq_stats().get(irq) {
+                let dst = UVAddr::from(addr) + stored * mem::size_of::<IrqStat>();
+                self.proc_mut().memory_mut().copy_out(dst, &stat)?;
+                stored += 1;
+            }
+        }
+        Ok(stored)
+    }
+}
+
+/// Registers the system calls of interrupt statistics.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(53, |ctx| ctx.sys_irqstat());
+}
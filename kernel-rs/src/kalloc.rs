@@ -1,12 +1,25 @@
 //! Physical memory allocator, for user processes,
 //! kernel stacks, page-table pages,
 //! and pipe buffers. Allocates whole 4096-byte pages.
-use core::{mem, pin::Pin};
+//!
+//! A page may be shared, such as a page of the page cache that processes map. Each `share` makes
+//! another `Page` of the same address, and the page returns to the free list only when every one
+//! of them is freed.
+//!
+//! The zero page is a page of zeros that is never freed. A page of zeros, such as a page a process
+//! has not written yet or a page freed by `madvise()`, may be a share of it until it is written.
+use core::{
+    cell::Cell,
+    mem::{self, ManuallyDrop},
+    pin::Pin,
+};
 
+use array_macro::array;
 use pin_project::pin_project;
 
 use crate::{
-    addr::{pgrounddown, pgroundup, PGSIZE},
+    addr::{pgrounddown, pgroundup, PAddr, PGSIZE},
+    arch::{interface::MemLayout, TargetArch},
     lock::SpinLock,
     memlayout::PHYSTOP,
     page::Page,
@@ -65,6 +78,26 @@ unsafe impl ListNode for Run {
 pub struct Kmem {
     #[pin]
     runs: List<Run>,
+
+    /// Number of pages in `runs`
+    nfree: Cell<usize>,
+
+    /// Number of pages managed by the allocator
+    ntotal: usize,
+
+    /// Number of the `Page`s of each page, beyond the first, indexed by `index`.
+    shares: [Cell<u32>; NPHYSPAGE],
+
+    /// Address of the zero page
+    zero: usize,
+}
+
+/// Number of the pages of physical memory.
+const NPHYSPAGE: usize = (PHYSTOP - TargetArch::KERNBASE) / PGSIZE;
+
+/// Returns the index of `page` among the pages of physical memory.
+fn index(page: &Page) -> usize {
+    (page.addr().into_usize() - TargetArch::KERNBASE) / PGSIZE
 }
 
 impl Kmem {
@@ -74,6 +107,10 @@ impl Kmem {
     pub const unsafe fn new() -> Self {
         Self {
             runs: unsafe { List::new() },
+            nfree: Cell::new(0),
+            ntotal: 0,
+            shares: array![_ => Cell::new(0); NPHYSPAGE],
+            zero: 0,
         }
     }
 
@@ -97,9 +134,23 @@ impl Kmem {
             //   created page does not overlap with existing pages
             self.as_ref().free(unsafe { Page::from_usize(pa) });
         }
+        let nfree = self.nfree.get();
+        *self.as_mut().project().ntotal = nfree;
+
+        let mut zero = self.as_ref().alloc().expect("Kmem::init");
+        zero.write_bytes(0);
+        *self.project().zero = zero.into_usize();
     }
 
     pub fn free(self: Pin<&Self>, mut page: Page) {
+        let shares = &self.shares[index(&page)];
+        if shares.get() > 0 {
+            // Another `Page` of the same address is still alive.
+            shares.set(shares.get() - 1);
+            mem::forget(page);
+            return;
+        }
+
         // Fill with junk to catch dangling refs.
         page.write_bytes(1);
 
@@ -109,6 +160,7 @@ impl Kmem {
         let mut run = unsafe { Pin::new_unchecked(run) };
         run.as_mut().init();
         self.runs().push_front(run.as_ref());
+        self.nfree.set(self.nfree.get() + 1);
 
         // Since the page has returned to the list, forget the page.
         mem::forget(page);
@@ -116,6 +168,7 @@ impl Kmem {
 
     pub fn alloc(self: Pin<&Self>) -> Option<Page> {
         let run = self.runs().pop_front()?;
+        self.nfree.set(self.nfree.get() - 1);
         // SAFETY: the invariant of `Kmem`.
         let mut page = unsafe { Page::from_usize(run as _) };
         // fill with junk
@@ -123,6 +176,43 @@ impl Kmem {
         Some(page)
     }
 
+    /// Returns another `Page` of the same address as `page`. The page is not freed until both
+    /// are freed. Its contents must not be modified while it is shared.
+    pub fn share(&self, page: &Page) -> Page {
+        let shares = &self.shares[index(page)];
+        shares.set(shares.get().checked_add(1).expect("Kmem::share"));
+        // SAFETY: the page is not freed while any `Page` of it is alive, and no `Page` of it
+        // modifies it while it is shared.
+        unsafe { Page::from_usize(page.addr().into_usize()) }
+    }
+
+    /// Returns whether there is another `Page` of the same address as `page`.
+    pub fn is_shared(&self, page: &Page) -> bool {
+        self.shares[index(page)].get() > 0
+    }
+
+    /// Returns the number of `Page`s of the same address as `page`, including `page`.
+    pub fn share_count(&self, page: &Page) -> usize {
+        self.shares[index(page)].get() as usize + 1
+    }
+
+    /// Returns a `Page` of the zero page.
+    pub fn zero_page(&self) -> Page {
+        // SAFETY: the zero page is never freed.
+        let zero = ManuallyDrop::new(unsafe { Page::from_usize(self.zero) });
+        self.share(&zero)
+    }
+
+    /// Returns whether `pa` is the address of the zero page.
+    pub fn is_zero_page(&self, pa: PAddr) -> bool {
+        pa.into_usize() == self.zero
+    }
+
+    /// Returns (number of free pages, number of pages managed by the allocator).
+    pub fn stat(&self) -> (usize, usize) {
+        (self.nfree.get(), self.ntotal)
+    }
+
     fn runs(self: Pin<&Self>) -> Pin<&List<Run>> {
         unsafe { Pin::new_unchecked(&self.get_ref().runs) }
     }
@@ -136,4 +226,28 @@ impl SpinLock<Kmem> {
     pub fn alloc(self: Pin<&Self>) -> Option<Page> {
         self.pinned_lock().get_pin_mut().as_ref().alloc()
     }
+
+    pub fn share(self: Pin<&Self>, page: &Page) -> Page {
+        self.pinned_lock().share(page)
+    }
+
+    pub fn is_shared(self: Pin<&Self>, page: &Page) -> bool {
+        self.pinned_lock().is_shared(page)
+    }
+
+    pub fn share_count(self: Pin<&Self>, page: &Page) -> usize {
+        self.pinned_lock().share_count(page)
+    }
+
+    pub fn zero_page(self: Pin<&Self>) -> Page {
+        self.pinned_lock().zero_page()
+    }
+
+    pub fn is_zero_page(self: Pin<&Self>, pa: PAddr) -> bool {
+        self.pinned_lock().is_zero_page(pa)
+    }
+
+    pub fn stat(self: Pin<&Self>) -> (usize, usize) {
+        self.pinned_lock().stat()
+    }
 }
@@ -2,29 +2,56 @@ use core::fmt::{self, Write};
 use core::mem::MaybeUninit;
 use core::ops::Deref;
 use core::pin::Pin;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use pin_project::pin_project;
 
 use crate::util::strong_pin::StrongPin;
 use crate::{
-    arch::interface::Arch,
+    arch::interface::{Arch, TrapManager},
     arch::TargetArch,
     bio::Bcache,
-    console::{console_read, console_write},
+    clock::{self, WallClock},
+    config,
+    console::{self, console_read, console_write, tty_read, tty_write, PrinterGuard, Severity},
     cpu::cpuid,
+    cycles, device,
+    epoll::{self, EpollTable},
+    eventfd::{self, EventFdTable},
+    exec::ExecCache,
+    fdinfo,
     file::{Devsw, FileTable},
-    fs::{DefaultFs, FileSystem},
+    fs::{DefaultFs, FileSystem, PageCache},
+    gang::{self, Gangs},
     hal::{hal, hal_init},
+    iosched,
+    ipi::IpiMessage,
+    irqstat::{self, IrqStats},
     kalloc::Kmem,
+    ksm::{self, Ksm},
+    ktrace, linux, load,
     lock::{SleepableLock, SpinLock},
-    param::NDEV,
+    lockstat, meminfo,
+    mqueue::{self, MsgQueueTable},
+    net::{self, Net},
+    param::{NDEV, NSEED},
+    perf,
+    power::{self, Power},
+    prctl,
     proc::Procs,
+    random::Entropy,
+    rlimit,
+    semaphore::{self, SemaphoreTable},
+    syscall::{self, SyscallTable},
     util::{branded::Branded, spin_loop},
-    vm::KernelMemory,
+    virtio,
+    vm::{AsidAllocator, KernelMemory},
+    watch::{self, WatchTable},
+    wss, zram,
 };
 
-const CONSOLE_IN_DEVSW: usize = 1;
+pub const CONSOLE_IN_DEVSW: usize = 1;
+const TTY_DEVSW: usize = 2;
 
 /// The kernel.
 static mut KERNEL: Kernel<TargetArch> = unsafe { Kernel::new() };
@@ -68,11 +95,22 @@ unsafe fn kernel_mut_unchecked<'s>() -> Pin<&'s mut Kernel<TargetArch>> {
 /// TODO: replace all the arch-dependent parts with generic or `TargetArch`.
 #[pin_project]
 pub struct Kernel<A: Arch> {
+    /// Has any CPU panicked? Then the other CPUs stop the next time they trap or print, which a
+    /// `StopAll` IPI makes happen at once.
     panicked: AtomicBool,
 
+    /// Bit i is set if CPU i has panicked.
+    panicked_cpus: AtomicUsize,
+
+    /// Serializes the messages of CPUs that panic at the same time.
+    panic_lock: AtomicBool,
+
     /// The kernel's memory manager.
     memory: MaybeUninit<KernelMemory<A>>,
 
+    /// ASIDs of user page tables.
+    asids: AsidAllocator,
+
     ticks: SleepableLock<u32>,
 
     /// Current process system.
@@ -82,6 +120,14 @@ pub struct Kernel<A: Arch> {
     #[pin]
     bcache: Bcache,
 
+    page_cache: PageCache,
+
+    exec_cache: ExecCache,
+
+    ksm: Ksm,
+
+    gangs: Gangs,
+
     devsw: [Devsw; NDEV],
 
     #[pin]
@@ -89,6 +135,30 @@ pub struct Kernel<A: Arch> {
 
     #[pin]
     file_system: DefaultFs,
+
+    watches: WatchTable,
+
+    epolls: EpollTable,
+
+    eventfds: EventFdTable,
+
+    mqueues: MsgQueueTable,
+
+    sems: SemaphoreTable,
+
+    net: Net,
+
+    irq_stats: IrqStats,
+
+    power: Power,
+
+    entropy: Entropy,
+
+    clock: WallClock,
+
+    syscalls: SyscallTable,
+
+    linux_syscalls: SyscallTable,
 }
 
 /// A branded reference to a `Kernel`.
@@ -119,6 +189,17 @@ impl<'id, 's> KernelRef<'id, 's> {
         self.0.into_inner().as_pin()
     }
 
+    /// Returns a reference to the kernel's memory manager.
+    pub fn memory(&self) -> &'s KernelMemory<TargetArch> {
+        // SAFETY: `memory` is initialized by `Kernel::init`, before any process runs.
+        unsafe { self.0.as_pin().get_ref().memory.assume_init_ref() }
+    }
+
+    /// Returns a reference to the kernel's `AsidAllocator`.
+    pub fn asids(&self) -> &'s AsidAllocator {
+        &self.0.as_pin().get_ref().asids
+    }
+
     /// Returns a reference to the kernel's ticks.
     pub fn ticks(&self) -> &'s SleepableLock<u32> {
         &self.0.as_pin().get_ref().ticks
@@ -132,6 +213,26 @@ impl<'id, 's> KernelRef<'id, 's> {
         unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().bcache) }
     }
 
+    /// Returns a reference to the kernel's `PageCache`.
+    pub fn page_cache(&self) -> &'s PageCache {
+        &self.0.as_pin().get_ref().page_cache
+    }
+
+    /// Returns a reference to the kernel's `ExecCache`.
+    pub fn exec_cache(&self) -> &'s ExecCache {
+        &self.0.as_pin().get_ref().exec_cache
+    }
+
+    /// Returns a reference to the kernel's `Ksm`.
+    pub fn ksm(&self) -> &'s Ksm {
+        &self.0.as_pin().get_ref().ksm
+    }
+
+    /// Returns a reference to the kernel's `Gangs`.
+    pub fn gangs(&self) -> &'s Gangs {
+        &self.0.as_pin().get_ref().gangs
+    }
+
     /// Returns a reference to the kernel's `Devsw` array.
     pub fn devsw(&self) -> &'s [Devsw; NDEV] {
         &self.0.as_pin().get_ref().devsw
@@ -145,6 +246,66 @@ impl<'id, 's> KernelRef<'id, 's> {
     pub fn ftable(&self) -> StrongPin<'s, FileTable> {
         unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().ftable) }
     }
+
+    /// Returns a reference to the kernel's `WatchTable`.
+    pub fn watches(&self) -> &'s WatchTable {
+        &self.0.as_pin().get_ref().watches
+    }
+
+    /// Returns a reference to the kernel's `EpollTable`.
+    pub fn epolls(&self) -> &'s EpollTable {
+        &self.0.as_pin().get_ref().epolls
+    }
+
+    /// Returns a reference to the kernel's `EventFdTable`.
+    pub fn eventfds(&self) -> &'s EventFdTable {
+        &self.0.as_pin().get_ref().eventfds
+    }
+
+    /// Returns a reference to the kernel's `MsgQueueTable`.
+    pub fn mqueues(&self) -> &'s MsgQueueTable {
+        &self.0.as_pin().get_ref().mqueues
+    }
+
+    /// Returns a reference to the kernel's `SemaphoreTable`.
+    pub fn sems(&self) -> &'s SemaphoreTable {
+        &self.0.as_pin().get_ref().sems
+    }
+
+    /// Returns a reference to the kernel's `Net`.
+    pub fn net(&self) -> &'s Net {
+        &self.0.as_pin().get_ref().net
+    }
+
+    /// Returns a reference to the kernel's `IrqStats`.
+    pub fn irq_stats(&self) -> &'s IrqStats {
+        &self.0.as_pin().get_ref().irq_stats
+    }
+
+    /// Returns a reference to the kernel's `Power`.
+    pub fn power(&self) -> &'s Power {
+        &self.0.as_pin().get_ref().power
+    }
+
+    /// Returns a reference to the kernel's `Entropy`.
+    pub fn entropy(&self) -> &'s Entropy {
+        &self.0.as_pin().get_ref().entropy
+    }
+
+    /// Returns a reference to the kernel's `WallClock`.
+    pub fn clock(&self) -> &'s WallClock {
+        &self.0.as_pin().get_ref().clock
+    }
+
+    /// Returns a reference to the kernel's `SyscallTable`.
+    pub fn syscalls(&self) -> &'s SyscallTable {
+        &self.0.as_pin().get_ref().syscalls
+    }
+
+    /// Returns a reference to the kernel's `SyscallTable` for the Linux ABI.
+    pub fn linux_syscalls(&self) -> &'s SyscallTable {
+        &self.0.as_pin().get_ref().linux_syscalls
+    }
 }
 
 impl<'id, 's> Deref for KernelRef<'id, 's> {
@@ -162,16 +323,35 @@ impl<A: Arch> Kernel<A> {
     const unsafe fn new() -> Self {
         Self {
             panicked: AtomicBool::new(false),
+            panicked_cpus: AtomicUsize::new(0),
+            panic_lock: AtomicBool::new(false),
             memory: MaybeUninit::uninit(),
+            asids: AsidAllocator::new(),
             ticks: SleepableLock::new("time", 0),
             procs: Procs::new(),
             bcache: unsafe { Bcache::new_bcache() },
+            page_cache: PageCache::new(),
+            exec_cache: ExecCache::new(),
+            ksm: Ksm::new(),
+            gangs: Gangs::new(),
             devsw: [Devsw {
                 read: None,
                 write: None,
             }; NDEV],
             ftable: FileTable::new_ftable(),
             file_system: DefaultFs::new(),
+            watches: WatchTable::new(),
+            epolls: EpollTable::new(),
+            eventfds: EventFdTable::new(),
+            mqueues: MsgQueueTable::new(),
+            sems: SemaphoreTable::new(),
+            net: Net::new(),
+            irq_stats: IrqStats::new(),
+            power: Power::new(),
+            entropy: Entropy::new(),
+            clock: WallClock::new(),
+            syscalls: SyscallTable::new(),
+            linux_syscalls: SyscallTable::new(),
         }
     }
 
@@ -183,6 +363,15 @@ impl<A: Arch> Kernel<A> {
     unsafe fn init(self: Pin<&mut Self>, allocator: Pin<&SpinLock<Kmem>>) {
         self.as_ref().write_str("\nrv6 kernel is booting\n\n");
 
+        // Create kernel memory manager.
+        let memory = KernelMemory::new(allocator).expect("PageTable::new failed");
+        memory.check_wx(|va| {
+            self.as_ref().log(
+                Severity::Error,
+                format_args!("W^X violation: {:#x} is writable and executable\n", va),
+            )
+        });
+
         let mut this = self.project();
 
         // Connect read and write system calls to consoleread and consolewrite.
@@ -190,16 +379,22 @@ impl<A: Arch> Kernel<A> {
             read: Some(console_read),
             write: Some(console_write),
         };
-
-        // Create kernel memory manager.
-        let memory = KernelMemory::new(allocator).expect("PageTable::new failed");
+        // /dev/tty refers to the controlling terminal of each process.
+        this.devsw[TTY_DEVSW] = Devsw {
+            read: Some(tty_read),
+            write: Some(tty_write),
+        };
+        device::register_devices(this.devsw);
+        console::register_devices(this.devsw);
+        ktrace::register_devices(this.devsw);
+        virtio::register_devices(this.devsw);
 
         // Turn on paging.
         // SAFETY: `memory.page_table` contains base address for a valid kernel page table.
         unsafe { this.memory.write(memory).init_register() };
 
-        // Process system.
-        this.procs.as_mut().init();
+        // Wall clock.
+        this.clock.init();
 
         // Trap vectors.
         A::trap_init();
@@ -219,9 +414,51 @@ impl<A: Arch> Kernel<A> {
         // Buffer cache.
         this.bcache.init();
 
+        // Seed the entropy pool with the virtio entropy device, if there is one.
+        let mut seed = [0; NSEED];
+        if hal()
+            .rng()
+            .pinned_lock()
+            .get_pin_mut()
+            .read(&mut seed)
+            .is_ok()
+        {
+            this.entropy.seed(&seed);
+        }
+
+        // System calls.
+        syscall::register_syscalls(this.syscalls);
+        watch::register_syscalls(this.syscalls);
+        epoll::register_syscalls(this.syscalls);
+        eventfd::register_syscalls(this.syscalls);
+        mqueue::register_syscalls(this.syscalls);
+        semaphore::register_syscalls(this.syscalls);
+        rlimit::register_syscalls(this.syscalls);
+        net::register_syscalls(this.syscalls);
+        irqstat::register_syscalls(this.syscalls);
+        meminfo::register_syscalls(this.syscalls);
+        load::register_syscalls(this.syscalls);
+        fdinfo::register_syscalls(this.syscalls);
+        prctl::register_syscalls(this.syscalls);
+        power::register_syscalls(this.syscalls);
+        clock::register_syscalls(this.syscalls);
+        cycles::register_syscalls(this.syscalls);
+        perf::register_syscalls(this.syscalls);
+        iosched::register_syscalls(this.syscalls);
+        config::register_syscalls(this.syscalls);
+        linux::register_syscalls(this.syscalls);
+        wss::register_syscalls(this.syscalls);
+        ksm::register_syscalls(this.syscalls);
+        zram::register_syscalls(this.syscalls);
+        gang::register_syscalls(this.syscalls);
+        lockstat::register_syscalls(this.syscalls);
+        linux::register_linux_syscalls(this.linux_syscalls);
+
         // First user process.
         let fs = unsafe { StrongPin::new_unchecked(this.file_system.as_ref().get_ref()) };
-        this.procs.user_proc_init(fs.root(), allocator);
+        // SAFETY: `memory` has been initialized above.
+        let memory = unsafe { this.memory.assume_init_ref() };
+        this.procs.user_proc_init(fs.root(), memory, allocator);
     }
 
     /// Initializes the kernel for a core.
@@ -245,7 +482,9 @@ impl<A: Arch> Kernel<A> {
         unsafe { A::intr_init_core() };
     }
 
+    /// Marks the current CPU as panicked. Must be called with interrupts disabled.
     fn panic(self: Pin<&Self>) {
+        let _ = self.panicked_cpus.fetch_or(1 << cpuid(), Ordering::AcqRel);
         self.panicked.store(true, Ordering::Release);
     }
 
@@ -253,13 +492,31 @@ impl<A: Arch> Kernel<A> {
         self.panicked.load(Ordering::Acquire)
     }
 
-    /// Prints the given formatted string with the Printer.
-    pub fn write_fmt(self: Pin<&Self>, args: fmt::Arguments<'_>) {
-        let mut guard = if self.is_panicked() {
+    /// Returns true if another CPU has panicked, so the current CPU must stop.
+    /// Must be called with interrupts disabled.
+    pub fn should_freeze(self: Pin<&Self>) -> bool {
+        self.is_panicked() && self.panicked_cpus.load(Ordering::Acquire) & (1 << cpuid()) == 0
+    }
+
+    /// Returns a guard of the Printer, which is not locked after a panic.
+    fn printer(self: Pin<&Self>) -> PrinterGuard<'_, A> {
+        if self.is_panicked() {
             hal().get_ref().printer().without_lock(self)
         } else {
             hal().get_ref().printer().lock(self)
-        };
+        }
+    }
+
+    /// Prints the given formatted string with the Printer.
+    pub fn write_fmt(self: Pin<&Self>, args: fmt::Arguments<'_>) {
+        let _ = self.printer().write_fmt(args);
+    }
+
+    /// Prints the given formatted string with the Printer as a kernel message of `severity`,
+    /// which starts on a new line after a prefix that tells the severity.
+    pub fn log(self: Pin<&Self>, severity: Severity, args: fmt::Arguments<'_>) {
+        let mut guard = self.printer();
+        guard.begin(severity);
         let _ = guard.write_fmt(args);
     }
 
@@ -269,12 +526,27 @@ impl<A: Arch> Kernel<A> {
 }
 
 /// Handles panic by freezing other CPUs.
+/// They stop the next time they trap, which the `StopAll` IPI makes happen at once, or print.
+/// If several CPUs panic at the same time, their messages are printed one by one.
 #[cfg(not(test))]
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo<'_>) -> ! {
+    // This CPU never enables interrupts again.
+    TargetArch::intr_off();
     let kernel = kernel().as_pin();
     kernel.panic();
-    kernel.write_fmt(format_args!("{}\n", info));
+    hal().ipi().send_others(IpiMessage::StopAll);
+
+    while kernel
+        .panic_lock
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        ::core::hint::spin_loop();
+    }
+    // The printer's lock may be held by a frozen CPU, so `write_fmt` does not take it.
+    kernel.log(Severity::Error, format_args!("cpu {}: {}\n", cpuid(), info));
+    kernel.panic_lock.store(false, Ordering::Release);
 
     spin_loop()
 }
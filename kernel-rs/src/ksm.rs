@@ -0,0 +1,233 @@
+//! Same-page merging.
+//!
+//! When enabled by `ksm()`, every process scans some of its writable pages on each timer interrupt
+//! while it runs in user space, resuming where its last scan stopped. There are no kernel threads
+//! to scan in the background, so the processes scan themselves, as they do for working-set
+//! estimation. A page of zeros is replaced by the zero page. Otherwise, the page is looked up by
+//! the hash of its contents in a table of stable pages, and replaced by a copy-on-write share of
+//! the stable page of the same contents if there is one. A page that is not found becomes a
+//! stable page itself, copy-on-write as well, so that the pages of other processes may be merged
+//! into it. A stable page that no process maps anymore is recycled first, and one that saves no
+//! memory next.
+
+use core::{
+    mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+use zerocopy::AsBytes;
+
+use crate::{
+    hal::hal, lock::SpinLock, page::Page, param::NKSM, proc::KernelCtx, syscall::SyscallTable,
+};
+
+/// Statistics of same-page merging, read by `ksm()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct KsmInfo {
+    /// Pages each process scans on a timer interrupt, or 0 if merging is disabled
+    pub pages_to_scan: usize,
+
+    /// Number of writable pages scanned
+    pub scanned: usize,
+
+    /// Number of pages replaced by a stable page
+    pub merged: usize,
+
+    /// Number of pages replaced by the zero page
+    pub zero: usize,
+
+    /// Number of stable pages
+    pub stable: usize,
+
+    /// Number of mappings of the stable pages, which take `stable` pages of memory
+    pub sharing: usize,
+}
+
+/// What becomes of a page scanned for merging.
+pub enum Merge {
+    /// The page is left as it is.
+    Keep,
+
+    /// The page has become a stable page, and must be mapped copy-on-write.
+    Protect,
+
+    /// The page must be replaced by the given page of the same contents, copy-on-write.
+    Replace(Page),
+}
+
+struct KsmEntry {
+    hash: u64,
+    page: Page,
+}
+
+struct KsmInner {
+    stable: [Option<KsmEntry>; NKSM],
+
+    /// The entry recycled last when every stable page is mapped
+    clock: usize,
+
+    scanned: usize,
+    merged: usize,
+    zero: usize,
+}
+
+pub struct Ksm {
+    /// Pages each process scans on a timer interrupt, or 0 if merging is disabled.
+    pages_to_scan: AtomicUsize,
+
+    inner: SpinLock<KsmInner>,
+}
+
+/// Returns the FNV-1a hash of `page`.
+fn hash(page: &Page) -> u64 {
+    page.iter().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl KsmInner {
+    /// Frees the stable pages.
+    fn clear(&mut self) {
+        for entry in self.stable.iter_mut().filter_map(Option::take) {
+            hal().kmem().free(entry.page);
+        }
+    }
+}
+
+impl Ksm {
+    pub const fn new() -> Self {
+        Self {
+            pages_to_scan: AtomicUsize::new(0),
+            inner: SpinLock::new(
+                "ksm",
+                KsmInner {
+                    stable: array![_ => None; NKSM],
+                    clock: 0,
+                    scanned: 0,
+                    merged: 0,
+                    zero: 0,
+                },
+            ),
+        }
+    }
+
+    pub fn pages_to_scan(&self) -> usize {
+        self.pages_to_scan.load(Ordering::Relaxed)
+    }
+
+    /// Decides what becomes of `page`, a writable page of a process.
+    pub fn merge(&self, page: &Page) -> Merge {
+        let kmem = hal().kmem();
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        inner.scanned += 1;
+        if page.iter().all(|b| *b == 0) {
+            inner.zero += 1;
+            return Merge::Replace(kmem.zero_page());
+        }
+
+        let hash = hash(page);
+        if let Some(entry) = inner
+            .stable
+            .iter()
+            .flatten()
+            .find(|e| e.hash == hash && e.page[..] == page[..])
+        {
+            inner.merged += 1;
+            return Merge::Replace(kmem.share(&entry.page));
+        }
+
+        // Recycle a stable page that no process maps, or else one that only a single process maps
+        // and so saves no memory, in turn. Keep the page as it is if every stable page saves memory.
+        let index = match inner
+            .stable
+            .iter()
+            .position(|e| e.as_ref().map_or(true, |e| !kmem.is_shared(&e.page)))
+        {
+            Some(index) => index,
+            None => {
+                let stable = &inner.stable;
+                let clock = inner.clock;
+                match (1..=NKSM).map(|i| (clock + i) % NKSM).find(|i| {
+                    stable[*i]
+                        .as_ref()
+                        .map_or(false, |e| kmem.share_count(&e.page) <= 2)
+                }) {
+                    Some(index) => {
+                        inner.clock = index;
+                        index
+                    }
+                    None => return Merge::Keep,
+                }
+            }
+        };
+        let entry = KsmEntry {
+            hash,
+            page: kmem.share(page),
+        };
+        if let Some(old) = mem::replace(&mut inner.stable[index], Some(entry)) {
+            kmem.free(old.page);
+        }
+        Merge::Protect
+    }
+
+    /// Returns the statistics of merging.
+    pub fn info(&self) -> KsmInfo {
+        let kmem = hal().kmem();
+        let guard = self.inner.lock();
+        let stable = guard.stable.iter().flatten();
+        KsmInfo {
+            pages_to_scan: self.pages_to_scan(),
+            scanned: guard.scanned,
+            merged: guard.merged,
+            zero: guard.zero,
+            stable: stable.clone().count(),
+            sharing: stable.map(|e| kmem.share_count(&e.page)).sum(),
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Scans the pages of the current process for merging, if merging is enabled.
+    pub fn merge_same_pages(&mut self) {
+        let ksm = self.kernel().ksm();
+        let n = ksm.pages_to_scan();
+        if n == 0 {
+            return;
+        }
+        self.proc_mut()
+            .memory_mut()
+            .merge_pages(n, |page| ksm.merge(page), hal().kmem());
+    }
+
+    /// Make every process scan pages pages for merging on each timer interrupt, or disable
+    /// merging and free the stable pages if pages is 0, and leave it as it is if pages is -1.
+    /// Then, place the statistics into struct ksminfo at addr, if addr is not 0.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_ksm(&mut self) -> Result<usize, ()> {
+        let pages = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?;
+        let ksm = self.kernel().ksm();
+        match pages {
+            -1 => {}
+            0 => {
+                ksm.pages_to_scan.store(0, Ordering::Relaxed);
+                ksm.inner.lock().clear();
+            }
+            _ if pages > 0 => ksm.pages_to_scan.store(pages as usize, Ordering::Relaxed),
+            _ => return Err(()),
+        }
+        if addr != 0 {
+            let info = ksm.info();
+            self.proc_mut().memory_mut().copy_out(addr.into(), &info)?;
+        }
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of same-page merging.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(115, |ctx| ctx.sys_ksm());
+}
@@ -0,0 +1,388 @@
+//! Scheduling and disk I/O event tracing.
+//!
+//! Writing "1" to /dev/trace starts recording events into a ring buffer from empty, and writing
+//! "0" stops it. Reading /dev/trace consumes the recorded events as text in the format of the
+//! ftrace `trace` file of Linux, so that a trace of a benchmark run copied to the host can be
+//! loaded by tools that read ftrace text, such as `trace-cmd` or KernelShark. The events are
+//! those of Linux: `sched_switch`, `sched_wakeup`, `block_rq_issue`, and `block_rq_complete`.
+//!
+//! When the ring buffer is full, the oldest events are overwritten. A process is shown with the
+//! name exec() gave it, and every process has the default priority of Linux.
+
+use core::{
+    cmp,
+    fmt::{self, Write},
+    str,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    addr::UVAddr,
+    arch::interface::TimeManager,
+    arch::TargetArch,
+    cpu::cpuid,
+    file::Devsw,
+    lock::SpinLock,
+    param::{MAXPROCNAME, NDEV, NTRACE},
+    proc::KernelCtx,
+};
+
+/// Major device number of /dev/trace.
+const TRACE_DEVSW: usize = 9;
+
+/// Maximum length of a line of the trace.
+const MAXLINE: usize = 200;
+
+/// Header of the ftrace `trace` file, read first after tracing starts.
+const HEADER: &str = "# tracer: nop\n\
+                      #\n\
+                      #           TASK-PID     CPU#   TIMESTAMP  FUNCTION\n\
+                      #              | |         |       |         |\n";
+
+/// Is tracing on? Checked before taking the lock of the buffer.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static TRACE: SpinLock<TraceBuf> = SpinLock::new("trace", TraceBuf::new());
+
+/// A process in an event. Pid 0 is the scheduler of a CPU.
+#[derive(Copy, Clone)]
+pub struct Task {
+    pid: i32,
+    comm: [u8; MAXPROCNAME],
+}
+
+/// What happened.
+#[derive(Copy, Clone)]
+pub enum Event {
+    /// The CPU switched from prev, whose state is given as by ftrace, to next.
+    Switch {
+        prev: Task,
+        prev_state: u8,
+        next: Task,
+    },
+
+    /// The process became runnable.
+    Wakeup { task: Task },
+
+    /// The process issued a disk request.
+    BlockIssue {
+        write: bool,
+        sector: usize,
+        nsector: usize,
+        task: Task,
+    },
+
+    /// A disk request completed.
+    BlockComplete {
+        write: bool,
+        sector: usize,
+        nsector: usize,
+    },
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    /// Uptime in microseconds.
+    us: usize,
+
+    cpu: usize,
+
+    /// Pid of the process running on the CPU.
+    pid: i32,
+
+    event: Event,
+}
+
+struct TraceBuf {
+    entries: [Option<Entry>; NTRACE],
+
+    /// Index of the oldest entry.
+    head: usize,
+
+    /// Number of entries.
+    len: usize,
+
+    /// Is the header still to be read?
+    header: bool,
+}
+
+/// Formats a line into a fixed buffer, cutting what does not fit.
+struct Line {
+    buf: [u8; MAXLINE],
+    len: usize,
+}
+
+impl Task {
+    /// The scheduler.
+    pub const IDLE: Self = Self {
+        pid: 0,
+        comm: [0; MAXPROCNAME],
+    };
+
+    pub fn new(pid: i32, name: &[u8]) -> Self {
+        let mut comm = [0; MAXPROCNAME];
+        let len = cmp::min(name.len(), MAXPROCNAME);
+        comm[..len].copy_from_slice(&name[..len]);
+        Self { pid, comm }
+    }
+
+    fn comm(&self) -> &str {
+        let len = self
+            .comm
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(MAXPROCNAME);
+        str::from_utf8(&self.comm[..len]).unwrap_or("???")
+    }
+}
+
+/// The name of a task in the fields of an event on the CPU.
+struct TaskName<'a>(&'a Task, usize);
+
+impl fmt::Display for TaskName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.pid == 0 {
+            write!(f, "swapper/{}", self.1)
+        } else {
+            f.write_str(self.0.comm())
+        }
+    }
+}
+
+impl TraceBuf {
+    const fn new() -> Self {
+        Self {
+            entries: [None; NTRACE],
+            head: 0,
+            len: 0,
+            header: false,
+        }
+    }
+
+    fn push(&mut self, entry: Entry) {
+        if self.len == NTRACE {
+            self.head = (self.head + 1) % NTRACE;
+            self.len -= 1;
+        }
+        self.entries[(self.head + self.len) % NTRACE] = Some(entry);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Entry> {
+        if self.len == 0 {
+            return None;
+        }
+        let entry = self.entries[self.head].take();
+        self.head = (self.head + 1) % NTRACE;
+        self.len -= 1;
+        entry
+    }
+
+    /// Puts back the entry that `pop` returned.
+    fn unpop(&mut self, entry: Entry) {
+        self.head = (self.head + NTRACE - 1) % NTRACE;
+        self.entries[self.head] = Some(entry);
+        self.len += 1;
+    }
+
+    /// Empties the buffer, and makes the header be read first.
+    fn reset(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.header = true;
+    }
+}
+
+impl Entry {
+    /// Formats the entry as a line of the ftrace `trace` file.
+    fn write_line(&self, line: &mut Line) -> fmt::Result {
+        let cpu = self.cpu;
+        let task = match &self.event {
+            Event::Switch { prev, .. } if prev.pid != 0 => prev.comm(),
+            _ if self.pid == 0 => "<idle>",
+            _ => "<...>",
+        };
+        write!(
+            line,
+            "{:>16}-{:<7} [{:03}] {:>5}.{:06}: ",
+            task,
+            self.pid,
+            self.cpu,
+            self.us / 1_000_000,
+            self.us % 1_000_000
+        )?;
+        match &self.event {
+            Event::Switch {
+                prev,
+                prev_state,
+                next,
+            } => {
+                writeln!(
+                    line,
+                    "sched_switch: prev_comm={} prev_pid={} prev_prio=120 prev_state={} ==> \
+                 next_comm={} next_pid={} next_prio=120",
+                    TaskName(prev, cpu),
+                    prev.pid,
+                    *prev_state as char,
+                    TaskName(next, cpu),
+                    next.pid
+                )
+            }
+            Event::Wakeup { task } => {
+                writeln!(
+                    line,
+                    "sched_wakeup: comm={} pid={} prio=120 target_cpu={:03}",
+                    TaskName(task, cpu),
+                    task.pid,
+                    cpu
+                )
+            }
+            Event::BlockIssue {
+                write,
+                sector,
+                nsector,
+                task,
+            } => {
+                writeln!(
+                    line,
+                    "block_rq_issue: 1,0 {} {} () {} + {} [{}]",
+                    if *write { "W" } else { "R" },
+                    nsector * 512,
+                    sector,
+                    nsector,
+                    TaskName(task, cpu)
+                )
+            }
+            Event::BlockComplete {
+                write,
+                sector,
+                nsector,
+            } => {
+                writeln!(
+                    line,
+                    "block_rq_complete: 1,0 {} () {} + {} [0]",
+                    if *write { "W" } else { "R" },
+                    sector,
+                    nsector
+                )
+            }
+        }
+    }
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            buf: [0; MAXLINE],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl fmt::Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = cmp::min(s.len(), MAXLINE - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Is tracing on? Callers check this before gathering the data of an event.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Records `event`, which happened while the process pid ran on the current CPU.
+pub fn record(pid: i32, event: Event) {
+    if !enabled() {
+        return;
+    }
+    let entry = Entry {
+        us: TargetArch::uptime_as_micro().unwrap_or(0),
+        cpu: cpuid(),
+        pid,
+        event,
+    };
+    TRACE.lock().push(entry);
+}
+
+/// User read()s from /dev/trace go here. Whole lines are read, and 0 is returned when no event
+/// is left.
+fn trace_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    if n < 0 {
+        return -1;
+    }
+    let mut read = 0;
+    loop {
+        let mut line = Line::new();
+        let mut trace = TRACE.lock();
+        let entry = if trace.header {
+            let _ = line.write_str(HEADER);
+            None
+        } else {
+            match trace.pop() {
+                Some(entry) => {
+                    let _ = entry.write_line(&mut line);
+                    Some(entry)
+                }
+                None => break,
+            }
+        };
+        if read + line.len > n as usize {
+            // Put the entry back for the next read.
+            if let Some(entry) = entry {
+                trace.unpop(entry);
+            }
+            break;
+        }
+        trace.header = false;
+        drop(trace);
+        if ctx
+            .proc_mut()
+            .memory_mut()
+            .copy_out_bytes(dst + read, line.as_bytes())
+            .is_err()
+        {
+            return -1;
+        }
+        read += line.len;
+    }
+    read as i32
+}
+
+/// User write()s to /dev/trace go here. "1" starts tracing from an empty buffer, and "0" stops
+/// it.
+fn trace_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    let mut c = [0u8];
+    if n < 1
+        || ctx
+            .proc_mut()
+            .memory_mut()
+            .copy_in_bytes(&mut c, src)
+            .is_err()
+    {
+        return -1;
+    }
+    match c[0] {
+        b'0' => ENABLED.store(false, Ordering::Relaxed),
+        b'1' => {
+            TRACE.lock().reset();
+            ENABLED.store(true, Ordering::Relaxed);
+        }
+        _ => return -1,
+    }
+    n
+}
+
+/// Registers /dev/trace.
+pub fn register_devices(devsw: &mut [Devsw; NDEV]) {
+    devsw[TRACE_DEVSW] = Devsw {
+        read: Some(trace_read),
+        write: Some(trace_write),
+    };
+}
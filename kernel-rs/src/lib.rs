@@ -58,23 +58,51 @@ mod addr;
 mod arch;
 mod arena;
 mod bio;
+mod clock;
+mod config;
 mod console;
 mod cpu;
+mod cycles;
+mod device;
+mod epoll;
+mod eventfd;
 mod exec;
+mod fdinfo;
 mod file;
 mod fs;
+mod gang;
 mod hal;
+mod iosched;
+mod ipi;
+mod irqstat;
 mod kalloc;
 mod kernel;
+mod ksm;
+mod ktrace;
+mod linux;
+mod load;
 mod lock;
+mod lockstat;
+mod meminfo;
 mod memlayout;
+mod mqueue;
+mod net;
 mod page;
 mod param;
+mod perf;
 mod pipe;
+mod power;
+mod prctl;
 mod proc;
+mod random;
+mod rlimit;
+mod semaphore;
 mod start;
 mod syscall;
 mod trap;
 mod util;
 mod virtio;
 mod vm;
+mod watch;
+mod wss;
+mod zram;
@@ -0,0 +1,189 @@
+//! Linux system call compatibility.
+//!
+//! After `setabi(ABI_LINUX)`, the program that a process runs with its next `exec()` makes system
+//! calls with the RISC-V Linux numbers, which are dispatched through a separate table onto the
+//! rv6 implementations. `exec()` also lays out argc, argv, an empty environment, and an auxiliary
+//! vector on the stack as Linux does.
+//!
+//! Only what simple statically linked programs need is provided:
+//! * `mmap()` maps only anonymous memory, at the end of the heap, and `munmap()` never frees it,
+//! * `brk()` never shrinks the heap, and
+//! * every error is reported as `-EPERM`, since rv6 does not tell errors apart.
+
+use core::mem;
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    addr::{pgroundup, UVAddr},
+    file::RcFile,
+    fs::{DefaultFs, FcntlFlags, FileSystem, FileSystemExt, Path, RcInode},
+    hal::hal,
+    param::MAXPATH,
+    proc::{Abi, KernelCtx},
+    syscall::SyscallTable,
+};
+
+/// Flags of `openat()`.
+const O_ACCMODE: i32 = 0o3;
+const O_CREAT: i32 = 0o100;
+const O_TRUNC: i32 = 0o1000;
+const O_CLOEXEC: i32 = 0o2000000;
+
+/// Flags of `mmap()`.
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
+
+/// A buffer written by `writev()`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+impl KernelCtx<'_, '_> {
+    /// Set the system call ABI of the programs the process runs from its next exec() on.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_setabi(&mut self) -> Result<usize, ()> {
+        let abi = Abi::from_usize(self.proc().argint(0)? as usize).ok_or(())?;
+        self.proc_mut().deref_mut_data().exec_abi = abi;
+        Ok(0)
+    }
+
+    /// Open the file at path, relative to the directory dirfd.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    fn linux_openat(&mut self) -> Result<usize, ()> {
+        let dir = self
+            .proc()
+            .argdirfd(0)?
+            .map(|ip| ip as *const RcInode<DefaultFs>);
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(1, &mut path)?);
+        let flags = self.proc().argint(2)?;
+        let mut omode = FcntlFlags::from_bits_truncate(flags & O_ACCMODE);
+        if flags & O_CREAT != 0 {
+            omode |= FcntlFlags::O_CREATE;
+        }
+        if flags & O_TRUNC != 0 {
+            omode |= FcntlFlags::O_TRUNC;
+        }
+        if flags & O_CLOEXEC != 0 {
+            omode |= FcntlFlags::O_CLOEXEC;
+        }
+        // SAFETY: open only adds a file descriptor, so dirfd stays open.
+        let dir = dir.map(|ip| unsafe { &*ip });
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self.kernel().fs().open(path, dir, omode, &tx, self);
+        tx.end(self);
+        res
+    }
+
+    /// Write the iovcnt buffers of the array of struct iovec at iov to fd.
+    /// Returns Ok(number written) on success, Err(()) on error.
+    fn linux_writev(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let f = f as *const RcFile;
+        let iov = self.proc().argaddr(1)?;
+        let iovcnt = self.proc().argint(2)?;
+        if iovcnt < 0 {
+            return Err(());
+        }
+        let mut written = 0;
+        for i in 0..iovcnt as usize {
+            let mut v = IoVec::default();
+            let src = UVAddr::from(iov + i * mem::size_of::<IoVec>());
+            // SAFETY: IoVec does not have any internal structure.
+            unsafe { self.proc_mut().memory_mut().copy_in(&mut v, src) }?;
+            // SAFETY: write will not access proc's open_files.
+            let n = unsafe { (*f).write(v.base.into(), v.len as i32, self) }?;
+            written += n;
+            if n < v.len {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Grow the heap up to addr. Never shrinks the heap.
+    /// Returns Ok(the end of the heap), which differs from addr on failure.
+    fn linux_brk(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let size = self.proc().memory().size();
+        if addr > size && addr - size <= i32::MAX as usize {
+            let _ = self
+                .proc_mut()
+                .memory_mut()
+                .resize((addr - size) as i32, hal().kmem());
+        }
+        Ok(self.proc().memory().size())
+    }
+
+    /// Map len bytes of anonymous memory at the end of the heap.
+    /// Returns Ok(address of the mapping) on success, Err(()) on error.
+    fn linux_mmap(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let len = self.proc().argaddr(1)?;
+        let flags = self.proc().argint(3)?;
+        if addr != 0 || flags != MAP_PRIVATE | MAP_ANONYMOUS || len == 0 || len > i32::MAX as usize
+        {
+            return Err(());
+        }
+        let size = self.proc().memory().size();
+        let start = pgroundup(size);
+        let n = start - size + pgroundup(len);
+        if n > i32::MAX as usize {
+            return Err(());
+        }
+        let _ = self
+            .proc_mut()
+            .memory_mut()
+            .resize(n as i32, hal().kmem())?;
+        Ok(start)
+    }
+}
+
+/// Registers the system calls of the Linux compatibility.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(60, |ctx| ctx.sys_setabi());
+}
+
+/// Registers the supported Linux system calls under their RISC-V Linux numbers.
+pub fn register_linux_syscalls(table: &mut SyscallTable) {
+    // getcwd, which counts the NUL in the length it returns
+    table.register(17, |ctx| ctx.sys_getcwd().map(|len| len + 1));
+    // fcntl, for F_GETFD and F_SETFD only
+    table.register(25, |ctx| ctx.sys_fcntl());
+    // mkdirat, whose mode is ignored
+    table.register(34, |ctx| ctx.sys_mkdirat());
+    // unlinkat, which removes empty directories regardless of the flags
+    table.register(35, |ctx| ctx.sys_unlinkat());
+    // openat
+    table.register(56, |ctx| ctx.linux_openat());
+    // close
+    table.register(57, |ctx| ctx.sys_close());
+    // read
+    table.register(63, |ctx| ctx.sys_read());
+    // write
+    table.register(64, |ctx| ctx.sys_write());
+    // writev
+    table.register(66, |ctx| ctx.linux_writev());
+    // exit
+    table.register(93, |ctx| ctx.sys_exit());
+    // exit_group
+    table.register(94, |ctx| ctx.sys_exit());
+    // nanosleep
+    table.register(101, |ctx| ctx.sys_nanosleep());
+    // gettimeofday
+    table.register(169, |ctx| ctx.sys_gettimeofday());
+    // settimeofday
+    table.register(170, |ctx| ctx.sys_settimeofday());
+    // umask
+    table.register(166, |ctx| ctx.sys_umask());
+    // brk
+    table.register(214, |ctx| ctx.linux_brk());
+    // munmap
+    table.register(215, |_| Ok(0));
+    // mmap
+    table.register(222, |ctx| ctx.linux_mmap());
+}
@@ -0,0 +1,54 @@
+//! CPU load statistics and idle injection.
+//!
+//! Each CPU counts the cycles it spends running processes, over windows of `LOADWINDOW` cycles.
+//! `cpuload()` reports the utilization of each CPU over its current and previous windows, and
+//! the number of runnable processes its scheduler last found.
+//!
+//! `throttle()` limits the share of the time a process may run. Once a throttled process has
+//! used up its share of a window of `THROTTLEWINDOW` cycles, the schedulers skip it until the
+//! next window begins, leaving the CPU idle if nothing else is runnable.
+
+use core::cmp;
+
+use zerocopy::AsBytes;
+
+use crate::{hal::hal, param::NCPU, proc::KernelCtx, syscall::SyscallTable};
+
+impl KernelCtx<'_, '_> {
+    /// Place the load of at most n CPUs into struct cpuload at addr.
+    /// Returns Ok(number of CPUs stored) on success, Err(()) on error.
+    pub fn sys_cpuload(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let n = self.proc().argint(1)?;
+        if n < 0 {
+            return Err(());
+        }
+        let load = hal().cpus().load();
+        let stored = cmp::min(NCPU, n as usize);
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr.into(), load[..stored].as_bytes())?;
+        Ok(stored)
+    }
+
+    /// Let the process pid, or the caller if pid is 0, run for at most percent percent of the
+    /// time. 100 removes the limit.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_throttle(&self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        let percent = self.proc().argint(1)?;
+        if !(1..=100).contains(&percent) {
+            return Err(());
+        }
+        self.kernel()
+            .procs()
+            .set_throttle(pid, percent as u32, self)?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of CPU load statistics.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(74, |ctx| ctx.sys_cpuload());
+    table.register(75, |ctx| ctx.sys_throttle());
+}
@@ -0,0 +1,137 @@
+//! MCS queues for spin locks
+use core::cell::Cell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use crate::{cpu::cpuid, param::NCPU};
+
+/// Maximum number of spin locks that a CPU holds or waits for at once.
+const NMCSNODE: usize = 16;
+
+/// The place of a CPU in the queue of a lock.
+struct McsNode {
+    /// The node of the CPU after this one in the queue, or null.
+    next: AtomicPtr<McsNode>,
+
+    /// Whether the CPU must keep waiting. The CPU before it clears it on its way out.
+    waiting: AtomicBool,
+}
+
+/// The nodes of a CPU. Only the CPU itself takes and returns them, with interrupts disabled.
+struct McsNodes {
+    nodes: [McsNode; NMCSNODE],
+
+    /// Bit i is set if `nodes[i]` is in a queue.
+    used: AtomicUsize,
+}
+
+static NODES: [McsNodes; NCPU] = array![_ => McsNodes::new(); NCPU];
+
+/// Orders the CPUs waiting for a `RawSpinLock` in a linked queue, so that they get the lock first
+/// come, first served. Unlike with a `TicketQueue`, each waiter spins on a node of its own, and
+/// only the next waiter's node changes when the lock changes hands.
+pub struct McsQueue {
+    /// The node of the last CPU in the queue, or null if the queue is empty.
+    tail: AtomicPtr<McsNode>,
+
+    /// The node of the CPU whose turn it is.
+    head: Cell<*const McsNode>,
+}
+
+impl McsNode {
+    const fn new() -> Self {
+        Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            waiting: AtomicBool::new(false),
+        }
+    }
+}
+
+impl McsNodes {
+    const fn new() -> Self {
+        Self {
+            nodes: array![_ => McsNode::new(); NMCSNODE],
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    fn take(&self) -> &McsNode {
+        let used = self.used.load(Ordering::Relaxed);
+        let i = (!used).trailing_zeros() as usize;
+        assert!(i < NMCSNODE, "McsNodes::take");
+        self.used.store(used | 1 << i, Ordering::Relaxed);
+        &self.nodes[i]
+    }
+
+    fn put(&self, node: &McsNode) {
+        let i = self
+            .nodes
+            .iter()
+            .position(|n| ptr::eq(n, node))
+            .expect("McsNodes::put");
+        let _ = self.used.fetch_and(!(1 << i), Ordering::Relaxed);
+    }
+}
+
+impl McsQueue {
+    /// `spinbench()` reports the implementation of spin locks as 2.
+    pub const KIND: usize = 2;
+
+    pub const fn new() -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            head: Cell::new(ptr::null()),
+        }
+    }
+
+    /// Joins the queue and waits for the turn of the current CPU. Interrupts must be off.
+    pub fn enter(&self) {
+        let node = NODES[cpuid()].take();
+        node.next.store(ptr::null_mut(), Ordering::Relaxed);
+        node.waiting.store(true, Ordering::Relaxed);
+        let node_ptr = node as *const _ as *mut McsNode;
+        let prev = self.tail.swap(node_ptr, Ordering::AcqRel);
+        if !prev.is_null() {
+            // SAFETY: the previous CPU does not return its node before it has seen this one.
+            unsafe { &*prev }.next.store(node_ptr, Ordering::Release);
+            while node.waiting.load(Ordering::Acquire) {
+                ::core::hint::spin_loop();
+            }
+        }
+        self.head.set(node);
+    }
+
+    /// Passes the turn to the next CPU in the queue, and leaves it. Only the CPU whose turn it is
+    /// may call it. Interrupts must be off.
+    pub fn leave(&self) {
+        // SAFETY: the node of the CPU whose turn it is stays in the queue until now.
+        let node = unsafe { &*self.head.get() };
+        let node_ptr = node as *const _ as *mut McsNode;
+        let mut next = node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            if self
+                .tail
+                .compare_exchange(
+                    node_ptr,
+                    ptr::null_mut(),
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                NODES[cpuid()].put(node);
+                return;
+            }
+            // A CPU has joined the queue, but has not linked its node to this one yet.
+            while next.is_null() {
+                ::core::hint::spin_loop();
+                next = node.next.load(Ordering::Acquire);
+            }
+        }
+        // SAFETY: the next CPU keeps its node in the queue while it waits.
+        unsafe { &*next }.waiting.store(false, Ordering::Release);
+        NODES[cpuid()].put(node);
+    }
+}
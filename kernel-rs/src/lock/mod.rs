@@ -22,13 +22,19 @@
 //!     you can make multiple data be protected by a single [`SpinLock`], and hence,
 //!     implement global locks. In this case, you may want to use an [`SpinLock<()>`]
 //!     if the [`SpinLock`] doesn't need to hold data.
-//! * When you want a lifetime-less smart pointer (such as [`Ref`](crate::util::rc_cell::Ref) or `std::rc::Rc`)
+//! * When you want a lifetime-less smart pointer (such as [`Ref`](crate::util::static_arc::Ref) or `std::rc::Rc`)
 //!   that points to the *inside* of a lock protected data.
-//!   * e.g. Suppose a [`Lock`] holds a [`RcCell`](crate::util::rc_cell::RcCell). Suppose you want to provide a
-//!     [`Ref`](crate::util::rc_cell::Ref) that borrows this [`RcCell`](crate::util::rc_cell::RcCell) to the outside, but still want
-//!     accesses to the [`RcCell`](crate::util::rc_cell::RcCell)'s inner data to be synchronized.
-//!     Then, instead of providing a [`Ref`](crate::util::rc_cell::Ref), you should provide a [`Ref`](crate::util::rc_cell::Ref) wrapped by a `RemoteLock`.
+//!   * e.g. Suppose a [`Lock`] holds a [`StaticArc`](crate::util::static_arc::StaticArc). Suppose you want to provide a
+//!     [`Ref`](crate::util::static_arc::Ref) that borrows this [`StaticArc`](crate::util::static_arc::StaticArc) to the outside, but still want
+//!     accesses to the [`StaticArc`](crate::util::static_arc::StaticArc)'s inner data to be synchronized.
+//!     Then, instead of providing a [`Ref`](crate::util::static_arc::Ref), you should provide a [`Ref`](crate::util::static_arc::Ref) wrapped by a `RemoteLock`.
 //!     to the outside.
+//!
+//! A `RemoteLock` must be used only with a guard of the [`Lock`] it borrows. Check this at compile time
+//! by branding the guard and the owner of the data with the same `'id` tag (see [`Branded`](crate::util::branded::Branded)),
+//! rather than by comparing pointers at runtime.
+//! For example, the `parent` field of a `ProcRef<'id, '_>` can be accessed only with a `WaitGuard<'id, '_>`,
+//! which holds the `wait_lock` of the `Procs` that owns the `Proc`.
 
 // Dead code is allowed in this file because not all components are used in the kernel.
 #![allow(dead_code)]
@@ -38,13 +44,19 @@ use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
 use core::pin::Pin;
 
+#[cfg(feature = "mcs")]
+mod mcs;
+mod mutex;
 mod sleepablelock;
 mod sleeplock;
 mod spinlock;
+#[cfg(feature = "ticket")]
+mod ticket;
 
+pub use mutex::{LockStat, Mutex, MutexGuard, RawMutex};
 pub use sleepablelock::{SleepableLock, SleepableLockGuard};
 pub use sleeplock::{SleepLock, SleepLockGuard};
-pub use spinlock::{RawSpinLock, SpinLock, SpinLockGuard};
+pub use spinlock::{RawSpinLock, SpinLock, SpinLockGuard, SPINLOCK_KIND};
 
 use crate::util::strong_pin::StrongPin;
 use crate::util::strong_pin::StrongPinMut;
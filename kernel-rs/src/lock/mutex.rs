@@ -0,0 +1,214 @@
+//! Adaptive mutexes
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use zerocopy::AsBytes;
+
+use super::{Guard, Lock, RawLock, SleepableLock};
+use crate::{
+    arch::interface::TrapManager, arch::TargetArch, cpu::cpuid, cycles::Cycles, hal::hal,
+    kernel::kernel_ref, param::MUTEXSPIN, proc::Proc,
+};
+
+/// Contention statistics of a `Mutex`, read by `lockstat()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct LockStat {
+    /// Name of the lock, padded with zeros
+    pub name: [u8; 16],
+
+    /// Number of acquisitions
+    pub acquired: usize,
+
+    /// Number of acquisitions that found the lock held
+    pub contended: usize,
+
+    /// Number of contended acquisitions that got the lock without sleeping
+    pub spun: usize,
+
+    /// Number of times a process slept waiting for the lock
+    pub slept: usize,
+
+    /// Cycles the contended acquisitions waited for the lock
+    pub wait_cycles: usize,
+}
+
+/// Mutual exclusion lock that spins while its holder runs, and sleeps otherwise.
+///
+/// A holder running on another CPU is likely to release the lock soon, so a waiter spins for it,
+/// for at most `MUTEXSPIN` rounds, rather than paying for a sleep and a wakeup. A holder that does
+/// not run, because it was preempted, will not release the lock before it is scheduled again, so
+/// a waiter sleeps instead. Unlike a `SpinLock`, the lock leaves interrupts enabled, so its holder
+/// may be preempted, but it must not sleep. Only a process with interrupts enabled may acquire it,
+/// as sleep locks require, and not an interrupt handler, the scheduler, or a holder of a
+/// `SpinLock`: spinning with interrupts off for a preempted holder would never end on a single
+/// CPU. The only exception is the boot CPU before its scheduler starts, when nothing else runs.
+pub struct RawMutex {
+    /// Name of lock.
+    name: &'static str,
+
+    locked: AtomicBool,
+
+    /// The process holding the lock, or null if the lock is free or held by no process.
+    owner: AtomicPtr<Proc>,
+
+    /// Number of processes sleeping for the lock, or about to.
+    sleepers: AtomicUsize,
+
+    /// Lock on whose waitchannel the sleepers sleep. Taking it in `release` makes sure that a
+    /// sleeper that has seen the lock held is asleep before it is woken up.
+    waiters: SleepableLock<()>,
+
+    acquired: AtomicUsize,
+    contended: AtomicUsize,
+    spun: AtomicUsize,
+    slept: AtomicUsize,
+    wait_cycles: AtomicUsize,
+}
+
+/// Locks that spin while the holder runs, and sleep otherwise.
+pub type Mutex<T> = Lock<RawMutex, T>;
+/// Guards of `Mutex<T>`.
+pub type MutexGuard<'s, T> = Guard<'s, RawMutex, T>;
+
+/// Returns the process running on the current CPU, or null.
+fn current_proc() -> *const Proc {
+    let cpus = hal().cpus();
+    let intr = cpus.push_off();
+    let proc = cpus.current(&intr).get_proc();
+    unsafe { cpus.pop_off(intr) };
+    proc
+}
+
+impl RawMutex {
+    /// Mutual exclusion adaptive locks.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            locked: AtomicBool::new(false),
+            owner: AtomicPtr::new(ptr::null_mut()),
+            sleepers: AtomicUsize::new(0),
+            waiters: SleepableLock::new(name, ()),
+            acquired: AtomicUsize::new(0),
+            contended: AtomicUsize::new(0),
+            spun: AtomicUsize::new(0),
+            slept: AtomicUsize::new(0),
+            wait_cycles: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes the lock for `proc` if it is free. Returns whether it did.
+    fn try_acquire(&self, proc: *const Proc) -> bool {
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        self.owner.store(proc as *mut _, Ordering::Relaxed);
+        true
+    }
+
+    /// Returns whether the holder of the lock is running on a CPU. A lock held by no process
+    /// counts as running, as its holder cannot be preempted.
+    fn holder_runs(&self) -> bool {
+        let owner = self.owner.load(Ordering::Relaxed);
+        owner.is_null() || hal().cpus().runs(owner)
+    }
+
+    /// Returns the contention statistics of the lock.
+    pub fn stat(&self) -> LockStat {
+        let mut name = [0; 16];
+        let len = self.name.len().min(name.len());
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        LockStat {
+            name,
+            acquired: self.acquired.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+            spun: self.spun.load(Ordering::Relaxed),
+            slept: self.slept.load(Ordering::Relaxed),
+            wait_cycles: self.wait_cycles.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl RawLock for RawMutex {
+    /// Acquires the lock.
+    /// Spins while the holder runs, for at most `MUTEXSPIN` rounds at a time, and sleeps until
+    /// the lock is released otherwise.
+    fn acquire(&self) {
+        let proc = current_proc();
+        assert!(
+            (!proc.is_null() && TargetArch::intr_get()) || !hal().cpus().is_started(cpuid()),
+            "acquire {}",
+            self.name
+        );
+        let _ = self.acquired.fetch_add(1, Ordering::Relaxed);
+        if self.try_acquire(proc) {
+            return;
+        }
+        let _ = self.contended.fetch_add(1, Ordering::Relaxed);
+        let start = Cycles::read();
+        let mut slept = false;
+        loop {
+            let mut spins = 0;
+            while self.locked.load(Ordering::Relaxed) && spins < MUTEXSPIN && self.holder_runs() {
+                ::core::hint::spin_loop();
+                spins += 1;
+            }
+            if self.try_acquire(proc) {
+                break;
+            }
+            if proc.is_null() {
+                // Booting, when the holder is not a process either.
+                continue;
+            }
+
+            // `release` stores to `locked` before it loads `sleepers`, and this stores to
+            // `sleepers` before it loads `locked`. Hence, either `release` sees the sleeper and
+            // wakes it up, or the sleeper sees the lock free and does not sleep.
+            let mut guard = self.waiters.lock();
+            let _ = self.sleepers.fetch_add(1, Ordering::SeqCst);
+            if self.locked.load(Ordering::SeqCst) {
+                let _ = self.slept.fetch_add(1, Ordering::Relaxed);
+                slept = true;
+                // SAFETY: a process runs only after the kernel is initialized.
+                unsafe { kernel_ref(|kernel| guard.sleep_current(kernel)) };
+            }
+            let _ = self.sleepers.fetch_sub(1, Ordering::SeqCst);
+        }
+        if !slept {
+            let _ = self.spun.fetch_add(1, Ordering::Relaxed);
+        }
+        let waited = (Cycles::read() - start).cycle;
+        let _ = self.wait_cycles.fetch_add(waited, Ordering::Relaxed);
+    }
+
+    /// Releases the lock, and wakes up the processes sleeping for it.
+    fn release(&self) {
+        self.owner.store(ptr::null_mut(), Ordering::Relaxed);
+        self.locked.store(false, Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let guard = self.waiters.lock();
+            // SAFETY: a process sleeps for the lock only after the kernel is initialized.
+            unsafe { kernel_ref(|kernel| guard.wakeup(kernel)) };
+        }
+    }
+}
+
+impl<T> Mutex<T> {
+    /// Returns a new `Mutex` with name `name` and data `data`.
+    pub const fn new(name: &'static str, data: T) -> Self {
+        Self {
+            lock: RawMutex::new(name),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Returns the contention statistics of the lock.
+    pub fn stat(&self) -> LockStat {
+        self.lock.stat()
+    }
+}
@@ -54,6 +54,30 @@ impl<T> SleepableLockGuard<'_, T> {
         self.lock.lock.waitchannel.sleep(self, ctx);
     }
 
+    /// Like `sleep`, for a caller that has no `KernelCtx` at hand. There must be a current
+    /// process.
+    pub fn sleep_current(&mut self, kernel: KernelRef<'_, '_>) {
+        self.lock.lock.waitchannel.sleep_current(self, kernel);
+    }
+
+    /// Sleeps as long as `cond` holds for the data. See `WaitChannel::wait_while`.
+    pub fn wait_while<F: FnMut(&T) -> bool>(&mut self, cond: F, ctx: &KernelCtx<'_, '_>) {
+        self.lock.lock.waitchannel.wait_while(self, cond, ctx);
+    }
+
+    /// Sleeps as long as `cond` holds for the data, unless the process gets killed.
+    /// See `WaitChannel::wait_while_killable`.
+    pub fn wait_while_killable<F: FnMut(&T) -> bool>(
+        &mut self,
+        cond: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        self.lock
+            .lock
+            .waitchannel
+            .wait_while_killable(self, cond, ctx)
+    }
+
     pub fn wakeup(&self, kernel: KernelRef<'_, '_>) {
         self.lock.lock.waitchannel.wakeup(kernel);
     }
@@ -6,9 +6,13 @@ use core::{
 };
 
 use super::SleepableLock;
-use crate::proc::KernelCtx;
+use crate::{hal::hal, proc::KernelCtx};
 
-/// Long-term locks for processes
+/// Long-term locks for processes.
+///
+/// A process waiting for the lock lends its priority to the holder, if it is higher, so that a
+/// process of a lower class holding the lock does not keep a process of a higher class waiting
+/// for as long as processes of the classes in between run.
 pub struct RawSleepLock {
     /// Process holding lock. `-1` means unlocked.
     inner: SleepableLock<i32>,
@@ -39,8 +43,15 @@ impl RawSleepLock {
 
     fn acquire(&self, ctx: &KernelCtx<'_, '_>) {
         let mut guard = self.inner.lock();
-        while *guard != -1 {
-            guard.sleep(ctx);
+        if *guard != -1 {
+            while *guard != -1 {
+                if ctx.kernel().procs().wait_for(*guard, ctx) {
+                    // SAFETY: interrupts are disabled while `guard` is held.
+                    unsafe { hal().cpus().current_unchecked() }.count_inheritance();
+                }
+                guard.sleep(ctx);
+            }
+            let _ = ctx.kernel().procs().wait_for(0, ctx);
         }
         *guard = ctx.proc().pid();
     }
@@ -49,6 +60,8 @@ impl RawSleepLock {
         let mut guard = self.inner.lock();
         *guard = -1;
         guard.wakeup(ctx.kernel());
+        drop(guard);
+        ctx.kernel().procs().disinherit(ctx);
     }
 }
 
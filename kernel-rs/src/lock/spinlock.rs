@@ -1,15 +1,52 @@
 //! Spin locks
+//!
+//! By default, the CPUs waiting for a spin lock race for it, and the one that wins is not
+//! necessarily the one that has waited longest, so a CPU may starve under contention. The
+//! `ticket` feature makes them take tickets, and the `mcs` feature makes them queue in a linked
+//! list, both of which hand the lock over first come, first served. `spinbench()` compares the
+//! three.
 use core::cell::{Cell, UnsafeCell};
 use core::mem::MaybeUninit;
 use core::ptr;
 use core::sync::atomic::{AtomicPtr, Ordering};
 
+use cfg_if::cfg_if;
+
 use super::{Guard, Lock, RawLock};
 use crate::{
     cpu::{Cpu, HeldInterrupts},
     hal::hal,
 };
 
+cfg_if! {
+    if #[cfg(feature = "mcs")] {
+        type Queue = super::mcs::McsQueue;
+    } else if #[cfg(feature = "ticket")] {
+        type Queue = super::ticket::TicketQueue;
+    } else {
+        type Queue = Unordered;
+    }
+}
+
+/// No queue: the CPUs waiting for a lock race for it as soon as it is released.
+struct Unordered;
+
+impl Unordered {
+    /// `spinbench()` reports the implementation of spin locks as 0.
+    const KIND: usize = 0;
+
+    const fn new() -> Self {
+        Self
+    }
+
+    fn enter(&self) {}
+
+    fn leave(&self) {}
+}
+
+/// The implementation of spin locks, as `spinbench()` reports it.
+pub const SPINLOCK_KIND: usize = Queue::KIND;
+
 /// Mutual exclusion lock that busy waits (spin).
 pub struct RawSpinLock {
     /// Name of lock.
@@ -20,6 +57,11 @@ pub struct RawSpinLock {
     ///
     /// Records info about lock acquisition for holding() and debugging.
     locked: AtomicPtr<Cpu>,
+
+    /// Orders the CPUs waiting for the lock, if the `ticket` or `mcs` feature is enabled. A CPU
+    /// sets `locked` only after its turn comes, and clears it before passing the turn on.
+    queue: Queue,
+
     intr: Cell<MaybeUninit<HeldInterrupts>>,
 }
 
@@ -34,6 +76,7 @@ impl RawSpinLock {
         Self {
             locked: AtomicPtr::new(ptr::null_mut()),
             name,
+            queue: Queue::new(),
             intr: Cell::new(MaybeUninit::uninit()),
         }
     }
@@ -64,6 +107,7 @@ impl RawLock for RawSpinLock {
         // Disable interrupts to avoid deadlock.
         let intr = hal().cpus().push_off();
         assert!(!self.holding(), "acquire {}", self.name);
+        self.queue.enter();
 
         // RISC-V supports two forms of atomic instructions, 1) load-reserved/store-conditional and 2) atomic fetch-and-op,
         // and we use the former here.
@@ -98,12 +142,15 @@ impl RawLock for RawSpinLock {
     fn release(&self) {
         assert!(self.holding(), "release {}", self.name);
 
+        // Take the saved interrupt state before unlocking, since the next holder overwrites it.
+        let intr = unsafe { self.intr.replace(MaybeUninit::uninit()).assume_init_read() };
+
         // Release the lock by storing ptr::null_mut() in `self.locked`
         // using an atomic store. This is actually done using a fence in RISC-V.
         //
         // 0x80000f5c | fence   rw,w            (Enforces `Release` memory ordering)
         self.locked.store(ptr::null_mut(), Ordering::Release);
-        let intr = unsafe { self.intr.replace(MaybeUninit::uninit()).assume_init_read() };
+        self.queue.leave();
         unsafe { hal().cpus().pop_off(intr) };
     }
 }
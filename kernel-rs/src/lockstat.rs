@@ -0,0 +1,113 @@
+//! Lock contention statistics and benchmarks.
+//!
+//! The locks of the file table and the inode table are `Mutex`es, which spin while the holder
+//! runs and sleep otherwise. Each counts its acquisitions, how many of them found it held, and
+//! how those waited, so that `lockstat()` shows how contended the locks are and whether spinning
+//! pays off.
+//!
+//! `spinbench()` makes the calling process acquire and release a spin lock of its own over and
+//! over for a given time. Run by a process on each CPU at once, it measures how many times the
+//! lock changes hands, and how evenly among the CPUs, under the implementation of spin locks the
+//! kernel was built with.
+
+use core::cmp;
+
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::interface::TimeManager,
+    arch::TargetArch,
+    clock,
+    lock::{LockStat, SpinLock, SPINLOCK_KIND},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// Number of locks whose statistics `lockstat()` reads.
+const NLOCKSTAT: usize = 2;
+
+/// Longest time a `spinbench()` call runs, in milliseconds.
+const MAXSPINBENCH: i32 = 10000;
+
+/// The lock that `spinbench()` acquires, with the number of times it has been acquired.
+static BENCH: SpinLock<usize> = SpinLock::new("spinbench", 0);
+
+/// Result of a `spinbench()` call.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct SpinBench {
+    /// Implementation of spin locks: 0 if the CPUs race, 1 for tickets, or 2 for MCS queues
+    pub kind: usize,
+
+    /// Number of times the process acquired the lock
+    pub acquired: usize,
+
+    /// Cycles the process waited for the lock in total
+    pub wait_cycles: usize,
+
+    /// Cycles of the longest wait of the process
+    pub max_wait: usize,
+
+    /// Microseconds the benchmark ran
+    pub elapsed: usize,
+}
+
+impl KernelCtx<'_, '_> {
+    /// Place the contention statistics of at most n locks into the array of struct lockstat at
+    /// addr.
+    /// Returns Ok(number of locks) on success, Err(()) on error.
+    pub fn sys_lockstat(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let n = self.proc().argint(1)?;
+        if n < 0 {
+            return Err(());
+        }
+        let stats: [LockStat; NLOCKSTAT] = [
+            self.kernel().ftable().lock_stat(),
+            self.kernel().fs().itable().lock_stat(),
+        ];
+        let stored = cmp::min(n as usize, NLOCKSTAT);
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr.into(), stats[..stored].as_bytes())?;
+        Ok(NLOCKSTAT)
+    }
+
+    /// Acquire and release the benchmark spin lock over and over for ms milliseconds, or until
+    /// the process is killed. Then, place the result into struct spinbench at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_spinbench(&mut self) -> Result<usize, ()> {
+        let ms = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?;
+        if ms <= 0 || ms > MAXSPINBENCH {
+            return Err(());
+        }
+        let mut result = SpinBench {
+            kind: SPINLOCK_KIND,
+            ..Default::default()
+        };
+        let start = clock::uptime();
+        let end = start + ms as usize * 1000;
+        while clock::uptime() < end && !self.proc().killed() {
+            let before = TargetArch::r_cycle();
+            let mut guard = BENCH.lock();
+            let wait = TargetArch::r_cycle().wrapping_sub(before);
+            *guard += 1;
+            drop(guard);
+            result.acquired += 1;
+            result.wait_cycles += wait;
+            result.max_wait = cmp::max(result.max_wait, wait);
+        }
+        result.elapsed = clock::uptime() - start;
+        self.proc_mut()
+            .memory_mut()
+            .copy_out(addr.into(), &result)?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of lock contention statistics and benchmarks.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(121, |ctx| ctx.sys_lockstat());
+    table.register(122, |ctx| ctx.sys_spinbench());
+}
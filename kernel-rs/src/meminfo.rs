@@ -0,0 +1,122 @@
+//! Memory statistics.
+//!
+//! `meminfo()` reports how much physical memory is free, how much the caches hold, how full the
+//! kernel object tables are, and how many pages each process has mapped. rv6 does not have slab
+//! caches; kernel objects live in fixed-size tables, whose usage is reported instead.
+
+use core::cmp;
+
+use zerocopy::AsBytes;
+
+use crate::{
+    addr::PGSIZE,
+    arena::Arena,
+    hal::hal,
+    param::{BSIZE, MAXNBUF},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// Maximum number of processes reported by a `meminfo()` call.
+const NPROCMEM: usize = 64;
+
+/// Usage of a kernel object table.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct TableUsage {
+    /// Number of entries in use
+    pub used: usize,
+
+    /// Number of entries in the table
+    pub capacity: usize,
+}
+
+/// Summary of the memory of the kernel, read by `meminfo()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct MemInfo {
+    /// Number of physical pages managed by the page allocator
+    pub total_pages: usize,
+
+    /// Number of free physical pages
+    pub free_pages: usize,
+
+    /// Number of pages taken by the buffers of the buffer cache
+    pub bcache_pages: usize,
+
+    /// Number of pages allocated by the page cache
+    pub page_cache_pages: usize,
+
+    /// Open files
+    pub files: TableUsage,
+
+    /// In-memory inodes
+    pub inodes: TableUsage,
+
+    /// Buffers of the buffer cache
+    pub bufs: TableUsage,
+}
+
+/// Resident set size and open files of a process, read by `meminfo()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct ProcMem {
+    pub pid: i32,
+
+    /// Number of open file descriptors
+    pub nfiles: u32,
+
+    /// Number of mapped pages, including the trampoline and the trap frame
+    pub rss: usize,
+}
+
+impl KernelCtx<'_, '_> {
+    /// Returns the summary of the memory of the kernel.
+    pub fn mem_info(&self) -> MemInfo {
+        let (free_pages, total_pages) = hal().kmem().stat();
+        MemInfo {
+            total_pages,
+            free_pages,
+            bcache_pages: (MAXNBUF * BSIZE + PGSIZE - 1) / PGSIZE,
+            page_cache_pages: self.kernel().page_cache().num_pages(),
+            files: TableUsage {
+                used: self.kernel().ftable().count_used(),
+                capacity: self.kernel().ftable().capacity(),
+            },
+            inodes: TableUsage {
+                used: self.kernel().fs().itable().count_used(),
+                capacity: self.kernel().fs().itable().capacity(),
+            },
+            bufs: TableUsage {
+                used: self.kernel().bcache().count_used(),
+                capacity: self.kernel().bcache().capacity(),
+            },
+        }
+    }
+
+    /// Place the summary of the memory of the kernel into struct meminfo at info, and
+    /// the resident set sizes of at most n processes into the array of struct procmem at p.
+    /// Returns Ok(number of processes stored) on success, Err(()) on error.
+    pub fn sys_meminfo(&mut self) -> Result<usize, ()> {
+        let info = self.proc().argaddr(0)?;
+        let p = self.proc().argaddr(1)?;
+        let n = self.proc().argint(2)?;
+        if n < 0 {
+            return Err(());
+        }
+        let mut procs = [ProcMem::default(); NPROCMEM];
+        let procs = &mut procs[..cmp::min(n as usize, NPROCMEM)];
+        let stored = self.kernel().procs().rss(procs, self);
+        let mem = self.mem_info();
+        self.proc_mut().memory_mut().copy_out(info.into(), &mem)?;
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(p.into(), procs[..stored].as_bytes())?;
+        Ok(stored)
+    }
+}
+
+/// Registers the system calls of memory statistics.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(71, |ctx| ctx.sys_meminfo());
+}
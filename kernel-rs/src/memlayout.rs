@@ -8,6 +8,9 @@
 //! 0C000000 -- PLIC
 //! 10000000 -- uart0
 //! 10001000 -- virtio disk
+//! 10002000 -- virtio entropy device
+//! 10003000 -- virtio console
+//! 10004000 -- virtio 9p transport
 //! 80000000 -- boot ROM jumps here in machine mode
 //!             -kernel loads the kernel here
 //! unused RAM after 80000000.
@@ -37,10 +40,10 @@ pub const TRAPFRAME: usize = TRAMPOLINE.wrapping_sub(PGSIZE);
 /// in both user and kernel space.
 pub const TRAMPOLINE: usize = MAXVA.wrapping_sub(PGSIZE);
 
-/// map kernel stacks beneath the MAXVA,
+/// map kernel stacks beneath the trampoline,
 /// each surrounded by invalid guard pages.
-pub fn kstack(p: usize) -> usize {
-    TRAMPOLINE - ((p + 1) * 2 * PGSIZE)
+pub fn kstack(i: usize) -> usize {
+    TRAMPOLINE - ((i + 1) * 2 * PGSIZE)
 }
 
 pub const PHYSTOP: usize = TargetArch::KERNBASE.wrapping_add(128 * 1024 * 1024);
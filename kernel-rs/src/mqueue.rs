@@ -0,0 +1,468 @@
+//! POSIX message queues.
+//!
+//! A message queue holds up to `maxmsg` messages of up to `msgsize` bytes each, and hands them out
+//! highest priority first, and oldest first among messages of the same priority. Unlike a pipe, a
+//! queue keeps the boundaries of messages, and unlike a socket, it has a name of its own, such as
+//! "/bench", by which unrelated processes open it with `mq_open()`.
+//! * `mq_send()` waits while the queue is full, and `mq_receive()` waits while it is empty.
+//! * A queue lives until it is unlinked with `mq_unlink()` and its last descriptor is closed, so
+//!   an unlinked queue remains usable through the descriptors still open.
+//! * The messages of a queue are kept in a page allocated when the queue is created. Creating a
+//!   queue fails if the messages of all queues would exceed the `RLIMIT_MSGQUEUE` of the creator.
+//!   As rv6 has no users, the queues of all processes are charged together.
+
+use core::{cmp::Reverse, ops::Deref, ptr::NonNull};
+
+use array_macro::array;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    addr::UVAddr,
+    epoll::{Waiter, Waiters},
+    file::{FileType, SelectEvent},
+    fs::FcntlFlags,
+    hal::hal,
+    kernel::KernelRef,
+    lock::{SleepableLock, SpinLock},
+    page::{Page, PGSIZE},
+    param::{IPCNAMEMAX, MAXPATH, MSGQUEUE_MAXMSG, NMSGQUEUE},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// Priorities of messages are less than `MQ_PRIO_MAX`.
+const MQ_PRIO_MAX: u32 = 32768;
+
+/// Attributes of a queue created without a `struct mq_attr`.
+const DEFAULT_MAXMSG: usize = 8;
+const DEFAULT_MSGSIZE: usize = 512;
+
+/// `struct mq_attr`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct MqAttr {
+    /// Always 0, as rv6 has no non-blocking queues
+    flags: i64,
+
+    /// Maximum number of messages
+    maxmsg: i64,
+
+    /// Maximum size of a message in bytes
+    msgsize: i64,
+
+    /// Number of messages in the queue
+    curmsgs: i64,
+}
+
+#[derive(Copy, Clone)]
+struct Message {
+    len: usize,
+    prio: u32,
+
+    /// Order in which the message was sent.
+    seq: u64,
+}
+
+struct MsgQueueInner {
+    name: [u8; IPCNAMEMAX],
+    namelen: usize,
+
+    /// The name still refers to this queue.
+    linked: bool,
+
+    /// Number of open descriptions of this queue.
+    nopen: usize,
+
+    maxmsg: usize,
+    msgsize: usize,
+
+    /// The `i`th message is kept at offset `i * msgsize` of the page.
+    buf: Option<Page>,
+    msgs: [Option<Message>; MSGQUEUE_MAXMSG],
+    nmsg: usize,
+    seq: u64,
+
+    /// Epolls watching the queue.
+    waiters: Waiters,
+}
+
+pub struct MsgQueue {
+    inner: SleepableLock<MsgQueueInner>,
+}
+
+pub struct MsgQueueTable {
+    /// Held while looking up a queue by name, so that a name refers to at most one queue.
+    lock: SpinLock<()>,
+    queues: [MsgQueue; NMSGQUEUE],
+}
+
+/// # Safety
+///
+/// `ptr` always refers to a queue in the kernel's `MsgQueueTable`, whose `nopen` counts this
+/// `AllocatedMsgQueue`. The queue is freed only after every `AllocatedMsgQueue` is closed.
+pub struct AllocatedMsgQueue {
+    ptr: NonNull<MsgQueue>,
+}
+
+// `AllocatedMsgQueue` is `Send` because we access `MsgQueueInner` only after acquring a lock
+// and because `AllocatedMsgQueue` does not point to thread-local data.
+unsafe impl Send for AllocatedMsgQueue {}
+
+impl Deref for AllocatedMsgQueue {
+    type Target = MsgQueue;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `ptr` always refers to a queue in the kernel's `MsgQueueTable`.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl MsgQueueInner {
+    const fn new() -> Self {
+        Self {
+            name: [0; IPCNAMEMAX],
+            namelen: 0,
+            linked: false,
+            nopen: 0,
+            maxmsg: 0,
+            msgsize: 0,
+            buf: None,
+            msgs: [None; MSGQUEUE_MAXMSG],
+            nmsg: 0,
+            seq: 0,
+            waiters: Waiters::new(),
+        }
+    }
+
+    fn name(&self) -> &[u8] {
+        &self.name[..self.namelen]
+    }
+
+    fn is_free(&self) -> bool {
+        !self.linked && self.nopen == 0
+    }
+
+    /// Returns the bytes of messages the queue may hold, as charged to `RLIMIT_MSGQUEUE`.
+    fn size(&self) -> usize {
+        self.maxmsg * self.msgsize
+    }
+
+    /// Frees the messages of the queue if it is neither linked nor open.
+    fn free_if_unused(&mut self) {
+        if self.is_free() {
+            self.nmsg = 0;
+            self.msgs = [None; MSGQUEUE_MAXMSG];
+            if let Some(page) = self.buf.take() {
+                hal().kmem().free(page);
+            }
+        }
+    }
+}
+
+impl MsgQueue {
+    const fn new() -> Self {
+        Self {
+            inner: SleepableLock::new("mqueue", MsgQueueInner::new()),
+        }
+    }
+}
+
+impl MsgQueueTable {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLock::new("mqueues", ()),
+            queues: array![_ => MsgQueue::new(); NMSGQUEUE],
+        }
+    }
+
+    /// Opens the queue of `name`. If there is none and `attr` is given, creates one with `maxmsg`
+    /// messages of `msgsize` bytes, unless the queues would hold more than `limit` bytes.
+    fn open(
+        &self,
+        name: &[u8],
+        attr: Option<(usize, usize)>,
+        limit: u64,
+    ) -> Result<AllocatedMsgQueue, ()> {
+        let _guard = self.lock.lock();
+        let mut charged = 0;
+        let mut free = None;
+        for queue in &self.queues {
+            let mut inner = queue.inner.lock();
+            if inner.linked && inner.name() == name {
+                inner.nopen += 1;
+                return Ok(AllocatedMsgQueue {
+                    ptr: NonNull::from(queue),
+                });
+            }
+            if !inner.is_free() {
+                charged += inner.size();
+            } else if free.is_none() {
+                free = Some(queue);
+            }
+        }
+
+        let (maxmsg, msgsize) = attr.ok_or(())?;
+        let queue = free.ok_or(())?;
+        if (charged + maxmsg * msgsize) as u64 > limit {
+            return Err(());
+        }
+        let page = hal().kmem().alloc().ok_or(())?;
+        let mut inner = queue.inner.lock();
+        inner.name[..name.len()].copy_from_slice(name);
+        inner.namelen = name.len();
+        inner.linked = true;
+        inner.nopen = 1;
+        inner.maxmsg = maxmsg;
+        inner.msgsize = msgsize;
+        inner.buf = Some(page);
+        inner.seq = 0;
+        inner.waiters = Waiters::new();
+        Ok(AllocatedMsgQueue {
+            ptr: NonNull::from(queue),
+        })
+    }
+
+    /// Removes `name`. The queue is freed once its last descriptor is closed.
+    fn unlink(&self, name: &[u8]) -> Result<(), ()> {
+        let _guard = self.lock.lock();
+        for queue in &self.queues {
+            let mut inner = queue.inner.lock();
+            if inner.linked && inner.name() == name {
+                inner.linked = false;
+                inner.free_if_unused();
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+}
+
+impl AllocatedMsgQueue {
+    /// Sends the `len` bytes at `addr` as a message of priority `prio`, waiting while the queue
+    /// is full.
+    pub fn send(
+        &self,
+        addr: UVAddr,
+        len: usize,
+        prio: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        if prio >= MQ_PRIO_MAX {
+            return Err(());
+        }
+        let mut inner = self.inner.lock();
+        if len > inner.msgsize {
+            return Err(());
+        }
+        inner.wait_while_killable(|inner| inner.nmsg == inner.maxmsg, ctx)?;
+
+        let slot = (0..inner.maxmsg)
+            .find(|&i| inner.msgs[i].is_none())
+            .expect("AllocatedMsgQueue::send");
+        let off = slot * inner.msgsize;
+        let buf = inner.buf.as_mut().expect("AllocatedMsgQueue::send");
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(&mut buf[off..off + len], addr)?;
+        inner.seq += 1;
+        let seq = inner.seq;
+        inner.msgs[slot] = Some(Message { len, prio, seq });
+        inner.nmsg += 1;
+        inner.wakeup(ctx.kernel());
+        inner.waiters.post(ctx.kernel());
+        Ok(())
+    }
+
+    /// Receives the oldest message of the highest priority into `addr`, which must have room for
+    /// `msgsize` bytes, waiting while the queue is empty.
+    /// Returns Ok((length, priority) of the message) on success, Err(()) on error.
+    pub fn receive(
+        &self,
+        addr: UVAddr,
+        len: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(usize, u32), ()> {
+        let mut inner = self.inner.lock();
+        if len < inner.msgsize {
+            return Err(());
+        }
+        inner.wait_while_killable(|inner| inner.nmsg == 0, ctx)?;
+
+        let (slot, msg) = inner
+            .msgs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, msg)| msg.map(|msg| (i, msg)))
+            .max_by_key(|(_, msg)| (msg.prio, Reverse(msg.seq)))
+            .expect("AllocatedMsgQueue::receive");
+        let off = slot * inner.msgsize;
+        let buf = inner.buf.as_ref().expect("AllocatedMsgQueue::receive");
+        // The message stays in the queue if it cannot be copied out.
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr, &buf[off..off + msg.len])?;
+        inner.msgs[slot] = None;
+        inner.nmsg -= 1;
+        inner.wakeup(ctx.kernel());
+        inner.waiters.post(ctx.kernel());
+        Ok((msg.len, msg.prio))
+    }
+
+    pub fn getattr(&self) -> MqAttr {
+        let inner = self.inner.lock();
+        MqAttr {
+            flags: 0,
+            maxmsg: inner.maxmsg as i64,
+            msgsize: inner.msgsize as i64,
+            curmsgs: inner.nmsg as i64,
+        }
+    }
+
+    pub fn close(self) {
+        let mut inner = self.inner.lock();
+        inner.nopen -= 1;
+        inner.free_if_unused();
+    }
+
+    pub fn is_ready(&self, event: SelectEvent) -> bool {
+        let inner = self.inner.lock();
+        match event {
+            SelectEvent::Read => inner.nmsg > 0,
+            SelectEvent::Write => inner.nmsg < inner.maxmsg,
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Registers `waiter` to be posted whenever a message is sent or received.
+    pub fn add_waiter(&self, waiter: Waiter, kernel: KernelRef<'_, '_>) -> Result<(), ()> {
+        self.inner.lock().waiters.add(waiter, kernel)
+    }
+}
+
+/// Checks that `name`, the name of a message queue or a semaphore, is a slash followed by at least
+/// one character other than a slash.
+pub fn check_name(name: &[u8]) -> Result<(), ()> {
+    match name.split_first() {
+        Some((b'/', rest))
+            if !rest.is_empty() && name.len() <= IPCNAMEMAX && !rest.contains(&b'/') =>
+        {
+            Ok(())
+        }
+        _ => Err(()),
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Open the message queue name for reading or writing as the access mode of oflag asks. With
+    /// O_CREATE, creates the queue if it does not exist, with the attributes of the struct
+    /// mq_attr at attr, or default attributes if attr is 0.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    pub fn sys_mq_open(&mut self) -> Result<usize, ()> {
+        let mut name: [u8; MAXPATH] = [0; MAXPATH];
+        let name = self.proc_mut().argstr(0, &mut name)?.to_bytes();
+        let omode = FcntlFlags::from_bits(self.proc().argint(1)?).ok_or(())?;
+        let addr = self.proc().argaddr(2)?;
+        check_name(name)?;
+
+        let attr = if !omode.contains(FcntlFlags::O_CREATE) {
+            None
+        } else if addr == 0 {
+            Some((DEFAULT_MAXMSG, DEFAULT_MSGSIZE))
+        } else {
+            let mut attr = MqAttr::default();
+            // SAFETY: MqAttr does not have any internal structure.
+            unsafe { self.proc_mut().memory_mut().copy_in(&mut attr, addr.into()) }?;
+            let (maxmsg, msgsize) = (attr.maxmsg as usize, attr.msgsize as usize);
+            if !(1..=MSGQUEUE_MAXMSG).contains(&maxmsg) || msgsize == 0 || msgsize > PGSIZE / maxmsg
+            {
+                return Err(());
+            }
+            Some((maxmsg, msgsize))
+        };
+        let limit = self.proc().deref_data().rlimits.msgqueue.cur;
+        let mq = self.kernel().mqueues().open(name, attr, limit)?;
+        let ptr = mq.ptr;
+
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(
+                FileType::MsgQueue { mq },
+                !omode.intersects(FcntlFlags::O_WRONLY),
+                omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
+                self,
+            )
+            .map_err(|_| AllocatedMsgQueue { ptr }.close())?;
+        let fd = f.fdalloc(self)?;
+        if let Some(entry) = &mut self.proc_mut().deref_mut_data().open_files[fd as usize] {
+            entry.cloexec = omode.contains(FcntlFlags::O_CLOEXEC);
+        }
+        Ok(fd as usize)
+    }
+
+    /// Remove the message queue name. The queue is freed once every descriptor of it is closed.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_mq_unlink(&mut self) -> Result<usize, ()> {
+        let mut name: [u8; MAXPATH] = [0; MAXPATH];
+        let name = self.proc_mut().argstr(0, &mut name)?.to_bytes();
+        check_name(name)?;
+        self.kernel().mqueues().unlink(name)?;
+        Ok(0)
+    }
+
+    /// Send the len bytes at addr as a message of priority prio to the message queue fd.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_mq_send(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(1)?;
+        let len = self.proc().argint(2)?;
+        let prio = self.proc().argint(3)?;
+        if len < 0 || prio < 0 {
+            return Err(());
+        }
+        let (_, f) = self.proc().argfd(0)?;
+        let f = f as *const _;
+        // SAFETY: mq_send will not access proc's open_files.
+        unsafe { (*f).mq_send(addr.into(), len as usize, prio as u32, self) }?;
+        Ok(0)
+    }
+
+    /// Receive a message of the message queue fd into addr, which has room for len bytes, and
+    /// store its priority at prio unless prio is 0.
+    /// Returns Ok(the length of the message) on success, Err(()) on error.
+    pub fn sys_mq_receive(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(1)?;
+        let len = self.proc().argint(2)?;
+        let prio_addr = self.proc().argaddr(3)?;
+        if len < 0 {
+            return Err(());
+        }
+        let (_, f) = self.proc().argfd(0)?;
+        let f = f as *const _;
+        // SAFETY: mq_receive will not access proc's open_files.
+        let (n, prio) = unsafe { (*f).mq_receive(addr.into(), len as usize, self) }?;
+        if prio_addr != 0 {
+            self.proc_mut()
+                .memory_mut()
+                .copy_out(prio_addr.into(), &prio)?;
+        }
+        Ok(n)
+    }
+
+    /// Store the attributes of the message queue fd at the struct mq_attr at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_mq_getattr(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(1)?;
+        let attr = self.proc().argfd(0)?.1.msgqueue()?.getattr();
+        self.proc_mut().memory_mut().copy_out(addr.into(), &attr)?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of message queues.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(101, |ctx| ctx.sys_mq_open());
+    table.register(102, |ctx| ctx.sys_mq_unlink());
+    table.register(103, |ctx| ctx.sys_mq_send());
+    table.register(104, |ctx| ctx.sys_mq_receive());
+    table.register(105, |ctx| ctx.sys_mq_getattr());
+}
@@ -0,0 +1,41 @@
+//! The loopback interface.
+//!
+//! It owns 127.0.0.0/8, and delivers every packet sent through it back to the network stack of
+//! the same machine, so local sockets work without any network device. Delivery is synchronous:
+//! `transmit` returns only after the destination socket took the packet.
+
+use super::{Net, Packet};
+use crate::proc::KernelCtx;
+
+/// The address of the interface, which is the source of the packets sent through it by sockets
+/// bound to no address.
+pub const LOOPBACK_ADDR: u32 = 0x7f00_0001;
+
+/// Largest payload of a packet. Longer writes to stream sockets are split.
+pub const LOOPBACK_MTU: usize = 1024;
+
+pub struct Loopback;
+
+impl Loopback {
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// Does the interface own `addr`?
+    pub fn owns(&self, addr: u32) -> bool {
+        addr >> 24 == LOOPBACK_ADDR >> 24
+    }
+
+    /// Sends `packet`, which comes back in at once.
+    /// Returns Ok(number of bytes of the payload the destination took) on success, Err(()) if
+    /// the destination refused the packet.
+    pub fn transmit(
+        &self,
+        packet: &Packet<'_>,
+        net: &Net,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        debug_assert!(packet.payload.len() <= LOOPBACK_MTU);
+        net.input(packet, ctx)
+    }
+}
@@ -0,0 +1,1119 @@
+//! Sockets of the Internet domain, on a network stack whose only interface is the loopback
+//! interface.
+//!
+//! A socket sends a packet through the interface that owns the packet's destination address.
+//! There is no network device, so only the addresses of the loopback interface, 127.0.0.0/8, are
+//! reachable, and the interface hands every packet back to `Net::input`, which delivers it to
+//! the socket it is addressed to.
+//! * A datagram socket (`SOCK_DGRAM`) sends each write as a single packet. As in UDP, the packet
+//!   is dropped if no socket is bound to its destination or the receiver's buffer is full.
+//! * A stream socket (`SOCK_STREAM`) connects to a listening socket by sending it a SYN, on which
+//!   the listening socket creates the socket `accept()` returns. Writes are split into packets of
+//!   at most `LOOPBACK_MTU` bytes, and closing sends a FIN, after which the peer reads end of
+//!   file. Delivery waits while the receiver's buffer is full, and tells the sender how much of
+//!   the packet fit, so the sender sends the rest again. This stands in for flow control.
+//!
+//! A port is held by one socket of each type, whatever address the socket is bound to. Ports and
+//! addresses are in network byte order in `struct sockaddr_in`, and in host byte order in the
+//! kernel.
+//!
+//! Sockets of the UNIX domain share the socket table, and are in `unix`.
+
+use core::{
+    cmp,
+    convert::TryFrom,
+    mem,
+    ops::Deref,
+    ptr::{self, NonNull},
+};
+
+use array_macro::array;
+use zerocopy::{AsBytes, FromBytes};
+
+use self::loopback::{Loopback, LOOPBACK_ADDR, LOOPBACK_MTU};
+use self::unix::{SockAddrUn, UnixInner, UnixPath, AF_UNIX};
+use crate::{
+    addr::{UVAddr, PGSIZE},
+    epoll::{Waiter, Waiters},
+    file::{FileType, RcFile, SelectEvent},
+    hal::hal,
+    kernel::KernelRef,
+    lock::{SleepableLock, SleepableLockGuard, SpinLock},
+    ok_or,
+    param::{NBACKLOG, NSOCKET, SOCKBUF},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+mod loopback;
+mod unix;
+
+const AF_INET: u16 = 2;
+
+/// Types of `socket()`.
+const SOCK_STREAM: i32 = 1;
+const SOCK_DGRAM: i32 = 2;
+
+/// Protocols of `socket()`, each of which is the only protocol of its type.
+const IPPROTO_TCP: i32 = 6;
+const IPPROTO_UDP: i32 = 17;
+
+/// The address of a socket bound to every address of the machine.
+const INADDR_ANY: u32 = 0;
+
+/// The first port given to sockets that bind to port 0, or send before binding.
+const EPHEMERAL_PORT: u16 = 49152;
+
+/// `struct sockaddr_in`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct SockAddrIn {
+    /// Always `AF_INET`
+    family: u16,
+
+    /// Port, in network byte order
+    port: u16,
+
+    /// Address, in network byte order
+    addr: u32,
+
+    zero: [u8; 8],
+}
+
+/// The record before each datagram in the receive buffer of a datagram socket.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct DgramHeader {
+    len: u16,
+    port: u16,
+    addr: u32,
+}
+
+/// An address and port, in host byte order.
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct Endpoint {
+    pub addr: u32,
+    pub port: u16,
+}
+
+/// An address of either domain.
+#[derive(Copy, Clone)]
+pub enum SockAddr {
+    Inet(Endpoint),
+    Unix(UnixPath),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Domain {
+    Inet,
+    Unix,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SocketType {
+    Stream,
+    Dgram,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum SocketState {
+    Free,
+    /// Allocated, and neither listening nor connected.
+    Open,
+    Listening,
+    Connected,
+    /// Connected, and the peer has closed. The rest of the received data can still be read.
+    PeerClosed,
+}
+
+/// Kinds of packets of stream sockets. Datagram sockets send only `Data`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Asks a listening socket for a connection.
+    Syn,
+    Data,
+    /// Tells the peer that the sender has closed.
+    Fin,
+}
+
+pub struct Packet<'a> {
+    pub typ: SocketType,
+    pub seg: Segment,
+    pub src: Endpoint,
+    pub dst: Endpoint,
+    pub payload: &'a [u8],
+}
+
+struct SocketInner {
+    state: SocketState,
+    domain: Domain,
+    typ: SocketType,
+
+    /// Does the socket hold the port of `local`? The sockets a listening socket accepts share
+    /// its port without holding it.
+    bound: bool,
+    local: Endpoint,
+
+    /// The peer of a stream socket, or the default destination of a datagram socket.
+    remote: Option<Endpoint>,
+
+    /// Maximum number of connections waiting to be accepted.
+    backlog: usize,
+
+    /// Indices of the sockets of the connections waiting to be accepted, oldest first.
+    pending: [usize; NBACKLOG],
+    npending: usize,
+
+    /// Received bytes, or received datagrams each after its `DgramHeader`.
+    data: [u8; SOCKBUF],
+
+    /// Number of bytes read
+    nread: u32,
+
+    /// Number of bytes received
+    nwrite: u32,
+
+    /// The state of a UNIX domain socket.
+    unix: UnixInner,
+
+    /// Epolls watching the socket.
+    waiters: Waiters,
+}
+
+pub struct Socket {
+    inner: SleepableLock<SocketInner>,
+}
+
+pub struct Net {
+    lo: Loopback,
+    sockets: [Socket; NSOCKET],
+
+    /// The next ephemeral port to try. It is held while binding, so that two sockets cannot take
+    /// the same port.
+    next_port: SpinLock<u16>,
+}
+
+/// # Safety
+///
+/// `ptr` always refers to an allocated socket in the kernel's `Net`.
+/// For a single socket, we have a single `AllocatedSocket`, and the socket is freed only when
+/// the `AllocatedSocket` is closed.
+pub struct AllocatedSocket {
+    ptr: NonNull<Socket>,
+}
+
+// `AllocatedSocket` is `Send` because we access `SocketInner` only after acquring a lock
+// and because `AllocatedSocket` does not point to thread-local data.
+unsafe impl Send for AllocatedSocket {}
+
+impl Deref for AllocatedSocket {
+    type Target = Socket;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `ptr` always refers to a socket in the kernel's `Net`.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl SockAddrIn {
+    fn endpoint(&self) -> Result<Endpoint, ()> {
+        if self.family != AF_INET {
+            return Err(());
+        }
+        Ok(Endpoint {
+            addr: u32::from_be(self.addr),
+            port: u16::from_be(self.port),
+        })
+    }
+}
+
+impl From<Endpoint> for SockAddrIn {
+    fn from(ep: Endpoint) -> Self {
+        Self {
+            family: AF_INET,
+            port: ep.port.to_be(),
+            addr: ep.addr.to_be(),
+            zero: [0; 8],
+        }
+    }
+}
+
+impl SocketInner {
+    const fn new() -> Self {
+        Self {
+            state: SocketState::Free,
+            domain: Domain::Inet,
+            typ: SocketType::Stream,
+            bound: false,
+            local: Endpoint { addr: 0, port: 0 },
+            remote: None,
+            backlog: 0,
+            pending: [0; NBACKLOG],
+            npending: 0,
+            data: [0; SOCKBUF],
+            nread: 0,
+            nwrite: 0,
+            unix: UnixInner::new(),
+            waiters: Waiters::new(),
+        }
+    }
+
+    /// Frees the socket. The received data is discarded.
+    fn reset(&mut self) {
+        self.state = SocketState::Free;
+        self.bound = false;
+        self.local = Endpoint::default();
+        self.remote = None;
+        self.npending = 0;
+        self.nread = 0;
+        self.nwrite = 0;
+        self.unix = UnixInner::new();
+        self.waiters = Waiters::new();
+    }
+
+    fn len(&self) -> usize {
+        self.nwrite.wrapping_sub(self.nread) as usize
+    }
+
+    fn space(&self) -> usize {
+        SOCKBUF - self.len()
+    }
+
+    /// Is the socket the stream socket of the connection from `local` to `remote`?
+    fn is_connection(&self, local: Endpoint, remote: Endpoint) -> bool {
+        self.typ == SocketType::Stream
+            && matches!(self.state, SocketState::Connected | SocketState::PeerClosed)
+            && self.local == local
+            && self.remote == Some(remote)
+    }
+
+    /// Is the socket bound to a port and address to which `dst` is addressed?
+    fn is_bound_to(&self, dst: Endpoint) -> bool {
+        self.bound
+            && self.local.port == dst.port
+            && (self.local.addr == INADDR_ANY || self.local.addr == dst.addr)
+    }
+
+    /// Returns the address of the peer of a stream socket. A UNIX domain socket reports no name.
+    fn remote_addr(&self) -> SockAddr {
+        match self.domain {
+            Domain::Inet => SockAddr::Inet(self.remote.unwrap_or_default()),
+            Domain::Unix => SockAddr::Unix(UnixPath::new()),
+        }
+    }
+
+    fn is_readable(&self) -> bool {
+        match self.state {
+            SocketState::Listening => self.npending > 0,
+            SocketState::PeerClosed => true,
+            _ => self.len() > 0,
+        }
+    }
+
+    /// A datagram socket is always writable, and a stream socket once it is connected, though
+    /// a write may still wait for room at the receiver. After the peer has closed, a write
+    /// fails without waiting.
+    fn is_writable(&self) -> bool {
+        match self.typ {
+            SocketType::Dgram => true,
+            SocketType::Stream => {
+                matches!(self.state, SocketState::Connected | SocketState::PeerClosed)
+            }
+        }
+    }
+
+    /// Appends as many bytes of `src` as fit. Returns the number of appended bytes.
+    fn push(&mut self, src: &[u8]) -> usize {
+        let n = cmp::min(src.len(), self.space());
+        for &b in &src[..n] {
+            self.data[self.nwrite as usize % SOCKBUF] = b;
+            self.nwrite = self.nwrite.wrapping_add(1);
+        }
+        n
+    }
+
+    /// Removes at most `dst.len()` bytes into `dst`. Returns the number of removed bytes.
+    fn pop(&mut self, dst: &mut [u8]) -> usize {
+        let n = cmp::min(dst.len(), self.len());
+        for b in &mut dst[..n] {
+            *b = self.data[self.nread as usize % SOCKBUF];
+            self.nread = self.nread.wrapping_add(1);
+        }
+        n
+    }
+
+    /// Appends the datagram `payload` from `src`, unless it does not fit.
+    fn push_dgram(&mut self, src: Endpoint, payload: &[u8]) -> bool {
+        if self.space() < mem::size_of::<DgramHeader>() + payload.len() {
+            return false;
+        }
+        let header = DgramHeader {
+            len: payload.len() as u16,
+            port: src.port,
+            addr: src.addr,
+        };
+        let _ = self.push(header.as_bytes());
+        let _ = self.push(payload);
+        true
+    }
+
+    /// Removes the first datagram into `dst`, discarding the bytes that do not fit.
+    /// Returns the number of bytes copied into `dst` and the sender of the datagram.
+    fn pop_dgram(&mut self, dst: &mut [u8]) -> (usize, Endpoint) {
+        let mut header = DgramHeader::default();
+        let _ = self.pop(header.as_bytes_mut());
+        let len = header.len as usize;
+        let n = self.pop(&mut dst[..cmp::min(len, dst.len())]);
+        self.nread = self.nread.wrapping_add((len - n) as u32);
+        let src = Endpoint {
+            addr: header.addr,
+            port: header.port,
+        };
+        (n, src)
+    }
+}
+
+impl Socket {
+    const fn new() -> Self {
+        Self {
+            inner: SleepableLock::new("socket", SocketInner::new()),
+        }
+    }
+}
+
+impl Net {
+    pub const fn new() -> Self {
+        Self {
+            lo: Loopback::new(),
+            sockets: array![_ => Socket::new(); NSOCKET],
+            next_port: SpinLock::new("ports", EPHEMERAL_PORT),
+        }
+    }
+
+    /// Allocates an unbound socket of domain `domain` and type `typ`.
+    fn alloc(&self, domain: Domain, typ: SocketType) -> Result<AllocatedSocket, ()> {
+        for socket in &self.sockets {
+            let mut inner = socket.inner.lock();
+            if inner.state == SocketState::Free {
+                inner.reset();
+                inner.state = SocketState::Open;
+                inner.domain = domain;
+                inner.typ = typ;
+                return Ok(AllocatedSocket {
+                    ptr: NonNull::from(socket),
+                });
+            }
+        }
+        Err(())
+    }
+
+    fn index(&self, socket: &Socket) -> usize {
+        (socket as *const Socket as usize - self.sockets.as_ptr() as usize)
+            / mem::size_of::<Socket>()
+    }
+
+    /// Locks the first socket for which `f` returns true.
+    fn find<F>(&self, f: F) -> Option<SleepableLockGuard<'_, SocketInner>>
+    where
+        F: Fn(&SocketInner) -> bool,
+    {
+        for socket in &self.sockets {
+            let inner = socket.inner.lock();
+            if inner.state != SocketState::Free && f(&inner) {
+                return Some(inner);
+            }
+        }
+        None
+    }
+
+    /// Binds `socket` to `local`, or to an ephemeral port if the port of `local` is 0.
+    /// Returns Ok(()) on success, Err(()) if the socket is bound or the port is in use.
+    fn bind(&self, socket: &Socket, mut local: Endpoint) -> Result<(), ()> {
+        let mut next = self.next_port.lock();
+        let typ = socket.inner.lock().typ;
+        let in_use = |port: u16| {
+            self.sockets.iter().any(|s| {
+                !ptr::eq(s, socket) && {
+                    let inner = s.inner.lock();
+                    inner.state != SocketState::Free
+                        && inner.bound
+                        && inner.typ == typ
+                        && inner.local.port == port
+                }
+            })
+        };
+
+        if local.port == 0 {
+            let first = *next;
+            loop {
+                let port = *next;
+                *next = if port == u16::MAX {
+                    EPHEMERAL_PORT
+                } else {
+                    port + 1
+                };
+                if !in_use(port) {
+                    local.port = port;
+                    break;
+                }
+                if *next == first {
+                    return Err(());
+                }
+            }
+        } else if in_use(local.port) {
+            return Err(());
+        }
+
+        let mut inner = socket.inner.lock();
+        if inner.bound || inner.state != SocketState::Open {
+            return Err(());
+        }
+        inner.bound = true;
+        inner.local = local;
+        Ok(())
+    }
+
+    /// Binds `socket` to an ephemeral port unless it is bound.
+    /// Returns Ok(the address and port the socket is bound to) on success, Err(()) on error.
+    fn autobind(&self, socket: &Socket) -> Result<Endpoint, ()> {
+        if !socket.inner.lock().bound {
+            self.bind(socket, Endpoint::default())?;
+        }
+        Ok(socket.inner.lock().local)
+    }
+
+    /// Returns the interface that owns `addr`.
+    fn route(&self, addr: u32) -> Result<&Loopback, ()> {
+        if self.lo.owns(addr) {
+            Ok(&self.lo)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns the source of the packets a socket bound to `local` sends to address `dst`.
+    fn source(&self, local: Endpoint, dst: u32) -> Result<Endpoint, ()> {
+        let _ = self.route(dst)?;
+        let addr = if local.addr == INADDR_ANY {
+            LOOPBACK_ADDR
+        } else {
+            local.addr
+        };
+        Ok(Endpoint {
+            addr,
+            port: local.port,
+        })
+    }
+
+    /// Sends `packet` through the interface that owns its destination.
+    /// Returns Ok(number of bytes of the payload the destination took) on success, Err(()) if
+    /// the destination is unreachable or refused the packet.
+    fn send(&self, packet: &Packet<'_>, ctx: &KernelCtx<'_, '_>) -> Result<usize, ()> {
+        self.route(packet.dst.addr)?.transmit(packet, self, ctx)
+    }
+
+    /// Delivers `packet`, which came in through an interface, to the socket it is addressed to.
+    /// Returns Ok(number of bytes of the payload the socket took) on success, Err(()) if no
+    /// socket takes the packet.
+    fn input(&self, packet: &Packet<'_>, ctx: &KernelCtx<'_, '_>) -> Result<usize, ()> {
+        let (src, dst) = (packet.src, packet.dst);
+        match (packet.typ, packet.seg) {
+            (SocketType::Dgram, _) => {
+                let inner = self.find(|s| s.typ == SocketType::Dgram && s.is_bound_to(dst));
+                if let Some(mut inner) = inner {
+                    if inner.push_dgram(src, packet.payload) {
+                        inner.wakeup(ctx.kernel());
+                        inner.waiters.post(ctx.kernel());
+                    }
+                }
+                // A datagram nobody takes is dropped silently.
+                Ok(packet.payload.len())
+            }
+            (SocketType::Stream, Segment::Syn) => {
+                // Allocate first, since we cannot allocate while holding the listening socket.
+                let child = self.alloc(Domain::Inet, SocketType::Stream)?;
+                {
+                    let mut inner = child.inner.lock();
+                    inner.state = SocketState::Connected;
+                    inner.local = dst;
+                    inner.remote = Some(src);
+                }
+                let listener =
+                    self.find(|s| s.state == SocketState::Listening && s.is_bound_to(dst));
+                if let Some(mut listener) = listener {
+                    if listener.npending < listener.backlog {
+                        let n = listener.npending;
+                        listener.pending[n] = self.index(&child);
+                        listener.npending += 1;
+                        listener.wakeup(ctx.kernel());
+                        listener.waiters.post(ctx.kernel());
+                        return Ok(0);
+                    }
+                }
+                // Refused, as nobody listens or the backlog is full.
+                child.inner.lock().reset();
+                Err(())
+            }
+            (SocketType::Stream, Segment::Data) => {
+                let mut inner = self.find(|s| s.is_connection(dst, src)).ok_or(())?;
+                inner.wait_while_killable(
+                    |s| {
+                        s.space() == 0
+                            && s.state == SocketState::Connected
+                            && s.is_connection(dst, src)
+                    },
+                    ctx,
+                )?;
+                if inner.state != SocketState::Connected || !inner.is_connection(dst, src) {
+                    return Err(());
+                }
+                let n = inner.push(packet.payload);
+                inner.wakeup(ctx.kernel());
+                inner.waiters.post(ctx.kernel());
+                Ok(n)
+            }
+            (SocketType::Stream, Segment::Fin) => {
+                let mut inner = self.find(|s| s.is_connection(dst, src)).ok_or(())?;
+                inner.state = SocketState::PeerClosed;
+                inner.wakeup(ctx.kernel());
+                inner.waiters.post(ctx.kernel());
+                Ok(0)
+            }
+        }
+    }
+}
+
+impl AllocatedSocket {
+    /// Binds the socket to `sa`. An Internet address must be an address of an interface or
+    /// `INADDR_ANY`.
+    pub fn bind(&self, sa: &SockAddr, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let local = match sa {
+            SockAddr::Inet(local) => *local,
+            SockAddr::Unix(path) => return self.bind_unix(path, ctx),
+        };
+        let net = ctx.kernel().net();
+        if local.addr != INADDR_ANY {
+            let _ = net.route(local.addr)?;
+        }
+        net.bind(self, local)
+    }
+
+    /// Starts accepting connections, at most `backlog` of which wait to be accepted.
+    pub fn listen(&self, backlog: i32, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let domain = {
+            let inner = self.inner.lock();
+            if inner.typ != SocketType::Stream {
+                return Err(());
+            }
+            inner.domain
+        };
+        match domain {
+            Domain::Inet => {
+                let _ = ctx.kernel().net().autobind(self)?;
+            }
+            // Nobody can connect to a UNIX domain socket without a name.
+            Domain::Unix => {
+                if self.inner.lock().unix.name.is_none() {
+                    return Err(());
+                }
+            }
+        }
+        let mut inner = self.inner.lock();
+        if !matches!(inner.state, SocketState::Open | SocketState::Listening) {
+            return Err(());
+        }
+        inner.state = SocketState::Listening;
+        inner.backlog = cmp::max(1, cmp::min(backlog, NBACKLOG as i32)) as usize;
+        Ok(())
+    }
+
+    /// Waits for a connection, and accepts it.
+    /// Returns Ok((socket of the connection, peer)) on success, Err(()) on error.
+    pub fn accept(&self, ctx: &KernelCtx<'_, '_>) -> Result<(AllocatedSocket, SockAddr), ()> {
+        let mut inner = self.inner.lock();
+        if inner.state != SocketState::Listening {
+            return Err(());
+        }
+        inner.wait_while_killable(|s| s.npending == 0, ctx)?;
+        let index = inner.pending[0];
+        let n = inner.npending;
+        inner.pending.copy_within(1..n, 0);
+        inner.npending -= 1;
+        drop(inner);
+
+        let socket = &ctx.kernel().net().sockets[index];
+        let remote = socket.inner.lock().remote_addr();
+        Ok((
+            AllocatedSocket {
+                ptr: NonNull::from(socket),
+            },
+            remote,
+        ))
+    }
+
+    /// Connects a stream socket to the listening socket at `sa`, or sets the default destination
+    /// of a datagram socket to `sa`.
+    pub fn connect(&self, sa: &SockAddr, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let dst = match sa {
+            SockAddr::Inet(dst) => *dst,
+            SockAddr::Unix(path) => return self.connect_unix(path, ctx),
+        };
+        let net = ctx.kernel().net();
+        let src = net.source(net.autobind(self)?, dst.addr)?;
+
+        let mut inner = self.inner.lock();
+        if inner.state != SocketState::Open {
+            return Err(());
+        }
+        if inner.typ == SocketType::Dgram {
+            inner.remote = Some(dst);
+            return Ok(());
+        }
+        // Connected before sending the SYN, so that the peer can send at once.
+        inner.state = SocketState::Connected;
+        inner.local.addr = src.addr;
+        inner.remote = Some(dst);
+        drop(inner);
+
+        let syn = Packet {
+            typ: SocketType::Stream,
+            seg: Segment::Syn,
+            src,
+            dst,
+            payload: &[],
+        };
+        if net.send(&syn, ctx).is_err() {
+            let mut inner = self.inner.lock();
+            inner.state = SocketState::Open;
+            inner.remote = None;
+            return Err(());
+        }
+        Ok(())
+    }
+
+    /// Sends `n` bytes at `addr` to `dst`, or to the peer of the socket if `dst` is `None`.
+    /// A stream socket sends only to its peer, and a datagram socket sends `n` bytes as one
+    /// datagram.
+    /// Returns Ok(number of bytes sent) on success, Err(()) on error.
+    pub fn send(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        dst: Option<SockAddr>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let net = ctx.kernel().net();
+        let (domain, typ) = {
+            let inner = self.inner.lock();
+            (inner.domain, inner.typ)
+        };
+        let dst = match (domain, dst) {
+            (Domain::Inet, Some(SockAddr::Inet(dst))) => Some(dst),
+            (Domain::Inet, None) => None,
+            (Domain::Unix, Some(SockAddr::Unix(path))) => {
+                return self.send_unix(addr, n, Some(&path), ctx)
+            }
+            (Domain::Unix, None) => return self.send_unix(addr, n, None, ctx),
+            _ => return Err(()),
+        };
+
+        let allocator = hal().kmem();
+        let page = allocator.alloc().ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+
+        if typ == SocketType::Dgram {
+            if n > LOOPBACK_MTU {
+                return Err(());
+            }
+            let local = net.autobind(self)?;
+            let dst = dst.or(self.inner.lock().remote).ok_or(())?;
+            let src = net.source(local, dst.addr)?;
+            ctx.proc_mut()
+                .memory_mut()
+                .copy_in_bytes(&mut page[..n], addr)?;
+            let packet = Packet {
+                typ,
+                seg: Segment::Data,
+                src,
+                dst,
+                payload: &page[..n],
+            };
+            return net.send(&packet, ctx);
+        }
+
+        let (src, dst) = {
+            let inner = self.inner.lock();
+            if inner.state != SocketState::Connected {
+                return Err(());
+            }
+            (inner.local, inner.remote.ok_or(())?)
+        };
+        let mut sent = 0;
+        while sent < n {
+            let m = cmp::min(n - sent, LOOPBACK_MTU);
+            let r = ctx
+                .proc_mut()
+                .memory_mut()
+                .copy_in_bytes(&mut page[..m], addr + sent);
+            ok_or!(r, break);
+            // Send again what did not fit in the peer's buffer.
+            let mut off = 0;
+            while off < m {
+                let packet = Packet {
+                    typ,
+                    seg: Segment::Data,
+                    src,
+                    dst,
+                    payload: &page[off..m],
+                };
+                off += ok_or!(net.send(&packet, ctx), break);
+            }
+            sent += off;
+            if off < m {
+                break;
+            }
+        }
+        if sent == 0 && n > 0 {
+            return Err(());
+        }
+        Ok(sent)
+    }
+
+    /// Receives at most `n` bytes into `addr`, sleeping until there is something to receive.
+    /// A datagram socket receives one datagram, and discards the bytes of it that do not fit.
+    /// Returns Ok((number of bytes received, sender)) on success, Err(()) on error.
+    /// A stream socket receives 0 bytes after the peer has closed.
+    pub fn recv(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(usize, SockAddr), ()> {
+        let allocator = hal().kmem();
+        let page = allocator.alloc().ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+        let m = cmp::min(n, PGSIZE);
+
+        let mut inner = self.inner.lock();
+        let (len, src) = match inner.typ {
+            SocketType::Stream => {
+                if !matches!(
+                    inner.state,
+                    SocketState::Connected | SocketState::PeerClosed
+                ) {
+                    return Err(());
+                }
+                inner.wait_while_killable(
+                    |s| s.len() == 0 && s.state == SocketState::Connected,
+                    ctx,
+                )?;
+                let len = inner.pop(&mut page[..m]);
+                (len, inner.remote_addr())
+            }
+            SocketType::Dgram => {
+                inner.wait_while_killable(|s| s.len() == 0, ctx)?;
+                match inner.domain {
+                    Domain::Inet => {
+                        let (len, src) = inner.pop_dgram(&mut page[..m]);
+                        (len, SockAddr::Inet(src))
+                    }
+                    Domain::Unix => {
+                        let (len, src) = inner.pop_unix_dgram(&mut page[..m]);
+                        (len, SockAddr::Unix(src))
+                    }
+                }
+            }
+        };
+        // Wake up the senders waiting for room.
+        inner.wakeup(ctx.kernel());
+        drop(inner);
+
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr, &page[..len])?;
+        Ok((len, src))
+    }
+
+    pub fn domain(&self) -> Domain {
+        self.inner.lock().domain
+    }
+
+    /// Returns the address the socket is bound to.
+    pub fn local(&self) -> SockAddr {
+        let inner = self.inner.lock();
+        match inner.domain {
+            Domain::Inet => SockAddr::Inet(inner.local),
+            Domain::Unix => SockAddr::Unix(inner.unix.path),
+        }
+    }
+
+    /// Returns the port of an Internet socket, or the inode number of the name of a UNIX domain
+    /// socket.
+    pub fn id(&self) -> u32 {
+        let inner = self.inner.lock();
+        match inner.domain {
+            Domain::Inet => inner.local.port as u32,
+            Domain::Unix => inner.unix.name.map_or(0, |name| name.ino()),
+        }
+    }
+
+    /// Frees the socket. A connected stream socket sends a FIN to its peer, and so do the
+    /// connections waiting to be accepted by a listening socket. A UNIX domain socket unlinks
+    /// its peer instead.
+    pub fn close(self, ctx: &KernelCtx<'_, '_>) {
+        let net = ctx.kernel().net();
+        let mut inner = self.inner.lock();
+        let peer = inner.unix.peer;
+        let fin = match (inner.state, inner.remote) {
+            (SocketState::Connected, Some(remote)) => Some((inner.local, remote)),
+            _ => None,
+        };
+        let mut pending = [0; NBACKLOG];
+        let npending = if inner.state == SocketState::Listening {
+            inner.npending
+        } else {
+            0
+        };
+        pending[..npending].copy_from_slice(&inner.pending[..npending]);
+        inner.reset();
+        // Wake up the senders waiting for room, which fail now.
+        inner.wakeup(ctx.kernel());
+        drop(inner);
+
+        if let Some(peer) = peer {
+            net.unlink(peer, net.index(&self), ctx);
+        }
+        if let Some((src, dst)) = fin {
+            let packet = Packet {
+                typ: SocketType::Stream,
+                seg: Segment::Fin,
+                src,
+                dst,
+                payload: &[],
+            };
+            let _ = net.send(&packet, ctx);
+        }
+        for &index in &pending[..npending] {
+            AllocatedSocket {
+                ptr: NonNull::from(&net.sockets[index]),
+            }
+            .close(ctx);
+        }
+    }
+
+    pub fn is_ready(&self, event: SelectEvent) -> bool {
+        match event {
+            SelectEvent::Read => self.inner.lock().is_readable(),
+            SelectEvent::Write => self.inner.lock().is_writable(),
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Registers `waiter` to be posted whenever data or a connection arrives, or the peer
+    /// closes.
+    pub fn add_waiter(&self, waiter: Waiter, kernel: KernelRef<'_, '_>) -> Result<(), ()> {
+        self.inner.lock().waiters.add(waiter, kernel)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Allocate a file descriptor for `socket`. The socket is closed on failure.
+    /// Returns Ok(fd) on success, Err(()) on error.
+    fn socket_fd(&mut self, socket: AllocatedSocket) -> Result<i32, ()> {
+        let ptr = socket.ptr;
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Socket { socket }, true, true, self)
+            .map_err(|_| AllocatedSocket { ptr }.close(self))?;
+        f.fdalloc(self)
+    }
+
+    /// Copy in the address of `domain` of `len` bytes at `addr`.
+    fn sockaddr(&mut self, domain: Domain, addr: usize, len: i32) -> Result<SockAddr, ()> {
+        if domain == Domain::Unix {
+            return self.sockaddr_un(addr, len).map(SockAddr::Unix);
+        }
+        if len < mem::size_of::<SockAddrIn>() as i32 {
+            return Err(());
+        }
+        let mut sa = SockAddrIn::default();
+        // SAFETY: SockAddrIn does not have any internal structure.
+        unsafe { self.proc_mut().memory_mut().copy_in(&mut sa, addr.into()) }?;
+        Ok(SockAddr::Inet(sa.endpoint()?))
+    }
+
+    /// Copy out `sa` as a `struct sockaddr_in` or `struct sockaddr_un` to `addr`, and its
+    /// length to `lenp`, unless `addr` is 0. A `struct sockaddr_un` is truncated if it does not
+    /// fit.
+    fn copy_out_sockaddr(&mut self, sa: &SockAddr, addr: usize, lenp: usize) -> Result<(), ()> {
+        if addr == 0 {
+            return Ok(());
+        }
+        let mut len: i32 = 0;
+        // SAFETY: i32 does not have any internal structure.
+        unsafe { self.proc_mut().memory_mut().copy_in(&mut len, lenp.into()) }?;
+        let memory = self.proc_mut().memory_mut();
+        match sa {
+            SockAddr::Inet(ep) => {
+                let size = mem::size_of::<SockAddrIn>() as i32;
+                if len < size {
+                    return Err(());
+                }
+                memory.copy_out(addr.into(), &SockAddrIn::from(*ep))?;
+                memory.copy_out(lenp.into(), &size)
+            }
+            SockAddr::Unix(path) => {
+                let size = path.sockaddr_len();
+                let n = cmp::min(size, cmp::max(len, 0) as usize);
+                memory.copy_out_bytes(addr.into(), &SockAddrUn::from(path).as_bytes()[..n])?;
+                memory.copy_out(lenp.into(), &(size as i32))
+            }
+        }
+    }
+
+    /// Create a socket of domain, type, and protocol.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    pub fn sys_socket(&mut self) -> Result<usize, ()> {
+        let domain = self.proc().argint(0)?;
+        let typ = self.proc().argint(1)?;
+        let protocol = self.proc().argint(2)?;
+        let domain = match u16::try_from(domain) {
+            Ok(AF_INET) => Domain::Inet,
+            Ok(AF_UNIX) => Domain::Unix,
+            _ => return Err(()),
+        };
+        let typ = match (domain, typ, protocol) {
+            (_, SOCK_STREAM, 0) | (Domain::Inet, SOCK_STREAM, IPPROTO_TCP) => SocketType::Stream,
+            (_, SOCK_DGRAM, 0) | (Domain::Inet, SOCK_DGRAM, IPPROTO_UDP) => SocketType::Dgram,
+            _ => return Err(()),
+        };
+        let socket = self.kernel().net().alloc(domain, typ)?;
+        let fd = self.socket_fd(socket)?;
+        Ok(fd as usize)
+    }
+
+    /// Bind the socket fd to the address at addr of len bytes.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_bind(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let domain = f.socket()?.domain();
+        let f = f as *const RcFile;
+        let addr = self.proc().argaddr(1)?;
+        let len = self.proc().argint(2)?;
+        let sa = self.sockaddr(domain, addr, len)?;
+        // SAFETY: bind will not access proc's open_files.
+        unsafe { (*f).socket()?.bind(&sa, self) }?;
+        Ok(0)
+    }
+
+    /// Make the socket fd accept connections, at most backlog of which wait to be accepted.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_listen(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let backlog = self.proc().argint(1)?;
+        // SAFETY: listen will not access proc's open_files.
+        unsafe { (*(f as *const RcFile)).socket()?.listen(backlog, self) }?;
+        Ok(0)
+    }
+
+    /// Accept a connection on the socket fd, and copy out the peer's address to addr and its
+    /// length to lenp, unless addr is 0.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    pub fn sys_accept(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let f = f as *const RcFile;
+        let addr = self.proc().argaddr(1)?;
+        let lenp = self.proc().argaddr(2)?;
+        // SAFETY: accept will not access proc's open_files.
+        let (conn, remote) = unsafe { (*f).socket()?.accept(self) }?;
+        let fd = self.socket_fd(conn)?;
+        self.copy_out_sockaddr(&remote, addr, lenp)?;
+        Ok(fd as usize)
+    }
+
+    /// Connect the socket fd to the address at addr of len bytes.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_connect(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let domain = f.socket()?.domain();
+        let f = f as *const RcFile;
+        let addr = self.proc().argaddr(1)?;
+        let len = self.proc().argint(2)?;
+        let sa = self.sockaddr(domain, addr, len)?;
+        // SAFETY: connect will not access proc's open_files.
+        unsafe { (*f).socket()?.connect(&sa, self) }?;
+        Ok(0)
+    }
+
+    /// Send n bytes at buf with the socket fd to the address at addr of len bytes, or to the
+    /// peer of the socket if addr is 0. No flags are supported.
+    /// Returns Ok(number of bytes sent) on success, Err(()) on error.
+    pub fn sys_sendto(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let domain = f.socket()?.domain();
+        let f = f as *const RcFile;
+        let buf = self.proc().argaddr(1)?;
+        let n = self.proc().argint(2)?;
+        let flags = self.proc().argint(3)?;
+        let addr = self.proc().argaddr(4)?;
+        let len = self.proc().argint(5)?;
+        if n < 0 || flags != 0 {
+            return Err(());
+        }
+        let dst = if addr == 0 {
+            None
+        } else {
+            Some(self.sockaddr(domain, addr, len)?)
+        };
+        // SAFETY: send will not access proc's open_files.
+        unsafe { (*f).socket()?.send(buf.into(), n as usize, dst, self) }
+    }
+
+    /// Receive at most n bytes into buf with the socket fd, and copy out the sender's address
+    /// to addr and its length to lenp, unless addr is 0. No flags are supported.
+    /// Returns Ok(number of bytes received) on success, Err(()) on error.
+    pub fn sys_recvfrom(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let f = f as *const RcFile;
+        let buf = self.proc().argaddr(1)?;
+        let n = self.proc().argint(2)?;
+        let flags = self.proc().argint(3)?;
+        let addr = self.proc().argaddr(4)?;
+        let lenp = self.proc().argaddr(5)?;
+        if n < 0 || flags != 0 {
+            return Err(());
+        }
+        // SAFETY: recv will not access proc's open_files.
+        let (len, src) = unsafe { (*f).socket()?.recv(buf.into(), n as usize, self) }?;
+        self.copy_out_sockaddr(&src, addr, lenp)?;
+        Ok(len)
+    }
+
+    /// Copy out the address the socket fd is bound to to addr, and its length to lenp.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_getsockname(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let addr = self.proc().argaddr(1)?;
+        let lenp = self.proc().argaddr(2)?;
+        if addr == 0 {
+            return Err(());
+        }
+        let local = f.socket()?.local();
+        self.copy_out_sockaddr(&local, addr, lenp)?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of sockets.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(83, |ctx| ctx.sys_socket());
+    table.register(84, |ctx| ctx.sys_bind());
+    table.register(85, |ctx| ctx.sys_listen());
+    table.register(86, |ctx| ctx.sys_accept());
+    table.register(87, |ctx| ctx.sys_connect());
+    table.register(88, |ctx| ctx.sys_sendto());
+    table.register(89, |ctx| ctx.sys_recvfrom());
+    table.register(90, |ctx| ctx.sys_getsockname());
+    table.register(91, |ctx| ctx.sys_socketpair());
+}
@@ -0,0 +1,553 @@
+//! Sockets of the UNIX domain, which connect the processes of the same machine.
+//!
+//! `bind()` names a socket by creating a socket inode at a path. The inode outlives the socket,
+//! and stays until it is unlinked, so binding to the path of an existing file fails. `connect()`
+//! and `sendto()` look up the inode at a path, and find the socket bound to it by the device,
+//! number, and generation of the inode.
+//!
+//! There is no network stack in between. A connected stream socket is linked to its peer, and a
+//! write goes straight into the peer's receive buffer, waiting while it is full. A datagram also
+//! waits for room in the receiver's buffer instead of being dropped. `socketpair()` creates two
+//! linked sockets without names.
+
+use core::{cmp, mem};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use super::{
+    AllocatedSocket, Domain, Net, Socket, SocketInner, SocketState, SocketType, SOCK_DGRAM,
+    SOCK_STREAM,
+};
+use crate::{
+    addr::{UVAddr, PGSIZE},
+    fs::{FileSystem, FileSystemExt, InodeType, Path},
+    hal::hal,
+    lock::SleepableLockGuard,
+    ok_or,
+    param::SOCKBUF,
+    proc::KernelCtx,
+};
+
+pub const AF_UNIX: u16 = 1;
+
+/// Size of the path of `struct sockaddr_un`.
+const UNIX_PATH_MAX: usize = 108;
+
+/// `struct sockaddr_un`.
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct SockAddrUn {
+    /// Always `AF_UNIX`
+    family: u16,
+
+    /// Path of the socket inode, terminated by a NUL unless it fills the array
+    path: [u8; UNIX_PATH_MAX],
+}
+
+/// The path a socket is bound to, which is empty if the socket has no name.
+#[derive(Copy, Clone)]
+pub struct UnixPath {
+    // Invariant: `bytes[..len]` contains no NUL characters.
+    bytes: [u8; UNIX_PATH_MAX],
+    len: usize,
+}
+
+/// The socket inode a socket is bound to.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct UnixName {
+    dev: i32,
+    ino: u32,
+    gen: u32,
+}
+
+/// Where a datagram or the bytes of a stream go.
+#[derive(Copy, Clone)]
+enum UnixDst {
+    /// The socket of the index, if it is linked to the sender.
+    Peer(usize),
+    /// The datagram socket bound to the inode.
+    Name(UnixName),
+}
+
+/// The record before each datagram in the receive buffer of a datagram socket. The path of the
+/// sender follows, and then the datagram.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct UnixDgramHeader {
+    len: u16,
+    pathlen: u16,
+}
+
+pub struct UnixInner {
+    /// The inode the socket is bound to. The sockets a listening socket accepts share it.
+    pub(super) name: Option<UnixName>,
+    pub(super) path: UnixPath,
+
+    /// Index of the peer of a stream socket, or of the other datagram socket of a pair.
+    pub(super) peer: Option<usize>,
+
+    /// The default destination of a datagram socket.
+    target: Option<UnixName>,
+}
+
+impl SockAddrUn {
+    const fn new() -> Self {
+        Self {
+            family: 0,
+            path: [0; UNIX_PATH_MAX],
+        }
+    }
+
+    /// Returns the path of the address of `len` bytes.
+    fn path(&self, len: usize) -> Result<UnixPath, ()> {
+        if self.family != AF_UNIX {
+            return Err(());
+        }
+        let bytes = &self.path[..len - mem::size_of::<u16>()];
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        if len == 0 {
+            return Err(());
+        }
+        let mut path = UnixPath::new();
+        path.bytes[..len].copy_from_slice(&bytes[..len]);
+        path.len = len;
+        Ok(path)
+    }
+}
+
+impl From<&UnixPath> for SockAddrUn {
+    fn from(path: &UnixPath) -> Self {
+        let mut sa = Self::new();
+        sa.family = AF_UNIX;
+        sa.path[..path.len].copy_from_slice(&path.bytes[..path.len]);
+        sa
+    }
+}
+
+impl UnixPath {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; UNIX_PATH_MAX],
+            len: 0,
+        }
+    }
+
+    fn as_path(&self) -> &Path {
+        // SAFETY: `bytes[..len]` contains no NUL characters.
+        unsafe { Path::from_bytes(&self.bytes[..self.len]) }
+    }
+
+    /// Returns the length of the `struct sockaddr_un` of the path, which has a NUL after the
+    /// path if it fits, or only the family if the path is empty.
+    pub fn sockaddr_len(&self) -> usize {
+        if self.len == 0 {
+            return mem::size_of::<u16>();
+        }
+        cmp::min(
+            mem::size_of::<u16>() + self.len + 1,
+            mem::size_of::<SockAddrUn>(),
+        )
+    }
+}
+
+impl UnixName {
+    pub fn ino(&self) -> u32 {
+        self.ino
+    }
+
+    /// Creates a socket inode at `path`.
+    /// Returns Ok(the name of the inode) on success, Err(()) if the path exists.
+    fn create(path: &UnixPath, ctx: &KernelCtx<'_, '_>) -> Result<Self, ()> {
+        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+        let res = ctx
+            .kernel()
+            .fs()
+            .create(path.as_path(), None, InodeType::Socket, &tx, ctx, |_| ())
+            .map(|(ptr, _)| {
+                let st = ptr.stat(ctx);
+                ptr.free((&tx, ctx));
+                st
+            });
+        tx.end(ctx);
+        let st = res?;
+        Ok(Self {
+            dev: st.dev,
+            ino: st.ino,
+            gen: st.gen,
+        })
+    }
+
+    /// Returns Ok(the name of the socket inode at `path`) on success, Err(()) if there is no
+    /// socket inode at the path.
+    fn lookup(path: &UnixPath, ctx: &KernelCtx<'_, '_>) -> Result<Self, ()> {
+        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
+        let res = ctx
+            .kernel()
+            .fs()
+            .namei(path.as_path(), None, &tx, ctx)
+            .map(|ptr| {
+                let ip = ptr.lock(ctx);
+                let typ = ip.deref_inner().typ;
+                ip.free(ctx);
+                let st = ptr.stat(ctx);
+                ptr.free((&tx, ctx));
+                (typ, st)
+            });
+        tx.end(ctx);
+        let (typ, st) = res?;
+        if typ != InodeType::Socket {
+            return Err(());
+        }
+        Ok(Self {
+            dev: st.dev,
+            ino: st.ino,
+            gen: st.gen,
+        })
+    }
+}
+
+impl UnixInner {
+    pub const fn new() -> Self {
+        Self {
+            name: None,
+            path: UnixPath::new(),
+            peer: None,
+            target: None,
+        }
+    }
+}
+
+impl SocketInner {
+    /// Does the socket take what socket `me` sends to `dst`?
+    fn accepts(&self, dst: UnixDst, me: usize) -> bool {
+        self.state != SocketState::Free
+            && self.domain == Domain::Unix
+            && match dst {
+                UnixDst::Peer(_) => self.unix.peer == Some(me),
+                UnixDst::Name(name) => {
+                    self.typ == SocketType::Dgram && self.unix.name == Some(name)
+                }
+            }
+    }
+
+    /// Appends the datagram `payload` from `src`, which must fit.
+    fn push_unix_dgram(&mut self, src: &UnixPath, payload: &[u8]) {
+        let header = UnixDgramHeader {
+            len: payload.len() as u16,
+            pathlen: src.len as u16,
+        };
+        let _ = self.push(header.as_bytes());
+        let _ = self.push(&src.bytes[..src.len]);
+        let _ = self.push(payload);
+    }
+
+    /// Removes the first datagram into `dst`, discarding the bytes that do not fit.
+    /// Returns the number of bytes copied into `dst` and the path of the sender.
+    pub fn pop_unix_dgram(&mut self, dst: &mut [u8]) -> (usize, UnixPath) {
+        let mut header = UnixDgramHeader::default();
+        let _ = self.pop(header.as_bytes_mut());
+        let mut src = UnixPath::new();
+        src.len = self.pop(&mut src.bytes[..header.pathlen as usize]);
+        let len = header.len as usize;
+        let n = self.pop(&mut dst[..cmp::min(len, dst.len())]);
+        self.nread = self.nread.wrapping_add((len - n) as u32);
+        (n, src)
+    }
+}
+
+impl Net {
+    /// Locks the socket that takes what socket `me` sends to `dst`.
+    fn lock_unix(&self, dst: UnixDst, me: usize) -> Option<SleepableLockGuard<'_, SocketInner>> {
+        match dst {
+            UnixDst::Peer(index) => {
+                let inner = self.sockets[index].inner.lock();
+                if inner.accepts(dst, me) {
+                    Some(inner)
+                } else {
+                    None
+                }
+            }
+            UnixDst::Name(_) => self.find(|s| s.accepts(dst, me)),
+        }
+    }
+
+    /// Appends as many bytes of `data` as fit to the receive buffer of `dst`, sleeping while it
+    /// is full.
+    /// Returns Ok(number of appended bytes) on success, Err(()) if `dst` is gone.
+    fn deliver(
+        &self,
+        dst: UnixDst,
+        me: usize,
+        data: &[u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let mut inner = self.lock_unix(dst, me).ok_or(())?;
+        inner.wait_while_killable(|s| s.space() == 0 && s.accepts(dst, me), ctx)?;
+        if !inner.accepts(dst, me) {
+            return Err(());
+        }
+        let n = inner.push(data);
+        inner.wakeup(ctx.kernel());
+        inner.waiters.post(ctx.kernel());
+        Ok(n)
+    }
+
+    /// Appends the datagram `payload` from `src` to the receive buffer of `dst`, sleeping until
+    /// it fits.
+    /// Returns Ok(()) on success, Err(()) if `dst` is gone.
+    fn deliver_dgram(
+        &self,
+        dst: UnixDst,
+        me: usize,
+        src: &UnixPath,
+        payload: &[u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        let size = mem::size_of::<UnixDgramHeader>() + src.len + payload.len();
+        let mut inner = self.lock_unix(dst, me).ok_or(())?;
+        inner.wait_while_killable(|s| s.space() < size && s.accepts(dst, me), ctx)?;
+        if !inner.accepts(dst, me) {
+            return Err(());
+        }
+        inner.push_unix_dgram(src, payload);
+        inner.wakeup(ctx.kernel());
+        inner.waiters.post(ctx.kernel());
+        Ok(())
+    }
+
+    /// Links `socket` and `peer`, which must be UNIX domain sockets of the same type.
+    /// Stream sockets become connected.
+    fn link(&self, socket: &Socket, peer: &Socket) {
+        for &(a, b) in &[(socket, peer), (peer, socket)] {
+            let mut inner = a.inner.lock();
+            inner.unix.peer = Some(self.index(b));
+            if inner.typ == SocketType::Stream {
+                inner.state = SocketState::Connected;
+            }
+        }
+    }
+
+    /// Unlinks socket `index` from its peer `me`, which is closing. A stream socket reads end of
+    /// file after the rest of the received data.
+    pub fn unlink(&self, index: usize, me: usize, ctx: &KernelCtx<'_, '_>) {
+        let mut inner = self.sockets[index].inner.lock();
+        if inner.accepts(UnixDst::Peer(index), me) {
+            inner.unix.peer = None;
+            if inner.state == SocketState::Connected {
+                inner.state = SocketState::PeerClosed;
+            }
+            inner.wakeup(ctx.kernel());
+            inner.waiters.post(ctx.kernel());
+        }
+    }
+
+    /// Allocates two linked UNIX domain sockets of type `typ`.
+    fn pair(&self, typ: SocketType) -> Result<(AllocatedSocket, AllocatedSocket), ()> {
+        let socket = self.alloc(Domain::Unix, typ)?;
+        let peer = ok_or!(self.alloc(Domain::Unix, typ), {
+            socket.inner.lock().reset();
+            return Err(());
+        });
+        self.link(&socket, &peer);
+        Ok((socket, peer))
+    }
+}
+
+impl AllocatedSocket {
+    /// Binds the socket to a new socket inode at `path`.
+    pub fn bind_unix(&self, path: &UnixPath, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let is_bindable = |s: &SocketInner| s.state == SocketState::Open && s.unix.name.is_none();
+        if !is_bindable(&self.inner.lock()) {
+            return Err(());
+        }
+        let name = UnixName::create(path, ctx)?;
+        let mut inner = self.inner.lock();
+        if !is_bindable(&inner) {
+            return Err(());
+        }
+        inner.unix.name = Some(name);
+        inner.unix.path = *path;
+        Ok(())
+    }
+
+    /// Connects a stream socket to the listening socket bound to the inode at `path`, or sets
+    /// the default destination of a datagram socket to the inode.
+    pub fn connect_unix(&self, path: &UnixPath, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let net = ctx.kernel().net();
+        let name = UnixName::lookup(path, ctx)?;
+        {
+            let mut inner = self.inner.lock();
+            if inner.state != SocketState::Open || inner.unix.peer.is_some() {
+                return Err(());
+            }
+            if inner.typ == SocketType::Dgram {
+                inner.unix.target = Some(name);
+                return Ok(());
+            }
+        }
+
+        // The socket `accept()` will return, linked before the listening socket gets it, so
+        // that the peer can send at once.
+        let child = net.alloc(Domain::Unix, SocketType::Stream)?;
+        {
+            let mut inner = child.inner.lock();
+            inner.unix.name = Some(name);
+            inner.unix.path = *path;
+        }
+        if self.inner.lock().state != SocketState::Open {
+            child.inner.lock().reset();
+            return Err(());
+        }
+        net.link(self, &child);
+
+        let listener = net.find(|s| {
+            s.domain == Domain::Unix
+                && s.state == SocketState::Listening
+                && s.unix.name == Some(name)
+        });
+        if let Some(mut listener) = listener {
+            if listener.npending < listener.backlog {
+                let n = listener.npending;
+                listener.pending[n] = net.index(&child);
+                listener.npending += 1;
+                listener.wakeup(ctx.kernel());
+                listener.waiters.post(ctx.kernel());
+                return Ok(());
+            }
+        }
+        // Refused, as nobody listens or the backlog is full.
+        child.inner.lock().reset();
+        let mut inner = self.inner.lock();
+        inner.state = SocketState::Open;
+        inner.unix.peer = None;
+        Err(())
+    }
+
+    /// Sends `n` bytes at `addr` to the datagram socket bound to the inode at `dst`, or to the
+    /// peer or default destination of the socket if `dst` is `None`. A stream socket sends only
+    /// to its peer.
+    /// Returns Ok(number of bytes sent) on success, Err(()) on error.
+    pub fn send_unix(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        dst: Option<&UnixPath>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let net = ctx.kernel().net();
+        let me = net.index(self);
+        let (typ, state, src, peer, target) = {
+            let inner = self.inner.lock();
+            (
+                inner.typ,
+                inner.state,
+                inner.unix.path,
+                inner.unix.peer,
+                inner.unix.target,
+            )
+        };
+
+        let allocator = hal().kmem();
+        let page = allocator.alloc().ok_or(())?;
+        let mut page = scopeguard::guard(page, |page| allocator.free(page));
+
+        if typ == SocketType::Dgram {
+            if n > PGSIZE || mem::size_of::<UnixDgramHeader>() + src.len + n > SOCKBUF {
+                return Err(());
+            }
+            let dst = match (dst, peer, target) {
+                (Some(path), _, _) => UnixDst::Name(UnixName::lookup(path, ctx)?),
+                (None, Some(index), _) => UnixDst::Peer(index),
+                (None, None, Some(name)) => UnixDst::Name(name),
+                (None, None, None) => return Err(()),
+            };
+            ctx.proc_mut()
+                .memory_mut()
+                .copy_in_bytes(&mut page[..n], addr)?;
+            net.deliver_dgram(dst, me, &src, &page[..n], ctx)?;
+            return Ok(n);
+        }
+
+        if state != SocketState::Connected {
+            return Err(());
+        }
+        let dst = UnixDst::Peer(peer.ok_or(())?);
+        let mut sent = 0;
+        while sent < n {
+            let m = cmp::min(n - sent, PGSIZE);
+            let r = ctx
+                .proc_mut()
+                .memory_mut()
+                .copy_in_bytes(&mut page[..m], addr + sent);
+            ok_or!(r, break);
+            let mut off = 0;
+            while off < m {
+                off += ok_or!(net.deliver(dst, me, &page[off..m], ctx), break);
+            }
+            sent += off;
+            if off < m {
+                break;
+            }
+        }
+        if sent == 0 && n > 0 {
+            return Err(());
+        }
+        Ok(sent)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Copy in the path of the `struct sockaddr_un` of `len` bytes at `addr`.
+    pub fn sockaddr_un(&mut self, addr: usize, len: i32) -> Result<UnixPath, ()> {
+        if len <= mem::size_of::<u16>() as i32 {
+            return Err(());
+        }
+        let len = cmp::min(len as usize, mem::size_of::<SockAddrUn>());
+        let mut sa = SockAddrUn::new();
+        self.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(&mut sa.as_bytes_mut()[..len], addr.into())?;
+        sa.path(len)
+    }
+
+    /// Create a pair of connected sockets of domain, type, and protocol, and copy out their
+    /// file descriptors to sv.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_socketpair(&mut self) -> Result<usize, ()> {
+        let domain = self.proc().argint(0)?;
+        let typ = self.proc().argint(1)?;
+        let protocol = self.proc().argint(2)?;
+        let sv = self.proc().argaddr(3)?;
+        if domain != AF_UNIX as i32 || protocol != 0 {
+            return Err(());
+        }
+        let typ = match typ {
+            SOCK_STREAM => SocketType::Stream,
+            SOCK_DGRAM => SocketType::Dgram,
+            _ => return Err(()),
+        };
+        let (socket, peer) = self.kernel().net().pair(typ)?;
+
+        let fd1 = if let Ok(fd) = self.socket_fd(socket) {
+            fd
+        } else {
+            peer.close(self);
+            return Err(());
+        };
+
+        let fd2 = if let Ok(fd) = self.socket_fd(peer) {
+            fd
+        } else {
+            self.proc_mut().deref_mut_data().open_files[fd1 as usize]
+                .take()
+                .unwrap()
+                .free(self);
+            return Err(());
+        };
+
+        self.proc_mut()
+            .memory_mut()
+            .copy_out(sv.into(), &[fd1, fd2])?;
+        Ok(0)
+    }
+}
@@ -25,7 +25,8 @@ pub struct RawPage {
 /// - inner is 4096 bytes-aligned.
 /// - end <= inner < PHYSTOP
 /// - Two different pages never overwrap. If p1: Page and p2: Page, then
-///   *(p1.inner).inner and *(p1.inner).inner are non-overwrapping arrays.
+///   *(p1.inner).inner and *(p1.inner).inner are non-overwrapping arrays,
+///   unless they are shared by `Kmem::share`, in which case neither modifies the array.
 pub struct Page {
     inner: NonNull<RawPage>,
 }
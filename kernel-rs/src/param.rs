@@ -1,5 +1,15 @@
-/// Maximum number of processes.
-pub const NPROC: usize = 64;
+/// Maximum number of kernel stacks.
+pub const NKSTACK: usize = 4096;
+
+/// Maximum number of freed kernel stacks kept for reuse.
+pub const NKSTACKPOOL: usize = 16;
+
+/// Length of the windows over which the utilization of a CPU is measured, in cycles.
+pub const LOADWINDOW: usize = 1 << 30;
+
+/// Length of the windows over which the running time of a throttled process is limited,
+/// in cycles.
+pub const THROTTLEWINDOW: usize = 1 << 27;
 
 /// Maximum number of CPUs.
 pub const NCPU: usize = 8;
@@ -13,8 +23,63 @@ pub const NFILE: usize = 100;
 /// Maximum number of active i-nodes.
 pub const NINODE: usize = 50;
 
+/// Maximum number of watches.
+pub const NWATCH: usize = 8;
+
+/// Maximum number of epolls.
+pub const NEPOLL: usize = 8;
+
+/// Maximum number of epolls watching a single pipe, socket, or watch.
+pub const NEPOLLWAITER: usize = 4;
+
+/// Maximum number of eventfds and timerfds.
+pub const NEVENTFD: usize = 16;
+
+/// Maximum number of message queues.
+pub const NMSGQUEUE: usize = 8;
+
+/// Maximum number of messages of a message queue.
+pub const MSGQUEUE_MAXMSG: usize = 16;
+
+/// Maximum length of the name of a message queue or a semaphore, including the leading slash.
+pub const IPCNAMEMAX: usize = 32;
+
+/// Default limit on the bytes of messages of all message queues, `RLIMIT_MSGQUEUE`.
+pub const MSGQUEUE_LIMIT: usize = 16384;
+
+/// Maximum number of semaphores.
+pub const NSEM: usize = 16;
+
+/// Maximum number of sockets.
+pub const NSOCKET: usize = 16;
+
+/// Maximum number of connections waiting to be accepted by a listening socket.
+pub const NBACKLOG: usize = 4;
+
+/// Size of the receive buffer of a socket, in bytes.
+pub const SOCKBUF: usize = 2048;
+
+/// IRQs numbered below it are accounted for in the interrupt statistics.
+pub const NIRQ: usize = 64;
+
+/// Interval between timer interrupts at boot, in microseconds.
+/// `make TICKUS=<interval>` overrides it.
+pub const TICK_US: usize = match option_env!("TICK_US") {
+    Some(us) => parse_usize(us),
+    None => 100_000,
+};
+
+/// Minimum interval between timer interrupts, in microseconds.
+pub const MINTICK_US: usize = 1_000;
+
 /// Maximum major device number.
-pub const NDEV: usize = 10;
+pub const NDEV: usize = 14;
+
+/// Number of virtual consoles, including the console itself.
+pub const NVCONSOLE: usize = 4;
+
+/// Number of ports of the virtio console.
+pub const NVPORT: usize = 4;
 
 /// Device number of file system root disk.
 pub const ROOTDEV: u32 = 1;
@@ -22,8 +87,11 @@ pub const ROOTDEV: u32 = 1;
 /// Max exec arguments.
 pub const MAXARG: usize = 32;
 
+/// Max system calls in a batch.
+pub const MAXBATCH: usize = 64;
+
 /// Block Size.
-pub const BSIZE: usize = 1024;
+pub const BSIZE: usize = ufs_layout::BSIZE;
 
 /// Max # of blocks any FS op writes.
 /// Will be handled in #31.
@@ -32,11 +100,92 @@ pub const MAXOPBLOCKS: usize = 10;
 /// Max data blocks in on-disk log.
 pub const LOGSIZE: usize = MAXOPBLOCKS * 3;
 
-/// Size of disk block cache.
+/// Size of disk block cache, unless the boot configuration changes it.
 pub const NBUF: usize = MAXOPBLOCKS * 3;
 
+/// Maximum size of disk block cache.
+pub const MAXNBUF: usize = NBUF * 2;
+
+/// Maximum number of pages in the page cache.
+pub const NPAGECACHE: usize = 32;
+
+/// Maximum number of programs in the exec cache.
+pub const NEXECCACHE: usize = 4;
+
+/// Maximum number of pages of a program that the exec cache keeps.
+pub const NEXECPAGE: usize = 32;
+
 /// Maximum file path name.
 pub const MAXPATH: usize = 128;
 
 /// Maximum length of process name.
 pub const MAXPROCNAME: usize = 16;
+
+/// Maximum length of process title set by prctl().
+pub const MAXPROCTITLE: usize = 64;
+
+/// Bytes of the exec() arguments kept for inspection.
+pub const MAXPROCARGS: usize = 128;
+
+/// Maximum number of hardware breakpoints and watchpoints of a traced process.
+pub const NTRIGGER: usize = 4;
+
+/// Number of hardware performance counters that perf() programs.
+pub const NPERFCTR: usize = 4;
+
+/// Number of buckets of a system call latency histogram. Bucket i counts the calls that took
+/// less than 2^(i + 1) cycles, and the last one also the longer calls.
+pub const NLATBUCKET: usize = 32;
+
+/// Number of events the trace buffer of /dev/trace holds.
+pub const NTRACE: usize = 512;
+
+/// Maximum number of disk requests waiting to be sent to the disk.
+pub const NIOQUEUE: usize = 16;
+
+/// Maximum number of blocks merged into a disk request.
+pub const NIOMERGE: usize = 4;
+
+/// Number of random bytes of the entropy device that seed the entropy pool at boot.
+pub const NSEED: usize = 32;
+
+/// Maximum number of memory regions reported by a single pmap().
+pub const NMAPREGION: usize = 16;
+
+/// Maximum number of stable pages of same-page merging.
+pub const NKSM: usize = 64;
+
+/// Maximum number of pages that the compressed swap takes.
+pub const NZRAM: usize = 1024;
+
+/// Maximum number of gangs.
+pub const NGANG: usize = 8;
+
+/// Length of the time slot of a gang, in ticks.
+pub const GANGSLICE: u32 = 5;
+
+/// Maximum number of holders of sleep locks, each waiting for the next, that inherit the
+/// priority of a process waiting for the first.
+pub const NINHERIT: usize = 8;
+
+/// Maximum number of rounds that a process waiting for a mutex spins while the holder runs,
+/// before it sleeps.
+pub const MUTEXSPIN: usize = 1000;
+
+/// Size of the system call tables.
+pub const NSYSCALL: usize = 256;
+
+/// File mode creation mask of the initial process.
+pub const UMASK: u32 = 0o022;
+
+const fn parse_usize(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut n = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(bytes[i].is_ascii_digit(), "not a number");
+        n = n * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+    n
+}
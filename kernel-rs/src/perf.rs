@@ -0,0 +1,205 @@
+//! Hardware performance counters.
+//!
+//! `perf()` chooses the events that the hardware performance counters of every CPU count, such as
+//! cache misses or branch mispredictions, and reads the counts of a process. Events are numbered
+//! by the CPU; on RISC-V they are the values of mhpmevent3..6, and qemu counts TLB misses with
+//! its sscofpmf extension. A counter of an event the CPU does not support stays at 0.
+//!
+//! Like `cycles()` with `CYCLES_VIRTUAL`, the counts of a process only advance while it runs.
+//! The scheduler programs new events into a CPU between two runs of processes, but the counts of
+//! a process are not cleared, so they mix the old and new events after the events change.
+//!
+//! `perf()` also collects a latency histogram of each system call while it is asked to, so that
+//! benchmarks can find the slow tail of the calls they make. The latency of a call is the cycles
+//! from its dispatch to its return, including the time it sleeps, and the buckets of its
+//! histogram double in width. Only the system calls of the rv6 ABI are measured.
+
+use core::{
+    cmp,
+    ops::{Add, Sub},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    arch::interface::TimeManager,
+    arch::TargetArch,
+    cpu::cpuid,
+    param::{NCPU, NLATBUCKET, NPERFCTR, NSYSCALL},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// Operations of `perf()`.
+pub const PERF_SETEVENTS: i32 = 1;
+pub const PERF_GETEVENTS: i32 = 2;
+pub const PERF_READ: i32 = 3;
+pub const PERF_SETLATENCY: i32 = 4;
+pub const PERF_GETLATENCY: i32 = 5;
+
+/// Events of the counters, the same on every CPU.
+static EVENTS: [AtomicUsize; NPERFCTR] = array![_ => AtomicUsize::new(0); NPERFCTR];
+
+/// Incremented whenever `EVENTS` change.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// The generation of the events each CPU counts.
+static CPU_GENERATIONS: [AtomicUsize; NCPU] = array![_ => AtomicUsize::new(0); NCPU];
+
+/// Are the latencies of system calls collected?
+static LATENCY_ON: AtomicBool = AtomicBool::new(false);
+
+/// Latency histogram of each system call.
+static LATENCIES: [[AtomicUsize; NLATBUCKET]; NSYSCALL] =
+    array![_ => array![_ => AtomicUsize::new(0); NLATBUCKET]; NSYSCALL];
+
+/// Counts of the hardware performance counters.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct PerfCounts(pub [usize; NPERFCTR]);
+
+impl PerfCounts {
+    pub const fn new() -> Self {
+        Self([0; NPERFCTR])
+    }
+
+    /// Reads the counters of the current CPU.
+    pub fn read() -> Self {
+        Self(array![i => TargetArch::r_perf_counter(i); NPERFCTR])
+    }
+}
+
+impl Add for PerfCounts {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(array![i => self.0[i].wrapping_add(other.0[i]); NPERFCTR])
+    }
+}
+
+impl Sub for PerfCounts {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self(array![i => self.0[i].wrapping_sub(other.0[i]); NPERFCTR])
+    }
+}
+
+/// Programs the events into the counters of the current CPU if they have changed since it last
+/// did. The scheduler calls this while no process runs on the CPU.
+pub fn sync_events() {
+    let generation = GENERATION.load(Ordering::Acquire);
+    let cpu = &CPU_GENERATIONS[cpuid()];
+    if cpu.load(Ordering::Relaxed) == generation {
+        return;
+    }
+    for (i, event) in EVENTS.iter().enumerate() {
+        // SAFETY: i < NPERFCTR.
+        unsafe { TargetArch::set_perf_event(i, event.load(Ordering::Relaxed)) };
+    }
+    cpu.store(generation, Ordering::Relaxed);
+}
+
+/// Returns the cycle counter if the latencies of system calls are collected. The system call
+/// dispatcher calls this before a system call, and passes the result to `latency_end` after it.
+pub fn latency_begin() -> Option<usize> {
+    if LATENCY_ON.load(Ordering::Relaxed) {
+        Some(TargetArch::r_cycle())
+    } else {
+        None
+    }
+}
+
+/// Adds the latency of the system call num that began at `begin` to its histogram.
+pub fn latency_end(num: i32, begin: Option<usize>) {
+    if let (Some(begin), Some(histogram)) = (begin, LATENCIES.get(num as usize)) {
+        let cycles = TargetArch::r_cycle().wrapping_sub(begin);
+        let bucket = cmp::min(
+            (usize::BITS - (cycles | 1).leading_zeros() - 1) as usize,
+            NLATBUCKET - 1,
+        );
+        let _ = histogram[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Returns the counts of the process pid.
+    fn perf_counts(&self, pid: i32) -> Result<PerfCounts, ()> {
+        if pid == self.proc().pid() {
+            let data = self.proc().deref_data();
+            return Ok(data.perf_counts + (PerfCounts::read() - data.perf_start));
+        }
+        self.kernel()
+            .procs()
+            .inspect(pid, self, |data| data.perf_counts)
+    }
+
+    /// Controls the hardware performance counters by op, with the uint64 array of NPERFCTR at
+    /// addr:
+    /// * `PERF_SETEVENTS` makes the counters of every CPU count the events in the array.
+    /// * `PERF_GETEVENTS` copies the events into the array.
+    /// * `PERF_READ` copies the counts of the process arg, or of the current process if arg is 0,
+    ///   into the array.
+    /// Or controls the latency histograms of system calls:
+    /// * `PERF_SETLATENCY` starts collecting them from zero if arg is nonzero, or stops if it is 0.
+    /// * `PERF_GETLATENCY` copies the histogram of the system call arg into the uint64 array of
+    ///   NLATBUCKET at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_perf(&mut self) -> Result<usize, ()> {
+        let op = self.proc().argint(0)?;
+        let arg = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?;
+        match op {
+            PERF_SETEVENTS => {
+                let mut events = [0usize; NPERFCTR];
+                // SAFETY: [usize; NPERFCTR] does not have any internal structure.
+                unsafe {
+                    self.proc_mut()
+                        .memory_mut()
+                        .copy_in(&mut events, addr.into())
+                }?;
+                for (event, new) in EVENTS.iter().zip(events.iter()) {
+                    event.store(*new, Ordering::Relaxed);
+                }
+                let _ = GENERATION.fetch_add(1, Ordering::Release);
+            }
+            PERF_GETEVENTS => {
+                let events = array![i => EVENTS[i].load(Ordering::Relaxed); NPERFCTR];
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_out(addr.into(), &events)?;
+            }
+            PERF_READ => {
+                let pid = if arg == 0 { self.proc().pid() } else { arg };
+                let counts = self.perf_counts(pid)?;
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_out(addr.into(), &counts)?;
+            }
+            PERF_SETLATENCY => {
+                if arg != 0 {
+                    for count in LATENCIES.iter().flatten() {
+                        count.store(0, Ordering::Relaxed);
+                    }
+                }
+                LATENCY_ON.store(arg != 0, Ordering::Relaxed);
+            }
+            PERF_GETLATENCY => {
+                let histogram = LATENCIES.get(arg as usize).ok_or(())?;
+                let counts = array![i => histogram[i].load(Ordering::Relaxed); NLATBUCKET];
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_out(addr.into(), &counts)?;
+            }
+            _ => return Err(()),
+        }
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of the hardware performance counters.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(81, |ctx| ctx.sys_perf());
+}
@@ -1,15 +1,20 @@
 use core::{mem, ops::Deref, ptr::NonNull};
 
 use crate::{
-    addr::UVAddr,
+    addr::{UVAddr, PGSIZE},
+    epoll::{Waiter, Waiters},
     file::{FileType, RcFile, SelectEvent},
     hal::hal,
+    kernel::KernelRef,
     lock::SpinLock,
     page::Page,
     proc::{KernelCtx, WaitChannel},
 };
 
-const PIPESIZE: usize = 512;
+pub const PIPESIZE: usize = 512;
+
+/// A write to a pipe of at most this many bytes is not interleaved with other writes.
+const PIPE_BUF: usize = PIPESIZE;
 
 struct PipeInner {
     data: [u8; PIPESIZE],
@@ -25,6 +30,9 @@ struct PipeInner {
 
     /// Write fd is still open.
     writeopen: bool,
+
+    /// Epolls watching either end.
+    waiters: Waiters,
 }
 
 pub struct Pipe {
@@ -38,55 +46,105 @@ pub struct Pipe {
 }
 
 impl Pipe {
-    /// Tries to read up to `n` bytes using `Pipe::try_read()`.
-    /// If successfully read i > 0 bytes, wakeups the `write_waitchannel` and returns `Ok(i: usize)`.
-    /// If the pipe was empty, sleeps at `read_waitchannel` and tries again after wakeup.
-    /// If an error happened, returns `Err(())`.
+    /// Reads up to `n` bytes using `PipeInner::read()`.
+    /// If the pipe is empty, sleeps at `read_waitchannel` until some bytes are written or the write fd is closed.
+    /// After reading i >= 0 bytes, wakeups the `write_waitchannel` and returns `Ok(i: usize)`.
+    /// If the process was killed while waiting, returns `Err(())`.
     pub fn read(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
         let mut inner = self.inner.lock();
-        loop {
-            match inner.try_read(addr, n, ctx) {
-                Ok(r) => {
-                    //DOC: piperead-wakeup
-                    self.write_waitchannel.wakeup(ctx.kernel());
-                    return Ok(r);
-                }
-                Err(PipeError::WaitForIO) => {
-                    //DOC: piperead-sleep
-                    self.read_waitchannel.sleep(&mut inner, ctx);
-                }
-                _ => return Err(()),
-            }
-        }
+        //DOC: piperead-sleep
+        self.read_waitchannel.wait_while_killable(
+            &mut inner,
+            |pipe| pipe.nread == pipe.nwrite && pipe.writeopen,
+            ctx,
+        )?;
+        let r = inner.read(addr, n, ctx);
+        //DOC: piperead-wakeup
+        self.write_waitchannel.wakeup(ctx.kernel());
+        inner.waiters.post(ctx.kernel());
+        Ok(r)
     }
 
     /// Tries to write up to `n` bytes by repeatedly calling `Pipe::try_write()`.
     /// Wakeups `read_waitchannel` for every successful `Pipe::try_write()`.
     /// After successfully writing i >= 0 bytes, returns `Ok(i)`.
-    /// Note that we may have i < `n` if an copy-in error happened.
+    /// Note that we may have i < `n` if an copy-in error happened, or if the process was killed
+    /// or the read fd was closed after writing i > 0 bytes.
     /// If the pipe was full, sleeps at `write_waitchannel` and tries again after wakeup.
+    /// If `n` <= `PIPE_BUF`, first sleeps until all `n` bytes fit, so that they are written at once.
     /// If an error happened, returns `Err(())`.
     pub fn write(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
         let mut written = 0;
         let mut inner = self.inner.lock();
+        if n <= PIPE_BUF {
+            self.write_waitchannel.wait_while_killable(
+                &mut inner,
+                |pipe| pipe.space() < n && pipe.readopen,
+                ctx,
+            )?;
+        }
         loop {
             match inner.try_write(addr + written, n - written, ctx) {
                 Ok(r) => {
                     written += r;
                     self.read_waitchannel.wakeup(ctx.kernel());
-                    if written < n {
-                        self.write_waitchannel.sleep(&mut inner, ctx);
-                    } else {
+                    inner.waiters.post(ctx.kernel());
+                    if written == n {
                         return Ok(written);
                     }
+                    //DOC: pipewrite-full
+                    if self
+                        .write_waitchannel
+                        .wait_while_killable(
+                            &mut inner,
+                            |pipe| pipe.is_full() && pipe.readopen,
+                            ctx,
+                        )
+                        .is_err()
+                    {
+                        break;
+                    }
                 }
                 Err(PipeError::InvalidCopyin(i)) => {
                     self.read_waitchannel.wakeup(ctx.kernel());
+                    inner.waiters.post(ctx.kernel());
                     return Ok(written + i);
                 }
-                _ => return Err(()),
+                _ => break,
             }
         }
+        // Killed or the read fd was closed. Report what has been written, if any.
+        if written > 0 {
+            Ok(written)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Writes all of `src` in the kernel memory, like `Pipe::write()`.
+    /// Wakeups `read_waitchannel` whenever some bytes are written.
+    /// If the pipe was full, sleeps at `write_waitchannel` and tries again after wakeup.
+    /// Returns `Ok(src.len())` on success, or `Err(())` if the read fd was closed or the
+    /// process was killed.
+    pub fn write_kernel(&self, src: &[u8], ctx: &KernelCtx<'_, '_>) -> Result<usize, ()> {
+        let mut written = 0;
+        let mut inner = self.inner.lock();
+        loop {
+            if !inner.readopen || ctx.proc().killed() {
+                return Err(());
+            }
+            written += inner.write_bytes(&src[written..]);
+            self.read_waitchannel.wakeup(ctx.kernel());
+            inner.waiters.post(ctx.kernel());
+            if written == src.len() {
+                return Ok(written);
+            }
+            self.write_waitchannel.wait_while_killable(
+                &mut inner,
+                |pipe| pipe.is_full() && pipe.readopen,
+                ctx,
+            )?;
+        }
     }
 
     fn close(&self, writable: bool, ctx: &KernelCtx<'_, '_>) -> bool {
@@ -99,6 +157,7 @@ impl Pipe {
             inner.readopen = false;
             self.write_waitchannel.wakeup(ctx.kernel());
         }
+        inner.waiters.post(ctx.kernel());
 
         // Return whether pipe should be freed or not.
         !inner.readopen && !inner.writeopen
@@ -120,6 +179,14 @@ pub struct AllocatedPipe {
 // and because `AllocatedPipe` does not point to thread-local data.
 unsafe impl Send for AllocatedPipe {}
 
+impl AllocatedPipe {
+    /// Returns a number that identifies the pipe. Both ends of a pipe have the same number.
+    pub fn id(&self) -> u32 {
+        // A pipe takes a whole page.
+        (self.ptr.as_ptr() as usize / PGSIZE) as u32
+    }
+}
+
 impl Deref for AllocatedPipe {
     type Target = Pipe;
 
@@ -147,6 +214,7 @@ impl KernelCtx<'_, '_> {
                     nread: 0,
                     readopen: true,
                     writeopen: true,
+                    waiters: Waiters::new(),
                 },
             ),
             read_waitchannel: WaitChannel::new(),
@@ -158,6 +226,7 @@ impl KernelCtx<'_, '_> {
             },
             true,
             false,
+            self,
         )?;
         let f0 = scopeguard::guard(f0, |f0| f0.free(self));
         let f1 = self.kernel().ftable().alloc_file(
@@ -166,6 +235,7 @@ impl KernelCtx<'_, '_> {
             },
             false,
             true,
+            self,
         )?;
 
         // Since files have been created successfully, prevent the page from being deallocated.
@@ -191,15 +261,28 @@ impl AllocatedPipe {
         let inner = self.inner.lock();
         inner.is_ready(event)
     }
+
+    /// Registers `waiter` to be posted whenever the pipe is read, written, or closed.
+    pub fn add_waiter(&self, waiter: Waiter, kernel: KernelRef<'_, '_>) -> Result<(), ()> {
+        self.inner.lock().waiters.add(waiter, kernel)
+    }
 }
 
 pub enum PipeError {
-    WaitForIO,
     InvalidStatus,
     InvalidCopyin(usize),
 }
 
 impl PipeInner {
+    fn is_full(&self) -> bool {
+        self.space() == 0
+    }
+
+    /// Returns the number of bytes that can be written without waiting.
+    fn space(&self) -> usize {
+        PIPESIZE - self.nwrite.wrapping_sub(self.nread) as usize
+    }
+
     /// Tries to write up to `n` bytes.
     /// If the process was killed, returns `Err(InvalidStatus)`.
     /// If an copy-in error happened after successfully writing i >= 0 bytes, returns `Err(InvalidCopyIn(i))`.
@@ -215,8 +298,7 @@ impl PipeInner {
             return Err(PipeError::InvalidStatus);
         }
         for i in 0..n {
-            if self.nwrite == self.nread.wrapping_add(PIPESIZE as u32) {
-                //DOC: pipewrite-full
+            if self.is_full() {
                 return Ok(i);
             }
             if ctx
@@ -233,28 +315,26 @@ impl PipeInner {
         Ok(n)
     }
 
-    /// Tries to read up to `n` bytes.
-    /// If successful read i > 0 bytes, returns `Ok(i: usize)`.
-    /// If the pipe was empty, returns `Err(WaitForIO)`.
-    /// If the process was killed, returns `Err(InvalidStatus)`.
-    fn try_read(
-        &mut self,
-        addr: UVAddr,
-        n: usize,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, PipeError> {
-        //DOC: pipe-empty
-        if self.nread == self.nwrite && self.writeopen {
-            if ctx.proc().killed() {
-                return Err(PipeError::InvalidStatus);
+    /// Writes as many bytes of `src` as fit in the pipe.
+    /// Returns the number of written bytes.
+    fn write_bytes(&mut self, src: &[u8]) -> usize {
+        for (i, ch) in src.iter().enumerate() {
+            if self.is_full() {
+                return i;
             }
-            return Err(PipeError::WaitForIO);
+            self.data[self.nwrite as usize % PIPESIZE] = *ch;
+            self.nwrite = self.nwrite.wrapping_add(1);
         }
+        src.len()
+    }
 
+    /// Reads up to `n` bytes without waiting.
+    /// Returns the number of bytes read, which is 0 if the pipe is empty.
+    fn read(&mut self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> usize {
         //DOC: piperead-copy
         for i in 0..n {
             if self.nread == self.nwrite {
-                return Ok(i);
+                return i;
             }
             let ch = [self.data[self.nread as usize % PIPESIZE]];
             self.nread = self.nread.wrapping_add(1);
@@ -264,15 +344,17 @@ impl PipeInner {
                 .copy_out_bytes(addr + i, &ch)
                 .is_err()
             {
-                return Ok(i);
+                return i;
             }
         }
-        Ok(n)
+        n
     }
 
     fn is_ready(&self, event: SelectEvent) -> bool {
         match event {
-            SelectEvent::Read => self.nread != self.nwrite,
+            // A read does not wait at the end of file either.
+            SelectEvent::Read => self.nread != self.nwrite || !self.writeopen,
+            SelectEvent::Write => self.space() > 0 || !self.readopen,
             _ => unimplemented!(),
         }
     }
@@ -0,0 +1,137 @@
+//! Power management.
+//!
+//! An idle CPU either polls for a process to run, or suspends itself until its next interrupt,
+//! which saves energy at the cost of a longer wakeup latency. A suspended CPU is woken up by its
+//! ticks, so an idle CPU keeps ticking while idle CPUs are suspended.
+//!
+//! The performance state of the CPUs can be set where the machine supports it.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::interface::{PowerManager, TimeManager},
+    arch::TargetArch,
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// What an idle CPU does.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum IdlePolicy {
+    /// Keep looking for a process to run.
+    Poll = 0,
+
+    /// Suspend until the next interrupt.
+    Suspend = 1,
+}
+
+pub struct Power {
+    /// Do idle CPUs suspend themselves?
+    suspend: AtomicBool,
+
+    /// Number of times CPUs have suspended themselves
+    nsuspend: AtomicUsize,
+
+    /// Cycles spent while suspended
+    suspend_cycles: AtomicUsize,
+}
+
+/// Power statistics, read by `powerctl(POWER_STAT)`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct PowerStat {
+    pub policy: usize,
+    pub nsuspend: usize,
+    pub suspend_cycles: usize,
+}
+
+impl IdlePolicy {
+    pub fn from_usize(n: usize) -> Option<Self> {
+        match n {
+            0 => Some(Self::Poll),
+            1 => Some(Self::Suspend),
+            _ => None,
+        }
+    }
+}
+
+impl Power {
+    pub const fn new() -> Self {
+        Self {
+            suspend: AtomicBool::new(false),
+            nsuspend: AtomicUsize::new(0),
+            suspend_cycles: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn idle_policy(&self) -> IdlePolicy {
+        if self.suspend.load(Ordering::Relaxed) {
+            IdlePolicy::Suspend
+        } else {
+            IdlePolicy::Poll
+        }
+    }
+
+    pub fn set_idle_policy(&self, policy: IdlePolicy) {
+        self.suspend
+            .store(policy == IdlePolicy::Suspend, Ordering::Relaxed);
+    }
+
+    /// Suspends the current CPU until its next interrupt.
+    pub fn suspend(&self) {
+        let start = TargetArch::r_cycle();
+        TargetArch::cpu_suspend();
+        let _ = self.nsuspend.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .suspend_cycles
+            .fetch_add(TargetArch::r_cycle().wrapping_sub(start), Ordering::Relaxed);
+    }
+
+    /// Sets the performance state of every CPU, where 0 is the fastest.
+    /// Returns Ok(()) on success, Err(()) if the state is not supported.
+    pub fn set_perf_level(&self, level: usize) -> Result<(), ()> {
+        TargetArch::set_perf_level(level)
+    }
+
+    pub fn stat(&self) -> PowerStat {
+        PowerStat {
+            policy: self.idle_policy() as usize,
+            nsuspend: self.nsuspend.load(Ordering::Relaxed),
+            suspend_cycles: self.suspend_cycles.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Control the power management according to op, with arg as a value or an address.
+    /// Returns Ok(0 or the idle policy) on success, Err(()) on error.
+    pub fn sys_powerctl(&mut self) -> Result<usize, ()> {
+        let op = self.proc().argint(0)?;
+        let arg = self.proc().argaddr(1)?;
+        match op {
+            // POWER_GETIDLE
+            0 => return Ok(self.kernel().power().idle_policy() as usize),
+            // POWER_SETIDLE
+            1 => {
+                let policy = IdlePolicy::from_usize(arg).ok_or(())?;
+                self.kernel().power().set_idle_policy(policy);
+            }
+            // POWER_SETPERF
+            2 => self.kernel().power().set_perf_level(arg)?,
+            // POWER_STAT
+            3 => {
+                let stat = self.kernel().power().stat();
+                self.proc_mut().memory_mut().copy_out(arg.into(), &stat)?;
+            }
+            _ => return Err(()),
+        }
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of power management.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(56, |ctx| ctx.sys_powerctl());
+}
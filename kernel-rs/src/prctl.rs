@@ -0,0 +1,99 @@
+//! Process titles and arguments.
+//!
+//! `prctl()` lets a process set a title for itself, longer than the name that exec() gives it,
+//! and reads the title and the exec() arguments of any process. Programs that fork workers for
+//! different roles set their titles, so that the workers can be told apart in the process list.
+
+use core::cmp;
+
+use crate::{
+    param::{MAXPROCARGS, MAXPROCNAME, MAXPROCTITLE},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// Operations of `prctl()`.
+pub const PR_SET_NAME: i32 = 15;
+pub const PR_GET_NAME: i32 = 16;
+pub const PR_GET_ARGS: i32 = 1000;
+
+impl KernelCtx<'_, '_> {
+    /// Sets the title of the current process to the string at the nth argument. The name of the
+    /// process becomes the beginning of the title, and an empty title gives the name back.
+    fn set_title(&mut self, n: usize) -> Result<usize, ()> {
+        let mut buf = [0u8; MAXPROCTITLE];
+        let title = self.proc_mut().argstr(n, &mut buf)?.to_bytes();
+        let data = self.proc_mut().deref_mut_data();
+        data.title = [0; MAXPROCTITLE];
+        data.title[..title.len()].copy_from_slice(title);
+        if !title.is_empty() {
+            data.name = [0; MAXPROCNAME];
+            let len = cmp::min(title.len(), MAXPROCNAME - 1);
+            data.name[..len].copy_from_slice(&title[..len]);
+        }
+        Ok(0)
+    }
+
+    /// Copies the title of the process pid, terminated by NUL, into addr of n bytes.
+    fn get_title(&mut self, pid: i32, addr: usize, n: usize) -> Result<usize, ()> {
+        let mut buf = [0u8; MAXPROCTITLE + 1];
+        let len = self.kernel().procs().inspect(pid, self, |data| {
+            let title = data.title();
+            buf[..title.len()].copy_from_slice(title);
+            title.len()
+        })?;
+        if len + 1 > n {
+            return Err(());
+        }
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr.into(), &buf[..len + 1])?;
+        Ok(len)
+    }
+
+    /// Copies the exec() arguments of the process pid, each terminated by NUL, into addr of n
+    /// bytes.
+    fn get_args(&mut self, pid: i32, addr: usize, n: usize) -> Result<usize, ()> {
+        let mut buf = [0u8; MAXPROCARGS];
+        let len = self.kernel().procs().inspect(pid, self, |data| {
+            let args = data.args();
+            buf[..args.len()].copy_from_slice(args);
+            args.len()
+        })?;
+        if len > n {
+            return Err(());
+        }
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr.into(), &buf[..len])?;
+        Ok(len)
+    }
+
+    /// Controls the process pid, or the current process if pid is 0, by option:
+    /// * `PR_SET_NAME` sets the title of the current process to the string at addr.
+    /// * `PR_GET_NAME` copies the title of the process into addr of n bytes.
+    /// * `PR_GET_ARGS` copies the exec() arguments of the process into addr of n bytes.
+    /// Returns Ok(number of bytes copied, without the NUL of a title) on success, Err(()) on
+    /// error.
+    pub fn sys_prctl(&mut self) -> Result<usize, ()> {
+        let option = self.proc().argint(0)?;
+        let pid = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?;
+        let n = self.proc().argint(3)?;
+        let pid = if pid == 0 { self.proc().pid() } else { pid };
+        if n < 0 {
+            return Err(());
+        }
+        match option {
+            PR_SET_NAME if pid == self.proc().pid() => self.set_title(2),
+            PR_GET_NAME => self.get_title(pid, addr, n as usize),
+            PR_GET_ARGS => self.get_args(pid, addr, n as usize),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Registers the system calls of process titles and arguments.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(79, |ctx| ctx.sys_prctl());
+}
@@ -96,6 +96,19 @@ impl<'id, 'p> CurrentProc<'id, 'p> {
         unsafe { (*self.info.get_mut_raw()).pid }
     }
 
+    /// Returns the counters of the current process, given `now`, the counters of its CPU.
+    pub fn run_cycles(&self, now: Cycles) -> Cycles {
+        // SAFETY: run_cycles and run_start are not modified while CurrentProc exists.
+        let info = unsafe { &*self.info.get_mut_raw() };
+        info.run_cycles + (now - info.run_start)
+    }
+
+    /// Updates the disk I/O accounting of the current process by f.
+    pub fn account_io<R, F: FnOnce(&mut IoAccount) -> R>(&self, f: F) -> R {
+        let mut guard = self.lock();
+        f(&mut guard.deref_mut_info().io)
+    }
+
     pub fn trap_frame(&self) -> &<TargetArch as ProcManager>::TrapFrame {
         // SAFETY: trap_frame is a valid pointer according to the invariants
         // of Proc and CurrentProc.
@@ -152,6 +165,17 @@ impl<'id, 's> KernelRef<'id, 's> {
         proc
     }
 
+    /// Returns the pid of the current proc, or 0 if there is none.
+    pub fn current_pid(&self) -> Pid {
+        let proc = self.current_proc();
+        if proc.is_null() {
+            0
+        } else {
+            // SAFETY: pid is not modified while the process runs.
+            unsafe { (*(*proc).info.get_mut_raw()).pid }
+        }
+    }
+
     /// Returns `Some<KernelCtx<'id, '_>>` if current proc exists (i.e., when (*cpu).proc is non-null).
     /// Note that `'id` is same with the given `KernelRef`'s `'id`.
     /// Otherwise, returns `None` (when current proc is null).
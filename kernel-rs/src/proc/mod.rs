@@ -1,5 +1,6 @@
 use core::{
     cell::UnsafeCell,
+    cmp,
     mem::{self, MaybeUninit},
     ops::Deref,
     ptr, str,
@@ -7,30 +8,52 @@ use core::{
 };
 
 use array_macro::array;
+use bitflags::bitflags;
+use zerocopy::AsBytes;
 
 use crate::{
     arch::interface::{ContextManager, ProcManager, TrapManager},
     arch::TargetArch,
-    file::RcFile,
+    cycles::Cycles,
+    fdinfo::FdInfo,
+    file::FdEntry,
     fs::{DefaultFs, RcInode},
     hal::hal,
+    iosched::{IoAccount, IoStats},
+    ktrace::Task,
     lock::SpinLock,
     page::Page,
-    param::{MAXPROCNAME, NOFILE},
+    param::{
+        MAXPATH, MAXPROCARGS, MAXPROCNAME, MAXPROCTITLE, NCPU, NOFILE, NTRIGGER, THROTTLEWINDOW,
+        UMASK,
+    },
+    perf::PerfCounts,
+    rlimit::RLimits,
     util::branded::Branded,
-    vm::UserMemory,
+    vm::{KernelMemory, UserMemory},
+    wss::WorkingSet,
 };
 
 mod kernel_ctx;
 mod procs;
+mod ptrace;
 mod wait_channel;
 
 pub use kernel_ctx::*;
 pub use procs::*;
+pub use ptrace::*;
 pub use wait_channel::*;
 
 type Context = <TargetArch as ProcManager>::Context;
 
+bitflags! {
+    /// Options of `waitpid()`.
+    pub struct WaitOptions: i32 {
+        /// Also report a child that has stopped.
+        const WUNTRACED = 0x2;
+    }
+}
+
 extern "C" {
     // swtch.S
     fn swtch(_: *mut Context, _: *mut Context);
@@ -42,12 +65,67 @@ pub enum Procstate {
     RUNNING,
     RUNNABLE,
     SLEEPING,
+    STOPPED,
     UNUSED,
     USED,
 }
 
+/// Scheduling class of a process.
+/// A process runs only when no process of a higher class is runnable.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SchedClass {
+    /// The default class.
+    Interactive = 0,
+
+    /// Work that does not need to respond quickly.
+    Batch = 1,
+
+    /// Work that runs only when the CPU would be idle otherwise.
+    Idle = 2,
+
+    /// Periodic work with a reservation of `setdeadline()`. The process of the earliest deadline
+    /// runs first, for at most its budget in each period.
+    Deadline = 3,
+}
+
+/// Reservation of a process of the deadline class, read by `dlstat()`.
+#[derive(Copy, Clone, AsBytes)]
+#[repr(C)]
+pub struct DeadlineInfo {
+    /// Length of each period, in microseconds
+    pub period: usize,
+
+    /// Time the process may run in each period, in microseconds
+    pub budget: usize,
+
+    /// Time after the beginning of each period by which the process must have run for its
+    /// budget, in microseconds
+    pub deadline: usize,
+
+    /// Time the process has run in the current period, in microseconds
+    pub used: usize,
+
+    /// Number of periods at whose deadline the process was still runnable without having run
+    /// for its budget
+    pub misses: usize,
+}
+
+/// System call ABI of a process.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Abi {
+    /// The rv6 system calls.
+    Rv6 = 0,
+
+    /// The RISC-V Linux system calls that `linux` supports.
+    Linux = 1,
+}
+
 type Pid = i32;
 
+/// Priority of a process, where a smaller one is higher: the position of its class in
+/// `SchedClass::ALL`, and its deadline if it is of the deadline class.
+type Priority = (usize, usize);
+
 /// Proc::info's spinlock must be held when using these.
 pub struct ProcInfo {
     /// Process state.
@@ -61,6 +139,59 @@ pub struct ProcInfo {
 
     /// Process ID.
     pid: Pid,
+
+    /// Tracing state, shared with the tracing parent.
+    trace: Trace,
+
+    /// Has the parent been told by waitpid() that the process is stopped?
+    stop_reported: bool,
+
+    /// Scheduling class, inherited by the children.
+    class: SchedClass,
+
+    /// CPU the process last ran on, or `NCPU` if it has not run yet. The scheduler prefers to
+    /// run it there again, where the caches may still hold its data.
+    cpu: usize,
+
+    /// CPU that migrate() has bound the process to, on which alone it runs.
+    bound: Option<usize>,
+
+    /// Gang that gang() has put the process in, or 0 if it is in no gang. Not inherited by the
+    /// children.
+    gang: i32,
+
+    /// Reservation of the deadline class. Not inherited by the children, which begin in the
+    /// interactive class instead.
+    reservation: DeadlineInfo,
+
+    /// Uptime when the current period of the reservation began, in microseconds.
+    release: usize,
+
+    /// Pid of the process holding the sleep lock that the process waits for, or 0.
+    waits_for: Pid,
+
+    /// Priority inherited from the processes waiting for the sleep locks that the process holds,
+    /// if it is higher than its own.
+    boost: Option<Priority>,
+
+    /// Percentage of the time the process may run, or 100 if it is not throttled. Inherited by
+    /// the children.
+    throttle: u32,
+
+    /// Cycle counter when the current throttling window began.
+    throttle_start: usize,
+
+    /// Cycles the process has run in the current throttling window.
+    throttle_used: usize,
+
+    /// Counters of the CPUs while the process ran, not counting its current run.
+    run_cycles: Cycles,
+
+    /// Counters of the CPU when the current run of the process began.
+    run_start: Cycles,
+
+    /// Disk I/O of the process.
+    io: IoAccount,
 }
 
 /// Proc::data are private to the process, so lock need not be held.
@@ -77,14 +208,57 @@ pub struct ProcData {
     /// swtch() here to run process.
     context: Context,
 
-    /// Open files.
-    pub open_files: [Option<RcFile>; NOFILE],
+    /// File descriptor table.
+    pub open_files: [Option<FdEntry>; NOFILE],
 
     /// Current directory.
     cwd: MaybeUninit<RcInode<DefaultFs>>,
 
+    /// Absolute path of the current directory, in its first `cwd_path_len` bytes.
+    cwd_path: [u8; MAXPATH],
+
+    /// Length of `cwd_path`, or 0 if the path is not known because it is too long.
+    cwd_path_len: usize,
+
     /// Process name (debugging).
     pub name: [u8; MAXPROCNAME],
+
+    /// Process title set by prctl(), terminated by NUL. Empty if none has been set since
+    /// exec().
+    pub title: [u8; MAXPROCTITLE],
+
+    /// Arguments of exec(), each terminated by NUL, in the first `args_len` bytes.
+    args: [u8; MAXPROCARGS],
+
+    args_len: usize,
+
+    /// System call ABI of the running program.
+    pub abi: Abi,
+
+    /// System call ABI that exec() gives the next program.
+    pub exec_abi: Abi,
+
+    /// Permission bits cleared from the mode of the files the process creates.
+    pub umask: u32,
+
+    /// Major device number of the controlling terminal, which /dev/tty refers to.
+    pub ctty: Option<u16>,
+
+    /// Limits on resources, set by setrlimit().
+    pub rlimits: RLimits,
+
+    /// Hardware breakpoints and watchpoints set by the tracing parent.
+    pub triggers: [Trigger; NTRIGGER],
+
+    /// Counts of the hardware performance counters while the process ran, not counting its
+    /// current run.
+    pub perf_counts: PerfCounts,
+
+    /// Hardware performance counters of the CPU when the current run of the process began.
+    pub perf_start: PerfCounts,
+
+    /// Working-set scans selected by wss().
+    pub wss: WorkingSet,
 }
 
 /// Per-process state.
@@ -111,6 +285,16 @@ pub struct Proc {
 
     /// If true, the process have been killed.
     killed: AtomicBool,
+
+    /// Waitchannel saying traced proc is resumed.
+    trace_waitchannel: WaitChannel,
+
+    /// If true, the traced process stops at system call entry and exit.
+    trace_syscall: AtomicBool,
+
+    /// If true, the process stops before returning to user space, until it is thawed.
+    /// Written only while `info` is locked.
+    frozen: AtomicBool,
 }
 
 /// A branded reference to a `Proc`.
@@ -126,17 +310,72 @@ pub struct ProcGuard<'id, 's> {
     proc: ProcRef<'id, 's>,
 }
 
+impl SchedClass {
+    /// All classes, from the highest to the lowest.
+    const ALL: [Self; 4] = [Self::Deadline, Self::Interactive, Self::Batch, Self::Idle];
+
+    /// Returns the class numbered `n`, unless it is the deadline class, which needs a
+    /// reservation.
+    pub fn from_usize(n: usize) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|class| *class as usize == n && *class != Self::Deadline)
+    }
+
+    /// Returns the position of the class in `ALL`.
+    fn rank(self) -> usize {
+        Self::ALL
+            .iter()
+            .position(|class| *class == self)
+            .expect("SchedClass::rank")
+    }
+}
+
+impl DeadlineInfo {
+    pub const fn new() -> Self {
+        Self {
+            period: 0,
+            budget: 0,
+            deadline: 0,
+            used: 0,
+            misses: 0,
+        }
+    }
+}
+
+impl Abi {
+    pub fn from_usize(n: usize) -> Option<Self> {
+        match n {
+            0 => Some(Self::Rv6),
+            1 => Some(Self::Linux),
+            _ => None,
+        }
+    }
+}
+
 impl Procstate {
     fn as_str(&self) -> &'static str {
         match self {
             Procstate::USED => "used",
             Procstate::UNUSED => "unused",
             Procstate::SLEEPING => "sleep ",
+            Procstate::STOPPED => "stop  ",
             Procstate::RUNNABLE => "runble",
             Procstate::RUNNING => "run   ",
             Procstate::ZOMBIE => "zombie",
         }
     }
+
+    /// Returns the state as a letter of the `prev_state` of ftrace.
+    fn trace_state(&self) -> u8 {
+        match self {
+            Procstate::SLEEPING => b'S',
+            Procstate::STOPPED => b'T',
+            Procstate::ZOMBIE => b'Z',
+            _ => b'R',
+        }
+    }
 }
 
 impl ProcData {
@@ -148,7 +387,160 @@ impl ProcData {
             context: Context::new(),
             open_files: array![_ => None; NOFILE],
             cwd: MaybeUninit::uninit(),
+            cwd_path: [0; MAXPATH],
+            cwd_path_len: 0,
             name: [0; MAXPROCNAME],
+            title: [0; MAXPROCTITLE],
+            args: [0; MAXPROCARGS],
+            args_len: 0,
+            abi: Abi::Rv6,
+            exec_abi: Abi::Rv6,
+            umask: UMASK,
+            ctty: None,
+            rlimits: RLimits::new(),
+            triggers: [Trigger::new(); NTRIGGER],
+            perf_counts: PerfCounts::new(),
+            perf_start: PerfCounts::new(),
+            wss: WorkingSet::new(),
+        }
+    }
+
+    /// Returns the number of open file descriptors.
+    fn nfiles(&self) -> usize {
+        self.open_files.iter().flatten().count()
+    }
+
+    /// Returns the absolute path of the current directory, if it is known.
+    pub fn cwd_path(&self) -> Option<&[u8]> {
+        if self.cwd_path_len == 0 {
+            None
+        } else {
+            Some(&self.cwd_path[..self.cwd_path_len])
+        }
+    }
+
+    /// Records `path` as the absolute path of the current directory, or that it is not known.
+    pub fn set_cwd_path(&mut self, path: Option<&[u8]>) {
+        let path = path.filter(|path| path.len() <= MAXPATH).unwrap_or(&[]);
+        self.cwd_path[..path.len()].copy_from_slice(path);
+        self.cwd_path_len = path.len();
+    }
+
+    /// Returns the title of the process, or its name if it has no title.
+    pub fn title(&self) -> &[u8] {
+        let title = if self.title[0] != 0 {
+            &self.title[..]
+        } else {
+            &self.name[..]
+        };
+        let len = title.iter().position(|&c| c == 0).unwrap_or(title.len());
+        &title[..len]
+    }
+
+    /// Returns the arguments of exec(), each terminated by NUL.
+    pub fn args(&self) -> &[u8] {
+        &self.args[..self.args_len]
+    }
+
+    /// Keeps `args`, given without their NULs, as the arguments of exec(). Arguments that do
+    /// not fit are truncated or dropped.
+    pub fn set_args<'a>(&mut self, args: impl Iterator<Item = &'a [u8]>) {
+        let mut len = 0;
+        for arg in args {
+            if len == MAXPROCARGS {
+                break;
+            }
+            let n = cmp::min(arg.len(), MAXPROCARGS - len - 1);
+            self.args[len..len + n].copy_from_slice(&arg[..n]);
+            self.args[len + n] = 0;
+            len += n + 1;
+        }
+        self.args_len = len;
+    }
+
+    /// Store the open file descriptors into `out`.
+    /// Returns the number of file descriptors stored.
+    fn fd_info(&self, out: &mut [FdInfo; NOFILE]) -> usize {
+        let mut stored = 0;
+        for (fd, entry) in self.open_files.iter().enumerate() {
+            if let Some(entry) = entry {
+                out[stored] = entry.info(fd as i32);
+                stored += 1;
+            }
+        }
+        stored
+    }
+}
+
+impl ProcInfo {
+    /// Returns true if the process has run for its share of the current throttling window,
+    /// beginning a new window if the current one has ended at `now`.
+    fn throttled(&mut self, now: usize) -> bool {
+        if self.throttle >= 100 {
+            return false;
+        }
+        if now.wrapping_sub(self.throttle_start) >= THROTTLEWINDOW {
+            self.throttle_start = now;
+            self.throttle_used = 0;
+        }
+        self.throttle_used >= THROTTLEWINDOW / 100 * self.throttle as usize
+    }
+
+    /// Returns true if the process has run for its budget in the current period of its
+    /// reservation, beginning a new period if the current one has ended at `now`. A process
+    /// still runnable at its deadline misses it, and waits for the next period. A process that
+    /// has inherited a higher priority runs regardless of its budget.
+    fn depleted(&mut self, now: usize) -> bool {
+        if self.priority() != self.own_priority() {
+            return false;
+        }
+        let elapsed = now.saturating_sub(self.release);
+        if elapsed >= self.reservation.period {
+            self.release = now - elapsed % self.reservation.period;
+            self.reservation.used = 0;
+        }
+        let due = self.release + self.reservation.deadline;
+        let r = &mut self.reservation;
+        if r.used < r.budget && now >= due {
+            r.used = r.budget;
+            r.misses += 1;
+        }
+        r.used >= r.budget
+    }
+
+    /// Returns the priority of the process, not counting the one it has inherited.
+    fn own_priority(&self) -> Priority {
+        match self.class {
+            SchedClass::Deadline => (self.class.rank(), self.release + self.reservation.deadline),
+            class => (class.rank(), 0),
+        }
+    }
+
+    /// Returns the priority the process runs at: its own, or the one it has inherited if that
+    /// is higher.
+    fn priority(&self) -> Priority {
+        let own = self.own_priority();
+        self.boost.map_or(own, |boost| cmp::min(own, boost))
+    }
+
+    /// Returns the class the process runs in, which may be inherited.
+    fn sched_class(&self) -> SchedClass {
+        SchedClass::ALL[self.priority().0]
+    }
+
+    /// Returns the uptime by which a process of the deadline class must have run for its budget
+    /// in the current period of its reservation, or the deadline it has inherited.
+    fn due(&self) -> usize {
+        self.priority().1
+    }
+
+    /// Returns whether the process should run on CPU `id`. It runs only on the CPU it is bound
+    /// to, if any. Otherwise, it is left to the CPU it last ran on, unless that CPU is running
+    /// another process.
+    fn prefers(&self, id: usize) -> bool {
+        match self.bound {
+            Some(cpu) => cpu == id,
+            None => self.cpu == NCPU || self.cpu == id || hal().cpus().is_running(self.cpu),
         }
     }
 }
@@ -164,11 +556,30 @@ impl Proc {
                     waitchannel: ptr::null(),
                     xstate: 0,
                     pid: 0,
+                    trace: Trace::new(),
+                    stop_reported: false,
+                    class: SchedClass::Interactive,
+                    cpu: NCPU,
+                    bound: None,
+                    gang: 0,
+                    reservation: DeadlineInfo::new(),
+                    release: 0,
+                    waits_for: 0,
+                    boost: None,
+                    throttle: 100,
+                    throttle_start: 0,
+                    throttle_used: 0,
+                    run_cycles: Cycles::new(),
+                    run_start: Cycles::new(),
+                    io: IoAccount::new(),
                 },
             ),
             data: UnsafeCell::new(ProcData::new()),
             child_waitchannel: WaitChannel::new(),
             killed: AtomicBool::new(false),
+            trace_waitchannel: WaitChannel::new(),
+            trace_syscall: AtomicBool::new(false),
+            frozen: AtomicBool::new(false),
         }
     }
 }
@@ -191,6 +602,8 @@ impl<'id, 's> ProcRef<'id, 's> {
         &'a self,
         _guard: &'b mut WaitGuard<'id, '_>,
     ) -> &'b mut *const Proc {
+        // SAFETY: `_guard` holds the wait lock of the `Procs` that owns `self`, since both have the
+        // same `'id` tag, and it is mutably borrowed as long as the returned reference lives.
         unsafe { &mut *self.parent.get() }
     }
 
@@ -249,28 +662,36 @@ impl<'id> ProcGuard<'id, '_> {
         assert_eq!(cpu.get_noff(), 1, "sched locks");
 
         let interrupt_enabled = cpu.get_interrupt();
+        cpu.switch_begin();
         unsafe { swtch(&mut self.deref_mut_data().context, cpu.context_raw_mut()) };
 
         // We cannot use `cpu` again because `swtch` may move this thread to another cpu.
         // SAFETY: interrupts are disabled.
         let cpu = unsafe { hal().get_ref().cpus().current_unchecked() };
+        cpu.switch_end(false);
         cpu.set_interrupt(interrupt_enabled);
     }
 
-    /// Frees a `Proc` structure and the data hanging from it, including user pages.
-    /// Also, clears `p`'s parent field into `ptr::null_mut()`.
+    /// Frees a `Proc` structure and the data hanging from it, including user pages and the
+    /// kernel stack. Also, clears `p`'s parent field into `ptr::null_mut()`.
     /// The caller must provide a `ProcGuard`.
     ///
     /// # Safety
     ///
     /// `self.info.state` ≠ `UNUSED`
-    unsafe fn clear(&mut self, mut parent_guard: WaitGuard<'id, '_>) {
+    unsafe fn clear(
+        &mut self,
+        mut parent_guard: WaitGuard<'id, '_>,
+        kernel_memory: &KernelMemory<TargetArch>,
+    ) {
         // SAFETY: this process cannot be the current process any longer.
         let data = unsafe { self.deref_mut_data() };
         let trap_frame = mem::replace(&mut data.trap_frame, ptr::null_mut());
         let allocator = hal().kmem();
         // SAFETY: trap_frame uniquely refers to a valid page.
         allocator.free(unsafe { Page::from_usize(trap_frame as _) });
+        // The process has switched away from its kernel stack for the last time.
+        kernel_memory.free_kstack(mem::replace(&mut data.kstack, 0), allocator);
         // SAFETY:
         // * ok to assume_init() because memory has been initialized according to the invariant.
         // * ok to replace memory with uninit() because state will become UNUSED.
@@ -282,6 +703,16 @@ impl<'id> ProcGuard<'id, '_> {
 
         // Clear the name.
         data.name[0] = 0;
+        data.title[0] = 0;
+        data.args_len = 0;
+        data.abi = Abi::Rv6;
+        data.exec_abi = Abi::Rv6;
+        data.umask = UMASK;
+        data.ctty = None;
+        data.rlimits = RLimits::new();
+        data.triggers = [Trigger::new(); NTRIGGER];
+        data.perf_counts = PerfCounts::new();
+        data.wss = WorkingSet::new();
 
         // Clear the process's parent field.
         *self.get_mut_parent(&mut parent_guard) = ptr::null_mut();
@@ -292,9 +723,32 @@ impl<'id> ProcGuard<'id, '_> {
         info.waitchannel = ptr::null();
         info.pid = 0;
         info.xstate = 0;
+        info.trace = Trace::new();
+        info.stop_reported = false;
+        info.class = SchedClass::Interactive;
+        info.cpu = NCPU;
+        info.bound = None;
+        info.gang = 0;
+        info.reservation = DeadlineInfo::new();
+        info.waits_for = 0;
+        info.boost = None;
+        info.throttle = 100;
+        info.run_cycles = Cycles::new();
+        info.io = IoAccount::new();
         info.state = Procstate::UNUSED;
 
         self.killed.store(false, Ordering::Release);
+        self.trace_syscall.store(false, Ordering::Release);
+        self.frozen.store(false, Ordering::Release);
+        ProcChunk::of(self).put();
+    }
+
+    /// Returns the process as a task of trace events.
+    fn trace_task(&self) -> Task {
+        // SAFETY: only the process itself writes its name, in exec(), and the process is not
+        // running. A name torn by a race would only garble the trace.
+        let data = unsafe { &*self.data.get() };
+        Task::new(self.deref_info().pid, &data.name)
     }
 
     /// Wake process from sleep().
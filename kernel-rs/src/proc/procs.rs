@@ -1,48 +1,96 @@
 use core::{
+    cmp,
     marker::PhantomPinned,
+    mem,
     ops::Deref,
     pin::Pin,
     ptr, str,
-    sync::atomic::{AtomicI32, Ordering},
+    sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicUsize, Ordering},
 };
 
-use array_macro::array;
 use itertools::izip;
 use pin_project::pin_project;
+use static_assertions::const_assert;
 
 use super::*;
 use crate::{
-    addr::{Addr, UVAddr, PGSIZE},
+    addr::{pgrounddown, Addr, UVAddr, PGSIZE},
     fs::{DefaultFs, FileSystem, FileSystemExt},
-    arch::interface::TrapFrameManager,
+    arch::interface::{TimeManager, TrapFrameManager},
+    clock,
+    cpu::cpuid,
+    fdinfo::FdInfo,
     hal::hal,
     kalloc::Kmem,
-    kernel::KernelRef,
+    kernel::{KernelRef, CONSOLE_IN_DEVSW},
+    ktrace::{self, Event},
     lock::{SpinLock, SpinLockGuard},
-    memlayout::kstack,
+    meminfo::ProcMem,
     page::Page,
-    param::{NPROC, ROOTDEV},
+    param::{NINHERIT, NOFILE, ROOTDEV},
+    perf,
+    power::IdlePolicy,
     util::branded::Branded,
-    vm::UserMemory,
+    vm::{KernelMemory, MapInfo, MapRegion, UserMemory},
 };
 
+/// Status reported by `waitpid()` for a stopped child.
+/// It is what Linux reports for a child stopped by SIGSTOP.
+const STOPPED_XSTATE: i32 = 0x137f;
+
+/// Number of processes in a `ProcChunk`.
+const NCHUNKPROC: usize = (PGSIZE - 3 * mem::size_of::<usize>()) / mem::size_of::<Proc>();
+
+const_assert!(NCHUNKPROC > 0);
+const_assert!(mem::size_of::<ProcChunk>() <= PGSIZE);
+
+/// A page of processes. `Procs` allocates chunks from the page allocator when every process is
+/// in use, and frees a chunk when none of its processes is in use any longer.
+///
+/// # Safety
+///
+/// * A chunk is at the start of a page that it owns, so the chunk of a `Proc` is found by
+///   rounding the address of the `Proc` down to a page.
+/// * A chunk is freed only when `pins` and `nused` are 0, while `Procs::chunk_lock` is held.
+#[repr(C)]
+pub struct ProcChunk {
+    /// The next chunk in the list, or null
+    next: AtomicPtr<ProcChunk>,
+
+    /// Number of `ProcIter`s at this chunk
+    pins: AtomicUsize,
+
+    /// Number of processes whose state is not UNUSED
+    nused: AtomicUsize,
+
+    procs: [Proc; NCHUNKPROC],
+}
+
 /// Process system type containing & managing whole processes.
 ///
 /// # Safety
 ///
-/// `initial_proc` is null or valid. `initial_proc` is not modified after its initialization in
-/// `user_proc_init`.
+/// * `initial_proc` is null or valid. `initial_proc` is not modified after its initialization in
+///   `user_proc_init`.
+/// * `chunks` is the first of the list of valid chunks linked by `ProcChunk::next`, or null. The
+///   list is modified only while `chunk_lock` is held.
 #[pin_project]
 pub struct Procs {
     nextpid: AtomicI32,
-    #[pin]
-    process_pool: [Proc; NPROC],
+    chunks: AtomicPtr<ProcChunk>,
+    chunk_lock: SpinLock<()>,
     initial_proc: *const Proc,
     // Helps ensure that wakeups of wait()ing
     // parents are not lost. Helps obey the
     // memory model when using p->parent.
     // Must be acquired before any p->lock.
     wait_lock: SpinLock<()>,
+
+    /// Kill a process when memory runs out?
+    oom_killer: AtomicBool,
+
+    /// Maximum number of processes in use
+    capacity: AtomicUsize,
     #[pin]
     _marker: PhantomPinned,
 }
@@ -56,7 +104,18 @@ pub struct Procs {
 /// A `ProcsRef<'id, 's>` can be created only from a `KernelRef<'id, 's>` that has the same `'id` tag.
 pub struct ProcsRef<'id, 's>(Branded<'id, Pin<&'s Procs>>);
 
-struct ProcIter<'id, 'a>(Branded<'id, core::slice::Iter<'a, Proc>>);
+/// An iterator over the processes of a `Procs`, from the oldest chunk.
+///
+/// # Safety
+///
+/// `chunk` is null or a chunk in the list of `procs`, and the iterator is counted in its `pins`.
+/// Hence, a `ProcRef` returned by the iterator is valid until the iterator moves to the next
+/// chunk or is dropped, and after that as long as the process is in use.
+struct ProcIter<'id, 'a> {
+    procs: Branded<'id, &'a Procs>,
+    chunk: *const ProcChunk,
+    index: usize,
+}
 
 /// A branded type that holds the guard of a `Procs::wait_lock`.
 ///
@@ -71,26 +130,21 @@ impl Procs {
     pub const fn new() -> Self {
         Self {
             nextpid: AtomicI32::new(1),
-            process_pool: array![_ => Proc::new(); NPROC],
+            chunks: AtomicPtr::new(ptr::null_mut()),
+            chunk_lock: SpinLock::new("chunk_lock", ()),
             initial_proc: ptr::null(),
             wait_lock: SpinLock::new("wait_lock", ()),
+            oom_killer: AtomicBool::new(false),
+            capacity: AtomicUsize::new(usize::MAX),
             _marker: PhantomPinned,
         }
     }
 
-    /// Initialize the proc table at boot time.
-    pub fn init(self: Pin<&mut Self>) {
-        // SAFETY: we don't move the `Procs`.
-        let this = unsafe { self.get_unchecked_mut() };
-        for (i, p) in this.process_pool.iter_mut().enumerate() {
-            p.data.get_mut().kstack = kstack(i);
-        }
-    }
-
     /// Set up first user process.
     pub fn user_proc_init(
         self: Pin<&mut Self>,
         cwd: RcInode<DefaultFs>,
+        kernel_memory: &KernelMemory<TargetArch>,
         allocator: Pin<&SpinLock<Kmem>>,
     ) {
         let initial_proc = Branded::new(self.as_ref(), |procs| {
@@ -112,7 +166,11 @@ impl Procs {
             .expect("user_proc_init: UserMemory::new");
 
             let mut guard = procs
-                .alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)
+                .alloc(
+                    scopeguard::ScopeGuard::into_inner(trap_frame),
+                    memory,
+                    kernel_memory,
+                )
                 .expect("user_proc_init: Procs::alloc");
 
             // SAFETY: this process cannot be the current process yet.
@@ -135,6 +193,9 @@ impl Procs {
             let name = b"initcode\x00";
             (&mut data.name[..name.len()]).copy_from_slice(name);
             let _ = data.cwd.write(cwd);
+            data.set_cwd_path(Some(b"/"));
+            // Every process inherits the console as its controlling terminal from here.
+            data.ctty = Some(CONSOLE_IN_DEVSW as u16);
             // It's safe because cwd now has been initialized.
             guard.deref_mut_info().state = Procstate::RUNNABLE;
 
@@ -142,8 +203,8 @@ impl Procs {
         });
 
         // It does not break the invariant since
-        // * initial_proc is a pointer to a `Proc` inside self.
-        // * self is pinned.
+        // * initial_proc is a pointer to a `Proc` in a chunk of self.
+        // * the chunk is never freed, since the initial process never exits.
         *self.project().initial_proc = initial_proc;
     }
 
@@ -156,6 +217,99 @@ impl Procs {
     fn allocpid(self: Pin<&Self>) -> Pid {
         self.nextpid.fetch_add(1, Ordering::Relaxed)
     }
+
+    /// Allocate a chunk of processes, and append it to the list of chunks. Returns Ok(the chunk), which is pinned, or Err(()) if memory runs out.
+    fn grow(&self) -> Result<&ProcChunk, ()> {
+        let allocator = hal().kmem();
+        let chunk = allocator.alloc().ok_or(())?.into_usize() as *mut ProcChunk;
+
+        // Initialize the chunk in place, since it is too large for the kernel stack.
+        // SAFETY: `chunk` points to a page that we own, which can contain a `ProcChunk`.
+        unsafe {
+            ptr::addr_of_mut!((*chunk).next).write(AtomicPtr::new(ptr::null_mut()));
+            ptr::addr_of_mut!((*chunk).pins).write(AtomicUsize::new(1));
+            ptr::addr_of_mut!((*chunk).nused).write(AtomicUsize::new(0));
+            let procs = ptr::addr_of_mut!((*chunk).procs) as *mut Proc;
+            for i in 0..NCHUNKPROC {
+                procs.add(i).write(Proc::new());
+            }
+        }
+
+        let _guard = self.chunk_lock.lock();
+        let mut link = &self.chunks;
+        // SAFETY: invariant.
+        while let Some(last) = unsafe { link.load(Ordering::Relaxed).as_ref() } {
+            link = &last.next;
+        }
+        link.store(chunk, Ordering::Relaxed);
+        // SAFETY: `chunk` has been initialized, and it is freed only after we unpin it.
+        Ok(unsafe { &*chunk })
+    }
+
+    /// Count a process of `chunk` as in use, unless `capacity` processes are in use already.
+    /// Returns Ok(()) on success, Err(()) on error.
+    fn count_used(&self, chunk: &ProcChunk) -> Result<(), ()> {
+        let _guard = self.chunk_lock.lock();
+        let mut used = 0;
+        let mut next = self.chunks.load(Ordering::Relaxed);
+        // SAFETY: invariant.
+        while let Some(c) = unsafe { next.as_ref() } {
+            used += c.nused.load(Ordering::Relaxed);
+            next = c.next.load(Ordering::Relaxed);
+        }
+        if used >= self.capacity.load(Ordering::Relaxed) {
+            return Err(());
+        }
+        let _ = chunk.nused.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Move a pin of a `ProcIter` from `chunk` to the next chunk, and return the next chunk.
+    fn pin_next(&self, chunk: &ProcChunk) -> *const ProcChunk {
+        let guard = self.chunk_lock.lock();
+        let next = chunk.next.load(Ordering::Relaxed);
+        // SAFETY: invariant.
+        if let Some(next) = unsafe { next.as_ref() } {
+            let _ = next.pins.fetch_add(1, Ordering::Relaxed);
+        }
+        self.unpin(chunk, &guard);
+        next
+    }
+
+    /// Remove a pin from `chunk`, and free `chunk` if it has no other pins and none of its
+    /// processes is in use. `chunk` must be pinned, and it must not be used after this.
+    fn unpin(&self, chunk: *const ProcChunk, _guard: &SpinLockGuard<'_, ()>) {
+        // SAFETY: `chunk` is pinned.
+        let c = unsafe { &*chunk };
+        if c.pins.fetch_sub(1, Ordering::Relaxed) > 1 || c.nused.load(Ordering::Relaxed) > 0 {
+            return;
+        }
+
+        // Unlink the chunk.
+        let mut link = &self.chunks;
+        while link.load(Ordering::Relaxed) as *const ProcChunk != chunk {
+            // SAFETY: invariant, and `chunk` is in the list.
+            link = unsafe { &(*link.load(Ordering::Relaxed)).next };
+        }
+        link.store(c.next.load(Ordering::Relaxed), Ordering::Relaxed);
+
+        let allocator = hal().kmem();
+        // SAFETY: the chunk is a page that it owns, and no one refers to it any longer.
+        allocator.free(unsafe { Page::from_usize(chunk as usize) });
+    }
+}
+
+impl ProcChunk {
+    /// Returns the chunk that holds `p`.
+    pub(super) fn of(p: &Proc) -> &Self {
+        // SAFETY: invariant.
+        unsafe { &*(pgrounddown(p as *const _ as usize) as *const Self) }
+    }
+
+    /// Count a process of this chunk as no longer in use.
+    pub(super) fn put(&self) {
+        let _ = self.nused.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl<'id, 's> ProcsRef<'id, 's> {
@@ -169,20 +323,46 @@ impl<'id, 's> ProcsRef<'id, 's> {
         WaitGuard(self.0.brand(self.0.get_ref().wait_lock.lock()))
     }
 
+    /// Look into process system for an UNUSED proc, adding a chunk of processes if there is none,
+    /// and return it with p->lock held, counted as in use.
+    /// If `capacity` processes are in use, or memory runs out, return Err.
+    fn alloc_unused(&self) -> Result<ProcGuard<'id, '_>, ()> {
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.deref_info().state == Procstate::UNUSED {
+                self.0.count_used(ProcChunk::of(&guard))?;
+                return Ok(guard);
+            }
+        }
+
+        let chunk = self.0.grow()?;
+        let guard = ProcRef(self.0.brand(&chunk.procs[0])).lock();
+        // On failure, the guard is dropped before the chunk is unpinned and freed.
+        let result = self.0.count_used(chunk).map(|_| guard);
+        self.0.unpin(chunk, &self.0.chunk_lock.lock());
+        result
+    }
+
     /// Look into process system for an UNUSED proc.
-    /// If found, initialize state required to run in the kernel,
+    /// If found, allocate its kernel stack, initialize state required to run in the kernel,
     /// and return with p->lock held.
     /// If there are no free procs, or a memory allocation fails, return Err.
-    fn alloc(&self, trap_frame: Page, memory: UserMemory) -> Result<ProcGuard<'id, '_>, ()> {
-        for p in self.process_pool() {
-            let mut guard = p.lock();
-            if guard.deref_info().state == Procstate::UNUSED {
+    fn alloc(
+        &self,
+        trap_frame: Page,
+        memory: UserMemory,
+        kernel_memory: &KernelMemory<TargetArch>,
+    ) -> Result<ProcGuard<'id, '_>, ()> {
+        let allocator = hal().kmem();
+        if let Some(kstack) = kernel_memory.alloc_kstack(allocator) {
+            if let Ok(mut guard) = self.alloc_unused() {
                 // SAFETY: this process cannot be the current process yet.
                 let data = unsafe { guard.deref_mut_data() };
 
-                // Initialize trap frame and page table.
+                // Initialize trap frame, page table, and kernel stack.
                 data.trap_frame = trap_frame.into_usize() as _;
                 let _ = data.memory.write(memory);
+                data.kstack = kstack;
 
                 // Set up new context to start executing at forkret,
                 // which returns to user space.
@@ -197,9 +377,9 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
                 return Ok(guard);
             }
+            kernel_memory.free_kstack(kstack, allocator);
         }
 
-        let allocator = hal().kmem();
         allocator.free(trap_frame);
         memory.free(allocator);
         Err(())
@@ -213,6 +393,10 @@ impl<'id, 's> ProcsRef<'id, 's> {
             if p.deref() as *const _ != current_proc {
                 let mut guard = p.lock();
                 if guard.deref_info().waitchannel == target as _ {
+                    if ktrace::enabled() && guard.state() == Procstate::SLEEPING {
+                        let task = guard.trace_task();
+                        ktrace::record(kernel.current_pid(), Event::Wakeup { task });
+                    }
                     guard.wakeup()
                 }
             }
@@ -232,6 +416,15 @@ impl<'id, 's> ProcsRef<'id, 's> {
             if *parent == proc {
                 *parent = self.0.initial_proc();
                 self.0.initial_proc().child_waitchannel.wakeup(kernel);
+
+                // Nobody traces the child any longer, so let it go.
+                let mut guard = pp.lock();
+                if guard.deref_info().trace.traced {
+                    guard.deref_mut_info().trace = Trace::new();
+                    pp.trace_syscall.store(false, Ordering::Release);
+                    drop(guard);
+                    pp.trace_waitchannel.wakeup(kernel);
+                }
             }
         }
     }
@@ -248,18 +441,37 @@ impl<'id, 's> ProcsRef<'id, 's> {
     pub fn fork(&self, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
         let allocator = hal().kmem();
         // Allocate trap frame.
-        let trap_frame =
-            scopeguard::guard(allocator.alloc().ok_or(())?, |page| allocator.free(page));
+        let trap_frame = allocator.alloc().ok_or_else(|| {
+            self.out_of_memory(ctx);
+        })?;
+        let trap_frame = scopeguard::guard(trap_frame, |page| allocator.free(page));
 
         // Copy user memory from parent to child.
         let memory = ctx
             .proc_mut()
             .memory_mut()
             .clone(trap_frame.addr(), allocator)
-            .ok_or(())?;
+            .ok_or_else(|| {
+                self.out_of_memory(ctx);
+            })?;
+
+        // The child inherits the scheduling class and the throttle, but not the reservation of
+        // the deadline class.
+        let (class, throttle) = {
+            let guard = ctx.proc().lock();
+            let class = match guard.deref_info().class {
+                SchedClass::Deadline => SchedClass::Interactive,
+                class => class,
+            };
+            (class, guard.deref_info().throttle)
+        };
 
         // Allocate process.
-        let mut np = self.alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)?;
+        let mut np = self.alloc(
+            scopeguard::ScopeGuard::into_inner(trap_frame),
+            memory,
+            ctx.kernel().memory(),
+        )?;
         // SAFETY: this process cannot be the current process yet.
         let npdata = unsafe { np.deref_mut_data() };
 
@@ -276,14 +488,25 @@ impl<'id, 's> ProcsRef<'id, 's> {
             npdata.open_files.iter_mut(),
             ctx.proc().deref_data().open_files.iter()
         ) {
-            if let Some(file) = f {
-                *nf = Some(file.clone());
+            if let Some(entry) = f {
+                *nf = Some(entry.clone());
             }
         }
         let _ = npdata.cwd.write(ctx.proc().cwd().clone());
+        npdata.set_cwd_path(ctx.proc().deref_data().cwd_path());
 
         npdata.name.copy_from_slice(&ctx.proc().deref_data().name);
-
+        npdata.title.copy_from_slice(&ctx.proc().deref_data().title);
+        npdata.args = ctx.proc().deref_data().args;
+        npdata.args_len = ctx.proc().deref_data().args_len;
+        npdata.abi = ctx.proc().deref_data().abi;
+        npdata.exec_abi = ctx.proc().deref_data().exec_abi;
+        npdata.umask = ctx.proc().deref_data().umask;
+        npdata.ctty = ctx.proc().deref_data().ctty;
+        npdata.rlimits = ctx.proc().deref_data().rlimits;
+
+        np.deref_mut_info().class = class;
+        np.deref_mut_info().throttle = throttle;
         let pid = np.deref_mut_info().pid;
 
         // Now drop the guard before we acquire the `wait_lock`.
@@ -328,26 +551,35 @@ impl<'id, 's> ProcsRef<'id, 's> {
                             return Err(());
                         }
                         // Reap the zombie child process.
+                        ctx.kernel().gangs().leave(np.deref_info().gang);
                         // SAFETY: np.state() equals ZOMBIE.
-                        unsafe { np.clear(parent_guard) };
+                        unsafe { np.clear(parent_guard, ctx.kernel().memory()) };
                         return Ok(pid);
                     }
                 }
             }
 
             // No point waiting if we don't have any children.
-            if !havekids || ctx.proc().killed() {
+            if !havekids {
                 return Err(());
             }
 
             // Wait for a child to exit.
             //DOC: wait-sleep
-            ctx.proc().child_waitchannel.sleep(&mut parent_guard.0, ctx);
+            ctx.proc()
+                .child_waitchannel
+                .sleep_killable(&mut parent_guard.0, ctx)?;
         }
     }
 
-    // Wait for a child process with `pid` to exit.
-    pub fn waitpid(&self, pid: Pid, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    // Wait for a child process with `pid` to exit, or to stop if `options` has WUNTRACED.
+    pub fn waitpid(
+        &self,
+        pid: Pid,
+        addr: UVAddr,
+        options: WaitOptions,
+        ctx: &mut KernelCtx<'id, '_>,
+    ) -> Result<Pid, ()> {
         let mut parent_guard = self.wait_guard();
 
         let mut found = false;
@@ -377,21 +609,41 @@ impl<'id, 's> ProcsRef<'id, 's> {
                             return Err(());
                         }
                         // Reap the zombie child process.
+                        ctx.kernel().gangs().leave(np.deref_info().gang);
                         // SAFETY: np.state() equals ZOMBIE.
-                        unsafe { np.clear(parent_guard) };
+                        unsafe { np.clear(parent_guard, ctx.kernel().memory()) };
+                        return Ok(pid);
+                    }
+
+                    if options.contains(WaitOptions::WUNTRACED)
+                        && np.state() == Procstate::STOPPED
+                        && !np.deref_info().stop_reported
+                    {
+                        if !addr.is_null()
+                            && ctx
+                                .proc_mut()
+                                .memory_mut()
+                                .copy_out(addr, &STOPPED_XSTATE)
+                                .is_err()
+                        {
+                            return Err(());
+                        }
+                        np.deref_mut_info().stop_reported = true;
                         return Ok(pid);
                     }
                 }
             }
 
             // No point waiting if we don't have any children.
-            if !found || ctx.proc().killed() {
+            if !found {
                 return Err(());
             }
 
             // Wait for a child to exit.
             //DOC: wait-sleep
-            ctx.proc().child_waitchannel.sleep(&mut parent_guard.0, ctx);
+            ctx.proc()
+                .child_waitchannel
+                .sleep_killable(&mut parent_guard.0, ctx)?;
             found = false;
         }
     }
@@ -406,12 +658,756 @@ impl<'id, 's> ProcsRef<'id, 's> {
             if guard.deref_info().pid == pid {
                 p.kill();
                 guard.wakeup();
+                // A stopped process must run to exit.
+                if guard.state() == Procstate::STOPPED {
+                    guard.deref_mut_info().state = Procstate::RUNNABLE;
+                }
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+
+    /// Enable or disable the OOM killer.
+    pub fn set_oom_killer(&self, enabled: bool) {
+        self.0.oom_killer.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Limit the number of processes in use to `capacity`.
+    /// Processes already in use beyond the new capacity are not affected.
+    pub fn set_capacity(&self, capacity: usize) {
+        self.0.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    /// Handle a failure to allocate pages for the current process.
+    /// If the OOM killer is enabled, kill the process with the most resident pages other than
+    /// the initial process, which may be the current process itself. Its pages are freed once
+    /// it exits and is reaped, so the allocation may succeed if it is tried again later.
+    /// While a process killed earlier has not exited, no other process is killed, since its
+    /// pages are about to be freed.
+    /// Processes running on other CPUs are not considered, since their memory may be changing.
+    /// Returns true if a process was killed, or is exiting after being killed.
+    pub fn out_of_memory(&self, ctx: &KernelCtx<'id, '_>) -> bool {
+        if !self.0.oom_killer.load(Ordering::Relaxed) {
+            return false;
+        }
+        let mut victim = None;
+        for p in self.process_pool() {
+            if ptr::eq(p.deref(), self.0.initial_proc()) {
+                continue;
+            }
+            let mut guard = p.lock();
+            if p.killed() {
+                if !matches!(guard.state(), Procstate::UNUSED | Procstate::ZOMBIE) {
+                    return true;
+                }
+                continue;
+            }
+            let pid = guard.deref_info().pid;
+            // Pages mapped from the zero page take no memory until they are written.
+            let size = if pid == ctx.proc().pid() {
+                ctx.proc().memory().map_info(&mut []).rss
+            } else if matches!(
+                guard.state(),
+                Procstate::RUNNABLE | Procstate::SLEEPING | Procstate::STOPPED
+            ) {
+                // SAFETY: the process is runnable, sleeping, or stopped, and it cannot be
+                // scheduled while we hold its lock. Hence, its `CurrentProc` does
+                // not access its `ProcData` until we release the lock.
+                let data = unsafe { guard.deref_mut_data() };
+                // SAFETY: memory has been initialized since the process is
+                // runnable, sleeping, or stopped.
+                unsafe { data.memory.assume_init_ref() }
+                    .map_info(&mut [])
+                    .rss
+            } else {
+                continue;
+            };
+            if victim.map_or(true, |(_, max)| size > max) {
+                victim = Some((pid, size));
+            }
+        }
+        match victim {
+            Some((pid, _)) => self.kill(pid).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Freeze the process with the given pid.
+    /// The process stops when it next tries to return to user space,
+    /// and does not run until it is thawed or killed.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn freeze(&self, pid: Pid) -> Result<(), ()> {
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                p.frozen.store(true, Ordering::Release);
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+
+    /// Thaw the process with the given pid, resuming it if it has stopped.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn thaw(&self, pid: Pid) -> Result<(), ()> {
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                p.frozen.store(false, Ordering::Release);
+                if guard.state() == Procstate::STOPPED {
+                    guard.deref_mut_info().state = Procstate::RUNNABLE;
+                }
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+
+    /// Set the scheduling class of the process with the given pid,
+    /// or of the current process if pid is 0.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn set_sched_class(
+        &self,
+        pid: Pid,
+        class: SchedClass,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<(), ()> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                guard.deref_mut_info().class = class;
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+
+    /// Returns the scheduling class of the process with the given pid,
+    /// or of the current process if pid is 0.
+    pub fn sched_class(&self, pid: Pid, ctx: &KernelCtx<'id, '_>) -> Result<SchedClass, ()> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                return Ok(guard.deref_info().class);
+            }
+        }
+        Err(())
+    }
+
+    /// Put the process with the given pid, or the current process if pid is 0, in the deadline
+    /// class with the reservation (period, budget, deadline), in microseconds, or move it back to
+    /// the interactive class if `reservation` is `None`.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn set_deadline(
+        &self,
+        pid: Pid,
+        reservation: Option<(usize, usize, usize)>,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<(), ()> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                let info = guard.deref_mut_info();
+                match reservation {
+                    Some((period, budget, deadline)) => {
+                        info.class = SchedClass::Deadline;
+                        info.reservation = DeadlineInfo {
+                            period,
+                            budget,
+                            deadline,
+                            ..DeadlineInfo::new()
+                        };
+                        info.release = clock::uptime();
+                    }
+                    None if info.class == SchedClass::Deadline => {
+                        info.class = SchedClass::Interactive
+                    }
+                    None => {}
+                }
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+
+    /// Returns the reservation of the deadline class of the process with the given pid,
+    /// or of the current process if pid is 0.
+    pub fn deadline_info(&self, pid: Pid, ctx: &KernelCtx<'id, '_>) -> Result<DeadlineInfo, ()> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                return Ok(guard.deref_info().reservation);
+            }
+        }
+        Err(())
+    }
+
+    /// Record that the current process waits for a sleep lock held by the process `holder`, or
+    /// for none if `holder` is 0. The holder inherits the priority of the current process if it
+    /// is higher than its own, and so does the process that the holder waits for in turn, and so
+    /// on, up to `NINHERIT` processes.
+    /// Returns true if the priority of the holder has been raised.
+    pub fn wait_for(&self, holder: Pid, ctx: &KernelCtx<'id, '_>) -> bool {
+        let priority = {
+            let mut guard = ctx.proc().lock();
+            let info = guard.deref_mut_info();
+            info.waits_for = holder;
+            info.priority()
+        };
+        let mut raised = false;
+        let mut pid = holder;
+        for _ in 0..NINHERIT {
+            if pid == 0 || pid == ctx.proc().pid() {
+                break;
+            }
+            let next = self.process_pool().find_map(|p| {
+                let mut guard = p.lock();
+                if guard.deref_info().pid != pid || guard.state() == Procstate::ZOMBIE {
+                    return None;
+                }
+                let info = guard.deref_mut_info();
+                if info.priority() <= priority {
+                    return Some(None);
+                }
+                info.boost = Some(priority);
+                Some(Some(info.waits_for))
+            });
+            match next {
+                Some(Some(next)) => {
+                    raised = true;
+                    pid = next;
+                }
+                _ => break,
+            }
+        }
+        raised
+    }
+
+    /// Drop the priority that the current process has inherited, and inherit again from the
+    /// processes still waiting for the sleep locks that it holds. Called after the current
+    /// process releases a sleep lock, whose waiters have been woken up.
+    pub fn disinherit(&self, ctx: &KernelCtx<'id, '_>) {
+        let pid = {
+            let mut guard = ctx.proc().lock();
+            let info = guard.deref_mut_info();
+            if info.boost.is_none() {
+                return;
+            }
+            info.boost = None;
+            info.pid
+        };
+        let boost = self
+            .process_pool()
+            .filter_map(|p| {
+                let guard = p.lock();
+                let info = guard.deref_info();
+                if guard.state() == Procstate::SLEEPING && info.waits_for == pid {
+                    Some(info.priority())
+                } else {
+                    None
+                }
+            })
+            .min();
+
+        // A process may have lent its priority since the boost was dropped.
+        let mut guard = ctx.proc().lock();
+        let info = guard.deref_mut_info();
+        let own = info.own_priority();
+        info.boost = match (info.boost, boost.filter(|boost| *boost < own)) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    /// Bind the process with the given pid, or the current process if pid is 0, to CPU `cpu`,
+    /// or let it run on any CPU again if `cpu` is `None`.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn migrate(
+        &self,
+        pid: Pid,
+        cpu: Option<usize>,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<(), ()> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                guard.deref_mut_info().bound = cpu;
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+
+    /// Put the process with the given pid, or the current process if pid is 0, in gang `gang`,
+    /// or in no gang if `gang` is `Some(0)`, and leave it as it is if `gang` is `None`.
+    /// Returns Ok(gang of the process) on success, Err(()) on error.
+    pub fn set_gang(
+        &self,
+        pid: Pid,
+        gang: Option<i32>,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<i32, ()> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                let info = guard.deref_mut_info();
+                if let Some(gang) = gang {
+                    ctx.kernel().gangs().join(info.gang, gang)?;
+                    info.gang = gang;
+                }
+                return Ok(info.gang);
+            }
+        }
+        Err(())
+    }
+
+    /// Returns the disk I/O of the process with the given pid,
+    /// or of the current process if pid is 0.
+    pub fn io_stats(&self, pid: Pid, ctx: &KernelCtx<'id, '_>) -> Result<IoStats, ()> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                return Ok(guard.deref_info().io.stats);
+            }
+        }
+        Err(())
+    }
+
+    /// Let the process with the given pid, or the current process if pid is 0, run for at most
+    /// percent percent of the time. 100 removes the limit.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn set_throttle(&self, pid: Pid, percent: u32, ctx: &KernelCtx<'id, '_>) -> Result<(), ()> {
+        let pid = if pid == 0 { ctx.proc().pid() } else { pid };
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                let info = guard.deref_mut_info();
+                info.throttle = percent;
+                info.throttle_start = TargetArch::r_cycle();
+                info.throttle_used = 0;
                 return Ok(());
             }
         }
         Err(())
     }
 
+    /// Stop the current process if it is frozen, and return once it is thawed or killed.
+    /// Called right before returning to user space.
+    pub fn stop_if_frozen(&self, ctx: &mut KernelCtx<'id, '_>) {
+        if !ctx.proc().frozen.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut parent_guard = self.wait_guard();
+
+        // Parent might be sleeping in waitpid().
+        let parent = *ctx.proc().get_mut_parent(&mut parent_guard);
+        // SAFETY: `parent` is null or a valid pointer according to the invariants of
+        // `Proc` and `CurrentProc`.
+        if let Some(parent) = unsafe { parent.as_ref() } {
+            parent.child_waitchannel.wakeup(ctx.kernel());
+        }
+
+        let mut guard = ctx.proc().lock();
+        if !ctx.proc().frozen.load(Ordering::Acquire) || ctx.proc().killed() {
+            return;
+        }
+        guard.deref_mut_info().state = Procstate::STOPPED;
+        guard.deref_mut_info().stop_reported = false;
+        drop(parent_guard);
+
+        // Jump into the scheduler, and return after being thawed or killed.
+        unsafe { guard.sched() };
+    }
+
+    /// Summarize the memory mappings of the process with the given pid,
+    /// storing its regions into `regions`. A process other than the current
+    /// one is inspected only while it is not running.
+    /// Returns Ok(summary) on success, Err(()) on error.
+    pub fn map_info(
+        &self,
+        pid: Pid,
+        regions: &mut [MapRegion],
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<MapInfo, ()> {
+        if pid == ctx.proc().pid() {
+            return Ok(ctx.proc().memory().map_info(regions));
+        }
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid {
+                if !matches!(
+                    guard.state(),
+                    Procstate::RUNNABLE | Procstate::SLEEPING | Procstate::STOPPED
+                ) {
+                    return Err(());
+                }
+                // SAFETY: the process is runnable, sleeping, or stopped, and it cannot be
+                // scheduled while we hold its lock. Hence, its `CurrentProc` does
+                // not access its `ProcData` until we release the lock.
+                let data = unsafe { guard.deref_mut_data() };
+                // SAFETY: memory has been initialized since the process is
+                // runnable, sleeping, or stopped.
+                let memory = unsafe { data.memory.assume_init_ref() };
+                return Ok(memory.map_info(regions));
+            }
+        }
+        Err(())
+    }
+
+    /// Store the open file descriptors of the process with the given pid into `out`.
+    /// Returns Ok(number of file descriptors stored) on success, Err(()) on error.
+    pub fn fd_info(
+        &self,
+        pid: Pid,
+        out: &mut [FdInfo; NOFILE],
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<usize, ()> {
+        self.inspect(pid, ctx, |data| data.fd_info(out))
+    }
+
+    /// Calls `f` with the data of the process with the given pid.
+    /// A process other than the current one is inspected only while it is not running.
+    /// Returns Ok(the result of `f`) on success, Err(()) on error.
+    pub fn inspect<R, F: FnOnce(&ProcData) -> R>(
+        &self,
+        pid: Pid,
+        ctx: &KernelCtx<'id, '_>,
+        f: F,
+    ) -> Result<R, ()> {
+        if pid == ctx.proc().pid() {
+            return Ok(f(ctx.proc().deref_data()));
+        }
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid {
+                if !matches!(
+                    guard.state(),
+                    Procstate::RUNNABLE | Procstate::SLEEPING | Procstate::STOPPED
+                ) {
+                    return Err(());
+                }
+                // SAFETY: the process is runnable, sleeping, or stopped, and it cannot be
+                // scheduled while we hold its lock. Hence, its `CurrentProc` does
+                // not access its `ProcData` until we release the lock.
+                let data = unsafe { guard.deref_mut_data() };
+                return Ok(f(data));
+            }
+        }
+        Err(())
+    }
+
+    /// Calls `f` with the data of the process with the given pid to modify it.
+    /// A process other than the current one is modified only while it is not running.
+    /// Returns Ok(the result of `f`) on success, Err(()) on error.
+    pub fn inspect_mut<R, F: FnOnce(&mut ProcData) -> R>(
+        &self,
+        pid: Pid,
+        ctx: &mut KernelCtx<'id, '_>,
+        f: F,
+    ) -> Result<R, ()> {
+        if pid == ctx.proc().pid() {
+            return Ok(f(ctx.proc_mut().deref_mut_data()));
+        }
+        for p in self.process_pool() {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid {
+                if !matches!(
+                    guard.state(),
+                    Procstate::RUNNABLE | Procstate::SLEEPING | Procstate::STOPPED
+                ) {
+                    return Err(());
+                }
+                // SAFETY: the process is runnable, sleeping, or stopped, and it cannot be
+                // scheduled while we hold its lock. Hence, its `CurrentProc` does
+                // not access its `ProcData` until we release the lock.
+                let data = unsafe { guard.deref_mut_data() };
+                return Ok(f(data));
+            }
+        }
+        Err(())
+    }
+
+    /// Store the pid, the resident set size, and the number of open files of each process into
+    /// `out`, as many as it can hold. Processes running on other CPUs are skipped, since their mappings may be changing.
+    /// Returns the number of processes stored.
+    pub fn rss(&self, out: &mut [ProcMem], ctx: &KernelCtx<'id, '_>) -> usize {
+        let mut stored = 0;
+        for p in self.process_pool() {
+            if stored == out.len() {
+                break;
+            }
+            let mut guard = p.lock();
+            let pid = guard.deref_info().pid;
+            let (rss, nfiles) = if pid == ctx.proc().pid() {
+                (
+                    ctx.proc().memory().map_info(&mut []).rss,
+                    ctx.proc().deref_data().nfiles(),
+                )
+            } else if matches!(
+                guard.state(),
+                Procstate::RUNNABLE | Procstate::SLEEPING | Procstate::STOPPED
+            ) {
+                // SAFETY: the process is runnable, sleeping, or stopped, and it cannot be
+                // scheduled while we hold its lock. Hence, its `CurrentProc` does
+                // not access its `ProcData` until we release the lock.
+                let data = unsafe { guard.deref_mut_data() };
+                // SAFETY: memory has been initialized since the process is
+                // runnable, sleeping, or stopped.
+                let memory = unsafe { data.memory.assume_init_ref() };
+                (memory.map_info(&mut []).rss, data.nfiles())
+            } else {
+                continue;
+            };
+            out[stored] = ProcMem {
+                pid,
+                nfiles: nfiles as u32,
+                rss,
+            };
+            stored += 1;
+        }
+        stored
+    }
+
+    /// Make the current process traced by its parent, and stop it.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn trace_me(&self, ctx: &mut KernelCtx<'id, '_>) -> Result<(), ()> {
+        let mut parent_guard = self.wait_guard();
+        if ctx.proc().get_mut_parent(&mut parent_guard).is_null() {
+            return Err(());
+        }
+        let mut guard = ctx.proc().lock();
+        if guard.deref_info().trace.traced {
+            return Err(());
+        }
+        guard.deref_mut_info().trace.traced = true;
+        drop(guard);
+        drop(parent_guard);
+
+        self.trace_stop(TraceEvent::Start, ctx);
+        Ok(())
+    }
+
+    /// Stop the current process at system call entry or exit
+    /// if its parent traces its system calls.
+    pub fn trace_syscall(&self, event: TraceEvent, ctx: &mut KernelCtx<'id, '_>) {
+        if ctx.proc().trace_syscall.load(Ordering::Acquire) {
+            self.trace_stop(event, ctx);
+        }
+    }
+
+    /// Stop the current process for `event` if it is traced,
+    /// and sleep until the parent resumes it or it is killed.
+    fn trace_stop(&self, event: TraceEvent, ctx: &mut KernelCtx<'id, '_>) {
+        let mut parent_guard = self.wait_guard();
+        let mut guard = ctx.proc().lock();
+        if !guard.deref_info().trace.traced {
+            return;
+        }
+        guard.deref_mut_info().trace.stop = Some(event);
+        drop(guard);
+
+        // The parent might be sleeping in ptrace(PTRACE_WAIT).
+        let parent = *ctx.proc().get_mut_parent(&mut parent_guard);
+        // SAFETY:
+        // * `parent` cannot be null because a process is traced only by its parent,
+        //   and a process stops being traced when it loses its parent.
+        // * `parent` is a valid pointer according to the invariants of
+        //   `Proc` and `CurrentProc`.
+        unsafe { (*parent).child_waitchannel.wakeup(ctx.kernel()) };
+
+        let _ = ctx.proc().trace_waitchannel.wait_while_killable(
+            &mut parent_guard.0,
+            |_| ctx.proc().lock().deref_info().trace.stop.is_some(),
+            ctx,
+        );
+        ctx.proc().lock().deref_mut_info().trace.stop = None;
+    }
+
+    /// Returns the locked child of the current process with the given pid,
+    /// if the child is traced and has not exited.
+    fn traced_child(
+        &self,
+        pid: Pid,
+        parent_guard: &mut WaitGuard<'id, '_>,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<ProcGuard<'id, 's>, ()> {
+        for np in self.process_pool() {
+            if *np.get_mut_parent(parent_guard) == ctx.proc().deref().deref() {
+                let np = np.lock();
+                if np.deref_info().pid == pid
+                    && np.deref_info().trace.traced
+                    && np.state() != Procstate::ZOMBIE
+                {
+                    return Ok(np);
+                }
+            }
+        }
+        Err(())
+    }
+
+    /// Returns the locked child of the current process with the given pid,
+    /// if the child is traced and stopped.
+    /// A stopped child does not run while it is locked.
+    fn stopped_child(
+        &self,
+        pid: Pid,
+        parent_guard: &mut WaitGuard<'id, '_>,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<ProcGuard<'id, 's>, ()> {
+        let np = self.traced_child(pid, parent_guard, ctx)?;
+        if np.deref_info().trace.stop.is_none()
+            || !matches!(np.state(), Procstate::RUNNABLE | Procstate::SLEEPING)
+        {
+            return Err(());
+        }
+        Ok(np)
+    }
+
+    /// Wait for the traced child with the given pid to stop.
+    /// Returns Ok(why the child is stopped) on success,
+    /// Err(()) if the child has exited or is not traced.
+    pub fn trace_wait(&self, pid: Pid, ctx: &mut KernelCtx<'id, '_>) -> Result<TraceEvent, ()> {
+        let mut parent_guard = self.wait_guard();
+        loop {
+            let np = self.traced_child(pid, &mut parent_guard, ctx)?;
+            if let Some(event) = np.deref_info().trace.stop {
+                return Ok(event);
+            }
+            drop(np);
+
+            // Wait for the child to stop or exit.
+            ctx.proc()
+                .child_waitchannel
+                .sleep_killable(&mut parent_guard.0, ctx)?;
+        }
+    }
+
+    /// Resume the stopped child with the given pid. If `syscall` is true, the child
+    /// stops again at the next system call entry or exit.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn trace_resume(
+        &self,
+        pid: Pid,
+        syscall: bool,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<(), ()> {
+        let mut parent_guard = self.wait_guard();
+        let mut np = self.stopped_child(pid, &mut parent_guard, ctx)?;
+        np.deref_mut_info().trace.stop = None;
+        np.trace_syscall.store(syscall, Ordering::Release);
+        let p = *np;
+        drop(np);
+        p.trace_waitchannel.wakeup(ctx.kernel());
+        Ok(())
+    }
+
+    /// Read the word at `addr` of the stopped child with the given pid.
+    /// Returns Ok(the word) on success, Err(()) on error.
+    pub fn trace_peek(
+        &self,
+        pid: Pid,
+        addr: UVAddr,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<usize, ()> {
+        let mut parent_guard = self.wait_guard();
+        let mut np = self.stopped_child(pid, &mut parent_guard, ctx)?;
+        // SAFETY: the child is stopped, and it cannot be scheduled while we hold its lock.
+        let data = unsafe { np.deref_mut_data() };
+        let mut word = 0;
+        // SAFETY:
+        // * memory has been initialized since the child is runnable or sleeping.
+        // * usize does not have any internal structure.
+        unsafe { data.memory.assume_init_mut().copy_in(&mut word, addr) }?;
+        Ok(word)
+    }
+
+    /// Write `word` to the word at `addr` of the stopped child with the given pid.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn trace_poke(
+        &self,
+        pid: Pid,
+        addr: UVAddr,
+        word: usize,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<(), ()> {
+        let mut parent_guard = self.wait_guard();
+        let mut np = self.stopped_child(pid, &mut parent_guard, ctx)?;
+        // SAFETY: the child is stopped, and it cannot be scheduled while we hold its lock.
+        let data = unsafe { np.deref_mut_data() };
+        // SAFETY: memory has been initialized since the child is runnable or sleeping.
+        unsafe { data.memory.assume_init_mut() }.copy_out(addr, &word)
+    }
+
+    /// Read the registers of the stopped child with the given pid.
+    /// Returns Ok(the registers) on success, Err(()) on error.
+    pub fn trace_get_regs(&self, pid: Pid, ctx: &KernelCtx<'id, '_>) -> Result<TraceRegs, ()> {
+        let mut parent_guard = self.wait_guard();
+        let mut np = self.stopped_child(pid, &mut parent_guard, ctx)?;
+        // SAFETY: the child is stopped, and it cannot be scheduled while we hold its lock.
+        let data = unsafe { np.deref_mut_data() };
+        // SAFETY: trap_frame is a valid pointer according to the invariants of Proc.
+        Ok(TraceRegs::read(unsafe { &*data.trap_frame }))
+    }
+
+    /// Write `regs` to the registers of the stopped child with the given pid.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn trace_set_regs(
+        &self,
+        pid: Pid,
+        regs: &TraceRegs,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<(), ()> {
+        let mut parent_guard = self.wait_guard();
+        let mut np = self.stopped_child(pid, &mut parent_guard, ctx)?;
+        // SAFETY: the child is stopped, and it cannot be scheduled while we hold its lock.
+        let data = unsafe { np.deref_mut_data() };
+        // SAFETY: trap_frame is a valid pointer according to the invariants of Proc.
+        regs.write(unsafe { &mut *data.trap_frame });
+        Ok(())
+    }
+
+    /// Set or clear a hardware trigger of the stopped child with the given pid.
+    /// Returns Ok(()) on success, Err(()) if the CPU does not have the trigger or cannot match
+    /// its kind, or on error.
+    pub fn trace_set_trigger(
+        &self,
+        pid: Pid,
+        trigger: &Trigger,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<(), ()> {
+        if trigger.index as usize >= TargetArch::ntrigger()
+            || trigger.kind & !(TRIGGER_EXEC | TRIGGER_LOAD | TRIGGER_STORE) != 0
+        {
+            return Err(());
+        }
+        let mut parent_guard = self.wait_guard();
+        let mut np = self.stopped_child(pid, &mut parent_guard, ctx)?;
+        // SAFETY: the child is stopped, and it cannot be scheduled while we hold its lock.
+        let data = unsafe { np.deref_mut_data() };
+        data.triggers[trigger.index as usize] = *trigger;
+        Ok(())
+    }
+
+    /// Stop the current process at a breakpoint or a hardware trigger,
+    /// and sleep until the parent resumes it or it is killed.
+    /// Returns false if the process is not traced.
+    pub fn trace_breakpoint(&self, ctx: &mut KernelCtx<'id, '_>) -> bool {
+        if !ctx.proc().lock().deref_info().trace.traced {
+            return false;
+        }
+        self.trace_stop(TraceEvent::Breakpoint, ctx);
+        true
+    }
+
     /// Exit the current process.  Does not return.
     /// An exited process remains in the zombie state
     /// until its parent calls wait().
@@ -428,6 +1424,7 @@ impl<'id, 's> ProcsRef<'id, 's> {
                 f.free(ctx);
             }
         }
+        ctx.kernel().fs().reap(ctx);
 
         let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
         // SAFETY:
@@ -490,6 +1487,10 @@ unsafe fn forkret() -> ! {
         // regular process (e.g., because it calls sleep), and thus cannot
         // be run from main().
         ctx.kernel().fs().init(ROOTDEV, &ctx);
+        // The other processes inherit the configuration from the initial process.
+        if ptr::eq(&***ctx.proc(), ctx.kernel().ps().initial_proc()) {
+            let _ = ctx.apply_config(&ctx.read_config());
+        }
         unsafe { ctx.user_trap_ret() }
     };
 
@@ -498,7 +1499,18 @@ unsafe fn forkret() -> ! {
 
 impl<'id, 's> ProcIter<'id, 's> {
     fn new(procs: &ProcsRef<'id, 's>) -> Self {
-        Self(procs.0.brand(procs.0.get_ref().process_pool.iter()))
+        let this = procs.0.get_ref();
+        let _guard = this.chunk_lock.lock();
+        let chunk = this.chunks.load(Ordering::Relaxed);
+        // SAFETY: invariant of `Procs`.
+        if let Some(chunk) = unsafe { chunk.as_ref() } {
+            let _ = chunk.pins.fetch_add(1, Ordering::Relaxed);
+        }
+        Self {
+            procs: procs.0.brand(this),
+            chunk,
+            index: 0,
+        }
     }
 }
 
@@ -506,7 +1518,24 @@ impl<'id, 'a> Iterator for ProcIter<'id, 'a> {
     type Item = ProcRef<'id, 'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|inner| ProcRef(self.0.brand(inner)))
+        loop {
+            // SAFETY: invariant.
+            let chunk = unsafe { self.chunk.as_ref() }?;
+            if let Some(p) = chunk.procs.get(self.index) {
+                self.index += 1;
+                return Some(ProcRef(self.procs.brand(p)));
+            }
+            self.chunk = self.procs.pin_next(chunk);
+            self.index = 0;
+        }
+    }
+}
+
+impl Drop for ProcIter<'_, '_> {
+    fn drop(&mut self) {
+        if !self.chunk.is_null() {
+            self.procs.unpin(self.chunk, &self.procs.chunk_lock.lock());
+        }
     }
 }
 
@@ -527,25 +1556,163 @@ impl<'id, 's> KernelRef<'id, 's> {
         // SAFETY: this function never moves to another CPU.
         let cpu = unsafe { hal().get_ref().cpus().current_unchecked() };
         cpu.set_proc(ptr::null_mut());
+        let id = cpuid();
+        let mut tickless = false;
         loop {
             // Avoid deadlock by ensuring that devices can interrupt.
             unsafe { TargetArch::intr_on() };
 
-            for p in self.procs().process_pool() {
-                let mut guard = p.lock();
-                if guard.state() == Procstate::RUNNABLE {
+            // Count the events chosen by perf() from the next run on.
+            perf::sync_events();
+
+            // Run the processes of the highest class that has a runnable one.
+            // Throttled processes that used up their share of the window are skipped.
+            // Of the deadline class, only the process of the earliest deadline runs, if it has
+            // not used up its budget.
+            let now = clock::uptime();
+            let earliest = self
+                .procs()
+                .process_pool()
+                .filter_map(|p| {
+                    let mut guard = p.lock();
+                    let runnable = guard.state() == Procstate::RUNNABLE;
+                    let info = guard.deref_mut_info();
+                    if !runnable
+                        || info.sched_class() != SchedClass::Deadline
+                        || info.gang != self.gangs().active()
+                        || !info.prefers(id)
+                        || info.depleted(now)
+                    {
+                        return None;
+                    }
+                    Some(info.due())
+                })
+                .min();
+            let mut ran = false;
+            let mut throttled = false;
+            let mut nrunnable = 0;
+            for (i, class) in SchedClass::ALL.iter().enumerate() {
+                for p in self.procs().process_pool() {
+                    let mut guard = p.lock();
+                    if guard.state() != Procstate::RUNNABLE {
+                        continue;
+                    }
+                    if i == 0 {
+                        nrunnable += 1;
+                    }
+                    if guard.deref_info().sched_class() != *class {
+                        continue;
+                    }
+                    if guard.deref_mut_info().throttled(TargetArch::r_cycle()) {
+                        throttled = true;
+                        continue;
+                    }
+                    // A member of a gang runs only in the slot of its gang, and a process in
+                    // no gang only outside the slots of the gangs.
+                    if guard.deref_info().gang != self.gangs().active() {
+                        continue;
+                    }
+                    if !guard.deref_info().prefers(id) {
+                        continue;
+                    }
+                    if *class == SchedClass::Deadline {
+                        let info = guard.deref_mut_info();
+                        if info.depleted(now) {
+                            throttled = true;
+                            continue;
+                        }
+                        if Some(info.due()) != earliest {
+                            continue;
+                        }
+                    }
+
+                    if tickless {
+                        TargetArch::timer_resume();
+                        tickless = false;
+                    }
+
                     // Switch to chosen process.  It is the process's job
                     // to release its lock and then reacquire it
                     // before jumping back to us.
-                    guard.deref_mut_info().state = Procstate::RUNNING;
+                    let info = guard.deref_mut_info();
+                    if info.cpu != id && info.cpu != NCPU {
+                        cpu.count_migration();
+                    }
+                    info.cpu = id;
+                    info.state = Procstate::RUNNING;
+                    self.memory().sync_kstacks();
                     cpu.set_proc(p.deref());
+                    cpu.switch_begin();
+                    if ktrace::enabled() {
+                        let next = guard.trace_task();
+                        ktrace::record(
+                            0,
+                            Event::Switch {
+                                prev: Task::IDLE,
+                                prev_state: b'R',
+                                next,
+                            },
+                        );
+                    }
+                    let start = Cycles::read();
+                    let start_us = clock::uptime();
+                    let perf_start = PerfCounts::read();
+                    guard.deref_mut_info().run_start = start;
+                    // SAFETY: the process is not running, and we hold its lock.
+                    unsafe { guard.deref_mut_data().perf_start = perf_start };
                     unsafe { swtch(cpu.context_raw_mut(), &mut guard.deref_mut_data().context) };
+                    let run = Cycles::read() - start;
+                    let perf_run = PerfCounts::read() - perf_start;
+                    cpu.switch_end(true);
+                    cpu.add_busy(run.cycle);
+                    let info = guard.deref_mut_info();
+                    info.throttle_used += run.cycle;
+                    if info.class == SchedClass::Deadline {
+                        info.reservation.used += clock::uptime().saturating_sub(start_us);
+                    }
+                    info.run_cycles = info.run_cycles + run;
+                    // SAFETY: the process has switched back to us, and we hold its lock.
+                    let data = unsafe { guard.deref_mut_data() };
+                    data.perf_counts = data.perf_counts + perf_run;
+                    if ktrace::enabled() {
+                        let prev = guard.trace_task();
+                        ktrace::record(
+                            guard.deref_info().pid,
+                            Event::Switch {
+                                prev,
+                                prev_state: guard.state().trace_state(),
+                                next: Task::IDLE,
+                            },
+                        );
+                    }
 
                     // Process is done running for now.
                     // It should have changed its p->state before coming back.
                     cpu.set_proc(ptr::null_mut());
+                    ran = true;
+                }
+                if ran {
+                    break;
                 }
             }
+            cpu.update_load(nrunnable);
+
+            if ran {
+                continue;
+            }
+            if self.power().idle_policy() == IdlePolicy::Suspend {
+                // A suspended CPU wakes up at its next tick to look for a process to run.
+                if tickless {
+                    TargetArch::timer_resume();
+                    tickless = false;
+                }
+                self.power().suspend();
+            } else if !tickless && !throttled && id != 0 {
+                // Skip the ticks of this CPU while it has nothing to run. CPU 0 keeps ticking,
+                // since it counts the ticks that sleep() and uptime() rely on.
+                TargetArch::timer_stop();
+                tickless = true;
+            }
         }
     }
 
@@ -562,16 +1729,24 @@ impl<'id, 's> KernelRef<'id, 's> {
             let info = p.info.get_mut_raw();
             let state = unsafe { &(*info).state };
             if *state != Procstate::UNUSED {
-                let name = unsafe { &(*p.data.get()).name };
-                // For null character recognization.
-                // Required since str::from_utf8 cannot recognize interior null characters.
-                let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                let data = unsafe { &*p.data.get() };
+                let cwd = data
+                    .cwd_path()
+                    .map_or("?", |path| str::from_utf8(path).unwrap_or("???"));
                 self.as_ref().write_fmt(format_args!(
-                    "{} {} {}",
+                    "{} {} {} {}",
                     unsafe { (*info).pid },
                     Procstate::as_str(state),
-                    str::from_utf8(&name[0..length]).unwrap_or("???")
+                    str::from_utf8(data.title()).unwrap_or("???"),
+                    cwd
                 ));
+                // The arguments of exec(), separated by spaces.
+                if let Some((_, args)) = data.args().split_last() {
+                    for arg in args.split(|&c| c == 0) {
+                        self.as_ref()
+                            .write_fmt(format_args!(" {}", str::from_utf8(arg).unwrap_or("???")));
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,119 @@
+//! Minimal process tracing.
+//!
+//! A process calls `ptrace(PTRACE_TRACEME)` to be traced by its parent, and stops right away.
+//! The parent waits for a traced child to stop with `PTRACE_WAIT`. While the child is stopped,
+//! the parent can read and write its registers and memory, and resume it with `PTRACE_CONT`, or
+//! with `PTRACE_SYSCALL` to stop it again at the next system call entry or exit.
+//!
+//! The parent can also set hardware breakpoints and watchpoints in the child with
+//! `PTRACE_SETTRIGGER`, which the CPU raises before the instruction at the address runs, or
+//! before it is loaded from or stored to. The child then stops for `TraceEvent::Breakpoint`, as
+//! it does at an `ebreak`. Since the trigger fires again when the instruction is retried, the
+//! parent must clear or move the trigger, or move the pc past an `ebreak`, before resuming it.
+//! The triggers of a process are loaded into the CPU whenever it returns to user space.
+//!
+//! Single-stepping is not supported, since RISC-V supervisor mode cannot single-step user code.
+
+use array_macro::array;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    arch::interface::{ProcManager, TrapFrameManager},
+    arch::TargetArch,
+};
+
+/// Why a traced process is stopped.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TraceEvent {
+    /// The process has just become traced.
+    Start = 1,
+
+    /// The process is about to run a system call.
+    SyscallEntry = 2,
+
+    /// The process has run a system call.
+    SyscallExit = 3,
+
+    /// The process has hit a breakpoint or a hardware trigger.
+    Breakpoint = 4,
+}
+
+/// Kinds of accesses a hardware trigger matches, which can be combined. A trigger of no kind is
+/// cleared.
+pub const TRIGGER_EXEC: u32 = 1;
+pub const TRIGGER_LOAD: u32 = 2;
+pub const TRIGGER_STORE: u32 = 4;
+
+/// Proc::info's spinlock must be held when using these.
+pub struct Trace {
+    /// Is the process traced by its parent?
+    pub traced: bool,
+
+    /// Why the process is stopped, if it is.
+    pub stop: Option<TraceEvent>,
+}
+
+/// Registers of a traced process, read by `PTRACE_GETREGS` and written by `PTRACE_SETREGS`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct TraceRegs {
+    /// User program counter
+    pub pc: usize,
+
+    /// User stack pointer
+    pub sp: usize,
+
+    /// Function argument registers. The last one holds the system call number.
+    pub args: [usize; 8],
+}
+
+/// A hardware breakpoint or watchpoint of a traced process, written by `PTRACE_SETTRIGGER`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Trigger {
+    /// User address to match
+    pub addr: usize,
+
+    /// Which trigger of the process this is
+    pub index: u32,
+
+    /// Kinds of accesses to match, or 0 to clear the trigger
+    pub kind: u32,
+}
+
+impl Trace {
+    pub const fn new() -> Self {
+        Self {
+            traced: false,
+            stop: None,
+        }
+    }
+}
+
+impl Trigger {
+    pub const fn new() -> Self {
+        Self {
+            addr: 0,
+            index: 0,
+            kind: 0,
+        }
+    }
+}
+
+impl TraceRegs {
+    pub fn read(trap_frame: &<TargetArch as ProcManager>::TrapFrame) -> Self {
+        Self {
+            pc: trap_frame.get_pc(),
+            sp: trap_frame.get_sp(),
+            args: array![i => trap_frame.get_param_reg(i.into()); 8],
+        }
+    }
+
+    pub fn write(&self, trap_frame: &mut <TargetArch as ProcManager>::TrapFrame) {
+        trap_frame.set_pc(self.pc);
+        trap_frame.set_sp(self.sp);
+        for (i, arg) in self.args.iter().enumerate() {
+            *trap_frame.param_reg_mut(i.into()) = *arg;
+        }
+    }
+}
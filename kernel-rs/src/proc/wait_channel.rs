@@ -26,7 +26,29 @@ impl WaitChannel {
         // so it's okay to release lk.
 
         //DOC: sleeplock1
-        let mut guard = ctx.proc().lock();
+        self.sleep_locked(lock_guard, ctx.proc().lock());
+    }
+
+    /// Like `sleep`, for a caller that has no `KernelCtx` at hand, such as a lock.
+    /// There must be a current process.
+    pub fn sleep_current<R: RawLock, T>(
+        &self,
+        lock_guard: &mut Guard<'_, R, T>,
+        kernel: KernelRef<'_, '_>,
+    ) {
+        // SAFETY: the process is the current process of this CPU, which stays alive while it
+        // sleeps.
+        let proc = unsafe { kernel.current_proc().as_ref() }.expect("sleep_current");
+        self.sleep_locked(lock_guard, ProcRef(kernel.brand(proc)).lock());
+    }
+
+    /// Releases the lock of `lock_guard` and sleeps on waitchannel, with `guard` holding the lock
+    /// of the current process. Reacquires the lock when awakened.
+    fn sleep_locked<R: RawLock, T>(
+        &self,
+        lock_guard: &mut Guard<'_, R, T>,
+        mut guard: ProcGuard<'_, '_>,
+    ) {
         // Release the lock while we sleep on the waitchannel, and reacquire after the process wakes up.
         lock_guard.reacquire_after(move || {
             // Go to sleep.
@@ -46,6 +68,51 @@ impl WaitChannel {
         });
     }
 
+    /// Like `sleep`, but returns Err(()) without sleeping if the process has been killed.
+    ///
+    /// Err(()) plays the role of EINTR. A system call interrupted this way returns the amount
+    /// it has already transferred if it is not zero, and fails otherwise. The killed process
+    /// then exits on its way back to user space.
+    pub fn sleep_killable<R: RawLock, T>(
+        &self,
+        lock_guard: &mut Guard<'_, R, T>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        if ctx.proc().killed() {
+            return Err(());
+        }
+        self.sleep(lock_guard, ctx);
+        Ok(())
+    }
+
+    /// Sleeps on waitchannel as long as `cond` holds for the data of the lock.
+    /// `cond` is checked while holding the lock, and the lock is released only while sleeping,
+    /// so a wakeup right after the check is not lost.
+    pub fn wait_while<R: RawLock, T, F: FnMut(&T) -> bool>(
+        &self,
+        lock_guard: &mut Guard<'_, R, T>,
+        mut cond: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        while cond(lock_guard) {
+            self.sleep(lock_guard, ctx);
+        }
+    }
+
+    /// Like `wait_while`, but returns Err(()) if the process gets killed while `cond` holds.
+    /// Returns Ok(()) once `cond` does not hold.
+    pub fn wait_while_killable<R: RawLock, T, F: FnMut(&T) -> bool>(
+        &self,
+        lock_guard: &mut Guard<'_, R, T>,
+        mut cond: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), ()> {
+        while cond(lock_guard) {
+            self.sleep_killable(lock_guard, ctx)?;
+        }
+        Ok(())
+    }
+
     /// Wake up all processes sleeping on waitchannel.
     /// Must be called without any p->lock.
     pub fn wakeup(&self, kernel: KernelRef<'_, '_>) {
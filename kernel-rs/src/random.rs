@@ -0,0 +1,66 @@
+//! The kernel's entropy pool.
+//!
+//! The pool is stirred with the cycle counter at every device interrupt and every time random
+//! bytes are taken from it, so its output depends on the timing of events the user cannot see.
+//! It is good enough for stack canaries, but not for cryptography.
+//!
+//! Under qemu, the kernel also seeds the pool at boot with the random bytes of the virtio entropy
+//! device, so that its output does not depend on timing alone.
+
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{arch::interface::TimeManager, arch::TargetArch};
+
+pub struct Entropy {
+    /// The mixed state of everything added so far.
+    pool: AtomicUsize,
+
+    /// Number of words taken from the pool, so no two of them are the same.
+    count: AtomicUsize,
+}
+
+/// Scrambles the bits of `x` (the finalizer of SplitMix64).
+fn mix(x: usize) -> usize {
+    let x = x as u64;
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (x ^ (x >> 31)) as usize
+}
+
+impl Entropy {
+    pub const fn new() -> Self {
+        Self {
+            pool: AtomicUsize::new(0x9e3779b97f4a7c15),
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Stirs `value` into the pool.
+    pub fn add(&self, value: usize) {
+        let _ = self
+            .pool
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pool| {
+                Some(mix(pool ^ value))
+            });
+    }
+
+    /// Stirs the random bytes of `seed` into the pool.
+    pub fn seed(&self, seed: &[u8]) {
+        for chunk in seed.chunks(mem::size_of::<usize>()) {
+            let mut word = [0; mem::size_of::<usize>()];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.add(usize::from_ne_bytes(word));
+        }
+    }
+
+    /// Fills `buf` with random bytes.
+    pub fn fill(&self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(mem::size_of::<usize>()) {
+            self.add(TargetArch::r_cycle());
+            let count = self.count.fetch_add(1, Ordering::Relaxed);
+            let word = mix(self.pool.load(Ordering::Relaxed) ^ mix(count));
+            chunk.copy_from_slice(&word.to_ne_bytes()[..chunk.len()]);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Resource limits.
+//!
+//! `getrlimit()` and `setrlimit()` get and set the limits of the current process, which a child
+//! inherits on fork() and keeps across exec(). Each limit is a soft limit, which the kernel
+//! enforces, and a hard limit, the ceiling of the soft limit. As every process of rv6 is
+//! privileged, a process may raise its hard limit as well.
+//!
+//! The only resource is `RLIMIT_MSGQUEUE`, the bytes of messages the message queues may hold.
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{param::MSGQUEUE_LIMIT, proc::KernelCtx, syscall::SyscallTable};
+
+/// Resources of `getrlimit()` and `setrlimit()`.
+const RLIMIT_MSGQUEUE: i32 = 12;
+
+/// `struct rlimit`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct RLimit {
+    /// The soft limit
+    pub cur: u64,
+
+    /// The hard limit
+    pub max: u64,
+}
+
+/// The limits of a process. `RLIM_INFINITY`, the largest value, is never reached.
+#[derive(Copy, Clone)]
+pub struct RLimits {
+    /// Bytes of messages the message queues may hold, as charged to the process creating a
+    /// queue.
+    pub msgqueue: RLimit,
+}
+
+impl RLimits {
+    pub const fn new() -> Self {
+        Self {
+            msgqueue: RLimit {
+                cur: MSGQUEUE_LIMIT as u64,
+                max: MSGQUEUE_LIMIT as u64,
+            },
+        }
+    }
+
+    fn get_mut(&mut self, resource: i32) -> Result<&mut RLimit, ()> {
+        match resource {
+            RLIMIT_MSGQUEUE => Ok(&mut self.msgqueue),
+            _ => Err(()),
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Store the limit of the current process on resource at the struct rlimit at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_getrlimit(&mut self) -> Result<usize, ()> {
+        let resource = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?;
+        let limit = *self.proc_mut().deref_mut_data().rlimits.get_mut(resource)?;
+        self.proc_mut().memory_mut().copy_out(addr.into(), &limit)?;
+        Ok(0)
+    }
+
+    /// Set the limit of the current process on resource to the struct rlimit at addr, whose
+    /// soft limit must not exceed its hard limit.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_setrlimit(&mut self) -> Result<usize, ()> {
+        let resource = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?;
+        let mut limit = RLimit::default();
+        // SAFETY: RLimit does not have any internal structure.
+        unsafe {
+            self.proc_mut()
+                .memory_mut()
+                .copy_in(&mut limit, addr.into())
+        }?;
+        if limit.cur > limit.max {
+            return Err(());
+        }
+        *self.proc_mut().deref_mut_data().rlimits.get_mut(resource)? = limit;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of resource limits.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(99, |ctx| ctx.sys_getrlimit());
+    table.register(100, |ctx| ctx.sys_setrlimit());
+}
@@ -0,0 +1,264 @@
+//! POSIX named semaphores.
+//!
+//! A semaphore is a counter that processes take with `sem_wait()`, which waits while the counter
+//! is 0, and give back with `sem_post()`. Like a message queue, a semaphore has a name, such as
+//! "/bench", by which unrelated processes open it with `sem_open()`, and lives until it is
+//! unlinked with `sem_unlink()` and its last descriptor is closed. A process refers to an open
+//! semaphore by a file descriptor, which is inherited on fork() and closed with close().
+
+use core::{ops::Deref, ptr::NonNull};
+
+use array_macro::array;
+
+use crate::{
+    file::FileType,
+    fs::FcntlFlags,
+    lock::{SleepableLock, SpinLock},
+    mqueue::check_name,
+    param::{IPCNAMEMAX, MAXPATH, NSEM},
+    proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// The largest value of a semaphore.
+const SEM_VALUE_MAX: u32 = i32::MAX as u32;
+
+struct SemaphoreInner {
+    name: [u8; IPCNAMEMAX],
+    namelen: usize,
+
+    /// The name still refers to this semaphore.
+    linked: bool,
+
+    /// Number of open descriptions of this semaphore.
+    nopen: usize,
+
+    value: u32,
+}
+
+pub struct Semaphore {
+    inner: SleepableLock<SemaphoreInner>,
+}
+
+pub struct SemaphoreTable {
+    /// Held while looking up a semaphore by name, so that a name refers to at most one semaphore.
+    lock: SpinLock<()>,
+    sems: [Semaphore; NSEM],
+}
+
+/// # Safety
+///
+/// `ptr` always refers to a semaphore in the kernel's `SemaphoreTable`, whose `nopen` counts this
+/// `AllocatedSemaphore`. The semaphore is freed only after every `AllocatedSemaphore` is closed.
+pub struct AllocatedSemaphore {
+    ptr: NonNull<Semaphore>,
+}
+
+// `AllocatedSemaphore` is `Send` because we access `SemaphoreInner` only after acquring a lock
+// and because `AllocatedSemaphore` does not point to thread-local data.
+unsafe impl Send for AllocatedSemaphore {}
+
+impl Deref for AllocatedSemaphore {
+    type Target = Semaphore;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `ptr` always refers to a semaphore in the kernel's `SemaphoreTable`.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl SemaphoreInner {
+    const fn new() -> Self {
+        Self {
+            name: [0; IPCNAMEMAX],
+            namelen: 0,
+            linked: false,
+            nopen: 0,
+            value: 0,
+        }
+    }
+
+    fn name(&self) -> &[u8] {
+        &self.name[..self.namelen]
+    }
+}
+
+impl Semaphore {
+    const fn new() -> Self {
+        Self {
+            inner: SleepableLock::new("semaphore", SemaphoreInner::new()),
+        }
+    }
+}
+
+impl SemaphoreTable {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLock::new("semaphores", ()),
+            sems: array![_ => Semaphore::new(); NSEM],
+        }
+    }
+
+    /// Opens the semaphore of `name`. If there is none and `value` is given, creates one whose
+    /// value is `value`.
+    fn open(&self, name: &[u8], value: Option<u32>) -> Result<AllocatedSemaphore, ()> {
+        let _guard = self.lock.lock();
+        let mut free = None;
+        for sem in &self.sems {
+            let mut inner = sem.inner.lock();
+            if inner.linked && inner.name() == name {
+                inner.nopen += 1;
+                return Ok(AllocatedSemaphore {
+                    ptr: NonNull::from(sem),
+                });
+            }
+            if !inner.linked && inner.nopen == 0 && free.is_none() {
+                free = Some(sem);
+            }
+        }
+
+        let value = value.ok_or(())?;
+        let sem = free.ok_or(())?;
+        let mut inner = sem.inner.lock();
+        inner.name[..name.len()].copy_from_slice(name);
+        inner.namelen = name.len();
+        inner.linked = true;
+        inner.nopen = 1;
+        inner.value = value;
+        Ok(AllocatedSemaphore {
+            ptr: NonNull::from(sem),
+        })
+    }
+
+    /// Removes `name`. The semaphore is freed once its last descriptor is closed.
+    fn unlink(&self, name: &[u8]) -> Result<(), ()> {
+        let _guard = self.lock.lock();
+        for sem in &self.sems {
+            let mut inner = sem.inner.lock();
+            if inner.linked && inner.name() == name {
+                inner.linked = false;
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+}
+
+impl AllocatedSemaphore {
+    /// Takes 1 from the semaphore, waiting while it is 0.
+    pub fn wait(&self, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let mut inner = self.inner.lock();
+        inner.wait_while_killable(|inner| inner.value == 0, ctx)?;
+        inner.value -= 1;
+        Ok(())
+    }
+
+    /// Takes 1 from the semaphore if it is not 0.
+    pub fn trywait(&self) -> Result<(), ()> {
+        let mut inner = self.inner.lock();
+        if inner.value == 0 {
+            return Err(());
+        }
+        inner.value -= 1;
+        Ok(())
+    }
+
+    /// Adds 1 to the semaphore, and wakes up the processes waiting for it.
+    pub fn post(&self, ctx: &KernelCtx<'_, '_>) -> Result<(), ()> {
+        let mut inner = self.inner.lock();
+        if inner.value == SEM_VALUE_MAX {
+            return Err(());
+        }
+        inner.value += 1;
+        inner.wakeup(ctx.kernel());
+        Ok(())
+    }
+
+    pub fn value(&self) -> u32 {
+        self.inner.lock().value
+    }
+
+    pub fn close(self) {
+        self.inner.lock().nopen -= 1;
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Open the semaphore name. With O_CREATE in oflag, creates the semaphore if it does not
+    /// exist, with value as its value.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    pub fn sys_sem_open(&mut self) -> Result<usize, ()> {
+        let mut name: [u8; MAXPATH] = [0; MAXPATH];
+        let name = self.proc_mut().argstr(0, &mut name)?.to_bytes();
+        let omode = FcntlFlags::from_bits(self.proc().argint(1)?).ok_or(())?;
+        let value = self.proc().argint(2)? as u32;
+        check_name(name)?;
+        if value > SEM_VALUE_MAX {
+            return Err(());
+        }
+
+        let value = Some(value).filter(|_| omode.contains(FcntlFlags::O_CREATE));
+        let sem = self.kernel().sems().open(name, value)?;
+        let ptr = sem.ptr;
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Semaphore { sem }, true, true, self)
+            .map_err(|_| AllocatedSemaphore { ptr }.close())?;
+        let fd = f.fdalloc(self)?;
+        if let Some(entry) = &mut self.proc_mut().deref_mut_data().open_files[fd as usize] {
+            entry.cloexec = omode.contains(FcntlFlags::O_CLOEXEC);
+        }
+        Ok(fd as usize)
+    }
+
+    /// Remove the semaphore name. The semaphore is freed once every descriptor of it is closed.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_sem_unlink(&mut self) -> Result<usize, ()> {
+        let mut name: [u8; MAXPATH] = [0; MAXPATH];
+        let name = self.proc_mut().argstr(0, &mut name)?.to_bytes();
+        check_name(name)?;
+        self.kernel().sems().unlink(name)?;
+        Ok(0)
+    }
+
+    /// Take 1 from the semaphore fd, waiting while it is 0.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_sem_wait(&mut self) -> Result<usize, ()> {
+        self.proc().argfd(0)?.1.semaphore()?.wait(self)?;
+        Ok(0)
+    }
+
+    /// Take 1 from the semaphore fd, or fail if it is 0.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_sem_trywait(&mut self) -> Result<usize, ()> {
+        self.proc().argfd(0)?.1.semaphore()?.trywait()?;
+        Ok(0)
+    }
+
+    /// Add 1 to the semaphore fd.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_sem_post(&mut self) -> Result<usize, ()> {
+        self.proc().argfd(0)?.1.semaphore()?.post(self)?;
+        Ok(0)
+    }
+
+    /// Store the value of the semaphore fd at the int at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_sem_getvalue(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(1)?;
+        let value = self.proc().argfd(0)?.1.semaphore()?.value() as i32;
+        self.proc_mut().memory_mut().copy_out(addr.into(), &value)?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of semaphores.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(106, |ctx| ctx.sys_sem_open());
+    table.register(107, |ctx| ctx.sys_sem_unlink());
+    table.register(108, |ctx| ctx.sys_sem_wait());
+    table.register(109, |ctx| ctx.sys_sem_trywait());
+    table.register(110, |ctx| ctx.sys_sem_post());
+    table.register(111, |ctx| ctx.sys_sem_getvalue());
+}
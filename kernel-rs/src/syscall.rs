@@ -4,25 +4,196 @@
 
 #![allow(clippy::unit_arg)]
 
-use core::{mem, str};
+use core::{cmp, mem, str};
 
 use arrayvec::ArrayVec;
 use cstr_core::CStr;
+use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
     addr::{Addr, UVAddr},
-    fs::{FcntlFlags, FileSystem, FileSystemExt, InodeType, Path},
-    file::{RcFile, SelectEvent, SeekWhence},
-    arch::TargetArch,
     arch::interface::{PowerOff, TimeManager, TrapFrameManager},
+    arch::TargetArch,
+    console::Severity,
+    cpu::cpuid,
+    file::{FileType, RcFile, SeekWhence, SelectEvent},
+    fs::{
+        AccessFlags, DefaultFs, FcntlFlags, FileSystem, FileSystemExt, InodeType, Path, RcInode,
+        AT_FDCWD,
+    },
     hal::hal,
     ok_or,
     page::{Page, PGSIZE},
-    param::{MAXARG, MAXPATH},
-    proc::{CurrentProc, KernelCtx},
+    param::{MAXARG, MAXBATCH, MAXPATH, MINTICK_US, NCPU, NMAPREGION, NSYSCALL},
+    perf,
+    proc::{Abi, CurrentProc, KernelCtx, SchedClass, TraceRegs, Trigger, WaitOptions},
     some_or,
+    vm::MapRegion,
 };
 
+/// A system call in a batch run by `batch()`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct BatchEntry {
+    /// System call number
+    num: usize,
+
+    /// Arguments of the system call
+    args: [usize; 6],
+
+    /// Result of the system call, filled by `batch()`
+    ret: usize,
+}
+
+/// A time interval of `nanosleep()` or of a timer.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct TimeSpec {
+    sec: i64,
+
+    /// Nanoseconds, less than a second
+    nsec: i64,
+}
+
+const US_PER_S: usize = 1_000_000;
+const NS_PER_US: usize = 1_000;
+
+impl TimeSpec {
+    /// Returns the interval in microseconds, rounded up.
+    /// Returns Err(()) if the interval is negative or its nanoseconds are not less than a second.
+    pub fn as_micro(&self) -> Result<usize, ()> {
+        if self.sec < 0 || self.nsec < 0 || self.nsec as usize >= US_PER_S * NS_PER_US {
+            return Err(());
+        }
+        Ok((self.sec as usize)
+            .checked_mul(US_PER_S)
+            .ok_or(())?
+            .saturating_add((self.nsec as usize + NS_PER_US - 1) / NS_PER_US))
+    }
+
+    pub fn from_micro(us: usize) -> Self {
+        Self {
+            sec: (us / US_PER_S) as i64,
+            nsec: (us % US_PER_S * NS_PER_US) as i64,
+        }
+    }
+}
+
+/// Commands of `fcntl()`.
+const F_GETFD: i32 = 1;
+const F_SETFD: i32 = 2;
+
+/// File descriptor flag of `F_GETFD` and `F_SETFD`, which closes the file descriptor on exec().
+const FD_CLOEXEC: i32 = 1;
+
+/// Advice of `madvise()`.
+const MADV_DONTNEED: i32 = 4;
+const MADV_FREE: i32 = 8;
+
+/// A system call handler.
+/// Returns Ok(return value) on success, Err(()) on error.
+pub type Syscall = fn(&mut KernelCtx<'_, '_>) -> Result<usize, ()>;
+
+/// Maps system call numbers to their handlers.
+///
+/// Each subsystem registers its own system calls with a `register_syscalls` function while the
+/// kernel is initialized, and the table is not modified afterwards.
+pub struct SyscallTable {
+    handlers: [Option<Syscall>; NSYSCALL],
+}
+
+impl SyscallTable {
+    pub const fn new() -> Self {
+        Self {
+            handlers: [None; NSYSCALL],
+        }
+    }
+
+    /// Registers `handler` as the system call `num`.
+    pub fn register(&mut self, num: usize, handler: Syscall) {
+        assert!(self.handlers[num].is_none(), "syscall registered twice");
+        self.handlers[num] = Some(handler);
+    }
+
+    fn get(&self, num: i32) -> Option<Syscall> {
+        if num < 0 {
+            return None;
+        }
+        *self.handlers.get(num as usize)?
+    }
+}
+
+/// Registers the system calls of processes, files, and the machine.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(1, |ctx| ctx.sys_fork());
+    table.register(2, |ctx| ctx.sys_exit());
+    table.register(3, |ctx| ctx.sys_wait());
+    table.register(4, |ctx| ctx.sys_pipe());
+    table.register(5, |ctx| ctx.sys_read());
+    table.register(6, |ctx| ctx.sys_kill());
+    table.register(7, |ctx| ctx.sys_exec());
+    table.register(8, |ctx| ctx.sys_fstat());
+    table.register(9, |ctx| ctx.sys_chdir());
+    table.register(10, |ctx| ctx.sys_dup());
+    table.register(11, |ctx| ctx.sys_getpid());
+    table.register(12, |ctx| ctx.sys_sbrk());
+    table.register(13, |ctx| ctx.sys_sleep());
+    table.register(14, |ctx| ctx.sys_uptime());
+    table.register(15, |ctx| ctx.sys_open());
+    table.register(16, |ctx| ctx.sys_write());
+    table.register(17, |ctx| ctx.sys_mknod());
+    table.register(18, |ctx| ctx.sys_unlink());
+    table.register(19, |ctx| ctx.sys_link());
+    table.register(20, |ctx| ctx.sys_mkdir());
+    table.register(21, |ctx| ctx.sys_close());
+    table.register(22, |ctx| ctx.sys_poweroff());
+    table.register(23, |ctx| ctx.sys_select());
+    table.register(24, |ctx| ctx.sys_getpagesize());
+    table.register(25, |ctx| ctx.sys_waitpid());
+    table.register(26, |ctx| ctx.sys_getppid());
+    table.register(27, |ctx| ctx.sys_lseek());
+    table.register(28, |ctx| ctx.sys_clock());
+    table.register(29, |ctx| ctx.sys_uptime_as_micro());
+    table.register(30, |ctx| ctx.sys_defrag());
+    table.register(31, |ctx| ctx.sys_snapshot());
+    table.register(32, |ctx| ctx.sys_snapdrop());
+    table.register(33, |ctx| ctx.sys_snapread());
+    table.register(34, |ctx| ctx.sys_snapstat());
+    table.register(35, |ctx| ctx.sys_getdents());
+    table.register(39, |ctx| ctx.sys_linkat());
+    table.register(40, |ctx| ctx.sys_sendfile());
+    table.register(41, |ctx| ctx.sys_pmap());
+    table.register(42, |ctx| ctx.sys_ptrace());
+    table.register(43, |ctx| ctx.sys_batch());
+    table.register(46, |ctx| ctx.sys_yield());
+    table.register(47, |ctx| ctx.sys_gettid());
+    table.register(48, |ctx| ctx.sys_schedstat());
+    table.register(49, |ctx| ctx.sys_freeze());
+    table.register(50, |ctx| ctx.sys_thaw());
+    table.register(51, |ctx| ctx.sys_setsched());
+    table.register(52, |ctx| ctx.sys_getsched());
+    table.register(54, |ctx| ctx.sys_settick());
+    table.register(55, |ctx| ctx.sys_gettick());
+    table.register(57, |ctx| ctx.sys_reboot());
+    table.register(61, |ctx| ctx.sys_openat());
+    table.register(62, |ctx| ctx.sys_mkdirat());
+    table.register(63, |ctx| ctx.sys_unlinkat());
+    table.register(64, |ctx| ctx.sys_stat());
+    table.register(65, |ctx| ctx.sys_lstat());
+    table.register(66, |ctx| ctx.sys_access());
+    table.register(67, |ctx| ctx.sys_ftruncate());
+    table.register(68, |ctx| ctx.sys_truncate());
+    table.register(69, |ctx| ctx.sys_fcntl());
+    table.register(70, |ctx| ctx.sys_umask());
+    table.register(72, |ctx| ctx.sys_getcwd());
+    table.register(76, |ctx| ctx.sys_nanosleep());
+    table.register(112, |ctx| ctx.sys_brk());
+    table.register(113, |ctx| ctx.sys_madvise());
+    table.register(117, |ctx| ctx.sys_migrate());
+    table.register(119, |ctx| ctx.sys_setdeadline());
+    table.register(120, |ctx| ctx.sys_dlstat());
+}
+
 impl CurrentProc<'_, '_> {
     /// Fetch the usize at addr from the current process.
     /// Returns Ok(fetched integer) on success, Err(()) on error.
@@ -74,61 +245,58 @@ impl CurrentProc<'_, '_> {
 
     /// Fetch the nth word-sized system call argument as a file descriptor
     /// and return both the descriptor and the corresponding struct file.
-    fn argfd(&self, n: usize) -> Result<(i32, &RcFile), ()> {
+    pub fn argfd(&self, n: usize) -> Result<(i32, &RcFile), ()> {
         let fd = self.argint(n)?;
-        let f = self
+        let entry = self
             .deref_data()
             .open_files
             .get(fd as usize)
             .ok_or(())?
             .as_ref()
             .ok_or(())?;
-        Ok((fd, f))
+        Ok((fd, &entry.file))
+    }
+
+    /// Fetch the nth word-sized system call argument as a directory file descriptor.
+    /// Returns Ok(None) if it is AT_FDCWD, Ok(Some(inode of the file)) if it is an open
+    /// inode file, and Err(()) otherwise.
+    pub fn argdirfd(&self, n: usize) -> Result<Option<&RcInode<DefaultFs>>, ()> {
+        if self.argint(n)? == AT_FDCWD {
+            return Ok(None);
+        }
+        let (_, f) = self.argfd(n)?;
+        match &f.typ {
+            FileType::Inode { inner } => Ok(Some(&inner.ip)),
+            _ => Err(()),
+        }
     }
 }
 
 impl KernelCtx<'_, '_> {
     pub fn syscall(&mut self, num: i32) -> Result<usize, ()> {
-        match num {
-            1 => self.sys_fork(),
-            2 => self.sys_exit(),
-            3 => self.sys_wait(),
-            4 => self.sys_pipe(),
-            5 => self.sys_read(),
-            6 => self.sys_kill(),
-            7 => self.sys_exec(),
-            8 => self.sys_fstat(),
-            9 => self.sys_chdir(),
-            10 => self.sys_dup(),
-            11 => self.sys_getpid(),
-            12 => self.sys_sbrk(),
-            13 => self.sys_sleep(),
-            14 => self.sys_uptime(),
-            15 => self.sys_open(),
-            16 => self.sys_write(),
-            17 => self.sys_mknod(),
-            18 => self.sys_unlink(),
-            19 => self.sys_link(),
-            20 => self.sys_mkdir(),
-            21 => self.sys_close(),
-            22 => self.sys_poweroff(),
-            23 => self.sys_select(),
-            24 => self.sys_getpagesize(),
-            25 => self.sys_waitpid(),
-            26 => self.sys_getppid(),
-            27 => self.sys_lseek(),
-            28 => self.sys_clock(),
-            29 => self.sys_uptime_as_micro(),
-            _ => {
-                self.kernel().as_ref().write_fmt(format_args!(
-                    "{} {}: unknown sys call {}",
-                    self.proc().pid(),
-                    str::from_utf8(&self.proc().deref_data().name).unwrap_or("???"),
-                    num
-                ));
+        let (syscalls, begin) = match self.proc().deref_data().abi {
+            Abi::Rv6 => (self.kernel().syscalls(), perf::latency_begin()),
+            Abi::Linux => (self.kernel().linux_syscalls(), None),
+        };
+        let res = match syscalls.get(num) {
+            Some(handler) => handler(self),
+            None => {
+                self.kernel().as_ref().log(
+                    Severity::Warning,
+                    format_args!(
+                        "{} {}: unknown sys call {}\n",
+                        self.proc().pid(),
+                        str::from_utf8(&self.proc().deref_data().name).unwrap_or("???"),
+                        num
+                    ),
+                );
                 Err(())
             }
-        }
+        };
+        // Finalize the inodes of the files closed by the system call.
+        self.kernel().fs().reap(self);
+        perf::latency_end(num, begin);
+        res
     }
 
     /// Terminate the current process; status reported to wait(). No return.
@@ -159,7 +327,54 @@ impl KernelCtx<'_, '_> {
     /// Returns Ok(start of new memory) on success, Err(()) on error.
     pub fn sys_sbrk(&mut self) -> Result<usize, ()> {
         let n = self.proc().argint(0)?;
-        self.proc_mut().memory_mut().resize(n, hal().kmem())
+        let mut res = self.proc_mut().memory_mut().resize(n, hal().kmem());
+        // Retry with the lazily freed pages of the process freed.
+        if res.is_err() && self.proc_mut().memory_mut().reclaim(hal().kmem()) {
+            res = self.proc_mut().memory_mut().resize(n, hal().kmem());
+        }
+        // Retry with the pages of the kernel stacks kept for reuse.
+        if res.is_err() && self.kernel().memory().drain_kstacks(hal().kmem()) {
+            res = self.proc_mut().memory_mut().resize(n, hal().kmem());
+        }
+        if res.is_err() {
+            self.kernel().procs().out_of_memory(self);
+        }
+        res
+    }
+
+    /// Set the end of the process’s memory to addr, growing or shrinking the heap.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_brk(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let mut res = self.proc_mut().memory_mut().brk(addr, hal().kmem());
+        let grow = addr > self.proc().memory().size();
+        // Retry with the lazily freed pages of the process freed.
+        if res.is_err() && grow && self.proc_mut().memory_mut().reclaim(hal().kmem()) {
+            res = self.proc_mut().memory_mut().brk(addr, hal().kmem());
+        }
+        // Retry with the pages of the kernel stacks kept for reuse.
+        if res.is_err() && grow && self.kernel().memory().drain_kstacks(hal().kmem()) {
+            res = self.proc_mut().memory_mut().brk(addr, hal().kmem());
+        }
+        res.map(|_| 0)
+    }
+
+    /// Advise how the pages from addr, which must be page-aligned, to addr + len will be used.
+    /// With MADV_DONTNEED, they are freed, and read as zeros after that. With MADV_FREE, they
+    /// are freed when memory runs short, unless they are written before.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_madvise(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let len = self.proc().argaddr(1)?;
+        let lazy = match self.proc().argint(2)? {
+            MADV_DONTNEED => false,
+            MADV_FREE => true,
+            _ => return Err(()),
+        };
+        self.proc_mut()
+            .memory_mut()
+            .madvise(addr, len, lazy, hal().kmem())?;
+        Ok(0)
     }
 
     /// Pause for n clock ticks.
@@ -170,15 +385,37 @@ impl KernelCtx<'_, '_> {
 
         let mut ticks = self.kernel().ticks().lock();
         let ticks0 = *ticks;
-        while ticks.wrapping_sub(ticks0) < n as u32 {
-            if self.proc().killed() {
-                return Err(());
-            }
-            ticks.sleep(self);
-        }
+        ticks.wait_while_killable(|ticks| ticks.wrapping_sub(ticks0) < n as u32, self)?;
         Ok(0)
     }
 
+    /// Pause for the interval of the struct timespec at req, rounded up to microseconds.
+    /// The process wakes up at the first clock tick after the deadline, so a change of the tick
+    /// interval while it sleeps does not shift the deadline. If the process is killed while it
+    /// sleeps, the remaining time is stored at rem unless rem is 0.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_nanosleep(&mut self) -> Result<usize, ()> {
+        let req = self.proc().argaddr(0)?;
+        let rem = self.proc().argaddr(1)?;
+        let mut ts = TimeSpec::default();
+        // SAFETY: TimeSpec does not have any internal structure.
+        unsafe { self.proc_mut().memory_mut().copy_in(&mut ts, req.into()) }?;
+        let deadline = TargetArch::uptime_as_micro()?.saturating_add(ts.as_micro()?);
+
+        let mut ticks = self.kernel().ticks().lock();
+        let result = ticks.wait_while_killable(
+            |_| TargetArch::uptime_as_micro().map_or(false, |now| now < deadline),
+            self,
+        );
+        drop(ticks);
+        if result.is_err() && rem != 0 {
+            let left = deadline.saturating_sub(TargetArch::uptime_as_micro()?);
+            let ts = TimeSpec::from_micro(left);
+            self.proc_mut().memory_mut().copy_out(rem.into(), &ts)?;
+        }
+        result.map(|_| 0)
+    }
+
     /// Terminate process PID.
     /// Returns Ok(0) on success, Err(()) on error.
     pub fn sys_kill(&self) -> Result<usize, ()> {
@@ -199,10 +436,28 @@ impl KernelCtx<'_, '_> {
         TargetArch::uptime_as_micro()
     }
 
-    /// Shutdowns this machine, discarding all unsaved data. No return.
+    /// Shutdowns this machine once the file system has been unmounted cleanly. No return.
     pub fn sys_poweroff(&self) -> Result<usize, ()> {
         let exitcode = self.proc().argint(0)?;
-        TargetArch::machine_poweroff(exitcode as _);
+        self.kernel()
+            .fs()
+            .unmount(|| TargetArch::machine_poweroff(exitcode as _), self);
+        unreachable!("Poweroff failed")
+    }
+
+    /// Restarts this machine once the file system has been unmounted cleanly, and the disk has
+    /// been reset. No return.
+    pub fn sys_reboot(&self) -> Result<usize, ()> {
+        self.kernel().fs().unmount(
+            || {
+                hal().disk().reset();
+                hal().virtio_console().reset();
+                hal().ninep().reset();
+                TargetArch::machine_reboot();
+            },
+            self,
+        );
+        unreachable!("Reboot failed")
     }
 
     /// Return a new file descriptor referring to the same file as given fd.
@@ -244,6 +499,59 @@ impl KernelCtx<'_, '_> {
         Ok(0)
     }
 
+    /// Get or set the flags of file descriptor fd, as cmd asks.
+    /// Returns Ok(the flags for F_GETFD, 0 otherwise) on success, Err(()) on error.
+    pub fn sys_fcntl(&mut self) -> Result<usize, ()> {
+        let (fd, _) = self.proc().argfd(0)?;
+        let cmd = self.proc().argint(1)?;
+        let arg = self.proc().argint(2)?;
+        let entry = self.proc_mut().deref_mut_data().open_files[fd as usize]
+            .as_mut()
+            .ok_or(())?;
+        let cloexec = &mut entry.cloexec;
+        match cmd {
+            F_GETFD if *cloexec => Ok(FD_CLOEXEC as usize),
+            F_GETFD => Ok(0),
+            F_SETFD => {
+                *cloexec = arg & FD_CLOEXEC != 0;
+                Ok(0)
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Set the file mode creation mask of the process to mask.
+    /// Files have no permission bits yet, so the mask does not affect them.
+    /// Returns Ok(the previous mask).
+    pub fn sys_umask(&mut self) -> Result<usize, ()> {
+        let mask = self.proc().argint(0)? as u32 & 0o777;
+        let old = mem::replace(&mut self.proc_mut().deref_mut_data().umask, mask);
+        Ok(old as usize)
+    }
+
+    /// Read directory entries of the directory fd into buf, by at most n bytes.
+    /// Returns Ok(number read) on success, Err(()) on error.
+    pub fn sys_getdents(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let n = self.proc().argint(2)?;
+        let p = self.proc().argaddr(1)?;
+        // SAFETY: getdents will not access proc's open_files.
+        unsafe { (*(f as *const RcFile)).getdents(p.into(), n, self) }
+    }
+
+    /// Copy at most count bytes from the file in_fd into the file out_fd inside the kernel.
+    /// Returns Ok(number of bytes copied) on success, Err(()) on error.
+    pub fn sys_sendfile(&mut self) -> Result<usize, ()> {
+        let (_, out) = self.proc().argfd(0)?;
+        let (_, f) = self.proc().argfd(1)?;
+        let n = self.proc().argint(2)?;
+        if n < 0 {
+            return Err(());
+        }
+        // SAFETY: sendfile will not access proc's open_files.
+        unsafe { (*(out as *const RcFile)).sendfile(&*(f as *const RcFile), n as usize, self) }
+    }
+
     /// Place info about an open file into struct stat.
     /// Returns Ok(0) on success, Err(()) on error.
     pub fn sys_fstat(&mut self) -> Result<usize, ()> {
@@ -255,6 +563,48 @@ impl KernelCtx<'_, '_> {
         Ok(0)
     }
 
+    /// Place info about the file at path into struct stat.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_stat(&mut self) -> Result<usize, ()> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        // user pointer to struct stat
+        let addr = self.proc().argaddr(1)?;
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self.kernel().fs().namei(path, None, &tx, self).map(|ip| {
+            let st = ip.stat(self);
+            ip.free((&tx, self));
+            st
+        });
+        tx.end(self);
+        self.proc_mut().memory_mut().copy_out(addr.into(), &res?)?;
+        Ok(0)
+    }
+
+    /// Place info about the file at path into struct stat, without following a symbolic link.
+    /// Since rv6 has no symbolic links, this is the same as stat.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_lstat(&mut self) -> Result<usize, ()> {
+        self.sys_stat()
+    }
+
+    /// Check whether the file at path can be accessed as mode asks.
+    /// rv6 has no users or file permissions, so any file that exists can be accessed.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_access(&mut self) -> Result<usize, ()> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let _ = AccessFlags::from_bits(self.proc().argint(1)?).ok_or(())?;
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self
+            .kernel()
+            .fs()
+            .namei(path, None, &tx, self)
+            .map(|ip| ip.free((&tx, self)));
+        tx.end(self);
+        res.map(|_| 0)
+    }
+
     /// Create the path new as a link to the same inode as old.
     /// Returns Ok(0) on success, Err(()) on error.
     pub fn sys_link(&mut self) -> Result<usize, ()> {
@@ -264,21 +614,36 @@ impl KernelCtx<'_, '_> {
         let new = Path::new(self.proc_mut().argstr(1, &mut new)?);
         let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
         let res = try {
-            let inode = self.kernel().fs().namei(old, &tx, self)?;
-            let _ = self.kernel().fs().link(inode, new, &tx, self)?;
+            let inode = self.kernel().fs().namei(old, None, &tx, self)?;
+            let _ = self
+                .kernel()
+                .fs()
+                .link(inode, new, None, false, &tx, self)?;
             0
         };
         tx.end(self);
         res
     }
 
+    /// Create the path new as a link to the file fd, such as an unnamed file opened with O_TMPFILE.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_linkat(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let f = f as *const RcFile;
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(1, &mut path)?);
+        // SAFETY: link will not access proc's open_files.
+        unsafe { (*f).link(path, self) }?;
+        Ok(0)
+    }
+
     /// Remove a file.
     /// Returns Ok(0) on success, Err(()) on error.
     pub fn sys_unlink(&mut self) -> Result<usize, ()> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self.kernel().fs().unlink(path, &tx, self).map(|_| 0);
+        let res = self.kernel().fs().unlink(path, None, &tx, self).map(|_| 0);
         tx.end(self);
         res
     }
@@ -291,7 +656,7 @@ impl KernelCtx<'_, '_> {
         let omode = self.proc().argint(1)?;
         let omode = FcntlFlags::from_bits_truncate(omode);
         let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self.kernel().fs().open(path, omode, &tx, self);
+        let res = self.kernel().fs().open(path, None, omode, &tx, self);
         tx.end(self);
         res
     }
@@ -305,7 +670,7 @@ impl KernelCtx<'_, '_> {
         let res = self
             .kernel()
             .fs()
-            .create(path, InodeType::Dir, &tx, self, |_| ())
+            .create(path, None, InodeType::Dir, &tx, self, |_| ())
             .map(|(ptr, _)| {
                 ptr.free((&tx, self));
                 0
@@ -325,7 +690,57 @@ impl KernelCtx<'_, '_> {
         let res = self
             .kernel()
             .fs()
-            .create(path, InodeType::Device { major, minor }, &tx, self, |_| ())
+            .create(
+                path,
+                None,
+                InodeType::Device { major, minor },
+                &tx,
+                self,
+                |_| (),
+            )
+            .map(|(ptr, _)| {
+                ptr.free((&tx, self));
+                0
+            });
+        tx.end(self);
+        res
+    }
+
+    /// Open the file at path, relative to the directory dirfd.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    pub fn sys_openat(&mut self) -> Result<usize, ()> {
+        let dir = self
+            .proc()
+            .argdirfd(0)?
+            .map(|ip| ip as *const RcInode<DefaultFs>);
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(1, &mut path)?);
+        let omode = self.proc().argint(2)?;
+        let omode = FcntlFlags::from_bits_truncate(omode);
+        // SAFETY: open only adds a file descriptor, so dirfd stays open.
+        let dir = dir.map(|ip| unsafe { &*ip });
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self.kernel().fs().open(path, dir, omode, &tx, self);
+        tx.end(self);
+        res
+    }
+
+    /// Create a new directory at path, relative to the directory dirfd.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_mkdirat(&mut self) -> Result<usize, ()> {
+        let dir = self
+            .proc()
+            .argdirfd(0)?
+            .map(|ip| ip as *const RcInode<DefaultFs>);
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(1, &mut path)?);
+        // SAFETY: create will not access proc's open_files.
+        let dir = dir.map(|ip| unsafe { &*ip });
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self
+            .kernel()
+            .fs()
+            .create(path, dir, InodeType::Dir, &tx, self, |_| ())
             .map(|(ptr, _)| {
                 ptr.free((&tx, self));
                 0
@@ -334,6 +749,23 @@ impl KernelCtx<'_, '_> {
         res
     }
 
+    /// Remove the file at path, relative to the directory dirfd.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_unlinkat(&mut self) -> Result<usize, ()> {
+        let dir = self
+            .proc()
+            .argdirfd(0)?
+            .map(|ip| ip as *const RcInode<DefaultFs>);
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(1, &mut path)?);
+        // SAFETY: unlink will not access proc's open_files.
+        let dir = dir.map(|ip| unsafe { &*ip });
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self.kernel().fs().unlink(path, dir, &tx, self).map(|_| 0);
+        tx.end(self);
+        res
+    }
+
     /// Change the current directory.
     /// Returns Ok(0) on success, Err(()) on error.
     pub fn sys_chdir(&mut self) -> Result<usize, ()> {
@@ -341,7 +773,7 @@ impl KernelCtx<'_, '_> {
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
         let res = try {
-            let inode = self.kernel().fs().namei(path, &tx, self)?;
+            let inode = self.kernel().fs().namei(path, None, &tx, self)?;
             let _ = self.kernel().fs().chdir(inode, &tx, self)?;
             0
         };
@@ -349,6 +781,28 @@ impl KernelCtx<'_, '_> {
         res
     }
 
+    /// Copy the absolute path of the current directory, terminated by NUL, into buf of n bytes.
+    /// Returns Ok(length of the path) on success, Err(()) on error.
+    pub fn sys_getcwd(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let n = self.proc().argint(1)?;
+        if n < 0 {
+            return Err(());
+        }
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        // Leave room for the NUL.
+        let size = cmp::min(n as usize, MAXPATH).saturating_sub(1);
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = self.kernel().fs().getcwd(&mut path[..size], &tx, self);
+        tx.end(self);
+        let len = res?;
+        path[len] = 0;
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr.into(), &path[..len + 1])?;
+        Ok(len)
+    }
+
     /// Load a file and execute it with arguments.
     /// Returns Ok(argc argument to user main) on success, Err(()) on error.
     pub fn sys_exec(&mut self) -> Result<usize, ()> {
@@ -437,11 +891,17 @@ impl KernelCtx<'_, '_> {
         }
 
         if write_fds != 0 {
-            unimplemented!("Handling for write fd set has not been implemented yet");
+            // SAFETY: `write_fds` is a valid user space address given by a user.
+            unsafe {
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_in(&mut wfds, write_fds.into())
+            }?;
         }
 
+        // No file reports exceptional conditions.
         if err_fds != 0 {
-            unimplemented!("Handling for exceptional fd set has not been implemented yet");
+            return Err(());
         }
 
         // the number of fds that are ready
@@ -460,14 +920,15 @@ impl KernelCtx<'_, '_> {
                     let mask = 1 << (fd % 8);
 
                     if fds[i][idx] & mask != 0 {
-                        let f = self
+                        let f = &self
                             .proc()
                             .deref_data()
                             .open_files
                             .get(fd as usize)
                             .ok_or(())?
                             .as_ref()
-                            .ok_or(())?;
+                            .ok_or(())?
+                            .file;
                         // SAFETY: `is_ready` will not access proc's open_files.
                         if unsafe { (*(f as *const RcFile)).is_ready(event)? } {
                             ready_cnt += 1;
@@ -488,6 +949,7 @@ impl KernelCtx<'_, '_> {
             if ticks.wrapping_sub(ticks0) >= n_ticks as u32 {
                 for idx in 0..(nfds + 1) / 8 + 1 {
                     rfds[idx as usize] = 0;
+                    wfds[idx as usize] = 0;
                 }
                 break;
             }
@@ -522,13 +984,145 @@ impl KernelCtx<'_, '_> {
     pub fn sys_waitpid(&mut self) -> Result<usize, ()> {
         let pid = self.proc().argint(0)?;
         let stat = self.proc().argaddr(1)?;
-        Ok(self.kernel().procs().waitpid(pid, stat.into(), self)? as _)
+        let options = WaitOptions::from_bits(self.proc().argint(2)?).ok_or(())?;
+        Ok(self
+            .kernel()
+            .procs()
+            .waitpid(pid, stat.into(), options, self)? as _)
     }
 
     pub fn sys_getppid(&mut self) -> Result<usize, ()> {
         Ok(self.kernel().procs().get_parent_pid(self) as _)
     }
 
+    /// Place a summary of the memory mappings of process pid into struct pmapinfo,
+    /// and its first regions into the array of at most n struct pmapregion.
+    /// Returns Ok(number of regions stored) on success, Err(()) on error.
+    pub fn sys_pmap(&mut self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        let info = self.proc().argaddr(1)?;
+        let p = self.proc().argaddr(2)?;
+        let n = self.proc().argint(3)?;
+        if n < 0 {
+            return Err(());
+        }
+        let mut regions = [MapRegion::default(); NMAPREGION];
+        let regions = &mut regions[..cmp::min(n as usize, NMAPREGION)];
+        let map = self.kernel().procs().map_info(pid, regions, self)?;
+        let stored = cmp::min(regions.len(), map.nregion);
+        self.proc_mut().memory_mut().copy_out(info.into(), &map)?;
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(p.into(), regions[..stored].as_bytes())?;
+        Ok(stored)
+    }
+
+    /// Become traced by the parent, or trace the child pid, according to request.
+    /// addr is an address in the child, and data is a word or an address in the caller.
+    /// Returns Ok(0 or why the child is stopped) on success, Err(()) on error.
+    pub fn sys_ptrace(&mut self) -> Result<usize, ()> {
+        let request = self.proc().argint(0)?;
+        let pid = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?;
+        let data = self.proc().argaddr(3)?;
+        match request {
+            // PTRACE_TRACEME
+            0 => self.kernel().procs().trace_me(self)?,
+            // PTRACE_WAIT
+            1 => return Ok(self.kernel().procs().trace_wait(pid, self)? as usize),
+            // PTRACE_CONT
+            2 => self.kernel().procs().trace_resume(pid, false, self)?,
+            // PTRACE_SYSCALL
+            3 => self.kernel().procs().trace_resume(pid, true, self)?,
+            // PTRACE_PEEKDATA
+            4 => {
+                let word = self.kernel().procs().trace_peek(pid, addr.into(), self)?;
+                self.proc_mut().memory_mut().copy_out(data.into(), &word)?;
+            }
+            // PTRACE_POKEDATA
+            5 => {
+                self.kernel()
+                    .procs()
+                    .trace_poke(pid, addr.into(), data, self)?
+            }
+            // PTRACE_GETREGS
+            6 => {
+                let regs = self.kernel().procs().trace_get_regs(pid, self)?;
+                self.proc_mut().memory_mut().copy_out(data.into(), &regs)?;
+            }
+            // PTRACE_SETREGS
+            7 => {
+                let mut regs = TraceRegs::default();
+                // SAFETY: TraceRegs does not have any internal structure.
+                unsafe { self.proc_mut().memory_mut().copy_in(&mut regs, data.into()) }?;
+                self.kernel().procs().trace_set_regs(pid, &regs, self)?;
+            }
+            // PTRACE_SETTRIGGER
+            8 => {
+                let mut trigger = Trigger::default();
+                // SAFETY: Trigger does not have any internal structure.
+                unsafe {
+                    self.proc_mut()
+                        .memory_mut()
+                        .copy_in(&mut trigger, data.into())
+                }?;
+                self.kernel()
+                    .procs()
+                    .trace_set_trigger(pid, &trigger, self)?;
+            }
+            _ => return Err(()),
+        }
+        Ok(0)
+    }
+
+    /// Run the n system calls of the struct syscallent array at addr in order, storing
+    /// the result of each into its ret field. fork, exit, exec, and batch cannot be batched.
+    /// Returns Ok(n) on success, Err(()) on error.
+    pub fn sys_batch(&mut self) -> Result<usize, ()> {
+        let addr: UVAddr = self.proc().argaddr(0)?.into();
+        let n = self.proc().argint(1)?;
+        if n < 0 || n as usize > MAXBATCH {
+            return Err(());
+        }
+
+        // Each system call takes its arguments from the trap frame.
+        let saved = *self.proc_mut().trap_frame_mut();
+        let size = mem::size_of::<BatchEntry>();
+        let mut result = Ok(n as usize);
+        for i in 0..n as usize {
+            let entry_addr = addr + i * size;
+            let mut entry = BatchEntry::default();
+            // SAFETY: BatchEntry does not have any internal structure.
+            if unsafe { self.proc_mut().memory_mut().copy_in(&mut entry, entry_addr) }.is_err() {
+                result = Err(());
+                break;
+            }
+            for (j, arg) in entry.args.iter().enumerate() {
+                *self.proc_mut().trap_frame_mut().param_reg_mut(j.into()) = *arg;
+            }
+            entry.ret = match entry.num {
+                // fork, exit, exec, and batch
+                1 | 2 | 7 | 43 => usize::MAX,
+                num if num <= i32::MAX as usize => ok_or!(self.syscall(num as i32), usize::MAX),
+                _ => usize::MAX,
+            };
+            if self
+                .proc_mut()
+                .memory_mut()
+                .copy_out(entry_addr, &entry)
+                .is_err()
+            {
+                result = Err(());
+                break;
+            }
+            if self.proc().killed() {
+                break;
+            }
+        }
+        *self.proc_mut().trap_frame_mut() = saved;
+        result
+    }
+
     pub fn sys_lseek(&mut self) -> Result<usize, ()> {
         let (_, f) = self.proc().argfd(0)?;
         let offset = self.proc().argint(1)?;
@@ -544,13 +1138,210 @@ impl KernelCtx<'_, '_> {
         unsafe { (*(f as *const RcFile)).lseek(offset, whence, self) }
     }
 
-    pub fn sys_clock(&mut self) -> Result<usize, ()> {
-        let p = self.proc().argaddr(0)?;
-        let addr = UVAddr::from(p);
+    /// Relocate the blocks of the file fd into a contiguous run on the disk.
+    /// Returns Ok(number of relocated blocks) on success, Err(()) on error.
+    pub fn sys_defrag(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        // SAFETY: defrag will not access proc's open_files.
+        unsafe { (*(f as *const RcFile)).defrag(self) }
+    }
+
+    /// Shrink or extend the file fd to length bytes.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_ftruncate(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let length = self.proc().argint(1)?;
+        if length < 0 {
+            return Err(());
+        }
+        f.truncate(length as u32, self)?;
+        Ok(0)
+    }
+
+    /// Shrink or extend the file at path to length bytes.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_truncate(&mut self) -> Result<usize, ()> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let length = self.proc().argint(1)?;
+        if length < 0 {
+            return Err(());
+        }
+        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let res = try {
+            let ptr = self.kernel().fs().namei(path, None, &tx, self)?;
+            let mut ip = ptr.lock(self);
+            let res = if ip.deref_inner().typ == InodeType::File {
+                ip.truncate(length as u32, &tx, self)
+            } else {
+                Err(())
+            };
+            ip.free(self);
+            ptr.free((&tx, self));
+            res?;
+            0
+        };
+        tx.end(self);
+        res
+    }
 
-        let clk = TargetArch::r_cycle();
-        self.proc_mut().memory_mut().copy_out(addr, &clk)?;
+    /// Take a snapshot of the file system, replacing the previous one.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_snapshot(&mut self) -> Result<usize, ()> {
+        self.kernel().fs().snapshot(self)?;
+        Ok(0)
+    }
 
+    /// Drop the snapshot of the file system.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_snapdrop(&mut self) -> Result<usize, ()> {
+        self.kernel().fs().snapshot_drop(self)?;
         Ok(0)
     }
+
+    /// Read n bytes into buf from inode inum of the snapshot at offset off.
+    /// Returns Ok(number read) on success, Err(()) on error.
+    pub fn sys_snapread(&mut self) -> Result<usize, ()> {
+        let inum = self.proc().argint(0)?;
+        let off = self.proc().argint(1)?;
+        let p = self.proc().argaddr(2)?;
+        let n = self.proc().argint(3)?;
+        self.kernel()
+            .fs()
+            .snapshot_read(inum as u32, off as u32, p.into(), n as u32, self)
+    }
+
+    /// Place info about inode inum of the snapshot into struct stat.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_snapstat(&mut self) -> Result<usize, ()> {
+        let inum = self.proc().argint(0)?;
+        // user pointer to struct stat
+        let st = self.proc().argaddr(1)?;
+        let stat = self.kernel().fs().snapshot_stat(inum as u32, self)?;
+        self.proc_mut().memory_mut().copy_out(st.into(), &stat)?;
+        Ok(0)
+    }
+
+    /// Give up the CPU, and let the scheduler run another process.
+    /// Returns Ok(0).
+    pub fn sys_yield(&self) -> Result<usize, ()> {
+        self.yield_cpu();
+        Ok(0)
+    }
+
+    /// Return the thread ID of the caller.
+    /// Every process has a single thread for now, so it is the same as the process ID.
+    pub fn sys_gettid(&self) -> Result<usize, ()> {
+        Ok(self.proc().pid() as _)
+    }
+
+    /// Copy the context switch counters of all CPUs into the struct schedstat at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_schedstat(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let stat = hal().cpus().sched_stat();
+        self.proc_mut().memory_mut().copy_out(addr.into(), &stat)?;
+        Ok(0)
+    }
+
+    /// Stop the process pid before it next returns to user space, until it is thawed.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_freeze(&self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        self.kernel().procs().freeze(pid)?;
+        Ok(0)
+    }
+
+    /// Resume the process pid frozen by freeze().
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_thaw(&self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        self.kernel().procs().thaw(pid)?;
+        Ok(0)
+    }
+
+    /// Set the scheduling class of the process pid, or of the caller if pid is 0.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_setsched(&self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        let class = SchedClass::from_usize(self.proc().argint(1)? as usize).ok_or(())?;
+        self.kernel().procs().set_sched_class(pid, class, self)?;
+        Ok(0)
+    }
+
+    /// Return the scheduling class of the process pid, or of the caller if pid is 0.
+    /// Returns Ok(class) on success, Err(()) on error.
+    pub fn sys_getsched(&self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        Ok(self.kernel().procs().sched_class(pid, self)? as usize)
+    }
+
+    /// Put the process pid, or the caller if pid is 0, in the deadline class, where it runs for
+    /// at most budget microseconds in each period of period microseconds, and should have done
+    /// so by deadline microseconds after the period begins. A period of 0 moves the process back
+    /// to the interactive class instead.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_setdeadline(&self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        let period = self.proc().argint(1)?;
+        let budget = self.proc().argint(2)?;
+        let deadline = self.proc().argint(3)?;
+        let reservation = if period == 0 {
+            None
+        } else if 0 < budget && budget <= deadline && deadline <= period {
+            Some((period as usize, budget as usize, deadline as usize))
+        } else {
+            return Err(());
+        };
+        self.kernel().procs().set_deadline(pid, reservation, self)?;
+        Ok(0)
+    }
+
+    /// Copy the reservation of the deadline class of the process pid, or of the caller if pid is
+    /// 0, into the struct dlinfo at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_dlstat(&mut self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?;
+        let info = self.kernel().procs().deadline_info(pid, self)?;
+        self.proc_mut().memory_mut().copy_out(addr.into(), &info)?;
+        Ok(0)
+    }
+
+    /// Bind the process pid, or the caller if pid is 0, to CPU cpu, on which alone it runs from
+    /// then on, or let it run on any CPU again if cpu is -1. A caller bound to another CPU moves
+    /// there before returning.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_migrate(&self) -> Result<usize, ()> {
+        let pid = self.proc().argint(0)?;
+        let cpu = match self.proc().argint(1)? {
+            -1 => None,
+            cpu if cpu >= 0 && (cpu as usize) < NCPU && hal().cpus().is_started(cpu as usize) => {
+                Some(cpu as usize)
+            }
+            _ => return Err(()),
+        };
+        self.kernel().procs().migrate(pid, cpu, self)?;
+        if (pid == 0 || pid == self.proc().pid()) && cpu.map_or(false, |cpu| cpu != cpuid()) {
+            self.yield_cpu();
+        }
+        Ok(0)
+    }
+
+    /// Set the interval between timer interrupts to us microseconds.
+    /// sleep() and uptime() count timer interrupts, so they follow the new interval.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_settick(&self) -> Result<usize, ()> {
+        let us = self.proc().argint(0)?;
+        if us < MINTICK_US as i32 {
+            return Err(());
+        }
+        TargetArch::set_tick_interval(us as usize);
+        Ok(0)
+    }
+
+    /// Return the interval between timer interrupts, in microseconds.
+    pub fn sys_gettick(&self) -> Result<usize, ()> {
+        Ok(TargetArch::tick_interval())
+    }
 }
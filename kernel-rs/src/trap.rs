@@ -1,12 +1,16 @@
 use core::fmt;
 
 use crate::{
-    arch::interface::{ProcManager, TrapFrameManager, TrapManager},
+    arch::interface::{ProcManager, TimeManager, TrapFrameManager, TrapManager},
     arch::TargetArch,
+    console::Severity,
     hal::hal,
     kernel::{kernel_ref, KernelRef},
     ok_or,
-    proc::{kernel_ctx, KernelCtx, Procstate},
+    proc::{kernel_ctx, KernelCtx, Procstate, TraceEvent},
+    util::spin_loop,
+    virtio::{Virtio9p, VirtioConsole, VirtioDisk},
+    vm::FaultError,
 };
 
 /// In ARM.v8 architecture, interrupts are part
@@ -22,12 +26,23 @@ pub enum TrapTypes {
     Irq(IrqTypes),
     Syscall,
     BadTrap,
+    /// A breakpoint instruction, or a hardware trigger set by `PTRACE_SETTRIGGER`.
+    Breakpoint,
+    /// An access to `addr` that the page table does not allow, which writes if `write`.
+    PageFault {
+        addr: usize,
+        write: bool,
+    },
     TimerInterrupt,
+    /// An inter-processor interrupt, whose messages are handled by `Ipi::handle`.
+    Ipi,
 }
 
 #[derive(Debug)]
 pub enum IrqTypes {
     Virtio,
+    VirtioConsole,
+    Virtio9p,
     Uart,
     Others(IrqNum),
     Unknown(IrqNum),
@@ -68,6 +83,10 @@ impl KernelCtx<'_, '_> {
             TargetArch::switch_to_kernel_vec();
         }
 
+        if self.kernel().as_ref().should_freeze() {
+            spin_loop();
+        }
+
         // Save user program counter.
         self.proc_mut().trap_frame_mut().set_pc(TargetArch::r_epc());
 
@@ -78,6 +97,7 @@ impl KernelCtx<'_, '_> {
             TargetArch::before_handling_trap(&trap_type, Some(self.proc_mut().trap_frame_mut()));
         }
 
+        let mut reschedule = matches!(trap_type, TrapTypes::TimerInterrupt);
         match &trap_type {
             TrapTypes::Syscall => {
                 // system call
@@ -89,26 +109,49 @@ impl KernelCtx<'_, '_> {
                 // so don't enable until done with those registers.
                 // SAFETY: Interrupt handlers has been configured properly
                 unsafe { TargetArch::intr_on() };
+
+                // The tracing parent may change the system call number and its arguments
+                // at entry, and the return value at exit.
+                self.kernel()
+                    .procs()
+                    .trace_syscall(TraceEvent::SyscallEntry, &mut self);
                 let syscall_no = self.proc_mut().trap_frame_mut().get_param_reg(7.into()) as i32;
                 *self.proc_mut().trap_frame_mut().param_reg_mut(0.into()) =
                     ok_or!(self.syscall(syscall_no), usize::MAX);
+                self.kernel()
+                    .procs()
+                    .trace_syscall(TraceEvent::SyscallExit, &mut self);
             }
             TrapTypes::Irq(irq_type) => unsafe {
                 self.kernel().handle_irq(irq_type);
             },
-            TrapTypes::BadTrap => {
-                self.kernel().as_ref().write_str("usertrap(): ");
-
-                TargetArch::print_trap_status(|arg: fmt::Arguments<'_>| {
-                    self.kernel().as_ref().write_fmt(arg);
-                });
-                self.proc().kill();
-                self.kernel().procs().exit_current(-1, &mut self);
+            TrapTypes::Breakpoint if self.kernel().procs().trace_breakpoint(&mut self) => {}
+            TrapTypes::PageFault { addr, write } => {
+                match self
+                    .proc_mut()
+                    .memory_mut()
+                    .handle_fault(*addr, *write, hal().kmem())
+                {
+                    Ok(()) => {}
+                    // Retry the access once the killed process, which may be this one, has
+                    // freed its memory.
+                    Err(FaultError::OutOfMemory) if self.kernel().procs().out_of_memory(&self) => {
+                        reschedule = true;
+                    }
+                    Err(_) => self.bad_trap(),
+                }
             }
+            TrapTypes::BadTrap | TrapTypes::Breakpoint => self.bad_trap(),
             TrapTypes::TimerInterrupt => {
                 if TargetArch::cpu_id() == 0 {
                     self.kernel().clock_intr();
                 }
+                let _ = hal().ipi().handle();
+                self.scan_working_set();
+                self.merge_same_pages();
+            }
+            TrapTypes::Ipi => {
+                reschedule = hal().ipi().handle();
             }
         }
 
@@ -118,33 +161,57 @@ impl KernelCtx<'_, '_> {
             TargetArch::after_handling_trap(&trap_type);
         }
 
+        self.kernel().procs().stop_if_frozen(&mut self);
+
         if self.proc().killed() {
             self.kernel().procs().exit_current(-1, &mut self);
         }
 
-        // Give up the CPU if this is a timer interrupt.
-        if let TrapTypes::TimerInterrupt = trap_type {
+        // Give up the CPU if this is a timer interrupt, or another CPU asked to.
+        if reschedule {
             self.yield_cpu();
         }
 
         unsafe { self.user_trap_ret() }
     }
 
+    /// Kill the current process for a trap it cannot recover from.
+    fn bad_trap(&mut self) -> ! {
+        self.kernel()
+            .as_ref()
+            .log(Severity::Error, format_args!("usertrap(): "));
+
+        TargetArch::print_trap_status(|arg: fmt::Arguments<'_>| {
+            self.kernel().as_ref().write_fmt(arg);
+        });
+        self.proc().kill();
+        self.kernel().procs().exit_current(-1, self);
+    }
+
     /// Return to user space.
     ///
     /// # Safety
     ///
     /// It must be called only by `user_trap`.
     pub unsafe fn user_trap_ret(mut self) -> ! {
-        // Tell trampoline.S the user page table to switch to.
+        // Tell trampoline.S the user page table to switch to, and its ASID.
+        // Interrupts stay disabled until the process is in user space.
+        TargetArch::intr_off();
+        let asids = self.kernel().asids();
+        let asid = self.proc_mut().memory_mut().activate(asids);
         let user_table = self.proc().memory().page_table_addr();
 
         let kstack = self.proc_mut().deref_mut_data().kstack;
 
+        // SAFETY: interrupts are disabled, and we are going back to user space.
+        unsafe { TargetArch::load_triggers(&self.proc().deref_data().triggers) };
+
         let trapframe = self.proc_mut().trap_frame_mut();
 
         // SAFETY: It is called by `user_trap_ret`, after handling the user trap.
-        unsafe { TargetArch::user_trap_ret(user_table, trapframe, kstack, usertrap as usize) };
+        unsafe {
+            TargetArch::user_trap_ret(user_table, asid, trapframe, kstack, usertrap as usize)
+        };
     }
 }
 
@@ -160,12 +227,17 @@ impl KernelRef<'_, '_> {
         );
         assert!(!TargetArch::intr_get(), "kerneltrap: interrupts enabled");
 
+        if self.as_ref().should_freeze() {
+            spin_loop();
+        }
+
         let trap_type = TargetArch::get_trap_type(trap_info);
 
         // SAFETY: Actually received trap with type of `trap_type`.
         unsafe {
             TargetArch::before_handling_trap(&trap_type, None);
         }
+        let mut reschedule = matches!(trap_type, TrapTypes::TimerInterrupt);
         match &trap_type {
             TrapTypes::Syscall => {
                 // kernel trap cannot be a syscall.
@@ -174,8 +246,9 @@ impl KernelRef<'_, '_> {
             TrapTypes::Irq(irq_type) => unsafe {
                 self.handle_irq(irq_type);
             },
-            TrapTypes::BadTrap => {
-                self.as_ref().write_str("kerneltrap(): ");
+            TrapTypes::BadTrap | TrapTypes::Breakpoint | TrapTypes::PageFault { .. } => {
+                self.as_ref()
+                    .log(Severity::Error, format_args!("kerneltrap(): "));
 
                 TargetArch::print_trap_status(|arg: fmt::Arguments<'_>| {
                     self.as_ref().write_fmt(arg);
@@ -186,6 +259,10 @@ impl KernelRef<'_, '_> {
                 if TargetArch::cpu_id() == 0 {
                     self.clock_intr();
                 }
+                let _ = hal().ipi().handle();
+            }
+            TrapTypes::Ipi => {
+                reschedule = hal().ipi().handle();
             }
         }
 
@@ -195,8 +272,8 @@ impl KernelRef<'_, '_> {
             TargetArch::after_handling_trap(&trap_type);
         }
 
-        // Give up the CPU if this is a timer interrupt.
-        if let TrapTypes::TimerInterrupt = trap_type {
+        // Give up the CPU if this is a timer interrupt, or another CPU asked to.
+        if reschedule {
             // TODO(https://github.com/kaist-cp/rv6/issues/517): safety?
             if let Some(ctx) = unsafe { self.get_ctx() } {
                 // SAFETY:
@@ -223,13 +300,21 @@ impl KernelRef<'_, '_> {
     /// It must be called only when corresponding irq has actually
     /// been received.
     unsafe fn handle_irq(self, irq_type: &IrqTypes) {
+        let start = TargetArch::r_cycle();
+        self.entropy().add(start);
         match irq_type {
             IrqTypes::Uart => {
                 // SAFETY: it's unsafe only when ctrl+p is pressed.
                 unsafe { hal().console().intr(self) };
             }
             IrqTypes::Virtio => {
-                hal().disk().pinned_lock().get_pin_mut().intr(self);
+                VirtioDisk::intr(&mut hal().disk().pinned_lock(), self);
+            }
+            IrqTypes::VirtioConsole => {
+                VirtioConsole::intr(&mut hal().virtio_console().pinned_lock(), self);
+            }
+            IrqTypes::Virtio9p => {
+                Virtio9p::intr(&mut hal().ninep().pinned_lock(), self);
             }
             IrqTypes::Unknown(irq_num) => {
                 // Use `panic!` instead of `println` to prevent stack overflow.
@@ -240,11 +325,21 @@ impl KernelRef<'_, '_> {
                 // do nothing
             }
         }
+        self.irq_stats().record(
+            irq_type.into(),
+            TargetArch::cpu_id(),
+            TargetArch::r_cycle().wrapping_sub(start),
+        );
     }
 
     fn clock_intr(self) {
         let mut ticks = self.ticks().lock();
         *ticks = ticks.wrapping_add(1);
+        let now = *ticks;
         ticks.wakeup(self);
+        drop(ticks);
+        self.epolls().tick(self);
+        self.eventfds().tick(self);
+        self.gangs().tick(now);
     }
 }
@@ -41,22 +41,22 @@ pub struct List<T: ListNode> {
     _marker: PhantomData<T>,
 }
 
-/// An iterator over the elements of `List`.
-pub struct Iter<'s, T: ListNode> {
-    last: &'s ListEntry,
-    curr: &'s ListEntry,
-    _marker: PhantomData<T>,
-}
-
 pub struct IterStrongPinMut<'s, T> {
     last: *const ListEntry,
     curr: *const ListEntry,
     _marker: PhantomData<&'s mut T>,
 }
 
-/// A pinned mutable iterator over the elements of `List`.
-pub struct IterPinMut<'s, T: ListNode> {
-    last: *const ListEntry,
+/// A cursor over a `List`, which can move in both directions and remove or insert nodes.
+///
+/// A cursor points to either a node or the head of the list, which is a "ghost" position that
+/// does not refer to a node. Moving past either end of the list reaches the ghost position, and
+/// moving once more wraps around to the other end.
+///
+/// A cursor can only be made from a `StrongPinMut` to the `List`, so no one else can access the
+/// list, for example because the lock that owns the list is held, while the cursor is alive.
+pub struct CursorMut<'s, T: ListNode> {
+    head: *const ListEntry,
     curr: *const ListEntry,
     _marker: PhantomData<&'s mut T>,
 }
@@ -178,67 +178,26 @@ impl<T: ListNode> List<T> {
         while self.pop_front().is_some() {}
     }
 
-    /// Provides an unsafe forward iterator.
-    ///
-    /// # Note
-    ///
-    /// The caller should be careful when removing nodes currently accessed by iterators.
-    /// If an iterator's current node gets removed, the iterator will get stuck at the current node and never advance.
-    ///
-    /// # Safety
-    ///
-    /// The caller should be even more careful when mutating or dropping nodes that are currently
-    /// accessed by iterators. This can lead to undefined behavior.
-    ///
-    /// # Examples
-    ///
-    /// *Incorrect* usage of this method.
-    ///
-    /// ```rust,no_run
-    /// # #[pin_project]
-    /// # struct Node {
-    /// #     data: usize,
-    /// #     #[pin]
-    /// #     list_entry: ListEntry,
-    /// # }
-    /// #
-    /// # unsafe impl ListNode for Node {
-    /// #     fn get_list_entry(&self) -> &ListEntry {
-    /// #         &self.list_entry
-    /// #     }
-    /// #
-    /// #     fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
-    /// #         (list_entry as usize - offset_of!(Node, list_entry)) as *const Self
-    /// #     }
-    /// # }
-    /// #
-    /// # fn main() {
-    ///     // Make and initialize a `List` and a `Node` that implements the `ListNode` trait.
-    ///     let mut list = unsafe { List::new() };
-    ///     let mut node = Some(unsafe { Node { data: 10, list_entry: ListEntry::new() }});
-    ///     let list_pin = unsafe { Pin::new_unchecked(&mut list) };
-    ///     let node_pin = unsafe { Pin::new_unchecked(node.as_mut().expect("")) };
-    ///     list_pin.init();
-    ///     node_pin.project().list_entry.init();
-    ///
-    ///     // Push the `ListNode` to the `List`.
-    ///     list.push_front(node.as_ref().expect(""));
-    ///
-    ///     // Use an unsafe iterator.
-    ///     for n in unsafe { list.iter_unchecked() } {
-    ///         assert!(n.data == 10);  // okay!
-    ///         node = None;
-    ///         assert!(n.data == 10);  // not okay! reading data of already dropped node!
-    ///                                 // undefined behavior! ⚠️
-    ///     }
-    ///
-    ///     assert!(node.is_none());
-    /// # }
-    /// ```
-    pub unsafe fn iter_unchecked(self: Pin<&Self>) -> Iter<'_, T> {
-        Iter {
-            last: self.head().get_ref(),
-            curr: unsafe { &*self.head().next() },
+    /// Provides a cursor at the front node, or at the ghost position if the list is empty.
+    #[allow(clippy::needless_lifetimes)]
+    pub fn cursor_front_mut<'s>(self: StrongPinMut<'s, Self>) -> CursorMut<'s, T> {
+        let head = unsafe { &(*self.ptr().as_ptr()).head };
+        let curr = unsafe { Pin::new_unchecked(head) }.next();
+        CursorMut {
+            head,
+            curr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Provides a cursor at the back node, or at the ghost position if the list is empty.
+    #[allow(clippy::needless_lifetimes)]
+    pub fn cursor_back_mut<'s>(self: StrongPinMut<'s, Self>) -> CursorMut<'s, T> {
+        let head = unsafe { &(*self.ptr().as_ptr()).head };
+        let curr = unsafe { Pin::new_unchecked(head) }.prev();
+        CursorMut {
+            head,
+            curr,
             _marker: PhantomData,
         }
     }
@@ -253,14 +212,6 @@ impl<T: ListNode> List<T> {
             _marker: PhantomData,
         }
     }
-
-    pub unsafe fn iter_pin_mut_unchecked(self: Pin<&mut Self>) -> IterPinMut<'_, T> {
-        IterPinMut {
-            last: &self.head,
-            curr: unsafe { &*self.as_ref().head().next() },
-            _marker: PhantomData,
-        }
-    }
 }
 
 #[pinned_drop]
@@ -270,37 +221,6 @@ impl<T: ListNode> PinnedDrop for List<T> {
     }
 }
 
-impl<'s, T: 's + ListNode> Iterator for Iter<'s, T> {
-    type Item = &'s T;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if ptr::eq(self.last, self.curr) {
-            None
-        } else {
-            // Safe since `self.curr` is a `ListEntry` contained inside a `T`.
-            let res = Some(unsafe { &*T::from_list_entry(self.curr) });
-            let curr = unsafe { Pin::new_unchecked(self.curr) };
-            debug_assert_ne!(self.curr as *const _, curr.next(), "loops forever");
-            self.curr = unsafe { &*curr.next() };
-            res
-        }
-    }
-}
-
-impl<'s, T: 's + ListNode> DoubleEndedIterator for Iter<'s, T> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        if ptr::eq(self.last, self.curr) {
-            None
-        } else {
-            let last = unsafe { Pin::new_unchecked(self.last) };
-            debug_assert_ne!(self.last as *const _, last.prev(), "loops forever");
-            self.last = unsafe { &*last.prev() };
-            // Safe since `self.last` is a `ListEntry` contained inside a `T`.
-            Some(unsafe { &*T::from_list_entry(self.last) })
-        }
-    }
-}
-
 impl<'s, T: 's + ListNode> Iterator for IterStrongPinMut<'s, T> {
     type Item = StrongPinMut<'s, T>;
 
@@ -334,35 +254,70 @@ impl<'s, T: 's + ListNode> DoubleEndedIterator for IterStrongPinMut<'s, T> {
     }
 }
 
-impl<'s, T: 's + ListNode> Iterator for IterPinMut<'s, T> {
-    type Item = Pin<&'s mut T>;
+impl<'s, T: 's + ListNode> CursorMut<'s, T> {
+    fn curr(&self) -> Pin<&ListEntry> {
+        // SAFETY: `self.curr` is an initialized `ListEntry` in the list, which cannot be dropped
+        // while the list is borrowed.
+        unsafe { Pin::new_unchecked(&*self.curr) }
+    }
+
+    /// Returns `true` if the cursor is at the ghost position.
+    pub fn is_ghost(&self) -> bool {
+        ptr::eq(self.curr, self.head)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if ptr::eq(self.last, self.curr) {
+    /// Returns the node at the cursor, or `None` if the cursor is at the ghost position.
+    pub fn current(&mut self) -> Option<StrongPinMut<'_, T>> {
+        if self.is_ghost() {
             None
         } else {
-            // Safe since `self.curr` is a `ListEntry` contained inside a `T`.
+            // Safe since `self.curr` is a `ListEntry` contained inside a `T`, and the returned
+            // `StrongPinMut` borrows the cursor, which uniquely borrows the list.
             let ptr = T::from_list_entry(self.curr) as *mut T;
-            let res = Some(unsafe { Pin::new_unchecked(&mut *ptr) });
-            let curr = unsafe { Pin::new_unchecked(&*self.curr) };
-            debug_assert_ne!(self.curr, curr.next(), "loops forever");
-            self.curr = curr.next();
-            res
+            Some(unsafe { StrongPinMut::new_unchecked(ptr) })
         }
     }
-}
 
-impl<'s, T: 's + ListNode> DoubleEndedIterator for IterPinMut<'s, T> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        if ptr::eq(self.last, self.curr) {
-            None
-        } else {
-            let last = unsafe { Pin::new_unchecked(&*self.last) };
-            debug_assert_ne!(self.last, last.prev(), "loops forever");
-            self.last = last.prev();
-            // Safe since `self.last` is a `ListEntry` contained inside a `T`.
-            let ptr = T::from_list_entry(self.last) as *mut T;
-            Some(unsafe { Pin::new_unchecked(&mut *ptr) })
+    /// Moves the cursor to the next node.
+    pub fn move_next(&mut self) {
+        self.curr = self.curr().next();
+    }
+
+    /// Moves the cursor to the previous node.
+    pub fn move_prev(&mut self) {
+        self.curr = self.curr().prev();
+    }
+
+    /// Removes the node at the cursor from the list, and moves the cursor to the next node.
+    /// Returns a raw pointer to the removed node, or `None` if the cursor is at the ghost position.
+    pub fn remove_current(&mut self) -> Option<*const T> {
+        if self.is_ghost() {
+            return None;
+        }
+        let curr = self.curr;
+        self.move_next();
+        // SAFETY: `curr` is an initialized `ListEntry` in the list.
+        unsafe { Pin::new_unchecked(&*curr) }.remove();
+        Some(T::from_list_entry(curr))
+    }
+
+    /// Inserts `elt` in front of the cursor after unlinking `elt`.
+    /// If the cursor is at the ghost position, `elt` becomes the back node.
+    /// Does nothing if `elt` is the node at the cursor.
+    pub fn insert_before(&mut self, elt: Pin<&T>) {
+        let elt = elt.get_list_entry();
+        if !ptr::eq(elt.get_ref(), self.curr) {
+            self.curr().push_back(elt);
+        }
+    }
+
+    /// Inserts `elt` at the back of the cursor after unlinking `elt`.
+    /// If the cursor is at the ghost position, `elt` becomes the front node.
+    /// Does nothing if `elt` is the node at the cursor.
+    pub fn insert_after(&mut self, elt: Pin<&T>) {
+        let elt = elt.get_list_entry();
+        if !ptr::eq(elt.get_ref(), self.curr) {
+            self.curr().push_front(elt);
         }
     }
 }
@@ -1,50 +1,165 @@
 //! Similar to `Arc<T>`, but is not allocated on heap.
 //! This type panics if it gets dropped before all `Ref<T>`/`RefMut<T>` drops.
+//!
+//! The counters are `AtomicUsize`s by default. With `Cell<usize>` instead, the counters are cheaper,
+//! but the `StaticArc` cannot be shared between threads.
+use core::cell::Cell;
 use core::ops::{Deref, DerefMut};
-use core::ptr::NonNull;
+use core::ptr::{self, NonNull};
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use super::strong_pin::StrongPinMut;
 
 const BORROWED_MUT: usize = usize::MAX;
 
+/// A counter of a `StaticArc`.
+pub trait Counter {
+    const ZERO: Self;
+
+    fn load(&self, order: Ordering) -> usize;
+
+    fn store(&self, val: usize, order: Ordering);
+
+    fn compare_exchange(
+        &self,
+        current: usize,
+        new: usize,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<usize, usize>;
+
+    fn fetch_add(&self, val: usize, order: Ordering) -> usize;
+
+    fn fetch_sub(&self, val: usize, order: Ordering) -> usize;
+}
+
+impl Counter for AtomicUsize {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: Self = AtomicUsize::new(0);
+
+    fn load(&self, order: Ordering) -> usize {
+        AtomicUsize::load(self, order)
+    }
+
+    fn store(&self, val: usize, order: Ordering) {
+        AtomicUsize::store(self, val, order)
+    }
+
+    fn compare_exchange(
+        &self,
+        current: usize,
+        new: usize,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<usize, usize> {
+        AtomicUsize::compare_exchange(self, current, new, success, failure)
+    }
+
+    fn fetch_add(&self, val: usize, order: Ordering) -> usize {
+        AtomicUsize::fetch_add(self, val, order)
+    }
+
+    fn fetch_sub(&self, val: usize, order: Ordering) -> usize {
+        AtomicUsize::fetch_sub(self, val, order)
+    }
+}
+
+/// Ignores the orderings, since a `Cell` is never shared between threads.
+impl Counter for Cell<usize> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: Self = Cell::new(0);
+
+    fn load(&self, _order: Ordering) -> usize {
+        self.get()
+    }
+
+    fn store(&self, val: usize, _order: Ordering) {
+        self.set(val)
+    }
+
+    fn compare_exchange(
+        &self,
+        current: usize,
+        new: usize,
+        _success: Ordering,
+        _failure: Ordering,
+    ) -> Result<usize, usize> {
+        let val = self.get();
+        if val == current {
+            self.set(new);
+            Ok(val)
+        } else {
+            Err(val)
+        }
+    }
+
+    fn fetch_add(&self, val: usize, _order: Ordering) -> usize {
+        self.replace(self.get().wrapping_add(val))
+    }
+
+    fn fetch_sub(&self, val: usize, _order: Ordering) -> usize {
+        self.replace(self.get().wrapping_sub(val))
+    }
+}
+
 /// # Safety
 ///
 /// * If `refcnt` equals `BORROWED_MUT`, a single `RefMut` refers to `self`.
 /// * If `refcnt` equals n where n < `BORROWED_MUT`, n `Ref`s refer to `self`.
 /// * `RefMut` can mutate both `data` and `refcnt`.
 /// * `Ref` can mutate `refcnt` and read `data`.
-pub struct StaticArc<T> {
+/// * `generation` changes only when `data` may be replaced, which needs a `StrongPinMut` and no
+///   `Ref` nor `RefMut`.
+pub struct StaticArc<T, R: Counter = AtomicUsize> {
     data: T,
-    refcnt: AtomicUsize,
+    refcnt: R,
+    generation: R,
 }
 
 /// # Safety
 ///
 /// * It holds a valid pointer.
 #[repr(transparent)]
-pub struct Ref<T>(NonNull<StaticArc<T>>);
+pub struct Ref<T, R: Counter = AtomicUsize>(NonNull<StaticArc<T, R>>);
 
 /// # Safety
 ///
 /// * It holds a valid pointer.
 #[repr(transparent)]
-pub struct RefMut<T>(NonNull<StaticArc<T>>);
+pub struct RefMut<T, R: Counter = AtomicUsize>(NonNull<StaticArc<T, R>>);
+
+/// A reference that does not keep the data of a `StaticArc`. It can be upgraded to a `Ref` as
+/// long as the data has not been replaced since the `Weak` was made.
+///
+/// # Safety
+///
+/// * It holds a valid pointer.
+pub struct Weak<T, R: Counter = AtomicUsize> {
+    ptr: NonNull<StaticArc<T, R>>,
+    generation: usize,
+}
 
-impl<T> StaticArc<T> {
+impl<T, R: Counter> StaticArc<T, R> {
     pub const fn new(data: T) -> Self {
         Self {
             data,
-            refcnt: AtomicUsize::new(0),
+            refcnt: R::ZERO,
+            generation: R::ZERO,
         }
     }
 
     #[allow(clippy::needless_lifetimes)]
-    fn rc<'s>(self: StrongPinMut<'s, Self>) -> &'s AtomicUsize {
+    fn rc<'s>(self: StrongPinMut<'s, Self>) -> &'s R {
         // SAFETY: invariant of StrongPinMut
         unsafe { &(*self.ptr().as_ptr()).refcnt }
     }
 
+    #[allow(clippy::needless_lifetimes)]
+    fn generation<'s>(self: StrongPinMut<'s, Self>) -> &'s R {
+        // SAFETY: invariant of StrongPinMut
+        unsafe { &(*self.ptr().as_ptr()).generation }
+    }
+
     pub fn is_borrowed(self: StrongPinMut<'_, Self>) -> bool {
         self.rc().load(Ordering::Acquire) > 0
     }
@@ -55,17 +170,19 @@ impl<T> StaticArc<T> {
             None
         } else {
             // SAFETY: no `Ref` nor `RefMut` points to `self`.
-            Some(unsafe { &mut (*self.ptr().as_ptr()).data })
+            Some(unsafe { self.get_mut_unchecked() })
         }
     }
 
+    /// Invalidates the `Weak`s to `self`, since the returned reference may replace the data.
     #[allow(clippy::needless_lifetimes)]
-    pub unsafe fn get_mut_unchecked<'s>(self: StrongPinMut<'s, Self>) -> &'s mut T {
+    pub unsafe fn get_mut_unchecked<'s>(mut self: StrongPinMut<'s, Self>) -> &'s mut T {
+        let _ = self.as_mut().generation().fetch_add(1, Ordering::Relaxed);
         // SAFETY: no `Ref` nor `RefMut` points to `self`.
         unsafe { &mut (*self.ptr().as_ptr()).data }
     }
 
-    pub fn try_borrow(mut self: StrongPinMut<'_, Self>) -> Option<Ref<T>> {
+    pub fn try_borrow(mut self: StrongPinMut<'_, Self>) -> Option<Ref<T, R>> {
         loop {
             let r = self.as_mut().rc().load(Ordering::Acquire);
 
@@ -84,17 +201,30 @@ impl<T> StaticArc<T> {
         }
     }
 
-    pub fn borrow(self: StrongPinMut<'_, Self>) -> Ref<T> {
+    pub fn borrow(self: StrongPinMut<'_, Self>) -> Ref<T, R> {
         self.try_borrow().expect("already mutably borrowed")
     }
 
-    pub unsafe fn borrow_unchecked(mut self: StrongPinMut<'_, Self>) -> Ref<T> {
+    pub unsafe fn borrow_unchecked(mut self: StrongPinMut<'_, Self>) -> Ref<T, R> {
         let _ = self.as_mut().rc().fetch_add(1, Ordering::Relaxed);
         Ref(self.ptr())
     }
+
+    /// Borrows `self` if the data has not been replaced since `weak` was made.
+    ///
+    /// # Panics
+    ///
+    /// `weak` must refer to `self`.
+    pub fn upgrade(mut self: StrongPinMut<'_, Self>, weak: &Weak<T, R>) -> Option<Ref<T, R>> {
+        assert!(ptr::eq(self.ptr().as_ptr(), weak.ptr.as_ptr()));
+        if self.as_mut().generation().load(Ordering::Relaxed) != weak.generation {
+            return None;
+        }
+        self.try_borrow()
+    }
 }
 
-impl<T> Drop for StaticArc<T> {
+impl<T, R: Counter> Drop for StaticArc<T, R> {
     fn drop(&mut self) {
         assert_eq!(
             self.refcnt.load(Ordering::Acquire),
@@ -104,13 +234,42 @@ impl<T> Drop for StaticArc<T> {
     }
 }
 
-impl<T> Ref<T> {
-    fn rc(&self) -> &AtomicUsize {
+impl<T, R: Counter> Ref<T, R> {
+    fn rc(&self) -> &R {
         // SAFETY: invariant
         unsafe { &(*self.0.as_ptr()).refcnt }
     }
 
-    pub fn into_mut(self) -> Result<RefMut<T>, Self> {
+    /// Makes a `Weak` to the data of `self`.
+    pub fn downgrade(&self) -> Weak<T, R> {
+        // SAFETY: invariant. The generation does not change while `self` is alive.
+        let generation = unsafe { &(*self.0.as_ptr()).generation }.load(Ordering::Relaxed);
+        Weak {
+            ptr: self.0,
+            generation,
+        }
+    }
+
+    /// Drops `self` if it is not the only `Ref`.
+    /// Returns Err(self) otherwise.
+    pub fn try_drop(self) -> Result<(), Self> {
+        let mut r = self.rc().load(Ordering::Relaxed);
+        while r > 1 {
+            match self
+                .rc()
+                .compare_exchange(r, r - 1, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    core::mem::forget(self);
+                    return Ok(());
+                }
+                Err(actual) => r = actual,
+            }
+        }
+        Err(self)
+    }
+
+    pub fn into_mut(self) -> Result<RefMut<T, R>, Self> {
         if self
             .rc()
             .compare_exchange(1, BORROWED_MUT, Ordering::Relaxed, Ordering::Relaxed)
@@ -125,7 +284,7 @@ impl<T> Ref<T> {
     }
 }
 
-impl<T> Deref for Ref<T> {
+impl<T, R: Counter> Deref for Ref<T, R> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -134,31 +293,31 @@ impl<T> Deref for Ref<T> {
     }
 }
 
-impl<T> Clone for Ref<T> {
+impl<T, R: Counter> Clone for Ref<T, R> {
     fn clone(&self) -> Self {
         let _ = self.rc().fetch_add(1, Ordering::Relaxed);
         Self(self.0)
     }
 }
 
-impl<T> Drop for Ref<T> {
+impl<T, R: Counter> Drop for Ref<T, R> {
     fn drop(&mut self) {
         let _ = self.rc().fetch_sub(1, Ordering::Release);
     }
 }
 
-impl<T> RefMut<T> {
-    fn rc(&self) -> &AtomicUsize {
+impl<T, R: Counter> RefMut<T, R> {
+    fn rc(&self) -> &R {
         // SAFETY: invariant
         unsafe { &(*self.0.as_ptr()).refcnt }
     }
 
-    pub fn cell(&self) -> *mut StaticArc<T> {
+    pub fn cell(&self) -> *mut StaticArc<T, R> {
         self.0.as_ptr()
     }
 }
 
-impl<T> Deref for RefMut<T> {
+impl<T, R: Counter> Deref for RefMut<T, R> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -167,15 +326,30 @@ impl<T> Deref for RefMut<T> {
     }
 }
 
-impl<T> DerefMut for RefMut<T> {
+impl<T, R: Counter> DerefMut for RefMut<T, R> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         // SAFETY: `RefMut` can mutate `data`.
         unsafe { &mut (*self.0.as_ptr()).data }
     }
 }
 
-impl<T> Drop for RefMut<T> {
+impl<T, R: Counter> Drop for RefMut<T, R> {
     fn drop(&mut self) {
         self.rc().store(0, Ordering::Release);
     }
 }
+
+impl<T, R: Counter> Weak<T, R> {
+    pub fn cell(&self) -> *mut StaticArc<T, R> {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T, R: Counter> Clone for Weak<T, R> {
+    fn clone(&self) -> Self {
+        Self {
+            ptr: self.ptr,
+            generation: self.generation,
+        }
+    }
+}
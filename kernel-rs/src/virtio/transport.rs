@@ -0,0 +1,334 @@
+//! The virtio mmio transport, shared by the drivers of all virtio devices.
+//!
+//! A driver initializes its device by the sequence of the spec's Section 3.1.1: it resets the
+//! device, acknowledges it, negotiates the features, sets up the virtqueues, and finally tells the
+//! device that the driver is ready. Version 1 of the mmio interface is the "legacy" one, and
+//! version 2 is the modern (1.0+) one, which requires `F_VERSION_1` and takes the address of each
+//! ring of a virtqueue instead of a single page number.
+
+// virtio mmio control registers, from qemu virtio_mmio.h
+
+use core::ptr;
+
+use bitflags::bitflags;
+
+use super::virtqueue::NUM;
+use crate::addr::{PGSHIFT, PGSIZE};
+
+/// Memory mapped IO registers, as offsets from the base of a device.
+/// The kernel and virtio driver communicates to each other using these registers.
+///
+/// # Safety
+///
+/// * The `GuestPageSize` should be set to the page size of the guest architecture.
+/// * All queues should be correctly initialized.
+#[repr(usize)]
+#[derive(Copy, Clone)]
+enum MmioRegs {
+    /// 0x74726976
+    MagicValue = 0x000,
+    /// version; 1 is legacy, 2 is modern
+    Version = 0x004,
+    /// device type, as `DeviceType`
+    DeviceId = 0x008,
+    /// 0x554d4551
+    VendorId = 0x00c,
+    DeviceFeatures = 0x010,
+    /// selects the 32 bits of `DeviceFeatures`, write-only
+    DeviceFeaturesSel = 0x014,
+    DriverFeatures = 0x020,
+    /// selects the 32 bits of `DriverFeatures`, write-only
+    DriverFeaturesSel = 0x024,
+    /// page size for PFN, write-only (legacy)
+    GuestPageSize = 0x028,
+    /// select queue, write-only
+    QueueSel = 0x030,
+    /// max size of current queue, read-only
+    QueueNumMax = 0x034,
+    /// size of current queue, write-only
+    QueueNum = 0x038,
+    /// physical page number for queue, read/write (legacy)
+    QueuePfn = 0x040,
+    /// ready bit (modern)
+    QueueReady = 0x044,
+    /// write-only
+    QueueNotify = 0x050,
+    /// read-only
+    InterruptStatus = 0x060,
+    /// write-only
+    InterruptAck = 0x064,
+    /// read/write
+    Status = 0x070,
+    /// physical address of the descriptor table, write-only (modern)
+    QueueDescLow = 0x080,
+    QueueDescHigh = 0x084,
+    /// physical address of the avail ring, write-only (modern)
+    QueueDriverLow = 0x090,
+    QueueDriverHigh = 0x094,
+    /// physical address of the used ring, write-only (modern)
+    QueueDeviceLow = 0x0a0,
+    QueueDeviceHigh = 0x0a4,
+    /// device-specific configuration space
+    Config = 0x100,
+}
+
+/// Types of virtio devices.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DeviceType {
+    Net = 1,
+    Block = 2,
+    Console = 3,
+    Entropy = 4,
+    NineP = 9,
+}
+
+bitflags! {
+    /// Status register bits, from qemu virtio_config.h
+    pub struct VirtIOStatus: u32 {
+        const ACKNOWLEDGE = 0b0001;
+        const DRIVER = 0b0010;
+        const DRIVER_OK = 0b0100;
+        const FEATURES_OK = 0b1000;
+
+        /// The driver gave up on the device.
+        const FAILED = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    // Device feature bits
+    pub struct VirtIOFeatures: u64 {
+        /// Console size is in the configuration
+        const CONSOLE_F_SIZE = 1 << 0;
+
+        /// Console has multiple ports, added by control messages
+        const CONSOLE_F_MULTIPORT = 1 << 1;
+
+        /// Disk is read-only
+        const BLK_F_RO = 1 << 5;
+
+        /// Supports scsi command passthru
+        const BLK_F_SCSI = 1 << 7;
+
+        /// Cache flush command support
+        const BLK_F_FLUSH = 1 << 9;
+
+        /// Writeback mode available in config
+        const BLK_F_CONFIG_WCE = 1 << 11;
+
+        /// support more than one vq
+        const BLK_F_MQ = 1 << 12;
+
+        const F_ANY_LAYOUT = 1 << 27;
+        const RING_F_INDIRECT_DESC = 1 << 28;
+        const RING_F_EVENT_IDX = 1 << 29;
+
+        /// Complies with the version 1.0 of the spec, rather than the legacy interface
+        const F_VERSION_1 = 1 << 32;
+    }
+}
+
+/// The mmio registers of a virtio device.
+///
+/// # Safety
+///
+/// The kernel can access [base..base+PGSIZE), the registers of a virtio device.
+#[derive(Debug)]
+pub struct VirtioMmio {
+    base: usize,
+}
+
+impl VirtioMmio {
+    /// # Safety
+    ///
+    /// The kernel must be able to access [base..base+PGSIZE), the registers of a virtio device.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    fn read(&self, reg: MmioRegs) -> u32 {
+        // SAFETY:
+        // * `src` is valid, as the kernel can access [base..base+PGSIZE).
+        // * `src` is properly aligned, as reg % 4 == 0.
+        // * `src` points to a properly initialized value, as u32 does not have
+        //   any internal structure to be initialized.
+        // * volatile concurrent accesses are safe.
+        //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
+        unsafe { ptr::read_volatile((self.base as *mut u8).add(reg as _) as _) }
+    }
+
+    /// # Safety
+    ///
+    /// Writing at memory mapped registers may cause hardware side effects.
+    /// For example, after writing at `QueueNotify`, the virtio driver reads/writes the address given by the kernel.
+    /// If a wrong address was given, this could lead to undefined behavior.
+    unsafe fn write(&self, reg: MmioRegs, value: u32) {
+        // SAFETY:
+        // * `dst` is valid, as the kernel can access [base..base+PGSIZE).
+        // * `dst` is properly aligned, as reg % 4 == 0.
+        // * volatile concurrent accesses are safe.
+        //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
+        unsafe { ptr::write_volatile((self.base as *mut u8).add(reg as _) as _, value) }
+    }
+
+    /// Returns true if the registers belong to a virtio device of typ, with either interface.
+    pub fn probe(&self, typ: DeviceType) -> bool {
+        self.read(MmioRegs::MagicValue) == 0x74726976
+            && matches!(self.read(MmioRegs::Version), 1 | 2)
+            && self.read(MmioRegs::DeviceId) == typ as u32
+            && self.read(MmioRegs::VendorId) == 0x554d4551
+    }
+
+    /// Does the device have the modern interface?
+    fn is_modern(&self) -> bool {
+        self.read(MmioRegs::Version) == 2
+    }
+
+    fn status(&self) -> VirtIOStatus {
+        VirtIOStatus::from_bits_truncate(self.read(MmioRegs::Status))
+    }
+
+    /// Sets the virtio status.
+    fn set_status(&self, status: VirtIOStatus) {
+        // SAFETY: simply setting status bits does not cause side effects.
+        unsafe {
+            self.write(MmioRegs::Status, status.bits());
+        }
+    }
+
+    /// Returns the device's virtio features. A legacy device offers only the low 32 bits.
+    fn device_features(&self) -> VirtIOFeatures {
+        // SAFETY: simply selecting features bits does not cause side effects.
+        let low = unsafe {
+            self.write(MmioRegs::DeviceFeaturesSel, 0);
+            self.read(MmioRegs::DeviceFeatures)
+        };
+        let high = unsafe {
+            self.write(MmioRegs::DeviceFeaturesSel, 1);
+            self.read(MmioRegs::DeviceFeatures)
+        };
+        VirtIOFeatures::from_bits_truncate(((high as u64) << 32) | low as u64)
+    }
+
+    /// Sets the driver's virtio features.
+    fn set_driver_features(&self, features: VirtIOFeatures) {
+        // SAFETY: simply setting features bits does not cause side effects.
+        unsafe {
+            self.write(MmioRegs::DriverFeaturesSel, 0);
+            self.write(MmioRegs::DriverFeatures, features.bits() as u32);
+            self.write(MmioRegs::DriverFeaturesSel, 1);
+            self.write(MmioRegs::DriverFeatures, (features.bits() >> 32) as u32);
+        }
+    }
+
+    /// Resets the device, and negotiates its features: the driver accepts those of wanted that
+    /// the device offers, and `F_VERSION_1` of a modern device. Returns Ok(accepted features), or
+    /// Err(()) if the device refuses them, in which case the device is marked failed.
+    pub fn negotiate(&self, wanted: VirtIOFeatures) -> Result<VirtIOFeatures, ()> {
+        self.reset();
+        let mut status = VirtIOStatus::ACKNOWLEDGE;
+        self.set_status(status);
+        status.insert(VirtIOStatus::DRIVER);
+        self.set_status(status);
+
+        let features = self.device_features() & (wanted | VirtIOFeatures::F_VERSION_1);
+        // A modern device does not work with a driver of the legacy interface.
+        if self.is_modern() && !features.contains(VirtIOFeatures::F_VERSION_1) {
+            self.set_status(status | VirtIOStatus::FAILED);
+            return Err(());
+        }
+        self.set_driver_features(features);
+
+        // Tell device that feature negotiation is complete. A device that
+        // cannot work with the features clears FEATURES_OK.
+        status.insert(VirtIOStatus::FEATURES_OK);
+        self.set_status(status);
+        if !self.status().contains(VirtIOStatus::FEATURES_OK) {
+            self.set_status(status | VirtIOStatus::FAILED);
+            return Err(());
+        }
+        Ok(features)
+    }
+
+    /// Initializes the queue `queue_num` of NUM descriptors, given the physical addresses of its
+    /// descriptor table, avail ring, and used ring. The legacy interface only takes the page of
+    /// the descriptor table, so the avail ring must follow the table, and the used ring must
+    /// begin at the next page.
+    ///
+    /// # Safety
+    ///
+    /// The virtio driver will later use this info to read/write descriptors.
+    /// Hence, the caller must give correct info.
+    pub unsafe fn init_queue(&self, queue_num: u32, desc: usize, avail: usize, used: usize) {
+        // SAFETY: simply selecting the queue does not cause side effects.
+        unsafe {
+            self.write(MmioRegs::QueueSel, queue_num);
+        }
+        let max = self.read(MmioRegs::QueueNumMax);
+        assert!(max != 0, "virtio device has no queue {}", queue_num);
+        assert!(max >= NUM as u32, "virtio device max queue too short");
+
+        unsafe {
+            self.write(MmioRegs::QueueNum, NUM as _);
+            if self.is_modern() {
+                self.write(MmioRegs::QueueDescLow, desc as u32);
+                self.write(MmioRegs::QueueDescHigh, (desc >> 32) as u32);
+                self.write(MmioRegs::QueueDriverLow, avail as u32);
+                self.write(MmioRegs::QueueDriverHigh, (avail >> 32) as u32);
+                self.write(MmioRegs::QueueDeviceLow, used as u32);
+                self.write(MmioRegs::QueueDeviceHigh, (used >> 32) as u32);
+                self.write(MmioRegs::QueueReady, 1);
+            } else {
+                assert!(
+                    desc % PGSIZE == 0 && used == desc + PGSIZE,
+                    "virtio legacy queue layout"
+                );
+                self.write(MmioRegs::GuestPageSize, PGSIZE as _);
+                self.write(MmioRegs::QueuePfn, (desc >> PGSHIFT) as _);
+            }
+        }
+    }
+
+    /// Tells the device that the driver is completely ready.
+    pub fn driver_ok(&self) {
+        self.set_status(self.status() | VirtIOStatus::DRIVER_OK);
+    }
+
+    /// Resets the device, so that it stops accessing the queues.
+    pub fn reset(&self) {
+        self.set_status(VirtIOStatus::empty());
+    }
+
+    /// Notifies the given queue number.
+    ///
+    /// # Safety
+    ///
+    /// After notifying the queue, the driver will try to access the queue and read/write at the addresses given through descriptors.
+    /// This may cause undefined behavior if the descriptors were not well set or contains wrong addresses.
+    pub unsafe fn notify(&self, queue_num: u32) {
+        unsafe {
+            self.write(MmioRegs::QueueNotify, queue_num);
+        }
+    }
+
+    /// Acknowledges all interrupts.
+    pub fn ack_intr(&self) {
+        let intr_status = self.read(MmioRegs::InterruptStatus) & 0x3;
+        // SAFETY: simply acknowledging interrupts does not cause undefined behavior.
+        unsafe {
+            self.write(MmioRegs::InterruptAck, intr_status);
+        }
+    }
+
+    /// Reads the 32 bits at offset of the device-specific configuration space.
+    pub fn config(&self, offset: usize) -> u32 {
+        assert!(offset % 4 == 0, "VirtioMmio::config");
+        // SAFETY: the configuration space lies within [base..base+PGSIZE),
+        // and the same reasons as `read` apply.
+        unsafe {
+            ptr::read_volatile(
+                (self.base as *mut u8).add(MmioRegs::Config as usize + offset) as *const u32,
+            )
+        }
+    }
+}
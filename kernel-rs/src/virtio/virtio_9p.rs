@@ -0,0 +1,183 @@
+/// Driver for qemu's virtio 9p transport, which carries the messages of the 9p client file system
+/// to a directory of the host. The driver sends one request at a time and sleeps until the device
+/// replies, so it needs interrupts.
+///
+/// qemu ... -fsdev local,id=fs0,path=<dir>,security_model=mapped-xattr
+///          -device virtio-9p-device,fsdev=fs0,mount_tag=rv6,bus=virtio-mmio-bus.3
+use core::cmp;
+use core::pin::Pin;
+
+use pin_project::pin_project;
+
+use super::{
+    transport::{DeviceType, VirtIOFeatures, VirtioMmio},
+    virtqueue::Virtqueue,
+};
+use crate::{
+    arch::interface::MemLayout,
+    arch::TargetArch,
+    kernel::KernelRef,
+    lock::{SleepableLock, SleepableLockGuard},
+    proc::KernelCtx,
+};
+
+/// Size of the request and reply buffers, and so the largest message the client proposes.
+pub const NINEP_MSIZE: usize = 8192;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    /// The buffers are free.
+    Idle,
+    /// The device owns the buffers, until it replies.
+    Sent,
+    /// The reply is in `resp`, waiting for its sender to read it.
+    Replied,
+}
+
+#[pin_project]
+pub struct Virtio9p {
+    /// The queue of requests.
+    #[pin]
+    vq: Virtqueue,
+
+    mmio: VirtioMmio,
+
+    /// The request being sent.
+    req: [u8; NINEP_MSIZE],
+
+    /// The reply of the request.
+    resp: [u8; NINEP_MSIZE],
+
+    /// Bytes the device wrote into `resp`.
+    resp_len: usize,
+
+    state: State,
+
+    /// Is there the device?
+    present: bool,
+}
+
+impl Virtio9p {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `Virtio9p::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            vq: Virtqueue::new(),
+            // SAFETY: the kernel maps the registers of the 9p transport at VIRTIO3.
+            mmio: unsafe { VirtioMmio::new(TargetArch::VIRTIO3) },
+            req: [0; NINEP_MSIZE],
+            resp: [0; NINEP_MSIZE],
+            resp_len: 0,
+            state: State::Idle,
+            present: false,
+        }
+    }
+
+    /// Initializes the device if there is one.
+    pub fn init(mut self: Pin<&mut Self>) {
+        if !self.mmio.probe(DeviceType::NineP) {
+            return;
+        }
+
+        // The client attaches to the exported directory regardless of its mount tag.
+        if self.mmio.negotiate(VirtIOFeatures::empty()).is_err() {
+            return;
+        }
+
+        // Initialize queue 0.
+        let this = self.as_mut().project();
+        this.vq.into_ref().init(this.mmio, 0);
+
+        // Tell device we're completely ready.
+        self.mmio.driver_ok();
+        *self.project().present = true;
+    }
+
+    /// Hands the len bytes of `req` to the device, which writes the reply into `resp`.
+    fn send(self: Pin<&mut Self>, len: usize) {
+        let mut this = self.project();
+        let desc = this.vq.as_mut().alloc(2).expect("Virtio9p::send");
+        // Device reads the request, and writes the reply.
+        this.vq
+            .as_mut()
+            .set(&desc[0], this.req.as_ptr() as _, len, false, Some(&desc[1]));
+        this.vq.as_mut().set(
+            &desc[1],
+            this.resp.as_mut_ptr() as _,
+            NINEP_MSIZE,
+            true,
+            None,
+        );
+        this.vq.submit(desc);
+        *this.state = State::Sent;
+
+        // SAFETY: the buffers are in the driver, which is pinned, and nobody
+        // touches them until the device replies.
+        unsafe {
+            this.mmio.notify(0);
+        }
+    }
+
+    pub fn intr(guard: &mut SleepableLockGuard<'_, Self>, kernel: KernelRef<'_, '_>) {
+        // The device won't raise another interrupt until we tell it
+        // we've seen this interrupt.
+        guard.mmio.ack_intr();
+
+        let mut this = guard.get_pin_mut().project();
+        while let Some((head, len)) = this.vq.as_mut().pop_used() {
+            this.vq.as_mut().free_chain(head, |_| ());
+            *this.resp_len = cmp::min(len, NINEP_MSIZE);
+            *this.state = State::Replied;
+        }
+
+        // Wake up the sender of the request.
+        guard.wakeup(kernel);
+    }
+}
+
+impl SleepableLock<Virtio9p> {
+    /// Is there the device?
+    pub fn present(self: Pin<&Self>) -> bool {
+        self.pinned_lock().present
+    }
+
+    /// Sends the request that fill writes into the buffer it is given, returning its length, and
+    /// waits for the reply, which is given to reply.
+    /// Returns Ok(the result of reply), or Err(()) if there is no device.
+    pub fn transact<F, R, T>(
+        self: Pin<&Self>,
+        fill: F,
+        reply: R,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<T, ()>
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+        R: FnOnce(&[u8]) -> T,
+    {
+        let mut guard = self.pinned_lock();
+        if !guard.present {
+            return Err(());
+        }
+
+        // Requests share the buffers, so they are sent one at a time.
+        guard.wait_while(|this| this.state != State::Idle, ctx);
+        let len = fill(&mut guard.get_pin_mut().project().req[..]);
+        guard.get_pin_mut().send(len);
+
+        // The device owns the buffers until it replies, so this wait cannot be interrupted even if
+        // the process is killed.
+        guard.wait_while(|this| this.state == State::Sent, ctx);
+        let res = reply(&guard.resp[..guard.resp_len]);
+        *guard.get_pin_mut().project().state = State::Idle;
+
+        // Wake up the processes waiting to send their requests.
+        guard.wakeup(ctx.kernel());
+        Ok(res)
+    }
+
+    /// Resets the device, so that it stops accessing the queue before the machine restarts.
+    pub fn reset(self: Pin<&Self>) {
+        self.pinned_lock().mmio.reset();
+    }
+}
@@ -0,0 +1,535 @@
+/// Driver for qemu's virtio console device, whose ports are /dev/vport0 to /dev/vportN.
+/// A port is a byte stream to a character device of the host, such as a file or a socket, so a
+/// benchmark harness can talk to the machine without going through the uart.
+///
+/// With the multiport feature, the device adds and removes ports by control messages, and port
+/// n exists only if qemu has a virtserialport with nr=n. Without it, there is only port 0.
+///
+/// qemu ... -device virtio-serial-device,bus=virtio-mmio-bus.2,max_ports=4
+///          -chardev file,id=vport1,path=vport1.out -device virtserialport,chardev=vport1,nr=1
+use core::cmp;
+use core::mem;
+use core::pin::Pin;
+use core::ptr;
+
+use array_macro::array;
+use arrayvec::ArrayVec;
+use pin_project::pin_project;
+use static_assertions::const_assert;
+
+use super::{
+    transport::{DeviceType, VirtIOFeatures, VirtioMmio},
+    virtqueue::{Descriptor, Virtqueue, NUM},
+};
+use crate::{
+    addr::UVAddr,
+    arch::interface::MemLayout,
+    arch::TargetArch,
+    file::Devsw,
+    hal::hal,
+    kernel::KernelRef,
+    lock::{SleepableLock, SleepableLockGuard},
+    param::{NDEV, NVPORT},
+    proc::KernelCtx,
+    some_or,
+};
+
+/// Major device number of port 0. Port n uses the next ones.
+const VPORT_DEVSW: usize = 10;
+
+// `register_devices` registers up to four ports.
+const_assert!(NVPORT <= 4 && VPORT_DEVSW + NVPORT <= NDEV);
+
+/// Size of the receive and transmit buffers of a port, and so the most bytes a read() or a
+/// message of a write() moves.
+const PORT_BUF: usize = 128;
+
+/// Size of the buffer of a received control message.
+const CONTROL_BUF: usize = 64;
+
+/// Queues of the control messages. The receive and transmit queues of port 0 come before them,
+/// and those of the other ports after them.
+const CONTROL_RX: usize = 2;
+const CONTROL_TX: usize = 3;
+const NQUEUE: usize = 2 * NVPORT + 2;
+
+/// Control events
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+const VIRTIO_CONSOLE_DEVICE_ADD: u16 = 1;
+const VIRTIO_CONSOLE_DEVICE_REMOVE: u16 = 2;
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+
+#[pin_project]
+pub struct VirtioConsole {
+    /// The receive and transmit queues of the ports and of the control messages.
+    #[pin]
+    queues: [Virtqueue; NQUEUE],
+
+    ports: [Port; NVPORT],
+
+    /// Received control messages. One-for-one with descriptors, for convenience.
+    control_in: [[u8; CONTROL_BUF]; NUM],
+
+    /// Control messages being sent. One-for-one with descriptors, for convenience.
+    control_out: [VirtIOConsoleControl; NUM],
+
+    mmio: VirtioMmio,
+
+    /// Number of ports whose queues are set up.
+    nport: usize,
+
+    /// Does the device send control messages?
+    multiport: bool,
+}
+
+struct Port {
+    /// Has the device added the port?
+    present: bool,
+
+    /// Received bytes, of which rx[r..w] are left to read.
+    rx: [u8; PORT_BUF],
+    r: usize,
+    w: usize,
+
+    /// Does the device own rx, to receive bytes into it?
+    rx_posted: bool,
+
+    /// Bytes being sent.
+    tx: [u8; PORT_BUF],
+
+    /// Does the device own tx, until it has sent the bytes?
+    tx_busy: bool,
+}
+
+/// A control message, from the spec.
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VirtIOConsoleControl {
+    /// port number
+    id: u32,
+    event: u16,
+    value: u16,
+}
+
+/// Index of the receive queue of port. The transmit queue follows it.
+const fn rx_queue(port: usize) -> usize {
+    if port == 0 {
+        0
+    } else {
+        2 * port + 2
+    }
+}
+
+/// Returns the queue i of queues.
+fn queue(queues: Pin<&mut [Virtqueue; NQUEUE]>, i: usize) -> Pin<&mut Virtqueue> {
+    // SAFETY: the elements of a pinned array are pinned too, as they never
+    // move out of it.
+    unsafe { queues.map_unchecked_mut(|queues| &mut queues[i]) }
+}
+
+impl Port {
+    const fn new() -> Self {
+        Self {
+            present: false,
+            rx: [0; PORT_BUF],
+            r: 0,
+            w: 0,
+            rx_posted: false,
+            tx: [0; PORT_BUF],
+            tx_busy: false,
+        }
+    }
+}
+
+impl VirtIOConsoleControl {
+    const fn new() -> Self {
+        Self {
+            id: 0,
+            event: 0,
+            value: 0,
+        }
+    }
+}
+
+impl VirtioConsole {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `VirtioConsole::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            queues: array![_ => Virtqueue::new(); NQUEUE],
+            ports: array![_ => Port::new(); NVPORT],
+            control_in: [[0; CONTROL_BUF]; NUM],
+            control_out: [VirtIOConsoleControl::new(); NUM],
+            // SAFETY: the kernel maps the registers of the console at VIRTIO2.
+            mmio: unsafe { VirtioMmio::new(TargetArch::VIRTIO2) },
+            nport: 0,
+            multiport: false,
+        }
+    }
+
+    /// Initializes the device if there is one.
+    pub fn init(mut self: Pin<&mut Self>) {
+        if !self.mmio.probe(DeviceType::Console) {
+            return;
+        }
+        let features = some_or!(
+            self.mmio
+                .negotiate(VirtIOFeatures::CONSOLE_F_MULTIPORT)
+                .ok(),
+            return
+        );
+        let multiport = features.contains(VirtIOFeatures::CONSOLE_F_MULTIPORT);
+        let nport = if multiport {
+            // max_nr_ports, which follows the columns and rows
+            cmp::min(self.mmio.config(4) as usize, NVPORT)
+        } else {
+            1
+        };
+
+        let mut this = self.as_mut().project();
+        *this.nport = nport;
+        *this.multiport = multiport;
+        // Each receive queue is followed by its transmit queue.
+        let rx_queues = (0..nport)
+            .map(rx_queue)
+            .chain(multiport.then(|| CONTROL_RX));
+        for i in rx_queues.flat_map(|i| i..i + 2) {
+            queue(this.queues.as_mut(), i)
+                .into_ref()
+                .init(this.mmio, i as u32);
+        }
+
+        // Tell device we're completely ready.
+        this.mmio.driver_ok();
+
+        if multiport {
+            for _ in 0..NUM {
+                self.as_mut().post_control();
+            }
+            self.send_control(0, VIRTIO_CONSOLE_DEVICE_READY, 1);
+        } else {
+            self.add_port(0);
+        }
+    }
+
+    /// Allocates a descriptor of the queue i.
+    fn alloc(self: Pin<&mut Self>, i: usize) -> Option<ArrayVec<Descriptor, NUM>> {
+        queue(self.project().queues, i).alloc(1)
+    }
+
+    /// Hands the len bytes at addr to the device through the queue i, in the descriptor desc.
+    /// The device writes them if `device_writes` is true, and reads them otherwise.
+    fn submit(
+        self: Pin<&mut Self>,
+        i: usize,
+        desc: ArrayVec<Descriptor, NUM>,
+        addr: usize,
+        len: usize,
+        device_writes: bool,
+    ) {
+        let this = self.project();
+        let mut vq = queue(this.queues, i);
+        vq.as_mut().set(&desc[0], addr, len, device_writes, None);
+        vq.submit(desc);
+
+        // SAFETY: addr is in the console, which is pinned, and the driver
+        // does not touch the bytes until the device is done with them.
+        unsafe {
+            this.mmio.notify(i as u32);
+        }
+    }
+
+    /// Gives a buffer for a control message to the device.
+    fn post_control(mut self: Pin<&mut Self>) {
+        let desc = self
+            .as_mut()
+            .alloc(CONTROL_RX)
+            .expect("VirtioConsole::post_control");
+        let addr = self.control_in[desc[0].idx()].as_ptr() as usize;
+        self.submit(CONTROL_RX, desc, addr, CONTROL_BUF, true);
+    }
+
+    /// Sends a control message about the port id. The message is dropped if the device has not
+    /// taken the earlier ones yet.
+    fn send_control(mut self: Pin<&mut Self>, id: usize, event: u16, value: u16) {
+        let desc = match self.as_mut().alloc(CONTROL_TX) {
+            Some(desc) => desc,
+            None => {
+                let _ = self.as_mut().reclaim(CONTROL_TX);
+                some_or!(self.as_mut().alloc(CONTROL_TX), return)
+            }
+        };
+        let msg = &mut self.as_mut().project().control_out[desc[0].idx()];
+        *msg = VirtIOConsoleControl {
+            id: id as u32,
+            event,
+            value,
+        };
+        let addr = msg as *const _ as usize;
+        self.submit(
+            CONTROL_TX,
+            desc,
+            addr,
+            mem::size_of::<VirtIOConsoleControl>(),
+            false,
+        );
+    }
+
+    /// Frees the descriptors of the queue i that the device is done with.
+    /// Returns true if there were any.
+    fn reclaim(self: Pin<&mut Self>, i: usize) -> bool {
+        let mut vq = queue(self.project().queues, i);
+        let mut any = false;
+        while let Some((head, _)) = vq.as_mut().pop_used() {
+            vq.as_mut().free_chain(head, |_| ());
+            any = true;
+        }
+        any
+    }
+
+    /// Gives the receive buffer of port to the device.
+    fn post_rx(mut self: Pin<&mut Self>, port: usize) {
+        let desc = self
+            .as_mut()
+            .alloc(rx_queue(port))
+            .expect("VirtioConsole::post_rx");
+        let this = self.as_mut().project();
+        this.ports[port].rx_posted = true;
+        let addr = this.ports[port].rx.as_ptr() as usize;
+        self.submit(rx_queue(port), desc, addr, PORT_BUF, true);
+    }
+
+    fn add_port(mut self: Pin<&mut Self>, port: usize) {
+        let p = &mut self.as_mut().project().ports[port];
+        p.present = true;
+        p.r = 0;
+        p.w = 0;
+        if !p.rx_posted {
+            self.post_rx(port);
+        }
+    }
+
+    /// Handles a control message of the device.
+    fn control(mut self: Pin<&mut Self>, msg: VirtIOConsoleControl) {
+        let port = msg.id as usize;
+        match msg.event {
+            VIRTIO_CONSOLE_DEVICE_ADD => {
+                let ready = port < self.nport;
+                if ready {
+                    self.as_mut().add_port(port);
+                }
+                self.as_mut()
+                    .send_control(port, VIRTIO_CONSOLE_PORT_READY, ready as u16);
+                // The ports stay open as long as the device has them.
+                if ready {
+                    self.send_control(port, VIRTIO_CONSOLE_PORT_OPEN, 1);
+                }
+            }
+            VIRTIO_CONSOLE_DEVICE_REMOVE => {
+                if port < self.nport {
+                    self.project().ports[port].present = false;
+                }
+            }
+            // The others only inform the driver.
+            _ => (),
+        }
+    }
+
+    /// Sends bytes, which fit in the transmit buffer, through port.
+    fn send(mut self: Pin<&mut Self>, port: usize, bytes: &[u8]) {
+        let desc = self
+            .as_mut()
+            .alloc(rx_queue(port) + 1)
+            .expect("VirtioConsole::send");
+        let p = &mut self.as_mut().project().ports[port];
+        p.tx[..bytes.len()].copy_from_slice(bytes);
+        p.tx_busy = true;
+        let addr = p.tx.as_ptr() as usize;
+        self.submit(rx_queue(port) + 1, desc, addr, bytes.len(), false);
+    }
+
+    pub fn intr(guard: &mut SleepableLockGuard<'_, Self>, kernel: KernelRef<'_, '_>) {
+        // The device won't raise another interrupt until we tell it
+        // we've seen this interrupt.
+        guard.mmio.ack_intr();
+
+        let mut this = guard.get_pin_mut();
+        if this.multiport {
+            let _ = this.as_mut().reclaim(CONTROL_TX);
+            while let Some((head, len)) =
+                queue(this.as_mut().project().queues, CONTROL_RX).pop_used()
+            {
+                queue(this.as_mut().project().queues, CONTROL_RX).free_chain(head, |_| ());
+                // SAFETY: the buffer is larger than a message, and any bytes
+                // are a valid message.
+                let msg = unsafe {
+                    ptr::read_unaligned(
+                        this.control_in[head].as_ptr() as *const VirtIOConsoleControl
+                    )
+                };
+                this.as_mut().post_control();
+                if len >= mem::size_of::<VirtIOConsoleControl>() {
+                    this.as_mut().control(msg);
+                }
+            }
+        }
+
+        for port in 0..this.nport {
+            while let Some((head, len)) =
+                queue(this.as_mut().project().queues, rx_queue(port)).pop_used()
+            {
+                queue(this.as_mut().project().queues, rx_queue(port)).free_chain(head, |_| ());
+                let p = &mut this.as_mut().project().ports[port];
+                p.rx_posted = false;
+                p.r = 0;
+                p.w = cmp::min(len, PORT_BUF);
+            }
+            // Nothing to read, so receive more at once.
+            let p = &this.ports[port];
+            if p.present && !p.rx_posted && p.r == p.w {
+                this.as_mut().post_rx(port);
+            }
+
+            if this.as_mut().reclaim(rx_queue(port) + 1) {
+                this.as_mut().project().ports[port].tx_busy = false;
+            }
+        }
+
+        // Wake up the processes waiting to read or write.
+        guard.wakeup(kernel);
+    }
+}
+
+impl SleepableLock<VirtioConsole> {
+    /// Reads up to n bytes from port to dst, once some have arrived.
+    /// Returns Ok(bytes read), or Err(()) if there is no port or the process is killed.
+    pub fn read(
+        self: Pin<&Self>,
+        port: usize,
+        dst: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let mut buf = [0; PORT_BUF];
+        let mut guard = self.pinned_lock();
+        guard.wait_while_killable(
+            |this| this.ports[port].present && this.ports[port].r == this.ports[port].w,
+            ctx,
+        )?;
+        if !guard.ports[port].present {
+            return Err(());
+        }
+
+        let p = &mut guard.get_pin_mut().project().ports[port];
+        let m = cmp::min(n, p.w - p.r);
+        buf[..m].copy_from_slice(&p.rx[p.r..p.r + m]);
+        p.r += m;
+        if p.r == p.w {
+            guard.get_pin_mut().post_rx(port);
+        }
+        drop(guard);
+
+        ctx.proc_mut().memory_mut().copy_out_bytes(dst, &buf[..m])?;
+        Ok(m)
+    }
+
+    /// Writes n bytes from src to port, a message of up to `PORT_BUF` bytes at a time.
+    /// Returns Ok(bytes written), which is less than n if there is an error after some bytes
+    /// have been written, or Err(()) if there is no port or the process is killed.
+    pub fn write(
+        self: Pin<&Self>,
+        port: usize,
+        src: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, ()> {
+        let mut written = 0;
+        while written < n {
+            let mut buf = [0; PORT_BUF];
+            let m = cmp::min(n - written, PORT_BUF);
+            if ctx
+                .proc_mut()
+                .memory_mut()
+                .copy_in_bytes(&mut buf[..m], src + written)
+                .is_err()
+            {
+                break;
+            }
+
+            let mut guard = self.pinned_lock();
+            if guard
+                .wait_while_killable(
+                    |this| this.ports[port].present && this.ports[port].tx_busy,
+                    ctx,
+                )
+                .is_err()
+                || !guard.ports[port].present
+            {
+                break;
+            }
+            guard.get_pin_mut().send(port, &buf[..m]);
+            written += m;
+        }
+        if written > 0 || n == 0 {
+            Ok(written)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Resets the device, so that it stops accessing the queues before the machine restarts.
+    pub fn reset(self: Pin<&Self>) {
+        self.pinned_lock().mmio.reset();
+    }
+}
+
+/// User read()s from /dev/vportN go here.
+fn vport_read<const N: usize>(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    if n < 0 {
+        return -1;
+    }
+    hal()
+        .virtio_console()
+        .read(N, dst, n as usize, ctx)
+        .map_or(-1, |r| r as i32)
+}
+
+/// User write()s to /dev/vportN go here.
+fn vport_write<const N: usize>(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    if n < 0 {
+        return -1;
+    }
+    hal()
+        .virtio_console()
+        .write(N, src, n as usize, ctx)
+        .map_or(-1, |r| r as i32)
+}
+
+/// Registers the ports of the virtio console.
+pub fn register_devices(devsw: &mut [Devsw; NDEV]) {
+    let ports = [
+        Devsw {
+            read: Some(vport_read::<0>),
+            write: Some(vport_write::<0>),
+        },
+        Devsw {
+            read: Some(vport_read::<1>),
+            write: Some(vport_write::<1>),
+        },
+        Devsw {
+            read: Some(vport_read::<2>),
+            write: Some(vport_write::<2>),
+        },
+        Devsw {
+            read: Some(vport_read::<3>),
+            write: Some(vport_write::<3>),
+        },
+    ];
+    for (i, port) in ports.iter().take(NVPORT).enumerate() {
+        devsw[VPORT_DEVSW + i] = *port;
+    }
+}
@@ -1,72 +1,69 @@
 /// Driver for qemu's virtio disk device.
 /// Uses qemu's mmio interface to virtio.
-/// qemu presents a "legacy" virtio interface.
+/// qemu presents a "legacy" virtio interface, or a modern one with
+/// -global virtio-mmio.force-legacy=false.
 ///
 /// qemu ... -drive file=fs.img,if=none,format=raw,id=x0 -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0
-use core::array::IntoIter;
 use core::marker::PhantomPinned;
 use core::mem;
 use core::pin::Pin;
 use core::ptr;
-use core::sync::atomic::{fence, Ordering};
 
 use arrayvec::ArrayVec;
-use bitmaps::Bitmap;
-use const_zero::const_zero;
 use pin_project::pin_project;
 
 use super::{
-    MmioRegs, VirtIOFeatures, VirtIOStatus, VirtqAvail, VirtqDesc, VirtqDescFlags, VirtqUsed, NUM,
-    VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
+    transport::{DeviceType, VirtIOFeatures, VirtioMmio},
+    virtqueue::{Descriptor, Virtqueue, NUM},
 };
 use crate::{
-    addr::{PGSHIFT, PGSIZE},
+    arch::interface::{MemLayout, TimeManager},
+    arch::TargetArch,
     bio::Buf,
+    iosched::{IoQueue, Request},
     kernel::KernelRef,
+    ktrace::{self, Event, Task},
     lock::{SleepableLock, SleepableLockGuard},
     param::BSIZE,
     proc::KernelCtx,
 };
 
-// It must be page-aligned.
-// It needs repr(C) because it is read by device.
-// https://github.com/kaist-cp/rv6/issues/52
-#[repr(C, align(4096))]
+/// for disk ops
+/// read the disk
+const VIRTIO_BLK_T_IN: u32 = 0;
+
+/// write the disk
+const VIRTIO_BLK_T_OUT: u32 = 1;
+
+/// write the cache of the disk to the disk
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+
 #[pin_project]
 pub struct VirtioDisk {
-    /// The first region is a set (not a ring) of DMA descriptors, with which
-    /// the driver tells the device where to read and write individual disk
-    /// operations. There are NUM descriptors. Most commands consist of a
-    /// "chain" (a linked list) of a couple of these descriptors.
-    desc: [VirtqDesc; NUM],
-
-    /// The next is a ring in which the driver writes descriptor numbers that
-    /// the driver would like the device to process. It only includes the head
-    /// descriptor of each chain. The ring has NUM elements.
-    avail: VirtqAvail,
-
-    /// Finally a ring in which the device writes descriptor numbers that the
-    /// device has finished processing (just the head of each chain). There are
-    /// NUM used ring entries.
-    used: VirtqUsed,
+    /// The queue of disk operations.
+    #[pin]
+    vq: Virtqueue,
 
     #[pin]
     info: DiskInfo,
+
+    mmio: VirtioMmio,
+
+    /// Requests waiting for descriptors.
+    queue: IoQueue,
+
+    /// Does the device refuse writes?
+    read_only: bool,
+
+    /// Does the device cache writes, and flush the cache when asked to?
+    flush: bool,
 }
 
-// It must be page-aligned because a virtqueue (desc + avail + used) occupies
-// two or more physically-contiguous pages.
-#[repr(align(4096))]
 #[pin_project]
 struct DiskInfo {
-    /// is a descriptor allocated?
-    allocated: Bitmap<NUM>,
-
-    /// we've looked this far in used.
-    used_idx: u16,
-
     /// Track info about in-flight operations, for use when completion
-    /// interrupt arrives. Indexed by first descriptor index of chain.
+    /// interrupt arrives. The status is indexed by first descriptor index of
+    /// chain, and each buffer by the index of its descriptor.
     inflight: [InflightInfo; NUM],
 
     /// Disk command headers. One-for-one with descriptors, for convenience.
@@ -78,11 +75,13 @@ struct DiskInfo {
 
 /// # Safety
 ///
-/// `b` refers to a valid `Buf` unless it is null.
+/// * `b` refers to a valid `Buf` unless it is null.
+/// * `flushed` refers to the flag of a waiting flush unless it is null.
 #[derive(Copy, Clone)]
 struct InflightInfo {
     b: *mut Buf,
     status: bool,
+    flushed: *mut bool,
 }
 
 /// The format of the first descriptor in a disk request. To be followed by two
@@ -103,10 +102,13 @@ impl VirtioDisk {
     /// It must be used only after initializing it with `VirtioDisk::init`.
     pub const unsafe fn new() -> Self {
         Self {
-            desc: [VirtqDesc::new(); NUM],
-            avail: VirtqAvail::new(),
-            used: VirtqUsed::new(),
+            vq: Virtqueue::new(),
             info: DiskInfo::new(),
+            // SAFETY: the kernel maps the registers of the virtio disk at VIRTIO0.
+            mmio: unsafe { VirtioMmio::new(TargetArch::VIRTIO0) },
+            queue: IoQueue::new(),
+            read_only: false,
+            flush: false,
         }
     }
 }
@@ -114,9 +116,6 @@ impl VirtioDisk {
 impl DiskInfo {
     const fn new() -> Self {
         Self {
-            // SAFETY: bitmap is safe to be zero-initialized.
-            allocated: unsafe { const_zero!(Bitmap::<NUM>) },
-            used_idx: 0,
             inflight: [InflightInfo::new(); NUM],
             ops: [VirtIOBlockOutHeader::default(); NUM],
             _marker: PhantomPinned,
@@ -129,6 +128,7 @@ impl InflightInfo {
         Self {
             b: ptr::null_mut(),
             status: false,
+            flushed: ptr::null_mut(),
         }
     }
 }
@@ -147,6 +147,14 @@ impl VirtIOBlockOutHeader {
             sector,
         }
     }
+
+    fn flush() -> Self {
+        Self {
+            typ: VIRTIO_BLK_T_FLUSH,
+            reserved: 0,
+            sector: 0,
+        }
+    }
 }
 
 impl const Default for VirtIOBlockOutHeader {
@@ -159,26 +167,6 @@ impl const Default for VirtIOBlockOutHeader {
     }
 }
 
-/// A descriptor allocated by driver.
-#[derive(Debug)]
-struct Descriptor {
-    idx: usize,
-}
-
-impl Descriptor {
-    fn new(idx: usize) -> Self {
-        Self { idx }
-    }
-}
-
-impl Drop for Descriptor {
-    fn drop(&mut self) {
-        // HACK(@efenniht): we really need linear type here:
-        // https://github.com/rust-lang/rfcs/issues/814
-        panic!("Descriptor must never drop. Use Disk::free instead.");
-    }
-}
-
 impl SleepableLock<VirtioDisk> {
     /// Return a locked Buf with the `latest` contents of the indicated block.
     /// If buf.valid is true, we don't need to access Disk.
@@ -191,237 +179,309 @@ impl SleepableLock<VirtioDisk> {
         buf
     }
 
+    /// Returns true if the device is read-only, which `write` must not be called for.
+    pub fn read_only(self: Pin<&Self>) -> bool {
+        self.pinned_lock().read_only
+    }
+
     pub fn write(self: Pin<&Self>, b: &mut Buf, ctx: &KernelCtx<'_, '_>) {
         VirtioDisk::rw(&mut self.pinned_lock(), b, true, ctx)
     }
+
+    /// Writes b, and returns once it is durable, like a write with FUA (Force Unit Access). The
+    /// device has no FUA, so it is a write followed by a flush.
+    pub fn write_fua(self: Pin<&Self>, b: &mut Buf, ctx: &KernelCtx<'_, '_>) {
+        let mut guard = self.pinned_lock();
+        VirtioDisk::rw(&mut guard, b, true, ctx);
+        VirtioDisk::flush(&mut guard, ctx);
+    }
+
+    /// Makes the completed writes durable, i.e., a write barrier. Returns at once if the device
+    /// does not cache writes.
+    pub fn flush(self: Pin<&Self>, ctx: &KernelCtx<'_, '_>) {
+        VirtioDisk::flush(&mut self.pinned_lock(), ctx)
+    }
+
+    /// Resets the device, so that it stops accessing the queue before the machine restarts.
+    /// Requests in flight are never completed.
+    pub fn reset(self: Pin<&Self>) {
+        self.pinned_lock().mmio.reset();
+    }
 }
 
 impl VirtioDisk {
-    pub fn init(self: Pin<&Self>) {
-        let mut status: VirtIOStatus = VirtIOStatus::empty();
-
+    pub fn init(mut self: Pin<&mut Self>) {
         // MMIO registers are located below KERNBASE, while kernel text and data
         // are located above KERNBASE, so we can safely read/write MMIO registers.
-        MmioRegs::check_virtio_disk();
-        status.insert(VirtIOStatus::ACKNOWLEDGE);
-        MmioRegs::set_status(&status);
-        status.insert(VirtIOStatus::DRIVER);
-        MmioRegs::set_status(&status);
-
-        // Negotiate features
-        let features = MmioRegs::get_features()
-            - (VirtIOFeatures::BLK_F_RO
-                | VirtIOFeatures::BLK_F_SCSI
-                | VirtIOFeatures::BLK_F_CONFIG_WCE
-                | VirtIOFeatures::BLK_F_MQ
-                | VirtIOFeatures::F_ANY_LAYOUT
-                | VirtIOFeatures::RING_F_EVENT_IDX
-                | VirtIOFeatures::RING_F_INDIRECT_DESC);
-
-        MmioRegs::set_features(&features);
-
-        // Tell device that feature negotiation is complete.
-        status.insert(VirtIOStatus::FEATURES_OK);
-        MmioRegs::set_status(&status);
-
-        // Tell device we're completely ready.
-        status.insert(VirtIOStatus::DRIVER_OK);
-        MmioRegs::set_status(&status);
-        // SAFETY: page size is `PGSIZE`.
-        unsafe {
-            MmioRegs::set_pg_size(PGSIZE as _);
-        }
+        assert!(
+            self.mmio.probe(DeviceType::Block),
+            "could not find virtio disk"
+        );
+
+        // Negotiate features. The driver uses none of the others.
+        let features = self
+            .mmio
+            .negotiate(VirtIOFeatures::BLK_F_RO | VirtIOFeatures::BLK_F_FLUSH)
+            .expect("virtio disk refused the features");
+        *self.as_mut().project().read_only = features.contains(VirtIOFeatures::BLK_F_RO);
+        // Without the flush command, the device completes a write only after it is durable.
+        *self.as_mut().project().flush = features.contains(VirtIOFeatures::BLK_F_FLUSH);
 
         // Initialize queue 0.
-        unsafe {
-            MmioRegs::select_and_init_queue(
-                0,
-                NUM as _,
-                (self.desc.as_ptr() as usize >> PGSHIFT) as _,
-            );
-        }
+        let this = self.as_mut().project();
+        this.vq.into_ref().init(this.mmio, 0);
+
+        // Tell device we're completely ready.
+        self.mmio.driver_ok();
 
         // plic.rs and trap.rs arrange for interrupts from VIRTIO0_IRQ.
     }
 
-    // This method reads and writes disk by reading and writing MMIO registers.
-    // By the construction of the kernel page table in KernelMemory::new, the
-    // virtual addresses of the MMIO registers are mapped to the proper physical
-    // addresses. Therefore, this method is safe.
+    /// Queues a request for b, and waits until the device completes it.
     fn rw(
         guard: &mut SleepableLockGuard<'_, Self>,
         b: &mut Buf,
         write: bool,
         ctx: &KernelCtx<'_, '_>,
     ) {
+        assert!(!(write && guard.read_only), "virtio disk write: read-only");
         let sector: usize = (*b).blockno as usize * (BSIZE / 512);
+        let start = TargetArch::r_cycle();
 
-        // The spec's Section 5.2 says that legacy block operations use
-        // three descriptors: one for type/reserved/sector, one for the
-        // data, one for a 1-byte status result.
-
-        // Allocate the three descriptors.
-        let desc = loop {
-            match guard.get_pin_mut().alloc_three_descriptors() {
-                Some(idx) => break idx,
-                // We do not need wakeup for the None case:
-                // * alloc_three_descriptors can be executed by one thread at
-                //   once. Thus, we do not need to consider interleaving of
-                //   alloc_three_descriptors.
-                // * If alloc_three_descriptors fails, it frees only the
-                //   descriptors that it created. It does not increase the
-                //   number of free descriptors. Therefore, sleeping threads
-                //   do not need to wake up, as alloc_three_descriptors will
-                //   still fail.
-                None => guard.sleep(ctx),
-            }
-        };
+        // Wait for room in the queue. virtio_disk_intr() makes room by sending
+        // queued requests to the device, and wakes us up.
+        while guard.queue.is_full() {
+            guard.sleep(ctx);
+        }
 
-        let mut this = guard.get_pin_mut().project();
-        let mut info = this.info.project();
+        if ktrace::enabled() {
+            let task = Task::new(ctx.proc().pid(), &ctx.proc().deref_data().name);
+            ktrace::record(
+                ctx.proc().pid(),
+                Event::BlockIssue {
+                    write,
+                    sector,
+                    nsector: BSIZE / 512,
+                    task,
+                },
+            );
+        }
 
-        // Format the three descriptors.
-        // qemu's virtio-blk.c reads them.
+        let vtime = guard.queue.vtime();
+        let tag = ctx.proc().account_io(|io| io.tag(vtime));
+        b.deref_inner_mut().disk = true;
+        // It does not break the invariant of Request because b is &mut Buf,
+        // which refers to a valid Buf until the request completes below.
+        guard
+            .get_pin_mut()
+            .project()
+            .queue
+            .push(Request::new(b, write, tag));
+        guard.get_pin_mut().dispatch();
 
-        // 1. Set the first descriptor.
-        let buf0 = &mut info.ops[desc[0].idx];
-        *buf0 = VirtIOBlockOutHeader::new(write, sector);
+        // Wait for virtio_disk_intr() to say request has finished.
+        // The device owns the buffer until then, so this wait cannot be interrupted even if the
+        // process is killed; a kill only wakes it up spuriously.
+        b.vdisk_request_waitchannel
+            .wait_while(guard, |_| b.deref_inner().disk, ctx);
 
-        this.desc[desc[0].idx] = VirtqDesc {
-            addr: buf0 as *const _ as _,
-            len: mem::size_of::<VirtIOBlockOutHeader>() as _,
-            flags: VirtqDescFlags::NEXT,
-            next: desc[1].idx as _,
-        };
+        let wait = TargetArch::r_cycle().wrapping_sub(start);
+        ctx.proc().account_io(|io| io.add(write, wait));
+    }
 
-        // 2. Set the second descriptor.
-        // Device reads/writes b->data
-        this.desc[desc[1].idx] = VirtqDesc {
-            addr: b.deref_inner().data.as_ptr() as _,
-            len: BSIZE as _,
-            flags: if write {
-                VirtqDescFlags::NEXT
-            } else {
-                VirtqDescFlags::NEXT | VirtqDescFlags::WRITE
-            },
-            next: desc[2].idx as _,
-        };
+    /// Sends the queued requests to the device while it has descriptors for them.
+    // This method reads and writes disk by reading and writing MMIO registers.
+    // By the construction of the kernel page table in KernelMemory::new, the
+    // virtual addresses of the MMIO registers are mapped to the proper physical
+    // addresses. Therefore, this method is safe.
+    fn dispatch(mut self: Pin<&mut Self>) {
+        loop {
+            // The spec's Section 5.2 says that legacy block operations use
+            // a descriptor for type/reserved/sector, one or more for the
+            // data, and one for a 1-byte status result.
+            let free = self.vq.nfree();
+            if free < 3 {
+                break;
+            }
+            let batch = match self.as_mut().project().queue.pop(free - 2) {
+                Some(batch) => batch,
+                None => break,
+            };
+            let write = batch[0].write;
+            let sector = batch[0].blockno as usize * (BSIZE / 512);
+            let desc = self
+                .as_mut()
+                .project()
+                .vq
+                .alloc(batch.len() + 2)
+                .expect("Disk::dispatch");
+
+            let mut this = self.as_mut().project();
+            let mut info = this.info.as_mut().project();
+
+            // Format the descriptors.
+            // qemu's virtio-blk.c reads them.
+
+            // 1. Set the first descriptor.
+            let buf0 = &mut info.ops[desc[0].idx()];
+            *buf0 = VirtIOBlockOutHeader::new(write, sector);
+            this.vq.as_mut().set(
+                &desc[0],
+                buf0 as *const _ as _,
+                mem::size_of::<VirtIOBlockOutHeader>(),
+                false,
+                Some(&desc[1]),
+            );
 
-        // 3. Set the third descriptor.
-        // device writes 0 on success
-        info.inflight[desc[0].idx].status = true;
+            // 2. Set a descriptor for each block, in the order of the sectors.
+            // Device reads/writes b->data
+            for (i, request) in batch.iter().enumerate() {
+                this.vq.as_mut().set(
+                    &desc[i + 1],
+                    // SAFETY: from the invariant of Request, b refers to a valid Buf.
+                    unsafe { (*request.b).deref_inner().data.as_ptr() } as _,
+                    BSIZE,
+                    !write,
+                    Some(&desc[i + 2]),
+                );
+
+                // Record struct Buf for virtio_disk_intr().
+                // It does not break the invariant because the Buf stays valid
+                // until the request completes.
+                info.inflight[desc[i + 1].idx()].b = request.b;
+            }
 
-        // Device writes the status
-        this.desc[desc[2].idx] = VirtqDesc {
-            addr: &info.inflight[desc[0].idx].status as *const _ as _,
-            len: 1,
-            flags: VirtqDescFlags::WRITE,
-            next: 0,
-        };
+            self.as_mut().submit(desc);
+        }
+    }
 
-        // Record struct Buf for virtio_disk_intr().
-        b.deref_inner_mut().disk = true;
-        // It does not break the invariant because b is &mut Buf, which refers
-        // to a valid Buf.
-        info.inflight[desc[0].idx].b = b;
+    /// Asks the device to write its cache to the disk, and waits until it has.
+    fn flush(guard: &mut SleepableLockGuard<'_, Self>, ctx: &KernelCtx<'_, '_>) {
+        if !guard.flush {
+            return;
+        }
 
-        // Tell the device the first index in our chain of descriptors.
-        let ring_idx = this.avail.idx as usize % NUM;
-        this.avail.ring[ring_idx] = desc[0].idx as _;
+        // A flush has no data, so it uses two descriptors.
+        let desc = loop {
+            match guard.get_pin_mut().project().vq.alloc(2) {
+                Some(desc) => break desc,
+                // virtio_disk_intr() wakes us up when it frees descriptors.
+                None => guard.sleep(ctx),
+            }
+        };
 
-        fence(Ordering::SeqCst);
+        let mut flushed = false;
+        let this = guard.get_pin_mut().project();
+        let info = this.info.project();
+        let buf0 = &mut info.ops[desc[0].idx()];
+        *buf0 = VirtIOBlockOutHeader::flush();
+        this.vq.set(
+            &desc[0],
+            buf0 as *const _ as _,
+            mem::size_of::<VirtIOBlockOutHeader>(),
+            false,
+            Some(&desc[1]),
+        );
+
+        // It does not break the invariant because we wait for the flush to
+        // complete below.
+        info.inflight[desc[0].idx()].flushed = &mut flushed;
+        guard.get_pin_mut().submit(desc);
+
+        // Wait for virtio_disk_intr() to say the flush has finished.
+        while !flushed {
+            guard.sleep(ctx);
+        }
+    }
 
-        // Tell the device another avail ring entry is available.
-        this.avail.idx += 1;
+    /// Sets the last of desc as the status descriptor, and hands the chain of
+    /// desc to the device.
+    fn submit(self: Pin<&mut Self>, desc: ArrayVec<Descriptor, NUM>) {
+        let mut this = self.project();
+        let info = this.info.project();
 
-        fence(Ordering::SeqCst);
+        // device writes 0 on success
+        let status = &mut info.inflight[desc[0].idx()].status;
+        *status = true;
 
-        // SAFETY: the all three descriptors' fields are well set.
+        // Device writes the status
+        this.vq.as_mut().set(
+            &desc[desc.len() - 1],
+            status as *const _ as _,
+            1,
+            true,
+            None,
+        );
+
+        // The descriptors belong to the device until complete() frees them.
+        this.vq.submit(desc);
+
+        // SAFETY: the all descriptors' fields are well set.
         // Value is queue number.
         unsafe {
-            MmioRegs::notify_queue(0);
+            this.mmio.notify(0);
         }
-
-        // Wait for virtio_disk_intr() to say request has finished.
-        b.vdisk_request_waitchannel.sleep(guard, ctx);
-
-        // As it assigns null, the invariant of inflight is maintained even if
-        // b: &mut Buf becomes invalid after this method returns.
-        guard.get_pin_mut().project().info.project().inflight[desc[0].idx].b = ptr::null_mut();
-        IntoIter::new(desc).for_each(|desc| guard.get_pin_mut().free(desc));
-        guard.wakeup(ctx.kernel());
     }
 
-    pub fn intr(self: Pin<&mut Self>, kernel: KernelRef<'_, '_>) {
-        // The device won't raise another interrupt until we tell it
-        // we've seen this interrupt, which the following line does.
-        // This may race with the device writing new entries to
-        // the "used" ring, in which case we may process the new
-        // completion entries in this interrupt, and have nothing to do
-        // in the next interrupt, which is harmless.
-        MmioRegs::intr_ack_all();
-
-        fence(Ordering::SeqCst);
-
-        // The device increments disk.used->idx when it
-        // adds an entry to the used ring.
+    /// Completes the request whose chain of descriptors begins at head, and
+    /// frees its descriptors.
+    fn complete(mut self: Pin<&mut Self>, head: usize, kernel: KernelRef<'_, '_>) {
+        assert!(!self.info.inflight[head].status, "Disk::intr status");
+        let op = self.info.ops[head];
+        let flushed = mem::replace(
+            &mut self.as_mut().project().info.project().inflight[head].flushed,
+            ptr::null_mut(),
+        );
+        // SAFETY: from the invariant, flushed refers to a valid flag unless
+        // it is null.
+        if let Some(flushed) = unsafe { flushed.as_mut() } {
+            *flushed = true;
+        }
 
+        let mut nblock = 0;
         let this = self.project();
         let info = this.info.project();
-
-        while *info.used_idx != this.used.id {
-            fence(Ordering::SeqCst);
-            let id = this.used.ring[(*info.used_idx as usize) % NUM].id as usize;
-
-            assert!(!info.inflight[id].status, "Disk::intr status");
-
+        this.vq.free_chain(head, |idx| {
+            let b = mem::replace(&mut info.inflight[idx].b, ptr::null_mut());
             // SAFETY: from the invariant, b refers to a valid
             // buffer unless it is null.
-            let buf = unsafe { &mut *info.inflight[id].b };
-
-            // disk is done with buf
-            buf.deref_inner_mut().disk = false;
-            buf.vdisk_request_waitchannel.wakeup(kernel);
-
-            *info.used_idx += 1;
+            if let Some(buf) = unsafe { b.as_mut() } {
+                // disk is done with buf
+                buf.deref_inner_mut().disk = false;
+                buf.vdisk_request_waitchannel.wakeup(kernel);
+                nblock += 1;
+            }
+        });
+
+        if ktrace::enabled() && nblock > 0 {
+            ktrace::record(
+                kernel.current_pid(),
+                Event::BlockComplete {
+                    write: op.typ == VIRTIO_BLK_T_OUT,
+                    sector: op.sector,
+                    nsector: nblock * (BSIZE / 512),
+                },
+            );
         }
     }
 
-    /// Find a free descriptor, mark it non-free, return its index.
-    fn alloc(self: Pin<&mut Self>) -> Option<Descriptor> {
-        let info = self.project().info.project();
-        let idx = info.allocated.first_false_index()?;
-        let _ = info.allocated.set(idx, true);
-        Some(Descriptor::new(idx))
-    }
+    pub fn intr(guard: &mut SleepableLockGuard<'_, Self>, kernel: KernelRef<'_, '_>) {
+        // The device won't raise another interrupt until we tell it
+        // we've seen this interrupt, which the following line does.
+        // This may race with the device writing new entries to
+        // the "used" ring, in which case we may process the new
+        // completion entries in this interrupt, and have nothing to do
+        // in the next interrupt, which is harmless.
+        guard.mmio.ack_intr();
 
-    /// Allocate three descriptors (they need not be contiguous).
-    /// Disk transfers always use three descriptors.
-    fn alloc_three_descriptors(mut self: Pin<&mut Self>) -> Option<[Descriptor; 3]> {
-        let mut descs = ArrayVec::<_, 3>::new();
-
-        for _ in 0..3 {
-            if let Some(desc) = self.as_mut().alloc() {
-                descs.push(desc);
-            } else {
-                for desc in descs {
-                    self.as_mut().free(desc);
-                }
-                return None;
-            }
+        let mut this = guard.get_pin_mut();
+        while let Some((head, _)) = this.as_mut().project().vq.pop_used() {
+            this.as_mut().complete(head, kernel);
         }
 
-        descs.into_inner().ok()
-    }
-
-    fn free(self: Pin<&mut Self>, desc: Descriptor) {
-        let this = self.project();
-        let idx = desc.idx;
-        this.desc[idx].addr = 0;
-        this.desc[idx].len = 0;
-        this.desc[idx].flags = VirtqDescFlags::FREED;
-        this.desc[idx].next = 0;
-        assert!(this.info.project().allocated.set(idx, false), "Disk::free");
-        mem::forget(desc);
+        // Send the queued requests to the freed descriptors, and wake up the
+        // processes waiting for room in the queue.
+        this.dispatch();
+        guard.wakeup(kernel);
     }
 }
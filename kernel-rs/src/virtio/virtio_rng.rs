@@ -0,0 +1,99 @@
+/// Driver for qemu's virtio entropy device, with which the kernel seeds its entropy pool.
+/// The driver polls the device for the random bytes, so it needs no interrupts.
+///
+/// qemu ... -device virtio-rng-device,bus=virtio-mmio-bus.1
+use core::hint::spin_loop;
+use core::pin::Pin;
+
+use pin_project::pin_project;
+
+use super::{
+    transport::{DeviceType, VirtIOFeatures, VirtioMmio},
+    virtqueue::Virtqueue,
+};
+use crate::{arch::interface::MemLayout, arch::TargetArch};
+
+#[pin_project]
+pub struct VirtioRng {
+    /// The queue of requests for random bytes.
+    #[pin]
+    vq: Virtqueue,
+
+    mmio: VirtioMmio,
+
+    /// Is there the device?
+    present: bool,
+}
+
+impl VirtioRng {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `VirtioRng::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            vq: Virtqueue::new(),
+            // SAFETY: the kernel maps the registers of the entropy device at VIRTIO1.
+            mmio: unsafe { VirtioMmio::new(TargetArch::VIRTIO1) },
+            present: false,
+        }
+    }
+
+    /// Initializes the device if there is one.
+    pub fn init(mut self: Pin<&mut Self>) {
+        if !self.mmio.probe(DeviceType::Entropy) {
+            return;
+        }
+
+        // The device has no features.
+        if self.mmio.negotiate(VirtIOFeatures::empty()).is_err() {
+            return;
+        }
+
+        // Initialize queue 0.
+        let this = self.as_mut().project();
+        this.vq.into_ref().init(this.mmio, 0);
+
+        // Tell device we're completely ready.
+        self.mmio.driver_ok();
+        *self.project().present = true;
+    }
+
+    /// Fills buf with random bytes of the device.
+    /// Returns Ok(()) on success, Err(()) if there is no device.
+    pub fn read(self: Pin<&mut Self>, buf: &mut [u8]) -> Result<(), ()> {
+        if !self.present {
+            return Err(());
+        }
+
+        let mut this = self.project();
+        let mut filled = 0;
+        while filled < buf.len() {
+            let desc = this.vq.as_mut().alloc(1).expect("VirtioRng::read");
+            let rest = &mut buf[filled..];
+            // Device writes the random bytes.
+            this.vq
+                .as_mut()
+                .set(&desc[0], rest.as_mut_ptr() as _, rest.len(), true, None);
+            this.vq.as_mut().submit(desc);
+            // SAFETY: the descriptor points to rest, which outlives the
+            // request as we wait for it below.
+            unsafe {
+                this.mmio.notify(0);
+            }
+
+            // The device may write fewer bytes than asked for.
+            let (head, len) = loop {
+                if let Some(used) = this.vq.as_mut().pop_used() {
+                    break used;
+                }
+                spin_loop();
+            };
+            this.vq.as_mut().free_chain(head, |_| ());
+            filled += len;
+        }
+
+        // The device raised interrupts for the requests, which nobody handles.
+        this.mmio.ack_intr();
+        Ok(())
+    }
+}
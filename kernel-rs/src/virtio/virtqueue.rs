@@ -0,0 +1,330 @@
+//! Virtqueues, through which a driver hands requests to its virtio device.
+//!
+//! A request is a chain of descriptors, each pointing to a buffer that the device reads or
+//! writes. The driver puts the head of the chain in the avail ring, and the device puts it in the
+//! used ring once it has finished with the request.
+
+use core::marker::PhantomPinned;
+use core::mem;
+use core::pin::Pin;
+use core::sync::atomic::{fence, Ordering};
+
+use arrayvec::ArrayVec;
+use bitflags::bitflags;
+use bitmaps::Bitmap;
+use const_zero::const_zero;
+use pin_project::pin_project;
+
+use super::transport::VirtioMmio;
+
+/// This many virtio descriptors. It must be a power of two.
+pub const NUM: usize = 1 << 3;
+
+/// A virtqueue of NUM descriptors.
+// It must be page-aligned because the legacy interface finds the used ring at
+// the page after the descriptor table.
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C, align(4096))]
+#[pin_project]
+pub struct Virtqueue {
+    /// The first region is a set (not a ring) of DMA descriptors, with which
+    /// the driver tells the device where to read and write individual
+    /// operations. There are NUM descriptors. Most commands consist of a
+    /// "chain" (a linked list) of a couple of these descriptors.
+    desc: [VirtqDesc; NUM],
+
+    /// The next is a ring in which the driver writes descriptor numbers that
+    /// the driver would like the device to process. It only includes the head
+    /// descriptor of each chain. The ring has NUM elements.
+    avail: VirtqAvail,
+
+    /// Finally a ring in which the device writes descriptor numbers that the
+    /// device has finished processing (just the head of each chain). There are
+    /// NUM used ring entries.
+    used: VirtqUsed,
+
+    /// is a descriptor allocated?
+    allocated: Bitmap<NUM>,
+
+    /// we've looked this far in used.
+    used_idx: u16,
+
+    #[pin]
+    _marker: PhantomPinned,
+}
+
+/// A single descriptor, from the spec.
+/// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-320005
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VirtqDesc {
+    addr: usize,
+    len: u32,
+    flags: VirtqDescFlags,
+    next: u16,
+}
+
+bitflags! {
+    struct VirtqDescFlags: u16 {
+        const FREED = 0b00;
+
+        /// chained with another descriptor
+        const NEXT = 0b01;
+
+        /// device writes (vs read)
+        const WRITE = 0b10;
+    }
+}
+
+/// The (entire) avail ring, from the spec.
+/// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-380006
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C)]
+struct VirtqAvail {
+    /// always zero
+    flags: u16,
+
+    /// Tells the device how far to look in `ring`.
+    idx: u16,
+
+    /// `desc` indices the device should process.
+    ring: [u16; NUM],
+}
+
+/// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-430008
+// It must be page-aligned.
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C, align(4096))]
+struct VirtqUsed {
+    /// always zero
+    flags: u16,
+
+    /// device increments when it adds a ring[] entry
+    id: u16,
+
+    ring: [VirtqUsedElem; NUM],
+}
+
+/// One entry in the "used" ring, with which the device tells the driver about
+/// completed requests.
+/// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-430008
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VirtqUsedElem {
+    /// index of start of completed descriptor chain
+    id: u32,
+
+    /// bytes the device wrote into the chain
+    len: u32,
+}
+
+/// A descriptor allocated by driver.
+#[derive(Debug)]
+pub struct Descriptor {
+    idx: usize,
+}
+
+impl VirtqDesc {
+    const fn new() -> Self {
+        Self {
+            addr: 0,
+            len: 0,
+            flags: VirtqDescFlags::FREED,
+            next: 0,
+        }
+    }
+}
+
+impl VirtqAvail {
+    const fn new() -> Self {
+        Self {
+            flags: 0,
+            idx: 0,
+            ring: [0; NUM],
+        }
+    }
+}
+
+impl VirtqUsed {
+    const fn new() -> Self {
+        Self {
+            flags: 0,
+            id: 0,
+            ring: [VirtqUsedElem::new(); NUM],
+        }
+    }
+}
+
+impl VirtqUsedElem {
+    const fn new() -> Self {
+        Self { id: 0, len: 0 }
+    }
+}
+
+impl Descriptor {
+    fn new(idx: usize) -> Self {
+        Self { idx }
+    }
+
+    pub fn idx(&self) -> usize {
+        self.idx
+    }
+}
+
+impl Drop for Descriptor {
+    fn drop(&mut self) {
+        // HACK(@efenniht): we really need linear type here:
+        // https://github.com/rust-lang/rfcs/issues/814
+        panic!("Descriptor must never drop. Use Virtqueue::free instead.");
+    }
+}
+
+impl Virtqueue {
+    pub const fn new() -> Self {
+        Self {
+            desc: [VirtqDesc::new(); NUM],
+            avail: VirtqAvail::new(),
+            used: VirtqUsed::new(),
+            // SAFETY: bitmap is safe to be zero-initialized.
+            allocated: unsafe { const_zero!(Bitmap::<NUM>) },
+            used_idx: 0,
+            _marker: PhantomPinned,
+        }
+    }
+
+    /// Makes this the queue queue_num of the device.
+    pub fn init(self: Pin<&Self>, mmio: &VirtioMmio, queue_num: u32) {
+        // SAFETY: the queue is pinned, and the rings are laid out as the
+        // legacy interface expects.
+        unsafe {
+            mmio.init_queue(
+                queue_num,
+                self.desc.as_ptr() as usize,
+                &self.avail as *const _ as usize,
+                &self.used as *const _ as usize,
+            );
+        }
+    }
+
+    /// Returns the number of free descriptors.
+    pub fn nfree(&self) -> usize {
+        NUM - self.allocated.len()
+    }
+
+    /// Find a free descriptor, mark it non-free, return its index.
+    fn alloc_one(self: Pin<&mut Self>) -> Option<Descriptor> {
+        let this = self.project();
+        let idx = this.allocated.first_false_index()?;
+        let _ = this.allocated.set(idx, true);
+        Some(Descriptor::new(idx))
+    }
+
+    /// Allocate n descriptors (they need not be contiguous).
+    pub fn alloc(mut self: Pin<&mut Self>, n: usize) -> Option<ArrayVec<Descriptor, NUM>> {
+        let mut descs = ArrayVec::new();
+
+        for _ in 0..n {
+            if let Some(desc) = self.as_mut().alloc_one() {
+                descs.push(desc);
+            } else {
+                for desc in descs {
+                    self.as_mut().free(desc);
+                }
+                return None;
+            }
+        }
+
+        Some(descs)
+    }
+
+    fn free(self: Pin<&mut Self>, desc: Descriptor) {
+        let this = self.project();
+        let idx = desc.idx;
+        this.desc[idx] = VirtqDesc::new();
+        assert!(this.allocated.set(idx, false), "Virtqueue::free");
+        mem::forget(desc);
+    }
+
+    /// Points desc to the len bytes at addr, which the device writes if `device_writes` is true
+    /// and reads otherwise, and chains it to next.
+    pub fn set(
+        self: Pin<&mut Self>,
+        desc: &Descriptor,
+        addr: usize,
+        len: usize,
+        device_writes: bool,
+        next: Option<&Descriptor>,
+    ) {
+        let mut flags = VirtqDescFlags::FREED;
+        if device_writes {
+            flags.insert(VirtqDescFlags::WRITE);
+        }
+        if next.is_some() {
+            flags.insert(VirtqDescFlags::NEXT);
+        }
+        self.project().desc[desc.idx] = VirtqDesc {
+            addr,
+            len: len as _,
+            flags,
+            next: next.map_or(0, |next| next.idx as _),
+        };
+    }
+
+    /// Hands the chain beginning at the first of desc to the device. The caller notifies the
+    /// device afterwards.
+    pub fn submit(self: Pin<&mut Self>, desc: ArrayVec<Descriptor, NUM>) {
+        let this = self.project();
+
+        // Tell the device the first index in our chain of descriptors.
+        let ring_idx = this.avail.idx as usize % NUM;
+        this.avail.ring[ring_idx] = desc[0].idx as _;
+
+        fence(Ordering::SeqCst);
+
+        // Tell the device another avail ring entry is available.
+        this.avail.idx = this.avail.idx.wrapping_add(1);
+
+        fence(Ordering::SeqCst);
+
+        // The descriptors belong to the device until free_chain() frees them.
+        mem::forget(desc);
+    }
+
+    /// Returns the head of the next chain the device has finished with, and the bytes it wrote
+    /// into the chain, or None if there is none.
+    pub fn pop_used(self: Pin<&mut Self>) -> Option<(usize, usize)> {
+        // The device increments used.id when it adds an entry to the used
+        // ring.
+        fence(Ordering::SeqCst);
+        if self.used_idx == self.used.id {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let elem = self.used.ring[self.used_idx as usize % NUM];
+        let this = self.project();
+        *this.used_idx = this.used_idx.wrapping_add(1);
+        Some((elem.id as usize, elem.len as usize))
+    }
+
+    /// Frees the chain beginning at head, after calling f with the index of each of its
+    /// descriptors.
+    pub fn free_chain<F: FnMut(usize)>(mut self: Pin<&mut Self>, head: usize, mut f: F) {
+        let mut idx = head;
+        loop {
+            let desc = self.desc[idx];
+            f(idx);
+            self.as_mut().free(Descriptor::new(idx));
+            if !desc.flags.contains(VirtqDescFlags::NEXT) {
+                break;
+            }
+            idx = desc.next as usize;
+        }
+    }
+}
@@ -1,24 +1,44 @@
-use core::{cmp, marker::PhantomData, mem, pin::Pin, slice};
+use core::{
+    cmp,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop},
+    pin::Pin,
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
+use array_macro::array;
+use arrayvec::ArrayVec;
 use bitflags::bitflags;
 use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
-    addr::{pgrounddown, pgroundup, Addr, KVAddr, PAddr, UVAddr, VAddr, MAXVA, PGSIZE},
-    arch::interface::{Arch, IPageTableEntry, PageTableManager},
+    addr::{
+        pgrounddown, pgroundup, Addr, KVAddr, PAddr, UVAddr, VAddr, MAXVA, PGSHIFT, PGSIZE, PLSHIFT,
+    },
+    arch::interface::{Arch, IPageTableEntry, PageTableManager, ProcManager},
     arch::TargetArch,
     fs::{DefaultFs, InodeGuard},
+    hal::hal,
     kalloc::Kmem,
+    ksm::Merge,
     lock::SpinLock,
     memlayout::{kstack, PHYSTOP, TRAMPOLINE, TRAPFRAME},
     page::Page,
-    param::NPROC,
+    param::{NCPU, NKSTACK, NKSTACKPOOL},
     proc::KernelCtx,
+    some_or,
 };
 
 type PageTableEntry = <TargetArch as PageTableManager>::PageTableEntry;
 type PteFlags = <PageTableEntry as IPageTableEntry>::EntryFlags;
 
+/// Maximum number of unmapped pages whose TLB entries are flushed together.
+const TLB_BATCH: usize = 16;
+
+/// Maximum number of pages that a single `reclaim` swaps out.
+const SWAP_BATCH: usize = 64;
+
 extern "C" {
     // kernel.ld sets this to end of kernel code.
     static mut etext: [u8; 0];
@@ -50,6 +70,47 @@ bitflags! {
 
 const PTE_PER_PT: usize = PGSIZE / mem::size_of::<PageTableEntry>();
 
+/// A run of consecutive pages mapped with the same permissions.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct MapRegion {
+    /// First virtual address of the region
+    pub start: usize,
+
+    /// One beyond the last virtual address of the region
+    pub end: usize,
+
+    /// Permissions of the region, as `AccessFlags` bits
+    pub perm: usize,
+}
+
+/// Why a page fault could not be handled.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FaultError {
+    /// The access is not allowed.
+    Invalid,
+
+    /// The access is allowed, but no page could be allocated for it.
+    OutOfMemory,
+}
+
+/// Summary of the mappings of a `UserMemory`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct MapInfo {
+    /// Size of process memory (bytes)
+    pub size: usize,
+
+    /// Number of mapped pages, including the trampoline and the trap frame
+    pub rss: usize,
+
+    /// Number of page-table pages of each level, where level 2 is the root
+    pub ptpages: [usize; 3],
+
+    /// Number of regions, which may be more than the regions stored
+    pub nregion: usize,
+}
+
 /// # Safety
 ///
 /// It should be converted to a Page by Page::from_usize(self.inner.as_ptr() as _)
@@ -97,6 +158,27 @@ impl RawPageTable {
         pte
     }
 
+    /// Visit the data pages mapped by this page table of `level` in increasing order of
+    /// virtual address, where `base` is the first virtual address it covers.
+    /// Count this and the descendant page-table pages in `ptpages` by level.
+    fn walk<F: FnMut(usize, &PageTableEntry)>(
+        &self,
+        level: usize,
+        base: usize,
+        ptpages: &mut [usize; 3],
+        f: &mut F,
+    ) {
+        ptpages[level] += 1;
+        for (i, pte) in self.inner.iter().enumerate() {
+            let va = base + (i << (PGSHIFT + PLSHIFT * level));
+            if let Some(ptable) = pte.as_table().filter(|_| level > 0) {
+                ptable.walk(level - 1, va, ptpages, f);
+            } else if pte.is_data() {
+                f(va, pte);
+            }
+        }
+    }
+
     /// Recursively free page-table pages.
     /// All leaf mappings must already have been removed.
     ///
@@ -244,6 +326,12 @@ impl<A: VAddr> Drop for PageTable<A> {
 /// - TRAPFRAME ∈ dom(pt).
 /// - If va ∈ dom(pt) ∧ va ∉ { TRAMPOLINE, TRAPFRAME },
 ///   then Page::from_usize(pt(va)) succeeds without breaking the invariant of Page.
+///   If va is mapped without W, the page may be shared by `Kmem::share` with other memories
+///   and the page cache, and it is never written. A copy-on-write page is mapped with W again
+///   only once it is not shared.
+/// - A page mapped with W may be swapped out into the compressed swap, and then the entry of
+///   its va is invalid and refers to the slot of the compressed swap that holds it. Such a va
+///   counts as in dom(pt) below.
 /// - If va ∈ dom(pt) where va ∉ { 0, TRAMPOLINE, TRAPFRAME },
 ///   then va - PGSIZE ∈ dom(pt).
 /// - pgroundup(size) ∉ dom(pt).
@@ -253,6 +341,16 @@ pub struct UserMemory {
     page_table: PageTable<UVAddr>,
     /// Size of process memory (bytes).
     size: usize,
+    /// Start of the heap, below which brk() does not shrink the memory.
+    heap: usize,
+    /// ASID of the page table.
+    asid: Asid,
+    /// Bit i is set if CPU i may have TLB entries of the page table under `asid`.
+    cpus: usize,
+    /// Address of the page scanned next by `merge_pages`.
+    merge_next: usize,
+    /// Address of the page considered next by `swap_out`.
+    swap_next: usize,
 }
 
 impl UserMemory {
@@ -300,6 +398,11 @@ impl UserMemory {
         let mut memory = Self {
             page_table: scopeguard::ScopeGuard::into_inner(page_table),
             size: 0,
+            heap: 0,
+            asid: Asid::default(),
+            cpus: 0,
+            merge_next: 0,
+            swap_next: 0,
         };
 
         if let Some(src) = src_opt {
@@ -316,6 +419,7 @@ impl UserMemory {
                 .map_err(|page| allocator.free(page))
                 .ok()?;
         }
+        memory.heap = memory.size;
         Some(memory)
     }
 
@@ -328,6 +432,7 @@ impl UserMemory {
             let _ = new.dealloc(0, allocator);
         });
         for i in num_iter::range_step(0, self.size, PGSIZE) {
+            self.fault_in(i.into(), allocator).ok()?;
             let pte = self
                 .page_table
                 .get_mut(i.into(), None)
@@ -336,17 +441,27 @@ impl UserMemory {
 
             let pa = pte.get_pa();
             let flags = pte.get_flags();
-            let mut page = allocator.alloc()?;
-            // SAFETY: pa is an address in page_table,
-            // and thus it is the address of a page by the invariant.
-            let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
-            page.copy_from_slice(src);
+            let page = if pte.get_access_flags().contains(AccessFlags::W) {
+                let mut page = allocator.alloc()?;
+                // SAFETY: pa is an address in page_table,
+                // and thus it is the address of a page by the invariant.
+                let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
+                page.copy_from_slice(src);
+                page
+            } else {
+                // A read-only page, such as the text of a program, is shared rather than copied.
+                // SAFETY: pa is an address in page_table,
+                // and thus it is the address of a page by the invariant.
+                let page = ManuallyDrop::new(unsafe { Page::from_usize(pa.into_usize()) });
+                allocator.share(&page)
+            };
             new.push_page(page, flags, allocator)
                 .map_err(|page| allocator.free(page))
                 .ok()?;
         }
         let mut new = scopeguard::ScopeGuard::into_inner(new);
         new.size = self.size;
+        new.heap = self.heap;
         Some(new)
     }
 
@@ -355,8 +470,8 @@ impl UserMemory {
         self.size
     }
 
-    /// Load data from a file into memory at virtual address va. va must be
-    /// page-aligned, and the pages from va to va + sz must already be mapped.
+    /// Load data from a file into memory at virtual address va. The pages
+    /// from va to va + sz must already be mapped, writable or copy-on-write.
     ///
     /// Returns Ok(()) on success, Err(()) on failure.
     pub fn load_file(
@@ -367,22 +482,60 @@ impl UserMemory {
         sz: u32,
         ctx: &KernelCtx<'_, '_>,
     ) -> Result<(), ()> {
-        assert!(va.is_page_aligned(), "load_file: va must be page aligned");
-        for i in num_iter::range_step(0, sz, PGSIZE as _) {
-            let dst = self
-                .get_slice(va + i as usize)
-                .expect("load_file: address should exist");
-            let n = cmp::min((sz - i) as usize, PGSIZE);
-            let bytes_read = ip.read_bytes_kernel(&mut dst[..n], offset + i, ctx);
+        let mut dst = va.into_usize();
+        let mut i = 0;
+        while i < sz {
+            let poffset = dst % PGSIZE;
+            let n = cmp::min((sz - i) as usize, PGSIZE - poffset);
+            let page = self.get_writable_slice(pgrounddown(dst).into()).ok_or(())?;
+            let bytes_read = ip.read_bytes_kernel(&mut page[poffset..poffset + n], offset + i, ctx);
             if bytes_read != n {
                 return Err(());
             }
+            i += n as u32;
+            dst += n;
         }
         Ok(())
     }
 
-    /// Allocate PTEs and physical memory to grow process to newsz, which need
-    /// not be page aligned. Returns Ok(new size) or Err(()) on error.
+    /// Map the pages of a file from `offset`, which must be page-aligned, at the
+    /// end of the memory, which must be page-aligned as well, as long as `sz`
+    /// bytes cover whole pages. `share_page` returns the page of a given index
+    /// within the file, shared with the page cache and the other processes
+    /// running the file, so the pages are mapped read-only.
+    ///
+    /// Returns the number of bytes mapped.
+    pub fn map_file<F: FnMut(usize) -> Option<Page>>(
+        &mut self,
+        mut share_page: F,
+        offset: usize,
+        sz: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> usize {
+        assert!(
+            self.size % PGSIZE == 0 && offset % PGSIZE == 0,
+            "map_file: not page aligned"
+        );
+        let mut mapped = 0;
+        while mapped + PGSIZE <= sz {
+            let page = some_or!(share_page((offset + mapped) / PGSIZE), break);
+            if let Err(page) = self.push_page(
+                page,
+                (AccessFlags::R | AccessFlags::X | AccessFlags::U).into(),
+                allocator,
+            ) {
+                allocator.free(page);
+                break;
+            }
+            mapped += PGSIZE;
+        }
+        mapped
+    }
+
+    /// Allocate PTEs to grow process to newsz, which need not be page aligned.
+    /// The new pages map the zero page, copy-on-write, so that physical memory
+    /// is allocated only when each of them is first written.
+    /// Returns Ok(new size) or Err(()) on error.
     pub fn alloc(&mut self, newsz: usize, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
         if newsz <= self.size {
             return Ok(self.size);
@@ -393,14 +546,17 @@ impl UserMemory {
             let _ = this.dealloc(oldsz, allocator);
         });
         while pgroundup(this.size) < pgroundup(newsz) {
-            let mut page = allocator.alloc().ok_or(())?;
-            page.write_bytes(0);
+            let va = pgroundup(this.size);
             this.push_page(
-                page,
+                allocator.zero_page(),
                 (AccessFlags::R | AccessFlags::W | AccessFlags::X | AccessFlags::U).into(),
                 allocator,
             )
             .map_err(|page| allocator.free(page))?;
+            this.page_table
+                .get_mut(va.into(), None)
+                .expect("alloc")
+                .set_cow(false);
         }
         let this = scopeguard::ScopeGuard::into_inner(this);
         this.size = newsz;
@@ -409,20 +565,39 @@ impl UserMemory {
 
     /// Deallocate user pages to bring the process size to newsz, which need
     /// not be page-aligned. Returns the new process size.
+    ///
+    /// Other CPUs may still be using the pages through their TLBs, so the pages are freed only
+    /// after a TLB shootdown, which is done once for every `TLB_BATCH` pages.
     pub fn dealloc(&mut self, newsz: usize, allocator: Pin<&SpinLock<Kmem>>) -> usize {
         if self.size <= newsz {
             return self.size;
         }
 
+        let mut batch = ArrayVec::<Page, TLB_BATCH>::new();
         while pgroundup(newsz) < pgroundup(self.size) {
             if let Some(page) = self.pop_page() {
-                allocator.free(page);
+                if batch.is_full() {
+                    self.free_batch(&mut batch, allocator);
+                }
+                batch.push(page);
             }
         }
+        self.free_batch(&mut batch, allocator);
         self.size = newsz;
         newsz
     }
 
+    /// Frees the unmapped pages in `batch`, after flushing them from the TLBs of every CPU.
+    fn free_batch(&self, batch: &mut ArrayVec<Page, TLB_BATCH>, allocator: Pin<&SpinLock<Kmem>>) {
+        if batch.is_empty() {
+            return;
+        }
+        hal().ipi().tlb_shootdown(self.cpus);
+        for page in batch.drain(..) {
+            allocator.free(page);
+        }
+    }
+
     /// Grow or shrink process size by n bytes.
     /// Return Ok(old size) on success, Err(()) on failure.
     pub fn resize(&mut self, n: i32, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
@@ -439,6 +614,337 @@ impl UserMemory {
         Ok(size)
     }
 
+    /// Start the heap at the end of the memory.
+    pub fn start_heap(&mut self) {
+        self.heap = self.size;
+    }
+
+    /// Grow or shrink the heap so that the process size becomes addr, which
+    /// must not be below the start of the heap. Pages above addr are unmapped
+    /// and freed. Returns Ok(new size) on success, Err(()) on error.
+    pub fn brk(&mut self, addr: usize, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
+        if addr < self.heap || addr > TRAPFRAME {
+            return Err(());
+        }
+        if addr >= self.size {
+            self.alloc(addr, allocator)
+        } else {
+            Ok(self.dealloc(addr, allocator))
+        }
+    }
+
+    /// Advise that the pages from va, which must be page-aligned, to va + len will not be
+    /// needed. The pages stay mapped, and read as zeros once freed. If `lazy`, each page is
+    /// freed only when memory runs short, unless it is written before; otherwise, it is freed at
+    /// once. Read-only pages, such as the text of a program, are left as they are.
+    /// Returns Ok(()) on success, Err(()) on error.
+    pub fn madvise(
+        &mut self,
+        va: usize,
+        len: usize,
+        lazy: bool,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let end = va.checked_add(len).ok_or(())?;
+        if va % PGSIZE != 0 || pgroundup(end) > pgroundup(self.size) {
+            return Err(());
+        }
+
+        let mut batch = ArrayVec::<Page, TLB_BATCH>::new();
+        for va in num_iter::range_step(va, end, PGSIZE) {
+            let pte = self.page_table.get_mut(va.into(), None).expect("madvise");
+            if let Some(slot) = pte.swapped() {
+                // The page has left the memory already, and may be dropped as well.
+                hal().zram().free(slot);
+                pte.swap_in(allocator.zero_page().into_usize().into());
+                pte.set_cow(false);
+                continue;
+            }
+            let flags = pte.get_access_flags();
+            if !flags.contains(AccessFlags::U) || !(flags.contains(AccessFlags::W) || pte.is_cow())
+            {
+                continue;
+            }
+            if lazy {
+                pte.set_cow(true);
+            } else if !allocator.is_zero_page(pte.get_pa()) {
+                let page = Self::map_zero_page(pte, allocator);
+                if batch.is_full() {
+                    self.free_batch(&mut batch, allocator);
+                }
+                batch.push(page);
+            }
+        }
+        if lazy {
+            // Take the write permission away from the TLBs as well.
+            hal().ipi().tlb_shootdown(self.cpus);
+        }
+        self.free_batch(&mut batch, allocator);
+        Ok(())
+    }
+
+    /// Free the lazily freed pages that are not shared, mapping the zero page in their place.
+    /// The pages not accessed since their accessed bits were last cleared are freed first, and
+    /// the others only if there are none of them. If there are no lazily freed pages at all,
+    /// swap out some private pages into the compressed swap instead.
+    /// Returns whether any page has been freed.
+    pub fn reclaim(&mut self, allocator: Pin<&SpinLock<Kmem>>) -> bool {
+        self.reclaim_pages(false, allocator)
+            || self.reclaim_pages(true, allocator)
+            || self.swap_out(SWAP_BATCH) > 0
+    }
+
+    /// Free the lazily freed pages that are not shared, and not accessed unless `accessed`.
+    /// Returns whether any page has been freed.
+    fn reclaim_pages(&mut self, accessed: bool, allocator: Pin<&SpinLock<Kmem>>) -> bool {
+        let mut freed = false;
+        let mut batch = ArrayVec::<Page, TLB_BATCH>::new();
+        for va in num_iter::range_step(0, self.size, PGSIZE) {
+            let pte = self.page_table.get_mut(va.into(), None).expect("reclaim");
+            if !pte.is_lazyfree() || (pte.is_accessed() && !accessed) {
+                continue;
+            }
+            // SAFETY: pte.get_pa() is an address in page_table,
+            // and thus it is the address of a page by the invariant.
+            let page = ManuallyDrop::new(unsafe { Page::from_usize(pte.get_pa().into_usize()) });
+            if allocator.is_shared(&page) {
+                continue;
+            }
+            let page = Self::map_zero_page(pte, allocator);
+            if batch.is_full() {
+                self.free_batch(&mut batch, allocator);
+            }
+            batch.push(page);
+            freed = true;
+        }
+        self.free_batch(&mut batch, allocator);
+        freed
+    }
+
+    /// Swap out at most n writable user pages into the compressed swap, choosing them by the
+    /// clock algorithm: starting from where the last call stopped, a page accessed since its
+    /// accessed bit was last cleared gets its bit cleared and is passed over once.
+    /// Returns the number of pages swapped out.
+    fn swap_out(&mut self, n: usize) -> usize {
+        let npages = self.size / PGSIZE;
+        let mut swapped = 0;
+        let mut cleared = false;
+        let mut batch = ArrayVec::<(usize, Page), TLB_BATCH>::new();
+        for i in 0..2 * npages {
+            if swapped + batch.len() == n {
+                break;
+            }
+            if self.swap_next >= npages * PGSIZE {
+                self.swap_next = 0;
+            }
+            let va = self.swap_next;
+            self.swap_next += PGSIZE;
+            let pte = self.page_table.get_mut(va.into(), None).expect("swap_out");
+            if !pte.is_data()
+                || !pte
+                    .get_access_flags()
+                    .contains(AccessFlags::U | AccessFlags::W)
+            {
+                continue;
+            }
+            // Pass over an accessed page only in the first round, for hardware without the
+            // accessed bits.
+            if pte.is_accessed() && i < npages {
+                pte.clear_accessed();
+                cleared = true;
+                continue;
+            }
+            // SAFETY: pte.get_pa() is an address in page_table,
+            // and thus it is the address of a page by the invariant.
+            let page = unsafe { Page::from_usize(pte.get_pa().into_usize()) };
+            // The slot is known only once the page is stored, after the TLB shootdown.
+            pte.swap_out(0);
+            batch.push((va, page));
+            if batch.is_full() {
+                swapped += self.swap_out_batch(&mut batch);
+                cleared = false;
+            }
+        }
+        if batch.is_empty() && cleared {
+            // Let the hardware set the accessed bits cleared above again.
+            hal().ipi().tlb_shootdown(self.cpus);
+        }
+        swapped + self.swap_out_batch(&mut batch)
+    }
+
+    /// Store the pages in `batch`, whose entries have been made invalid, into the compressed
+    /// swap after flushing them from the TLBs of every CPU. A page that cannot be stored is
+    /// mapped again. Returns the number of pages stored.
+    fn swap_out_batch(&mut self, batch: &mut ArrayVec<(usize, Page), TLB_BATCH>) -> usize {
+        if batch.is_empty() {
+            return 0;
+        }
+        hal().ipi().tlb_shootdown(self.cpus);
+        let mut swapped = 0;
+        for (va, page) in batch.drain(..) {
+            let pte = self.page_table.get_mut(va.into(), None).expect("swap_out");
+            match hal().zram().store(page) {
+                Ok(slot) => {
+                    pte.swap_out(slot);
+                    swapped += 1;
+                }
+                Err(page) => pte.swap_in(page.into_usize().into()),
+            }
+        }
+        swapped
+    }
+
+    /// Bring the page at va back from the compressed swap into a new page, if it has been
+    /// swapped out. Ok(()) on success, Err(()) if a page could not be allocated.
+    fn swap_in(&mut self, va: UVAddr, allocator: Pin<&SpinLock<Kmem>>) -> Result<(), ()> {
+        let pte = self.page_table.get_mut(va, None).ok_or(())?;
+        let slot = some_or!(pte.swapped(), return Ok(()));
+        let mut page = allocator.alloc().ok_or(())?;
+        hal().zram().load(slot, &mut page);
+        pte.swap_in(page.into_usize().into());
+        Ok(())
+    }
+
+    /// Bring the page at va back from the compressed swap, reclaiming memory first if there is
+    /// no page for it. Ok(()) on success, Err(()) on failure.
+    fn fault_in(&mut self, va: UVAddr, allocator: Pin<&SpinLock<Kmem>>) -> Result<(), ()> {
+        if self.swap_in(va, allocator).is_ok() {
+            return Ok(());
+        }
+        if self.reclaim(allocator) {
+            self.swap_in(va, allocator)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Scan n writable user pages for merging, starting from where the last scan stopped. Each
+    /// page becomes copy-on-write, or is replaced copy-on-write by another page of the same
+    /// contents, as `merge` decides.
+    pub fn merge_pages<F: FnMut(&Page) -> Merge>(
+        &mut self,
+        n: usize,
+        mut merge: F,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) {
+        let npages = self.size / PGSIZE;
+        let mut protected = false;
+        let mut batch = ArrayVec::<Page, TLB_BATCH>::new();
+        for _ in 0..cmp::min(n, npages) {
+            if self.merge_next >= npages * PGSIZE {
+                self.merge_next = 0;
+            }
+            let va = self.merge_next;
+            self.merge_next += PGSIZE;
+            let pte = self
+                .page_table
+                .get_mut(va.into(), None)
+                .expect("merge_pages");
+            if !pte.is_data()
+                || !pte
+                    .get_access_flags()
+                    .contains(AccessFlags::U | AccessFlags::W)
+            {
+                continue;
+            }
+            // SAFETY: pte.get_pa() is an address in page_table,
+            // and thus it is the address of a page by the invariant.
+            let page = ManuallyDrop::new(unsafe { Page::from_usize(pte.get_pa().into_usize()) });
+            match merge(&page) {
+                Merge::Keep => {}
+                Merge::Protect => {
+                    pte.set_cow(false);
+                    protected = true;
+                }
+                Merge::Replace(same) => {
+                    let page = Self::map_page(pte, same);
+                    if batch.is_full() {
+                        self.free_batch(&mut batch, allocator);
+                    }
+                    batch.push(page);
+                }
+            }
+        }
+        if protected && batch.is_empty() {
+            // Take the write permission away from the TLBs as well.
+            hal().ipi().tlb_shootdown(self.cpus);
+        }
+        self.free_batch(&mut batch, allocator);
+    }
+
+    /// Handle a page fault of the process at addr, which writes if `write`. A write to a
+    /// copy-on-write page gets the page its write permission back.
+    /// Returns Ok(()) if the process may retry the access, Err(FaultError) otherwise.
+    pub fn handle_fault(
+        &mut self,
+        addr: usize,
+        write: bool,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), FaultError> {
+        if addr >= self.size {
+            return Err(FaultError::Invalid);
+        }
+        let va = pgrounddown(addr).into();
+        let pte = self
+            .page_table
+            .get_mut(va, None)
+            .ok_or(FaultError::Invalid)?;
+        if pte.swapped().is_some() {
+            return self
+                .fault_in(va, allocator)
+                .map_err(|_| FaultError::OutOfMemory);
+        }
+        let flags = pte.get_access_flags();
+        if flags.contains(AccessFlags::U)
+            && (!write || flags.contains(AccessFlags::W))
+            && !(pte.is_accessed() && (!write || pte.is_dirty()))
+        {
+            // The hardware does not set the accessed and dirty bits by itself.
+            pte.set_accessed(write);
+            hal().ipi().tlb_shootdown(self.cpus);
+            return Ok(());
+        }
+        if !write || !(pte.is_user() && pte.is_cow()) {
+            return Err(FaultError::Invalid);
+        }
+        // The page is copy-on-write, so break_cow fails only if a copy cannot be allocated.
+        if self.break_cow(va, allocator).is_ok() {
+            return Ok(());
+        }
+        // Retry with the lazily freed pages freed.
+        if self.reclaim(allocator) && self.break_cow(va, allocator).is_ok() {
+            Ok(())
+        } else {
+            Err(FaultError::OutOfMemory)
+        }
+    }
+
+    /// Count the user pages accessed and the user pages written since their accessed and dirty
+    /// bits were last cleared, and clear the bits. Returns (accessed pages, dirty pages).
+    pub fn scan_accessed(&mut self) -> (usize, usize) {
+        let mut accessed = 0;
+        let mut dirty = 0;
+        for va in num_iter::range_step(0, self.size, PGSIZE) {
+            let pte = self
+                .page_table
+                .get_mut(va.into(), None)
+                .expect("scan_accessed");
+            if !pte.is_data() || !pte.get_access_flags().contains(AccessFlags::U) {
+                continue;
+            }
+            if pte.is_accessed() {
+                accessed += 1;
+            }
+            if pte.is_dirty() {
+                dirty += 1;
+            }
+            pte.clear_accessed();
+        }
+        // The hardware sets the bits again only after the TLB entries are flushed.
+        hal().ipi().tlb_shootdown(self.cpus);
+        (accessed, dirty)
+    }
+
     /// Mark a PTE invalid for user access.
     /// Used by exec for the user stack guard page.
     pub fn clear(&mut self, va: UVAddr) {
@@ -446,6 +952,7 @@ impl UserMemory {
             .get_mut(va, None)
             .expect("clear")
             .clear_user();
+        hal().ipi().tlb_shootdown(self.cpus);
     }
 
     /// Copy from kernel to user.
@@ -458,7 +965,7 @@ impl UserMemory {
         while len > 0 {
             let va = pgrounddown(dst);
             let poffset = dst - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self.get_writable_slice(va.into()).ok_or(())?;
             let n = cmp::min(PGSIZE - poffset, len);
             page[poffset..poffset + n].copy_from_slice(&src[offset..offset + n]);
             len -= n;
@@ -468,6 +975,23 @@ impl UserMemory {
         Ok(())
     }
 
+    /// Fill len bytes at virtual address dstva in a given page table with zeroes.
+    /// Return Ok(()) on success, Err(()) on error.
+    pub fn zero_out(&mut self, dstva: UVAddr, len: usize) -> Result<(), ()> {
+        let mut dst = dstva.into_usize();
+        let mut len = len;
+        while len > 0 {
+            let va = pgrounddown(dst);
+            let poffset = dst - va;
+            let page = self.get_writable_slice(va.into()).ok_or(())?;
+            let n = cmp::min(PGSIZE - poffset, len);
+            page[poffset..poffset + n].fill(0);
+            len -= n;
+            dst += n;
+        }
+        Ok(())
+    }
+
     /// Copy from kernel to user.
     /// Copy from src to virtual address dstva in a given page table.
     /// Return Ok(()) on success, Err(()) on error.
@@ -537,16 +1061,79 @@ impl UserMemory {
         Err(())
     }
 
+    /// Prepares the current CPU to switch to the page table, and returns the ASID to switch with.
+    /// Must be called with interrupts disabled.
+    pub fn activate(&mut self, asids: &AsidAllocator) -> usize {
+        if asids.activate(&mut self.asid) {
+            // No CPU has TLB entries under the new ASID.
+            self.cpus = 0;
+        }
+        self.cpus |= 1 << TargetArch::cpu_id();
+        self.asid.id
+    }
+
     /// Return the address of the page table
     pub fn page_table_addr(&self) -> usize {
         self.page_table.as_usize()
     }
 
+    /// Summarize the mappings of this memory, including the trampoline and the trap frame.
+    /// Consecutive pages with the same permissions are coalesced into a region, and
+    /// the regions are stored into `regions` as many as it can hold.
+    pub fn map_info(&self, regions: &mut [MapRegion]) -> MapInfo {
+        let mut rss = 0;
+        let mut nregion = 0;
+        let mut ptpages = [0; 3];
+        let mut current: Option<MapRegion> = None;
+        let mut flush = |region: MapRegion| {
+            if let Some(r) = regions.get_mut(nregion) {
+                *r = region;
+            }
+            nregion += 1;
+        };
+
+        // SAFETY: self.page_table.ptr uniquely refers to a valid RawPageTable
+        // according to the invariant.
+        let root = unsafe { &*self.page_table.ptr };
+        let kmem = hal().kmem();
+        root.walk(2, 0, &mut ptpages, &mut |va, pte| {
+            // The zero page takes no memory of this process.
+            if !kmem.is_zero_page(pte.get_pa()) {
+                rss += 1;
+            }
+            let perm = pte.get_access_flags().bits();
+            if let Some(r) = current.as_mut().filter(|r| r.end == va && r.perm == perm) {
+                r.end += PGSIZE;
+                return;
+            }
+            if let Some(r) = current.replace(MapRegion {
+                start: va,
+                end: va + PGSIZE,
+                perm,
+            }) {
+                flush(r);
+            }
+        });
+        if let Some(r) = current {
+            flush(r);
+        }
+
+        MapInfo {
+            size: self.size,
+            rss,
+            ptpages,
+            nregion,
+        }
+    }
+
     /// Return a page at va as a slice. Some(page) on success, None on failure.
     fn get_slice(&mut self, va: UVAddr) -> Option<&mut [u8]> {
         if va.into_usize() >= TRAPFRAME {
             return None;
         }
+        if va.into_usize() < self.size {
+            self.fault_in(va, hal().kmem()).ok()?;
+        }
         let pte = self.page_table.get_mut(va, None)?;
         if !pte.is_user() {
             return None;
@@ -555,6 +1142,64 @@ impl UserMemory {
         Some(unsafe { slice::from_raw_parts_mut(pte.get_pa().into_usize() as _, PGSIZE) })
     }
 
+    /// Return a page at va as a slice to write. None if the page is not
+    /// writable, such as a page shared with the page cache. A copy-on-write
+    /// page is made writable, as the process would by writing to it.
+    fn get_writable_slice(&mut self, va: UVAddr) -> Option<&mut [u8]> {
+        let pte = self.page_table.get_mut(va, None)?;
+        if pte.is_cow() {
+            self.break_cow(va, hal().kmem()).ok()?;
+        } else if !pte.get_access_flags().contains(AccessFlags::W) {
+            return None;
+        }
+        self.get_slice(va)
+    }
+
+    /// Give the copy-on-write page at va its write permission back, copying the page first if
+    /// it is shared. Ok(()) on success, Err(()) if the page is not copy-on-write or a copy could
+    /// not be allocated.
+    fn break_cow(&mut self, va: UVAddr, allocator: Pin<&SpinLock<Kmem>>) -> Result<(), ()> {
+        let pte = self
+            .page_table
+            .get_mut(va, None)
+            .filter(|pte| pte.is_user() && pte.is_cow())
+            .ok_or(())?;
+        // SAFETY: pte.get_pa() is an address in page_table,
+        // and thus it is the address of a page by the invariant.
+        let page = ManuallyDrop::new(unsafe { Page::from_usize(pte.get_pa().into_usize()) });
+        let shared = allocator.is_shared(&page);
+        if shared {
+            let mut copy = allocator.alloc().ok_or(())?;
+            copy.copy_from_slice(&page[..]);
+            let flags = pte.get_flags();
+            pte.set_entry(copy.into_usize().into(), flags);
+        }
+        pte.clear_cow();
+        hal().ipi().tlb_shootdown(self.cpus);
+        if shared {
+            allocator.free(ManuallyDrop::into_inner(page));
+        }
+        Ok(())
+    }
+
+    /// Map the zero page, copy-on-write, in place of the page of pte, which is returned to be
+    /// freed after a TLB shootdown.
+    fn map_zero_page(pte: &mut PageTableEntry, allocator: Pin<&SpinLock<Kmem>>) -> Page {
+        Self::map_page(pte, allocator.zero_page())
+    }
+
+    /// Map `page`, copy-on-write, in place of the page of pte, which is returned to be freed
+    /// after a TLB shootdown. `page` must be shared, as it is never written.
+    fn map_page(pte: &mut PageTableEntry, page: Page) -> Page {
+        let pa = pte.get_pa().into_usize();
+        pte.set_cow(false);
+        let flags = pte.get_flags();
+        pte.set_entry(page.into_usize().into(), flags);
+        // SAFETY: pa was an address in page_table,
+        // and thus it is the address of a page by the invariant.
+        unsafe { Page::from_usize(pa) }
+    }
+
     /// Increase the size by appending a given page with given flags.
     /// Ok(()) on success, Err(given page) on failure.
     fn push_page(
@@ -575,12 +1220,22 @@ impl UserMemory {
     }
 
     /// Decrease the size by removing the most recently appended page.
-    /// Some(page) if size > 0, None if size = 0.
+    /// Some(page) if size > 0, None if size = 0 or the page has been swapped out, in which case
+    /// it is dropped from the compressed swap.
     fn pop_page(&mut self) -> Option<Page> {
         if self.size == 0 {
             return None;
         }
         self.size = pgroundup(self.size) - PGSIZE;
+        let pte = self
+            .page_table
+            .get_mut(self.size.into(), None)
+            .expect("pop_page");
+        if let Some(slot) = pte.swapped() {
+            pte.invalidate();
+            hal().zram().free(slot);
+            return None;
+        }
         let pa = self
             .page_table
             .remove(self.size.into())
@@ -591,8 +1246,15 @@ impl UserMemory {
         Some(unsafe { Page::from_usize(pa) })
     }
 
+    /// Frees the memory, which no CPU may use anymore. The TLB entries of the memory that
+    /// CPUs may still have are not flushed, since they are tagged with an ASID that is not used
+    /// again until the CPUs flush their TLBs for a new generation of ASIDs.
     pub fn free(mut self, allocator: Pin<&SpinLock<Kmem>>) {
-        let _ = self.dealloc(0, allocator);
+        while self.size > 0 {
+            if let Some(page) = self.pop_page() {
+                allocator.free(page);
+            }
+        }
         // SAFETY: self will be dropped.
         unsafe { self.page_table.free(allocator) };
         mem::forget(self);
@@ -607,6 +1269,69 @@ impl Drop for UserMemory {
     }
 }
 
+/// An address space identifier (ASID), which is valid only in the generation it was allocated in.
+#[derive(Default)]
+pub struct Asid {
+    id: usize,
+    generation: usize,
+}
+
+/// Allocates the ASIDs of user page tables, so that switching between page tables needs no TLB
+/// flush.
+///
+/// ASIDs are handed out in generations. When every ASID of a generation has been handed out, a
+/// new generation begins, and each page table gets a new ASID the next time it is activated.
+/// Each CPU flushes its TLB the first time it activates a page table in a new generation, so
+/// the TLB entries of a page table are never used for another page table that reuses its ASID.
+pub struct AsidAllocator {
+    /// The current generation, and the next ASID to hand out in it.
+    inner: SpinLock<(usize, usize)>,
+
+    /// The generation each CPU has flushed its TLB for.
+    cpu_generations: [AtomicUsize; NCPU],
+}
+
+impl AsidAllocator {
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new("asid", (1, 1)),
+            cpu_generations: array![_ => AtomicUsize::new(0); NCPU],
+        }
+    }
+
+    /// Makes `asid` valid in the current generation, and flushes the TLB of the current CPU if
+    /// it has not been flushed for the generation.
+    /// Returns true if `asid` has changed. Must be called with interrupts disabled.
+    fn activate(&self, asid: &mut Asid) -> bool {
+        if TargetArch::num_asids() <= 1 {
+            // Every page table uses ASID 0, and the TLB is flushed on every switch.
+            return false;
+        }
+
+        let mut inner = self.inner.lock();
+        let (generation, next) = &mut *inner;
+        let renewed = asid.generation != *generation;
+        if renewed {
+            if *next == TargetArch::num_asids() {
+                *generation += 1;
+                *next = 1;
+            }
+            asid.id = *next;
+            asid.generation = *generation;
+            *next += 1;
+        }
+        let generation = *generation;
+        drop(inner);
+
+        let cpu_generation = &self.cpu_generations[TargetArch::cpu_id()];
+        if cpu_generation.load(Ordering::Relaxed) != generation {
+            TargetArch::flush_tlb();
+            cpu_generation.store(generation, Ordering::Relaxed);
+        }
+        renewed
+    }
+}
+
 /// KernelMemory manages the page table and allocated pages of the kernel.
 /// Every PAddr in KernelMemory is not originated from a page. KernelMemory
 /// neither provides memory read/write methods nor decreases memory. Therefore,
@@ -616,11 +1341,38 @@ impl Drop for UserMemory {
 // what would be the proper invariant for KernelMemory and whether we can
 // combine UserMemory and KernelMemory to form a single type.
 pub struct KernelMemory<A> {
-    /// Page table of kernel.
-    page_table: PageTable<KVAddr>,
+    /// Page table of kernel. After `new`, only kernel stacks are mapped and unmapped.
+    page_table: SpinLock<PageTable<KVAddr>>,
+
+    kstacks: KStacks,
+
     _marker: PhantomData<A>,
 }
 
+/// Kernel stacks, which are mapped at `kstack(i)` for the slots i < `NKSTACK`.
+///
+/// A freed stack stays mapped in a pool to be reused, unless the pool is full. The page of a
+/// stack is freed when the stack is unmapped. Since the TLB of a CPU may still map an unmapped
+/// stack, each CPU flushes its TLB before it runs a process if a stack has been unmapped since
+/// it last flushed.
+struct KStacks {
+    inner: SpinLock<KStackSlots>,
+
+    /// Number of stacks unmapped so far.
+    generation: AtomicUsize,
+
+    /// The generation each CPU has flushed its TLB for.
+    cpu_generations: [AtomicUsize; NCPU],
+}
+
+struct KStackSlots {
+    /// Bit i % 64 of `mapped[i / 64]` is set if slot i has a stack mapped.
+    mapped: [u64; NKSTACK / 64],
+
+    /// Slots of the freed stacks that are still mapped.
+    pool: ArrayVec<usize, NKSTACKPOOL>,
+}
+
 impl<A: Arch> KernelMemory<A> {
     /// Make a direct-map page table for the kernel.
     pub fn new(allocator: Pin<&SpinLock<Kmem>>) -> Option<Self> {
@@ -630,100 +1382,165 @@ impl<A: Arch> KernelMemory<A> {
             mem::forget(page_table);
         });
 
+        // No kernel page is both writable and executable (W^X).
+        let mut insert_range = |va: usize, size: usize, pa: usize, perm: AccessFlags| {
+            assert!(
+                !perm.contains(AccessFlags::W | AccessFlags::X),
+                "KernelMemory::new: writable and executable"
+            );
+            page_table.insert_range(va.into(), size, pa.into(), perm.into(), allocator)
+        };
+
         for (start, range) in A::kernel_page_dev_mappings() {
-            page_table
-                .insert_range(
-                    (*start).into(),
-                    *range,
-                    (*start).into(),
-                    (AccessFlags::R | AccessFlags::W).into(),
-                    allocator,
-                )
-                .ok()?;
+            insert_range(*start, *range, *start, AccessFlags::R | AccessFlags::W).ok()?;
         }
 
         // Uart registers
-        page_table
-            .insert_range(
-                A::UART0.into(),
-                PGSIZE,
-                A::UART0.into(),
-                (AccessFlags::R | AccessFlags::W).into(),
-                allocator,
-            )
-            .ok()?;
+        insert_range(A::UART0, PGSIZE, A::UART0, AccessFlags::R | AccessFlags::W).ok()?;
 
-        // Virtio mmio disk interface
-        page_table
-            .insert_range(
-                A::VIRTIO0.into(),
-                PGSIZE,
-                A::VIRTIO0.into(),
-                (AccessFlags::R | AccessFlags::W).into(),
-                allocator,
-            )
-            .ok()?;
+        // Virtio mmio disk interface, and those of the entropy device, the console, and the 9p
+        // transport after it
+        insert_range(
+            A::VIRTIO0,
+            pgroundup(A::VIRTIO3 - A::VIRTIO0 + 1),
+            A::VIRTIO0,
+            AccessFlags::R | AccessFlags::W,
+        )
+        .ok()?;
 
         // Map the trampoline for trap entry/exit to
         // the highest virtual address in the kernel.
-        page_table
-            .insert_range(
-                TRAMPOLINE.into(),
-                PGSIZE,
-                // SAFETY: we assume that reading the address of trampoline is safe.
-                unsafe { trampoline.as_mut_ptr() as usize }.into(),
-                (AccessFlags::R | AccessFlags::X).into(),
-                allocator,
-            )
-            .ok()?;
+        insert_range(
+            TRAMPOLINE,
+            PGSIZE,
+            // SAFETY: we assume that reading the address of trampoline is safe.
+            unsafe { trampoline.as_mut_ptr() as usize },
+            AccessFlags::R | AccessFlags::X,
+        )
+        .ok()?;
 
         // Map kernel text executable and read-only.
         // SAFETY: we assume that reading the address of etext is safe.
         let et = unsafe { etext.as_mut_ptr() as usize };
-        page_table
-            .insert_range(
-                A::KERNBASE.into(),
-                et - A::KERNBASE,
-                A::KERNBASE.into(),
-                (AccessFlags::R | AccessFlags::X).into(),
-                allocator,
-            )
-            .ok()?;
+        insert_range(
+            A::KERNBASE,
+            et - A::KERNBASE,
+            A::KERNBASE,
+            AccessFlags::R | AccessFlags::X,
+        )
+        .ok()?;
 
         // Map kernel data and the physical RAM we'll make use of.
-        page_table
-            .insert_range(
-                et.into(),
-                PHYSTOP - et,
-                et.into(),
-                (AccessFlags::R | AccessFlags::W).into(),
-                allocator,
-            )
-            .ok()?;
+        insert_range(et, PHYSTOP - et, et, AccessFlags::R | AccessFlags::W).ok()?;
 
-        // Allocate a page for the process's kernel stack.
-        // Map it high in memory, followed by an invalid
-        // guard page.
-        for i in 0..NPROC {
-            let pa = allocator.alloc()?.into_usize();
-            let va: usize = kstack(i);
-            page_table
-                .insert_range(
-                    va.into(),
-                    PGSIZE,
-                    pa.into(),
-                    (AccessFlags::R | AccessFlags::W).into(),
-                    allocator,
-                )
-                .ok()?;
+        // Allocate the page-table pages of the kernel stacks now, so that mapping a stack needs
+        // only the page of the stack.
+        for i in 0..NKSTACK {
+            let _ = page_table.get_mut(kstack(i).into(), Some(allocator))?;
         }
 
         Some(Self {
-            page_table: scopeguard::ScopeGuard::into_inner(page_table),
+            page_table: SpinLock::new(
+                "kernel_page_table",
+                scopeguard::ScopeGuard::into_inner(page_table),
+            ),
+            kstacks: KStacks {
+                inner: SpinLock::new(
+                    "kstacks",
+                    KStackSlots {
+                        mapped: [0; NKSTACK / 64],
+                        pool: ArrayVec::new(),
+                    },
+                ),
+                generation: AtomicUsize::new(0),
+                cpu_generations: array![_ => AtomicUsize::new(0); NCPU],
+            },
             _marker: PhantomData,
         })
     }
 
+    /// Allocate a kernel stack, and return the address of its lowest byte.
+    /// Returns None if memory runs out, or every slot is taken.
+    pub fn alloc_kstack(&self, allocator: Pin<&SpinLock<Kmem>>) -> Option<usize> {
+        let mut slots = self.kstacks.inner.lock();
+        if let Some(i) = slots.pool.pop() {
+            return Some(kstack(i));
+        }
+        let i = (0..NKSTACK).find(|i| slots.mapped[i / 64] & (1 << (i % 64)) == 0)?;
+        let pa = allocator.alloc()?.into_usize();
+        // The page-table pages have been allocated by `new`.
+        self.page_table
+            .lock()
+            .insert(
+                kstack(i).into(),
+                pa.into(),
+                (AccessFlags::R | AccessFlags::W).into(),
+                allocator,
+            )
+            .expect("alloc_kstack");
+        slots.mapped[i / 64] |= 1 << (i % 64);
+        Some(kstack(i))
+    }
+
+    /// Free the kernel stack at `va`, which `alloc_kstack` returned.
+    pub fn free_kstack(&self, va: usize, allocator: Pin<&SpinLock<Kmem>>) {
+        let i = (TRAMPOLINE - va) / (2 * PGSIZE) - 1;
+        assert_eq!(kstack(i), va, "free_kstack");
+        let mut slots = self.kstacks.inner.lock();
+        if slots.pool.try_push(i).is_err() {
+            self.unmap_kstack(&mut slots, i, allocator);
+        }
+    }
+
+    /// Free the kernel stacks kept for reuse, so that their pages can be allocated.
+    /// Returns true if there were any.
+    pub fn drain_kstacks(&self, allocator: Pin<&SpinLock<Kmem>>) -> bool {
+        let mut slots = self.kstacks.inner.lock();
+        let drained = !slots.pool.is_empty();
+        while let Some(i) = slots.pool.pop() {
+            self.unmap_kstack(&mut slots, i, allocator);
+        }
+        drained
+    }
+
+    fn unmap_kstack(&self, slots: &mut KStackSlots, i: usize, allocator: Pin<&SpinLock<Kmem>>) {
+        let pa = self
+            .page_table
+            .lock()
+            .remove(kstack(i).into())
+            .expect("unmap_kstack");
+        let _ = self.kstacks.generation.fetch_add(1, Ordering::Relaxed);
+        slots.mapped[i / 64] &= !(1 << (i % 64));
+        // SAFETY: pa is the page of the stack, which is not used any longer.
+        allocator.free(unsafe { Page::from_usize(pa.into_usize()) });
+    }
+
+    /// Flush the TLB of the current CPU if a kernel stack has been unmapped since it last
+    /// flushed. Must be called with interrupts disabled, before running a process.
+    pub fn sync_kstacks(&self) {
+        let generation = self.kstacks.generation.load(Ordering::Relaxed);
+        let cpu_generation = &self.kstacks.cpu_generations[TargetArch::cpu_id()];
+        if cpu_generation.load(Ordering::Relaxed) != generation {
+            TargetArch::flush_tlb();
+            cpu_generation.store(generation, Ordering::Relaxed);
+        }
+    }
+
+    /// Calls `f` with the virtual address of every page that is mapped both writable and
+    /// executable, which `new` never does.
+    pub fn check_wx<F: FnMut(usize)>(&self, mut f: F) {
+        let mut ptpages = [0; 3];
+        let page_table = self.page_table.lock();
+        // SAFETY: page_table.ptr refers to a valid RawPageTable.
+        let root = unsafe { &*page_table.ptr };
+        root.walk(2, 0, &mut ptpages, &mut |va, pte| {
+            let perm = pte.get_access_flags();
+            if perm.contains(AccessFlags::W | AccessFlags::X) {
+                f(va);
+            }
+        });
+    }
+
     /// Initialize register(s) for turning MMU on.
     ///
     /// # Safety
@@ -732,7 +1549,7 @@ impl<A: Arch> KernelMemory<A> {
     pub unsafe fn init_register(&self) {
         // SAFETY: `self.page_table` contains valid page table address.
         unsafe {
-            A::switch_page_table_and_enable_mmu(self.page_table.as_usize());
+            A::switch_page_table_and_enable_mmu(self.page_table.lock().as_usize());
         }
     }
 }
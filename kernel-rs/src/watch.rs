@@ -0,0 +1,362 @@
+//! inotify-style file change notification.
+//!
+//! A process creates a watch with `watchopen()`, registers the inodes it is interested in with
+//! `watchadd()`, and reads `WatchEvent`s from the watch's file descriptor. The file system posts
+//! an event to every watch on an inode whenever the inode is changed:
+//! * `dirlink()` posts `CREATE` to the directory a new name is linked into,
+//! * `unlink()` posts `DELETE` to the directory a name is removed from, and
+//! * writing a file posts `MODIFY` to the file.
+
+use core::{mem, ops::Deref, ptr::NonNull};
+
+use array_macro::array;
+use bitflags::bitflags;
+use zerocopy::AsBytes;
+
+use crate::{
+    addr::UVAddr,
+    epoll::{Waiter, Waiters},
+    file::{FileType, RcFile, SelectEvent},
+    fs::Path,
+    kernel::KernelRef,
+    lock::SleepableLock,
+    param::{MAXPATH, NWATCH},
+    proc::KernelCtx,
+    some_or,
+    syscall::SyscallTable,
+};
+
+/// Maximum number of inodes a single watch can watch.
+const NWATCHTARGET: usize = 8;
+
+/// Maximum number of events queued in a single watch.
+const NWATCHEVENT: usize = 32;
+
+bitflags! {
+    pub struct WatchMask: u32 {
+        /// A name was linked into the watched directory.
+        const CREATE = 0x1;
+        /// A name was removed from the watched directory.
+        const DELETE = 0x2;
+        /// The watched file was written.
+        const MODIFY = 0x4;
+        /// Events were dropped because the queue was full.
+        const OVERFLOW = 0x8;
+    }
+}
+
+/// An event read from a watch's file descriptor.
+#[derive(Copy, Clone, AsBytes)]
+#[repr(C)]
+pub struct WatchEvent {
+    /// Watch descriptor of the watched inode, or -1 for `OVERFLOW`
+    pub wd: i32,
+
+    /// Kind of the event
+    pub mask: u32,
+
+    /// Inode number of the file the event is about
+    pub inum: u32,
+}
+
+#[derive(Copy, Clone)]
+struct WatchTarget {
+    dev: u32,
+    inum: u32,
+    mask: WatchMask,
+}
+
+struct WatchInner {
+    /// A file descriptor still refers to this watch.
+    open: bool,
+
+    /// Watched inodes, indexed by watch descriptor.
+    targets: [Option<WatchTarget>; NWATCHTARGET],
+
+    events: [WatchEvent; NWATCHEVENT],
+
+    /// Number of events read.
+    nread: u32,
+
+    /// Number of events posted.
+    nwrite: u32,
+
+    /// Events were dropped since the last read.
+    overflowed: bool,
+
+    /// Epolls watching the watch.
+    waiters: Waiters,
+}
+
+pub struct Watch {
+    inner: SleepableLock<WatchInner>,
+}
+
+pub struct WatchTable {
+    watches: [Watch; NWATCH],
+}
+
+/// # Safety
+///
+/// `ptr` always refers to an open watch in the kernel's `WatchTable`.
+/// For a single watch, we have a single `AllocatedWatch`, and the watch is marked closed
+/// only when the `AllocatedWatch` is closed.
+pub struct AllocatedWatch {
+    ptr: NonNull<Watch>,
+}
+
+// `AllocatedWatch` is `Send` because we access `WatchInner` only after acquring a lock
+// and because `AllocatedWatch` does not point to thread-local data.
+unsafe impl Send for AllocatedWatch {}
+
+impl Deref for AllocatedWatch {
+    type Target = Watch;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `ptr` always refers to a watch in the kernel's `WatchTable`.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl WatchInner {
+    const fn new() -> Self {
+        Self {
+            open: false,
+            targets: [None; NWATCHTARGET],
+            events: [WatchEvent {
+                wd: 0,
+                mask: 0,
+                inum: 0,
+            }; NWATCHEVENT],
+            nread: 0,
+            nwrite: 0,
+            overflowed: false,
+            waiters: Waiters::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.nread == self.nwrite && !self.overflowed
+    }
+
+    /// Queues an event of kind `mask` about inode `inum` for watch descriptor `wd`.
+    /// If the queue is full, the event is dropped and an `OVERFLOW` event is reported later.
+    fn push(&mut self, wd: usize, mask: WatchMask, inum: u32) {
+        if self.nwrite == self.nread.wrapping_add(NWATCHEVENT as u32) {
+            self.overflowed = true;
+            return;
+        }
+        self.events[self.nwrite as usize % NWATCHEVENT] = WatchEvent {
+            wd: wd as i32,
+            mask: mask.bits(),
+            inum,
+        };
+        self.nwrite = self.nwrite.wrapping_add(1);
+    }
+
+    /// Removes the next event from the queue, reporting dropped events first.
+    fn pop(&mut self) -> Option<WatchEvent> {
+        if self.overflowed {
+            self.overflowed = false;
+            return Some(WatchEvent {
+                wd: -1,
+                mask: WatchMask::OVERFLOW.bits(),
+                inum: 0,
+            });
+        }
+        if self.nread == self.nwrite {
+            return None;
+        }
+        let event = self.events[self.nread as usize % NWATCHEVENT];
+        self.nread = self.nread.wrapping_add(1);
+        Some(event)
+    }
+}
+
+impl Watch {
+    const fn new() -> Self {
+        Self {
+            inner: SleepableLock::new("watch", WatchInner::new()),
+        }
+    }
+}
+
+impl WatchTable {
+    pub const fn new() -> Self {
+        Self {
+            watches: array![_ => Watch::new(); NWATCH],
+        }
+    }
+
+    /// Allocates an unused watch.
+    fn alloc(&self) -> Result<AllocatedWatch, ()> {
+        for watch in &self.watches {
+            let mut inner = watch.inner.lock();
+            if !inner.open {
+                *inner = WatchInner::new();
+                inner.open = true;
+                return Ok(AllocatedWatch {
+                    ptr: NonNull::from(watch),
+                });
+            }
+        }
+        Err(())
+    }
+
+    /// Posts an event of kind `mask` about inode `inum` to every watch on inode `target` of
+    /// device `dev`, and wakes up the readers of the watches.
+    pub fn post(&self, dev: u32, target: u32, mask: WatchMask, inum: u32, ctx: &KernelCtx<'_, '_>) {
+        for watch in &self.watches {
+            let mut inner = watch.inner.lock();
+            if !inner.open {
+                continue;
+            }
+            let mut posted = false;
+            for wd in 0..NWATCHTARGET {
+                if let Some(t) = inner.targets[wd] {
+                    if t.dev == dev && t.inum == target && t.mask.contains(mask) {
+                        inner.push(wd, mask, inum);
+                        posted = true;
+                    }
+                }
+            }
+            if posted {
+                inner.wakeup(ctx.kernel());
+                inner.waiters.post(ctx.kernel());
+            }
+        }
+    }
+
+    /// Removes inode `inum` of device `dev` from every watch, since the inode was freed and
+    /// its inode number may be reused.
+    pub fn forget(&self, dev: u32, inum: u32) {
+        for watch in &self.watches {
+            let mut inner = watch.inner.lock();
+            for target in inner.targets.iter_mut() {
+                if matches!(target, Some(t) if t.dev == dev && t.inum == inum) {
+                    *target = None;
+                }
+            }
+        }
+    }
+}
+
+impl AllocatedWatch {
+    /// Reads whole events into `addr` by at most `n` bytes.
+    /// If there is no event, sleeps until an event is posted.
+    /// Returns Ok(number of bytes read) on success, Err(()) on error.
+    pub fn read(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+        let size = mem::size_of::<WatchEvent>();
+        if n < size {
+            return Err(());
+        }
+
+        let mut inner = self.inner.lock();
+        inner.wait_while_killable(|inner| inner.is_empty(), ctx)?;
+
+        let mut read = 0;
+        while read + size <= n {
+            let event = some_or!(inner.pop(), break);
+            ctx.proc_mut().memory_mut().copy_out(addr + read, &event)?;
+            read += size;
+        }
+        Ok(read)
+    }
+
+    /// Starts watching inode `inum` of device `dev` for events in `mask`.
+    /// If the inode is already watched, its mask is replaced.
+    /// Returns Ok(watch descriptor) on success, Err(()) on error.
+    pub fn add(&self, dev: u32, inum: u32, mask: u32) -> Result<usize, ()> {
+        let mask = WatchMask::from_bits(mask).ok_or(())? - WatchMask::OVERFLOW;
+        if mask.is_empty() {
+            return Err(());
+        }
+
+        let mut inner = self.inner.lock();
+        let wd = inner
+            .targets
+            .iter()
+            .position(|t| matches!(t, Some(t) if t.dev == dev && t.inum == inum))
+            .or_else(|| inner.targets.iter().position(|t| t.is_none()))
+            .ok_or(())?;
+        inner.targets[wd] = Some(WatchTarget { dev, inum, mask });
+        Ok(wd)
+    }
+
+    /// Stops watching the inode of watch descriptor `wd`.
+    pub fn remove(&self, wd: usize) -> Result<(), ()> {
+        let mut inner = self.inner.lock();
+        let target = inner.targets.get_mut(wd).ok_or(())?;
+        let _ = target.take().ok_or(())?;
+        Ok(())
+    }
+
+    pub fn close(self) {
+        let mut inner = self.inner.lock();
+        inner.open = false;
+        inner.targets = [None; NWATCHTARGET];
+    }
+
+    pub fn is_ready(&self, event: SelectEvent) -> bool {
+        match event {
+            SelectEvent::Read => !self.inner.lock().is_empty(),
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Registers `waiter` to be posted whenever an event is posted to the watch.
+    pub fn add_waiter(&self, waiter: Waiter, kernel: KernelRef<'_, '_>) -> Result<(), ()> {
+        self.inner.lock().waiters.add(waiter, kernel)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Create a watch, and return its file descriptor.
+    /// Returns Ok(fd) on success, Err(()) on error.
+    pub fn watch_open(&mut self) -> Result<i32, ()> {
+        let watch = self.kernel().watches().alloc()?;
+        let ptr = watch.ptr;
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Watch { watch }, true, false, self)
+            .map_err(|_| AllocatedWatch { ptr }.close())?;
+        f.fdalloc(self)
+    }
+
+    /// Create a watch for file change notification.
+    /// Returns Ok(new file descriptor) on success, Err(()) on error.
+    pub fn sys_watchopen(&mut self) -> Result<usize, ()> {
+        let fd = self.watch_open()?;
+        Ok(fd as usize)
+    }
+
+    /// Start watching the file at path with the watch fd for the events in mask.
+    /// Returns Ok(watch descriptor) on success, Err(()) on error.
+    pub fn sys_watchadd(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let f = f as *const RcFile;
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(1, &mut path)?);
+        let mask = self.proc().argint(2)?;
+        // SAFETY: watch_add will not access proc's open_files.
+        unsafe { (*f).watch_add(path, mask as u32, self) }
+    }
+
+    /// Stop watching the file of watch descriptor wd with the watch fd.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_watchrm(&mut self) -> Result<usize, ()> {
+        let (_, f) = self.proc().argfd(0)?;
+        let wd = self.proc().argint(1)?;
+        // SAFETY: watch_remove will not access proc's open_files.
+        unsafe { (*(f as *const RcFile)).watch_remove(wd as usize) }?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of watches.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(36, |ctx| ctx.sys_watchopen());
+    table.register(37, |ctx| ctx.sys_watchadd());
+    table.register(38, |ctx| ctx.sys_watchrm());
+}
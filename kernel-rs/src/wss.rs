@@ -0,0 +1,111 @@
+//! Working-set estimation.
+//!
+//! `wss()` selects a process to be scanned every given number of ticks. Each scan counts the
+//! pages whose accessed and dirty bits the hardware has set since the previous scan, and clears
+//! the bits again, so the pages accessed between two scans estimate the working set of the
+//! process. A process is scanned by itself, on a timer interrupt while it runs in user space, so
+//! a process that does not run is not scanned, as its working set does not change either.
+//!
+//! The accessed bits also guide the page replacement of `UserMemory::reclaim`, which frees the
+//! lazily freed pages not accessed since the last scan first.
+
+use zerocopy::AsBytes;
+
+use crate::{proc::KernelCtx, syscall::SyscallTable};
+
+/// Working-set estimate of a process, read by `wss()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct WssInfo {
+    /// Ticks between scans, or 0 if the process is not scanned
+    pub interval: usize,
+
+    /// Number of scans so far
+    pub scans: usize,
+
+    /// Number of pages accessed between the last two scans, or before the first scan: the
+    /// working-set size
+    pub accessed: usize,
+
+    /// Number of pages written between the last two scans, or before the first scan
+    pub dirty: usize,
+}
+
+/// The working-set scans of a process.
+#[derive(Copy, Clone)]
+pub struct WorkingSet {
+    info: WssInfo,
+
+    /// Ticks at the last scan
+    last: u32,
+}
+
+impl WorkingSet {
+    pub const fn new() -> Self {
+        Self {
+            info: WssInfo {
+                interval: 0,
+                scans: 0,
+                accessed: 0,
+                dirty: 0,
+            },
+            last: 0,
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Scans the memory of the current process if it is selected and its interval has passed
+    /// since its last scan.
+    pub fn scan_working_set(&mut self) {
+        let ws = self.proc().deref_data().wss;
+        if ws.info.interval == 0 {
+            return;
+        }
+        let now = *self.kernel().ticks().lock();
+        if (now.wrapping_sub(ws.last) as usize) < ws.info.interval {
+            return;
+        }
+        let (accessed, dirty) = self.proc_mut().memory_mut().scan_accessed();
+        let ws = &mut self.proc_mut().deref_mut_data().wss;
+        ws.last = now;
+        ws.info.scans += 1;
+        ws.info.accessed = accessed;
+        ws.info.dirty = dirty;
+    }
+
+    /// Scan the process pid, or the current process if pid is 0, every interval ticks, or stop
+    /// scanning it if interval is 0, and leave it as it is if interval is -1. Restarts the
+    /// estimate unless interval is -1. Then, place the estimate into struct wssinfo at addr, if
+    /// addr is not 0.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_wss(&mut self) -> Result<usize, ()> {
+        let pid = match self.proc().argint(0)? {
+            0 => self.proc().pid(),
+            pid => pid,
+        };
+        let interval = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?;
+        if interval < -1 {
+            return Err(());
+        }
+        let now = *self.kernel().ticks().lock();
+        let info = self.kernel().procs().inspect_mut(pid, self, |data| {
+            if interval >= 0 {
+                data.wss = WorkingSet::new();
+                data.wss.info.interval = interval as usize;
+                data.wss.last = now;
+            }
+            data.wss.info
+        })?;
+        if addr != 0 {
+            self.proc_mut().memory_mut().copy_out(addr.into(), &info)?;
+        }
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of working-set estimation.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(114, |ctx| ctx.sys_wss());
+}
@@ -0,0 +1,316 @@
+//! Compressed swap.
+//!
+//! When memory runs short and no lazily freed page is left to free, `UserMemory::reclaim` swaps
+//! out the private pages of the process that it has not accessed recently into the compressed
+//! swap: a pool of at most `NZRAM` pages in memory, each of which holds the compressed copies of
+//! several pages in slots of `ZSLOT` bytes. A page is compressed by a simple LZ77 scheme, and it
+//! stays in memory if it does not compress to half its size. A swapped-out page is decompressed
+//! back into a new page when the process accesses it again.
+//!
+//! The pool needs no memory of its own to grow: when no pool page has room for a compressed copy,
+//! the page being swapped out becomes a new pool page itself. There is no swap device to spill
+//! to, so a page stays in memory once the pool is full, which `ZramInfo::full` counts.
+
+use core::cmp;
+
+use array_macro::array;
+use zerocopy::AsBytes;
+
+use crate::{
+    addr::PGSIZE, hal::hal, lock::SpinLock, page::Page, param::NZRAM, proc::KernelCtx,
+    syscall::SyscallTable,
+};
+
+/// Size of a slot of a pool page.
+const ZSLOT: usize = 256;
+
+/// Number of slots of a pool page.
+const ZSLOTS: usize = PGSIZE / ZSLOT;
+
+/// Size of the header of a compressed copy: its length in bytes.
+const ZHEADER: usize = 2;
+
+/// Shortest match that the compression encodes.
+const MIN_MATCH: usize = 3;
+
+/// Longest match that the compression encodes.
+const MAX_MATCH: usize = 0x7f + MIN_MATCH;
+
+/// Largest number of literal bytes that a token of the compression carries.
+const MAX_LITERALS: usize = 0x80;
+
+/// Number of entries of the hash table of the compression.
+const NHASH: usize = 1024;
+
+/// Statistics of the compressed swap, read by `zram()`.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct ZramInfo {
+    /// Number of pages swapped out now
+    pub stored: usize,
+
+    /// Number of pages that the pool takes
+    pub pool_pages: usize,
+
+    /// Number of bytes of the compressed copies of the swapped-out pages
+    pub compressed: usize,
+
+    /// Number of pages swapped out so far
+    pub swapouts: usize,
+
+    /// Number of pages swapped in so far
+    pub swapins: usize,
+
+    /// Number of pages that stayed in memory since they did not compress to half their size
+    pub rejected: usize,
+
+    /// Number of pages that stayed in memory since the pool was full
+    pub full: usize,
+}
+
+struct ZramInner {
+    /// The pool pages.
+    pages: [Option<Page>; NZRAM],
+
+    /// Bit i of `used[p]` is set if the slot i of the pool page p is in use.
+    used: [u16; NZRAM],
+
+    /// The compressed copy of the page being swapped out, which must fit in half a page with its
+    /// header.
+    buf: [u8; PGSIZE / 2 - ZHEADER],
+
+    /// Position of the last occurrence of each hash of 3 bytes, for the compression.
+    table: [u16; NHASH],
+
+    info: ZramInfo,
+}
+
+pub struct Zram {
+    inner: SpinLock<ZramInner>,
+}
+
+/// Returns the hash of the first 3 bytes of `s`.
+fn hash3(s: &[u8]) -> usize {
+    let v = (s[0] as u32) << 16 | (s[1] as u32) << 8 | s[2] as u32;
+    (v.wrapping_mul(2654435761) >> 22) as usize % NHASH
+}
+
+/// Appends `lits` to `dst` at `out` as literal tokens. Returns the new end of `dst`, or `None`
+/// if `dst` is too short.
+fn emit_literals(lits: &[u8], dst: &mut [u8], mut out: usize) -> Option<usize> {
+    for chunk in lits.chunks(MAX_LITERALS) {
+        let end = out + 1 + chunk.len();
+        let d = dst.get_mut(out..end)?;
+        d[0] = (chunk.len() - 1) as u8;
+        d[1..].copy_from_slice(chunk);
+        out = end;
+    }
+    Some(out)
+}
+
+/// Compresses `src` into `dst`. Returns the length of the compressed data, or `None` if it does
+/// not fit in `dst`.
+///
+/// The compressed data is a sequence of tokens. A token byte t below 0x80 is followed by t + 1
+/// literal bytes. A token byte t from 0x80 is a match of (t & 0x7f) + `MIN_MATCH` bytes, copied
+/// from the distance given by the two bytes that follow, in little endian.
+fn compress(src: &[u8], dst: &mut [u8], table: &mut [u16; NHASH]) -> Option<usize> {
+    table.iter_mut().for_each(|e| *e = u16::MAX);
+    let mut out = 0;
+    let mut lit = 0;
+    let mut i = 0;
+    while i + MIN_MATCH <= src.len() {
+        let h = hash3(&src[i..]);
+        let candidate = table[h] as usize;
+        table[h] = i as u16;
+        if candidate == u16::MAX as usize
+            || src[candidate..candidate + MIN_MATCH] != src[i..i + MIN_MATCH]
+        {
+            i += 1;
+            continue;
+        }
+        let max = cmp::min(MAX_MATCH, src.len() - i);
+        let mut len = MIN_MATCH;
+        while len < max && src[candidate + len] == src[i + len] {
+            len += 1;
+        }
+        out = emit_literals(&src[lit..i], dst, out)?;
+        let d = dst.get_mut(out..out + 3)?;
+        d[0] = 0x80 | (len - MIN_MATCH) as u8;
+        d[1..].copy_from_slice(&((i - candidate) as u16).to_le_bytes());
+        out += 3;
+        i += len;
+        lit = i;
+    }
+    emit_literals(&src[lit..], dst, out)
+}
+
+/// Decompresses `src`, compressed by `compress`, into `dst`, which it must fill exactly.
+fn decompress(src: &[u8], dst: &mut [u8]) {
+    let mut i = 0;
+    let mut out = 0;
+    while i < src.len() {
+        let t = src[i] as usize;
+        i += 1;
+        if t < 0x80 {
+            let n = t + 1;
+            dst[out..out + n].copy_from_slice(&src[i..i + n]);
+            i += n;
+            out += n;
+        } else {
+            let distance = u16::from_le_bytes([src[i], src[i + 1]]) as usize;
+            i += 2;
+            // A match may overlap the bytes it produces, so it is copied byte by byte.
+            for _ in 0..(t & 0x7f) + MIN_MATCH {
+                dst[out] = dst[out - distance];
+                out += 1;
+            }
+        }
+    }
+    assert_eq!(out, dst.len(), "decompress");
+}
+
+/// Returns the mask of the slots of a compressed copy of `len` bytes that starts at slot `s`.
+fn slot_mask(s: usize, len: usize) -> u16 {
+    let n = (ZHEADER + len + ZSLOT - 1) / ZSLOT;
+    (((1u32 << n) - 1) << s) as u16
+}
+
+impl ZramInner {
+    /// Returns the pool page, and the range of its bytes that the compressed copy in `slot`
+    /// takes, after the header.
+    fn get(&self, slot: usize) -> (&Page, usize, usize) {
+        let (p, s) = (slot / ZSLOTS, slot % ZSLOTS);
+        let page = self.pages[p].as_ref().expect("Zram::get");
+        let start = s * ZSLOT;
+        let len = u16::from_le_bytes([page[start], page[start + 1]]) as usize;
+        (page, start + ZHEADER, len)
+    }
+
+    /// Frees `slot`, and the pool page as well if none of its slots is in use anymore.
+    fn free(&mut self, slot: usize) {
+        let (_, _, len) = self.get(slot);
+        let p = slot / ZSLOTS;
+        self.used[p] &= !slot_mask(slot % ZSLOTS, len);
+        self.info.stored -= 1;
+        self.info.compressed -= len;
+        if self.used[p] == 0 {
+            let page = self.pages[p].take().expect("Zram::free");
+            hal().kmem().free(page);
+            self.info.pool_pages -= 1;
+        }
+    }
+}
+
+impl Zram {
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new(
+                "zram",
+                ZramInner {
+                    pages: array![_ => None; NZRAM],
+                    used: [0; NZRAM],
+                    buf: [0; PGSIZE / 2 - ZHEADER],
+                    table: [0; NHASH],
+                    info: ZramInfo {
+                        stored: 0,
+                        pool_pages: 0,
+                        compressed: 0,
+                        swapouts: 0,
+                        swapins: 0,
+                        rejected: 0,
+                        full: 0,
+                    },
+                },
+            ),
+        }
+    }
+
+    /// Stores a compressed copy of `page`, which no TLB may have an entry of anymore, and frees
+    /// it or makes it a pool page. Returns Ok(slot) on success, or Err(page) if `page` does not
+    /// compress to half its size or the pool is full.
+    pub fn store(&self, page: Page) -> Result<usize, Page> {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        let len = match compress(&page[..], &mut inner.buf[..], &mut inner.table) {
+            Some(len) => len,
+            None => {
+                inner.info.rejected += 1;
+                return Err(page);
+            }
+        };
+
+        // Take the first free slots large enough in a pool page, or else make the page a pool
+        // page itself.
+        let n = slot_mask(0, len).count_ones() as usize;
+        let found = (0..NZRAM)
+            .filter(|p| inner.pages[*p].is_some())
+            .find_map(|p| {
+                (0..=ZSLOTS - n)
+                    .find(|s| inner.used[p] & slot_mask(*s, len) == 0)
+                    .map(|s| (p, s))
+            });
+        let (p, s) = match found {
+            Some(found) => {
+                hal().kmem().free(page);
+                found
+            }
+            None => {
+                match inner.pages.iter().position(Option::is_none) {
+                    Some(p) => {
+                        inner.pages[p] = Some(page);
+                        inner.info.pool_pages += 1;
+                        (p, 0)
+                    }
+                    None => {
+                        inner.info.full += 1;
+                        return Err(page);
+                    }
+                }
+            }
+        };
+        inner.used[p] |= slot_mask(s, len);
+        let dst = &mut inner.pages[p].as_mut().expect("Zram::store")[s * ZSLOT..];
+        dst[..ZHEADER].copy_from_slice(&(len as u16).to_le_bytes());
+        dst[ZHEADER..ZHEADER + len].copy_from_slice(&inner.buf[..len]);
+        inner.info.stored += 1;
+        inner.info.compressed += len;
+        inner.info.swapouts += 1;
+        Ok(p * ZSLOTS + s)
+    }
+
+    /// Decompresses the page in `slot` into `page`, and frees the slot.
+    pub fn load(&self, slot: usize, page: &mut Page) {
+        let mut inner = self.inner.lock();
+        let (src, start, len) = inner.get(slot);
+        decompress(&src[start..start + len], &mut page[..]);
+        inner.free(slot);
+        inner.info.swapins += 1;
+    }
+
+    /// Frees `slot`, dropping the page in it.
+    pub fn free(&self, slot: usize) {
+        self.inner.lock().free(slot);
+    }
+
+    /// Returns the statistics of the compressed swap.
+    pub fn info(&self) -> ZramInfo {
+        self.inner.lock().info
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Place the statistics of the compressed swap into struct zraminfo at addr.
+    /// Returns Ok(0) on success, Err(()) on error.
+    pub fn sys_zram(&mut self) -> Result<usize, ()> {
+        let addr = self.proc().argaddr(0)?;
+        let info = hal().zram().info();
+        self.proc_mut().memory_mut().copy_out(addr.into(), &info)?;
+        Ok(0)
+    }
+}
+
+/// Registers the system calls of the compressed swap.
+pub fn register_syscalls(table: &mut SyscallTable) {
+    table.register(116, |ctx| ctx.sys_zram());
+}
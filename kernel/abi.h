@@ -0,0 +1,3 @@
+// System call ABIs, set by setabi() for the next exec().
+#define ABI_RV6   0  // rv6 system calls
+#define ABI_LINUX 1  // RISC-V Linux system calls, for simple statically linked programs
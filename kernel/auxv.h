@@ -0,0 +1,11 @@
+// Types of the auxiliary vector entries, which exec() passes to
+// rv6 programs as the third argument of _start(argc, argv, auxv).
+// getauxval() looks them up.
+#define AT_NULL   0   // end of the vector
+#define AT_PHDR   3   // address of the program headers
+#define AT_PHENT  4   // size of a program header
+#define AT_PHNUM  5   // number of program headers
+#define AT_PAGESZ 6   // page size
+#define AT_BASE   7   // address of the program interpreter
+#define AT_ENTRY  9   // entry point of the program
+#define AT_RANDOM 25  // address of 16 random bytes
@@ -0,0 +1,6 @@
+// A system call in a batch run by batch().
+struct syscallent {
+  uint64 num;      // System call number
+  uint64 args[6];  // Arguments of the system call
+  uint64 ret;      // Result of the system call, filled by batch(); -1 on error
+};
@@ -0,0 +1,6 @@
+// Load of a CPU over its current and previous load windows.
+struct cpuload {
+  uint64 nrunnable;      // Number of runnable processes found by the last scan of the scheduler
+  uint64 busy_cycles;    // Cycles spent running processes
+  uint64 window_cycles;  // Cycles the windows have lasted, or 0 if the CPU has not started
+};
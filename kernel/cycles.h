@@ -0,0 +1,9 @@
+// Flags of cycles().
+#define CYCLES_SERIALIZE 1  // Complete the earlier instructions before reading
+#define CYCLES_VIRTUAL   2  // Read the counters of the calling process, not of its CPU
+
+// Counters read by cycles().
+struct cycles {
+  uint64 cycle;    // Cycles
+  uint64 instret;  // Instructions retired
+};
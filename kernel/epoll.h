@@ -0,0 +1,15 @@
+// Operations of epoll_ctl().
+#define EPOLL_CTL_ADD 1  // add a file descriptor to the interest list
+#define EPOLL_CTL_DEL 2  // remove a file descriptor from the interest list
+#define EPOLL_CTL_MOD 3  // change the events and data of a file descriptor
+
+// Events of struct epoll_event.
+#define EPOLLIN  0x1         // the file can be read without blocking
+#define EPOLLOUT 0x4         // the file can be written without blocking
+#define EPOLLET  (1U << 31)  // report the file only when an event is posted to it
+
+// An event to watch, given to epoll_ctl(), or a ready file, returned by epoll_wait().
+struct epoll_event {
+  uint events;  // EPOLLIN, EPOLLOUT, and EPOLLET
+  uint64 data;  // Returned as is by epoll_wait()
+};
@@ -0,0 +1,12 @@
+// Flags of eventfd().
+#define EFD_SEMAPHORE 1  // a read takes 1 from the counter, rather than all of it
+
+// Clocks of timerfd_create(). Both measure a timer from when it is set.
+#define CLOCK_REALTIME  0
+#define CLOCK_MONOTONIC 1
+
+// Setting of the timer of a timerfd.
+struct itimerspec {
+  struct timespec it_interval;  // Interval of a periodic timer, or 0 for a one-shot timer
+  struct timespec it_value;     // Time until the next expiration, or 0 for a disarmed timer
+};
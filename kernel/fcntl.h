@@ -3,3 +3,17 @@
 #define O_RDWR    0x002
 #define O_CREATE  0x200
 #define O_TRUNC   0x400
+#define O_TMPFILE 0x800
+#define O_CLOEXEC 0x1000
+
+#define AT_FDCWD  -100
+
+#define F_OK      0x0
+#define X_OK      0x1
+#define W_OK      0x2
+#define R_OK      0x4
+
+#define F_GETFD   1
+#define F_SETFD   2
+
+#define FD_CLOEXEC 1
@@ -0,0 +1,26 @@
+// Types of open files.
+#define FD_PIPE    1
+#define FD_INODE   2
+#define FD_DEVICE  3
+#define FD_WATCH   4
+#define FD_SOCKET  6
+#define FD_EPOLL   7
+#define FD_EVENTFD 8
+#define FD_TIMERFD 9
+#define FD_MQUEUE  10
+#define FD_SEM     11
+
+// Flags of a file descriptor.
+#define FDINFO_READ    0x1
+#define FDINFO_WRITE   0x2
+#define FDINFO_CLOEXEC 0x4
+
+// A file descriptor of a process.
+struct fdinfo {
+  int fd;
+  uint type;   // Type of the open file
+  uint flags;  // FDINFO_READ, FDINFO_WRITE, and FDINFO_CLOEXEC
+  uint dev;    // Device of an inode or a device file
+  uint ino;    // Inode number of an inode or a device file, a number shared by the two ends of a pipe,
+               // the port of an AF_INET socket, or the inode number of the name of an AF_UNIX socket
+};
@@ -38,3 +38,10 @@ struct devsw {
 extern struct devsw devsw[];
 
 #define CONSOLE 1
+#define TTY     2
+#define NULLDEV 3
+#define ZERODEV 4
+#define FULLDEV 5
+#define VCONSOLE 6  // virtual console 1, followed by the others
+#define TRACEDEV 9
+#define VPORT   10  // virtio console port 0, followed by the others
@@ -7,7 +7,15 @@
 
 // Disk layout:
 // [ boot block | super block | log | inode blocks |
-//                                          free bit map | data blocks]
+//                      free bit map | snapshot inode blocks |
+//                                   snapshot bit map | data blocks]
+//
+// or, with FS_GROUPS:
+// [ boot block | super block | log | snapshot inode blocks |
+//                      snapshot bit map | group 0 | group 1 | ... ]
+// where each group is [ inode blocks | free bit map block | data blocks ].
+// A group's bit map covers the group's blocks only. The last group may
+// be shorter than the others.
 //
 // mkfs computes the super block and builds an initial file system. The
 // super block describes the disk layout:
@@ -20,10 +28,30 @@ struct superblock {
   uint logstart;     // Block number of first log block
   uint inodestart;   // Block number of first inode block
   uint bmapstart;    // Block number of first free map block
+  uint snapinodestart; // Block number of first snapshot inode block
+  uint snapbmapstart;  // Block number of first snapshot map block
+  uint incompat;     // Features without which the file system cannot be mounted (FS_*)
+  uint groupstart;   // Block number of first block group (FS_GROUPS only)
+  uint ngroups;      // Number of block groups (FS_GROUPS only)
+  uint groupsize;    // Number of blocks in a group (FS_GROUPS only)
+  uint ipg;          // Inodes per group, a multiple of IPB (FS_GROUPS only)
+  uint state;        // FS_CLEAN if unmounted cleanly, 0 if the log may need recovery
+  uint compat;       // Features that can be ignored (FS_*)
+  uint ro_compat;    // Features without which the file system can only be read (FS_*)
+  uint snapshot;     // SNAP_VALID if the snapshot blocks hold a complete snapshot, 0 otherwise
 };
 
 #define FSMAGIC 0x10203040
 
+// Incompatible features
+#define FS_LONGNAMES 0x1  // Directories use the long name format
+#define FS_DIRTYPES  0x2  // Directory entries record the type of their file
+#define FS_GROUPS    0x4  // The disk is divided into block groups
+#define FS_GENERATIONS 0x8  // Inodes have a generation number (required)
+
+#define FS_CLEAN 1  // superblock.state of a cleanly unmounted file system
+#define SNAP_VALID 1  // superblock.snapshot when the snapshot blocks hold a snapshot
+
 #define NDIRECT 12
 #define NINDIRECT (BSIZE / sizeof(uint))
 #define MAXFILE (NDIRECT + NINDIRECT)
@@ -36,6 +64,7 @@ struct dinode {
   short nlink;          // Number of links to inode in file system
   uint size;            // Size of file (bytes)
   uint addrs[NDIRECT+1];   // Data block addresses
+  uint gen;             // Incremented each time the inode is allocated
 };
 
 // Inodes per block.
@@ -50,11 +79,32 @@ struct dinode {
 // Block of free map containing bit for block b
 #define BBLOCK(b, sb) ((b)/BPB + sb.bmapstart)
 
-// Directory is a file containing a sequence of dirent structures.
+// First block of group g, with FS_GROUPS
+#define GROUPSTART(g, sb) ((sb).groupstart + (g) * (sb).groupsize)
+
+// Block containing inode i, with FS_GROUPS
+#define GIBLOCK(i, sb) (GROUPSTART((i) / (sb).ipg, sb) + (i) % (sb).ipg / IPB)
+
+// Maximum length of a name in the legacy directory format,
+// which has fixed-size entries of a ushort inum and char name[DIRSIZ].
 #define DIRSIZ 14
 
+// Maximum length of a name in the long name directory format.
+#define MAXNAMELEN 255
+
+// Directory is a file containing a sequence of dirent structures.
+// Each dirent is followed by its name without NUL terminator, and padding
+// up to reclen bytes. Entries never cross a block boundary, and the last
+// entry in a block extends to the end of the block.
+// getdents() returns entries in this format for both directory formats.
 struct dirent {
   ushort inum;
-  char name[DIRSIZ];
+  ushort reclen;     // Length of the whole entry in bytes
+  uchar namelen;     // Length of the name in bytes
+  uchar type;        // Type of the file (T_*), or 0 if unknown
+  char name[];
 };
 
+// Smallest record length of an entry whose name is namelen bytes long.
+#define DIRENT_RECLEN(namelen) ((sizeof(struct dirent) + (namelen) + 3) & ~3)
+
@@ -0,0 +1,7 @@
+// Statistics of gang scheduling, read by gang().
+struct ganginfo {
+  uint64 active;   // Gang whose slot it is now, or 0 in the slot of the processes in no gang
+  uint64 gangs;    // Number of gangs
+  uint64 members;  // Number of members of the gang of the process, or 0 if it is in no gang
+  uint64 slots;    // Number of slots begun so far
+};
@@ -0,0 +1,23 @@
+// Operations of iosched().
+#define IOSCHED_SETPOLICY 1  // Set the policy of the disk queue
+#define IOSCHED_GETPOLICY 2  // Return the policy
+#define IOSCHED_STAT      3  // Read the struct iostat of a process, or of the caller if pid is 0
+#define IOSCHED_QSTAT     4  // Read the struct ioqstat of the disk
+
+// Policies of the disk queue.
+#define IOSCHED_NOOP     0  // In the order of arrival
+#define IOSCHED_ELEVATOR 1  // In the order of block numbers, sweeping up
+#define IOSCHED_FAIR     2  // Shared evenly between processes
+
+// Disk I/O of a process.
+struct iostat {
+  uint64 reads;   // Blocks read
+  uint64 writes;  // Blocks written
+  uint64 wait;    // Cycles spent waiting for the blocks
+};
+
+// Disk requests sent to the disk. Adjacent blocks are merged into a request.
+struct ioqstat {
+  uint64 requests;
+  uint64 blocks;
+};
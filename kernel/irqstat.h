@@ -0,0 +1,7 @@
+// Statistics of an IRQ that has fired.
+struct irqstat {
+  uint64 irq;
+  uint64 count[NCPU];   // Number of interrupts serviced by each CPU
+  uint64 cycles;        // Cycles spent in the handler
+  uint64 max_cycles;    // Cycles spent in the slowest run of the handler
+};
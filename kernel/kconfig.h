@@ -0,0 +1,15 @@
+// Boot configuration, stored on the disk by setconfig()
+// and applied at the next boot.
+struct kconfig {
+  uint sched_class;  // Scheduling class of the initial process (SCHED_*)
+  uint tick_us;      // Interval between timer interrupts, in microseconds
+  uint idle_policy;  // What idle CPUs do (IDLE_*)
+  uint oom_killer;   // Kill the process with the largest memory when memory runs out (0 or 1)
+  uint read_only;    // Mount the root file system read-write (0), read-only (1),
+                     // or read-only after replaying the log (2)
+  uint nfile;        // Size of the file table, or 0 for the default size
+  uint ninode;       // Size of the inode table, or 0 for the default size
+  uint nbuf;         // Size of the buffer cache, or 0 for the default size
+  uint nproc;        // Maximum number of processes, or 0 for no limit
+  uint log_color;    // Color the severity prefixes of kernel messages (0 or 1)
+};
@@ -0,0 +1,9 @@
+// Statistics of same-page merging, read by ksm().
+struct ksminfo {
+  uint64 pages_to_scan;  // Pages each process scans on a timer interrupt, or 0 if disabled
+  uint64 scanned;        // Number of writable pages scanned
+  uint64 merged;         // Number of pages replaced by a stable page
+  uint64 zero;           // Number of pages replaced by the zero page
+  uint64 stable;         // Number of stable pages
+  uint64 sharing;        // Mappings of the stable pages, which take stable pages of memory
+};
@@ -0,0 +1,23 @@
+// Contention statistics of a lock, read by lockstat().
+struct lockstat {
+  char name[16];        // Name of the lock, padded with zeros
+  uint64 acquired;      // Number of acquisitions
+  uint64 contended;     // Number of acquisitions that found the lock held
+  uint64 spun;          // Number of contended acquisitions that got the lock without sleeping
+  uint64 slept;         // Number of times a process slept waiting for the lock
+  uint64 wait_cycles;   // Cycles the contended acquisitions waited for the lock
+};
+
+// Implementations of spin locks, as spinbench() reports them.
+#define SPIN_RACE   0  // the waiting CPUs race for the lock
+#define SPIN_TICKET 1  // first come, first served, by tickets
+#define SPIN_MCS    2  // first come, first served, by MCS queues
+
+// Result of spinbench().
+struct spinbench {
+  uint64 kind;          // Implementation of spin locks
+  uint64 acquired;      // Number of times the process acquired the lock
+  uint64 wait_cycles;   // Cycles the process waited for the lock in total
+  uint64 max_wait;      // Cycles of its longest wait
+  uint64 elapsed;       // Microseconds the benchmark ran
+};
@@ -0,0 +1,23 @@
+// Usage of a kernel object table.
+struct tableusage {
+  uint64 used;      // Number of entries in use
+  uint64 capacity;  // Number of entries in the table
+};
+
+// Summary of the memory of the kernel.
+struct meminfo {
+  uint64 total_pages;       // Number of physical pages managed by the page allocator
+  uint64 free_pages;        // Number of free physical pages
+  uint64 bcache_pages;      // Number of pages taken by the buffers of the buffer cache
+  uint64 page_cache_pages;  // Number of pages allocated by the page cache
+  struct tableusage files;  // Open files
+  struct tableusage inodes; // In-memory inodes
+  struct tableusage bufs;   // Buffers of the buffer cache
+};
+
+// Resident set size and open files of a process.
+struct procmem {
+  int pid;
+  uint nfiles; // Number of open file descriptors
+  uint64 rss;  // Number of mapped pages, including the trampoline and the trap frame
+};
@@ -0,0 +1,3 @@
+// Advice of madvise().
+#define MADV_DONTNEED 4  // free the pages now; they read as zeros after that
+#define MADV_FREE     8  // free the pages when memory runs short, unless they are written before
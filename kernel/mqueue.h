@@ -0,0 +1,10 @@
+// Priorities of messages are less than MQ_PRIO_MAX.
+#define MQ_PRIO_MAX 32768
+
+// Attributes of a message queue, given to mq_open() or returned by mq_getattr().
+struct mq_attr {
+  long mq_flags;    // Always 0
+  long mq_maxmsg;   // Maximum number of messages, at most 16
+  long mq_msgsize;  // Maximum size of a message in bytes
+  long mq_curmsgs;  // Number of messages in the queue
+};
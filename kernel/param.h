@@ -1,9 +1,11 @@
-#define NPROC        64  // maximum number of processes
+#define NPROC        64  // maximum number of processes listed by the tools
 #define NCPU          8  // maximum number of CPUs
 #define NOFILE       40  // open files per process
 #define NFILE       100  // open files per system
 #define NINODE       50  // maximum number of active i-nodes
-#define NDEV         10  // maximum major device number
+#define NDEV         14  // maximum major device number
+#define NVCONSOLE     4  // number of virtual consoles, including the console
+#define NVPORT        4  // number of ports of the virtio console
 #define ROOTDEV       1  // device number of file system root disk
 #define MAXARG       32  // max exec arguments
 #define MAXOPBLOCKS  10  // max # of blocks any FS op writes
@@ -11,3 +13,10 @@
 #define NBUF         (MAXOPBLOCKS*3)  // size of disk block cache
 #define FSSIZE       5000  // size of file system in blocks
 #define MAXPATH      128   // maximum file path name
+#define MAXPROCTITLE 64    // maximum length of a process title
+#define MAXPROCARGS  128   // bytes of exec arguments kept for each process
+#define NTRIGGER       4   // max hardware breakpoints and watchpoints per process
+#define NPERFCTR       4   // hardware performance counters programmed by perf()
+#define NLATBUCKET    32   // buckets of a syscall latency histogram, by log2 of cycles
+#define NSYSCALL     256   // size of the system call tables
+#define PIPE_BUF     512   // writes to a pipe of at most this many bytes are atomic
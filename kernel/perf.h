@@ -0,0 +1,13 @@
+// Operations of perf(), each on an array of NPERFCTR uint64.
+#define PERF_SETEVENTS 1  // Set the events the counters of every CPU count
+#define PERF_GETEVENTS 2  // Read the events
+#define PERF_READ      3  // Read the counts of a process, or of the caller if pid is 0
+
+// Operations of perf() on the syscall latency histograms.
+#define PERF_SETLATENCY 4  // Start collecting them from zero, or stop if the argument is 0
+#define PERF_GETLATENCY 5  // Read the histogram of a syscall into an array of NLATBUCKET uint64
+
+// Events of qemu's sscofpmf extension.
+#define PERF_EV_DTLB_READ_MISS  0x10019
+#define PERF_EV_DTLB_WRITE_MISS 0x1001b
+#define PERF_EV_ITLB_MISS       0x10021
@@ -0,0 +1,20 @@
+// Permissions of a memory region.
+#define PMAP_R 0x1  // readable
+#define PMAP_W 0x2  // writable
+#define PMAP_X 0x4  // executable
+#define PMAP_U 0x8  // user-accessible
+
+// A run of consecutive pages mapped with the same permissions.
+struct pmapregion {
+  uint64 start;  // First virtual address of the region
+  uint64 end;    // One beyond the last virtual address of the region
+  uint64 perm;   // PMAP_* bits
+};
+
+// Summary of the memory mappings of a process.
+struct pmapinfo {
+  uint64 size;        // Size of process memory (bytes)
+  uint64 rss;         // Number of mapped pages, including the trampoline and the trap frame
+  uint64 ptpages[3];  // Number of page-table pages of each level, where level 2 is the root
+  uint64 nregion;     // Number of regions, which may be more than the regions stored
+};
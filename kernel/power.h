@@ -0,0 +1,15 @@
+// Operations of powerctl().
+#define POWER_GETIDLE 0  // return the idle policy
+#define POWER_SETIDLE 1  // set the idle policy to arg
+#define POWER_SETPERF 2  // set the performance state of every CPU to arg, where 0 is the fastest
+#define POWER_STAT    3  // copy struct powerstat to arg
+
+// Idle policies.
+#define IDLE_POLL    0  // an idle CPU keeps looking for a process to run
+#define IDLE_SUSPEND 1  // an idle CPU suspends itself until its next interrupt
+
+struct powerstat {
+  uint64 policy;          // Idle policy
+  uint64 nsuspend;        // Number of times CPUs have suspended themselves
+  uint64 suspend_cycles;  // Cycles spent while suspended
+};
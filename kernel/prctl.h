@@ -0,0 +1,4 @@
+// Operations of prctl().
+#define PR_SET_NAME 15    // Set the title of the calling process
+#define PR_GET_NAME 16    // Read the title of a process
+#define PR_GET_ARGS 1000  // Read the exec arguments of a process, each terminated by NUL
@@ -0,0 +1,37 @@
+// ptrace() requests.
+#define PTRACE_TRACEME  0  // become traced by the parent, and stop
+#define PTRACE_WAIT     1  // wait for the child to stop, and return why
+#define PTRACE_CONT     2  // resume the stopped child
+#define PTRACE_SYSCALL  3  // resume, and stop at the next syscall entry or exit
+#define PTRACE_PEEKDATA 4  // read the word at addr of the child into *data
+#define PTRACE_POKEDATA 5  // write data to the word at addr of the child
+#define PTRACE_GETREGS  6  // read the registers of the child into *data
+#define PTRACE_SETREGS  7  // write *data to the registers of the child
+#define PTRACE_SETTRIGGER 8  // set or clear the hardware trigger *data of the child
+
+// Why a traced child is stopped, returned by PTRACE_WAIT.
+#define PTRACE_EV_START    1  // it has just become traced
+#define PTRACE_EV_SYSENTER 2  // it is about to run a system call
+#define PTRACE_EV_SYSEXIT  3  // it has run a system call
+#define PTRACE_EV_BREAK    4  // it has hit a breakpoint or a hardware trigger
+
+// Registers of a traced child.
+struct ptraceregs {
+  uint64 pc;       // User program counter
+  uint64 sp;       // User stack pointer
+  uint64 args[8];  // Argument registers; args[7] holds the system call number
+};
+
+// Kinds of accesses a hardware trigger matches, which can be combined.
+#define TRIGGER_EXEC  1
+#define TRIGGER_LOAD  2
+#define TRIGGER_STORE 4
+
+// A hardware breakpoint or watchpoint of a traced child. The child stops
+// before the matching access, so the tracer must clear or move the trigger
+// before resuming it. PTRACE_SETTRIGGER fails if the CPU lacks the trigger.
+struct ptracetrigger {
+  uint64 addr;   // User address to match
+  uint index;    // Which trigger, less than NTRIGGER
+  uint type;     // TRIGGER_* bits, or 0 to clear the trigger
+};
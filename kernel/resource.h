@@ -0,0 +1,10 @@
+// Resources of getrlimit() and setrlimit().
+#define RLIMIT_MSGQUEUE 12  // bytes of messages the message queues may hold
+
+#define RLIM_INFINITY (~0ULL)
+
+// A limit on a resource.
+struct rlimit {
+  uint64 rlim_cur;  // The soft limit, which the kernel enforces
+  uint64 rlim_max;  // The hard limit, the ceiling of the soft limit
+};
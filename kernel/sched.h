@@ -0,0 +1,15 @@
+// Scheduling classes. A process runs only when
+// no process of a higher class is runnable.
+#define SCHED_INTERACTIVE 0  // the default class
+#define SCHED_BATCH       1  // work that does not need to respond quickly
+#define SCHED_IDLE        2  // work that runs only when the CPU would be idle otherwise
+#define SCHED_DEADLINE    3  // periodic work with a reservation of setdeadline()
+
+// Reservation of a process of the deadline class, read by dlstat(). In microseconds.
+struct dlinfo {
+  uint64 period;    // Length of each period
+  uint64 budget;    // Time the process may run in each period
+  uint64 deadline;  // Time after the beginning of each period by which it must have run
+  uint64 used;      // Time the process has run in the current period
+  uint64 misses;    // Number of periods at whose deadline it was still runnable short of its budget
+};
@@ -0,0 +1,7 @@
+// Context switch counters, summed over all CPUs.
+struct schedstat {
+  uint64 nswitch;        // Number of switches from a process to the scheduler
+  uint64 switch_cycles;  // Cycles spent switching between processes and the scheduler
+  uint64 nmigrate;       // Number of runs of a process on another CPU than its last one
+  uint64 ninherit;       // Number of times a waiter for a sleep lock raised the holder's priority
+};
@@ -0,0 +1,2 @@
+// The largest value of a semaphore.
+#define SEM_VALUE_MAX 0x7fffffff
@@ -0,0 +1,45 @@
+// Domains of socket().
+#define AF_UNIX 1  // named by socket files, see socketpair()
+#define AF_INET 2
+
+// Types of socket().
+#define SOCK_STREAM 1  // reliable byte stream
+#define SOCK_DGRAM  2  // datagrams, dropped if an AF_INET receiver cannot take them
+
+// Protocols of socket(). 0 chooses the protocol of the type.
+#define IPPROTO_IP  0
+#define IPPROTO_TCP 6   // SOCK_STREAM
+#define IPPROTO_UDP 17  // SOCK_DGRAM
+
+// Addresses, in host byte order.
+#define INADDR_ANY      0x00000000  // every address of the machine
+#define INADDR_LOOPBACK 0x7f000001  // 127.0.0.1, owned by the loopback interface
+
+// Options of setsockopt(), which are accepted and ignored.
+#define SOL_SOCKET   1
+#define SO_REUSEADDR 2
+#define SO_SNDBUF    7
+#define SO_RCVBUF    8
+
+struct sockaddr {
+  uint16 sa_family;
+  char sa_data[14];
+};
+
+struct in_addr {
+  uint s_addr;
+};
+
+// Address of a socket. The port and address are in network byte order.
+struct sockaddr_in {
+  uint16 sin_family;  // AF_INET
+  uint16 sin_port;
+  struct in_addr sin_addr;
+  char sin_zero[8];
+};
+
+// Address of a socket of AF_UNIX: the path of its socket file.
+struct sockaddr_un {
+  uint16 sun_family;  // AF_UNIX
+  char sun_path[108];
+};
@@ -1,11 +1,13 @@
 #define T_DIR     1   // Directory
 #define T_FILE    2   // File
 #define T_DEVICE  3   // Device
+#define T_SOCK    4   // UNIX domain socket
 
 struct stat {
   int dev;     // File system's disk device
   uint ino;    // Inode number
   short type;  // Type of file
   short nlink; // Number of links to file
+  uint gen;    // Generation of the inode number
   uint64 size; // Size of file in bytes
 };
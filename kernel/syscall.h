@@ -28,3 +28,94 @@
 #define SYS_lseek 27
 #define SYS_clock 28
 #define SYS_uptime_as_micro 29
+#define SYS_defrag 30
+#define SYS_snapshot 31
+#define SYS_snapdrop 32
+#define SYS_snapread 33
+#define SYS_snapstat 34
+#define SYS_getdents 35
+#define SYS_watchopen 36
+#define SYS_watchadd 37
+#define SYS_watchrm 38
+#define SYS_linkat 39
+#define SYS_sendfile 40
+#define SYS_pmap 41
+#define SYS_ptrace 42
+#define SYS_batch 43
+#define SYS_yield 46
+#define SYS_gettid 47
+#define SYS_schedstat 48
+#define SYS_freeze 49
+#define SYS_thaw 50
+#define SYS_setsched 51
+#define SYS_getsched 52
+#define SYS_irqstat 53
+#define SYS_settick 54
+#define SYS_gettick 55
+#define SYS_powerctl 56
+#define SYS_reboot 57
+#define SYS_getconfig 58
+#define SYS_setconfig 59
+#define SYS_setabi 60
+#define SYS_openat 61
+#define SYS_mkdirat 62
+#define SYS_unlinkat 63
+#define SYS_stat 64
+#define SYS_lstat 65
+#define SYS_access 66
+#define SYS_ftruncate 67
+#define SYS_truncate 68
+#define SYS_fcntl 69
+#define SYS_umask 70
+#define SYS_meminfo 71
+#define SYS_getcwd 72
+#define SYS_fdinfo 73
+#define SYS_cpuload 74
+#define SYS_throttle 75
+#define SYS_nanosleep 76
+#define SYS_gettimeofday 77
+#define SYS_settimeofday 78
+#define SYS_prctl 79
+#define SYS_cycles 80
+#define SYS_perf 81
+#define SYS_iosched 82
+#define SYS_socket 83
+#define SYS_bind 84
+#define SYS_listen 85
+#define SYS_accept 86
+#define SYS_connect 87
+#define SYS_sendto 88
+#define SYS_recvfrom 89
+#define SYS_getsockname 90
+#define SYS_socketpair 91
+#define SYS_epoll_create 92
+#define SYS_epoll_ctl 93
+#define SYS_epoll_wait 94
+#define SYS_eventfd 95
+#define SYS_timerfd_create 96
+#define SYS_timerfd_settime 97
+#define SYS_timerfd_gettime 98
+#define SYS_getrlimit 99
+#define SYS_setrlimit 100
+#define SYS_mq_open 101
+#define SYS_mq_unlink 102
+#define SYS_mq_send 103
+#define SYS_mq_receive 104
+#define SYS_mq_getattr 105
+#define SYS_sem_open 106
+#define SYS_sem_unlink 107
+#define SYS_sem_wait 108
+#define SYS_sem_trywait 109
+#define SYS_sem_post 110
+#define SYS_sem_getvalue 111
+#define SYS_brk    112
+#define SYS_madvise 113
+#define SYS_wss 114
+#define SYS_ksm 115
+#define SYS_zram 116
+#define SYS_migrate 117
+#define SYS_gang 118
+#define SYS_setdeadline 119
+#define SYS_dlstat 120
+#define SYS_lockstat 121
+#define SYS_spinbench 122
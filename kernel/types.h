@@ -1,4 +1,5 @@
 #include <bits/types/struct_timeval.h>
+#include <bits/types/struct_timespec.h>
 #include <bits/types.h>
 #include <bits/types/sigset_t.h>
 
@@ -20,6 +21,7 @@ typedef long int off_t;
 typedef long unsigned int size_t;
 typedef signed long int ssize_t;
 typedef unsigned long u_long;
+typedef unsigned short u_short;
 
 # define SEEK_SET	0	/* Seek from beginning of file.  */
 # define SEEK_CUR	1	/* Seek from current position.  */
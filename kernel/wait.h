@@ -0,0 +1,6 @@
+// Options of waitpid().
+#define WUNTRACED 0x2  // also report a child that has stopped
+
+// Status reported by waitpid() for a stopped child.
+#define WSTOPPED      0x137f
+#define WIFSTOPPED(s) ((s) == WSTOPPED)
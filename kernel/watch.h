@@ -0,0 +1,12 @@
+// Kinds of events reported by watches.
+#define WATCH_CREATE   0x1  // a name was linked into the watched directory
+#define WATCH_DELETE   0x2  // a name was removed from the watched directory
+#define WATCH_MODIFY   0x4  // the watched file was written
+#define WATCH_OVERFLOW 0x8  // events were dropped because the queue was full
+
+// Event read from a watch's file descriptor.
+struct watchevent {
+  int wd;     // Watch descriptor of the watched file, or -1 for WATCH_OVERFLOW
+  uint mask;  // Kind of the event
+  uint inum;  // Inode number of the file the event is about
+};
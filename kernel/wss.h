@@ -0,0 +1,7 @@
+// Working-set estimate of a process, read by wss().
+struct wssinfo {
+  uint64 interval;  // Ticks between scans, or 0 if the process is not scanned
+  uint64 scans;     // Number of scans so far
+  uint64 accessed;  // Pages accessed between the last two scans, or before the first scan
+  uint64 dirty;     // Pages written between the last two scans, or before the first scan
+};
@@ -0,0 +1,10 @@
+// Statistics of the compressed swap, read by zram().
+struct zraminfo {
+  uint64 stored;      // Number of pages swapped out now
+  uint64 pool_pages;  // Number of pages that the pool takes
+  uint64 compressed;  // Number of bytes of the compressed copies of the swapped-out pages
+  uint64 swapouts;    // Number of pages swapped out so far
+  uint64 swapins;     // Number of pages swapped in so far
+  uint64 rejected;    // Pages that stayed in memory since they did not compress to half a page
+  uint64 full;        // Pages that stayed in memory since the pool was full
+};
@@ -100,9 +100,10 @@ typedef int64 off64_t;
 #include	"stats.h"
 #include	"timing.h"
 #include	"lib_debug.h"
-// #include	"lib_tcp.h"
-// #include	"lib_udp.h"
-// #include	"lib_unix.h"
+#include	"kernel/socket.h"
+#include	"lib_tcp.h"
+#include	"lib_udp.h"
+#include	"lib_unix.h"
 
 
 #ifdef	DEBUG
@@ -179,7 +180,7 @@ int	gettimeofday(struct timeval *tv, struct timezone *tz);
 #define	SMALLEST_LINE	32		/* smallest cache line size */
 #define	TIME_OPEN2CLOSE
 
-#define	GO_AWAY	signal(SIGALRM, exit); alarm(60 * 60);
+#define	GO_AWAY	signal(SIGALRM, (sighandler_t)exit); alarm(60 * 60);
 #define	REAL_SHORT	   50000
 #define	SHORT	 	 1000000
 #define	MEDIUM	 	 2000000
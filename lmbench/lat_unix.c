@@ -85,13 +85,13 @@ initialize(iter_t iterations, void* cookie)
 	}
 	handle_scheduler(benchmp_childid(), 0, 1);
 
-	if (pState->pid = fork())
+	if ((pState->pid = fork()))
 		return;
 
 	handle_scheduler(benchmp_childid(), 1, 1);
 
 	/* Child sits and ping-pongs packets back to parent */
-	signal(SIGTERM, exit);
+	signal(SIGTERM, (sighandler_t)exit);
 	while (read(pState->sv[0], pState->buf, pState->msize) == pState->msize) {
 		write(pState->sv[0], pState->buf, pState->msize);
 	}
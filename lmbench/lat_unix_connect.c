@@ -15,7 +15,8 @@
 char	*id = "$Id$\n";
 #include "bench.h"
 
-#define CONNAME "/tmp/af_unix"
+// #define CONNAME "/tmp/af_unix"
+#define CONNAME "af_unix"  // rv6 has no /tmp
 
 void server_main(void);
 
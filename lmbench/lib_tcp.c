@@ -41,6 +41,7 @@ tcp_server(int prog, int rdwr)
 		perror("listen");
 		exit(4);
 	}
+#ifndef	NO_PORTMAPPER
 	if (prog > 0) {
 #ifdef	LIBTCP_VERBOSE
 		fprintf(stderr, "Server port %d\n", sockport(sock));
@@ -52,6 +53,7 @@ tcp_server(int prog, int rdwr)
 			exit(5);
 		}
 	}
+#endif
 	return (sock);
 }
 
@@ -61,9 +63,11 @@ tcp_server(int prog, int rdwr)
 int
 tcp_done(int prog)
 {
+#ifndef	NO_PORTMAPPER
 	if (prog > 0) {
 		pmap_unset((u_long)prog, (u_long)1);
 	}
+#endif
 	return (0);
 }
 
@@ -107,7 +111,9 @@ tcp_connect(char *host, int prog, int rdwr)
 {
 	static	struct hostent *h;
 	static	struct sockaddr_in s;
+#ifndef	NO_PORTMAPPER
 	static	u_short	save_port;
+#endif
 	static	u_long save_prog;
 	static	char *save_host;
 	int	sock;
@@ -160,6 +166,7 @@ tcp_connect(char *host, int prog, int rdwr)
 		bzero((void *) &s, sizeof(s));
 		s.sin_family = AF_INET;
 		bcopy((void*)h->h_addr, (void *)&s.sin_addr, h->h_length);
+#ifndef	NO_PORTMAPPER
 		if (prog > 0) {
 			save_port = pmap_getport(&s, prog,
 			    (u_long)1, IPPROTO_TCP);
@@ -171,7 +178,9 @@ tcp_connect(char *host, int prog, int rdwr)
 			fprintf(stderr, "Server port %d\n", save_port);
 #endif
 			s.sin_port = htons(save_port);
-		} else {
+		} else
+#endif
+		{
 			s.sin_port = htons(-prog);
 		}
 	}
@@ -1,8 +1,8 @@
-#include	<sys/types.h>
-#include	<sys/socket.h>
-#include	<netinet/in.h>
-#include	<netdb.h>
-#include	<arpa/inet.h>
+// #include	<sys/types.h>
+// #include	<sys/socket.h>
+// #include	<netinet/in.h>
+// #include	<netdb.h>
+// #include	<arpa/inet.h>
 
 int	tcp_server(int prog, int rdwr);
 int	tcp_done(int prog);
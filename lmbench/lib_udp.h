@@ -1,8 +1,8 @@
-#include	<sys/types.h>
-#include	<sys/socket.h>
-#include	<netinet/in.h>
-#include	<netdb.h>
-#include	<arpa/inet.h>
+// #include	<sys/types.h>
+// #include	<sys/socket.h>
+// #include	<netinet/in.h>
+// #include	<netdb.h>
+// #include	<arpa/inet.h>
 
 int	udp_server(u_long prog, int rdwr);
 void	udp_done(u_long prog);
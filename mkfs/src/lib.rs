@@ -0,0 +1,366 @@
+//! Builds file system images.
+//!
+//! The image is built in memory, and written out by the caller. The on-disk structures and the
+//! disk layout come from the ufs-layout crate, which the kernel also uses.
+//!
+//! Inodes and blocks are allocated in order, so the same files added in the same order always
+//! make the same image. `Mkfs::add_tree` adds a host directory tree in a fixed order, so that
+//! an image of the same tree can be made again and compared with the old one.
+
+use std::{cmp, convert::TryInto, fs, io, os::unix::ffi::OsStrExt, path::Path};
+
+use ufs_layout::{
+    dirent_reclen, DInodeType, Dinode, DirentHeader, Superblock, BPB, BSIZE, DIRENT_HEADER_SIZE,
+    FSMAGIC, FS_CLEAN, FS_DIRTYPES, FS_GENERATIONS, FS_GROUPS, FS_LONGNAMES, IPB, MAXFILE,
+    MAXNAMELEN, NDIRECT, ROOTINO,
+};
+
+/// Size of an inode on the disk.
+const DINODE_SIZE: usize = BSIZE / IPB;
+
+/// Layout of a file system image.
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// Size of the image in blocks
+    pub size: u32,
+
+    /// Minimum number of inodes
+    pub ninodes: u32,
+
+    /// Number of log blocks
+    pub nlog: u32,
+
+    /// Number of blocks in a group, or 0 for the flat layout without block groups
+    pub groupsize: u32,
+}
+
+impl Default for Options {
+    /// The layout of fs.img. `size` and `nlog` are FSSIZE and LOGSIZE in kernel/param.h.
+    fn default() -> Self {
+        Self {
+            size: 5000,
+            ninodes: 200,
+            nlog: 30,
+            groupsize: 1024,
+        }
+    }
+}
+
+/// A directory being built.
+///
+/// Its entries are collected in a block, which is appended to the directory when it is full or
+/// when the directory is flushed.
+pub struct Dir {
+    inum: u32,
+    /// The next block of the directory
+    buf: [u8; BSIZE],
+    /// Bytes used in `buf`
+    used: usize,
+    /// Offset of the last entry in `buf`
+    last: usize,
+}
+
+impl Dir {
+    /// Returns the inode number of the directory.
+    pub fn inum(&self) -> u32 {
+        self.inum
+    }
+}
+
+pub struct Mkfs {
+    sb: Superblock,
+    image: Vec<u8>,
+    /// Number of meta blocks at the start of each group (inode, bitmap)
+    ngmeta: u32,
+    freeinode: u32,
+    /// The first free block that we can allocate
+    freeblock: u32,
+}
+
+impl Mkfs {
+    /// Computes the super block of an image with the layout `opts`, and returns an image
+    /// without files.
+    ///
+    /// Panics if the layout does not fit in the image.
+    pub fn new(opts: Options) -> Self {
+        let mut sb = Superblock {
+            magic: FSMAGIC,
+            size: opts.size,
+            nlog: opts.nlog,
+            logstart: 2,
+            incompat: FS_LONGNAMES | FS_DIRTYPES | FS_GENERATIONS,
+            state: FS_CLEAN,
+            ..Superblock::default()
+        };
+        let (nmeta, ngmeta) = if opts.groupsize == 0 {
+            let nbitmap = opts.size / BPB as u32 + 1;
+            let ninodeblocks = opts.ninodes / IPB as u32 + 1;
+            sb.ninodes = opts.ninodes;
+            sb.inodestart = 2 + opts.nlog;
+            sb.bmapstart = sb.inodestart + ninodeblocks;
+            sb.snapinodestart = sb.bmapstart + nbitmap;
+            sb.snapbmapstart = sb.snapinodestart + ninodeblocks;
+            (sb.snapbmapstart + nbitmap, 0)
+        } else {
+            assert!(
+                opts.groupsize as usize <= BPB,
+                "groups larger than a bitmap block"
+            );
+            let ngroups = (opts.size - 2 - opts.nlog + opts.groupsize - 1) / opts.groupsize;
+            let ipg = (opts.ninodes / ngroups / IPB as u32 + 1) * IPB as u32;
+            let ngmeta = ipg / IPB as u32 + 1;
+            sb.incompat |= FS_GROUPS;
+            sb.ngroups = ngroups;
+            sb.groupsize = opts.groupsize;
+            sb.ipg = ipg;
+            sb.ninodes = ngroups * ipg;
+            sb.snapinodestart = 2 + opts.nlog;
+            sb.snapbmapstart = sb.snapinodestart + ngroups * sb.group_iblocks();
+            sb.groupstart = sb.snapbmapstart + ngroups;
+            sb.inodestart = sb.groupstart;
+            sb.bmapstart = sb.groupstart + sb.group_iblocks();
+            // The last group must hold its meta blocks and some data blocks.
+            assert!(
+                opts.size - sb.group_start(ngroups - 1) > ngmeta,
+                "the last group is too small"
+            );
+            (sb.groupstart + ngroups * ngmeta, ngmeta)
+        };
+        assert!(nmeta < opts.size, "no room for data blocks");
+        sb.nblocks = opts.size - nmeta;
+
+        let mut image = vec![0; opts.size as usize * BSIZE];
+        sb.encode(&mut image[BSIZE..]);
+        Self {
+            sb,
+            image,
+            ngmeta,
+            freeinode: 1,
+            freeblock: if sb.grouped() { sb.groupstart } else { nmeta },
+        }
+    }
+
+    /// Returns the super block of the image.
+    pub fn superblock(&self) -> &Superblock {
+        &self.sb
+    }
+
+    /// Returns the number of blocks allocated so far, including the meta blocks.
+    pub fn used_blocks(&self) -> u32 {
+        self.freeblock
+    }
+
+    fn block_mut(&mut self, b: u32) -> &mut [u8] {
+        &mut self.image[b as usize * BSIZE..(b as usize + 1) * BSIZE]
+    }
+
+    fn inode_off(&self, inum: u32) -> usize {
+        self.sb.iblock(inum) as usize * BSIZE + inum as usize % IPB * DINODE_SIZE
+    }
+
+    fn rinode(&self, inum: u32) -> Dinode {
+        let off = self.inode_off(inum);
+        Dinode::decode(&self.image[off..]).expect("rinode")
+    }
+
+    fn winode(&mut self, inum: u32, dinode: &Dinode) {
+        let off = self.inode_off(inum);
+        dinode.encode(&mut self.image[off..off + DINODE_SIZE]);
+    }
+
+    /// Allocates an inode of type `typ`.
+    pub fn ialloc(&mut self, typ: DInodeType) -> u32 {
+        let inum = self.freeinode;
+        assert!(inum < self.sb.ninodes, "out of inodes");
+        self.freeinode += 1;
+        let mut dinode = Dinode::new(typ);
+        dinode.nlink = 1;
+        dinode.gen = 1;
+        self.winode(inum, &dinode);
+        inum
+    }
+
+    /// Allocates the next data block, skipping the meta blocks at the start of each group.
+    fn nextblock(&mut self) -> u32 {
+        if self.sb.grouped() {
+            let g = self.sb.block_group(self.freeblock);
+            if self.freeblock == self.sb.group_start(g) {
+                self.freeblock += self.ngmeta;
+            }
+        }
+        assert!(self.freeblock < self.sb.size, "out of blocks");
+        self.freeblock += 1;
+        self.freeblock - 1
+    }
+
+    /// Appends `data` to the file `inum`.
+    pub fn iappend(&mut self, inum: u32, mut data: &[u8]) {
+        let mut dinode = self.rinode(inum);
+        let mut off = dinode.size as usize;
+        while !data.is_empty() {
+            let fbn = off / BSIZE;
+            assert!(fbn < MAXFILE, "file too large");
+            let addr = if fbn < NDIRECT {
+                if dinode.addr_direct[fbn] == 0 {
+                    dinode.addr_direct[fbn] = self.nextblock();
+                }
+                dinode.addr_direct[fbn]
+            } else {
+                if dinode.addr_indirect == 0 {
+                    dinode.addr_indirect = self.nextblock();
+                }
+                let entry = dinode.addr_indirect as usize * BSIZE + (fbn - NDIRECT) * 4;
+                let addr = self.image[entry..entry + 4].try_into().unwrap();
+                let mut addr = u32::from_le_bytes(addr);
+                if addr == 0 {
+                    addr = self.nextblock();
+                    self.image[entry..entry + 4].copy_from_slice(&addr.to_le_bytes());
+                }
+                addr
+            };
+            let n = cmp::min(data.len(), (fbn + 1) * BSIZE - off);
+            let start = off - fbn * BSIZE;
+            self.block_mut(addr)[start..start + n].copy_from_slice(&data[..n]);
+            data = &data[n..];
+            off += n;
+        }
+        dinode.size = off as u32;
+        self.winode(inum, &dinode);
+    }
+
+    /// Allocates a directory with its "." and ".." entries, whose parent is `parent`, or
+    /// itself if `parent` is `None`.
+    fn alloc_dir(&mut self, parent: Option<u32>) -> Dir {
+        let inum = self.ialloc(DInodeType::Dir);
+        let mut dir = Dir {
+            inum,
+            buf: [0; BSIZE],
+            used: 0,
+            last: 0,
+        };
+        self.dirappend(&mut dir, inum, DInodeType::Dir, b".");
+        self.dirappend(&mut dir, parent.unwrap_or(inum), DInodeType::Dir, b"..");
+        dir
+    }
+
+    /// Allocates the root directory.
+    pub fn root(&mut self) -> Dir {
+        let dir = self.alloc_dir(None);
+        assert_eq!(dir.inum, ROOTINO);
+        dir
+    }
+
+    /// Adds a directory named `name` to the directory `dir`, and returns it.
+    pub fn mkdir(&mut self, dir: &mut Dir, name: &[u8]) -> Dir {
+        let sub = self.alloc_dir(Some(dir.inum));
+        self.dirappend(dir, sub.inum, DInodeType::Dir, name);
+        // For the ".." entry of the new directory.
+        let mut dinode = self.rinode(dir.inum);
+        dinode.nlink += 1;
+        self.winode(dir.inum, &dinode);
+        sub
+    }
+
+    /// Appends an entry to the directory `dir`.
+    pub fn dirappend(&mut self, dir: &mut Dir, inum: u32, typ: DInodeType, name: &[u8]) {
+        assert!(name.len() <= MAXNAMELEN, "name too long");
+        assert!(!name.contains(&0) && !name.contains(&b'/'), "invalid name");
+        let reclen = dirent_reclen(name.len()) as usize;
+        if dir.used + reclen > BSIZE {
+            self.dirflush(dir);
+        }
+        let header = DirentHeader {
+            inum: inum as u16,
+            reclen: reclen as u16,
+            namelen: name.len() as u8,
+            typ: typ as u8,
+        };
+        header.encode(&mut dir.buf[dir.used..]);
+        let start = dir.used + DIRENT_HEADER_SIZE;
+        dir.buf[start..start + name.len()].copy_from_slice(name);
+        dir.last = dir.used;
+        dir.used += reclen;
+    }
+
+    /// Appends the collected entries of `dir` to the directory as a whole block, extending its
+    /// last entry to the end of the block. Must be called after the last entry is appended.
+    pub fn dirflush(&mut self, dir: &mut Dir) {
+        if dir.used == 0 {
+            return;
+        }
+        let mut header = DirentHeader::decode(&dir.buf[dir.last..]);
+        header.reclen = (BSIZE - dir.last) as u16;
+        header.encode(&mut dir.buf[dir.last..]);
+        let buf = dir.buf;
+        self.iappend(dir.inum, &buf);
+        dir.buf = [0; BSIZE];
+        dir.used = 0;
+    }
+
+    /// Adds a file named `name` with the content `data` to the directory `dir`.
+    pub fn add_file(&mut self, dir: &mut Dir, name: &[u8], data: &[u8]) -> u32 {
+        let inum = self.ialloc(DInodeType::File);
+        self.dirappend(dir, inum, DInodeType::File, name);
+        self.iappend(inum, data);
+        inum
+    }
+
+    /// Adds the files and directories in the host directory `path` to the directory `dir`,
+    /// recursively.
+    ///
+    /// The entries of a directory are added in the order of their names, and a directory is
+    /// added with all of its content before the next entry. Hence the same tree always gets the
+    /// same inodes and blocks, whatever order the host lists it in.
+    ///
+    /// The file system has neither modes nor symbolic links yet, so modes are not kept, and
+    /// symbolic links and special files are skipped.
+    pub fn add_tree(&mut self, dir: &mut Dir, path: &Path) -> io::Result<()> {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        for name in entries {
+            let path = path.join(&name);
+            let metadata = fs::symlink_metadata(&path)?;
+            if metadata.is_dir() {
+                let mut sub = self.mkdir(dir, name.as_bytes());
+                self.add_tree(&mut sub, &path)?;
+                self.dirflush(&mut sub);
+            } else if metadata.is_file() {
+                let _ = self.add_file(dir, name.as_bytes(), &fs::read(&path)?);
+            } else {
+                eprintln!("mkfs: skipping {}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the free maps, and returns the image.
+    pub fn finish(mut self) -> Vec<u8> {
+        let sb = self.sb;
+        if sb.grouped() {
+            // Blocks are allocated in order, so the blocks of a group below freeblock are in use.
+            // The meta blocks of a group are always in use.
+            for g in 0..sb.ngroups {
+                let start = sb.group_start(g);
+                let used = self.freeblock.saturating_sub(start);
+                let used = cmp::max(cmp::min(used, sb.groupsize), self.ngmeta);
+                mark_used(self.block_mut(start + sb.group_iblocks()), used);
+            }
+        } else {
+            let mut used = self.freeblock;
+            for b in 0..(sb.size / BPB as u32 + 1) {
+                mark_used(self.block_mut(sb.bmapstart + b), cmp::min(used, BPB as u32));
+                used = used.saturating_sub(BPB as u32);
+            }
+        }
+        self.image
+    }
+}
+
+/// Sets the first `n` bits of the bitmap block `buf`.
+fn mark_used(buf: &mut [u8], n: u32) {
+    for i in 0..n as usize {
+        buf[i / 8] |= 1 << (i % 8);
+    }
+}
@@ -0,0 +1,100 @@
+//! mkfs [-s size] [-i ninodes] [-g groupsize] fs.img files...
+//!
+//! Makes a file system image containing the given files in its root directory. A directory is
+//! added with all of its content, under its own name. `-g 0` makes an image with the flat
+//! layout without block groups.
+
+use std::{env, fs, os::unix::ffi::OsStrExt, path::Path, process};
+
+use mkfs::{Mkfs, Options};
+
+fn usage() -> ! {
+    eprintln!("Usage: mkfs [-s size] [-i ninodes] [-g groupsize] fs.img files...");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let mut opts = Options::default();
+    while let Some(flag) = args.next_if(|arg| arg.starts_with('-')) {
+        let value = args
+            .next()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "-s" => opts.size = value,
+            "-i" => opts.ninodes = value,
+            "-g" => opts.groupsize = value,
+            _ => usage(),
+        }
+    }
+    let img = args.next().unwrap_or_else(|| usage());
+
+    let mut mkfs = Mkfs::new(opts);
+    let sb = *mkfs.superblock();
+    let meta = if sb.grouped() {
+        format!(
+            "{} groups of {} inode blocks and a bitmap block",
+            sb.ngroups,
+            sb.group_iblocks()
+        )
+    } else {
+        format!(
+            "inode blocks {}, bitmap blocks {}",
+            sb.bmapstart - sb.inodestart,
+            sb.snapinodestart - sb.bmapstart
+        )
+    };
+    println!(
+        "nmeta {} (boot, super, log blocks {}, {}) blocks {} total {}",
+        sb.size - sb.nblocks,
+        sb.nlog,
+        meta,
+        sb.nblocks,
+        sb.size
+    );
+
+    let mut root = mkfs.root();
+    for path in args {
+        if Path::new(&path).is_dir() {
+            let name = Path::new(&path).file_name().unwrap_or_else(|| usage());
+            let mut dir = mkfs.mkdir(&mut root, name.as_bytes());
+            if let Err(e) = mkfs.add_tree(&mut dir, Path::new(&path)) {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            }
+            mkfs.dirflush(&mut dir);
+            continue;
+        }
+
+        // get rid of "user/"
+        let name = path.strip_prefix("user/").unwrap_or(&path);
+        assert!(
+            !name.contains('/'),
+            "{}: not in the current directory",
+            path
+        );
+
+        // Skip leading _ in name when writing to file system.
+        // The binaries are named _rm, _cat, etc. to keep the
+        // build operating system from trying to execute them
+        // in place of system binaries like rm and cat.
+        let name = name.strip_prefix('_').unwrap_or(name);
+
+        let data = fs::read(&path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        });
+        let _ = mkfs.add_file(&mut root, name.as_bytes(), &data);
+    }
+    mkfs.dirflush(&mut root);
+
+    println!(
+        "balloc: first {} blocks have been allocated",
+        mkfs.used_blocks()
+    );
+    if let Err(e) = fs::write(&img, mkfs.finish()) {
+        eprintln!("{}: {}", img, e);
+        process::exit(1);
+    }
+}
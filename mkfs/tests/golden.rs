@@ -0,0 +1,203 @@
+//! Golden-image tests of mkfs.
+//!
+//! Each test builds an image from fixed contents, and compares its hash with the hash of the
+//! image that the previous mkfs made. A change of the disk format or of the allocation order
+//! changes the hashes. If the change is intended, update the hashes with the ones printed by the
+//! failing tests.
+
+use std::{env, fs, path::Path, process};
+
+use mkfs::{Mkfs, Options};
+use ufs_layout::{
+    DInodeType, Dinode, DirentHeader, Superblock, BSIZE, DIRENT_HEADER_SIZE, FS_CLEAN,
+    FS_GENERATIONS, IPB, MAXFILE, ROOTINO,
+};
+
+/// Returns `len` bytes of a pseudo-random content determined by `seed`.
+fn content(seed: u32, len: usize) -> Vec<u8> {
+    let mut x = seed.wrapping_mul(2654435761) | 1;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        })
+        .collect()
+}
+
+/// FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Makes an image with the layout `opts`, containing a small file, a file of the maximum size,
+/// a file with a long name, and enough files to fill several directory blocks.
+fn image(opts: Options) -> Vec<u8> {
+    let mut mkfs = Mkfs::new(opts);
+    let mut root = mkfs.root();
+    let _ = mkfs.add_file(&mut root, b"README", b"rv6\n");
+    let _ = mkfs.add_file(&mut root, b"big", &content(1, MAXFILE * BSIZE));
+    let _ = mkfs.add_file(&mut root, &[b'n'; 255], &content(2, 3000));
+    for i in 0..100 {
+        let name = format!("file{}", i);
+        let _ = mkfs.add_file(&mut root, name.as_bytes(), &content(i + 3, i as usize * 10));
+    }
+    mkfs.dirflush(&mut root);
+    mkfs.finish()
+}
+
+fn assert_golden(img: &[u8], golden: u64) {
+    let hash = fnv1a(img);
+    assert_eq!(hash, golden, "image changed, its hash is {:#x}", hash);
+}
+
+fn check_golden(opts: Options, golden: u64) {
+    let img = image(opts);
+    assert_eq!(img.len(), opts.size as usize * BSIZE);
+    assert_golden(&img, golden);
+}
+
+/// Reads the inode `inum` of the image `img`.
+fn dinode(img: &[u8], sb: &Superblock, inum: u32) -> Dinode {
+    let off = sb.iblock(inum) as usize * BSIZE + inum as usize % IPB * (BSIZE / IPB);
+    Dinode::decode(&img[off..]).unwrap()
+}
+
+#[test]
+fn golden_groups() {
+    check_golden(Options::default(), 0x57d2c2e0c7809919);
+}
+
+#[test]
+fn golden_flat() {
+    check_golden(
+        Options {
+            groupsize: 0,
+            ..Options::default()
+        },
+        0x054e77e90d852c2b,
+    );
+}
+
+#[test]
+fn golden_small_groups() {
+    check_golden(
+        Options {
+            size: 2000,
+            ninodes: 120,
+            groupsize: 300,
+            ..Options::default()
+        },
+        0x411e86c659cbf385,
+    );
+}
+
+#[test]
+fn root_directory() {
+    let img = image(Options::default());
+    let sb = Superblock::decode(&img[BSIZE..]).unwrap();
+    // A new image needs no log recovery.
+    assert_eq!(sb.state, FS_CLEAN);
+    let root = dinode(&img, &sb, ROOTINO);
+    assert_eq!(root.typ, DInodeType::Dir);
+    assert_eq!(root.gen, 1);
+    assert_eq!(root.size as usize % BSIZE, 0);
+
+    // Walk the entries of the root directory.
+    let mut names = Vec::new();
+    for bn in 0..root.size as usize / BSIZE {
+        let block = root.addr_direct[bn] as usize * BSIZE;
+        let block = &img[block..block + BSIZE];
+        let mut off = 0;
+        while off < BSIZE {
+            let de = DirentHeader::decode(&block[off..]);
+            let name = &block[off + DIRENT_HEADER_SIZE..][..de.namelen as usize];
+            let ip = dinode(&img, &sb, de.inum as u32);
+            assert_eq!(de.typ, ip.typ as u8);
+            names.push(name.to_vec());
+            off += de.reclen as usize;
+        }
+        assert_eq!(off, BSIZE);
+    }
+    assert_eq!(names.len(), 105);
+    assert_eq!(
+        names[..3],
+        [b".".to_vec(), b"..".to_vec(), b"README".to_vec()]
+    );
+}
+
+#[test]
+fn unknown_features() {
+    let mut sb = *Mkfs::new(Options::default()).superblock();
+    assert_eq!(sb.writable(), Ok(true));
+    // Inodes without generation numbers are no longer supported.
+    let old = Superblock {
+        incompat: sb.incompat & !FS_GENERATIONS,
+        ..sb
+    };
+    assert_eq!(old.writable(), Err(()));
+    sb.compat |= 1 << 31;
+    assert_eq!(sb.writable(), Ok(true));
+    sb.ro_compat |= 1 << 31;
+    assert_eq!(sb.writable(), Ok(false));
+    sb.incompat |= 1 << 31;
+    assert_eq!(sb.writable(), Err(()));
+}
+
+/// Makes the host directory `path` with a few files and directories, creating them in the
+/// reverse order if `reverse`.
+fn make_tree(path: &Path, reverse: bool) {
+    let mut entries = vec![
+        ("data", None),
+        ("data/small", Some(content(1, 100))),
+        ("data/large", Some(content(2, 100_000))),
+        ("data/nested", None),
+        ("data/nested/file", Some(content(3, 5000))),
+        ("empty", None),
+        ("top", Some(content(4, 10))),
+    ];
+    if reverse {
+        entries.reverse();
+    }
+    let _ = fs::remove_dir_all(path);
+    for (name, data) in &entries {
+        let path = path.join(name);
+        let dir = if data.is_some() {
+            path.parent().unwrap()
+        } else {
+            &path
+        };
+        fs::create_dir_all(dir).unwrap();
+        if let Some(data) = data {
+            fs::write(&path, data).unwrap();
+        }
+    }
+}
+
+fn tree_image(path: &Path) -> Vec<u8> {
+    let mut mkfs = Mkfs::new(Options::default());
+    let mut root = mkfs.root();
+    mkfs.add_tree(&mut root, path).unwrap();
+    mkfs.dirflush(&mut root);
+    mkfs.finish()
+}
+
+#[test]
+fn golden_tree() {
+    let base = env::temp_dir().join(format!("mkfs-golden-{}", process::id()));
+    let (forward, backward) = (base.join("forward"), base.join("backward"));
+    make_tree(&forward, false);
+    make_tree(&backward, true);
+    let img = tree_image(&forward);
+    let same = tree_image(&backward);
+    fs::remove_dir_all(&base).unwrap();
+
+    assert!(img == same, "the image depends on the creation order");
+    let sb = Superblock::decode(&img[BSIZE..]).unwrap();
+    // The root directory has "data" and "empty" as subdirectories.
+    assert_eq!(dinode(&img, &sb, ROOTINO).nlink, 3);
+    assert_golden(&img, 0x849483c9f2a15bc8);
+}
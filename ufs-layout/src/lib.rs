@@ -0,0 +1,550 @@
+//! On-disk format of the rv6 file system.
+//!
+//! Both the kernel and mkfs use this crate, so that they agree on the disk layout. kernel/fs.h
+//! describes the same format for the C user programs.
+//!
+//! All numbers are stored in little-endian byte order. The `decode` and `encode` functions
+//! convert the structures from and to their on-disk representation, so that they can be used
+//! on hosts of any byte order. The kernel also accesses the structures in place, which is why
+//! they are `repr(C)`.
+
+#![no_std]
+
+use core::{cmp, convert::TryInto, mem};
+
+#[cfg(feature = "zerocopy")]
+use zerocopy::{AsBytes, FromBytes};
+
+/// Block size.
+pub const BSIZE: usize = 1024;
+
+/// root i-number
+pub const ROOTINO: u32 = 1;
+
+pub const NDIRECT: usize = 12;
+pub const NINDIRECT: usize = BSIZE.wrapping_div(mem::size_of::<u32>());
+pub const MAXFILE: usize = NDIRECT.wrapping_add(NINDIRECT);
+
+pub const FSMAGIC: u32 = 0x10203040;
+
+// Features are recorded in three sets of flags in the super block:
+// * `compat`: a kernel that does not know the feature can still read and write the file system.
+// * `ro_compat`: a kernel that does not know the feature can only read the file system.
+// * `incompat`: a kernel that does not know the feature cannot mount the file system.
+// A new on-disk feature takes the next free bit of the set it belongs to.
+
+/// Directories use the long name format. (incompat)
+pub const FS_LONGNAMES: u32 = 0x1;
+
+/// Directory entries in the long name format record the type of their file. (incompat)
+pub const FS_DIRTYPES: u32 = 0x2;
+
+/// The disk is divided into block groups. (incompat)
+pub const FS_GROUPS: u32 = 0x4;
+
+/// Inodes have a generation number. (incompat, required)
+pub const FS_GENERATIONS: u32 = 0x8;
+
+/// The compatible features that this crate knows.
+pub const FS_COMPAT_SUPP: u32 = 0;
+
+/// The read-only compatible features that this crate knows.
+pub const FS_RO_COMPAT_SUPP: u32 = 0;
+
+/// The incompatible features that this crate knows.
+pub const FS_INCOMPAT_SUPP: u32 = FS_LONGNAMES | FS_DIRTYPES | FS_GROUPS | FS_GENERATIONS;
+
+/// State of a file system that was unmounted cleanly, whose log needs no recovery. A mounted
+/// file system, or one that was not unmounted, has the state 0.
+pub const FS_CLEAN: u32 = 1;
+
+/// Value of `Superblock::snapshot` when the snapshot blocks hold a complete snapshot. The
+/// snapshot blocks are ignored otherwise, so that a crash while taking a snapshot leaves none.
+pub const SNAP_VALID: u32 = 1;
+
+/// Disk layout:
+/// [ boot block | super block | log | inode blocks |
+///                      free bit map | snapshot inode blocks |
+///                                   snapshot bit map | data blocks]
+///
+/// or, with FS_GROUPS:
+/// [ boot block | super block | log | snapshot inode blocks |
+///                      snapshot bit map | group 0 | group 1 | ... ]
+/// where each group is [ inode blocks | free bit map block | data blocks ].
+/// A group's bit map covers the group's blocks only, and the snapshot keeps
+/// a copy of the inode blocks and the bit map block of each group in order.
+/// The last group may be shorter than the others.
+///
+/// mkfs computes the super block and builds an initial file system. The
+/// super block describes the disk layout:
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+#[repr(C)]
+pub struct Superblock {
+    /// Must be FSMAGIC
+    pub magic: u32,
+
+    /// Size of file system image (blocks)
+    pub size: u32,
+
+    /// Number of data blocks
+    pub nblocks: u32,
+
+    /// Number of inodes
+    pub ninodes: u32,
+
+    /// Number of log blocks
+    pub nlog: u32,
+
+    /// Block number of first log block
+    pub logstart: u32,
+
+    /// Block number of first inode block
+    pub inodestart: u32,
+
+    /// Block number of first free map block
+    pub bmapstart: u32,
+
+    /// Block number of first snapshot inode block, or 0 if snapshots are not supported
+    pub snapinodestart: u32,
+
+    /// Block number of first snapshot map block, or 0 if snapshots are not supported
+    pub snapbmapstart: u32,
+
+    /// Features without which the file system cannot be mounted (FS_*)
+    pub incompat: u32,
+
+    /// Block number of the first block group (FS_GROUPS only)
+    pub groupstart: u32,
+
+    /// Number of block groups (FS_GROUPS only)
+    pub ngroups: u32,
+
+    /// Number of blocks in a group (FS_GROUPS only)
+    pub groupsize: u32,
+
+    /// Number of inodes in a group, a multiple of IPB (FS_GROUPS only)
+    pub ipg: u32,
+
+    /// FS_CLEAN if the file system was unmounted cleanly, 0 otherwise
+    pub state: u32,
+
+    /// Features that can be ignored (FS_*)
+    pub compat: u32,
+
+    /// Features without which the file system can only be mounted read-only (FS_*)
+    pub ro_compat: u32,
+
+    /// SNAP_VALID if the snapshot blocks hold a complete snapshot, 0 otherwise
+    pub snapshot: u32,
+}
+
+/// Inodes per block.
+pub const IPB: usize = BSIZE / mem::size_of::<Dinode>();
+
+/// Bitmap bits per block
+pub const BPB: usize = BSIZE * 8;
+
+impl Superblock {
+    /// Decodes the super block from the beginning of `bytes`.
+    /// Returns `None` if it is not a super block of a file system.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let field = |i: usize| read_u32(bytes, i * 4);
+        if field(0) != FSMAGIC {
+            return None;
+        }
+        Some(Self {
+            magic: field(0),
+            size: field(1),
+            nblocks: field(2),
+            ninodes: field(3),
+            nlog: field(4),
+            logstart: field(5),
+            inodestart: field(6),
+            bmapstart: field(7),
+            snapinodestart: field(8),
+            snapbmapstart: field(9),
+            incompat: field(10),
+            groupstart: field(11),
+            ngroups: field(12),
+            groupsize: field(13),
+            ipg: field(14),
+            state: field(15),
+            compat: field(16),
+            ro_compat: field(17),
+            snapshot: field(18),
+        })
+    }
+
+    /// Encodes the super block into the beginning of `bytes`.
+    pub fn encode(&self, bytes: &mut [u8]) {
+        let fields = [
+            self.magic,
+            self.size,
+            self.nblocks,
+            self.ninodes,
+            self.nlog,
+            self.logstart,
+            self.inodestart,
+            self.bmapstart,
+            self.snapinodestart,
+            self.snapbmapstart,
+            self.incompat,
+            self.groupstart,
+            self.ngroups,
+            self.groupsize,
+            self.ipg,
+            self.state,
+            self.compat,
+            self.ro_compat,
+            self.snapshot,
+        ];
+        for (i, field) in fields.iter().enumerate() {
+            write_u32(bytes, i * 4, *field);
+        }
+    }
+
+    /// Is the disk divided into block groups?
+    pub const fn grouped(self) -> bool {
+        self.incompat & FS_GROUPS != 0
+    }
+
+    /// Can the file system be mounted read-write by code that knows only the features of this
+    /// crate? Returns Err(()) if it cannot be mounted at all, including a file system made
+    /// before the inodes had the current format.
+    pub const fn writable(self) -> Result<bool, ()> {
+        if self.incompat & !FS_INCOMPAT_SUPP != 0 || self.incompat & FS_GENERATIONS == 0 {
+            return Err(());
+        }
+        Ok(self.ro_compat & !FS_RO_COMPAT_SUPP == 0)
+    }
+
+    /// First block of group g
+    pub const fn group_start(self, g: u32) -> u32 {
+        self.groupstart + g * self.groupsize
+    }
+
+    /// Group containing block b, which must be in a group
+    pub const fn block_group(self, b: u32) -> u32 {
+        (b - self.groupstart) / self.groupsize
+    }
+
+    /// Number of inode blocks in a group
+    pub const fn group_iblocks(self) -> u32 {
+        self.ipg / IPB as u32
+    }
+
+    /// Group containing inode i, or 0 if the disk is not divided into groups
+    pub const fn inode_group(self, i: u32) -> u32 {
+        if self.grouped() {
+            i / self.ipg
+        } else {
+            0
+        }
+    }
+
+    /// Block containing inode i
+    pub const fn iblock(self, i: u32) -> u32 {
+        if self.grouped() {
+            self.group_start(i / self.ipg) + i % self.ipg / IPB as u32
+        } else {
+            i / IPB as u32 + self.inodestart
+        }
+    }
+
+    /// Block of free map containing bit for block b
+    pub const fn bblock(self, b: u32) -> u32 {
+        if self.grouped() {
+            self.group_start(self.block_group(b)) + self.group_iblocks()
+        } else {
+            b / BPB as u32 + self.bmapstart
+        }
+    }
+
+    /// Index of the bit for block b in its free map block, which is the same in the snapshot map
+    pub const fn bbit(self, b: u32) -> usize {
+        if self.grouped() {
+            (b - self.group_start(self.block_group(b))) as usize
+        } else {
+            b as usize % BPB
+        }
+    }
+
+    /// Block containing inode i of the snapshot
+    pub const fn snap_iblock(self, i: u32) -> u32 {
+        i / IPB as u32 + self.snapinodestart
+    }
+
+    /// Block of snapshot map containing bit for block b
+    pub const fn snap_bblock(self, b: u32) -> u32 {
+        if self.grouped() {
+            self.block_group(b) + self.snapbmapstart
+        } else {
+            b / BPB as u32 + self.snapbmapstart
+        }
+    }
+
+    /// Returns the inode numbers from 1, starting from the group of the inode `near` so that
+    /// inodes are looked for near it, and wrapping around.
+    pub fn inode_order(self, near: u32) -> impl Iterator<Item = u32> {
+        let first = self.inode_group(near) * self.ipg;
+        let n = self.ninodes;
+        (0..n).map(move |i| (first + i) % n).filter(|&i| i != 0)
+    }
+
+    /// Returns the ranges of blocks that the free map blocks cover, as (first block, number
+    /// of blocks). The ranges start from the group `g`, so that blocks are looked for near it,
+    /// and wrap around.
+    pub fn bmap_ranges(self, g: u32) -> impl Iterator<Item = (u32, u32)> {
+        let n = if self.grouped() {
+            self.ngroups
+        } else {
+            (self.size + BPB as u32 - 1) / BPB as u32
+        };
+        (0..n).map(move |i| {
+            let i = (g + i) % n;
+            let (start, len) = if self.grouped() {
+                (self.group_start(i), self.groupsize)
+            } else {
+                (i * BPB as u32, BPB as u32)
+            };
+            (start, cmp::min(len, self.size - start))
+        })
+    }
+
+    /// Returns the runs of inode blocks, as (first block, first block of their copy in the
+    /// snapshot, number of blocks).
+    pub fn inode_runs(self) -> impl Iterator<Item = (u32, u32, u32)> {
+        let (n, len) = if self.grouped() {
+            (self.ngroups, self.group_iblocks())
+        } else {
+            (1, self.ninodes / IPB as u32 + 1)
+        };
+        (0..n).map(move |i| {
+            let start = if self.grouped() {
+                self.group_start(i)
+            } else {
+                self.inodestart
+            };
+            (start, self.snapinodestart + i * len, len)
+        })
+    }
+
+    /// Returns the runs of free map blocks, as (first block, first block of their copy in the
+    /// snapshot, number of blocks).
+    pub fn bmap_runs(self) -> impl Iterator<Item = (u32, u32, u32)> {
+        let (n, len) = if self.grouped() {
+            (self.ngroups, 1)
+        } else {
+            (1, self.size / BPB as u32 + 1)
+        };
+        (0..n).map(move |i| {
+            let start = if self.grouped() {
+                self.group_start(i) + self.group_iblocks()
+            } else {
+                self.bmapstart
+            };
+            (start, self.snapbmapstart + i * len, len)
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(i16)]
+pub enum DInodeType {
+    None,
+    Dir,
+    File,
+    Device,
+    Socket,
+}
+
+impl DInodeType {
+    /// Returns the type numbered `t` on the disk, if any.
+    pub const fn from_i16(t: i16) -> Option<Self> {
+        match t {
+            0 => Some(Self::None),
+            1 => Some(Self::Dir),
+            2 => Some(Self::File),
+            3 => Some(Self::Device),
+            4 => Some(Self::Socket),
+            _ => None,
+        }
+    }
+}
+
+/// On-disk inode structure
+// It needs repr(C) because it's struct for in-disk representation
+// which should follow C(=machine) representation
+// https://github.com/kaist-cp/rv6/issues/52
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct Dinode {
+    /// File type
+    pub typ: DInodeType,
+
+    /// Major device number (T_DEVICE only)
+    pub major: u16,
+
+    /// Minor device number (T_DEVICE only)
+    pub minor: u16,
+
+    /// Number of links to inode in file system
+    pub nlink: i16,
+
+    /// Size of file (bytes)
+    pub size: u32,
+
+    /// Direct data block addresses
+    pub addr_direct: [u32; NDIRECT],
+
+    /// Indirect data block address
+    pub addr_indirect: u32,
+
+    /// Incremented each time the inode is allocated, so that a file can be told from a later
+    /// file with the same inode number
+    pub gen: u32,
+}
+
+impl Dinode {
+    /// Returns an inode of type `typ` without links and content.
+    pub const fn new(typ: DInodeType) -> Self {
+        Self {
+            typ,
+            major: 0,
+            minor: 0,
+            nlink: 0,
+            size: 0,
+            addr_direct: [0; NDIRECT],
+            addr_indirect: 0,
+            gen: 0,
+        }
+    }
+
+    /// Decodes the inode at the beginning of `bytes`.
+    /// Returns `None` if its type is invalid.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut dinode = Self::new(DInodeType::from_i16(read_u16(bytes, 0) as i16)?);
+        dinode.major = read_u16(bytes, 2);
+        dinode.minor = read_u16(bytes, 4);
+        dinode.nlink = read_u16(bytes, 6) as i16;
+        dinode.size = read_u32(bytes, 8);
+        for (i, addr) in dinode.addr_direct.iter_mut().enumerate() {
+            *addr = read_u32(bytes, 12 + i * 4);
+        }
+        dinode.addr_indirect = read_u32(bytes, 12 + NDIRECT * 4);
+        dinode.gen = read_u32(bytes, 16 + NDIRECT * 4);
+        Some(dinode)
+    }
+
+    /// Encodes the inode into the beginning of `bytes`.
+    pub fn encode(&self, bytes: &mut [u8]) {
+        write_u16(bytes, 0, self.typ as u16);
+        write_u16(bytes, 2, self.major);
+        write_u16(bytes, 4, self.minor);
+        write_u16(bytes, 6, self.nlink as u16);
+        write_u32(bytes, 8, self.size);
+        for (i, addr) in self.addr_direct.iter().enumerate() {
+            write_u32(bytes, 12 + i * 4, *addr);
+        }
+        write_u32(bytes, 12 + NDIRECT * 4, self.addr_indirect);
+        write_u32(bytes, 16 + NDIRECT * 4, self.gen);
+    }
+}
+
+/// Maximum length of a name in the legacy directory format.
+pub const DIRSIZ: usize = 14;
+
+/// Maximum length of a name in the long name directory format.
+pub const MAXNAMELEN: usize = 255;
+
+/// dirent size in the legacy directory format
+pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
+
+/// Size of the fixed part of a directory entry in the long name format.
+pub const DIRENT_HEADER_SIZE: usize = mem::size_of::<DirentHeader>();
+
+/// Directory entry in the legacy directory format.
+///
+/// A directory is a file containing a sequence of Dirent structures.
+#[repr(C)]
+#[derive(Default)]
+#[cfg_attr(feature = "zerocopy", derive(AsBytes, FromBytes))]
+pub struct Dirent {
+    pub inum: u16,
+    pub name: [u8; DIRSIZ],
+}
+
+impl Dirent {
+    /// Fill in name. If name is shorter than DIRSIZ, NUL character is appended as
+    /// terminator. Longer names are truncated to DIRSIZ bytes.
+    ///
+    /// `name` must not contain NUL characters, but this is not a safety invariant.
+    pub fn set_name(&mut self, name: &[u8]) {
+        let len = cmp::min(name.len(), DIRSIZ);
+        self.name[..len].copy_from_slice(&name[..len]);
+        if len < DIRSIZ {
+            self.name[len] = 0;
+        }
+    }
+}
+
+/// Fixed part of a directory entry in the long name directory format.
+///
+/// A directory is a file containing a sequence of variable-length entries. Each
+/// DirentHeader is followed by `namelen` bytes of the name without NUL terminator,
+/// and padding up to `reclen` bytes. Entries never cross a block boundary, and
+/// the last entry in a block extends to the end of the block.
+#[repr(C)]
+#[derive(Default)]
+#[cfg_attr(feature = "zerocopy", derive(AsBytes, FromBytes))]
+pub struct DirentHeader {
+    pub inum: u16,
+    /// Length of the whole entry in bytes
+    pub reclen: u16,
+    /// Length of the name in bytes
+    pub namelen: u8,
+    /// Type of the file as a `DInodeType`, or 0 if unknown. Recorded only if the file system
+    /// has FS_DIRTYPES.
+    pub typ: u8,
+}
+
+impl DirentHeader {
+    /// Decodes the header at the beginning of `bytes`.
+    pub fn decode(bytes: &[u8]) -> Self {
+        Self {
+            inum: read_u16(bytes, 0),
+            reclen: read_u16(bytes, 2),
+            namelen: bytes[4],
+            typ: bytes[5],
+        }
+    }
+
+    /// Encodes the header into the beginning of `bytes`.
+    pub fn encode(&self, bytes: &mut [u8]) {
+        write_u16(bytes, 0, self.inum);
+        write_u16(bytes, 2, self.reclen);
+        bytes[4] = self.namelen;
+        bytes[5] = self.typ;
+    }
+}
+
+/// Returns the smallest record length of an entry whose name is `namelen` bytes long.
+pub const fn dirent_reclen(namelen: usize) -> u32 {
+    ((DIRENT_HEADER_SIZE + namelen + 3) & !3) as u32
+}
+
+fn read_u16(bytes: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(bytes[off..off + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(bytes[off..off + 4].try_into().unwrap())
+}
+
+fn write_u16(bytes: &mut [u8], off: usize, value: u16) {
+    bytes[off..off + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], off: usize, value: u32) {
+    bytes[off..off + 4].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,42 @@
+#include "kernel/types.h"
+#include "kernel/sched.h"
+#include "user/user.h"
+
+char *classes[] = {
+  [SCHED_INTERACTIVE] "interactive",
+  [SCHED_BATCH]       "batch",
+  [SCHED_IDLE]        "idle",
+  [SCHED_DEADLINE]    "deadline",
+};
+
+// Run a command in a scheduling class, or print the class of a process.
+int
+main(int argc, char *argv[])
+{
+  int class;
+
+  if(argc == 3 && strcmp(argv[1], "-p") == 0){
+    if((class = getsched(atoi(argv[2]))) < 0){
+      fprintf(2, "chsched: no process %s\n", argv[2]);
+      exit(1);
+    }
+    printf("%s\n", classes[class]);
+    exit(0);
+  }
+
+  if(argc < 3){
+    fprintf(2, "Usage: chsched interactive|batch|idle cmd [args...]\n");
+    fprintf(2, "       chsched -p pid\n");
+    exit(1);
+  }
+  for(class = 0; class <= SCHED_IDLE; class++)
+    if(strcmp(argv[1], classes[class]) == 0)
+      break;
+  if(class > SCHED_IDLE || setsched(0, class) < 0){
+    fprintf(2, "chsched: bad class %s\n", argv[1]);
+    exit(1);
+  }
+  exec(argv[2], argv + 2);
+  fprintf(2, "chsched: exec %s failed\n", argv[2]);
+  exit(1);
+}
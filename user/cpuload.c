@@ -0,0 +1,41 @@
+#include "kernel/param.h"
+#include "kernel/types.h"
+#include "kernel/cpuload.h"
+#include "user/user.h"
+
+// Print the load of each CPU, or run a command throttled to a percentage of the time.
+int
+main(int argc, char *argv[])
+{
+  int i, n, percent;
+  struct cpuload load[NCPU];
+
+  if(argc >= 4 && strcmp(argv[1], "-t") == 0){
+    percent = atoi(argv[2]);
+    if(throttle(0, percent) < 0){
+      fprintf(2, "cpuload: bad percentage %s\n", argv[2]);
+      exit(1);
+    }
+    exec(argv[3], argv + 3);
+    fprintf(2, "cpuload: exec %s failed\n", argv[3]);
+    exit(1);
+  }
+
+  if(argc != 1){
+    fprintf(2, "Usage: cpuload\n");
+    fprintf(2, "       cpuload -t percent cmd [args...]\n");
+    exit(1);
+  }
+  if((n = cpuload(load, NCPU)) < 0){
+    fprintf(2, "cpuload: cannot read CPU load\n");
+    exit(1);
+  }
+  printf("cpu  runnable  busy%%\n");
+  for(i = 0; i < n; i++){
+    if(load[i].window_cycles == 0)
+      continue;
+    printf("%3d %9ld %6ld\n", i, load[i].nrunnable,
+           load[i].busy_cycles * 100 / load[i].window_cycles);
+  }
+  exit(0);
+}
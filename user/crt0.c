@@ -0,0 +1,32 @@
+#include "kernel/types.h"
+#include "kernel/auxv.h"
+#include "user/user.h"
+
+// The auxiliary vector that exec() passed to the program.
+static uint64 *auxv;
+
+// Entry point of every program. exec() passes argc, argv, and the
+// auxiliary vector, which must be set up before main() runs.
+void
+_start(int argc, char *argv[], uint64 *av)
+{
+  extern int main(int, char*[]);
+
+  auxv = av;
+  stackguard_init(av);
+  exit(main(argc, argv));
+}
+
+// Return the value of the auxiliary vector entry of the given type,
+// or 0 if there is none.
+uint64
+getauxval(uint64 type)
+{
+  uint64 *a;
+
+  for(a = auxv; a[0] != AT_NULL; a += 2){
+    if(a[0] == type)
+      return a[1];
+  }
+  return 0;
+}
@@ -0,0 +1,56 @@
+#include "kernel/types.h"
+#include "kernel/date.h"
+#include "user/user.h"
+
+// Convert seconds since the Unix epoch into a date in UTC.
+static void
+todate(uint64 secs, struct rtcdate *d)
+{
+  uint64 days, era, doe, yoe, doy, mp;
+
+  d->second = secs % 60;
+  d->minute = secs / 60 % 60;
+  d->hour = secs / 3600 % 24;
+
+  // Days since 0000-03-01, in eras of 400 years.
+  days = secs / 86400 + 719468;
+  era = days / 146097;
+  doe = days - era * 146097;
+  yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  mp = (5 * doy + 2) / 153;
+  d->day = doy - (153 * mp + 2) / 5 + 1;
+  d->month = mp < 10 ? mp + 3 : mp - 9;
+  d->year = yoe + era * 400 + (d->month <= 2);
+}
+
+// Print the current time, or set it to the given seconds since the Unix epoch.
+int
+main(int argc, char *argv[])
+{
+  struct timeval tv;
+  struct rtcdate d;
+
+  if(argc == 3 && strcmp(argv[1], "-s") == 0){
+    tv.tv_sec = atoi(argv[2]);
+    tv.tv_usec = 0;
+    if(settimeofday(&tv, 0) < 0){
+      fprintf(2, "date: cannot set the time\n");
+      exit(1);
+    }
+    exit(0);
+  }
+  if(argc != 1){
+    fprintf(2, "Usage: date [-s seconds]\n");
+    exit(1);
+  }
+
+  if(gettimeofday(&tv, 0) < 0){
+    fprintf(2, "date: cannot read the time\n");
+    exit(1);
+  }
+  todate(tv.tv_sec, &d);
+  printf("%d-%02d-%02d %02d:%02d:%02d UTC\n", d.year, d.month, d.day,
+         d.hour, d.minute, d.second);
+  exit(0);
+}
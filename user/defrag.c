@@ -0,0 +1,31 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/fcntl.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  int i, fd, n;
+
+  if(argc < 2){
+    fprintf(2, "Usage: defrag files...\n");
+    exit(1);
+  }
+
+  for(i = 1; i < argc; i++){
+    if((fd = open(argv[i], O_RDWR)) < 0){
+      fprintf(2, "defrag: cannot open %s\n", argv[i]);
+      exit(1);
+    }
+    if((n = defrag(fd)) < 0){
+      fprintf(2, "defrag: %s failed to defragment\n", argv[i]);
+      close(fd);
+      exit(1);
+    }
+    printf("%s: %d blocks relocated\n", argv[i], n);
+    close(fd);
+  }
+
+  exit(0);
+}
@@ -1,5 +1,6 @@
 // init: The initial user-level program
 
+#include "kernel/param.h"
 #include "kernel/types.h"
 #include "kernel/stat.h"
 #include "kernel/spinlock.h"
@@ -15,20 +16,76 @@ char *argv[] = { "usertests", 0 };
 char *argv[] = { "sh", 0 };
 #endif
 
+char *shargv[] = { "sh", 0 };
+
+// Start a shell on the virtual console n > 0.
+int
+startvc(int n)
+{
+  char path[] = "/dev/ttyN";
+  int pid;
+
+  pid = fork();
+  if(pid != 0)
+    return pid;
+  close(0);
+  close(1);
+  close(2);
+  path[8] = '0' + n;
+  if(open(path, O_RDWR) < 0)
+    exit(1);
+  dup(0);  // stdout
+  dup(0);  // stderr
+  exec("sh", shargv);
+  exit(1);
+}
+
 int
 main(void)
 {
   // https://github.com/kaist-cp/rv6/commit/d12c1db8d9d7a7e5632e51ae712123d868087fe4
   // Add xstate to immediately run usertests and poweroff.
-  int pid, wpid, xstate;
+  int i, pid, wpid, xstate;
+  int vcpids[NVCONSOLE];
+  char path[] = "/dev/ttyN";
+  char vport[] = "/dev/vportN";
 
-  if(open("console", O_RDWR) < 0){
-    mknod("console", CONSOLE, 0);
-    open("console", O_RDWR);
+  if(open("/dev/console", O_RDWR) < 0){
+    mkdir("/dev");
+    mknod("/dev/console", CONSOLE, 0);
+    mknod("/dev/tty", TTY, 0);
+    open("/dev/console", O_RDWR);
+  }
+  if(access("/dev/null", F_OK) < 0){
+    mknod("/dev/null", NULLDEV, 0);
+    mknod("/dev/zero", ZERODEV, 0);
+    mknod("/dev/full", FULLDEV, 0);
+  }
+  if(access("/dev/trace", F_OK) < 0)
+    mknod("/dev/trace", TRACEDEV, 0);
+  for(i = 1; i < NVCONSOLE; i++){
+    path[8] = '0' + i;
+    if(access(path, F_OK) < 0)
+      mknod(path, VCONSOLE + i - 1, 0);
+  }
+  for(i = 0; i < NVPORT; i++){
+    vport[10] = '0' + i;
+    if(access(vport, F_OK) < 0)
+      mknod(vport, VPORT + i, 0);
   }
   dup(0);  // stdout
   dup(0);  // stderr
 
+  // The other virtual consoles, switched to with control-t and a number, run shells of their
+  // own, e.g. to watch a benchmark running on the console.
+  for(i = 1; i < NVCONSOLE; i++){
+#ifdef USERTEST
+    vcpids[i] = 0;
+#else
+    vcpids[i] = startvc(i);
+#endif
+  }
+
   for(;;){
     printf("init: starting %s\n", argv[0]);
     pid = fork();
@@ -53,7 +110,11 @@ main(void)
         printf("init: wait returned an error\n");
         exit(1);
       } else {
-        // it was a parentless process; do nothing.
+        // restart the shell of a virtual console,
+        // or it was a parentless process; do nothing.
+        for(i = 1; i < NVCONSOLE; i++)
+          if(vcpids[i] > 0 && wpid == vcpids[i])
+            vcpids[i] = startvc(i);
       }
     }
 #ifdef USERTEST
@@ -0,0 +1,43 @@
+#include "kernel/types.h"
+#include "kernel/iosched.h"
+#include "user/user.h"
+
+#define NPOLICY 3
+
+static char *policies[NPOLICY] = {
+  [IOSCHED_NOOP]     "noop",
+  [IOSCHED_ELEVATOR] "elevator",
+  [IOSCHED_FAIR]     "fair",
+};
+
+// Print the disk I/O scheduling policy and the disk requests sent,
+// or set the policy.
+int
+main(int argc, char *argv[])
+{
+  int i, policy;
+  struct ioqstat st;
+
+  if(argc > 2){
+    fprintf(2, "Usage: iosched [noop|elevator|fair]\n");
+    exit(1);
+  }
+  if(argc == 2){
+    for(i = 0; i < NPOLICY; i++)
+      if(strcmp(argv[1], policies[i]) == 0)
+        break;
+    if(i == NPOLICY || iosched(IOSCHED_SETPOLICY, i, 0) < 0){
+      fprintf(2, "iosched: cannot set policy %s\n", argv[1]);
+      exit(1);
+    }
+    exit(0);
+  }
+
+  policy = iosched(IOSCHED_GETPOLICY, 0, 0);
+  if(policy < 0 || policy >= NPOLICY || iosched(IOSCHED_QSTAT, 0, &st) < 0){
+    fprintf(2, "iosched: iosched failed\n");
+    exit(1);
+  }
+  printf("policy %s\nrequests %ld\nblocks %ld\n", policies[policy], st.requests, st.blocks);
+  exit(0);
+}
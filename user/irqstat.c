@@ -0,0 +1,33 @@
+#include "kernel/param.h"
+#include "kernel/types.h"
+#include "kernel/irqstat.h"
+#include "user/user.h"
+
+#define NSTAT 16
+
+int
+main(int argc, char *argv[])
+{
+  int i, j, n;
+  uint64 total;
+  struct irqstat stats[NSTAT];
+
+  if((n = irqstat(stats, NSTAT)) < 0){
+    fprintf(2, "irqstat: cannot read interrupt statistics\n");
+    exit(1);
+  }
+
+  printf("irq     count    cycles/irq    max cycles  per cpu\n");
+  for(i = 0; i < n; i++){
+    total = 0;
+    for(j = 0; j < NCPU; j++)
+      total += stats[i].count[j];
+    printf("%3ld %9ld %13ld %13ld ", stats[i].irq, total,
+           stats[i].cycles / total, stats[i].max_cycles);
+    for(j = 0; j < NCPU; j++)
+      if(stats[i].count[j] > 0)
+        printf(" %d:%ld", j, stats[i].count[j]);
+    printf("\n");
+  }
+  exit(0);
+}
@@ -0,0 +1,47 @@
+#include "kernel/types.h"
+#include "kernel/kconfig.h"
+#include "user/user.h"
+
+// Print the boot configuration, or store a new one.
+int
+main(int argc, char *argv[])
+{
+  struct kconfig c;
+
+  if(argc == 6 || argc == 10 || argc == 11){
+    c.sched_class = atoi(argv[1]);
+    c.tick_us = atoi(argv[2]);
+    c.idle_policy = atoi(argv[3]);
+    c.oom_killer = atoi(argv[4]);
+    c.read_only = atoi(argv[5]);
+    c.nfile = c.ninode = c.nbuf = c.nproc = c.log_color = 0;
+    if(argc >= 10){
+      c.nfile = atoi(argv[6]);
+      c.ninode = atoi(argv[7]);
+      c.nbuf = atoi(argv[8]);
+      c.nproc = atoi(argv[9]);
+    }
+    if(argc == 11)
+      c.log_color = atoi(argv[10]);
+    if(setconfig(&c) < 0){
+      fprintf(2, "kconfig: bad configuration\n");
+      exit(1);
+    }
+    exit(0);
+  }
+  if(argc != 1){
+    fprintf(2, "Usage: kconfig [sched_class tick_us idle_policy oom_killer read_only "
+               "[nfile ninode nbuf nproc [log_color]]]\n");
+    exit(1);
+  }
+
+  if(getconfig(&c) < 0){
+    fprintf(2, "kconfig: cannot read the configuration\n");
+    exit(1);
+  }
+  printf("sched_class %d\ntick_us %d\nidle_policy %d\noom_killer %d\nread_only %d\n",
+         c.sched_class, c.tick_us, c.idle_policy, c.oom_killer, c.read_only);
+  printf("nfile %d\nninode %d\nnbuf %d\nnproc %d\n", c.nfile, c.ninode, c.nbuf, c.nproc);
+  printf("log_color %d\n", c.log_color);
+  exit(0);
+}
@@ -0,0 +1,22 @@
+// A minimal program interpreter.
+// exec() loads it above a program that names it in PT_INTERP, and starts
+// it with the arguments and the auxiliary vector of the program. There are
+// no shared libraries to link yet, so it only starts the program at
+// AT_ENTRY. As it runs at AT_BASE rather than where it is linked, it uses
+// neither global data nor the library.
+
+#include "kernel/types.h"
+#include "kernel/auxv.h"
+
+void
+_start(int argc, char *argv[], uint64 *auxv)
+{
+  void (*entry)(int, char*[], uint64*) = 0;
+  uint64 *a;
+
+  for(a = auxv; a[0] != AT_NULL; a += 2){
+    if(a[0] == AT_ENTRY)
+      entry = (void (*)(int, char*[], uint64*))a[1];
+  }
+  entry(argc, argv, auxv);
+}
@@ -0,0 +1,21 @@
+#include "kernel/types.h"
+#include "kernel/abi.h"
+#include "user/user.h"
+
+// Run a statically linked Linux program.
+int
+main(int argc, char *argv[])
+{
+  if(argc < 2){
+    fprintf(2, "Usage: linux prog [args...]\n");
+    exit(1);
+  }
+  if(setabi(ABI_LINUX) < 0){
+    fprintf(2, "linux: setabi failed\n");
+    exit(1);
+  }
+  exec(argv[1], argv + 1);
+  setabi(ABI_RV6);
+  fprintf(2, "linux: exec %s failed\n", argv[1]);
+  exit(1);
+}
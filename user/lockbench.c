@@ -0,0 +1,108 @@
+#include "kernel/param.h"
+#include "kernel/types.h"
+#include "kernel/cpuload.h"
+#include "kernel/lockstat.h"
+#include "user/user.h"
+
+#define MS 1000
+
+static char *kinds[] = { "race", "ticket", "mcs" };
+
+// A process on each CPU acquires the same spin lock over and over
+// for the given time, starting together. Reports how many times
+// each got the lock and how long it waited, the throughput, and the
+// fairness among the CPUs, so that the implementations of spin locks
+// (`make SPINLOCK=ticket` or `make SPINLOCK=mcs`) can be compared.
+// Then, reports the contention of the mutexes of the kernel.
+int
+main(int argc, char *argv[])
+{
+  int i, n, ms, ncpu, pid;
+  int cpus[NCPU], start[2], done[2];
+  char c;
+  struct cpuload load[NCPU];
+  struct spinbench r[NCPU];
+  struct lockstat stat[2];
+  uint64 total, sumsq, min, max;
+
+  ms = argc > 1 ? atoi(argv[1]) : MS;
+  if(argc > 2 || ms <= 0){
+    fprintf(2, "Usage: lockbench [ms]\n");
+    exit(1);
+  }
+  if((n = cpuload(load, NCPU)) < 0){
+    fprintf(2, "lockbench: cannot read CPU load\n");
+    exit(1);
+  }
+  ncpu = 0;
+  for(i = 0; i < n; i++)
+    if(load[i].window_cycles != 0)
+      cpus[ncpu++] = i;
+  if(pipe(start) < 0 || pipe(done) < 0){
+    fprintf(2, "lockbench: pipe failed\n");
+    exit(1);
+  }
+
+  for(i = 0; i < ncpu; i++){
+    pid = fork();
+    if(pid < 0){
+      fprintf(2, "lockbench: fork failed\n");
+      exit(1);
+    }
+    if(pid == 0){
+      close(start[1]);
+      close(done[0]);
+      if(migrate(0, cpus[i]) < 0)
+        exit(1);
+      // Wait until every process is ready.
+      read(start[0], &c, 1);
+      if(spinbench(ms, &r[i]) < 0)
+        exit(1);
+      write(done[1], &r[i], sizeof(r[i]));
+      exit(0);
+    }
+  }
+  close(start[0]);
+  close(done[1]);
+  sleep(1);
+  close(start[1]);
+  for(i = 0; i < ncpu; i++){
+    if(read(done[0], &r[i], sizeof(r[i])) != sizeof(r[i])){
+      fprintf(2, "lockbench: spinbench failed\n");
+      exit(1);
+    }
+  }
+  for(i = 0; i < ncpu; i++)
+    wait(0);
+
+  printf("spin locks: %s, %d CPUs, %d ms\n", kinds[r[0].kind], ncpu, ms);
+  printf("     acquired  avg wait  max wait\n");
+  total = sumsq = max = 0;
+  min = r[0].acquired;
+  for(i = 0; i < ncpu; i++){
+    printf("%9ld %9ld %9ld\n", r[i].acquired,
+           r[i].acquired ? r[i].wait_cycles / r[i].acquired : 0, r[i].max_wait);
+    total += r[i].acquired;
+    sumsq += r[i].acquired * r[i].acquired;
+    if(r[i].acquired < min)
+      min = r[i].acquired;
+    if(r[i].acquired > max)
+      max = r[i].acquired;
+  }
+  printf("throughput: %ld acquisitions per ms\n", total / ms);
+  // Jain's fairness index: 100 if every CPU got the lock as often.
+  if(sumsq > 0)
+    printf("fairness: %ld (min %ld, max %ld)\n",
+           total * total * 100 / (ncpu * sumsq), min, max);
+
+  n = lockstat(stat, 2);
+  if(n < 0){
+    fprintf(2, "lockbench: lockstat failed\n");
+    exit(1);
+  }
+  printf("mutex      acquired contended      spun     slept\n");
+  for(i = 0; i < n && i < 2; i++)
+    printf("%-8s %10ld %9ld %9ld %9ld\n", stat[i].name, stat[i].acquired,
+           stat[i].contended, stat[i].spun, stat[i].slept);
+  exit(0);
+}
@@ -22,12 +22,17 @@ fmtname(char *path)
   return buf;
 }
 
+// With -F, list only names, marking directories with a trailing '/'.
+// The type of an entry comes from the directory itself, so entries
+// are stat-ed only if their directory does not record their types.
+int names;
+
 void
 ls(char *path)
 {
-  char buf[512], *p;
-  int fd;
-  struct dirent de;
+  char buf[512], dents[512], *p;
+  int fd, n, off;
+  struct dirent *de;
   struct stat st;
 
   if((fd = open(path, 0)) < 0){
@@ -43,27 +48,38 @@ ls(char *path)
 
   switch(st.type){
   case T_FILE:
-    printf("%s %d %d %l\n", fmtname(path), st.type, st.ino, st.size);
+    if(names)
+      printf("%s\n", path);
+    else
+      printf("%s %d %d %l\n", fmtname(path), st.type, st.ino, st.size);
     break;
 
   case T_DIR:
-    if(strlen(path) + 1 + DIRSIZ + 1 > sizeof buf){
+    if(strlen(path) + 1 + MAXNAMELEN + 1 > sizeof buf){
       printf("ls: path too long\n");
       break;
     }
     strcpy(buf, path);
     p = buf+strlen(buf);
     *p++ = '/';
-    while(read(fd, &de, sizeof(de)) == sizeof(de)){
-      if(de.inum == 0)
-        continue;
-      memmove(p, de.name, DIRSIZ);
-      p[DIRSIZ] = 0;
-      if(stat(buf, &st) < 0){
-        printf("ls: cannot stat %s\n", buf);
-        continue;
+    while((n = getdents(fd, dents, sizeof(dents))) > 0){
+      for(off = 0; off < n; off += de->reclen){
+        de = (struct dirent*)(dents + off);
+        memmove(p, de->name, de->namelen);
+        p[de->namelen] = 0;
+        if(names && de->type != 0){
+          printf("%s%s\n", p, de->type == T_DIR ? "/" : "");
+          continue;
+        }
+        if(stat(buf, &st) < 0){
+          printf("ls: cannot stat %s\n", buf);
+          continue;
+        }
+        if(names)
+          printf("%s%s\n", p, st.type == T_DIR ? "/" : "");
+        else
+          printf("%s %d %d %d\n", fmtname(buf), st.type, st.ino, st.size);
       }
-      printf("%s %d %d %d\n", fmtname(buf), st.type, st.ino, st.size);
     }
     break;
   }
@@ -73,13 +89,17 @@ ls(char *path)
 int
 main(int argc, char *argv[])
 {
-  int i;
+  int i = 1;
 
-  if(argc < 2){
+  if(argc > 1 && strcmp(argv[1], "-F") == 0){
+    names = 1;
+    i++;
+  }
+  if(i == argc){
     ls(".");
     exit(0);
   }
-  for(i=1; i<argc; i++)
+  for(; i<argc; i++)
     ls(argv[i]);
   exit(0);
 }
@@ -0,0 +1,75 @@
+#include "kernel/param.h"
+#include "kernel/types.h"
+#include "kernel/fdinfo.h"
+#include "kernel/meminfo.h"
+#include "user/user.h"
+
+static char *types[] = {
+[FD_PIPE]    "pipe",
+[FD_INODE]   "inode",
+[FD_DEVICE]  "device",
+[FD_WATCH]   "watch",
+[FD_SOCKET]  "socket",
+[FD_EPOLL]   "epoll",
+[FD_EVENTFD] "eventfd",
+[FD_TIMERFD] "timerfd",
+[FD_MQUEUE]  "mqueue",
+[FD_SEM]     "sem",
+};
+
+// Print the file descriptors of the process pid.
+static int
+lsof(int pid)
+{
+  int i, n;
+  struct fdinfo fds[NOFILE];
+
+  if((n = fdinfo(pid, fds, NOFILE)) < 0)
+    return -1;
+  for(i = 0; i < n; i++){
+    printf("%3d %2d %-7s %c%c%c", pid, fds[i].fd,
+           fds[i].type <= FD_SEM ? types[fds[i].type] : "?",
+           fds[i].flags & FDINFO_READ ? 'r' : '-',
+           fds[i].flags & FDINFO_WRITE ? 'w' : '-',
+           fds[i].flags & FDINFO_CLOEXEC ? 'e' : '-');
+    if(fds[i].type == FD_INODE || fds[i].type == FD_DEVICE)
+      printf(" %d %d", fds[i].dev, fds[i].ino);
+    else if(fds[i].type == FD_PIPE || fds[i].type == FD_SOCKET)
+      printf(" - %d", fds[i].ino);
+    printf("\n");
+  }
+  return 0;
+}
+
+int
+main(int argc, char *argv[])
+{
+  int i, n, pid;
+  struct meminfo info;
+  struct procmem procs[NPROC];
+
+  if(argc > 2){
+    fprintf(2, "Usage: lsof [pid]\n");
+    exit(1);
+  }
+
+  printf("pid fd type    mode dev ino\n");
+  if(argc == 2){
+    pid = atoi(argv[1]);
+    if(lsof(pid) < 0){
+      fprintf(2, "lsof: cannot inspect process %d\n", pid);
+      exit(1);
+    }
+    exit(0);
+  }
+
+  if((n = meminfo(&info, procs, NPROC)) < 0){
+    fprintf(2, "lsof: cannot read the processes\n");
+    exit(1);
+  }
+  // A process that exits meanwhile is skipped.
+  for(i = 0; i < n; i++)
+    lsof(procs[i].pid);
+  printf("files %ld/%ld\n", info.files.used, info.files.capacity);
+  exit(0);
+}
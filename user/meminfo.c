@@ -0,0 +1,29 @@
+#include "kernel/param.h"
+#include "kernel/types.h"
+#include "kernel/meminfo.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  int i, n;
+  struct meminfo info;
+  struct procmem procs[NPROC];
+
+  if((n = meminfo(&info, procs, NPROC)) < 0){
+    fprintf(2, "meminfo: cannot read memory statistics\n");
+    exit(1);
+  }
+
+  printf("pages: %ld total, %ld free, %ld used\n", info.total_pages,
+         info.free_pages, info.total_pages - info.free_pages);
+  printf("caches: buffer cache %ld pages, page cache %ld pages\n",
+         info.bcache_pages, info.page_cache_pages);
+  printf("files %ld/%ld, inodes %ld/%ld, bufs %ld/%ld\n",
+         info.files.used, info.files.capacity, info.inodes.used,
+         info.inodes.capacity, info.bufs.used, info.bufs.capacity);
+  printf("pid    rss files\n");
+  for(i = 0; i < n; i++)
+    printf("%3d %6ld %5d\n", procs[i].pid, procs[i].rss, procs[i].nfiles);
+  exit(0);
+}
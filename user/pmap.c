@@ -0,0 +1,40 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/pmap.h"
+#include "user/user.h"
+
+#define NREGION 16
+
+int
+main(int argc, char *argv[])
+{
+  int i, n, pid;
+  struct pmapinfo info;
+  struct pmapregion regions[NREGION];
+
+  if(argc > 2){
+    fprintf(2, "Usage: pmap [pid]\n");
+    exit(1);
+  }
+
+  pid = argc == 2 ? atoi(argv[1]) : getpid();
+  if((n = pmap(pid, &info, regions, NREGION)) < 0){
+    fprintf(2, "pmap: cannot inspect process %d\n", pid);
+    exit(1);
+  }
+
+  printf("%d: size %ld, rss %ld pages, page-table pages %ld/%ld/%ld\n", pid,
+         info.size, info.rss, info.ptpages[2], info.ptpages[1], info.ptpages[0]);
+  for(i = 0; i < n; i++){
+    printf("%016lx-%016lx %c%c%c%c %ld pages\n", regions[i].start, regions[i].end,
+           regions[i].perm & PMAP_R ? 'r' : '-',
+           regions[i].perm & PMAP_W ? 'w' : '-',
+           regions[i].perm & PMAP_X ? 'x' : '-',
+           regions[i].perm & PMAP_U ? 'u' : '-',
+           (regions[i].end - regions[i].start) / 4096);
+  }
+  if(info.nregion > n)
+    printf("... %ld more regions\n", info.nregion - n);
+
+  exit(0);
+}
@@ -0,0 +1,40 @@
+#include "kernel/types.h"
+#include "kernel/power.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  struct powerstat st;
+
+  if(argc == 3 && strcmp(argv[1], "idle") == 0){
+    if(strcmp(argv[2], "poll") == 0)
+      powerctl(POWER_SETIDLE, IDLE_POLL);
+    else if(strcmp(argv[2], "suspend") == 0)
+      powerctl(POWER_SETIDLE, IDLE_SUSPEND);
+    else
+      goto usage;
+    exit(0);
+  }
+  if(argc == 3 && strcmp(argv[1], "perf") == 0){
+    if(powerctl(POWER_SETPERF, atoi(argv[2])) < 0){
+      fprintf(2, "power: performance state %s is not supported\n", argv[2]);
+      exit(1);
+    }
+    exit(0);
+  }
+  if(argc != 1)
+    goto usage;
+
+  if(powerctl(POWER_STAT, (uint64)&st) < 0){
+    fprintf(2, "power: cannot read power statistics\n");
+    exit(1);
+  }
+  printf("idle: %s, %ld suspends, %ld cycles suspended\n",
+         st.policy == IDLE_SUSPEND ? "suspend" : "poll", st.nsuspend, st.suspend_cycles);
+  exit(0);
+
+usage:
+  fprintf(2, "Usage: power [idle poll|suspend] [perf level]\n");
+  exit(1);
+}
@@ -0,0 +1,45 @@
+#include "kernel/param.h"
+#include "kernel/types.h"
+#include "kernel/meminfo.h"
+#include "kernel/prctl.h"
+#include "user/user.h"
+
+// Print the pid, the title, and the exec arguments of the process pid.
+static void
+ps(int pid)
+{
+  int i, n;
+  char title[MAXPROCTITLE + 1];
+  char args[MAXPROCARGS];
+
+  // A process that exits meanwhile is skipped.
+  if(prctl(PR_GET_NAME, pid, title, sizeof(title)) < 0 ||
+     (n = prctl(PR_GET_ARGS, pid, args, sizeof(args))) < 0)
+    return;
+  printf("%3d %-16s", pid, title);
+  for(i = 0; i < n; i += strlen(args + i) + 1)
+    printf(" %s", args + i);
+  printf("\n");
+}
+
+int
+main(int argc, char *argv[])
+{
+  int i, n;
+  struct meminfo info;
+  struct procmem procs[NPROC];
+
+  if(argc > 1){
+    fprintf(2, "Usage: ps\n");
+    exit(1);
+  }
+
+  if((n = meminfo(&info, procs, NPROC)) < 0){
+    fprintf(2, "ps: cannot read the processes\n");
+    exit(1);
+  }
+  printf("pid title            args\n");
+  for(i = 0; i < n; i++)
+    ps(procs[i].pid);
+  exit(0);
+}
@@ -0,0 +1,8 @@
+#include "kernel/types.h"
+#include "user/user.h"
+
+int
+main(void)
+{
+  reboot();
+}
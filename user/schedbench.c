@@ -0,0 +1,66 @@
+#include "kernel/types.h"
+#include "kernel/schedstat.h"
+#include "user/user.h"
+
+#define NYIELD 10000
+
+// Two processes yield to each other, and report
+// the cost of a context switch. Then, a process moves
+// back and forth between two CPUs, and reports the
+// cost of a migration.
+int
+main(int argc, char *argv[])
+{
+  int i, n, pid;
+  unsigned long start, end;
+  struct schedstat before, after;
+  uint64 nswitch;
+
+  n = argc > 1 ? atoi(argv[1]) : NYIELD;
+  if(n <= 0){
+    fprintf(2, "Usage: schedbench [nyield]\n");
+    exit(1);
+  }
+
+  if(schedstat(&before) < 0){
+    fprintf(2, "schedbench: schedstat failed\n");
+    exit(1);
+  }
+  clock(&start);
+
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "schedbench: fork failed\n");
+    exit(1);
+  }
+  for(i = 0; i < n; i++)
+    yield();
+  if(pid == 0)
+    exit(0);
+  wait(0);
+
+  clock(&end);
+  schedstat(&after);
+
+  nswitch = after.nswitch - before.nswitch;
+  printf("yield: %d calls in %ld cycles, %ld cycles per call\n",
+         2 * n, end - start, (end - start) / (2 * n));
+  if(nswitch > 0)
+    printf("switch: %ld switches, %ld cycles per switch\n",
+           nswitch, (after.switch_cycles - before.switch_cycles) / nswitch);
+
+  if(migrate(0, 0) < 0 || migrate(0, 1) < 0){
+    printf("migrate: fewer than 2 CPUs\n");
+    exit(0);
+  }
+  before = after;
+  clock(&start);
+  for(i = 0; i < n; i++)
+    migrate(0, i % 2);
+  clock(&end);
+  schedstat(&after);
+  migrate(0, -1);
+  printf("migrate: %d calls in %ld cycles, %ld cycles per call, %ld migrations\n",
+         n, end - start, (end - start) / n, after.nmigrate - before.nmigrate);
+  exit(0);
+}
@@ -0,0 +1,26 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  if(argc == 1){
+    if(snapshot() < 0){
+      fprintf(2, "snapshot: failed to take a snapshot\n");
+      exit(1);
+    }
+    exit(0);
+  }
+
+  if(argc == 2 && strcmp(argv[1], "-d") == 0){
+    if(snapdrop() < 0){
+      fprintf(2, "snapshot: failed to drop the snapshot\n");
+      exit(1);
+    }
+    exit(0);
+  }
+
+  fprintf(2, "Usage: snapshot [-d]\n");
+  exit(1);
+}
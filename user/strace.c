@@ -0,0 +1,53 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/ptrace.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  int ev, pid, entered;
+  struct ptraceregs regs;
+
+  if(argc < 2){
+    fprintf(2, "Usage: strace program [args...]\n");
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "strace: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    if(ptrace(PTRACE_TRACEME, 0, 0, 0) < 0){
+      fprintf(2, "strace: cannot be traced\n");
+      exit(1);
+    }
+    exec(argv[1], argv + 1);
+    fprintf(2, "strace: exec %s failed\n", argv[1]);
+    exit(1);
+  }
+
+  // The first stop at syscall exit is for PTRACE_TRACEME, which has no entry to match.
+  entered = 0;
+  while((ev = ptrace(PTRACE_WAIT, pid, 0, 0)) >= 0){
+    if(ev != PTRACE_EV_START && ptrace(PTRACE_GETREGS, pid, 0, &regs) == 0){
+      if(ev == PTRACE_EV_SYSENTER){
+        fprintf(2, "[%d] syscall %ld(0x%lx, 0x%lx, 0x%lx)", pid, regs.args[7],
+                regs.args[0], regs.args[1], regs.args[2]);
+        entered = 1;
+      } else if(entered){
+        fprintf(2, " = %ld\n", regs.args[0]);
+        entered = 0;
+      }
+    }
+    if(ptrace(PTRACE_SYSCALL, pid, 0, 0) < 0)
+      break;
+  }
+  if(entered)
+    fprintf(2, "\n");
+  wait(0);
+
+  exit(0);
+}
@@ -0,0 +1,65 @@
+#include "kernel/param.h"
+#include "kernel/types.h"
+#include "kernel/perf.h"
+#include "user/user.h"
+
+// Returns the upper bound in cycles of the bucket that holds the fraction
+// num/den of the n calls in histogram h.
+static uint64
+percentile(uint64 *h, uint64 n, int num, int den)
+{
+  uint64 seen, want;
+  int b;
+
+  want = (n * num + den - 1) / den;
+  seen = 0;
+  for(b = 0; b < NLATBUCKET - 1; b++){
+    seen += h[b];
+    if(seen >= want)
+      break;
+  }
+  return 2ULL << b;
+}
+
+// Run a command, and print the latency histogram of each syscall it made.
+int
+main(int argc, char *argv[])
+{
+  int i, b, pid;
+  uint64 h[NLATBUCKET], n;
+
+  if(argc < 2){
+    fprintf(2, "Usage: syslat command [args...]\n");
+    exit(1);
+  }
+  if(perf(PERF_SETLATENCY, 1, 0) < 0){
+    fprintf(2, "syslat: perf failed\n");
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "syslat: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    exec(argv[1], argv + 1);
+    fprintf(2, "syslat: exec %s failed\n", argv[1]);
+    exit(1);
+  }
+  wait(0);
+  perf(PERF_SETLATENCY, 0, 0);
+
+  printf("syscall    calls      p50 <      p99 <      max <  (cycles)\n");
+  for(i = 0; i < NSYSCALL; i++){
+    if(perf(PERF_GETLATENCY, i, h) < 0)
+      break;
+    n = 0;
+    for(b = 0; b < NLATBUCKET; b++)
+      n += h[b];
+    if(n == 0)
+      continue;
+    printf("%7d %8ld %10ld %10ld %10ld\n", i, n,
+           percentile(h, n, 1, 2), percentile(h, n, 99, 100), percentile(h, n, 1, 1));
+  }
+  exit(0);
+}
@@ -1,6 +1,8 @@
 #include "kernel/types.h"
 #include "kernel/stat.h"
 #include "kernel/fcntl.h"
+#include "kernel/auxv.h"
+#include "kernel/socket.h"
 #include "user/user.h"
 
 #define MICROSECS_PER_TICK 100000
@@ -72,20 +74,6 @@ gets(char *buf, int max)
   return buf;
 }
 
-int
-stat(const char *n, struct stat *st)
-{
-  int fd;
-  int r;
-
-  fd = open(n, O_RDONLY);
-  if(fd < 0)
-    return -1;
-  r = fstat(fd, st);
-  close(fd);
-  return r;
-}
-
 int
 atoi(const char *s)
 {
@@ -137,6 +125,30 @@ memcpy(void *dst, const void *src, uint n)
   return memmove(dst, src, n);
 }
 
+// The canary of the stack protector, checked by functions compiled
+// with it before they return.
+uint64 __stack_chk_guard = 0x595e9fbd94fda766;
+
+// Take the canary from the random bytes that exec() passes in
+// the auxiliary vector. _start() calls it before main(), so it must
+// not be protected itself.
+void
+stackguard_init(uint64 *auxv)
+{
+  for(; auxv[0] != AT_NULL; auxv += 2){
+    if(auxv[0] == AT_RANDOM)
+      memmove(&__stack_chk_guard, (void*)auxv[1], sizeof(__stack_chk_guard));
+  }
+}
+
+// Called by a protected function that finds its canary overwritten.
+void
+__stack_chk_fail(void)
+{
+  fprintf(2, "*** stack smashing detected ***\n");
+  exit(1);
+}
+
 void
 bzero (void *to, size_t count)
 {
@@ -275,16 +287,6 @@ int fsync(int fildes)
 //   return 0;
 // }
 
-// TODO: find better way to convert uptime ticks to real time.
-int gettimeofday(struct timeval *__restrict__ tp, 
-                struct timezone *__restrict__ tzp)
-{
-  int cur = uptime_as_micro();
-  tp->tv_sec = cur / 1000000;
-  tp->tv_usec = cur % 1000000;
-  return 0;
-}
-
 int * __errno_location(void){
   return 0;
 }
@@ -301,7 +303,11 @@ int * __errno_location(void){
 // }
 
 void usleep(unsigned long useconds) {
-  sleep(useconds / MICROSECS_PER_TICK);
+  struct timespec ts;
+
+  ts.tv_sec = useconds / 1000000;
+  ts.tv_nsec = useconds % 1000000 * 1000;
+  nanosleep(&ts, 0);
 }
 
 int creat(const char *path, mode_t mode){
@@ -316,11 +322,8 @@ int posix_select(int nfds, fd_set *restrict readfds,
             fd_set *restrict writefds, fd_set *restrict exceptfds,
             struct timeval* timeout)
 {
-  // doesn't support writefds and exceptfds now.
+  // doesn't support exceptfds now.
   long ticks = (timeout->tv_sec * 1000000 + timeout->tv_usec) / MICROSECS_PER_TICK;
-  if(writefds) {
-    FD_ZERO(writefds);
-  }
   if(exceptfds) {
     FD_ZERO(exceptfds);
   }
@@ -612,3 +615,79 @@ posix_mkdir(const char *pathname, mode_t mode)
 {
   return mkdir(pathname);
 }
+
+int
+send(int sockfd, const void *buf, int len, int flags)
+{
+  return sendto(sockfd, buf, len, flags, 0, 0);
+}
+
+int
+recv(int sockfd, void *buf, int len, int flags)
+{
+  return recvfrom(sockfd, buf, len, flags, 0, 0);
+}
+
+// nothing to do
+int
+setsockopt(int sockfd, int level, int optname, const void *optval, int optlen)
+{
+  return 0;
+}
+
+// Byte order conversions between the host and the network, which is big-endian.
+uint16
+htons(uint16 x)
+{
+  return (x << 8) | (x >> 8);
+}
+
+uint16
+ntohs(uint16 x)
+{
+  return htons(x);
+}
+
+uint
+htonl(uint x)
+{
+  return (x << 24) | ((x << 8) & 0xff0000) | ((x >> 8) & 0xff00) | (x >> 24);
+}
+
+uint
+ntohl(uint x)
+{
+  return htonl(x);
+}
+
+// There is no resolver, so only "localhost" and dotted quads are resolved.
+struct hostent*
+gethostbyname(const char *name)
+{
+  static uint addr;
+  static char *addrs[] = { (char*)&addr, 0 };
+  static struct hostent h = { 0, 0, AF_INET, sizeof(addr), addrs };
+  const char *p;
+  uint a, n;
+  int i;
+
+  if(strcmp(name, "localhost") == 0){
+    a = INADDR_LOOPBACK;
+  } else {
+    a = 0;
+    p = name;
+    for(i = 0; i < 4; i++){
+      if(*p < '0' || *p > '9')
+        return 0;
+      for(n = 0; *p >= '0' && *p <= '9'; p++)
+        n = n * 10 + *p - '0';
+      if(n > 255 || *p != (i < 3 ? '.' : '\0'))
+        return 0;
+      p++;
+      a = (a << 8) | n;
+    }
+  }
+  addr = htonl(a);
+  h.h_name = (char*)name;
+  return &h;
+}
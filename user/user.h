@@ -2,6 +2,29 @@
 
 struct stat;
 struct rtcdate;
+struct pmapinfo;
+struct pmapregion;
+struct syscallent;
+struct schedstat;
+struct irqstat;
+struct kconfig;
+struct meminfo;
+struct procmem;
+struct fdinfo;
+struct sockaddr;
+struct epoll_event;
+struct itimerspec;
+struct rlimit;
+struct mq_attr;
+struct cpuload;
+struct cycles;
+struct wssinfo;
+struct ksminfo;
+struct zraminfo;
+struct ganginfo;
+struct dlinfo;
+struct lockstat;
+struct spinbench;
 
 // system calls
 int fork(void);
@@ -23,6 +46,11 @@ int chdir(const char*);
 int dup(int);
 int getpid(void);
 char* sbrk(int);
+int brk(void*);
+int madvise(void*, uint64, int);
+int wss(int, int, struct wssinfo*);
+int ksm(int, struct ksminfo*);
+int zram(struct zraminfo*);
 int sleep(int);
 int uptime(void);
 int poweroff(int) __attribute__((noreturn));
@@ -37,12 +65,96 @@ int waitpid(int pid, int *stat_loc, int options);
 int getppid(void);
 off_t lseek(int fildes, off_t offset, int whence);
 int uptime_as_micro();
+int defrag(int);
+int snapshot(void);
+int snapdrop(void);
+int snapread(int inum, int off, void *buf, int n);
+int snapstat(int inum, struct stat*);
+int getdents(int, void*, int);
+int watchopen(void);
+int watchadd(int, const char*, int);
+int watchrm(int, int);
+int linkat(int, const char*);
+int sendfile(int, int, int);
+int pmap(int, struct pmapinfo*, struct pmapregion*, int);
+int ptrace(int, int, void*, void*);
+int batch(struct syscallent*, int);
+int yield(void);
+int gettid(void);
+int schedstat(struct schedstat*);
+int freeze(int);
+int thaw(int);
+int setsched(int, int);
+int getsched(int);
+int migrate(int, int);
+int setdeadline(int, int, int, int);
+int dlstat(int, struct dlinfo*);
+int gang(int, int, struct ganginfo*);
+int irqstat(struct irqstat*, int);
+int lockstat(struct lockstat*, int);
+int spinbench(int, struct spinbench*);
+int settick(int);
+int gettick(void);
+int powerctl(int, uint64);
+int reboot(void) __attribute__((noreturn));
+int getconfig(struct kconfig*);
+int setconfig(struct kconfig*);
+int setabi(int);
+int openat(int, const char*, int);
+int mkdirat(int, const char*);
+int unlinkat(int, const char*);
+int stat(const char*, struct stat*);
+int lstat(const char*, struct stat*);
+int access(const char*, int);
+int ftruncate(int, int);
+int truncate(const char*, int);
+int fcntl(int, int, int);
+int umask(int);
+int meminfo(struct meminfo*, struct procmem*, int);
+int getcwd(char*, int);
+int fdinfo(int, struct fdinfo*, int);
+int cpuload(struct cpuload*, int);
+int throttle(int, int);
+int nanosleep(const struct timespec*, struct timespec*);
 int gettimeofday(struct timeval *__restrict__ tp,
                 struct timezone *__restrict__ tzp);
+int settimeofday(const struct timeval*, const struct timezone*);
+int prctl(int, int, void*, int);
+int cycles(struct cycles*, int);
+int perf(int, int, uint64*);
+int iosched(int, int, void*);
+int socket(int, int, int);
+int bind(int, const struct sockaddr*, int);
+int listen(int, int);
+int accept(int, struct sockaddr*, int*);
+int connect(int, const struct sockaddr*, int);
+int sendto(int, const void*, int, int, const struct sockaddr*, int);
+int recvfrom(int, void*, int, int, struct sockaddr*, int*);
+int getsockname(int, struct sockaddr*, int*);
+int socketpair(int, int, int, int*);
+int epoll_create(int);
+int epoll_ctl(int, int, int, struct epoll_event*);
+int epoll_wait(int, struct epoll_event*, int, int);
+int eventfd(uint, int);
+int timerfd_create(int, int);
+int timerfd_settime(int, int, const struct itimerspec*, struct itimerspec*);
+int timerfd_gettime(int, struct itimerspec*);
+int getrlimit(int, struct rlimit*);
+int setrlimit(int, const struct rlimit*);
+int mq_open(const char*, int, struct mq_attr*);
+int mq_unlink(const char*);
+int mq_send(int, const char*, int, uint);
+int mq_receive(int, char*, int, uint*);
+int mq_getattr(int, struct mq_attr*);
+int sem_open(const char*, int, uint);
+int sem_unlink(const char*);
+int sem_wait(int);
+int sem_trywait(int);
+int sem_post(int);
+int sem_getvalue(int, int*);
 int clock(unsigned long*);
 
 // ulib.c
-int stat(const char*, struct stat*);
 char* strcpy(char*, const char*);
 void *memmove(void*, const void*, int);
 char* strchr(const char*, char c);
@@ -57,6 +169,10 @@ void free(void*);
 int atoi(const char*);
 int memcmp(const void *, const void *, uint);
 void *memcpy(void *, const void *, uint);
+void stackguard_init(uint64*);
+
+// crt0.c
+uint64 getauxval(uint64);
 
 // newly added ulibs
 int posix_select(int nfds, fd_set *restrict readfds,
@@ -82,6 +198,28 @@ void perror(const char *s);
 int sscanf(const char *buffer, const char *format, ...);
 int sprintf(char *buffer, const char *format_string, ...);
 
+// <sys/socket.h>
+int send(int sockfd, const void *buf, int len, int flags);
+int recv(int sockfd, void *buf, int len, int flags);
+int setsockopt(int sockfd, int level, int optname, const void *optval, int optlen);
+
+// <arpa/inet.h>
+uint16 htons(uint16 hostshort);
+uint16 ntohs(uint16 netshort);
+uint htonl(uint hostlong);
+uint ntohl(uint netlong);
+
+// <netdb.h>
+struct hostent {
+  char *h_name;
+  char **h_aliases;
+  int h_addrtype;
+  int h_length;
+  char **h_addr_list;
+};
+#define h_addr h_addr_list[0]
+struct hostent *gethostbyname(const char *name);
+
 // <stdlib.h>
 double atof(const char *string);
 void *valloc(size_t size);
@@ -4,6 +4,38 @@
 #include "user/user.h"
 #include "kernel/fs.h"
 #include "kernel/fcntl.h"
+#include "kernel/watch.h"
+#include "kernel/pmap.h"
+#include "kernel/ptrace.h"
+#include "kernel/batch.h"
+#include "kernel/schedstat.h"
+#include "kernel/wait.h"
+#include "kernel/sched.h"
+#include "kernel/irqstat.h"
+#include "kernel/meminfo.h"
+#include "kernel/fdinfo.h"
+#include "kernel/socket.h"
+#include "kernel/epoll.h"
+#include "kernel/eventfd.h"
+#include "kernel/mqueue.h"
+#include "kernel/resource.h"
+#include "kernel/semaphore.h"
+#include "kernel/mman.h"
+#include "kernel/wss.h"
+#include "kernel/ksm.h"
+#include "kernel/zram.h"
+#include "kernel/gang.h"
+#include "kernel/lockstat.h"
+#include "kernel/cpuload.h"
+#include "kernel/prctl.h"
+#include "kernel/cycles.h"
+#include "kernel/perf.h"
+#include "kernel/iosched.h"
+#include "kernel/power.h"
+#include "kernel/kconfig.h"
+#include "kernel/elf.h"
+#include "kernel/abi.h"
+#include "kernel/auxv.h"
 #include "kernel/syscall.h"
 #include "kernel/memlayout.h"
 #include "kernel/arch.h"
@@ -786,6 +818,59 @@ pipe1(char *s)
   }
 }
 
+// writes of at most PIPE_BUF bytes must not be interleaved.
+void
+pipeatomic(char *s)
+{
+  enum { NCHILD=4, N=20 };
+  int fds[2], i, j, n, pid, xstatus;
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe() failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < NCHILD; i++){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork() failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      close(fds[0]);
+      memset(buf, 'a' + i, PIPE_BUF);
+      for(j = 0; j < N; j++){
+        if(write(fds[1], buf, PIPE_BUF) != PIPE_BUF){
+          printf("%s: write failed\n", s);
+          exit(1);
+        }
+      }
+      exit(0);
+    }
+  }
+  close(fds[1]);
+  for(i = 0; i < NCHILD * N; i++){
+    // read the bytes of one write.
+    for(n = 0; n < PIPE_BUF; n += j){
+      j = read(fds[0], buf + n, PIPE_BUF - n);
+      if(j <= 0){
+        printf("%s: read failed\n", s);
+        exit(1);
+      }
+    }
+    for(j = 1; j < PIPE_BUF; j++){
+      if(buf[j] != buf[0]){
+        printf("%s: writes interleaved\n", s);
+        exit(1);
+      }
+    }
+  }
+  close(fds[0]);
+  for(i = 0; i < NCHILD; i++){
+    wait(&xstatus);
+    if(xstatus != 0)
+      exit(xstatus);
+  }
+}
 
 // test if child is killed (status = -1)
 void
@@ -816,6 +901,55 @@ killstatus(char *s)
   exit(0);
 }
 
+// a child blocked in a system call must exit promptly when killed.
+void
+killblocked(char *s)
+{
+  enum { NCHILD=3 };
+  int rfds[2], wfds[2], pids[NCHILD], i, xst;
+
+  // nobody writes to rfds, and nobody reads from wfds, but this
+  // process keeps both ends of each open.
+  if(pipe(rfds) != 0 || pipe(wfds) != 0){
+    printf("%s: pipe() failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < NCHILD; i++){
+    pids[i] = fork();
+    if(pids[i] < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pids[i] == 0){
+      if(i == 0){
+        // read from an empty pipe.
+        read(rfds[0], buf, 1);
+      } else if(i == 1){
+        sleep(1000000);
+      } else {
+        // write to a full pipe.
+        while(1)
+          write(wfds[1], buf, BUFSZ);
+      }
+      exit(0);
+    }
+  }
+  sleep(5);
+  for(i = 0; i < NCHILD; i++)
+    kill(pids[i]);
+  for(i = 0; i < NCHILD; i++){
+    wait(&xst);
+    if(xst != -1){
+      printf("%s: status should be -1\n", s);
+      exit(1);
+    }
+  }
+  close(rfds[0]);
+  close(rfds[1]);
+  close(wfds[0]);
+  close(wfds[1]);
+}
+
 // meant to be run w/ at most two CPUs
 void
 preempt(char *s)
@@ -1146,6 +1280,61 @@ sharedfd(char *s)
   }
 }
 
+// two processes write to the same file descriptor, each write
+// taking several transactions. do the writes stay contiguous?
+void
+sharedfdlarge(char *s)
+{
+  int fd, pid, i, n;
+  enum { N = 5 };
+
+  unlink("sharedfdlarge");
+  fd = open("sharedfdlarge", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: cannot open sharedfdlarge for writing\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  memset(buf, pid==0?'c':'p', BUFSZ);
+  for(i = 0; i < N; i++){
+    if(write(fd, buf, BUFSZ) != BUFSZ){
+      printf("%s: write sharedfdlarge failed\n", s);
+      exit(1);
+    }
+  }
+  if(pid == 0)
+    exit(0);
+  int xstatus;
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(xstatus);
+  close(fd);
+
+  fd = open("sharedfdlarge", 0);
+  if(fd < 0){
+    printf("%s: cannot open sharedfdlarge for reading\n", s);
+    exit(1);
+  }
+  for(n = 0; read(fd, buf, BUFSZ) == BUFSZ; n++){
+    for(i = 1; i < BUFSZ; i++){
+      if(buf[i] != buf[0]){
+        printf("%s: write %d was interleaved with another\n", s, n);
+        exit(1);
+      }
+    }
+  }
+  close(fd);
+  unlink("sharedfdlarge");
+  if(n != 2*N){
+    printf("%s: %d writes instead of %d\n", s, n, 2*N);
+    exit(1);
+  }
+}
+
 // four processes write different files at the same
 // time, to test block allocation.
 void
@@ -1213,121 +1402,4267 @@ fourfiles(char *s)
   }
 }
 
-// four processes create and delete different files in same directory
+// four processes create and delete different files in same directory
+void
+createdelete(char *s)
+{
+  enum { N = 20, NCHILD=4 };
+  int pid, i, fd, pi;
+  char name[32];
+
+  for(pi = 0; pi < NCHILD; pi++){
+    pid = fork();
+    if(pid < 0){
+      printf("fork failed\n", s);
+      exit(1);
+    }
+
+    if(pid == 0){
+      name[0] = 'p' + pi;
+      name[2] = '\0';
+      for(i = 0; i < N; i++){
+        name[1] = '0' + i;
+        fd = open(name, O_CREATE | O_RDWR);
+        if(fd < 0){
+          printf("%s: create failed\n", s);
+          exit(1);
+        }
+        close(fd);
+        if(i > 0 && (i % 2 ) == 0){
+          name[1] = '0' + (i / 2);
+          if(unlink(name) < 0){
+            printf("%s: unlink failed\n", s);
+            exit(1);
+          }
+        }
+      }
+      exit(0);
+    }
+  }
+
+  int xstatus;
+  for(pi = 0; pi < NCHILD; pi++){
+    wait(&xstatus);
+    if(xstatus != 0)
+      exit(1);
+  }
+
+  name[0] = name[1] = name[2] = 0;
+  for(i = 0; i < N; i++){
+    for(pi = 0; pi < NCHILD; pi++){
+      name[0] = 'p' + pi;
+      name[1] = '0' + i;
+      fd = open(name, 0);
+      if((i == 0 || i >= N/2) && fd < 0){
+        printf("%s: oops createdelete %s didn't exist\n", s, name);
+        exit(1);
+      } else if((i >= 1 && i < N/2) && fd >= 0){
+        printf("%s: oops createdelete %s did exist\n", s, name);
+        exit(1);
+      }
+      if(fd >= 0)
+        close(fd);
+    }
+  }
+
+  for(i = 0; i < N; i++){
+    for(pi = 0; pi < NCHILD; pi++){
+      name[0] = 'p' + i;
+      name[1] = '0' + i;
+      unlink(name);
+    }
+  }
+}
+
+// can I unlink a file and still read it?
+void
+unlinkread(char *s)
+{
+  enum { SZ = 5 };
+  int fd, fd1;
+
+  fd = open("unlinkread", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: create unlinkread failed\n", s);
+    exit(1);
+  }
+  write(fd, "hello", SZ);
+  close(fd);
+
+  fd = open("unlinkread", O_RDWR);
+  if(fd < 0){
+    printf("%s: open unlinkread failed\n", s);
+    exit(1);
+  }
+  if(unlink("unlinkread") != 0){
+    printf("%s: unlink unlinkread failed\n", s);
+    exit(1);
+  }
+
+  fd1 = open("unlinkread", O_CREATE | O_RDWR);
+  write(fd1, "yyy", 3);
+  close(fd1);
+
+  if(read(fd, buf, sizeof(buf)) != SZ){
+    printf("%s: unlinkread read failed", s);
+    exit(1);
+  }
+  if(buf[0] != 'h'){
+    printf("%s: unlinkread wrong data\n", s);
+    exit(1);
+  }
+  if(write(fd, buf, 10) != 10){
+    printf("%s: unlinkread write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("unlinkread");
+}
+
+// can sendfile copy a file into a pipe and into another file?
+void
+sendfiletest(char *s)
+{
+  enum { N = 20, SZ = 100 };
+  int fd, fd1, i, n, fds[2];
+
+  fd = open("sendfile0", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: create sendfile0 failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    memset(buf, 'a' + i, SZ);
+    if(write(fd, buf, SZ) != SZ){
+      printf("%s: write sendfile0 failed\n", s);
+      exit(1);
+    }
+  }
+  close(fd);
+
+  // file to pipe
+  if(pipe(fds) != 0){
+    printf("%s: pipe() failed\n", s);
+    exit(1);
+  }
+  fd = open("sendfile0", O_RDONLY);
+  if(fork() == 0){
+    close(fds[0]);
+    if(sendfile(fds[1], fd, N * SZ) != N * SZ){
+      printf("%s: sendfile to pipe failed\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  close(fds[1]);
+  close(fd);
+  for(i = 0; i < N * SZ; i += n){
+    n = read(fds[0], buf, 1);
+    if(n != 1 || buf[0] != 'a' + i / SZ){
+      printf("%s: wrong data from pipe\n", s);
+      exit(1);
+    }
+  }
+  close(fds[0]);
+  wait(&n);
+  if(n != 0)
+    exit(1);
+
+  // file to file, in two steps to check offsets
+  fd = open("sendfile0", O_RDONLY);
+  fd1 = open("sendfile1", O_CREATE | O_RDWR);
+  if(fd < 0 || fd1 < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  if(sendfile(fd1, fd, SZ + 1) != SZ + 1 ||
+     sendfile(fd1, fd, N * SZ) != N * SZ - (SZ + 1) ||
+     sendfile(fd1, fd, N * SZ) != 0){
+    printf("%s: sendfile to file failed\n", s);
+    exit(1);
+  }
+  if(sendfile(fd1, fd1, 1) >= 0){
+    printf("%s: sendfile to itself succeeded!\n", s);
+    exit(1);
+  }
+  close(fd);
+  close(fd1);
+
+  fd = open("sendfile1", O_RDONLY);
+  for(i = 0; i < N; i++){
+    if(read(fd, buf, SZ) != SZ){
+      printf("%s: read sendfile1 failed\n", s);
+      exit(1);
+    }
+    for(n = 0; n < SZ; n++){
+      if(buf[n] != 'a' + i){
+        printf("%s: wrong data in sendfile1\n", s);
+        exit(1);
+      }
+    }
+  }
+  close(fd);
+  unlink("sendfile0");
+  unlink("sendfile1");
+}
+
+// pmap() reports the regions and the resident pages of a process.
+void
+pmaptest(char *s)
+{
+  struct pmapinfo info, info1;
+  struct pmapregion regions[4];
+  int n, pid, fds[2];
+  char c, *p;
+
+  n = pmap(getpid(), &info, regions, 4);
+  if(n < 1 || info.size != (uint64)sbrk(0) || info.ptpages[2] != 1){
+    printf("%s: pmap of self failed\n", s);
+    exit(1);
+  }
+  // The pages not written yet, such as the stack guard, are not resident.
+  if(regions[0].start != 0 || !(regions[0].perm & PMAP_U) || info.rss == 0 ||
+     info.rss > (info.size + PGSIZE - 1) / PGSIZE + 2){
+    printf("%s: wrong pmap of self\n", s);
+    exit(1);
+  }
+
+  if((p = sbrk(PGSIZE)) == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  p[PGSIZE - 1] = 1;
+  if(pmap(getpid(), &info1, regions, 4) < 0 || info1.rss != info.rss + 1 ||
+     info1.size != info.size + PGSIZE){
+    printf("%s: pmap after sbrk wrong\n", s);
+    exit(1);
+  }
+  sbrk(-PGSIZE);
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe() failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(fds[1]);
+    read(fds[0], &c, 1);
+    exit(0);
+  }
+  close(fds[0]);
+  sleep(1);
+  if(pmap(pid, &info1, 0, 0) != 0 || info1.size != info.size){
+    printf("%s: pmap of child failed\n", s);
+    exit(1);
+  }
+  write(fds[1], "x", 1);
+  close(fds[1]);
+  wait(0);
+
+  if(pmap(pid, &info1, regions, 4) >= 0){
+    printf("%s: pmap of exited process succeeded!\n", s);
+    exit(1);
+  }
+  if(pmap(getpid(), &info1, regions, -1) >= 0){
+    printf("%s: pmap with negative count succeeded!\n", s);
+    exit(1);
+  }
+}
+
+uint64 ptraceword = 1;
+
+// a parent traces the syscalls of its child, and changes its memory and a return value.
+void
+ptracetest(char *s)
+{
+  int ev, pid, xstatus;
+  uint64 word;
+  struct ptraceregs regs;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(ptrace(PTRACE_TRACEME, 0, 0, 0) < 0)
+      exit(1);
+    if(ptraceword != 2)
+      exit(2);
+    if(getpid() != 12345)
+      exit(3);
+    exit(0);
+  }
+
+  if(ptrace(PTRACE_WAIT, pid, 0, 0) != PTRACE_EV_START){
+    printf("%s: child did not stop\n", s);
+    exit(1);
+  }
+  if(ptrace(PTRACE_PEEKDATA, pid, &ptraceword, &word) < 0 || word != 1 ||
+     ptrace(PTRACE_POKEDATA, pid, &ptraceword, (void*)2) < 0){
+    printf("%s: peek/poke failed\n", s);
+    exit(1);
+  }
+  if(ptrace(PTRACE_PEEKDATA, getpid(), &ptraceword, &word) >= 0){
+    printf("%s: peek of non-child succeeded!\n", s);
+    exit(1);
+  }
+
+  if(ptrace(PTRACE_SYSCALL, pid, 0, 0) < 0){
+    printf("%s: PTRACE_SYSCALL failed\n", s);
+    exit(1);
+  }
+  while((ev = ptrace(PTRACE_WAIT, pid, 0, 0)) >= 0){
+    if(ptrace(PTRACE_GETREGS, pid, 0, &regs) < 0){
+      printf("%s: PTRACE_GETREGS failed\n", s);
+      exit(1);
+    }
+    if(ev == PTRACE_EV_SYSEXIT && regs.args[7] == SYS_getpid){
+      if(regs.args[0] != pid){
+        printf("%s: wrong return value of traced getpid\n", s);
+        exit(1);
+      }
+      regs.args[0] = 12345;
+      if(ptrace(PTRACE_SETREGS, pid, 0, &regs) < 0){
+        printf("%s: PTRACE_SETREGS failed\n", s);
+        exit(1);
+      }
+    }
+    if(ptrace(PTRACE_SYSCALL, pid, 0, 0) < 0){
+      printf("%s: PTRACE_SYSCALL failed\n", s);
+      exit(1);
+    }
+  }
+
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: traced child failed with %d\n", s, xstatus);
+    exit(1);
+  }
+}
+
+volatile uint64 ptracewatched;
+
+// an ebreak stops a traced child, and so does a hardware watchpoint,
+// before the child stores to the watched variable.
+void
+ptracetriggertest(char *s)
+{
+  int pid, watch, xstatus;
+  uint64 word;
+  struct ptracetrigger trigger;
+  struct ptraceregs regs;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(ptrace(PTRACE_TRACEME, 0, 0, 0) < 0)
+      exit(1);
+    asm volatile(".option push\n.option norvc\nebreak\n.option pop");
+    ptracewatched = 1;
+    exit(0);
+  }
+
+  if(ptrace(PTRACE_WAIT, pid, 0, 0) != PTRACE_EV_START){
+    printf("%s: child did not stop\n", s);
+    exit(1);
+  }
+  trigger.addr = (uint64)&ptracewatched;
+  trigger.index = NTRIGGER;
+  trigger.type = TRIGGER_STORE;
+  if(ptrace(PTRACE_SETTRIGGER, pid, 0, &trigger) >= 0){
+    printf("%s: PTRACE_SETTRIGGER of a bad index succeeded!\n", s);
+    exit(1);
+  }
+  // the CPU may not have any triggers.
+  trigger.index = 0;
+  watch = ptrace(PTRACE_SETTRIGGER, pid, 0, &trigger) == 0;
+
+  if(ptrace(PTRACE_CONT, pid, 0, 0) < 0 ||
+     ptrace(PTRACE_WAIT, pid, 0, 0) != PTRACE_EV_BREAK){
+    printf("%s: child did not stop at ebreak\n", s);
+    exit(1);
+  }
+  if(ptrace(PTRACE_GETREGS, pid, 0, &regs) < 0){
+    printf("%s: PTRACE_GETREGS failed\n", s);
+    exit(1);
+  }
+  regs.pc += 4;
+  if(ptrace(PTRACE_SETREGS, pid, 0, &regs) < 0 || ptrace(PTRACE_CONT, pid, 0, 0) < 0){
+    printf("%s: resuming past ebreak failed\n", s);
+    exit(1);
+  }
+
+  if(watch){
+    if(ptrace(PTRACE_WAIT, pid, 0, 0) != PTRACE_EV_BREAK){
+      printf("%s: child did not stop at watchpoint\n", s);
+      exit(1);
+    }
+    if(ptrace(PTRACE_PEEKDATA, pid, (void*)&ptracewatched, &word) < 0 || word != 0){
+      printf("%s: child stopped after the watched store\n", s);
+      exit(1);
+    }
+    trigger.type = 0;
+    if(ptrace(PTRACE_SETTRIGGER, pid, 0, &trigger) < 0 || ptrace(PTRACE_CONT, pid, 0, 0) < 0){
+      printf("%s: clearing the watchpoint failed\n", s);
+      exit(1);
+    }
+  }
+
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: traced child failed with %d\n", s, xstatus);
+    exit(1);
+  }
+}
+
+// batch() runs system calls in order, and reports the result of each.
+void
+batchtest(char *s)
+{
+  enum { N = 8 };
+  struct syscallent ents[N + 4];
+  int fd, i;
+
+  fd = open("batch0", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: create batch0 failed\n", s);
+    exit(1);
+  }
+
+  memset(ents, 0, sizeof(ents));
+  for(i = 0; i < N; i++){
+    ents[i].num = SYS_write;
+    ents[i].args[0] = fd;
+    ents[i].args[1] = (uint64)"abcdefgh" + i;
+    ents[i].args[2] = 1;
+  }
+  ents[N].num = SYS_getpid;
+  ents[N + 1].num = SYS_fork;
+  ents[N + 2].num = 1000;
+  ents[N + 3].num = SYS_close;
+  ents[N + 3].args[0] = fd;
+  if(batch(ents, N + 4) != N + 4){
+    printf("%s: batch failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    if(ents[i].ret != 1){
+      printf("%s: batched write %d returned %d\n", s, i, (int)ents[i].ret);
+      exit(1);
+    }
+  }
+  if(ents[N].ret != getpid() || ents[N + 1].ret != -1 || ents[N + 2].ret != -1 ||
+     ents[N + 3].ret != 0){
+    printf("%s: wrong batch results\n", s);
+    exit(1);
+  }
+  if(write(fd, "x", 1) >= 0){
+    printf("%s: batched close did not close\n", s);
+    exit(1);
+  }
+
+  fd = open("batch0", O_RDONLY);
+  if(read(fd, buf, sizeof(buf)) != N || memcmp(buf, "abcdefgh", N) != 0){
+    printf("%s: wrong contents of batch0\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("batch0");
+
+  if(batch(ents, -1) >= 0 || batch(ents, 100000) >= 0 ||
+     batch((struct syscallent*)0xffffffffffff, 1) >= 0){
+    printf("%s: bad batch succeeded!\n", s);
+    exit(1);
+  }
+}
+
+// yield gives up the CPU, and is counted as a context switch.
+void
+schedtest(char *s)
+{
+  int i;
+  struct schedstat before, after;
+
+  if(gettid() != getpid()){
+    printf("%s: gettid differs from getpid\n", s);
+    exit(1);
+  }
+  if(schedstat(&before) < 0 || schedstat((void*)0xffffffffffff) >= 0){
+    printf("%s: schedstat failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 10; i++){
+    if(yield() != 0){
+      printf("%s: yield failed\n", s);
+      exit(1);
+    }
+  }
+  schedstat(&after);
+  if(after.nswitch < before.nswitch + 10 || after.switch_cycles <= before.switch_cycles){
+    printf("%s: yields were not counted\n", s);
+    exit(1);
+  }
+}
+
+// a frozen child stops, is reported by waitpid with WUNTRACED,
+// and runs again once thawed or killed.
+void
+freezetest(char *s)
+{
+  int pid, xst;
+
+  if(freeze(-1) >= 0 || thaw(-1) >= 0){
+    printf("%s: freeze of a bad pid succeeded\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    for(;;)
+      sleep(1);
+  }
+
+  if(freeze(pid) < 0 || waitpid(pid, &xst, WUNTRACED) != pid || !WIFSTOPPED(xst)){
+    printf("%s: child did not stop\n", s);
+    exit(1);
+  }
+
+  // the stop is reported again only if the child has run and stopped again.
+  xst = 0;
+  if(thaw(pid) < 0 || freeze(pid) < 0 ||
+     waitpid(pid, &xst, WUNTRACED) != pid || !WIFSTOPPED(xst)){
+    printf("%s: child did not resume\n", s);
+    exit(1);
+  }
+
+  // a stopped child can still be killed.
+  kill(pid);
+  if(waitpid(pid, &xst, 0) != pid || xst != -1){
+    printf("%s: stopped child was not killed\n", s);
+    exit(1);
+  }
+}
+
+// scheduling classes can be set, and are inherited by children.
+void
+schedclasstest(char *s)
+{
+  int pid, xst;
+
+  if(getsched(0) != SCHED_INTERACTIVE || getsched(getpid()) != SCHED_INTERACTIVE){
+    printf("%s: wrong default class\n", s);
+    exit(1);
+  }
+  if(setsched(0, 3) >= 0 || setsched(0, -1) >= 0 || setsched(-1, SCHED_BATCH) >= 0){
+    printf("%s: bad setsched succeeded\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setsched(0, SCHED_IDLE) < 0 || getsched(0) != SCHED_IDLE)
+      exit(1);
+    pid = fork();
+    if(pid == 0)
+      exit(getsched(0) == SCHED_IDLE ? 0 : 1);
+    wait(&xst);
+    exit(xst);
+  }
+  wait(&xst);
+  if(xst != 0){
+    printf("%s: class was not set or inherited\n", s);
+    exit(1);
+  }
+  if(getsched(0) != SCHED_INTERACTIVE){
+    printf("%s: class of the parent changed\n", s);
+    exit(1);
+  }
+}
+
+// disk writes are accounted for in the interrupt statistics.
+void
+irqstattest(char *s)
+{
+  int fd, i, j, n;
+  uint64 before, after;
+  struct irqstat stats[8];
+
+  before = 0;
+  n = irqstat(stats, 8);
+  for(i = 0; i < n; i++)
+    for(j = 0; j < NCPU; j++)
+      before += stats[i].count[j];
+
+  fd = open("irqstat0", O_CREATE | O_RDWR);
+  if(fd < 0 || write(fd, "x", 1) != 1){
+    printf("%s: write irqstat0 failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("irqstat0");
+
+  after = 0;
+  n = irqstat(stats, 8);
+  for(i = 0; i < n; i++){
+    if(i > 0 && stats[i].irq <= stats[i-1].irq){
+      printf("%s: statistics out of order\n", s);
+      exit(1);
+    }
+    for(j = 0; j < NCPU; j++)
+      after += stats[i].count[j];
+  }
+  if(n <= 0 || after <= before){
+    printf("%s: disk interrupts were not counted\n", s);
+    exit(1);
+  }
+  if(irqstat((void*)0xffffffffffff, 8) >= 0){
+    printf("%s: irqstat to a bad address succeeded\n", s);
+    exit(1);
+  }
+}
+
+// the tick interval can be changed, and ticks keep coming.
+void
+ticktest(char *s)
+{
+  int old, t0;
+
+  old = gettick();
+  if(old <= 0){
+    printf("%s: gettick failed\n", s);
+    exit(1);
+  }
+  if(settick(0) >= 0 || settick(-1) >= 0){
+    printf("%s: bad settick succeeded\n", s);
+    exit(1);
+  }
+  if(settick(old / 2) < 0 || gettick() != old / 2){
+    printf("%s: settick failed\n", s);
+    exit(1);
+  }
+  t0 = uptime();
+  sleep(2);
+  if(uptime() < t0 + 2){
+    printf("%s: ticks stopped\n", s);
+    exit(1);
+  }
+  settick(old);
+}
+
+// idle CPUs can suspend themselves, and still run processes afterwards.
+void
+powertest(char *s)
+{
+  int old, t0;
+  struct powerstat st;
+
+  old = powerctl(POWER_GETIDLE, 0);
+  if(old < 0 || powerctl(POWER_SETIDLE, 2) >= 0 || powerctl(7, 0) >= 0){
+    printf("%s: bad powerctl succeeded\n", s);
+    exit(1);
+  }
+  if(powerctl(POWER_SETIDLE, IDLE_SUSPEND) < 0 || powerctl(POWER_GETIDLE, 0) != IDLE_SUSPEND){
+    printf("%s: setting the idle policy failed\n", s);
+    exit(1);
+  }
+  t0 = uptime();
+  sleep(2);
+  if(uptime() < t0 + 2){
+    printf("%s: ticks stopped\n", s);
+    exit(1);
+  }
+  if(powerctl(POWER_STAT, (uint64)&st) < 0 || st.policy != IDLE_SUSPEND || st.nsuspend == 0){
+    printf("%s: idle CPUs did not suspend\n", s);
+    exit(1);
+  }
+  powerctl(POWER_SETIDLE, old);
+}
+
+// the boot configuration is stored, and bad settings are rejected.
+void
+configtest(char *s)
+{
+  struct kconfig old, c;
+
+  if(getconfig(&old) < 0){
+    printf("%s: getconfig failed\n", s);
+    exit(1);
+  }
+  c = old;
+  c.sched_class = 3;
+  if(setconfig(&c) >= 0){
+    printf("%s: setconfig with a bad class succeeded\n", s);
+    exit(1);
+  }
+  c = old;
+  c.tick_us = 1;
+  if(setconfig(&c) >= 0){
+    printf("%s: setconfig with a short tick succeeded\n", s);
+    exit(1);
+  }
+  c = old;
+  c.oom_killer = 2;
+  if(setconfig(&c) >= 0){
+    printf("%s: setconfig with a bad oom_killer succeeded\n", s);
+    exit(1);
+  }
+  c = old;
+  c.read_only = 3;
+  if(setconfig(&c) >= 0){
+    printf("%s: setconfig with a bad read_only succeeded\n", s);
+    exit(1);
+  }
+  c = old;
+  c.log_color = 2;
+  if(setconfig(&c) >= 0){
+    printf("%s: setconfig with a bad log_color succeeded\n", s);
+    exit(1);
+  }
+  c = old;
+  c.nbuf = 1;
+  if(setconfig(&c) >= 0){
+    printf("%s: setconfig with a tiny buffer cache succeeded\n", s);
+    exit(1);
+  }
+  c = old;
+  c.nfile = NFILE + 1;
+  if(setconfig(&c) >= 0){
+    printf("%s: setconfig with a huge file table succeeded\n", s);
+    exit(1);
+  }
+  c = old;
+  c.sched_class = SCHED_BATCH;
+  c.idle_policy = IDLE_SUSPEND;
+  c.oom_killer = 1;
+  c.nproc = NPROC / 2;
+  if(setconfig(&c) < 0 || getconfig(&c) < 0){
+    printf("%s: setconfig failed\n", s);
+    exit(1);
+  }
+  if(c.sched_class != SCHED_BATCH || c.tick_us != old.tick_us || c.idle_policy != IDLE_SUSPEND ||
+     c.oom_killer != 1 || c.nproc != NPROC / 2){
+    printf("%s: getconfig returned a different configuration\n", s);
+    exit(1);
+  }
+  setconfig(&old);
+}
+
+// the ABI set by setabi() applies from the next exec(), so this process keeps running
+// with rv6 system calls, and a failed exec() keeps the rv6 ABI too.
+void
+abitest(char *s)
+{
+  int pid, xstatus;
+  char *args[] = { "echo", "ok", 0 };
+
+  if(setabi(2) >= 0 || setabi(-1) >= 0){
+    printf("%s: setabi with a bad ABI succeeded\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setabi(ABI_LINUX) < 0)
+      exit(1);
+    if(getpid() <= 0)
+      exit(2);
+    exec("nonexistent", args);
+    if(getpid() <= 0)
+      exit(3);
+    setabi(ABI_RV6);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child failed with %d\n", s, xstatus);
+    exit(1);
+  }
+}
+
+// exec() describes the program in the auxiliary vector.
+void
+auxvtest(char *s)
+{
+  extern void _start(int, char*[], uint64*);
+
+  if(getauxval(AT_PAGESZ) != PGSIZE){
+    printf("%s: AT_PAGESZ is %p\n", s, getauxval(AT_PAGESZ));
+    exit(1);
+  }
+  if(getauxval(AT_ENTRY) != (uint64)_start){
+    printf("%s: AT_ENTRY is %p, not _start\n", s, getauxval(AT_ENTRY));
+    exit(1);
+  }
+  if(getauxval(AT_PHNUM) == 0 || getauxval(AT_PHENT) != 56){
+    printf("%s: bad AT_PHNUM or AT_PHENT\n", s);
+    exit(1);
+  }
+  if(getauxval(AT_NULL) != 0 || getauxval(12345) != 0){
+    printf("%s: found a missing entry\n", s);
+    exit(1);
+  }
+}
+
+__attribute__((stack_protect, noinline)) static int
+smash(int n)
+{
+  volatile char buf[8];
+
+  for(int i = 0; i < n; i++)
+    buf[i] = 'x';
+  return buf[0];
+}
+
+// exec() passes random bytes for the stack protector's canary in the
+// auxiliary vector, and a protected function that overruns its
+// buffer ends the process.
+void
+stackguard(char *s)
+{
+  extern uint64 __stack_chk_guard;
+  uchar *random = (uchar*)getauxval(AT_RANDOM);
+  int i, pid, xstatus;
+
+  if(random == 0){
+    printf("%s: no AT_RANDOM entry\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 16 && random[i] == 0; i++)
+    ;
+  if(i == 16 || memcmp(&__stack_chk_guard, random, sizeof(__stack_chk_guard)) != 0){
+    printf("%s: canary not taken from AT_RANDOM\n", s);
+    exit(1);
+  }
+
+  smash(sizeof(uint64));
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(2);
+    smash(64);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 1){
+    printf("%s: stack smashing not detected\n", s);
+    exit(1);
+  }
+}
+
+// do the *at() system calls resolve relative paths from the directory fd?
+void
+dirfdtest(char *s)
+{
+  int dfd, fd;
+  char c;
+
+  unlink("dirfd/d/f");
+  unlink("dirfd/d");
+  unlink("dirfd");
+  if(mkdir("dirfd") < 0){
+    printf("%s: mkdir dirfd failed\n", s);
+    exit(1);
+  }
+  dfd = open("dirfd", O_RDONLY);
+  if(dfd < 0){
+    printf("%s: open dirfd failed\n", s);
+    exit(1);
+  }
+  if(mkdirat(dfd, "d") < 0){
+    printf("%s: mkdirat failed\n", s);
+    exit(1);
+  }
+  fd = openat(dfd, "d/f", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: openat create failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "x", 1) != 1){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("dirfd/d/f", O_RDONLY);
+  if(fd < 0 || read(fd, &c, 1) != 1 || c != 'x'){
+    printf("%s: file not created in dirfd\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = openat(AT_FDCWD, "dirfd/d/f", O_RDONLY);
+  if(fd < 0){
+    printf("%s: openat AT_FDCWD failed\n", s);
+    exit(1);
+  }
+  // a file descriptor that is not a directory cannot be a starting point.
+  if(openat(fd, "f", O_RDONLY) >= 0){
+    printf("%s: openat from a file succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(unlinkat(dfd, "d") == 0){
+    printf("%s: unlinkat of non-empty directory succeeded\n", s);
+    exit(1);
+  }
+  if(unlinkat(dfd, "d/f") < 0 || unlinkat(dfd, "d") < 0){
+    printf("%s: unlinkat failed\n", s);
+    exit(1);
+  }
+  if(open("dirfd/d", O_RDONLY) >= 0){
+    printf("%s: dirfd/d still exists\n", s);
+    exit(1);
+  }
+  close(dfd);
+  if(unlink("dirfd") < 0){
+    printf("%s: unlink dirfd failed\n", s);
+    exit(1);
+  }
+}
+
+// do stat(), lstat() and access() work by path, without opening the file?
+void
+pathstattest(char *s)
+{
+  int fd;
+  struct stat st, fst;
+
+  unlink("pathstat");
+  fd = open("pathstat", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "abc", 3) != 3 || fstat(fd, &fst) < 0){
+    printf("%s: write or fstat failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(stat("pathstat", &st) < 0){
+    printf("%s: stat failed\n", s);
+    exit(1);
+  }
+  if(st.type != T_FILE || st.size != 3 || st.ino != fst.ino || st.dev != fst.dev){
+    printf("%s: stat disagrees with fstat\n", s);
+    exit(1);
+  }
+  if(lstat("pathstat", &st) < 0 || st.ino != fst.ino){
+    printf("%s: lstat failed\n", s);
+    exit(1);
+  }
+  if(stat(".", &st) < 0 || st.type != T_DIR){
+    printf("%s: stat of . failed\n", s);
+    exit(1);
+  }
+  if(stat("pathstat", (struct stat*)0xffffffffffff) >= 0){
+    printf("%s: stat to a bad address succeeded\n", s);
+    exit(1);
+  }
+
+  if(access("pathstat", F_OK) < 0 || access("pathstat", R_OK|W_OK) < 0){
+    printf("%s: access failed\n", s);
+    exit(1);
+  }
+  if(access(".", R_OK|W_OK|X_OK) < 0){
+    printf("%s: access to a directory failed\n", s);
+    exit(1);
+  }
+  if(access("pathstat", 0x8) >= 0){
+    printf("%s: access with a bad mode succeeded\n", s);
+    exit(1);
+  }
+
+  unlink("pathstat");
+  if(stat("pathstat", &st) >= 0 || access("pathstat", F_OK) >= 0){
+    printf("%s: removed file still exists\n", s);
+    exit(1);
+  }
+}
+
+// can a file be shrunk and extended to any length, with holes reading as zeroes?
+void
+truncatetest(char *s)
+{
+  enum { SZ = 3000, SHORT = 1500, LONG = 20000 };
+  int fd, i, n;
+  struct stat st;
+
+  unlink("truncfile");
+  fd = open("truncfile", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < SZ; i++)
+    buf[i] = 'a' + i % 26;
+  if(write(fd, buf, SZ) != SZ){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+
+  if(ftruncate(fd, SHORT) < 0 || fstat(fd, &st) < 0 || st.size != SHORT){
+    printf("%s: ftruncate to shrink failed\n", s);
+    exit(1);
+  }
+  // past the block that held the old end of the file, into the indirect blocks.
+  if(ftruncate(fd, LONG) < 0 || fstat(fd, &st) < 0 || st.size != LONG){
+    printf("%s: ftruncate to extend failed\n", s);
+    exit(1);
+  }
+
+  close(fd);
+  fd = open("truncfile", O_RDONLY);
+  n = 0;
+  while((i = read(fd, buf, sizeof(buf))) > 0){
+    for(int j = 0; j < i; j++){
+      char want = n + j < SHORT ? 'a' + (n + j) % 26 : 0;
+      if(buf[j] != want){
+        printf("%s: wrong byte %d after truncation\n", s, n + j);
+        exit(1);
+      }
+    }
+    n += i;
+  }
+  if(n != LONG){
+    printf("%s: read %d bytes, not %d\n", s, n, LONG);
+    exit(1);
+  }
+  if(ftruncate(fd, 0) >= 0){
+    printf("%s: ftruncate of a read-only fd succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("truncfile", O_RDWR);
+  if(fd < 0 || ftruncate(fd, 300000) >= 0 || ftruncate(fd, -1) >= 0){
+    printf("%s: ftruncate to a bad length succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(truncate("truncfile", 100) < 0 || stat("truncfile", &st) < 0 || st.size != 100){
+    printf("%s: truncate failed\n", s);
+    exit(1);
+  }
+  if(truncate(".", 0) >= 0 || truncate("nosuchfile", 0) >= 0){
+    printf("%s: truncate of a non-file succeeded\n", s);
+    exit(1);
+  }
+  unlink("truncfile");
+}
+
+// are file descriptors marked close-on-exec closed by exec, and only those?
+void
+cloexectest(char *s)
+{
+  int fd, fd2, p[2], n, pid, xstatus;
+  char *echoargv[] = { "echo", "hi", 0 };
+
+  fd = open("echo", O_RDONLY|O_CLOEXEC);
+  if(fd < 0 || fcntl(fd, F_GETFD, 0) != FD_CLOEXEC){
+    printf("%s: O_CLOEXEC not set\n", s);
+    exit(1);
+  }
+  // dup'd descriptors do not inherit the flag.
+  fd2 = dup(fd);
+  if(fd2 < 0 || fcntl(fd2, F_GETFD, 0) != 0){
+    printf("%s: dup inherited close-on-exec\n", s);
+    exit(1);
+  }
+  if(fcntl(fd2, F_SETFD, FD_CLOEXEC) < 0 || fcntl(fd2, F_GETFD, 0) != FD_CLOEXEC ||
+     fcntl(fd2, F_SETFD, 0) < 0 || fcntl(fd2, F_GETFD, 0) != 0){
+    printf("%s: F_SETFD failed\n", s);
+    exit(1);
+  }
+  if(fcntl(fd, 99, 0) >= 0){
+    printf("%s: bad fcntl command succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+  close(fd2);
+
+  for(int cloexec = 0; cloexec < 2; cloexec++){
+    if(pipe(p) < 0){
+      printf("%s: pipe failed\n", s);
+      exit(1);
+    }
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      // echo writes into the pipe, unless its stdout is closed by exec.
+      close(p[0]);
+      close(1);
+      dup(p[1]);
+      close(p[1]);
+      if(cloexec)
+        fcntl(1, F_SETFD, FD_CLOEXEC);
+      exec("echo", echoargv);
+      exit(1);
+    }
+    close(p[1]);
+    n = 0;
+    while((fd = read(p[0], buf + n, sizeof(buf) - n)) > 0)
+      n += fd;
+    close(p[0]);
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: exec failed\n", s);
+      exit(1);
+    }
+    if(cloexec && n != 0){
+      printf("%s: close-on-exec fd survived exec\n", s);
+      exit(1);
+    }
+    if(!cloexec && n != 3){
+      printf("%s: fd was closed by exec\n", s);
+      exit(1);
+    }
+  }
+}
+
+// is the umask kept per process, and inherited across fork?
+void
+umasktest(char *s)
+{
+  int old, pid, xstatus;
+
+  old = umask(077);
+  if(umask(0777) != 077 || umask(01777) != 0777 || umask(old) != 0777){
+    printf("%s: umask not kept\n", s);
+    exit(1);
+  }
+
+  umask(027);
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(umask(0) != 027)
+      exit(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child did not inherit the umask\n", s);
+    exit(1);
+  }
+  if(umask(old) != 027){
+    printf("%s: child changed the parent's umask\n", s);
+    exit(1);
+  }
+}
+
+// does /dev/tty reach the controlling terminal?
+void
+ttytest(char *s)
+{
+  int fd;
+  struct stat st;
+
+  if(stat("/dev/tty", &st) < 0 || st.type != T_DEVICE){
+    printf("%s: no /dev/tty\n", s);
+    exit(1);
+  }
+  fd = open("/dev/tty", O_WRONLY);
+  if(fd < 0){
+    printf("%s: open /dev/tty failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "", 0) != 0){
+    printf("%s: write to /dev/tty failed\n", s);
+    exit(1);
+  }
+  close(fd);
+}
+
+// do /dev/null, /dev/zero and /dev/full read and write as they should?
+void
+pseudodevtest(char *s)
+{
+  enum { SZ = 3 * 4096 + 100 };
+  int fd, i;
+  char *p;
+
+  fd = open("/dev/null", O_RDWR);
+  if(fd < 0){
+    printf("%s: open /dev/null failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "abc", 3) != 3 || read(fd, buf, sizeof(buf)) != 0){
+    printf("%s: /dev/null is wrong\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // a read of several pages, not starting at a page boundary.
+  p = sbrk(SZ + 1);
+  memset(p, 'x', SZ + 1);
+  fd = open("/dev/zero", O_RDWR);
+  if(fd < 0 || read(fd, p + 1, SZ) != SZ || write(fd, "abc", 3) != 3){
+    printf("%s: /dev/zero read or write failed\n", s);
+    exit(1);
+  }
+  if(p[0] != 'x'){
+    printf("%s: /dev/zero wrote before the buffer\n", s);
+    exit(1);
+  }
+  for(i = 1; i <= SZ; i++){
+    if(p[i] != 0){
+      printf("%s: /dev/zero returned a non-zero byte\n", s);
+      exit(1);
+    }
+  }
+  if(read(fd, (char*)0xffffffffffff, 10) >= 0){
+    printf("%s: /dev/zero read to a bad address succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+  sbrk(-(SZ + 1));
+
+  fd = open("/dev/full", O_RDWR);
+  if(fd < 0 || read(fd, buf, 10) != 10 || buf[0] != 0 || write(fd, "abc", 3) >= 0){
+    printf("%s: /dev/full is wrong\n", s);
+    exit(1);
+  }
+  close(fd);
+}
+
+// does meminfo() account for the pages a process allocates,
+// and report the resident set size of the caller?
+void
+meminfotest(char *s)
+{
+  int i, n, found;
+  struct meminfo before, after;
+  struct procmem procs[NPROC];
+  char *p;
+
+  if(meminfo(&before, procs, NPROC) < 0){
+    printf("%s: meminfo failed\n", s);
+    exit(1);
+  }
+  if(before.free_pages > before.total_pages || before.bcache_pages == 0 ||
+     before.files.used > before.files.capacity ||
+     before.inodes.used == 0 || before.inodes.used > before.inodes.capacity ||
+     before.bufs.used > before.bufs.capacity){
+    printf("%s: bad statistics\n", s);
+    exit(1);
+  }
+
+  p = sbrk(10 * 4096);
+  if(p == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  // Write the pages, which are allocated only then.
+  for(i = 0; i < 10; i++)
+    p[i * 4096 + 4095] = 1;
+  n = meminfo(&after, procs, NPROC);
+  if(n <= 0 || n > NPROC){
+    printf("%s: meminfo returned %d\n", s, n);
+    exit(1);
+  }
+  if(after.total_pages != before.total_pages || after.free_pages + 10 > before.free_pages){
+    printf("%s: sbrk not accounted for\n", s);
+    exit(1);
+  }
+  found = 0;
+  for(i = 0; i < n; i++)
+    if(procs[i].pid == getpid() && procs[i].rss > 10)
+      found = 1;
+  if(!found){
+    printf("%s: rss of the caller not reported\n", s);
+    exit(1);
+  }
+  sbrk(-10 * 4096);
+
+  if(meminfo(&after, procs, 0) != 0 || meminfo(&after, procs, -1) >= 0){
+    printf("%s: bad count not rejected\n", s);
+    exit(1);
+  }
+}
+
+// do fdinfo() and meminfo() report which files a process holds?
+void
+fdinfotest(char *s)
+{
+  int i, n, fd, pid, nfiles, fds[2], status;
+  uint pipeid;
+  struct fdinfo info[NOFILE];
+  struct meminfo mem;
+  struct procmem procs[NPROC];
+  struct stat st;
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  fd = open("README", O_RDONLY);
+  if(fd < 0 || fstat(fd, &st) < 0){
+    printf("%s: open README failed\n", s);
+    exit(1);
+  }
+
+  n = fdinfo(getpid(), info, NOFILE);
+  if(n < 5){
+    printf("%s: fdinfo returned %d\n", s, n);
+    exit(1);
+  }
+  pipeid = 0;
+  for(i = 0; i < n; i++){
+    if(i > 0 && info[i].fd <= info[i-1].fd){
+      printf("%s: file descriptors out of order\n", s);
+      exit(1);
+    }
+    if(info[i].fd == fds[0]){
+      if(info[i].type != FD_PIPE || info[i].flags != FDINFO_READ){
+        printf("%s: bad read end of the pipe\n", s);
+        exit(1);
+      }
+      pipeid = info[i].ino;
+    }
+    // The write end comes after the read end.
+    if(info[i].fd == fds[1] && (info[i].type != FD_PIPE || info[i].flags != FDINFO_WRITE ||
+                                info[i].ino != pipeid)){
+      printf("%s: bad write end of the pipe\n", s);
+      exit(1);
+    }
+    if(info[i].fd == fd && (info[i].type != FD_INODE || info[i].dev != st.dev ||
+                            info[i].ino != st.ino)){
+      printf("%s: bad inode\n", s);
+      exit(1);
+    }
+  }
+  nfiles = n;
+
+  // A child waiting for the pipe can be inspected too.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(fd);
+    close(fds[1]);
+    read(fds[0], &status, 1);
+    exit(0);
+  }
+  close(fds[0]);
+  n = meminfo(&mem, procs, NPROC);
+  for(i = 0; i < n; i++){
+    if(procs[i].pid == getpid() && procs[i].nfiles != nfiles - 1){
+      printf("%s: meminfo reported %d open files\n", s, procs[i].nfiles);
+      exit(1);
+    }
+  }
+  for(i = 0; i < 100 && fdinfo(pid, info, NOFILE) != nfiles - 2; i++)
+    sleep(1);
+  if(i == 100 || fdinfo(pid, info, 1) != 1){
+    printf("%s: bad open files of the child\n", s);
+    exit(1);
+  }
+  close(fds[1]);
+  close(fd);
+  wait(&status);
+  if(fdinfo(pid, info, NOFILE) >= 0 || fdinfo(getpid(), info, -1) >= 0){
+    printf("%s: bad fdinfo not rejected\n", s);
+    exit(1);
+  }
+}
+
+// can a virtual console in the background be written without blocking?
+void
+vconsoletest(char *s)
+{
+  int fd, i;
+  struct stat st;
+
+  fd = open("/dev/tty1", O_RDWR);
+  if(fd < 0 || fstat(fd, &st) < 0 || st.type != T_DEVICE){
+    printf("%s: cannot open /dev/tty1\n", s);
+    exit(1);
+  }
+  // Much more than the output a background console keeps.
+  for(i = 0; i < 1000; i++){
+    if(write(fd, "vconsoletest\n", 13) != 13){
+      printf("%s: write to /dev/tty1 failed\n", s);
+      exit(1);
+    }
+  }
+  close(fd);
+}
+
+// can another process read the title that a process sets, and its exec arguments?
+void
+prctltest(char *s)
+{
+  int fds[2], pid, n;
+  char title[MAXPROCTITLE + 1], args[MAXPROCARGS], wargs[MAXPROCARGS], c;
+  char *worker = "prctltest worker with a long title";
+
+  n = prctl(PR_GET_ARGS, 0, args, sizeof(args));
+  if(n <= 0 || args[n-1] != 0){
+    printf("%s: wrong exec arguments\n", s);
+    exit(1);
+  }
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(fds[0]);
+    if(prctl(PR_SET_NAME, 0, worker, 0) != 0){
+      printf("%s: PR_SET_NAME failed\n", s);
+      exit(1);
+    }
+    write(fds[1], "x", 1);
+    // Wait for the parent to look.
+    sleep(1000);
+    exit(0);
+  }
+  close(fds[1]);
+  if(read(fds[0], &c, 1) != 1){
+    printf("%s: the worker did not set its title\n", s);
+    exit(1);
+  }
+  if(prctl(PR_GET_NAME, pid, title, sizeof(title)) != strlen(worker) ||
+     strcmp(title, worker) != 0){
+    printf("%s: PR_GET_NAME returned %s\n", s, title);
+    exit(1);
+  }
+  if(prctl(PR_GET_NAME, pid, title, 5) >= 0){
+    printf("%s: title into a short buffer succeeded\n", s);
+    exit(1);
+  }
+  // The worker keeps the arguments of its parent.
+  if(prctl(PR_GET_ARGS, pid, wargs, sizeof(wargs)) != n || memcmp(args, wargs, n) != 0){
+    printf("%s: wrong exec arguments of the worker\n", s);
+    exit(1);
+  }
+  if(prctl(PR_SET_NAME, pid, worker, 0) >= 0){
+    printf("%s: PR_SET_NAME of another process succeeded\n", s);
+    exit(1);
+  }
+  kill(pid);
+  wait(0);
+  close(fds[0]);
+}
+
+// the counters of a process do not advance while it sleeps,
+// unlike those of its CPU.
+void
+cyclestest(char *s)
+{
+  struct cycles real0, real1, virt0, virt1;
+
+  if(cycles(&real0, 0) < 0 || cycles(&virt0, CYCLES_VIRTUAL | CYCLES_SERIALIZE) < 0){
+    printf("%s: cycles failed\n", s);
+    exit(1);
+  }
+  sleep(5);
+  if(cycles(&virt1, CYCLES_VIRTUAL | CYCLES_SERIALIZE) < 0 || cycles(&real1, 0) < 0){
+    printf("%s: cycles failed\n", s);
+    exit(1);
+  }
+  if(virt1.cycle <= virt0.cycle || virt1.instret <= virt0.instret){
+    printf("%s: counters of the process did not advance\n", s);
+    exit(1);
+  }
+  if(virt1.cycle - virt0.cycle >= real1.cycle - real0.cycle){
+    printf("%s: counters of the process advanced during sleep\n", s);
+    exit(1);
+  }
+  if(cycles(&real0, 4) >= 0){
+    printf("%s: cycles with a bad flag succeeded!\n", s);
+    exit(1);
+  }
+}
+
+// perf() sets and reads the events of the performance counters,
+// and the counts of a process do not go back.
+void
+perftest(char *s)
+{
+  uint64 events[NPERFCTR], events2[NPERFCTR], counts0[NPERFCTR], counts1[NPERFCTR];
+  int i;
+
+  if(perf(PERF_GETEVENTS, 0, events) < 0 || perf(PERF_SETEVENTS, 0, events) < 0 ||
+     perf(PERF_GETEVENTS, 0, events2) < 0){
+    printf("%s: perf events failed\n", s);
+    exit(1);
+  }
+  if(memcmp(events, events2, sizeof(events)) != 0){
+    printf("%s: perf events changed\n", s);
+    exit(1);
+  }
+  if(perf(PERF_READ, 0, counts0) < 0 || perf(PERF_READ, getpid(), counts1) < 0){
+    printf("%s: PERF_READ failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < NPERFCTR; i++){
+    if(counts1[i] < counts0[i]){
+      printf("%s: count %d went back\n", s, i);
+      exit(1);
+    }
+  }
+  if(perf(PERF_READ, 12345, counts0) >= 0 || perf(0, 0, counts0) >= 0){
+    printf("%s: bad perf succeeded!\n", s);
+    exit(1);
+  }
+}
+
+// the latencies of syscalls are collected while perf() is asked to.
+void
+syslattest(char *s)
+{
+  uint64 h[NLATBUCKET], n;
+  int i;
+
+  if(perf(PERF_SETLATENCY, 1, 0) < 0){
+    printf("%s: PERF_SETLATENCY failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 100; i++)
+    getpid();
+  perf(PERF_SETLATENCY, 0, 0);
+  for(i = 0; i < 100; i++)
+    getpid();
+
+  if(perf(PERF_GETLATENCY, SYS_getpid, h) < 0){
+    printf("%s: PERF_GETLATENCY failed\n", s);
+    exit(1);
+  }
+  n = 0;
+  for(i = 0; i < NLATBUCKET; i++)
+    n += h[i];
+  if(n != 100){
+    printf("%s: %d getpid calls collected, not 100\n", s, (int)n);
+    exit(1);
+  }
+  if(perf(PERF_GETLATENCY, NSYSCALL, h) >= 0){
+    printf("%s: PERF_GETLATENCY of a bad syscall succeeded!\n", s);
+    exit(1);
+  }
+}
+
+// do files survive each disk I/O scheduling policy, and is the disk I/O
+// of a process accounted?
+void
+ioschedtest(char *s)
+{
+  char name[] = "ioschedN";
+  char buf[BSIZE];
+  int old, policy, i, j, fd, pid, xstatus;
+  struct iostat before, after;
+  struct ioqstat q;
+
+  old = iosched(IOSCHED_GETPOLICY, 0, 0);
+  if(old < 0){
+    printf("%s: IOSCHED_GETPOLICY failed\n", s);
+    exit(1);
+  }
+  if(iosched(IOSCHED_SETPOLICY, 3, 0) >= 0){
+    printf("%s: a bad policy was set!\n", s);
+    exit(1);
+  }
+
+  for(policy = IOSCHED_NOOP; policy <= IOSCHED_FAIR; policy++){
+    if(iosched(IOSCHED_SETPOLICY, policy, 0) < 0 || iosched(IOSCHED_GETPOLICY, 0, 0) != policy){
+      printf("%s: cannot set policy %d\n", s, policy);
+      exit(1);
+    }
+    // Two writers that compete for the disk.
+    for(i = 0; i < 2; i++){
+      pid = fork();
+      if(pid < 0){
+        printf("%s: fork failed\n", s);
+        exit(1);
+      }
+      if(pid == 0){
+        name[7] = '0' + i;
+        fd = open(name, O_CREATE | O_RDWR);
+        if(fd < 0){
+          printf("%s: create %s failed\n", s, name);
+          exit(1);
+        }
+        for(j = 0; j < 8; j++){
+          memset(buf, 'a' + policy * 2 + i, sizeof(buf));
+          if(write(fd, buf, sizeof(buf)) != sizeof(buf)){
+            printf("%s: write %s failed\n", s, name);
+            exit(1);
+          }
+        }
+        close(fd);
+        exit(0);
+      }
+    }
+    for(i = 0; i < 2; i++){
+      wait(&xstatus);
+      if(xstatus != 0)
+        exit(1);
+    }
+    for(i = 0; i < 2; i++){
+      name[7] = '0' + i;
+      fd = open(name, O_RDONLY);
+      if(fd < 0){
+        printf("%s: open %s failed\n", s, name);
+        exit(1);
+      }
+      for(j = 0; j < 8; j++){
+        if(read(fd, buf, sizeof(buf)) != sizeof(buf) ||
+           buf[0] != 'a' + policy * 2 + i || buf[BSIZE-1] != 'a' + policy * 2 + i){
+          printf("%s: %s corrupted under policy %d\n", s, name, policy);
+          exit(1);
+        }
+      }
+      close(fd);
+      unlink(name);
+    }
+  }
+  iosched(IOSCHED_SETPOLICY, old, 0);
+
+  // No other process commits our writes.
+  if(iosched(IOSCHED_STAT, 0, &before) < 0){
+    printf("%s: IOSCHED_STAT failed\n", s);
+    exit(1);
+  }
+  fd = open("ioschedstat", O_CREATE | O_RDWR);
+  if(fd < 0 || write(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: write ioschedstat failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("ioschedstat");
+  if(iosched(IOSCHED_STAT, getpid(), &after) < 0 || after.writes <= before.writes ||
+     after.wait <= before.wait){
+    printf("%s: writes not accounted\n", s);
+    exit(1);
+  }
+
+  if(iosched(IOSCHED_QSTAT, 0, &q) < 0 || q.requests == 0 || q.blocks < q.requests){
+    printf("%s: bad IOSCHED_QSTAT\n", s);
+    exit(1);
+  }
+  if(iosched(IOSCHED_STAT, 1000000, &before) >= 0){
+    printf("%s: IOSCHED_STAT of a bad pid succeeded!\n", s);
+    exit(1);
+  }
+}
+
+// does /dev/trace record the switches to a child in ftrace text, header first?
+void
+tracetest(char *s)
+{
+  char buf[256];
+  int fd, n, i, pid, found;
+
+  fd = open("/dev/trace", O_RDWR);
+  if(fd < 0){
+    printf("%s: open /dev/trace failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "1", 1) != 1){
+    printf("%s: enabling the trace failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(0);
+  wait(0);
+  write(fd, "0", 1);
+
+  n = read(fd, buf, sizeof(buf));
+  if(n < 13 || memcmp(buf, "# tracer: nop", 13) != 0){
+    printf("%s: the trace does not begin with its header\n", s);
+    exit(1);
+  }
+  found = 0;
+  do {
+    if(buf[n-1] != '\n'){
+      printf("%s: read a partial line\n", s);
+      exit(1);
+    }
+    for(i = 0; i + 12 <= n; i++)
+      if(memcmp(buf + i, "sched_switch", 12) == 0)
+        found = 1;
+  } while((n = read(fd, buf, sizeof(buf))) > 0);
+  if(n < 0 || !found){
+    printf("%s: no sched_switch in the trace\n", s);
+    exit(1);
+  }
+  if(write(fd, "x", 1) >= 0){
+    printf("%s: a bad command succeeded!\n", s);
+    exit(1);
+  }
+  close(fd);
+}
+
+// can we write to the virtio console port that qemu attaches to a file,
+// and does a port with nothing behind it refuse reads?
+void
+vporttest(char *s)
+{
+  char buf[8];
+  int fd;
+
+  fd = open("/dev/vport1", O_RDWR);
+  if(fd < 0){
+    printf("%s: open /dev/vport1 failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "hello\n", 6) != 6){
+    printf("%s: write to /dev/vport1 failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("/dev/vport3", O_RDWR);
+  if(fd < 0){
+    printf("%s: open /dev/vport3 failed\n", s);
+    exit(1);
+  }
+  if(read(fd, buf, sizeof(buf)) >= 0){
+    printf("%s: read from an absent port succeeded!\n", s);
+    exit(1);
+  }
+  close(fd);
+}
+
+// does nanosleep() sleep at least as long as asked, and reject bad intervals?
+void
+nanosleeptest(char *s)
+{
+  int t0, t1;
+  struct timespec ts;
+
+  ts.tv_sec = 0;
+  ts.tv_nsec = 1000000000;
+  if(nanosleep(&ts, 0) >= 0){
+    printf("%s: too many nanoseconds not rejected\n", s);
+    exit(1);
+  }
+  ts.tv_sec = -1;
+  ts.tv_nsec = 0;
+  if(nanosleep(&ts, 0) >= 0){
+    printf("%s: negative interval not rejected\n", s);
+    exit(1);
+  }
+
+  ts.tv_sec = 0;
+  ts.tv_nsec = 150000000;
+  t0 = uptime_as_micro();
+  if(nanosleep(&ts, &ts) < 0){
+    printf("%s: nanosleep failed\n", s);
+    exit(1);
+  }
+  t1 = uptime_as_micro();
+  if(t1 - t0 < 150000){
+    printf("%s: slept %d us instead of 150000\n", s, t1 - t0);
+    exit(1);
+  }
+}
+
+// does the wall clock start from the RTC, advance, and follow settimeofday()?
+void
+timeofdaytest(char *s)
+{
+  struct timeval tv0, tv1;
+
+  if(gettimeofday(&tv0, 0) < 0){
+    printf("%s: gettimeofday failed\n", s);
+    exit(1);
+  }
+  // The RTC of qemu starts from the time of the host, long after 2020.
+  if(tv0.tv_sec < 1577836800 || tv0.tv_usec < 0 || tv0.tv_usec >= 1000000){
+    printf("%s: bad time %ld.%ld\n", s, tv0.tv_sec, tv0.tv_usec);
+    exit(1);
+  }
+  sleep(2);
+  gettimeofday(&tv1, 0);
+  if(tv1.tv_sec * 1000000 + tv1.tv_usec <= tv0.tv_sec * 1000000 + tv0.tv_usec){
+    printf("%s: clock does not advance\n", s);
+    exit(1);
+  }
+
+  tv1.tv_usec = 1000000;
+  if(settimeofday(&tv1, 0) >= 0){
+    printf("%s: bad time not rejected\n", s);
+    exit(1);
+  }
+  tv1.tv_sec = tv0.tv_sec + 3600;
+  tv1.tv_usec = 0;
+  if(settimeofday(&tv1, 0) < 0){
+    printf("%s: settimeofday failed\n", s);
+    exit(1);
+  }
+  gettimeofday(&tv1, 0);
+  if(tv1.tv_sec < tv0.tv_sec + 3600 || tv1.tv_sec > tv0.tv_sec + 3610){
+    printf("%s: time not set\n", s);
+    exit(1);
+  }
+  // Put the clock back.
+  tv1.tv_sec -= 3600;
+  settimeofday(&tv1, 0);
+}
+
+// do stream and datagram sockets work over the loopback interface?
+void
+sockettest(char *s)
+{
+  struct sockaddr_in sa, peer;
+  char buf[512];
+  int ls, c, d, e, i, n, total, len, pid, xstatus;
+
+  memset(&sa, 0, sizeof(sa));
+  sa.sin_family = AF_INET;
+  sa.sin_port = htons(7000);
+  sa.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+
+  ls = socket(AF_INET, SOCK_STREAM, 0);
+  if(ls < 0 || bind(ls, (struct sockaddr*)&sa, sizeof(sa)) < 0 || listen(ls, 1) < 0){
+    printf("%s: cannot listen on 127.0.0.1:7000\n", s);
+    exit(1);
+  }
+  c = socket(AF_INET, SOCK_STREAM, 0);
+  if(bind(c, (struct sockaddr*)&sa, sizeof(sa)) >= 0){
+    printf("%s: port bound twice\n", s);
+    exit(1);
+  }
+  close(c);
+
+  // More than a receive buffer, so that the writer waits for the reader.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(ls);
+    c = socket(AF_INET, SOCK_STREAM, 0);
+    if(connect(c, (struct sockaddr*)&sa, sizeof(sa)) < 0){
+      printf("%s: connect failed\n", s);
+      exit(1);
+    }
+    for(i = 0; i < 20; i++){
+      memset(buf, 'a' + i, sizeof(buf));
+      if(write(c, buf, sizeof(buf)) != sizeof(buf)){
+        printf("%s: write failed\n", s);
+        exit(1);
+      }
+    }
+    close(c);
+    exit(0);
+  }
+  len = sizeof(peer);
+  c = accept(ls, (struct sockaddr*)&peer, &len);
+  if(c < 0 || len != sizeof(peer) || peer.sin_addr.s_addr != htonl(INADDR_LOOPBACK)){
+    printf("%s: accept failed\n", s);
+    exit(1);
+  }
+  total = 0;
+  while((n = read(c, buf, 100)) > 0){
+    for(i = 0; i < n; i++){
+      if(buf[i] != 'a' + (total + i) / 512){
+        printf("%s: wrong byte at %d\n", s, total + i);
+        exit(1);
+      }
+    }
+    total += n;
+  }
+  if(n < 0 || total != 20 * 512){
+    printf("%s: read %d bytes, expected %d\n", s, total, 20 * 512);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+  close(c);
+  close(ls);
+
+  // Nobody listens on the port any more, and 10.0.0.1 is unreachable.
+  c = socket(AF_INET, SOCK_STREAM, 0);
+  if(connect(c, (struct sockaddr*)&sa, sizeof(sa)) >= 0){
+    printf("%s: connected to a closed port\n", s);
+    exit(1);
+  }
+  sa.sin_addr.s_addr = htonl(0x0a000001);
+  if(connect(c, (struct sockaddr*)&sa, sizeof(sa)) >= 0){
+    printf("%s: connected to an unreachable address\n", s);
+    exit(1);
+  }
+  close(c);
+
+  d = socket(AF_INET, SOCK_DGRAM, 0);
+  e = socket(AF_INET, SOCK_DGRAM, 0);
+  sa.sin_addr.s_addr = htonl(INADDR_ANY);
+  if(d < 0 || e < 0 || bind(d, (struct sockaddr*)&sa, sizeof(sa)) < 0){
+    printf("%s: cannot bind a datagram socket\n", s);
+    exit(1);
+  }
+  sa.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+  if(sendto(e, "hello", 5, 0, (struct sockaddr*)&sa, sizeof(sa)) != 5 ||
+     sendto(e, "world!", 6, 0, (struct sockaddr*)&sa, sizeof(sa)) != 6){
+    printf("%s: sendto failed\n", s);
+    exit(1);
+  }
+  len = sizeof(peer);
+  n = recvfrom(d, buf, 3, 0, (struct sockaddr*)&peer, &len);
+  if(n != 3 || memcmp(buf, "hel", 3) != 0){
+    printf("%s: recvfrom returned %d\n", s, n);
+    exit(1);
+  }
+  // The rest of the first datagram was discarded.
+  n = recv(d, buf, sizeof(buf), 0);
+  if(n != 6 || memcmp(buf, "world!", 6) != 0){
+    printf("%s: recv returned %d\n", s, n);
+    exit(1);
+  }
+  len = sizeof(sa);
+  if(getsockname(e, (struct sockaddr*)&sa, &len) < 0 || sa.sin_port != peer.sin_port ||
+     peer.sin_addr.s_addr != htonl(INADDR_LOOPBACK)){
+    printf("%s: wrong sender\n", s);
+    exit(1);
+  }
+  close(d);
+  close(e);
+}
+
+// do UNIX domain sockets bound to socket files, and socket pairs, work?
+void
+unixsockettest(char *s)
+{
+  struct sockaddr_un sa, peer;
+  struct stat st;
+  char buf[512];
+  int ls, c, d, e, sv[2], i, n, total, len, pid, xstatus;
+
+  unlink("unixsock");
+  unlink("unixdgram");
+  unlink("unixdgram2");
+  memset(&sa, 0, sizeof(sa));
+  sa.sun_family = AF_UNIX;
+  strcpy(sa.sun_path, "unixsock");
+
+  ls = socket(AF_UNIX, SOCK_STREAM, 0);
+  if(ls < 0 || bind(ls, (struct sockaddr*)&sa, sizeof(sa)) < 0 || listen(ls, 1) < 0){
+    printf("%s: cannot listen on unixsock\n", s);
+    exit(1);
+  }
+  if(stat("unixsock", &st) < 0 || st.type != T_SOCK){
+    printf("%s: unixsock is not a socket file\n", s);
+    exit(1);
+  }
+  if(open("unixsock", O_RDWR) >= 0){
+    printf("%s: opened a socket file\n", s);
+    exit(1);
+  }
+  c = socket(AF_UNIX, SOCK_STREAM, 0);
+  if(bind(c, (struct sockaddr*)&sa, sizeof(sa)) >= 0){
+    printf("%s: path bound twice\n", s);
+    exit(1);
+  }
+  close(c);
+
+  // More than a receive buffer, so that the writer waits for the reader.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(ls);
+    c = socket(AF_UNIX, SOCK_STREAM, 0);
+    if(connect(c, (struct sockaddr*)&sa, sizeof(sa)) < 0){
+      printf("%s: connect failed\n", s);
+      exit(1);
+    }
+    for(i = 0; i < 20; i++){
+      memset(buf, 'a' + i, sizeof(buf));
+      if(write(c, buf, sizeof(buf)) != sizeof(buf)){
+        printf("%s: write failed\n", s);
+        exit(1);
+      }
+    }
+    close(c);
+    exit(0);
+  }
+  c = accept(ls, 0, 0);
+  if(c < 0){
+    printf("%s: accept failed\n", s);
+    exit(1);
+  }
+  total = 0;
+  while((n = read(c, buf, 100)) > 0){
+    for(i = 0; i < n; i++){
+      if(buf[i] != 'a' + (total + i) / 512){
+        printf("%s: wrong byte at %d\n", s, total + i);
+        exit(1);
+      }
+    }
+    total += n;
+  }
+  if(n < 0 || total != 20 * 512){
+    printf("%s: read %d bytes, expected %d\n", s, total, 20 * 512);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+  close(c);
+  close(ls);
+
+  // The file stays, but nobody listens on it any more.
+  c = socket(AF_UNIX, SOCK_STREAM, 0);
+  if(connect(c, (struct sockaddr*)&sa, sizeof(sa)) >= 0){
+    printf("%s: connected to a closed socket\n", s);
+    exit(1);
+  }
+  close(c);
+  unlink("unixsock");
+
+  if(socketpair(AF_UNIX, SOCK_STREAM, 0, sv) < 0){
+    printf("%s: socketpair failed\n", s);
+    exit(1);
+  }
+  if(write(sv[0], "ping", 4) != 4 || read(sv[1], buf, sizeof(buf)) != 4 ||
+     memcmp(buf, "ping", 4) != 0){
+    printf("%s: socketpair does not carry data\n", s);
+    exit(1);
+  }
+  close(sv[0]);
+  if(read(sv[1], buf, sizeof(buf)) != 0 || write(sv[1], "pong", 4) >= 0){
+    printf("%s: the peer of a socket pair did not close\n", s);
+    exit(1);
+  }
+  close(sv[1]);
+
+  d = socket(AF_UNIX, SOCK_DGRAM, 0);
+  e = socket(AF_UNIX, SOCK_DGRAM, 0);
+  strcpy(sa.sun_path, "unixdgram");
+  if(d < 0 || e < 0 || bind(d, (struct sockaddr*)&sa, sizeof(sa)) < 0){
+    printf("%s: cannot bind a datagram socket\n", s);
+    exit(1);
+  }
+  if(sendto(e, "hello", 5, 0, (struct sockaddr*)&sa, sizeof(sa)) != 5){
+    printf("%s: sendto failed\n", s);
+    exit(1);
+  }
+  strcpy(sa.sun_path, "unixdgram2");
+  if(bind(e, (struct sockaddr*)&sa, sizeof(sa)) < 0){
+    printf("%s: cannot bind a datagram socket\n", s);
+    exit(1);
+  }
+  strcpy(sa.sun_path, "unixdgram");
+  if(sendto(e, "world!", 6, 0, (struct sockaddr*)&sa, sizeof(sa)) != 6){
+    printf("%s: sendto failed\n", s);
+    exit(1);
+  }
+  // The first datagram is from a socket without a name.
+  len = sizeof(peer);
+  n = recvfrom(d, buf, 3, 0, (struct sockaddr*)&peer, &len);
+  if(n != 3 || memcmp(buf, "hel", 3) != 0 || len != sizeof(peer.sun_family)){
+    printf("%s: recvfrom returned %d\n", s, n);
+    exit(1);
+  }
+  len = sizeof(peer);
+  n = recvfrom(d, buf, sizeof(buf), 0, (struct sockaddr*)&peer, &len);
+  if(n != 6 || memcmp(buf, "world!", 6) != 0 || strcmp(peer.sun_path, "unixdgram2") != 0){
+    printf("%s: recvfrom returned %d\n", s, n);
+    exit(1);
+  }
+  len = sizeof(peer);
+  if(getsockname(d, (struct sockaddr*)&peer, &len) < 0 || strcmp(peer.sun_path, "unixdgram") != 0){
+    printf("%s: wrong name\n", s);
+    exit(1);
+  }
+  close(d);
+  close(e);
+  unlink("unixdgram");
+  unlink("unixdgram2");
+}
+
+// does an epoll report the ready pipes of its interest list, in level- and edge-triggered modes?
+void
+epolltest(char *s)
+{
+  struct epoll_event ev, evs[4];
+  int ep, p[2], q[2], n, i, pid, seen;
+
+  ep = epoll_create(1);
+  if(ep < 0){
+    printf("%s: epoll_create failed\n", s);
+    exit(1);
+  }
+  if(pipe(p) < 0 || pipe(q) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  ev.events = EPOLLIN;
+  ev.data = 1;
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, p[0], &ev) < 0){
+    printf("%s: cannot add a pipe\n", s);
+    exit(1);
+  }
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, p[0], &ev) >= 0){
+    printf("%s: pipe added twice\n", s);
+    exit(1);
+  }
+  ev.events = EPOLLIN | EPOLLET;
+  ev.data = 2;
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, q[0], &ev) < 0){
+    printf("%s: cannot add a pipe edge-triggered\n", s);
+    exit(1);
+  }
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, ep, &ev) >= 0){
+    printf("%s: epoll watches itself\n", s);
+    exit(1);
+  }
+  if(epoll_wait(ep, evs, 4, 0) != 0 || epoll_wait(ep, evs, 4, 100) != 0){
+    printf("%s: empty pipes are ready\n", s);
+    exit(1);
+  }
+
+  // A write by another process wakes up the wait.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(2);
+    write(p[1], "x", 1);
+    exit(0);
+  }
+  n = epoll_wait(ep, evs, 4, -1);
+  wait(0);
+  if(n != 1 || evs[0].data != 1 || evs[0].events != EPOLLIN){
+    printf("%s: written pipe not reported\n", s);
+    exit(1);
+  }
+
+  // The unread pipe stays ready, while the edge-triggered one is reported once per write.
+  write(q[1], "x", 1);
+  n = epoll_wait(ep, evs, 4, 0);
+  seen = 0;
+  for(i = 0; i < n; i++)
+    seen |= 1 << evs[i].data;
+  if(n != 2 || seen != 0x6){
+    printf("%s: expected both pipes, got %d events\n", s, n);
+    exit(1);
+  }
+  n = epoll_wait(ep, evs, 4, 0);
+  if(n != 1 || evs[0].data != 1){
+    printf("%s: edge-triggered pipe reported twice\n", s);
+    exit(1);
+  }
+  write(q[1], "x", 1);
+  if(epoll_wait(ep, evs, 4, 0) != 2){
+    printf("%s: second write not reported\n", s);
+    exit(1);
+  }
+
+  if(epoll_ctl(ep, EPOLL_CTL_DEL, p[0], &ev) < 0 || epoll_wait(ep, evs, 4, 0) != 0){
+    printf("%s: removed pipe reported\n", s);
+    exit(1);
+  }
+  ev.events = EPOLLOUT;
+  ev.data = 3;
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, p[1], &ev) < 0){
+    printf("%s: cannot add a write end\n", s);
+    exit(1);
+  }
+  n = epoll_wait(ep, evs, 4, 0);
+  if(n != 1 || evs[0].data != 3 || evs[0].events != EPOLLOUT){
+    printf("%s: write end not writable\n", s);
+    exit(1);
+  }
+  // Closing a file removes it from the interest list.
+  close(p[1]);
+  if(epoll_wait(ep, evs, 4, 0) != 0){
+    printf("%s: closed pipe reported\n", s);
+    exit(1);
+  }
+  close(p[0]);
+  close(q[0]);
+  close(q[1]);
+  close(ep);
+}
+
+// do eventfds and timerfds count, and wake up epolls?
+void
+eventfdtest(char *s)
+{
+  struct epoll_event ev, evs[2];
+  struct itimerspec its, old;
+  uint64 v;
+  int efd, sfd, tfd, ep, pid;
+
+  efd = eventfd(3, 0);
+  ep = epoll_create(1);
+  if(efd < 0 || ep < 0){
+    printf("%s: eventfd or epoll_create failed\n", s);
+    exit(1);
+  }
+  if(read(efd, &v, sizeof(v)) != sizeof(v) || v != 3){
+    printf("%s: wrong initial count\n", s);
+    exit(1);
+  }
+  v = 2;
+  write(efd, &v, sizeof(v));
+  v = 5;
+  write(efd, &v, sizeof(v));
+  if(read(efd, &v, sizeof(v)) != sizeof(v) || v != 7){
+    printf("%s: writes not added\n", s);
+    exit(1);
+  }
+  ev.events = EPOLLIN;
+  ev.data = efd;
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, efd, &ev) < 0 || epoll_wait(ep, evs, 2, 0) != 0){
+    printf("%s: zero eventfd is ready\n", s);
+    exit(1);
+  }
+  v = 1;
+  write(efd, &v, sizeof(v));
+  if(epoll_wait(ep, evs, 2, 0) != 1 || evs[0].data != efd){
+    printf("%s: signaled eventfd not reported\n", s);
+    exit(1);
+  }
+  read(efd, &v, sizeof(v));
+
+  // A semaphore eventfd is taken 1 at a time, and a read waits for a write by another process.
+  sfd = eventfd(2, EFD_SEMAPHORE);
+  if(read(sfd, &v, sizeof(v)) != sizeof(v) || v != 1 ||
+     read(sfd, &v, sizeof(v)) != sizeof(v) || v != 1){
+    printf("%s: semaphore not taken by 1\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(2);
+    v = 1;
+    write(sfd, &v, sizeof(v));
+    exit(0);
+  }
+  if(read(sfd, &v, sizeof(v)) != sizeof(v) || v != 1){
+    printf("%s: semaphore not released\n", s);
+    exit(1);
+  }
+  wait(0);
+
+  // A periodic timer expires while the epoll waits.
+  tfd = timerfd_create(CLOCK_MONOTONIC, 0);
+  if(tfd < 0){
+    printf("%s: timerfd_create failed\n", s);
+    exit(1);
+  }
+  if(timerfd_gettime(tfd, &its) < 0 || its.it_value.tv_sec != 0 || its.it_value.tv_nsec != 0){
+    printf("%s: new timer is armed\n", s);
+    exit(1);
+  }
+  ev.data = tfd;
+  if(epoll_ctl(ep, EPOLL_CTL_ADD, tfd, &ev) < 0){
+    printf("%s: cannot add a timerfd\n", s);
+    exit(1);
+  }
+  its.it_interval.tv_sec = 0;
+  its.it_interval.tv_nsec = 100 * 1000 * 1000;
+  its.it_value = its.it_interval;
+  if(timerfd_settime(tfd, 0, &its, 0) < 0){
+    printf("%s: timerfd_settime failed\n", s);
+    exit(1);
+  }
+  if(epoll_wait(ep, evs, 2, -1) != 1 || evs[0].data != tfd){
+    printf("%s: expired timer not reported\n", s);
+    exit(1);
+  }
+  if(read(tfd, &v, sizeof(v)) != sizeof(v) || v < 1){
+    printf("%s: no expiration counted\n", s);
+    exit(1);
+  }
+  memset(&its, 0, sizeof(its));
+  if(timerfd_settime(tfd, 0, &its, &old) < 0 || old.it_interval.tv_nsec != 100 * 1000 * 1000){
+    printf("%s: wrong old setting\n", s);
+    exit(1);
+  }
+  if(epoll_wait(ep, evs, 2, 300) != 0){
+    printf("%s: disarmed timer expired\n", s);
+    exit(1);
+  }
+  if(write(tfd, &v, sizeof(v)) >= 0){
+    printf("%s: wrote a timerfd\n", s);
+    exit(1);
+  }
+  close(efd);
+  close(sfd);
+  close(tfd);
+  close(ep);
+}
+
+// do message queues hand out messages by priority, wait, and obey RLIMIT_MSGQUEUE?
+void
+mqueuetest(char *s)
+{
+  struct mq_attr attr;
+  struct rlimit rl, old;
+  char buf[64];
+  uint prio;
+  int mq, mq2, pid;
+
+  mq_unlink("/mqtest");
+  attr.mq_flags = 0;
+  attr.mq_maxmsg = 4;
+  attr.mq_msgsize = sizeof(buf);
+  mq = mq_open("/mqtest", O_RDWR|O_CREATE, &attr);
+  if(mq < 0){
+    printf("%s: mq_open failed\n", s);
+    exit(1);
+  }
+  if(mq_open("/mqnone", O_RDWR, 0) >= 0 || mq_open("bad", O_RDWR|O_CREATE, 0) >= 0){
+    printf("%s: opened a missing queue or a bad name\n", s);
+    exit(1);
+  }
+  mq_send(mq, "low", 4, 1);
+  mq_send(mq, "high", 5, 7);
+  mq_send(mq, "low2", 5, 1);
+  if(mq_getattr(mq, &attr) < 0 || attr.mq_maxmsg != 4 || attr.mq_curmsgs != 3){
+    printf("%s: wrong attributes\n", s);
+    exit(1);
+  }
+  if(mq_receive(mq, buf, sizeof(buf), &prio) != 5 || strcmp(buf, "high") != 0 || prio != 7 ||
+     mq_receive(mq, buf, sizeof(buf), &prio) != 4 || strcmp(buf, "low") != 0 || prio != 1 ||
+     mq_receive(mq, buf, sizeof(buf), 0) != 5 || strcmp(buf, "low2") != 0){
+    printf("%s: messages out of order\n", s);
+    exit(1);
+  }
+  if(mq_receive(mq, buf, 8, 0) >= 0 || mq_send(mq, buf, sizeof(buf) + 1, 0) >= 0){
+    printf("%s: message size not checked\n", s);
+    exit(1);
+  }
+
+  // A receive waits for a send by another process, which opens the queue by name.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(2);
+    mq2 = mq_open("/mqtest", O_WRONLY, 0);
+    if(mq2 < 0 || mq_send(mq2, "wake", 5, 0) < 0 || mq_receive(mq2, buf, sizeof(buf), 0) >= 0)
+      exit(1);
+    exit(0);
+  }
+  if(mq_receive(mq, buf, sizeof(buf), 0) != 5 || strcmp(buf, "wake") != 0){
+    printf("%s: waiting receive not woken\n", s);
+    exit(1);
+  }
+  wait(&pid);
+  if(pid != 0){
+    printf("%s: write-only queue received\n", s);
+    exit(1);
+  }
+
+  // An unlinked queue stays usable until closed, and its name can be reused.
+  mq_send(mq, "kept", 5, 0);
+  if(mq_unlink("/mqtest") < 0 || mq_unlink("/mqtest") >= 0){
+    printf("%s: mq_unlink failed\n", s);
+    exit(1);
+  }
+  if(mq_receive(mq, buf, sizeof(buf), 0) != 5 || strcmp(buf, "kept") != 0){
+    printf("%s: unlinked queue lost a message\n", s);
+    exit(1);
+  }
+  close(mq);
+
+  // Creating a queue fails once the queues would exceed RLIMIT_MSGQUEUE.
+  if(getrlimit(RLIMIT_MSGQUEUE, &old) < 0){
+    printf("%s: getrlimit failed\n", s);
+    exit(1);
+  }
+  rl.rlim_cur = sizeof(buf) * 4 + 1;
+  rl.rlim_max = old.rlim_max;
+  if(setrlimit(RLIMIT_MSGQUEUE, &rl) < 0){
+    printf("%s: setrlimit failed\n", s);
+    exit(1);
+  }
+  mq = mq_open("/mqtest", O_RDWR|O_CREATE, &attr);
+  mq2 = mq_open("/mqtest2", O_RDWR|O_CREATE, &attr);
+  if(mq < 0 || mq2 >= 0){
+    printf("%s: RLIMIT_MSGQUEUE not enforced\n", s);
+    exit(1);
+  }
+  mq_unlink("/mqtest");
+  close(mq);
+  if(setrlimit(RLIMIT_MSGQUEUE, &old) < 0){
+    printf("%s: cannot restore the limit\n", s);
+    exit(1);
+  }
+}
+
+// do named semaphores count, and wake up waiting processes?
+void
+semtest(char *s)
+{
+  int sem, sem2, pid, value, i;
+
+  sem_unlink("/semtest");
+  sem = sem_open("/semtest", O_CREATE, 2);
+  if(sem < 0){
+    printf("%s: sem_open failed\n", s);
+    exit(1);
+  }
+  if(sem_open("/semnone", 0, 0) >= 0 || sem_open("/a/b", O_CREATE, 0) >= 0 ||
+     sem_open("/semtest2", O_CREATE, SEM_VALUE_MAX + 1U) >= 0){
+    printf("%s: opened a missing semaphore or a bad one\n", s);
+    exit(1);
+  }
+  if(sem_wait(sem) < 0 || sem_trywait(sem) < 0 || sem_trywait(sem) >= 0){
+    printf("%s: semaphore not taken\n", s);
+    exit(1);
+  }
+
+  // A wait waits for posts by other processes, which open the semaphore by name.
+  for(i = 0; i < 2; i++){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      sleep(2);
+      sem2 = sem_open("/semtest", 0, 0);
+      if(sem2 < 0 || sem_post(sem2) < 0)
+        exit(1);
+      exit(0);
+    }
+  }
+  if(sem_wait(sem) < 0 || sem_wait(sem) < 0){
+    printf("%s: waiting sem_wait failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 2; i++){
+    wait(&pid);
+    if(pid != 0){
+      printf("%s: sem_post failed\n", s);
+      exit(1);
+    }
+  }
+
+  // An unlinked semaphore stays usable until closed, and its name can be reused.
+  sem_post(sem);
+  if(sem_unlink("/semtest") < 0 || sem_unlink("/semtest") >= 0){
+    printf("%s: sem_unlink failed\n", s);
+    exit(1);
+  }
+  if(sem_getvalue(sem, &value) < 0 || value != 1){
+    printf("%s: wrong value %d\n", s, value);
+    exit(1);
+  }
+  sem2 = sem_open("/semtest", O_CREATE, 5);
+  if(sem2 < 0 || sem_getvalue(sem2, &value) < 0 || value != 5){
+    printf("%s: name not reused\n", s);
+    exit(1);
+  }
+  if(read(sem, &value, sizeof(value)) >= 0){
+    printf("%s: read a semaphore\n", s);
+    exit(1);
+  }
+  sem_unlink("/semtest");
+  close(sem);
+  close(sem2);
+}
+
+// is the text of a program mapped read-only, so that exec can share it among processes?
+void
+textsharetest(char *s)
+{
+  struct pmapinfo info;
+  struct pmapregion regions[4];
+  int pid, xstatus, fds[2];
+  char *text = 0;
+
+  if(pmap(getpid(), &info, regions, 4) < 1 || regions[0].start != 0 ||
+     (regions[0].perm & (PMAP_X|PMAP_W)) != PMAP_X){
+    printf("%s: text is not read-only\n", s);
+    exit(1);
+  }
+
+  // A process writing to its text is killed.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    *text = 'x';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: wrote to the text\n", s);
+    exit(1);
+  }
+
+  // The kernel does not write to the text either.
+  if(pipe(fds) != 0){
+    printf("%s: pipe() failed\n", s);
+    exit(1);
+  }
+  write(fds[1], "x", 1);
+  if(read(fds[0], text, 1) >= 0){
+    printf("%s: read into the text\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+
+  // Processes running the same program still run independently.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    char *echoargv[] = { "echo", "OK", 0 };
+    close(1);
+    exec("echo", echoargv);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: exec echo failed\n", s);
+    exit(1);
+  }
+}
+
+// run path in a child, and return 0 if it exited 0.
+int
+execrun(char *path)
+{
+  char *echoargv[] = { "echo", "OK", 0 };
+  int pid, xstatus;
+
+  pid = fork();
+  if(pid < 0)
+    return -1;
+  if(pid == 0){
+    close(1);
+    exec(path, echoargv);
+    exit(1);
+  }
+  wait(&xstatus);
+  return xstatus == 0 ? 0 : -1;
+}
+
+// copy echo to path.
+int
+copyecho(char *path)
+{
+  static char buf[1024];
+  int fd, fd1, n;
+
+  fd = open("echo", O_RDONLY);
+  fd1 = open(path, O_CREATE|O_WRONLY|O_TRUNC);
+  if(fd < 0 || fd1 < 0)
+    return -1;
+  while((n = read(fd, buf, sizeof(buf))) > 0){
+    if(write(fd1, buf, n) != n)
+      return -1;
+  }
+  close(fd);
+  close(fd1);
+  return 0;
+}
+
+// does exec() of a program it has cached notice that the program changed?
+void
+execcachetest(char *s)
+{
+  int fd, i;
+
+  if(copyecho("execcache") < 0){
+    printf("%s: cannot copy echo\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 3; i++){
+    if(execrun("execcache") < 0){
+      printf("%s: exec of the copy failed\n", s);
+      exit(1);
+    }
+  }
+
+  // A truncated program does not run any more.
+  fd = open("execcache", O_WRONLY|O_TRUNC);
+  if(fd < 0 || write(fd, "junk", 4) != 4){
+    printf("%s: cannot truncate the copy\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(execrun("execcache") == 0){
+    printf("%s: ran a truncated program\n", s);
+    exit(1);
+  }
+
+  // Neither does a program whose header is overwritten.
+  if(copyecho("execcache") < 0 || execrun("execcache") < 0){
+    printf("%s: exec of a new copy failed\n", s);
+    exit(1);
+  }
+  fd = open("execcache", O_WRONLY);
+  if(fd < 0 || write(fd, "junk", 4) != 4){
+    printf("%s: cannot overwrite the copy\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(execrun("execcache") == 0){
+    printf("%s: ran an overwritten program\n", s);
+    exit(1);
+  }
+  unlink("execcache");
+}
+
+// make the program path name interp as its program interpreter, by
+// appending a copy of its program headers with PT_INTERP added, and interp.
+int
+addinterp(char *path, char *interp)
+{
+  static struct proghdr ph[8];
+  struct elfhdr elf;
+  struct stat st;
+  uint64 end;
+  int fd, n, ok;
+
+  fd = open(path, O_RDWR);
+  if(fd < 0)
+    return -1;
+  if(read(fd, &elf, sizeof(elf)) != sizeof(elf) || elf.phnum >= 8 ||
+     lseek(fd, elf.phoff, SEEK_SET) < 0 ||
+     read(fd, ph, elf.phnum * sizeof(ph[0])) != elf.phnum * sizeof(ph[0]) ||
+     fstat(fd, &st) < 0){
+    close(fd);
+    return -1;
+  }
+  n = elf.phnum;
+  end = (st.size + 7) & ~7;
+  memset(&ph[n], 0, sizeof(ph[n]));
+  ph[n].type = ELF_PROG_INTERP;
+  ph[n].off = end + (n + 1) * sizeof(ph[0]);
+  ph[n].filesz = strlen(interp) + 1;
+  elf.phoff = end;
+  elf.phnum = n + 1;
+  ok = lseek(fd, st.size, SEEK_SET) >= 0 &&
+       write(fd, "\0\0\0\0\0\0\0", end - st.size) == end - st.size &&
+       write(fd, ph, (n + 1) * sizeof(ph[0])) == (n + 1) * sizeof(ph[0]) &&
+       write(fd, interp, strlen(interp) + 1) == strlen(interp) + 1 &&
+       lseek(fd, 0, SEEK_SET) == 0 &&
+       write(fd, &elf, sizeof(elf)) == sizeof(elf);
+  close(fd);
+  return ok ? 0 : -1;
+}
+
+// does exec() start a program with the program interpreter it names?
+void
+interptest(char *s)
+{
+  if(copyecho("interp") < 0 || addinterp("interp", "/ld") < 0){
+    printf("%s: cannot add an interpreter\n", s);
+    exit(1);
+  }
+  if(execrun("interp") < 0){
+    printf("%s: exec with an interpreter failed\n", s);
+    exit(1);
+  }
+
+  // The interpreter must exist, and must not name an interpreter itself.
+  if(copyecho("interp2") < 0 || addinterp("interp2", "interp") < 0 ||
+     copyecho("interp") < 0 || addinterp("interp", "/nointerp") < 0){
+    printf("%s: cannot add an interpreter\n", s);
+    exit(1);
+  }
+  if(execrun("interp") == 0){
+    printf("%s: ran with a missing interpreter\n", s);
+    exit(1);
+  }
+  if(execrun("interp2") == 0){
+    printf("%s: ran with an interpreter of an interpreter\n", s);
+    exit(1);
+  }
+  unlink("interp");
+  unlink("interp2");
+}
+
+// does brk() grow and shrink the heap, freeing and unmapping the pages?
+void
+brktest(char *s)
+{
+  struct pmapinfo info, info1;
+  char *start, *p;
+  int pid, xstatus;
+
+  start = sbrk(0);
+  if(brk(start + 10*PGSIZE) != 0 || sbrk(0) != start + 10*PGSIZE){
+    printf("%s: brk failed to grow the heap\n", s);
+    exit(1);
+  }
+  // A page is allocated when it is first written.
+  for(p = start; p < start + 10*PGSIZE; p += PGSIZE)
+    *p = 'x';
+  p = start + 9*PGSIZE;
+  if(pmap(getpid(), &info, 0, 0) != 0){
+    printf("%s: pmap failed\n", s);
+    exit(1);
+  }
+  if(brk(start + PGSIZE/2) != 0 || sbrk(0) != start + PGSIZE/2){
+    printf("%s: brk failed to shrink the heap\n", s);
+    exit(1);
+  }
+  if(pmap(getpid(), &info1, 0, 0) != 0 || info1.rss > info.rss - 9){
+    printf("%s: brk did not free the pages\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    *p = 'y';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: wrote to a freed page\n", s);
+    exit(1);
+  }
+
+  // The heap does not shrink into the program, nor grow over the trap frame.
+  if(brk(0) == 0 || brk((void*)-1) == 0){
+    printf("%s: brk to a bad address succeeded\n", s);
+    exit(1);
+  }
+  if(brk(start) != 0){
+    printf("%s: brk failed to restore the heap\n", s);
+    exit(1);
+  }
+}
+
+// does madvise() free the pages of a range, which read as zeros after that,
+// and does MADV_FREE keep the pages that are written again?
+void
+madvisetest(char *s)
+{
+  struct pmapinfo info, info1;
+  char *a, *p;
+  int i, fds[2], pid, xstatus;
+
+  a = sbrk(0);
+  if((uint64)a % PGSIZE)
+    sbrk(PGSIZE - (uint64)a % PGSIZE);
+  p = sbrk(10*PGSIZE);
+  for(i = 0; i < 10; i++)
+    p[i*PGSIZE] = 'a' + i;
+  if(pmap(getpid(), &info, 0, 0) != 0){
+    printf("%s: pmap failed\n", s);
+    exit(1);
+  }
+  if(madvise(p, 10*PGSIZE, MADV_DONTNEED) != 0){
+    printf("%s: madvise(MADV_DONTNEED) failed\n", s);
+    exit(1);
+  }
+  if(pmap(getpid(), &info1, 0, 0) != 0 || info1.rss > info.rss - 10){
+    printf("%s: MADV_DONTNEED did not free the pages\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 10; i++){
+    if(p[i*PGSIZE] != 0){
+      printf("%s: freed page %d not zero\n", s, i);
+      exit(1);
+    }
+  }
+  p[0] = 'x';
+  if(p[0] != 'x' || p[PGSIZE] != 0){
+    printf("%s: write to a freed page went wrong\n", s);
+    exit(1);
+  }
+
+  // The kernel writes to a freed page as well.
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  write(fds[1], "y", 1);
+  if(read(fds[0], p + 2*PGSIZE, 1) != 1 || p[2*PGSIZE] != 'y' || p[3*PGSIZE] != 0){
+    printf("%s: read into a freed page failed\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+
+  // The text of the program is left as it is.
+  if(madvise(0, PGSIZE, MADV_DONTNEED) != 0){
+    printf("%s: madvise of the text failed\n", s);
+    exit(1);
+  }
+
+  for(i = 0; i < 10; i++)
+    p[i*PGSIZE] = 'a' + i;
+  if(madvise(p, 10*PGSIZE, MADV_FREE) != 0){
+    printf("%s: madvise(MADV_FREE) failed\n", s);
+    exit(1);
+  }
+  p[0] = 'z';
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    p[0] = 'c';
+    p[PGSIZE] = 'c';
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child could not write to lazily freed pages\n", s);
+    exit(1);
+  }
+  if(p[0] != 'z' || (p[PGSIZE] != 'b' && p[PGSIZE] != 0)){
+    printf("%s: lazily freed pages went wrong\n", s);
+    exit(1);
+  }
+
+  if(madvise(p + 1, PGSIZE, MADV_DONTNEED) == 0 ||
+     madvise(p, PGSIZE, 1234) == 0 ||
+     madvise(p, 100*PGSIZE, MADV_DONTNEED) == 0){
+    printf("%s: bad madvise succeeded\n", s);
+    exit(1);
+  }
+  sbrk(-10*PGSIZE);
+}
+
+// does wss() estimate the working set from the pages a process accesses between scans?
+void
+wsstest(char *s)
+{
+  struct wssinfo info;
+  char *p;
+  int i, t, pid, xstatus;
+
+  if(wss(0, -2, 0) >= 0 || wss(-5, 1, 0) >= 0){
+    printf("%s: bad wss succeeded\n", s);
+    exit(1);
+  }
+  p = sbrk(64*PGSIZE);
+  for(i = 0; i < 64; i++)
+    p[i*PGSIZE] = i;
+  if(wss(0, 1, &info) != 0 || info.interval != 1 || info.scans != 0){
+    printf("%s: wss failed to select the process\n", s);
+    exit(1);
+  }
+
+  // A child is not scanned.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(wss(0, -1, &info) != 0 || info.interval != 0)
+      exit(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child inherited the scans\n", s);
+    exit(1);
+  }
+
+  // Touch only the first 4 pages, until the scans after the first one cover them alone.
+  t = uptime();
+  do {
+    for(i = 0; i < 4; i++)
+      p[i*PGSIZE]++;
+    if(wss(0, -1, &info) != 0){
+      printf("%s: wss failed\n", s);
+      exit(1);
+    }
+  } while(info.scans < 3 && uptime() - t < 100);
+  if(info.scans < 3){
+    printf("%s: the process was not scanned\n", s);
+    exit(1);
+  }
+  if(info.accessed < 4 || info.dirty < 4 || info.dirty > info.accessed){
+    printf("%s: working set of %lu pages, %lu dirty\n", s, info.accessed, info.dirty);
+    exit(1);
+  }
+#if defined __riscv
+  if(info.accessed >= 64){
+    printf("%s: working set of %lu pages counts the untouched pages\n", s, info.accessed);
+    exit(1);
+  }
+#endif
+
+  if(wss(0, 0, &info) != 0 || info.interval != 0 || info.scans != 0){
+    printf("%s: wss failed to stop the scans\n", s);
+    exit(1);
+  }
+  sbrk(-64*PGSIZE);
+}
+
+// are untouched pages the shared zero page, until each is written?
+void
+zeropagetest(char *s)
+{
+  struct pmapinfo info, info1;
+  char *a, *p;
+  int i, sum, pid, xstatus;
+
+  a = sbrk(0);
+  if((uint64)a % PGSIZE)
+    sbrk(PGSIZE - (uint64)a % PGSIZE);
+  if(pmap(getpid(), &info, 0, 0) != 0){
+    printf("%s: pmap failed\n", s);
+    exit(1);
+  }
+  p = sbrk(64*PGSIZE);
+  sum = 0;
+  for(i = 0; i < 64*PGSIZE; i += 512)
+    sum += p[i];
+  if(sum != 0){
+    printf("%s: untouched pages not zero\n", s);
+    exit(1);
+  }
+  if(pmap(getpid(), &info1, 0, 0) != 0 || info1.rss != info.rss){
+    printf("%s: reading untouched pages allocated them\n", s);
+    exit(1);
+  }
+
+  p[5*PGSIZE] = 'x';
+  if(pmap(getpid(), &info1, 0, 0) != 0 || info1.rss != info.rss + 1 ||
+     p[5*PGSIZE] != 'x' || p[5*PGSIZE + 1] != 0 || p[6*PGSIZE] != 0){
+    printf("%s: write to an untouched page went wrong\n", s);
+    exit(1);
+  }
+
+  // A child shares the untouched pages, and gets pages of its own by writing.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    p[7*PGSIZE] = 'c';
+    exit(p[6*PGSIZE] == 0 && p[7*PGSIZE] == 'c' ? 0 : 1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || p[7*PGSIZE] != 0){
+    printf("%s: child went wrong with untouched pages\n", s);
+    exit(1);
+  }
+  sbrk(-64*PGSIZE);
+}
+
+// does ksm() merge pages of the same contents, and do merged pages still get written apart?
+void
+ksmtest(char *s)
+{
+  struct ksminfo info, info0;
+  char *a, *p;
+  int i, t;
+
+  if(ksm(-2, 0) >= 0){
+    printf("%s: bad ksm succeeded\n", s);
+    exit(1);
+  }
+  a = sbrk(0);
+  if((uint64)a % PGSIZE)
+    sbrk(PGSIZE - (uint64)a % PGSIZE);
+  p = sbrk(16*PGSIZE);
+  for(i = 0; i < 8; i++)
+    memset(p + i*PGSIZE, 'k', PGSIZE);
+  for(i = 8; i < 12; i++){
+    p[i*PGSIZE] = 1;
+    p[i*PGSIZE] = 0;
+  }
+
+  if(ksm(64, &info0) != 0 || info0.pages_to_scan != 64){
+    printf("%s: ksm failed to enable merging\n", s);
+    exit(1);
+  }
+  t = uptime();
+  do {
+    if(ksm(-1, &info) != 0){
+      printf("%s: ksm failed\n", s);
+      exit(1);
+    }
+  } while((info.merged - info0.merged < 7 || info.zero - info0.zero < 4) && uptime() - t < 100);
+  if(info.merged - info0.merged < 7 || info.zero - info0.zero < 4){
+    printf("%s: merged %lu pages, %lu zero pages\n", s, info.merged - info0.merged,
+           info.zero - info0.zero);
+    exit(1);
+  }
+  if(info.stable == 0 || info.sharing <= info.stable || info.scanned < info.merged + info.zero){
+    printf("%s: %lu stable pages with %lu mappings\n", s, info.stable, info.sharing);
+    exit(1);
+  }
+
+  // The merged pages keep their contents, and a write gets a page its own copy again.
+  for(i = 0; i < 16*PGSIZE; i += 256){
+    if(p[i] != (i < 8*PGSIZE ? 'k' : 0)){
+      printf("%s: merged page %d changed\n", s, i / PGSIZE);
+      exit(1);
+    }
+  }
+  p[3*PGSIZE] = 'x';
+  p[9*PGSIZE] = 'x';
+  if(p[3*PGSIZE] != 'x' || p[3*PGSIZE + 1] != 'k' || p[2*PGSIZE] != 'k' ||
+     p[4*PGSIZE] != 'k' || p[9*PGSIZE] != 'x' || p[10*PGSIZE] != 0){
+    printf("%s: write to a merged page went wrong\n", s);
+    exit(1);
+  }
+
+  if(ksm(0, &info) != 0 || info.pages_to_scan != 0 || info.stable != 0){
+    printf("%s: ksm failed to disable merging\n", s);
+    exit(1);
+  }
+  sbrk(-16*PGSIZE);
+}
+
+// when memory runs short, do pages go into the compressed swap, and come back intact?
+void
+zramtest(char *s)
+{
+  struct zraminfo info0, info;
+  struct meminfo mem;
+  uint64 i, n;
+  char *p;
+  int pid, xstatus;
+
+  if(zram(&info0) != 0){
+    printf("%s: zram failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    // Write more pages than are free, each compressible and telling its number.
+    if(meminfo(&mem, 0, 0) < 0)
+      exit(1);
+    n = mem.free_pages + 512;
+    p = sbrk(n*PGSIZE);
+    if(p == (char*)-1){
+      printf("%s: sbrk failed\n", s);
+      exit(1);
+    }
+    for(i = 0; i < n; i++)
+      *(uint64*)(p + i*PGSIZE) = i;
+    for(i = 0; i < n; i += 7){
+      if(*(uint64*)(p + i*PGSIZE) != i || p[i*PGSIZE + 100] != 0){
+        printf("%s: page %lu came back wrong\n", s, i);
+        exit(1);
+      }
+    }
+    if(zram(&info) != 0 || info.swapouts < info0.swapouts + 512 ||
+       info.swapins == info0.swapins || info.stored == 0 || info.pool_pages == 0 ||
+       info.compressed > info.stored * PGSIZE / 2){
+      printf("%s: %lu pages swapped out, %lu in, %lu stored in %lu pages\n", s,
+             info.swapouts - info0.swapouts, info.swapins - info0.swapins, info.stored,
+             info.pool_pages);
+      exit(1);
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+
+  // The pages of the child leave the compressed swap as it exits.
+  if(zram(&info) != 0 || info.stored != info0.stored || info.pool_pages != info0.pool_pages){
+    printf("%s: %lu pages left in the compressed swap\n", s, info.stored - info0.stored);
+    exit(1);
+  }
+}
+
+// does migrate() move a process to the CPU it binds it to, and count the migrations?
+void
+migratetest(char *s)
+{
+  struct cpuload load[NCPU];
+  struct schedstat before, after;
+  int i, n, a, b, pid, xstatus;
+
+  if(migrate(0, -2) >= 0 || migrate(0, NCPU) >= 0 || migrate(-5, 0) >= 0){
+    printf("%s: bad migrate succeeded\n", s);
+    exit(1);
+  }
+
+  // Find two CPUs that have started.
+  n = cpuload(load, NCPU);
+  a = b = -1;
+  for(i = 0; i < n; i++){
+    if(load[i].window_cycles == 0)
+      continue;
+    if(a < 0)
+      a = i;
+    else if(b < 0)
+      b = i;
+  }
+  if(a < 0 || migrate(0, a) != 0){
+    printf("%s: migrate failed\n", s);
+    exit(1);
+  }
+  if(b >= 0){
+    schedstat(&before);
+    for(i = 0; i < 10; i++){
+      if(migrate(0, i % 2 ? a : b) != 0){
+        printf("%s: migrate failed\n", s);
+        exit(1);
+      }
+    }
+    schedstat(&after);
+    if(after.nmigrate < before.nmigrate + 10){
+      printf("%s: %lu migrations counted\n", s, after.nmigrate - before.nmigrate);
+      exit(1);
+    }
+  }
+
+  // A child bound to a CPU still runs there.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    for(i = 0; i < 10; i++)
+      yield();
+    exit(0);
+  }
+  if(migrate(pid, b >= 0 ? b : a) != 0){
+    printf("%s: migrate of the child failed\n", s);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || migrate(0, -1) != 0){
+    printf("%s: bound child failed\n", s);
+    exit(1);
+  }
+}
+
+// do the members of a gang run, is a gang limited to the number of CPUs, and do the slots
+// rotate while a gang exists?
+void
+gangtest(char *s)
+{
+  struct cpuload load[NCPU];
+  struct ganginfo info0, info;
+  int fds[2], pids[NCPU];
+  int i, n, ncpu, start, xstatus;
+  char c;
+
+  if(gang(0, -2, 0) >= 0 || gang(-5, 1, 0) >= 0){
+    printf("%s: bad gang succeeded\n", s);
+    exit(1);
+  }
+  if(gang(0, -1, &info0) != 0 || info0.members != 0){
+    printf("%s: the process is in a gang\n", s);
+    exit(1);
+  }
+
+  n = cpuload(load, NCPU);
+  ncpu = 0;
+  for(i = 0; i < n; i++)
+    if(load[i].window_cycles != 0)
+      ncpu++;
+
+  // Fill a gang with as many members as there are CPUs, which spin for a while.
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  start = uptime();
+  for(i = 0; i < ncpu; i++){
+    pids[i] = fork();
+    if(pids[i] < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pids[i] == 0){
+      if(gang(0, 77, 0) != 77)
+        exit(1);
+      write(fds[1], "x", 1);
+      while(uptime() < start + 30)
+        ;
+      exit(0);
+    }
+  }
+  close(fds[1]);
+  for(i = 0; i < ncpu; i++){
+    if(read(fds[0], &c, 1) != 1){
+      printf("%s: a child failed to join the gang\n", s);
+      exit(1);
+    }
+  }
+  close(fds[0]);
+
+  if(gang(pids[0], -1, &info) != 77 || info.gangs != info0.gangs + 1 || info.members != ncpu){
+    printf("%s: %lu gangs, %lu members\n", s, info.gangs, info.members);
+    exit(1);
+  }
+  if(gang(0, 77, 0) >= 0){
+    printf("%s: joined a full gang\n", s);
+    exit(1);
+  }
+
+  // The processes in no gang, such as this one, still get their slots.
+  sleep(20);
+  if(gang(0, -1, &info) != 0 || info.slots <= info0.slots){
+    printf("%s: %lu slots\n", s, info.slots - info0.slots);
+    exit(1);
+  }
+
+  for(i = 0; i < ncpu; i++){
+    wait(&xstatus);
+    if(xstatus != 0)
+      exit(1);
+  }
+
+  // The gang is gone with its members.
+  if(gang(0, -1, &info) != 0 || info.gangs != info0.gangs){
+    printf("%s: %lu gangs left\n", s, info.gangs - info0.gangs);
+    exit(1);
+  }
+  if(gang(0, 78, &info) != 78 || info.members != 1 || gang(0, 0, &info) != 0 ||
+     info.gangs != info0.gangs){
+    printf("%s: joining and leaving a gang failed\n", s);
+    exit(1);
+  }
+}
+
+// does a process of the deadline class keep to its budget, and do its children leave the class?
+void
+deadlinetest(char *s)
+{
+  struct dlinfo info;
+  int fds[2], pid, start, xstatus;
+  char c;
+
+  if(setdeadline(0, 100, 0, 100) >= 0 || setdeadline(0, 100, 60, 50) >= 0 ||
+     setdeadline(0, 100, 50, 200) >= 0 || setdeadline(-5, 100, 50, 100) >= 0){
+    printf("%s: bad setdeadline succeeded\n", s);
+    exit(1);
+  }
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setdeadline(0, 50000, 10000, 40000) != 0 || getsched(0) != SCHED_DEADLINE)
+      exit(1);
+    write(fds[1], "x", 1);
+    pid = fork();
+    if(pid < 0)
+      exit(1);
+    if(pid == 0)
+      exit(getsched(0) == SCHED_INTERACTIVE ? 0 : 1);
+    wait(&xstatus);
+    if(xstatus != 0)
+      exit(1);
+
+    // Spin, running for at most the budget in each period. The budget may be overrun by the
+    // rest of a tick.
+    start = uptime();
+    while(uptime() < start + 20)
+      if(dlstat(0, &info) != 0 || info.used > info.budget + gettick())
+        exit(1);
+    exit(0);
+  }
+
+  // The spinning child leaves time to this process, even though it is of a higher class.
+  close(fds[1]);
+  if(read(fds[0], &c, 1) != 1){
+    printf("%s: child failed to set its reservation\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  if(getsched(pid) != SCHED_DEADLINE || dlstat(pid, &info) != 0 || info.period != 50000 ||
+     info.budget != 10000 || info.deadline != 40000){
+    printf("%s: reservation of the child not set\n", s);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child overran its budget or its child inherited the class\n", s);
+    exit(1);
+  }
+
+  if(setdeadline(0, 1000000, 500000, 1000000) != 0 || getsched(0) != SCHED_DEADLINE ||
+     setdeadline(0, 0, 0, 0) != 0 || getsched(0) != SCHED_INTERACTIVE){
+    printf("%s: leaving the deadline class failed\n", s);
+    exit(1);
+  }
+}
+
+// does a process of the deadline class waiting for a sleep lock lend its priority to the holder,
+// an idle process, so that a busy interactive process on the same CPU does not keep it waiting?
+void
+inherittest(char *s)
+{
+  struct cpuload load[NCPU];
+  struct schedstat before, after;
+  int fds[2], cpu, i, n, fd, holder, hog, xstatus;
+  char *big, c;
+
+  n = cpuload(load, NCPU);
+  for(cpu = 0; cpu < n; cpu++)
+    if(load[cpu].window_cycles != 0)
+      break;
+  if(cpu == n || migrate(0, cpu) != 0){
+    printf("%s: migrate failed\n", s);
+    exit(1);
+  }
+  fd = open("inherit", O_CREATE|O_RDWR);
+  if(fd < 0 || pipe(fds) != 0){
+    printf("%s: open or pipe failed\n", s);
+    exit(1);
+  }
+  if(setdeadline(0, 100000, 50000, 100000) != 0){
+    printf("%s: setdeadline failed\n", s);
+    exit(1);
+  }
+  schedstat(&before);
+
+  // The holder keeps writing through the file it shares with this process, holding the lock of
+  // its offset across each write.
+  holder = fork();
+  if(holder < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(holder == 0){
+    big = sbrk(64 * 1024);
+    if(big == (char*)-1 || migrate(0, cpu) != 0 || setsched(0, SCHED_IDLE) != 0)
+      exit(1);
+    memset(big, 'i', 64 * 1024);
+    write(fds[1], "x", 1);
+    for(;;)
+      write(fd, big, 64 * 1024);
+  }
+  close(fds[1]);
+  if(read(fds[0], &c, 1) != 1){
+    printf("%s: holder failed\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+
+  // The hog leaves no time to the holder, unless the holder inherits a higher priority.
+  hog = fork();
+  if(hog < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(hog == 0){
+    if(migrate(0, cpu) != 0)
+      exit(1);
+    for(;;)
+      ;
+  }
+  sleep(2);
+
+  for(i = 0; i < 3; i++){
+    if(write(fd, "p", 1) != 1){
+      printf("%s: write failed\n", s);
+      exit(1);
+    }
+  }
+  schedstat(&after);
+  if(after.ninherit <= before.ninherit){
+    printf("%s: no priority inherited\n", s);
+    exit(1);
+  }
+
+  kill(hog);
+  kill(holder);
+  wait(&xstatus);
+  wait(&xstatus);
+  close(fd);
+  unlink("inherit");
+  if(setdeadline(0, 0, 0, 0) != 0 || migrate(0, -1) != 0){
+    printf("%s: restoring the class or the CPU failed\n", s);
+    exit(1);
+  }
+}
+
+// do the mutexes of the file table and the inode table count the acquisitions of processes
+// opening and closing files at the same time, and account for each contended one?
+void
+mutextest(char *s)
+{
+  enum { NCHILD=4, N=200 };
+  struct lockstat before[2], after[2];
+  int i, j, fd, pid, xstatus;
+
+  if(lockstat(before, 2) != 2 || lockstat(before, 1) != 2 || lockstat(before, -1) >= 0){
+    printf("%s: lockstat failed\n", s);
+    exit(1);
+  }
+  if(strcmp(before[0].name, "FTABLE") != 0 || strcmp(before[1].name, "ITABLE") != 0){
+    printf("%s: unexpected locks %s and %s\n", s, before[0].name, before[1].name);
+    exit(1);
+  }
+  fd = open("mutex", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  for(i = 0; i < NCHILD; i++){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      for(j = 0; j < N; j++){
+        fd = open("mutex", O_RDONLY);
+        if(fd < 0)
+          exit(1);
+        close(fd);
+      }
+      exit(0);
+    }
+  }
+  for(i = 0; i < NCHILD; i++){
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: child failed\n", s);
+      exit(1);
+    }
+  }
+  unlink("mutex");
+
+  lockstat(after, 2);
+  for(i = 0; i < 2; i++){
+    if(after[i].acquired < before[i].acquired + NCHILD * N){
+      printf("%s: %s acquired only %d times\n", s, after[i].name,
+             (int)(after[i].acquired - before[i].acquired));
+      exit(1);
+    }
+    if(after[i].contended > after[i].acquired || after[i].spun > after[i].contended){
+      printf("%s: %s counts do not add up\n", s, after[i].name);
+      exit(1);
+    }
+    if(after[i].contended - after[i].spun > after[i].slept){
+      printf("%s: %s acquired without spinning or sleeping\n", s, after[i].name);
+      exit(1);
+    }
+  }
+}
+
+// does spinbench() make processes acquire the same spin lock at the same time, each getting it?
+void
+spinbenchtest(char *s)
+{
+  enum { NCHILD=2, MSEC=100 };
+  struct spinbench r;
+  int i, pid, fds[2], xstatus;
+
+  if(spinbench(0, &r) >= 0 || spinbench(-1, &r) >= 0){
+    printf("%s: spinbench accepted a bad time\n", s);
+    exit(1);
+  }
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < NCHILD; i++){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      close(fds[0]);
+      if(spinbench(MSEC, &r) != 0)
+        exit(1);
+      write(fds[1], &r, sizeof(r));
+      exit(0);
+    }
+  }
+  close(fds[1]);
+  for(i = 0; i < NCHILD; i++){
+    if(read(fds[0], &r, sizeof(r)) != sizeof(r)){
+      printf("%s: spinbench failed\n", s);
+      exit(1);
+    }
+    if(r.kind > SPIN_MCS || r.acquired == 0 || r.max_wait > r.wait_cycles){
+      printf("%s: bad result: kind %d, %d acquired\n", s, (int)r.kind, (int)r.acquired);
+      exit(1);
+    }
+    if(r.elapsed < MSEC * 1000){
+      printf("%s: ran only %d us\n", s, (int)r.elapsed);
+      exit(1);
+    }
+  }
+  close(fds[0]);
+  for(i = 0; i < NCHILD; i++){
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: child failed\n", s);
+      exit(1);
+    }
+  }
+}
+
+// do reads and writes larger than a 9p message come back whole, and do
+// truncation, links, and device nodes work? Run on a 9p root
+// (make qemu NINEP=<host directory>), it covers the 9p client.
+void
+ninepio(char *s)
+{
+  enum { OFF=5000 };
+  int fd, i, n;
+  struct stat st;
+
+  unlink("9pio");
+  unlink("9pio2");
+  unlink("9pdev");
+  fd = open("9pio", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: cannot create 9pio\n", s);
+    exit(1);
+  }
+  for(i = 0; i < BUFSZ; i++)
+    buf[i] = i % 251;
+  if(write(fd, buf, BUFSZ) != BUFSZ){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("9pio", O_RDONLY);
+  memset(buf, 0, BUFSZ);
+  if((n = read(fd, buf, BUFSZ)) != BUFSZ){
+    printf("%s: read %d bytes, not %d\n", s, n, BUFSZ);
+    exit(1);
+  }
+  for(i = 0; i < BUFSZ; i++){
+    if(buf[i] != (char)(i % 251)){
+      printf("%s: wrong byte at %d\n", s, i);
+      exit(1);
+    }
+  }
+  if(lseek(fd, OFF, SEEK_SET) != OFF || read(fd, buf, BUFSZ) != BUFSZ - OFF ||
+     buf[0] != (char)(OFF % 251)){
+    printf("%s: read at an offset failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(link("9pio", "9pio2") != 0 || unlink("9pio") != 0){
+    printf("%s: link failed\n", s);
+    exit(1);
+  }
+  fd = open("9pio2", O_RDWR | O_TRUNC);
+  if(fd < 0 || fstat(fd, &st) != 0 || st.size != 0){
+    printf("%s: truncate failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("9pio2");
+
+  if(mknod("9pdev", 1, 1) != 0){
+    printf("%s: mknod failed\n", s);
+    exit(1);
+  }
+  fd = open("9pdev", O_RDONLY);
+  if(fd < 0 || fstat(fd, &st) != 0 || st.type != T_DEVICE){
+    printf("%s: 9pdev is not a device\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("9pdev");
+}
+
+// does defrag() make a file contiguous without changing its contents,
+// and does it refuse sparse files and files that are not inodes?
+void
+defragtest(char *s)
+{
+  enum { N=8 };
+  int fda, fdb, fd, i, j, n;
+  int fds[2];
+
+  unlink("defraga");
+  unlink("defragb");
+  fda = open("defraga", O_CREATE | O_RDWR);
+  fdb = open("defragb", O_CREATE | O_RDWR);
+  if(fda < 0 || fdb < 0){
+    printf("%s: cannot create the files\n", s);
+    exit(1);
+  }
+
+  // interleave the blocks of the two files. defraga is written from
+  // its last block to its first, so its blocks are not in ascending
+  // order even if the files do not share a block group.
+  for(i = 0; i < N; i++){
+    memset(buf, 'a' + (N - 1 - i), BSIZE);
+    if(lseek(fda, (N - 1 - i) * BSIZE, SEEK_SET) != (N - 1 - i) * BSIZE ||
+       write(fda, buf, BSIZE) != BSIZE){
+      printf("%s: write to defraga failed\n", s);
+      exit(1);
+    }
+    memset(buf, 'A' + i, BSIZE);
+    if(write(fdb, buf, BSIZE) != BSIZE){
+      printf("%s: write to defragb failed\n", s);
+      exit(1);
+    }
+  }
+
+  if((n = defrag(fda)) != N){
+    printf("%s: defrag relocated %d blocks, not %d\n", s, n, N);
+    exit(1);
+  }
+  // the file is contiguous now.
+  if((n = defrag(fda)) != 0){
+    printf("%s: defrag of a contiguous file relocated %d blocks\n", s, n);
+    exit(1);
+  }
+
+  // both files read back the same.
+  for(i = 0; i < 2 * N; i++){
+    fd = i < N ? fda : fdb;
+    if(i % N == 0 && lseek(fd, 0, SEEK_SET) != 0){
+      printf("%s: lseek failed\n", s);
+      exit(1);
+    }
+    if(read(fd, buf, BSIZE) != BSIZE){
+      printf("%s: read failed\n", s);
+      exit(1);
+    }
+    for(j = 0; j < BSIZE; j++){
+      if(buf[j] != (i < N ? 'a' : 'A') + i % N){
+        printf("%s: wrong byte in block %d after defrag\n", s, i % N);
+        exit(1);
+      }
+    }
+  }
+  close(fda);
+  close(fdb);
+  unlink("defraga");
+  unlink("defragb");
+
+  // a hole has no block to relocate.
+  unlink("defragsparse");
+  fd = open("defragsparse", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: cannot create defragsparse\n", s);
+    exit(1);
+  }
+  if(lseek(fd, 2 * BSIZE, SEEK_SET) != 2 * BSIZE || write(fd, "x", 1) != 1){
+    printf("%s: write to defragsparse failed\n", s);
+    exit(1);
+  }
+  if(defrag(fd) >= 0){
+    printf("%s: defrag of a sparse file succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("defragsparse");
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe() failed\n", s);
+    exit(1);
+  }
+  if(defrag(fds[1]) >= 0){
+    printf("%s: defrag of a pipe succeeded\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+}
+
+// fill the disk with files in the current directory, and return the
+// number of data blocks written to them. the files are then removed.
+// the count goes up and down with the number of free blocks.
+int
+countfreeblocks(char *s)
+{
+  int fi, i, fd, n;
+  char name[4];
+
+  n = 0;
+  name[0] = 'f';
+  name[3] = '\0';
+  for(fi = 0; fi < 100; fi++){
+    name[1] = '0' + fi / 10;
+    name[2] = '0' + fi % 10;
+    fd = open(name, O_CREATE|O_RDWR);
+    if(fd < 0){
+      printf("%s: could not create file %s\n", s, name);
+      exit(1);
+    }
+    for(i = 0; i < MAXFILE; i++){
+      if(write(fd, buf, BSIZE) != BSIZE)
+        break;
+      n++;
+    }
+    close(fd);
+    if(i < MAXFILE)
+      break;
+  }
+  if(fi == 100){
+    printf("%s: disk did not fill up\n", s);
+    exit(1);
+  }
+  for(i = 0; i <= fi; i++){
+    name[1] = '0' + i / 10;
+    name[2] = '0' + i % 10;
+    unlink(name);
+  }
+  return n;
+}
+
+// does a snapshot keep the old contents of a file that is overwritten,
+// truncated, and unlinked, and are the blocks that only the snapshot
+// refers to freed when it is dropped?
+void
+snapshottest(char *s)
+{
+  enum { N=NDIRECT+2 };
+  int fd, i, j, inum, free0, free1;
+  struct stat st;
+
+  // the blocks of an earlier snapshot would be freed by this one.
+  snapdrop();
+
+  unlink("snapdir/snapfile");
+  unlink("snapdir");
+  if(mkdir("snapdir") != 0 || chdir("snapdir") != 0){
+    printf("%s: cannot create snapdir\n", s);
+    exit(1);
+  }
+  free0 = countfreeblocks(s);
+
+  // block 0, block 2, and the first block behind the indirect
+  // block are written; the rest are holes.
+  fd = open("snapfile", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: cannot create snapfile\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    if(i != 0 && i != 2 && i != N - 1)
+      continue;
+    memset(buf, 'a' + i, BSIZE);
+    if(lseek(fd, i * BSIZE, SEEK_SET) != i * BSIZE || write(fd, buf, BSIZE) != BSIZE){
+      printf("%s: write to snapfile failed\n", s);
+      exit(1);
+    }
+  }
+  if(fstat(fd, &st) != 0){
+    printf("%s: fstat failed\n", s);
+    exit(1);
+  }
+  inum = st.ino;
+
+  if(snapshot() != 0){
+    printf("%s: snapshot failed\n", s);
+    exit(1);
+  }
+
+  // overwriting a direct block and a block behind the indirect block
+  // copies them, and the indirect block, first.
+  memset(buf, 'z', BSIZE);
+  if(lseek(fd, 0, SEEK_SET) != 0 || write(fd, buf, BSIZE) != BSIZE ||
+     lseek(fd, (N - 1) * BSIZE, SEEK_SET) != (N - 1) * BSIZE ||
+     write(fd, buf, BSIZE) != BSIZE){
+    printf("%s: overwrite of snapfile failed\n", s);
+    exit(1);
+  }
+  memset(buf, 0, BSIZE);
+  if(lseek(fd, 0, SEEK_SET) != 0 || read(fd, buf, BSIZE) != BSIZE || buf[0] != 'z'){
+    printf("%s: snapfile does not read back the overwrite\n", s);
+    exit(1);
+  }
+  if(ftruncate(fd, BSIZE) != 0){
+    printf("%s: ftruncate failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(unlink("snapfile") != 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+
+  if(snapstat(inum, &st) != 0 || st.type != T_FILE || st.size != N * BSIZE){
+    printf("%s: snapstat does not report the old file\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    memset(buf, 'x', BSIZE);
+    if(snapread(inum, i * BSIZE, buf, BSIZE) != BSIZE){
+      printf("%s: snapread of block %d failed\n", s, i);
+      exit(1);
+    }
+    for(j = 0; j < BSIZE; j++){
+      if(buf[j] != (i == 0 || i == 2 || i == N - 1 ? 'a' + i : 0)){
+        printf("%s: wrong byte in block %d of the snapshot\n", s, i);
+        exit(1);
+      }
+    }
+  }
+  if(snapread(inum, N * BSIZE, buf, BSIZE) != 0){
+    printf("%s: snapread past the end returned bytes\n", s);
+    exit(1);
+  }
+
+  // the blocks of snapfile are not freed while the snapshot refers to them.
+  if(countfreeblocks(s) >= free0){
+    printf("%s: blocks were freed while the snapshot refers to them\n", s);
+    exit(1);
+  }
+
+  if(snapdrop() != 0){
+    printf("%s: snapdrop failed\n", s);
+    exit(1);
+  }
+  if(snapread(inum, 0, buf, BSIZE) >= 0){
+    printf("%s: snapread succeeded after snapdrop\n", s);
+    exit(1);
+  }
+  free1 = countfreeblocks(s);
+  if(free1 != free0){
+    printf("%s: %d blocks free after snapdrop, not %d\n", s, free1, free0);
+    exit(1);
+  }
+
+  if(chdir("..") != 0 || unlink("snapdir") != 0){
+    printf("%s: cannot remove snapdir\n", s);
+    exit(1);
+  }
+}
+
+// does select report pipe ends ready for write, and refuse exceptional sets?
+void
+selecttest(char *s)
+{
+  int fds[2];
+  fd_set r, w, e;
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  FD_ZERO(&r);
+  FD_ZERO(&w);
+  FD_SET(fds[0], &r);
+  FD_SET(fds[1], &w);
+  if(select(fds[1] + 1, &r, &w, 0, 0) != 1 || FD_ISSET(fds[0], &r) || !FD_ISSET(fds[1], &w)){
+    printf("%s: empty pipe not ready only for write\n", s);
+    exit(1);
+  }
+  write(fds[1], "x", 1);
+  FD_ZERO(&r);
+  FD_ZERO(&w);
+  FD_SET(fds[0], &r);
+  FD_SET(fds[1], &w);
+  if(select(fds[1] + 1, &r, &w, 0, 0) != 2 || !FD_ISSET(fds[0], &r) || !FD_ISSET(fds[1], &w)){
+    printf("%s: written pipe not ready for read and write\n", s);
+    exit(1);
+  }
+  FD_ZERO(&e);
+  FD_SET(fds[0], &e);
+  if(select(fds[1] + 1, 0, 0, &e, 0) >= 0){
+    printf("%s: select accepted an exceptional set\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+}
+
+// does cpuload() report the CPUs, and does a throttled process still make progress?
+void
+cpuloadtest(char *s)
+{
+  int i, n, pid, status;
+  volatile uint64 x;
+  struct cpuload load[NCPU];
+
+  n = cpuload(load, NCPU);
+  if(n != NCPU || cpuload(load, 1) != 1 || cpuload(load, -1) >= 0){
+    printf("%s: cpuload returned %d\n", s, n);
+    exit(1);
+  }
+  n = cpuload(load, NCPU);
+  for(i = 0; i < n; i++)
+    if(load[i].window_cycles > 0)
+      break;
+  if(i == n){
+    printf("%s: no CPU reports its load\n", s);
+    exit(1);
+  }
+  for(i = 0; i < n; i++){
+    if(load[i].busy_cycles > load[i].window_cycles){
+      printf("%s: CPU %d busy longer than its window\n", s, i);
+      exit(1);
+    }
+  }
+
+  if(throttle(0, 0) >= 0 || throttle(0, 101) >= 0 || throttle(-1, 50) >= 0){
+    printf("%s: bad throttle not rejected\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(throttle(0, 10) < 0){
+      printf("%s: throttle failed\n", s);
+      exit(1);
+    }
+    for(x = 0; x < 50000000; x++)
+      ;
+    exit(0);
+  }
+  wait(&status);
+  if(status != 0)
+    exit(1);
+  if(throttle(0, 100) < 0){
+    printf("%s: unthrottle failed\n", s);
+    exit(1);
+  }
+}
+
+// does running out of disk blocks make writes and creations fail,
+// rather than panic, and can the blocks be used again once freed?
 void
-createdelete(char *s)
+diskfull(char *s)
 {
-  enum { N = 20, NCHILD=4 };
-  int pid, i, fd, pi;
+  int fi, i, fd, nzz;
+  int done = 0;
   char name[32];
 
-  for(pi = 0; pi < NCHILD; pi++){
-    pid = fork();
-    if(pid < 0){
-      printf("fork failed\n", s);
-      exit(1);
+  for(fi = 0; done == 0 && fi < 100; fi++){
+    name[0] = 'b';
+    name[1] = 'i';
+    name[2] = 'g';
+    name[3] = '0' + fi / 10;
+    name[4] = '0' + fi % 10;
+    name[5] = '\0';
+    unlink(name);
+    fd = open(name, O_CREATE|O_RDWR|O_TRUNC);
+    if(fd < 0){
+      // oops, ran out of inodes before running out of blocks.
+      printf("%s: could not create file %s\n", s, name);
+      done = 1;
+      break;
     }
-
-    if(pid == 0){
-      name[0] = 'p' + pi;
-      name[2] = '\0';
-      for(i = 0; i < N; i++){
-        name[1] = '0' + i;
-        fd = open(name, O_CREATE | O_RDWR);
-        if(fd < 0){
-          printf("%s: create failed\n", s);
-          exit(1);
-        }
-        close(fd);
-        if(i > 0 && (i % 2 ) == 0){
-          name[1] = '0' + (i / 2);
-          if(unlink(name) < 0){
-            printf("%s: unlink failed\n", s);
-            exit(1);
-          }
-        }
+    for(i = 0; i < MAXFILE; i++){
+      if(write(fd, buf, BSIZE) != BSIZE){
+        done = 1;
+        break;
       }
-      exit(0);
     }
+    close(fd);
+  }
+  if(!done){
+    printf("%s: disk did not fill up\n", s);
+    exit(1);
   }
 
-  int xstatus;
-  for(pi = 0; pi < NCHILD; pi++){
-    wait(&xstatus);
-    if(xstatus != 0)
-      exit(1);
+  // now that there are no free blocks, creating files merely fails
+  // once the directory cannot be extended.
+  nzz = 128;
+  for(i = 0; i < nzz; i++){
+    name[0] = 'z';
+    name[1] = 'z';
+    name[2] = '0' + (i / 32) / 32;
+    name[3] = '0' + (i / 32) % 32;
+    name[4] = '0' + i % 32;
+    name[5] = '\0';
+    unlink(name);
+    fd = open(name, O_CREATE|O_RDWR|O_TRUNC);
+    if(fd < 0)
+      break;
+    close(fd);
   }
 
-  name[0] = name[1] = name[2] = 0;
-  for(i = 0; i < N; i++){
-    for(pi = 0; pi < NCHILD; pi++){
-      name[0] = 'p' + pi;
-      name[1] = '0' + i;
-      fd = open(name, 0);
-      if((i == 0 || i >= N/2) && fd < 0){
-        printf("%s: oops createdelete %s didn't exist\n", s, name);
-        exit(1);
-      } else if((i >= 1 && i < N/2) && fd >= 0){
-        printf("%s: oops createdelete %s did exist\n", s, name);
-        exit(1);
-      }
-      if(fd >= 0)
-        close(fd);
-    }
+  // a directory needs a block for its entries.
+  if(mkdir("diskfulldir") == 0){
+    printf("%s: mkdir(diskfulldir) unexpectedly succeeded\n", s);
+    exit(1);
+  }
+  if(open("diskfulldir", 0) >= 0){
+    printf("%s: failed mkdir left diskfulldir behind\n", s);
+    exit(1);
   }
 
-  for(i = 0; i < N; i++){
-    for(pi = 0; pi < NCHILD; pi++){
-      name[0] = 'p' + i;
-      name[1] = '0' + i;
-      unlink(name);
-    }
+  for(i = 0; i < nzz; i++){
+    name[0] = 'z';
+    name[1] = 'z';
+    name[2] = '0' + (i / 32) / 32;
+    name[3] = '0' + (i / 32) % 32;
+    name[4] = '0' + i % 32;
+    name[5] = '\0';
+    unlink(name);
+  }
+  for(i = 0; i < fi; i++){
+    name[0] = 'b';
+    name[1] = 'i';
+    name[2] = 'g';
+    name[3] = '0' + i / 10;
+    name[4] = '0' + i % 10;
+    name[5] = '\0';
+    unlink(name);
+  }
+
+  // the freed blocks can be used again.
+  fd = open("diskfull0", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, buf, BSIZE) != BSIZE){
+    printf("%s: write after freeing the disk failed\n", s);
+    exit(1);
   }
+  close(fd);
+  unlink("diskfull0");
 }
 
-// can I unlink a file and still read it?
+// can an unnamed file be written, and then linked into a directory?
 void
-unlinkread(char *s)
+tmpfiletest(char *s)
 {
   enum { SZ = 5 };
-  int fd, fd1;
+  int fd;
+  struct stat st;
 
-  fd = open("unlinkread", O_CREATE | O_RDWR);
+  unlink("tmplinked");
+
+  fd = open("README", O_TMPFILE | O_RDWR);
+  if(fd >= 0){
+    printf("%s: O_TMPFILE on a file succeeded!\n", s);
+    exit(1);
+  }
+  fd = open(".", O_TMPFILE | O_RDONLY);
+  if(fd >= 0){
+    printf("%s: read-only O_TMPFILE succeeded!\n", s);
+    exit(1);
+  }
+
+  fd = open(".", O_TMPFILE | O_RDWR);
   if(fd < 0){
-    printf("%s: create unlinkread failed\n", s);
+    printf("%s: O_TMPFILE failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "hello", SZ) != SZ){
+    printf("%s: write tmpfile failed\n", s);
+    exit(1);
+  }
+  if(fstat(fd, &st) < 0 || st.nlink != 0){
+    printf("%s: tmpfile has a link\n", s);
+    exit(1);
+  }
+  if(linkat(fd, "tmplinked") != 0){
+    printf("%s: linkat failed\n", s);
+    exit(1);
+  }
+  if(linkat(fd, "tmplinked") == 0){
+    printf("%s: linkat to an existing name succeeded!\n", s);
     exit(1);
   }
-  write(fd, "hello", SZ);
   close(fd);
 
-  fd = open("unlinkread", O_RDWR);
+  fd = open("tmplinked", O_RDONLY);
   if(fd < 0){
-    printf("%s: open unlinkread failed\n", s);
+    printf("%s: open tmplinked failed\n", s);
     exit(1);
   }
-  if(unlink("unlinkread") != 0){
-    printf("%s: unlink unlinkread failed\n", s);
+  if(read(fd, buf, sizeof(buf)) != SZ || buf[0] != 'h'){
+    printf("%s: read tmplinked failed\n", s);
     exit(1);
   }
+  close(fd);
+  unlink("tmplinked");
 
-  fd1 = open("unlinkread", O_CREATE | O_RDWR);
-  write(fd1, "yyy", 3);
-  close(fd1);
+  // an unlinked tmpfile is freed on close.
+  fd = open(".", O_TMPFILE | O_RDWR);
+  if(fd < 0){
+    printf("%s: O_TMPFILE failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "hello", SZ) != SZ){
+    printf("%s: write tmpfile failed\n", s);
+    exit(1);
+  }
+  close(fd);
 
-  if(read(fd, buf, sizeof(buf)) != SZ){
-    printf("%s: unlinkread read failed", s);
+  // an unlinked named file cannot be linked back.
+  fd = open("tmpunlinked", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: create tmpunlinked failed\n", s);
     exit(1);
   }
-  if(buf[0] != 'h'){
-    printf("%s: unlinkread wrong data\n", s);
+  unlink("tmpunlinked");
+  if(linkat(fd, "tmplinked") == 0){
+    printf("%s: linkat of an unlinked file succeeded!\n", s);
     exit(1);
   }
-  if(write(fd, buf, 10) != 10){
-    printf("%s: unlinkread write failed\n", s);
+  close(fd);
+}
+
+// a file that reuses the inode number of a deleted file has a new generation.
+void
+generationtest(char *s)
+{
+  struct stat old, st;
+  int fd;
+
+  fd = open("gen", O_CREATE | O_RDWR);
+  if(fd < 0 || fstat(fd, &old) < 0){
+    printf("%s: create gen failed\n", s);
     exit(1);
   }
   close(fd);
-  unlink("unlinkread");
+  unlink("gen");
+
+  fd = open("gen", O_CREATE | O_RDWR);
+  if(fd < 0 || fstat(fd, &st) < 0){
+    printf("%s: create gen failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("gen");
+  if(st.ino != old.ino){
+    printf("%s: inode %d was not reused\n", s, old.ino);
+    exit(1);
+  }
+  if(st.gen == old.gen){
+    printf("%s: reused inode %d has the old generation\n", s, st.ino);
+    exit(1);
+  }
+}
+
+// do watches report creation, modification, and deletion of files?
+void
+watchtest(char *s)
+{
+  int fd, wfd, dwd, fwd;
+  struct stat st;
+  struct watchevent ev[4];
+
+  if(mkdir("watchdir") != 0){
+    printf("%s: mkdir watchdir failed\n", s);
+    exit(1);
+  }
+  wfd = watchopen();
+  if(wfd < 0){
+    printf("%s: watchopen failed\n", s);
+    exit(1);
+  }
+  dwd = watchadd(wfd, "watchdir", WATCH_CREATE | WATCH_DELETE);
+  if(dwd < 0){
+    printf("%s: watchadd watchdir failed\n", s);
+    exit(1);
+  }
+
+  fd = open("watchdir/f", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: create watchdir/f failed\n", s);
+    exit(1);
+  }
+  fwd = watchadd(wfd, "watchdir/f", WATCH_MODIFY);
+  if(fwd < 0 || fwd == dwd){
+    printf("%s: watchadd watchdir/f failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "hello", 5) != 5){
+    printf("%s: write watchdir/f failed\n", s);
+    exit(1);
+  }
+  if(fstat(fd, &st) < 0){
+    printf("%s: fstat watchdir/f failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(unlink("watchdir/f") != 0){
+    printf("%s: unlink watchdir/f failed\n", s);
+    exit(1);
+  }
+
+  if(read(wfd, ev, sizeof(ev)) != 3 * sizeof(ev[0])){
+    printf("%s: read watch failed\n", s);
+    exit(1);
+  }
+  if(ev[0].wd != dwd || ev[0].mask != WATCH_CREATE || ev[0].inum != st.ino ||
+     ev[1].wd != fwd || ev[1].mask != WATCH_MODIFY || ev[1].inum != st.ino ||
+     ev[2].wd != dwd || ev[2].mask != WATCH_DELETE || ev[2].inum != st.ino){
+    printf("%s: wrong watch events\n", s);
+    exit(1);
+  }
+
+  if(watchrm(wfd, dwd) != 0){
+    printf("%s: watchrm failed\n", s);
+    exit(1);
+  }
+  if(watchrm(wfd, dwd) == 0){
+    printf("%s: watchrm of a removed watch succeeded!\n", s);
+    exit(1);
+  }
+  close(wfd);
+  unlink("watchdir");
 }
 
 void
@@ -1395,12 +5730,9 @@ concreate(char *s)
 {
   enum { N = 40 };
   char file[3];
-  int i, pid, n, fd;
-  char fa[N];
-  struct {
-    ushort inum;
-    char name[DIRSIZ];
-  } de;
+  int i, pid, n, fd, cc, off;
+  char fa[N], dents[512];
+  struct dirent *de;
 
   file[0] = 'C';
   file[2] = '\0';
@@ -1433,21 +5765,22 @@ concreate(char *s)
   memset(fa, 0, sizeof(fa));
   fd = open(".", 0);
   n = 0;
-  while(read(fd, &de, sizeof(de)) > 0){
-    if(de.inum == 0)
-      continue;
-    if(de.name[0] == 'C' && de.name[2] == '\0'){
-      i = de.name[1] - '0';
-      if(i < 0 || i >= sizeof(fa)){
-        printf("%s: concreate weird file %s\n", s, de.name);
-        exit(1);
-      }
-      if(fa[i]){
-        printf("%s: concreate duplicate file %s\n", s, de.name);
-        exit(1);
+  while((cc = getdents(fd, dents, sizeof(dents))) > 0){
+    for(off = 0; off < cc; off += de->reclen){
+      de = (struct dirent*)(dents + off);
+      if(de->namelen == 2 && de->name[0] == 'C'){
+        i = de->name[1] - '0';
+        if(i < 0 || i >= sizeof(fa)){
+          printf("%s: concreate weird file C%c\n", s, de->name[1]);
+          exit(1);
+        }
+        if(fa[i]){
+          printf("%s: concreate duplicate file C%c\n", s, de->name[1]);
+          exit(1);
+        }
+        fa[i] = 1;
+        n++;
       }
-      fa[i] = 1;
-      n++;
     }
   }
   close(fd);
@@ -1877,7 +6210,8 @@ fourteen(char *s)
 {
   int fd;
 
-  // DIRSIZ is 14.
+  // Names longer than DIRSIZ (14) used to be truncated,
+  // but the long name directory format keeps them intact.
 
   if(mkdir("12345678901234") != 0){
     printf("%s: mkdir 12345678901234 failed\n", s);
@@ -1887,33 +6221,30 @@ fourteen(char *s)
     printf("%s: mkdir 12345678901234/123456789012345 failed\n", s);
     exit(1);
   }
-  fd = open("123456789012345/123456789012345/123456789012345", O_CREATE);
+  fd = open("12345678901234/123456789012345/123456789012345", O_CREATE);
   if(fd < 0){
-    printf("%s: create 123456789012345/123456789012345/123456789012345 failed\n", s);
+    printf("%s: create 12345678901234/123456789012345/123456789012345 failed\n", s);
     exit(1);
   }
   close(fd);
   fd = open("12345678901234/12345678901234/12345678901234", 0);
-  if(fd < 0){
-    printf("%s: open 12345678901234/12345678901234/12345678901234 failed\n", s);
+  if(fd >= 0){
+    printf("%s: open 12345678901234/12345678901234/12345678901234 succeeded!\n", s);
     exit(1);
   }
-  close(fd);
 
-  if(mkdir("12345678901234/12345678901234") == 0){
-    printf("%s: mkdir 12345678901234/12345678901234 succeeded!\n", s);
+  if(mkdir("12345678901234/12345678901234") != 0){
+    printf("%s: mkdir 12345678901234/12345678901234 failed\n", s);
     exit(1);
   }
-  if(mkdir("123456789012345/12345678901234") == 0){
+  if(mkdir("12345678901234/123456789012345") == 0){
     printf("%s: mkdir 12345678901234/123456789012345 succeeded!\n", s);
     exit(1);
   }
 
   // clean up
-  unlink("123456789012345/12345678901234");
   unlink("12345678901234/12345678901234");
-  unlink("12345678901234/12345678901234/12345678901234");
-  unlink("123456789012345/123456789012345/123456789012345");
+  unlink("12345678901234/123456789012345/123456789012345");
   unlink("12345678901234/123456789012345");
   unlink("12345678901234");
 }
@@ -1955,6 +6286,123 @@ rmdot(char *s)
   }
 }
 
+// getdents() reports the type of each file without a stat().
+void
+direnttypes(char *s)
+{
+  char dents[512];
+  struct dirent *de;
+  int fd, n, off, found = 0;
+
+  if(mkdir("dtd") != 0 || mkdir("dtd/d") != 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  fd = open("dtd/f", O_CREATE | O_RDWR);
+  if(fd < 0){
+    printf("%s: create dtd/f failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(link("dtd/f", "dtd/l") != 0 || mknod("dtd/n", 99, 0) != 0){
+    printf("%s: link or mknod failed\n", s);
+    exit(1);
+  }
+
+  fd = open("dtd", O_RDONLY);
+  while((n = getdents(fd, dents, sizeof(dents))) > 0){
+    for(off = 0; off < n; off += de->reclen){
+      de = (struct dirent*)(dents + off);
+      int want = -1;
+      switch(de->name[0]){
+      case '.': want = T_DIR; break;
+      case 'd': want = T_DIR; break;
+      case 'f': want = T_FILE; break;
+      case 'l': want = T_FILE; break;
+      case 'n': want = T_DEVICE; break;
+      }
+      if(de->type != want){
+        printf("%s: %c has type %d, not %d\n", s, de->name[0], de->type, want);
+        exit(1);
+      }
+      found++;
+    }
+  }
+  close(fd);
+  if(found != 6){
+    printf("%s: found %d entries\n", s, found);
+    exit(1);
+  }
+
+  unlink("dtd/n");
+  unlink("dtd/l");
+  unlink("dtd/f");
+  unlink("dtd/d");
+  unlink("dtd");
+}
+
+void
+getcwdtest(char *s)
+{
+  char buf[MAXPATH];
+
+  if(chdir("/") != 0 || getcwd(buf, sizeof(buf)) != 1 || strcmp(buf, "/") != 0){
+    printf("%s: getcwd of / failed\n", s);
+    exit(1);
+  }
+  if(mkdir("cwdd") != 0 || mkdir("cwdd/sub") != 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  if(chdir("cwdd/sub") != 0){
+    printf("%s: chdir cwdd/sub failed\n", s);
+    exit(1);
+  }
+  if(getcwd(buf, sizeof(buf)) != 9 || strcmp(buf, "/cwdd/sub") != 0){
+    printf("%s: getcwd returned %s\n", s, buf);
+    exit(1);
+  }
+  // The path is canonical however the directory was reached.
+  if(chdir("../sub/./.././/sub") != 0 || getcwd(buf, sizeof(buf)) != 9 ||
+     strcmp(buf, "/cwdd/sub") != 0){
+    printf("%s: getcwd after chdir ../sub returned %s\n", s, buf);
+    exit(1);
+  }
+  // A child starts in the same directory.
+  int pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(getcwd(buf, sizeof(buf)) != 9 || strcmp(buf, "/cwdd/sub") != 0){
+      printf("%s: getcwd in the child returned %s\n", s, buf);
+      exit(1);
+    }
+    exit(0);
+  }
+  int xstatus;
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+  if(getcwd(buf, 9) >= 0){
+    printf("%s: getcwd into a short buffer succeeded\n", s);
+    exit(1);
+  }
+  if(unlink("../sub") != 0){
+    printf("%s: unlink cwdd/sub failed\n", s);
+    exit(1);
+  }
+  if(getcwd(buf, sizeof(buf)) >= 0){
+    printf("%s: getcwd of a removed directory succeeded\n", s);
+    exit(1);
+  }
+  if(chdir("/") != 0 || unlink("cwdd") != 0){
+    printf("%s: unlink cwdd failed\n", s);
+    exit(1);
+  }
+}
+
 void
 dirfile(char *s)
 {
@@ -2048,12 +6496,12 @@ iref(char *s)
 }
 
 // test that fork fails gracefully
-// the forktest binary also does this, but it runs out of proc entries first.
-// inside the bigger usertests binary, we run out of memory first.
+// the forktest binary also does this. since processes are allocated
+// as they are needed, both run out of memory, unless a limit is configured.
 void
 forktest(char *s)
 {
-  enum{ N = 1000 };
+  enum{ N = 100000 };
   int n, pid;
 
   for(n=0; n<N; n++){
@@ -2070,7 +6518,7 @@ forktest(char *s)
   }
 
   if(n == N){
-    printf("%s: fork claimed to work 1000 times!\n", s);
+    printf("%s: fork claimed to work %d times!\n", s, N);
     exit(1);
   }
 
@@ -2292,6 +6740,7 @@ sbrkfail(char *s)
     sbrk(10*BIG);
     int n = 0;
     for (i = 0; i < 10*BIG; i += PGSIZE) {
+      *(a+i) = 1;
       n += *(a+i);
     }
     // print n so the compiler doesn't optimize away
@@ -2727,6 +7176,19 @@ countfree()
   return n;
 }
 
+// are the verdicts colored? only when the output goes to a terminal.
+int color;
+
+// print a verdict, green if it is a pass and red if it is a failure.
+void
+verdict(int pass, char *msg)
+{
+  if(color)
+    printf("\x1b[%dm%s\x1b[0m\n", pass ? 32 : 31, msg);
+  else
+    printf("%s\n", msg);
+}
+
 // run each test in its own process. run returns 1 if child's exit()
 // indicates success.
 int
@@ -2745,9 +7207,9 @@ run(void f(char *), char *s) {
   } else {
     wait(&xstatus);
     if(xstatus != 0) 
-      printf("FAILED\n");
+      verdict(0, "FAILED");
     else
-      printf("OK\n");
+      verdict(1, "OK");
     return xstatus == 0;
   }
 }
@@ -2757,7 +7219,9 @@ main(int argc, char *argv[])
 {
   int continuous = 0;
   char *justone = 0;
+  struct stat st;
 
+  color = fstat(1, &st) == 0 && st.type == T_DEVICE;
   if(argc == 2 && strcmp(argv[1], "-c") == 0){
     continuous = 1;
   } else if(argc == 2 && strcmp(argv[1], "-C") == 0){
@@ -2833,6 +7297,75 @@ main(int argc, char *argv[])
     {iref, "iref"},
     {forktest, "forktest"},
     {bigdir, "bigdir"}, // slow
+    {diskfull, "diskfull"}, // slow
+    {watchtest, "watchtest"},
+    {tmpfiletest, "tmpfiletest"},
+    {generationtest, "generationtest"},
+    {sendfiletest, "sendfiletest"},
+    {pmaptest, "pmaptest"},
+    {ptracetest, "ptracetest"},
+    {ptracetriggertest, "ptracetriggertest"},
+    {batchtest, "batchtest"},
+    {schedtest, "schedtest"},
+    {freezetest, "freezetest"},
+    {schedclasstest, "schedclasstest"},
+    {irqstattest, "irqstattest"},
+    {ticktest, "ticktest"},
+    {powertest, "powertest"},
+    {configtest, "configtest"},
+    {abitest, "abitest"},
+    {auxvtest, "auxvtest"},
+    {stackguard, "stackguard"},
+    {dirfdtest, "dirfdtest"},
+    {pathstattest, "pathstattest"},
+    {truncatetest, "truncatetest"},
+    {cloexectest, "cloexectest"},
+    {umasktest, "umasktest"},
+    {ttytest, "ttytest"},
+    {pseudodevtest, "pseudodevtest"},
+    {meminfotest, "meminfotest"},
+    {fdinfotest, "fdinfotest"},
+    {cpuloadtest, "cpuloadtest"},
+    {nanosleeptest, "nanosleeptest"},
+    {vconsoletest, "vconsoletest"},
+    {prctltest, "prctltest"},
+    {cyclestest, "cyclestest"},
+    {perftest, "perftest"},
+    {syslattest, "syslattest"},
+    {tracetest, "tracetest"},
+    {vporttest, "vporttest"},
+    {ioschedtest, "ioschedtest"},
+    {timeofdaytest, "timeofdaytest"},
+    {sockettest, "sockettest"},
+    {unixsockettest, "unixsockettest"},
+    {epolltest, "epolltest"},
+    {eventfdtest, "eventfdtest"},
+    {mqueuetest, "mqueuetest"},
+    {semtest, "semtest"},
+    {textsharetest, "textsharetest"},
+    {execcachetest, "execcachetest"},
+    {interptest, "interptest"},
+    {brktest, "brktest"},
+    {madvisetest, "madvisetest"},
+    {wsstest, "wsstest"},
+    {zeropagetest, "zeropagetest"},
+    {ksmtest, "ksmtest"},
+    {zramtest, "zramtest"},
+    {migratetest, "migratetest"},
+    {gangtest, "gangtest"},
+    {deadlinetest, "deadlinetest"},
+    {inherittest, "inherittest"},
+    {mutextest, "mutextest"},
+    {spinbenchtest, "spinbenchtest"},
+    {ninepio, "ninepio"},
+    {sharedfdlarge, "sharedfdlarge"},
+    {pipeatomic, "pipeatomic"},
+    {killblocked, "killblocked"},
+    {getcwdtest, "getcwdtest"},
+    {direnttypes, "direnttypes"},
+    {defragtest, "defragtest"},
+    {snapshottest, "snapshottest"}, // slow
+    {selecttest, "selecttest"},
     { 0, 0},
   };
 
@@ -2848,7 +7381,7 @@ main(int argc, char *argv[])
         }
       }
       if(fail){
-        printf("SOME TESTS FAILED\n");
+        verdict(0, "SOME TESTS FAILED");
         if(continuous != 2)
           exit(1);
       }
@@ -2901,13 +7434,13 @@ main(int argc, char *argv[])
   }
 
   if(fail){
-    printf("SOME TESTS FAILED\n");
+    verdict(0, "SOME TESTS FAILED");
     exit(1);
   } else if((free1 = countfree()) < free0){
     printf("FAILED -- lost some free pages %d (out of %d)\n", free1, free0);
     exit(1);
   } else {
-    printf("ALL TESTS PASSED\n");
+    verdict(1, "ALL TESTS PASSED");
     exit(0);
   }
 }
@@ -64,3 +64,94 @@ entry("getppid");
 entry("lseek");
 entry("uptime_as_micro");
 entry("clock");
+entry("defrag");
+entry("snapshot");
+entry("snapdrop");
+entry("snapread");
+entry("snapstat");
+entry("getdents");
+entry("watchopen");
+entry("watchadd");
+entry("watchrm");
+entry("linkat");
+entry("sendfile");
+entry("pmap");
+entry("ptrace");
+entry("batch");
+entry("yield");
+entry("gettid");
+entry("schedstat");
+entry("freeze");
+entry("thaw");
+entry("setsched");
+entry("getsched");
+entry("irqstat");
+entry("settick");
+entry("gettick");
+entry("powerctl");
+entry("reboot");
+entry("getconfig");
+entry("setconfig");
+entry("setabi");
+entry("openat");
+entry("mkdirat");
+entry("unlinkat");
+entry("stat");
+entry("lstat");
+entry("access");
+entry("ftruncate");
+entry("truncate");
+entry("fcntl");
+entry("umask");
+entry("meminfo");
+entry("getcwd");
+entry("fdinfo");
+entry("cpuload");
+entry("throttle");
+entry("nanosleep");
+entry("gettimeofday");
+entry("settimeofday");
+entry("prctl");
+entry("cycles");
+entry("perf");
+entry("iosched");
+entry("socket");
+entry("bind");
+entry("listen");
+entry("accept");
+entry("connect");
+entry("sendto");
+entry("recvfrom");
+entry("getsockname");
+entry("socketpair");
+entry("epoll_create");
+entry("epoll_ctl");
+entry("epoll_wait");
+entry("eventfd");
+entry("timerfd_create");
+entry("timerfd_settime");
+entry("timerfd_gettime");
+entry("getrlimit");
+entry("setrlimit");
+entry("mq_open");
+entry("mq_unlink");
+entry("mq_send");
+entry("mq_receive");
+entry("mq_getattr");
+entry("sem_open");
+entry("sem_unlink");
+entry("sem_wait");
+entry("sem_trywait");
+entry("sem_post");
+entry("sem_getvalue");
+entry("brk");
+entry("madvise");
+entry("wss");
+entry("ksm");
+entry("zram");
+entry("migrate");
+entry("gang");
+entry("setdeadline");
+entry("dlstat");
+entry("lockstat");
+entry("spinbench");
@@ -0,0 +1,59 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/watch.h"
+#include "user/user.h"
+
+#define NTARGET 8
+
+char *targets[NTARGET];
+
+char*
+kind(uint mask)
+{
+  switch(mask){
+  case WATCH_CREATE:
+    return "create";
+  case WATCH_DELETE:
+    return "delete";
+  case WATCH_MODIFY:
+    return "modify";
+  case WATCH_OVERFLOW:
+    return "overflow";
+  }
+  return "?";
+}
+
+int
+main(int argc, char *argv[])
+{
+  int fd, i, n, wd;
+  struct watchevent ev[8];
+
+  if(argc < 2 || argc - 1 > NTARGET){
+    fprintf(2, "Usage: watch path...\n");
+    exit(1);
+  }
+
+  if((fd = watchopen()) < 0){
+    fprintf(2, "watch: cannot create a watch\n");
+    exit(1);
+  }
+  for(i = 1; i < argc; i++){
+    wd = watchadd(fd, argv[i], WATCH_CREATE | WATCH_DELETE | WATCH_MODIFY);
+    if(wd < 0 || wd >= NTARGET){
+      fprintf(2, "watch: cannot watch %s\n", argv[i]);
+      exit(1);
+    }
+    targets[wd] = argv[i];
+  }
+
+  while((n = read(fd, ev, sizeof(ev))) > 0){
+    for(i = 0; i < n / sizeof(ev[0]); i++){
+      if(ev[i].wd < 0)
+        printf("%s\n", kind(ev[i].mask));
+      else
+        printf("%s: %s %d\n", targets[ev[i].wd], kind(ev[i].mask), ev[i].inum);
+    }
+  }
+  exit(0);
+}